@@ -0,0 +1,11 @@
+//! `.nomade` bundles from untrusted sources
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nomade_storage::{import_bundle, BundleKey, InMemoryStore};
+
+fuzz_target!(|data: &[u8]| {
+    let store = InMemoryStore::new();
+    let keypair = nomade_crypto::generate_keypair();
+    let _ = import_bundle(data, BundleKey::Device(&keypair), &store, &store);
+});
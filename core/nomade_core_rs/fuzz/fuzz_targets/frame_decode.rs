@@ -0,0 +1,19 @@
+//! Frames from untrusted peers, whole and in arbitrary pieces
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nomade_quic::frame::{Frame, FrameDecoder};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Frame::decode(data);
+
+    // First byte picks where the stream is split
+    if let Some((&split, rest)) = data.split_first() {
+        let (head, tail) = rest.split_at(split as usize % (rest.len() + 1));
+        let mut decoder = FrameDecoder::new();
+        decoder.extend(head);
+        while let Ok(Some(_)) = decoder.next_frame() {}
+        decoder.extend(tail);
+        while let Ok(Some(_)) = decoder.next_frame() {}
+    }
+});
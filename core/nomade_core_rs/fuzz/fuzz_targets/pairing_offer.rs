@@ -0,0 +1,18 @@
+//! Pairing offers from QR codes, pasted links and BLE fragments
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nomade_crypto::pairing::transport::{BleTransport, PairingTransport, BLE_MIN_MTU};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(url) = std::str::from_utf8(data) {
+        if let Ok(offer) = nomade_crypto::decode_pairing_offer(url) {
+            let _ = offer.verify_signature();
+        }
+    }
+
+    let mut transport = BleTransport::new(BLE_MIN_MTU).expect("minimum MTU is valid");
+    for fragment in data.chunks(BLE_MIN_MTU) {
+        let _ = transport.receive_chunk(fragment);
+    }
+});
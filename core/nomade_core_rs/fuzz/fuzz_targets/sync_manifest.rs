@@ -0,0 +1,11 @@
+//! Sync manifests received from peers
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(entries) = nomade_sync::decode_manifest(data) {
+        let reencoded = nomade_sync::encode_manifest(&entries);
+        assert_eq!(nomade_sync::decode_manifest(&reencoded).unwrap(), entries);
+    }
+});
@@ -0,0 +1,350 @@
+//! Nomade command line tool
+//!
+//! Administers a device's store and pairings straight from its data
+//! directory, for debugging and headless setups. Stop the app or daemon
+//! using the directory first: the stores are locked while open.
+
+mod qr;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use nomade_core::nomade_crypto::{DeviceId, TrustState};
+use nomade_core::nomade_quic::{Direction, FallbackTransport};
+use nomade_core::nomade_sync::SyncState;
+use nomade_core::{Context, NomadeConfig, NomadeRuntime};
+
+const USAGE: &str = "\
+usage: nomade (--data-dir <dir> | --config <config.json>) <command>
+
+commands:
+  identity                     Create the device identity if needed and print its ID
+  pair [--name <name>] [--invert]
+                               Print a pairing QR code and offer URL
+  accept <offer-url>           Trust the device behind a pairing offer
+  devices                      List paired devices
+  artifacts                    List artifacts
+  show <artifact-id>           Print an artifact as JSON
+  sync <device-id> <address>   Sync with a paired device listening at <address>
+  scrub                        Verify stored content, quarantining damaged blobs
+  gc                           Delete content no artifact refers to
+  health                       Print storage statistics and check the chunk index";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Identity,
+    Pair { name: String, invert: bool },
+    Accept { offer: String },
+    Devices,
+    Artifacts,
+    Show { id: String },
+    Sync { device_id: String, address: String },
+    Scrub,
+    Gc,
+    Health,
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    config: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    command: Command,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut args = args.into_iter();
+        let mut config = None;
+        let mut data_dir = None;
+        let value = |args: &mut dyn Iterator<Item = String>, flag: &str| {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", flag, USAGE))
+        };
+        let command = loop {
+            match args.next().as_deref() {
+                Some("--config") => config = Some(value(&mut args, "--config")?.into()),
+                Some("--data-dir") => data_dir = Some(value(&mut args, "--data-dir")?.into()),
+                Some(command) => break command.to_string(),
+                None => bail!(USAGE),
+            }
+        };
+        let operand = |args: &mut dyn Iterator<Item = String>, name: &str| {
+            args.next()
+                .ok_or_else(|| anyhow!("{} needs <{}>\n{}", command, name, USAGE))
+        };
+        let command = match command.as_str() {
+            "identity" => Command::Identity,
+            "pair" => {
+                let mut name = "Nomade CLI".to_string();
+                let mut invert = false;
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--name" => name = value(&mut args, "--name")?,
+                        "--invert" => invert = true,
+                        other => bail!("Unknown argument {}\n{}", other, USAGE),
+                    }
+                }
+                Command::Pair { name, invert }
+            }
+            "accept" => Command::Accept {
+                offer: operand(&mut args, "offer-url")?,
+            },
+            "devices" => Command::Devices,
+            "artifacts" => Command::Artifacts,
+            "show" => Command::Show {
+                id: operand(&mut args, "artifact-id")?,
+            },
+            "sync" => Command::Sync {
+                device_id: operand(&mut args, "device-id")?,
+                address: operand(&mut args, "address")?,
+            },
+            "scrub" => Command::Scrub,
+            "gc" => Command::Gc,
+            "health" => Command::Health,
+            "-h" | "--help" | "help" => bail!(USAGE),
+            other => bail!("Unknown command {}\n{}", other, USAGE),
+        };
+        if let Some(extra) = args.next() {
+            bail!("Unexpected argument {}\n{}", extra, USAGE);
+        }
+        if config.is_some() == data_dir.is_some() {
+            bail!(USAGE);
+        }
+        Ok(Self {
+            config,
+            data_dir,
+            command,
+        })
+    }
+
+    fn config(&self) -> anyhow::Result<NomadeConfig> {
+        match (&self.config, &self.data_dir) {
+            (Some(path), _) => Ok(NomadeConfig::from_json(&std::fs::read_to_string(path)?)?),
+            (None, Some(dir)) => Ok(NomadeConfig::new(dir)),
+            (None, None) => bail!(USAGE),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse(std::env::args().skip(1))?;
+    let context = Context::new(args.config()?)?;
+    std::fs::create_dir_all(&context.config().data_dir)?;
+    let runtime = Arc::new(
+        NomadeRuntime::builder(context)
+            .handle(tokio::runtime::Handle::current())
+            .build()?,
+    );
+    let result = run(&runtime, args.command).await;
+    runtime.shutdown().await?;
+    result
+}
+
+async fn run(runtime: &Arc<NomadeRuntime>, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Identity => println!("{}", runtime.device_id()),
+        Command::Pair { name, invert } => {
+            let offer = runtime.pairing_offer(&name)?;
+            print!("{}", qr::QrCode::encode(offer.as_bytes())?.to_text(invert));
+            println!("{}", offer);
+        }
+        Command::Accept { offer } => {
+            let device_id = runtime.accept_pairing_offer(&offer)?;
+            println!("Paired with {}", device_id);
+        }
+        Command::Devices => {
+            for device in runtime.trust().read().unwrap().list() {
+                let state = match &device.state {
+                    TrustState::Trusted => "trusted",
+                    TrustState::Revoked { .. } => "revoked",
+                };
+                println!("{}\t{}\t{}", device.device_id, state, device.device_name);
+            }
+        }
+        Command::Artifacts => {
+            let mut artifacts = runtime.artifacts().list()?;
+            artifacts.sort_by(|a, b| a.id.cmp(&b.id));
+            for artifact in artifacts {
+                println!("{}\t{}", artifact.id, artifact.title);
+            }
+        }
+        Command::Show { id } => {
+            let artifact = runtime
+                .artifacts()
+                .get(&id)?
+                .ok_or_else(|| anyhow!("No artifact {}", id))?;
+            println!("{}", serde_json::to_string_pretty(&artifact)?);
+        }
+        Command::Sync { device_id, address } => {
+            let device_id = DeviceId(device_id);
+            let network = &runtime.context().config().network;
+            let transport = FallbackTransport::bind(
+                "0.0.0.0:0".parse().expect("valid address"),
+                runtime.keystore().keypair(),
+            )?
+            .with_proxy(network.proxy.clone());
+            let connection = transport.connect_device(&address, &device_id).await?;
+            // Linked like any peer, so the device is admitted both ways
+            runtime.attach(connection, Direction::Outbound).await?;
+            let progress = runtime.start_sync(&device_id)?.wait().await;
+            runtime.disconnect_peer(&device_id);
+            transport.close();
+            match progress.state {
+                SyncState::Completed => println!(
+                    "Synced {} artifacts ({} bytes) from {}",
+                    progress.artifacts_synced, progress.bytes_transferred, device_id
+                ),
+                state => bail!("Sync {:?}: {}", state, progress.error.unwrap_or_default()),
+            }
+        }
+        Command::Scrub => {
+            let report = runtime.scrub()?;
+            for corrupt in &report.corrupt {
+                let problem = if corrupt.quarantined {
+                    "damaged"
+                } else {
+                    "missing"
+                };
+                println!(
+                    "{}\t{}\t{}",
+                    corrupt.content_hash,
+                    problem,
+                    corrupt.artifact_ids.join(",")
+                );
+            }
+            println!(
+                "Checked {} blobs, {} corrupt",
+                report.checked,
+                report.corrupt.len()
+            );
+        }
+        Command::Gc => {
+            let report = runtime.collect_garbage()?;
+            println!(
+                "Removed {} blobs, freed {} bytes",
+                report.removed.len(),
+                report.freed_bytes
+            );
+        }
+        Command::Health => {
+            let health = runtime.store_health()?;
+            let time = |at: Option<u64>| at.map_or("never".to_string(), |at| at.to_string());
+            println!(
+                "Artifacts\t{} ({} without local content)",
+                health.artifacts, health.artifacts_without_content
+            );
+            println!(
+                "Content\t{} bytes stored for {} ({:.2}x dedup)",
+                health.stored_bytes, health.logical_bytes, health.dedup_ratio
+            );
+            println!(
+                "Orphaned\t{} blobs, {} bytes",
+                health.orphaned_blobs, health.orphaned_bytes
+            );
+            println!("Last scrub\t{}", time(health.last_scrub));
+            println!("Last gc\t{}", time(health.last_gc));
+            for backend in &health.backends {
+                println!(
+                    "Backend\t{}\t{}\t{} bytes",
+                    backend.name, backend.kind, backend.disk_bytes
+                );
+            }
+            let index = &health.index;
+            println!(
+                "Chunk index\t{} chunks, {} missing, {} miscounted",
+                index.chunks_checked,
+                index.missing_chunks.len(),
+                index.miscounted_chunks.len()
+            );
+            if !health.is_healthy() {
+                bail!("Chunk index is inconsistent; run scrub to repair affected artifacts");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_core::config::StorageBackend;
+    use nomade_tests::device;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&[
+                "--data-dir",
+                "/tmp/n",
+                "pair",
+                "--invert",
+                "--name",
+                "Server"
+            ])
+            .unwrap(),
+            Args {
+                config: None,
+                data_dir: Some("/tmp/n".into()),
+                command: Command::Pair {
+                    name: "Server".into(),
+                    invert: true,
+                },
+            }
+        );
+        assert_eq!(
+            parse(&["--config", "c.json", "sync", "blake3-ab", "10.0.0.2:8765"])
+                .unwrap()
+                .command,
+            Command::Sync {
+                device_id: "blake3-ab".into(),
+                address: "10.0.0.2:8765".into(),
+            }
+        );
+        assert!(parse(&["gc"]).is_err());
+        assert!(parse(&["--data-dir", "/tmp/n"]).is_err());
+        assert!(parse(&["--data-dir", "/tmp/n", "show"]).is_err());
+        assert!(parse(&["--data-dir", "/tmp/n", "gc", "now"]).is_err());
+        assert!(parse(&["--data-dir", "/tmp/n", "reboot"]).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_commands_on_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = device(dir.path(), "server", StorageBackend::Persistent).unwrap();
+        let laptop = device(dir.path(), "laptop", StorageBackend::Persistent).unwrap();
+        run(&server, Command::Identity).await.unwrap();
+        run(
+            &laptop,
+            Command::Accept {
+                offer: server.pairing_offer("Server").unwrap(),
+            },
+        )
+        .await
+        .unwrap();
+        for command in [
+            Command::Devices,
+            Command::Artifacts,
+            Command::Scrub,
+            Command::Gc,
+            Command::Health,
+        ] {
+            run(&laptop, command).await.unwrap();
+        }
+        assert!(run(
+            &laptop,
+            Command::Show {
+                id: "missing".into()
+            }
+        )
+        .await
+        .is_err());
+        server.shutdown().await.unwrap();
+        laptop.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,609 @@
+//! QR codes for the terminal
+//!
+//! Byte-mode encoder at error correction level M, the level
+//! `QR_MAX_BYTES` is sized for, picking the smallest version that fits.
+//! Codes are drawn with half-block characters so each text row covers two
+//! module rows and the code stays roughly square.
+
+use anyhow::bail;
+
+/// Error correction codewords per block at level M, by version
+const ECC_PER_BLOCK: [usize; 41] = [
+    0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+];
+
+/// Error correction blocks at level M, by version
+const BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23,
+    25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+];
+
+/// Format bits of level M
+const LEVEL_M: u32 = 0;
+
+/// Quiet zone around the code, in modules
+const QUIET_ZONE: usize = 2;
+
+/// Square grid of dark and light modules
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that holds it
+    pub fn encode(data: &[u8]) -> anyhow::Result<Self> {
+        let Some(version) = (1..=40).find(|v| capacity(*v) >= data.len()) else {
+            bail!("{} bytes do not fit in a QR code", data.len());
+        };
+        let codewords = add_ecc_and_interleave(version, &data_codewords(version, data));
+
+        let mut code = Self::blank(version);
+        code.draw_codewords(&codewords);
+        let mask = (0..8)
+            .min_by_key(|mask| {
+                code.apply_mask(*mask);
+                code.draw_format_bits(*mask);
+                let penalty = code.penalty();
+                code.apply_mask(*mask);
+                penalty
+            })
+            .unwrap_or(0);
+        code.apply_mask(mask);
+        code.draw_format_bits(mask);
+        Ok(code)
+    }
+
+    /// Whether the module at column `x`, row `y` is dark
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// Draw as text, dark modules in the terminal's foreground colour
+    ///
+    /// Scanners expect dark modules on a light background, so terminals
+    /// with light text on dark need `invert`.
+    pub fn to_text(&self, invert: bool) -> String {
+        let padded = self.size + 2 * QUIET_ZONE;
+        let dark = |x: usize, y: usize| {
+            let inside = (QUIET_ZONE..QUIET_ZONE + self.size).contains(&x)
+                && (QUIET_ZONE..QUIET_ZONE + self.size).contains(&y);
+            let dark = inside && self.is_dark(x - QUIET_ZONE, y - QUIET_ZONE);
+            dark != invert
+        };
+        let mut text = String::new();
+        for y in (0..padded).step_by(2) {
+            for x in 0..padded {
+                let bottom = y + 1 < padded && dark(x, y + 1);
+                text.push(match (dark(x, y), bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Grid of `version` with every function pattern drawn
+    fn blank(version: usize) -> Self {
+        let size = version * 4 + 17;
+        let mut code = Self {
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        for i in 0..size {
+            code.set_function(6, i, i % 2 == 0);
+            code.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            code.draw_finder(x, y);
+        }
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Corners taken by finder patterns
+                if (i, j) != (0, 0) && (i, j) != (0, last) && (i, j) != (last, 0) {
+                    code.draw_alignment(x, y);
+                }
+            }
+        }
+        // Reserve the format areas; the real bits go in after masking
+        code.draw_format_bits(0);
+        code.draw_version(version);
+        code
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4i32..=4 {
+            for dx in -4i32..=4 {
+                let (xx, yy) = (x as i32 + dx, y as i32 + dy);
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                let dark = dx.abs().max(dy.abs()) != 1;
+                self.set_function((x as i32 + dx) as usize, (y as i32 + dy) as usize, dark);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(mask);
+        let bit = |i: u32| (bits >> i) & 1 != 0;
+        let size = self.size;
+        for i in 0..=5 {
+            self.set_function(8, i as usize, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i as usize, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i as usize, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i as usize, bit(i));
+        }
+        // Always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+        let bits = version_bits(version);
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Place codewords in the zigzag order, skipping function modules
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut bit = 0;
+        let mut right = size - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right, right - 1] {
+                    if !self.function[y * size + x] && bit < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[bit / 8] >> (7 - bit % 8)) & 1 != 0;
+                        bit += 1;
+                    }
+                }
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// XOR a mask pattern onto the data modules; applying it twice undoes it
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let i = y * self.size + x;
+                self.modules[i] ^= invert && !self.function[i];
+            }
+        }
+    }
+
+    /// Scanning difficulty from long runs, finder lookalikes, 2x2 blocks
+    /// and colour imbalance (ISO/IEC 18004 section 7.8.3)
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        for horizontal in [true, false] {
+            for a in 0..size {
+                let mut runs = RunHistory::new(size);
+                for b in 0..size {
+                    let dark = if horizontal {
+                        self.is_dark(b, a)
+                    } else {
+                        self.is_dark(a, b)
+                    };
+                    penalty += runs.push(dark);
+                }
+                penalty += runs.finish();
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if dark == self.is_dark(x + 1, y)
+                    && dark == self.is_dark(x, y + 1)
+                    && dark == self.is_dark(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|dark| **dark).count();
+        let total = size * size;
+        // Each 5% away from half dark costs 10
+        let k = (dark * 20).abs_diff(total * 10).div_ceil(total);
+        penalty + k.saturating_sub(1) * 10
+    }
+}
+
+/// Runs of one row or column, scoring long runs and finder lookalikes
+struct RunHistory {
+    size: usize,
+    dark: bool,
+    run: usize,
+    /// Lengths of the last seven completed runs, latest first
+    lengths: [usize; 7],
+}
+
+impl RunHistory {
+    /// Start a line, which the light quiet zone precedes
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            dark: false,
+            run: 0,
+            lengths: [0; 7],
+        }
+    }
+
+    /// Add the next module, returning the penalty it incurs
+    fn push(&mut self, dark: bool) -> usize {
+        if dark == self.dark {
+            self.run += 1;
+            return match self.run {
+                5 => 3,
+                run if run > 5 => 1,
+                _ => 0,
+            };
+        }
+        self.complete(self.run);
+        self.dark = dark;
+        self.run = 1;
+        // A light run just ended, so a lookalike may end here
+        match dark {
+            true => self.finder_patterns() * 40,
+            false => 0,
+        }
+    }
+
+    /// End the line, which the light quiet zone follows
+    fn finish(mut self) -> usize {
+        let mut light = self.run;
+        if self.dark {
+            self.complete(light);
+            light = 0;
+        }
+        self.complete(light + self.size);
+        self.finder_patterns() * 40
+    }
+
+    fn complete(&mut self, mut run: usize) {
+        // The quiet zone extends the first light run
+        if self.lengths[0] == 0 {
+            run += self.size;
+        }
+        self.lengths.copy_within(0..6, 1);
+        self.lengths[0] = run;
+    }
+
+    /// Dark-light-dark-light-dark runs of 1:1:3:1:1, counted once for
+    /// each side with four units of light
+    fn finder_patterns(&self) -> usize {
+        let [after, a, b, c, d, e, before] = self.lengths;
+        let n = a;
+        let core = n > 0 && b == n && c == 3 * n && d == n && e == n;
+        usize::from(core && after >= 4 * n && before >= n)
+            + usize::from(core && before >= 4 * n && after >= n)
+    }
+}
+
+/// Data modules of `version` that carry codewords
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codeword_count(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_PER_BLOCK[version] * BLOCKS[version]
+}
+
+fn count_bits(version: usize) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// Bytes a byte-mode code of `version` holds
+fn capacity(version: usize) -> usize {
+    (data_codeword_count(version) * 8 - 4 - count_bits(version)) / 8
+}
+
+/// Mode, length, data, terminator and padding as codewords
+fn data_codewords(version: usize, data: &[u8]) -> Vec<u8> {
+    let mut bits = BitBuffer::default();
+    bits.push(0b0100, 4);
+    bits.push(data.len() as u32, count_bits(version));
+    for byte in data {
+        bits.push(u32::from(*byte), 8);
+    }
+    let capacity = data_codeword_count(version) * 8;
+    bits.push(0, 4.min(capacity - bits.len));
+    bits.push(0, (8 - bits.len % 8) % 8);
+    let mut codewords = bits.bytes;
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if codewords.len() == capacity / 8 {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+#[derive(Default)]
+struct BitBuffer {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitBuffer {
+    fn push(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+/// Split data into blocks, append each block's Reed-Solomon codewords and
+/// interleave the blocks
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks = BLOCKS[version];
+    let ecc_len = ECC_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let short_blocks = blocks - raw_codewords % blocks;
+    let short_len = raw_codewords / blocks;
+    let divisor = reed_solomon_divisor(ecc_len);
+
+    let mut split = Vec::with_capacity(blocks);
+    let mut offset = 0;
+    for i in 0..blocks {
+        let len = short_len - ecc_len + usize::from(i >= short_blocks);
+        let mut block = data[offset..offset + len].to_vec();
+        offset += len;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        if i < short_blocks {
+            // Placeholder keeping columns aligned; skipped when interleaving
+            block.push(0);
+        }
+        block.extend(ecc);
+        split.push(block);
+    }
+
+    let mut codewords = Vec::with_capacity(raw_codewords);
+    for i in 0..split[0].len() {
+        for (j, block) in split.iter().enumerate() {
+            if i != short_len - ecc_len || j >= short_blocks {
+                codewords.push(block[i]);
+            }
+        }
+    }
+    codewords
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0u8; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    divisor
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0u8; divisor.len()];
+    for byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, d) in remainder.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(*d, factor);
+        }
+    }
+    remainder
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= u32::from((y >> i) & 1) * u32::from(x);
+    }
+    z as u8
+}
+
+/// Centres of the alignment patterns along each axis
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let size = version * 4 + 17;
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Level and mask with their BCH code, masked as the spec requires
+fn format_bits(mask: u32) -> u32 {
+    let data = LEVEL_M << 3 | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    (data << 10 | remainder) ^ 0x5412
+}
+
+/// Version number with its BCH code
+fn version_bits(version: usize) -> u32 {
+    let mut remainder = version as u32;
+    for _ in 0..12 {
+        remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+    }
+    (version as u32) << 12 | remainder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_core::nomade_crypto::qr_payload::QR_MAX_BYTES;
+
+    #[test]
+    fn test_reference_values() {
+        // ISO/IEC 18004 annex examples and capacity tables
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(version_bits(7), 0b000111110010010100);
+        assert_eq!(alignment_positions(7), [6, 22, 38]);
+        assert_eq!(alignment_positions(32), [6, 34, 60, 86, 112, 138]);
+        assert_eq!(capacity(1), 14);
+        assert_eq!(capacity(10), 213);
+        assert_eq!(capacity(40), QR_MAX_BYTES);
+        // "HELLO WORLD" at 1-M
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn test_matches_reference_symbol() {
+        // From the qrcodegen reference encoder: byte mode, 2-M, mask 4
+        let expected = [
+            "#######.#...#.#...#######",
+            "#.....#..##.....#.#.....#",
+            "#.###.#..##.##.##.#.###.#",
+            "#.###.#.#.....##..#.###.#",
+            "#.###.#.##..####..#.###.#",
+            "#.....#.#..####.#.#.....#",
+            "#######.#.#.#.#.#.#######",
+            "........#.....##.........",
+            "#...#.###.##.#########..#",
+            ".####..###.##..#.#..###..",
+            ".###.#####..######.##.#..",
+            ".##..#..#..#.#...#.##.#.#",
+            ".#.#.##.#.#.##..####..##.",
+            "###..#....#..####..##....",
+            "..#.###........#.#.##.#..",
+            "..####....#.#.#....##.###",
+            "##.##.##.###.########.##.",
+            "........###.#..##...##...",
+            "#######.#######.#.#.###..",
+            "#.....#..###.#.##...#.#..",
+            "#.###.#.#...##..#####.#.#",
+            "#.###.#...#..##.####.#.##",
+            "#.###.#..#.....####...##.",
+            "#.....#..#..#.#..##.####.",
+            "#######.####.###.###.####",
+        ];
+        let code = QrCode::encode(b"nomade://pair?v=1").unwrap();
+        let rows: Vec<String> = (0..code.size)
+            .map(|y| {
+                (0..code.size)
+                    .map(|x| if code.is_dark(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn test_encode() {
+        let code = QrCode::encode(b"nomade://pair?v=1").unwrap();
+        assert_eq!(code.size, 25);
+        // Finder pattern corners and the always-dark module
+        for (x, y) in [(0, 0), (24, 0), (0, 24), (8, 17)] {
+            assert!(code.is_dark(x, y));
+        }
+        assert!(!code.is_dark(7, 7));
+
+        let text = code.to_text(false);
+        assert_eq!(text.lines().count(), (25 + 2 * QUIET_ZONE).div_ceil(2));
+        assert!(text
+            .lines()
+            .all(|line| line.chars().count() == 25 + 2 * QUIET_ZONE));
+        assert_eq!(
+            QrCode::encode(&[0; 2400]).err().map(|e| e.to_string()),
+            Some("2400 bytes do not fit in a QR code".into())
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! `nomade sync` against a running `nomaded`
+//!
+//! Cargo only builds binaries of the package under test, so the test
+//! builds `nomaded` itself before starting it.
+
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output};
+use std::time::{Duration, Instant};
+
+use nomade_core::nomade_storage::{content_hash, Artifact};
+use nomade_core::{Context, NomadeConfig, NomadeRuntime};
+use serde_json::{json, Value};
+
+fn nomade(data_dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nomade"))
+        .arg("--data-dir")
+        .arg(data_dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Stops the daemon even when an assertion fails
+struct Daemon {
+    child: Child,
+    socket: PathBuf,
+}
+
+impl Daemon {
+    fn start(binary: &Path, config: &Path, socket: PathBuf) -> Self {
+        let child = Command::new(binary)
+            .arg("--config")
+            .arg(config)
+            .arg("--socket")
+            .arg(&socket)
+            .spawn()
+            .unwrap();
+        let mut daemon = Self { child, socket };
+        let started = Instant::now();
+        while UnixStream::connect(&daemon.socket).is_err() {
+            assert!(daemon.child.try_wait().unwrap().is_none(), "nomaded exited");
+            assert!(started.elapsed() < Duration::from_secs(10), "nomaded hung");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        daemon
+    }
+
+    fn call(&self, method: &str, params: Value) -> Value {
+        let mut stream = UnixStream::connect(&self.socket).unwrap();
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        writeln!(stream, "{}", request).unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        let mut response: Value = serde_json::from_str(&line).unwrap();
+        assert!(response["error"].is_null(), "{}: {}", method, response);
+        response["result"].take()
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if UnixStream::connect(&self.socket).is_ok() {
+            self.call("shutdown", Value::Null);
+            let stopped = Instant::now();
+            while self.child.try_wait().unwrap().is_none() {
+                if stopped.elapsed() > Duration::from_secs(10) {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        }
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+/// Store one artifact in the daemon's data directory before it starts
+fn seed(config: NomadeConfig) {
+    let tokio = tokio::runtime::Runtime::new().unwrap();
+    let _context = tokio.enter();
+    let runtime = NomadeRuntime::builder(Context::new(config).unwrap())
+        .handle(tokio.handle().clone())
+        .build()
+        .unwrap();
+    let content = b"from the daemon";
+    let hash = content_hash(content);
+    runtime.content().put_content(&hash, content).unwrap();
+    runtime
+        .artifacts()
+        .store(&Artifact {
+            id: "note".into(),
+            title: "Note".into(),
+            content_hash: hash,
+            modified_at: 1,
+            ..Default::default()
+        })
+        .unwrap();
+    tokio.block_on(runtime.shutdown()).unwrap();
+}
+
+/// Build `nomaded` next to the CLI binary, with the same profile
+fn build_daemon() -> PathBuf {
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo.args([
+        "build",
+        "--quiet",
+        "-p",
+        "nomade_daemon",
+        "--bin",
+        "nomaded",
+    ]);
+    if !cfg!(debug_assertions) {
+        cargo.arg("--release");
+    }
+    let status = cargo.status().unwrap();
+    assert!(status.success(), "building nomaded failed");
+    let binary = Path::new(env!("CARGO_BIN_EXE_nomade")).with_file_name("nomaded");
+    assert!(binary.exists(), "{} missing", binary.display());
+    binary
+}
+
+#[test]
+fn test_cli_syncs_from_daemon() {
+    let daemon_binary = build_daemon();
+    let dir = tempfile::tempdir().unwrap();
+    let cli_dir = dir.path().join("cli");
+    let mut config = NomadeConfig::new(dir.path().join("daemon"));
+    config.network.bind_address = "127.0.0.1".parse().unwrap();
+    config.network.listen_port = 0;
+    std::fs::create_dir_all(&config.data_dir).unwrap();
+    let config_path = dir.path().join("daemon.json");
+    std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+    seed(config);
+
+    let cli_offer = stdout(nomade(&cli_dir, &["pair", "--name", "CLI"]));
+    let cli_offer = cli_offer
+        .lines()
+        .find(|line| line.starts_with("nomade://"))
+        .unwrap();
+    let daemon = Daemon::start(&daemon_binary, &config_path, dir.path().join("d.sock"));
+    let cli_id = daemon.call("accept_pairing_offer", json!({ "offer": cli_offer }));
+    assert_eq!(
+        stdout(nomade(&cli_dir, &["identity"])).trim(),
+        cli_id.as_str().unwrap()
+    );
+    let offer = daemon.call("pairing_offer", json!({ "device_name": "Daemon" }));
+    stdout(nomade(&cli_dir, &["accept", offer.as_str().unwrap()]));
+
+    let daemon_id = daemon.call("device_id", Value::Null);
+    let port = daemon.call("listen", Value::Null);
+    let synced = stdout(nomade(
+        &cli_dir,
+        &[
+            "sync",
+            daemon_id.as_str().unwrap(),
+            &format!("127.0.0.1:{}", port),
+        ],
+    ));
+    assert!(synced.starts_with("Synced 1 artifacts"), "{}", synced);
+    drop(daemon);
+
+    let artifacts = stdout(nomade(&cli_dir, &["artifacts"]));
+    assert_eq!(artifacts, "note\tNote\n");
+}
@@ -1,4 +1,14 @@
-use flutter_rust_bridge::frb;
+use std::sync::Arc;
+use std::time::Duration;
+
+use flutter_rust_bridge::{frb, DartFnFuture};
+use nomade_crypto::{CryptoError, DeviceId};
+use nomade_storage::{Artifact, BulkProgress, Projection};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::frb_generated::StreamSink;
+use crate::runtime::executor;
+use crate::NomadeConfig;
 
 #[frb(sync)]
 pub fn process_message(input: String) -> String {
@@ -10,3 +20,956 @@ pub fn init_app() {
     // Default utilities - Flutter Rust Bridge
     flutter_rust_bridge::setup_default_user_utils();
 }
+
+/// Initialize Nomade core from a JSON-encoded `NomadeConfig`
+pub fn ffi_init(config_json: String) -> anyhow::Result<()> {
+    let config = NomadeConfig::from_json(&config_json)?;
+    crate::init(config)?;
+    if let Err(e) = crate::start() {
+        executor().block_on(crate::shutdown())?;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Stop the runtime started by `ffi_init`
+///
+/// Cancels background tasks, flushes storage, closes connections and
+/// drains pending events before returning.
+pub fn ffi_shutdown() -> anyhow::Result<()> {
+    executor().block_on(crate::shutdown())?;
+    Ok(())
+}
+
+/// Destroy this device's keys, stores and journals at once
+///
+/// Stops the runtime; `ffi_init` sets the device up from scratch.
+pub fn ffi_wipe() -> anyhow::Result<()> {
+    executor().block_on(crate::wipe())?;
+    Ok(())
+}
+
+/// Ask another device of this user to wipe itself, returning the
+/// JSON-encoded `WipeCommand` for the app to deliver if not connected
+pub fn ffi_wipe_device(device_id: String) -> anyhow::Result<String> {
+    let command = crate::runtime()?.wipe_device(&DeviceId(device_id))?;
+    Ok(serde_json::to_string(&command)?)
+}
+
+/// Honor a JSON-encoded `WipeCommand` from another device of this user
+///
+/// Fails without wiping unless the command is signed, fresh and sent by a
+/// trusted device enrolled under the same user.
+pub fn ffi_apply_wipe_command(command_json: String) -> anyhow::Result<()> {
+    let command = serde_json::from_str(&command_json)?;
+    crate::runtime()?.check_wipe(&command)?;
+    executor().block_on(crate::wipe())?;
+    Ok(())
+}
+
+/// Keep the identity key in the platform keystore
+///
+/// Call before `ffi_init`. `public_key` is the raw Ed25519 public key held
+/// by the Secure Enclave or StrongBox; `sign` returns the 64-byte signature
+/// of its argument, or an empty list if signing failed or was refused.
+pub fn ffi_register_platform_signer(
+    public_key: Vec<u8>,
+    sign: impl Fn(Vec<u8>) -> DartFnFuture<Vec<u8>> + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    crate::signer::register(&public_key, Arc::new(sign))?;
+    Ok(())
+}
+
+/// Ask the user to authenticate before sensitive operations
+///
+/// Call before `ffi_init`. `prompt` shows the platform prompt (biometrics
+/// or device credential) with the given reason and returns whether the
+/// user authenticated. `auth.gated` in the config selects the operations.
+pub fn ffi_register_auth_gate(
+    prompt: impl Fn(String) -> DartFnFuture<bool> + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    crate::auth::register(Arc::new(prompt));
+    Ok(())
+}
+
+/// ID of the local device
+pub fn ffi_device_id() -> anyhow::Result<String> {
+    Ok(crate::runtime()?.device_id().to_string())
+}
+
+/// Stream structured log records to the in-app debug console
+///
+/// Each item is a JSON-encoded `LogRecord`. The stream ends when the Dart
+/// side closes it.
+pub fn ffi_log_stream(sink: StreamSink<String>) -> anyhow::Result<()> {
+    let mut records = crate::logging::subscribe();
+    executor().spawn(async move {
+        loop {
+            match records.recv().await {
+                Ok(record) => {
+                    let Ok(json) = serde_json::to_string(&record) else {
+                        continue;
+                    };
+                    if sink.add(json).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Stream runtime events to the app
+///
+/// Each item is a JSON-encoded `Event` envelope carrying its schema version
+/// (`{"v":2,"type":...,"payload":...}`); apps should skip types they don't
+/// recognize. Bursts arrive as one `batched` event. The stream ends when the
+/// Dart side closes it or the runtime shuts down.
+pub fn ffi_event_stream(sink: StreamSink<String>) -> anyhow::Result<()> {
+    let mut events = crate::runtime()?.ui_events().subscribe();
+    executor().spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let Ok(json) = serde_json::to_string(&event) else {
+                        continue;
+                    };
+                    if sink.add(json).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Current metrics as a JSON-encoded `MetricsSnapshot`
+pub fn ffi_metrics_snapshot() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(
+        &crate::runtime()?.metrics_snapshot(),
+    )?)
+}
+
+/// Storage statistics and consistency checks as a JSON-encoded
+/// `StoreHealth`, for the storage settings screen
+pub fn ffi_store_health() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.store_health()?)?)
+}
+
+/// Check the crypto primitives against known-answer vectors
+///
+/// Needs no running runtime. Fails naming the first vector that does not
+/// match; `paranoid` in the configuration runs it before every start.
+pub fn ffi_crypto_self_test() -> anyhow::Result<()> {
+    Ok(nomade_crypto::self_test()?)
+}
+
+/// Current metrics in the Prometheus text exposition format
+pub fn ffi_metrics_prometheus() -> anyhow::Result<String> {
+    let runtime = crate::runtime()?;
+    runtime.metrics_snapshot();
+    Ok(nomade_metrics::global().render_prometheus())
+}
+
+/// Stream a JSON-encoded `MetricsSnapshot` every `interval_ms`
+///
+/// The stream stops when the Dart side closes it or the runtime shuts down.
+pub fn ffi_metrics_stream(sink: StreamSink<String>, interval_ms: u32) -> anyhow::Result<()> {
+    let runtime = crate::runtime()?;
+    let interval = Duration::from_millis(interval_ms.max(100) as u64);
+    let task_runtime = runtime.clone();
+    runtime
+        .supervisor()
+        .spawn("metrics-stream", move |token| async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = ticker.tick() => {}
+                }
+                let snapshot = task_runtime.metrics_snapshot();
+                let Ok(json) = serde_json::to_string(&snapshot) else {
+                    continue;
+                };
+                if sink.add(json).is_err() {
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Accept paired devices on the network listener, returning its port
+///
+/// Connected peers sync both ways over one connection, whichever side
+/// dialed. Calling it again returns the port already bound.
+pub fn ffi_listen() -> anyhow::Result<u32> {
+    let runtime = crate::runtime()?;
+    let _context = executor().enter();
+    Ok(runtime.listen()?.into())
+}
+
+/// Connect to a paired device at `address`, e.g. from its pairing offer
+///
+/// Needs `ffi_listen` first; the device can be synced with once this
+/// returns.
+pub fn ffi_connect_peer(peer_id: String, address: String) -> anyhow::Result<()> {
+    let runtime = crate::runtime()?;
+    executor().block_on(runtime.dial_peer(&DeviceId(peer_id), &address))?;
+    Ok(())
+}
+
+/// Connected peers available for sync as JSON-encoded device IDs
+pub fn ffi_linked_peers() -> anyhow::Result<String> {
+    let peers: Vec<String> = crate::runtime()?
+        .linked_peers()
+        .into_iter()
+        .map(|peer| peer.0)
+        .collect();
+    Ok(serde_json::to_string(&peers)?)
+}
+
+/// JSON-encoded `ConnectionQuality` of a linked peer, `null` until its
+/// keepalive has reported
+pub fn ffi_connection_quality(peer_id: String) -> anyhow::Result<String> {
+    let quality = crate::runtime()?.connection_quality(&DeviceId(peer_id));
+    Ok(serde_json::to_string(&quality)?)
+}
+
+/// Start syncing with a connected peer, returning the sync handle
+pub fn ffi_start_sync(peer_id: String) -> anyhow::Result<u64> {
+    let handle = crate::runtime()?.start_sync(&DeviceId(peer_id))?;
+    Ok(handle.id)
+}
+
+/// Preview a sync with a connected peer as a JSON-encoded `SyncPreview`
+///
+/// Fetches only the peer's manifest and metadata, so the app can show how
+/// many artifacts, chunks and bytes would move before starting the sync.
+pub fn ffi_plan_sync(peer_id: String) -> anyhow::Result<String> {
+    let runtime = crate::runtime()?;
+    let preview = executor().block_on(runtime.plan_sync(&DeviceId(peer_id)))?;
+    Ok(serde_json::to_string(&preview)?)
+}
+
+/// Stream progress of a sync as JSON-encoded `SyncProgress`
+///
+/// The stream ends after the final (completed, cancelled or failed) update.
+pub fn ffi_sync_progress(handle: u64, sink: StreamSink<String>) -> anyhow::Result<()> {
+    let mut progress = crate::runtime()?
+        .sync()
+        .sync_progress(handle)
+        .ok_or_else(|| anyhow::anyhow!("No running sync with handle {}", handle))?;
+    executor().spawn(async move {
+        loop {
+            let update = progress.borrow_and_update().clone();
+            let Ok(json) = serde_json::to_string(&update) else {
+                break;
+            };
+            if sink.add(json).is_err() || progress.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Cancel a running sync, keeping partial progress for the next sync
+pub fn ffi_cancel_sync(handle: u64) -> anyhow::Result<bool> {
+    Ok(crate::runtime()?.sync().cancel_sync(handle))
+}
+
+/// Record a connected peer's self-reported state from JSON-encoded `PeerHints`
+///
+/// Low-battery and metered peers are the last choice as download sources.
+pub fn ffi_set_peer_hints(peer_id: String, hints_json: String) -> anyhow::Result<()> {
+    let hints = serde_json::from_str(&hints_json)?;
+    crate::runtime()?.set_peer_hints(&DeviceId(peer_id), hints);
+    Ok(())
+}
+
+/// Report the network the device is on, from the platform's connectivity
+/// callbacks
+///
+/// `state_json` is a `NetworkState`, e.g. `{"kind": "cellular"}` or
+/// `{"kind": "wifi", "metered": true}`; kinds are `wifi`, `ethernet`,
+/// `cellular`, `offline` and `unknown`.
+pub fn ffi_set_network_state(state_json: String) -> anyhow::Result<()> {
+    let state = serde_json::from_str(&state_json)?;
+    crate::runtime()?.set_network_state(state);
+    Ok(())
+}
+
+/// Current network state as JSON-encoded `NetworkState`
+pub fn ffi_network_state() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.network_state())?)
+}
+
+/// Set or change the passphrase protecting keystore keys
+///
+/// Changing it needs the keystore unlocked.
+pub fn ffi_set_keystore_passphrase(passphrase: String) -> anyhow::Result<()> {
+    crate::runtime()?.set_keystore_passphrase(&passphrase)?;
+    Ok(())
+}
+
+/// Unlock keystore keys with the passphrase
+///
+/// Keys stay in memory for `auto_lock_secs`. Operations needing them fail
+/// with "Keystore is locked" meanwhile; prompt the user and call this.
+pub fn ffi_unlock_keystore(passphrase: String) -> anyhow::Result<()> {
+    crate::runtime()?.unlock_keystore(&passphrase)?;
+    Ok(())
+}
+
+/// Unlock keystore keys through a platform biometric prompt
+///
+/// `release` shows the prompt and returns the key the app stored behind
+/// it, or an empty list if the user cancelled.
+pub fn ffi_unlock_keystore_biometric(
+    release: impl Fn() -> DartFnFuture<Vec<u8>> + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    let released = executor().block_on(release());
+    let key: [u8; 32] = released
+        .try_into()
+        .map_err(|_| CryptoError::KeystoreLocked)?;
+    crate::runtime()?.unlock_keystore_with(&move || Ok(key))?;
+    Ok(())
+}
+
+/// Zeroize keystore keys now
+pub fn ffi_lock_keystore() -> anyhow::Result<()> {
+    crate::runtime()?.lock_keystore();
+    Ok(())
+}
+
+/// Remove the keystore passphrase, after authenticating if gated
+pub fn ffi_remove_keystore_passphrase() -> anyhow::Result<()> {
+    crate::runtime()?.remove_keystore_passphrase()?;
+    Ok(())
+}
+
+/// Raw identity secret key, after authenticating if gated
+pub fn ffi_export_secret_key() -> anyhow::Result<Vec<u8>> {
+    Ok(crate::runtime()?.export_secret_key()?)
+}
+
+/// Lock state as JSON-encoded `KeystoreStatus`
+pub fn ffi_keystore_status() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.keystore_status())?)
+}
+
+/// Signed wake token asking a paired device to sync with this one
+///
+/// Hand it to the push service that reaches `peer_id`; the token itself
+/// needs no further protection.
+pub fn ffi_wake_token(peer_id: String) -> anyhow::Result<String> {
+    Ok(crate::runtime()?.wake_token(&DeviceId(peer_id))?)
+}
+
+/// Handle a push notification carrying a wake token
+///
+/// Waits for the sending device to connect and syncs with it for at most
+/// `budget_ms`, then returns a JSON-encoded `WakeReport`. End the
+/// platform background task when this returns.
+pub fn ffi_handle_push(payload: String, budget_ms: u32) -> anyhow::Result<String> {
+    let runtime = crate::runtime()?;
+    let budget = Duration::from_millis(budget_ms.into());
+    let report = executor().block_on(runtime.handle_push(&payload, budget))?;
+    Ok(serde_json::to_string(&report)?)
+}
+
+/// Sync with every connected peer within a JSON-encoded `SyncBudget`
+///
+/// Metadata and small artifacts go first. Returns JSON-encoded
+/// `BudgetReport`s listing what is left for the next background window.
+pub fn ffi_background_sync(budget_json: String) -> anyhow::Result<String> {
+    let budget = serde_json::from_str(&budget_json)?;
+    let runtime = crate::runtime()?;
+    let reports = executor().block_on(runtime.background_sync(budget))?;
+    Ok(serde_json::to_string(&reports)?)
+}
+
+/// Selective sync rules for a peer as JSON-encoded `SyncRules`
+pub fn ffi_sync_rules(peer_id: String) -> anyhow::Result<String> {
+    let rules = crate::runtime()?.sync_rules(&DeviceId(peer_id));
+    Ok(serde_json::to_string(&rules)?)
+}
+
+/// Replace a peer's selective sync rules from JSON-encoded `SyncRules`
+///
+/// Returns the JSON-encoded `RuleEvaluation` listing affected artifacts.
+pub fn ffi_set_sync_rules(peer_id: String, rules_json: String) -> anyhow::Result<String> {
+    let rules = serde_json::from_str(&rules_json)?;
+    let evaluation = crate::runtime()?.set_sync_rules(&DeviceId(peer_id), rules)?;
+    Ok(serde_json::to_string(&evaluation)?)
+}
+
+/// Signed pairing offer URL advertising this device as `device_name`
+pub fn ffi_pairing_offer(device_name: String) -> anyhow::Result<String> {
+    Ok(crate::runtime()?.pairing_offer(&device_name)?)
+}
+
+/// Trust the device behind a pairing offer URL, returning its device ID
+pub fn ffi_accept_pairing_offer(offer_url: String) -> anyhow::Result<String> {
+    Ok(crate::runtime()?
+        .accept_pairing_offer(&offer_url)?
+        .to_string())
+}
+
+/// Enter pairing mode as `device_name`, returning the code to show
+///
+/// Needs `ffi_listen` first; the next unpaired device to connect may try
+/// the code, once.
+pub fn ffi_start_code_pairing(device_name: String) -> anyhow::Result<String> {
+    Ok(crate::runtime()?.start_code_pairing(&device_name)?)
+}
+
+/// Leave pairing mode without pairing
+pub fn ffi_cancel_code_pairing() -> anyhow::Result<()> {
+    crate::runtime()?.cancel_code_pairing();
+    Ok(())
+}
+
+/// Pair with the device at `address` showing `code`, returning its
+/// device ID
+///
+/// Needs `ffi_listen` first. A code that fails cannot be tried again.
+pub fn ffi_pair_with_code(
+    address: String,
+    code: String,
+    device_name: String,
+) -> anyhow::Result<String> {
+    let runtime = crate::runtime()?;
+    let paired = executor().block_on(runtime.dial_with_code(&address, &code, &device_name))?;
+    Ok(paired.to_string())
+}
+
+/// List paired devices as JSON-encoded `Vec<TrustedDevice>`
+pub fn ffi_trusted_devices() -> anyhow::Result<String> {
+    let devices: Vec<_> = crate::runtime()?
+        .trust()
+        .read()
+        .unwrap()
+        .list()
+        .cloned()
+        .collect();
+    Ok(serde_json::to_string(&devices)?)
+}
+
+/// Revoke a paired device, returning the JSON-encoded `RevocationRecord`
+pub fn ffi_revoke_device(device_id: String, reason: String) -> anyhow::Result<String> {
+    let record = crate::runtime()?.revoke_device(&DeviceId(device_id), &reason)?;
+    Ok(serde_json::to_string(&record)?)
+}
+
+/// Enroll this device or a paired one under the user of `passphrase`,
+/// returning the JSON-encoded `EnrollmentCertificate`
+pub fn ffi_enroll_device(
+    passphrase: String,
+    device_id: String,
+    device_name: String,
+) -> anyhow::Result<String> {
+    let certificate =
+        crate::runtime()?.enroll_device(&passphrase, &DeviceId(device_id), &device_name)?;
+    Ok(serde_json::to_string(&certificate)?)
+}
+
+/// Withdraw a device from the user of `passphrase`, returning the
+/// JSON-encoded `UserRevocation` to send to the user's other devices
+pub fn ffi_revoke_enrolled(
+    passphrase: String,
+    device_id: String,
+    reason: String,
+) -> anyhow::Result<String> {
+    let revocation =
+        crate::runtime()?.revoke_enrolled(&passphrase, &DeviceId(device_id), &reason)?;
+    Ok(serde_json::to_string(&revocation)?)
+}
+
+/// Trust the user behind a JSON-encoded `EnrollmentCertificate` confirmed
+/// in person, admitting its device
+pub fn ffi_trust_user(certificate_json: String, name: String) -> anyhow::Result<bool> {
+    let certificate = serde_json::from_str(&certificate_json)?;
+    Ok(crate::runtime()?.trust_user(&certificate, &name)?)
+}
+
+/// Admit a device presenting a JSON-encoded `EnrollmentCertificate` from a
+/// trusted user, returning `false` if it was already trusted
+pub fn ffi_admit_enrolled(certificate_json: String) -> anyhow::Result<bool> {
+    let certificate = serde_json::from_str(&certificate_json)?;
+    Ok(crate::runtime()?.admit_enrolled(&certificate)?)
+}
+
+/// Apply a JSON-encoded `UserRevocation` from a trusted user
+pub fn ffi_apply_user_revocation(revocation_json: String) -> anyhow::Result<bool> {
+    let revocation = serde_json::from_str(&revocation_json)?;
+    Ok(crate::runtime()?.apply_user_revocation(&revocation)?)
+}
+
+/// Sign and send this device's identity statement, valid for `ttl_secs`,
+/// returning the JSON-encoded `Attestation`
+pub fn ffi_publish_attestation(
+    device_name: String,
+    user: String,
+    ttl_secs: u64,
+) -> anyhow::Result<String> {
+    let attestation = crate::runtime()?.publish_attestation(
+        &device_name,
+        &user,
+        Duration::from_secs(ttl_secs),
+    )?;
+    Ok(serde_json::to_string(&attestation)?)
+}
+
+/// Vouch for a paired device's statement, returning the JSON-encoded
+/// `Attestation`
+pub fn ffi_countersign_attestation(device_id: String) -> anyhow::Result<String> {
+    let attestation = crate::runtime()?.countersign_attestation(&DeviceId(device_id))?;
+    Ok(serde_json::to_string(&attestation)?)
+}
+
+/// Statements of paired devices as a JSON array of `AttestationInfo`
+pub fn ffi_attestations() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.attestations())?)
+}
+
+/// Rekey the encrypted metadata store, returning the new key generation
+///
+/// Fails unless `encrypt_metadata` is enabled.
+pub fn ffi_rotate_metadata_key() -> anyhow::Result<u32> {
+    Ok(crate::runtime()?.rotate_metadata_key()?)
+}
+
+/// Progress rotating data keys after revocations as JSON-encoded
+/// `ReencryptProgress`
+pub fn ffi_reencryption_progress() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(
+        &crate::runtime()?.reencryption_progress(),
+    )?)
+}
+
+/// Send a text snippet to a connected peer, returning the JSON-encoded
+/// `Snippet`
+pub fn ffi_send_snippet(peer_id: String, text: String) -> anyhow::Result<String> {
+    let snippet = crate::runtime()?.send_snippet(&DeviceId(peer_id), &text)?;
+    Ok(serde_json::to_string(&snippet)?)
+}
+
+/// Keep a JSON-encoded `Snippet` as an artifact, returning the
+/// JSON-encoded `Artifact`
+pub fn ffi_save_snippet(snippet_json: String) -> anyhow::Result<String> {
+    let snippet = serde_json::from_str(&snippet_json)?;
+    let artifact = crate::runtime()?.save_snippet(&snippet)?;
+    Ok(serde_json::to_string(&artifact)?)
+}
+
+/// List stored artifacts as JSON-encoded `Vec<Artifact>`
+pub fn ffi_artifacts() -> anyhow::Result<String> {
+    let artifacts = crate::runtime()?.artifacts().list()?;
+    Ok(serde_json::to_string(&artifacts)?)
+}
+
+/// Page of at most `limit` artifacts as JSON-encoded `Page`
+///
+/// `cursor` is the `next` cursor of the previous page, empty for the first
+/// one; `sort` is `id`, `modified` or `title` and must stay the same
+/// across pages. Prefer this over `ffi_artifacts` for large stores.
+pub fn ffi_list_page(cursor: String, limit: u32, sort: String) -> anyhow::Result<String> {
+    let sort = serde_json::from_value(serde_json::Value::String(sort))?;
+    let cursor = (!cursor.is_empty()).then_some(cursor.as_str());
+    let page = crate::runtime()?.list_page(cursor, limit as usize, sort)?;
+    Ok(serde_json::to_string(&page)?)
+}
+
+/// Page like `ffi_list_page` of JSON-encoded `ArtifactSummary`s keeping
+/// the fields of a JSON-encoded `Projection`
+///
+/// An empty `projection_json` keeps what a list row shows: ID, title and
+/// modification time.
+pub fn ffi_list_summaries(
+    cursor: String,
+    limit: u32,
+    sort: String,
+    projection_json: String,
+) -> anyhow::Result<String> {
+    let sort = serde_json::from_value(serde_json::Value::String(sort))?;
+    let cursor = (!cursor.is_empty()).then_some(cursor.as_str());
+    let projection = projection(&projection_json)?;
+    let page = crate::runtime()?.list_projected(cursor, limit as usize, sort, &projection)?;
+    Ok(serde_json::to_string(&page)?)
+}
+
+fn projection(json: &str) -> anyhow::Result<Projection> {
+    Ok(match json.is_empty() {
+        true => Projection::default(),
+        false => serde_json::from_str(json)?,
+    })
+}
+
+/// Store a JSON array of artifacts in one transaction
+///
+/// The stream carries JSON-encoded `BulkProgress` every hundred artifacts
+/// and ends when the batch is committed.
+pub fn ffi_store_many(artifacts_json: String, sink: StreamSink<String>) -> anyhow::Result<()> {
+    let artifacts: Vec<Artifact> = serde_json::from_str(&artifacts_json)?;
+    let runtime = crate::runtime()?;
+    executor().spawn_blocking(move || {
+        let result = runtime.store_many(&artifacts, |progress| send_progress(&sink, progress));
+        if let Err(e) = result {
+            let _ = sink.add_error(e.to_string());
+        }
+    });
+    Ok(())
+}
+
+/// Delete a JSON array of artifact IDs in one transaction, streaming
+/// JSON-encoded `BulkProgress` like `ffi_store_many`
+pub fn ffi_delete_many(artifact_ids_json: String, sink: StreamSink<String>) -> anyhow::Result<()> {
+    let ids: Vec<String> = serde_json::from_str(&artifact_ids_json)?;
+    let runtime = crate::runtime()?;
+    executor().spawn_blocking(move || {
+        let result = runtime.delete_many(&ids, |progress| send_progress(&sink, progress));
+        if let Err(e) = result {
+            let _ = sink.add_error(e.to_string());
+        }
+    });
+    Ok(())
+}
+
+/// Apply a JSON-encoded `Retag` to a JSON array of artifact IDs in one
+/// transaction, streaming JSON-encoded `BulkProgress` like `ffi_store_many`
+pub fn ffi_retag_many(
+    artifact_ids_json: String,
+    retag_json: String,
+    sink: StreamSink<String>,
+) -> anyhow::Result<()> {
+    let ids: Vec<String> = serde_json::from_str(&artifact_ids_json)?;
+    let retag = serde_json::from_str(&retag_json)?;
+    let runtime = crate::runtime()?;
+    executor().spawn_blocking(move || {
+        let result = runtime.retag_many(&ids, &retag, |progress| send_progress(&sink, progress));
+        if let Err(e) = result {
+            let _ = sink.add_error(e.to_string());
+        }
+    });
+    Ok(())
+}
+
+fn send_progress(sink: &StreamSink<String>, progress: &BulkProgress) {
+    if let Ok(json) = serde_json::to_string(progress) {
+        let _ = sink.add(json);
+    }
+}
+
+/// Move an artifact to the trash, on paired devices too
+pub fn ffi_trash_artifact(artifact_id: String) -> anyhow::Result<()> {
+    crate::runtime()?.trash_artifact(&artifact_id)?;
+    Ok(())
+}
+
+/// Take an artifact back out of the trash
+pub fn ffi_restore_artifact(artifact_id: String) -> anyhow::Result<()> {
+    crate::runtime()?.restore_artifact(&artifact_id)?;
+    Ok(())
+}
+
+/// Artifacts in the trash as JSON-encoded `Vec<Artifact>`, most recently
+/// trashed first
+pub fn ffi_trash() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.trash()?)?)
+}
+
+/// Delete everything in the trash for good, returning the IDs as a JSON
+/// array
+pub fn ffi_empty_trash() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.empty_trash()?)?)
+}
+
+/// Add or replace a custom field schema from a JSON-encoded `MetadataSchema`
+pub fn ffi_register_schema(schema_json: String) -> anyhow::Result<()> {
+    let schema = serde_json::from_str(&schema_json)?;
+    Ok(crate::runtime()?.register_schema(schema)?)
+}
+
+/// Registered custom field schemas as JSON-encoded `Vec<MetadataSchema>`
+pub fn ffi_schemas() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.schemas())?)
+}
+
+/// Artifacts matching a JSON-encoded `FieldQuery` on an indexed field, as
+/// JSON-encoded `Vec<Artifact>`
+pub fn ffi_query_artifacts(query_json: String) -> anyhow::Result<String> {
+    let query = serde_json::from_str(&query_json)?;
+    let artifacts = crate::runtime()?.query_artifacts(&query)?;
+    Ok(serde_json::to_string(&artifacts)?)
+}
+
+/// Matches of a JSON-encoded `FieldQuery` as JSON-encoded
+/// `Vec<ArtifactSummary>`, keeping the fields of a JSON-encoded
+/// `Projection` (empty for the list row defaults)
+pub fn ffi_query_summaries(query_json: String, projection_json: String) -> anyhow::Result<String> {
+    let query = serde_json::from_str(&query_json)?;
+    let projection = projection(&projection_json)?;
+    let summaries = crate::runtime()?.query_projected(&query, &projection)?;
+    Ok(serde_json::to_string(&summaries)?)
+}
+
+/// Back up the whole store now, returning JSON-encoded `BackupInfo`
+pub fn ffi_create_backup(label: String) -> anyhow::Result<String> {
+    let info = crate::runtime()?.create_backup(&label)?;
+    Ok(serde_json::to_string(&info)?)
+}
+
+/// Backups taken so far as JSON-encoded `Vec<BackupInfo>`, oldest first
+pub fn ffi_backups() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.backups())?)
+}
+
+/// Roll the store back to a backup, returning JSON-encoded `RestoreReport`
+pub fn ffi_restore_backup(backup_id: String) -> anyhow::Result<String> {
+    let report = crate::runtime()?.restore_backup(&backup_id, |_| {})?;
+    Ok(serde_json::to_string(&report)?)
+}
+
+/// Delete a backup, letting content only it kept be collected
+pub fn ffi_delete_backup(backup_id: String) -> anyhow::Result<()> {
+    Ok(crate::runtime()?.delete_backup(&backup_id)?)
+}
+
+/// Keep an artifact's content on this device, exempt from eviction
+pub fn ffi_pin_artifact(artifact_id: String) -> anyhow::Result<()> {
+    Ok(crate::runtime()?.pin_artifact(&artifact_id)?)
+}
+
+/// Let an artifact's content be evicted when storage is over quota
+pub fn ffi_unpin_artifact(artifact_id: String) -> anyhow::Result<bool> {
+    Ok(crate::runtime()?.unpin_artifact(&artifact_id)?)
+}
+
+/// Pinned artifact IDs as a JSON array
+pub fn ffi_pinned_artifacts() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(
+        &crate::runtime()?.pinned_artifacts(),
+    )?)
+}
+
+/// Content of an artifact, fetched from a connected peer if evicted
+pub fn ffi_artifact_content(artifact_id: String) -> anyhow::Result<Vec<u8>> {
+    let runtime = crate::runtime()?;
+    Ok(executor().block_on(runtime.retrieve_or_fetch(&artifact_id))?)
+}
+
+/// Sync conflicts waiting for the user as a JSON array of `ConflictRecord`
+pub fn ffi_conflicts() -> anyhow::Result<String> {
+    Ok(serde_json::to_string(&crate::runtime()?.conflicts())?)
+}
+
+/// Settle a sync conflict from a JSON-encoded `Resolution`
+///
+/// `{"choice":"local"}`, `{"choice":"remote"}` or
+/// `{"choice":"merged","content":"..."}`. Returns the resolved artifact as
+/// JSON.
+pub fn ffi_resolve_conflict(
+    artifact_id: String,
+    resolution_json: String,
+) -> anyhow::Result<String> {
+    let resolution = serde_json::from_str(&resolution_json)?;
+    let artifact = crate::runtime()?.resolve_conflict(&artifact_id, resolution)?;
+    Ok(serde_json::to_string(&artifact)?)
+}
+
+/// Permissions of a paired device as JSON-encoded `Permissions`
+pub fn ffi_device_permissions(device_id: String) -> anyhow::Result<String> {
+    let permissions = crate::runtime()?.device_permissions(&DeviceId(device_id))?;
+    Ok(serde_json::to_string(&permissions)?)
+}
+
+/// Replace a paired device's permissions from JSON-encoded `Permissions`
+pub fn ffi_set_device_permissions(
+    device_id: String,
+    permissions_json: String,
+) -> anyhow::Result<()> {
+    let permissions = serde_json::from_str(&permissions_json)?;
+    crate::runtime()?.set_device_permissions(&DeviceId(device_id), permissions)?;
+    Ok(())
+}
+
+/// Issue a share link for one artifact, valid for `ttl_secs` seconds
+pub fn ffi_share_artifact(artifact_id: String, ttl_secs: u64) -> anyhow::Result<String> {
+    let token = crate::runtime()?.share_artifact(&artifact_id, Duration::from_secs(ttl_secs))?;
+    Ok(token.encode()?)
+}
+
+/// List issued share tokens as JSON-encoded `Vec<IssuedShare>`
+pub fn ffi_shares() -> anyhow::Result<String> {
+    let shares: Vec<_> = crate::runtime()?
+        .shares()
+        .read()
+        .unwrap()
+        .list()
+        .cloned()
+        .collect();
+    Ok(serde_json::to_string(&shares)?)
+}
+
+/// Revoke a share token by ID, returning `false` if unknown or revoked
+pub fn ffi_revoke_share(token_id: String) -> anyhow::Result<bool> {
+    Ok(crate::runtime()?.revoke_share(&token_id)?)
+}
+
+/// List collections as JSON-encoded `Vec<Collection>`
+pub fn ffi_collections() -> anyhow::Result<String> {
+    let collections = crate::runtime()?.collections().lock().unwrap().list();
+    Ok(serde_json::to_string(&collections)?)
+}
+
+/// Create a collection, returning the JSON-encoded `Collection`
+///
+/// An empty `parent_id` creates a top-level collection.
+pub fn ffi_create_collection(
+    name: String,
+    parent_id: String,
+    position: i32,
+) -> anyhow::Result<String> {
+    let parent = (!parent_id.is_empty()).then_some(parent_id.as_str());
+    let collection =
+        crate::runtime()?
+            .collections()
+            .lock()
+            .unwrap()
+            .create(name, parent, position.into())?;
+    Ok(serde_json::to_string(&collection)?)
+}
+
+/// Rename a collection
+pub fn ffi_rename_collection(id: String, name: String) -> anyhow::Result<()> {
+    crate::runtime()?
+        .collections()
+        .lock()
+        .unwrap()
+        .rename(&id, name)
+}
+
+/// Move a collection; an empty `parent_id` moves it to the top level
+pub fn ffi_move_collection(id: String, parent_id: String, position: i32) -> anyhow::Result<()> {
+    let parent = (!parent_id.is_empty()).then_some(parent_id.as_str());
+    crate::runtime()?
+        .collections()
+        .lock()
+        .unwrap()
+        .move_to(&id, parent, position.into())
+}
+
+/// Delete a collection and its sub-collections
+pub fn ffi_delete_collection(id: String) -> anyhow::Result<()> {
+    crate::runtime()?.collections().lock().unwrap().delete(&id)
+}
+
+/// Put an artifact into a collection; an empty `collection_id` removes it
+pub fn ffi_set_artifact_collection(
+    artifact_id: String,
+    collection_id: String,
+) -> anyhow::Result<()> {
+    let runtime = crate::runtime()?;
+    let collection = (!collection_id.is_empty()).then_some(collection_id.as_str());
+    let collections = runtime.collections().lock().unwrap();
+    collections.assign(runtime.artifacts().as_ref(), &artifact_id, collection)
+}
+
+/// Derived asset (e.g. "thumbnail", "excerpt") of an artifact
+///
+/// Computed on first request and cached. Returns an empty buffer when the
+/// processor does not apply to the artifact or its content is not local.
+pub fn ffi_derived_asset(
+    artifact_id: String,
+    processor: String,
+    params: String,
+) -> anyhow::Result<Vec<u8>> {
+    let runtime = crate::runtime()?;
+    let artifact = runtime
+        .artifacts()
+        .get(&artifact_id)?
+        .ok_or_else(|| anyhow::anyhow!("Artifact not found: {}", artifact_id))?;
+    Ok(runtime
+        .derived()
+        .get(&artifact, &processor, &params)?
+        .unwrap_or_default())
+}
+
+/// Export artifacts to an encrypted `.nomade` bundle file
+///
+/// `seal_json` is a JSON-encoded `BundleSeal`: `{"password": "..."}` or
+/// `{"recipients": [[...public key bytes...]]}`.
+pub fn ffi_export_bundle(
+    path: String,
+    artifact_ids_json: String,
+    seal_json: String,
+) -> anyhow::Result<()> {
+    let ids: Vec<String> = serde_json::from_str(&artifact_ids_json)?;
+    let seal = serde_json::from_str(&seal_json)?;
+    let bundle = crate::runtime()?.export_bundle(&ids, &seal)?;
+    std::fs::write(path, bundle)?;
+    Ok(())
+}
+
+/// Import a `.nomade` bundle file, returning the JSON-encoded `ImportReport`
+///
+/// An empty `password` opens bundles sealed to this device.
+pub fn ffi_import_bundle(path: String, password: String) -> anyhow::Result<String> {
+    let bundle = std::fs::read(path)?;
+    let password = (!password.is_empty()).then_some(password.as_str());
+    let report = crate::runtime()?.import_bundle(&bundle, password)?;
+    Ok(serde_json::to_string(&report)?)
+}
+
+/// Export artifacts as plain files into the directory at `path`
+///
+/// `artifact_ids_json` is a JSON array of IDs (empty for all artifacts) and
+/// `options_json` a JSON-encoded `ExportOptions` (`{}` for the defaults:
+/// metadata sidecars and collection folders). The stream carries
+/// JSON-encoded `ExportProgress` after each artifact and ends when the
+/// export is done.
+pub fn ffi_export_dir(
+    path: String,
+    artifact_ids_json: String,
+    options_json: String,
+    sink: StreamSink<String>,
+) -> anyhow::Result<()> {
+    let ids: Vec<String> = serde_json::from_str(&artifact_ids_json)?;
+    let options = serde_json::from_str(&options_json)?;
+    let runtime = crate::runtime()?;
+    executor().spawn_blocking(move || {
+        let result = runtime.export_dir(&ids, path.as_ref(), &options, |progress| {
+            if let Ok(json) = serde_json::to_string(progress) {
+                let _ = sink.add(json);
+            }
+        });
+        if let Err(e) = result {
+            let _ = sink.add_error(e.to_string());
+        }
+    });
+    Ok(())
+}
+
+/// Import a directory written by `ffi_export_dir`, returning the
+/// JSON-encoded `ImportReport`
+pub fn ffi_import_dir(path: String) -> anyhow::Result<String> {
+    let report = crate::runtime()?.import_dir(path.as_ref())?;
+    Ok(serde_json::to_string(&report)?)
+}
+
+/// Warm-start snapshot loaded at launch as JSON, or `null` if none
+///
+/// Lets the UI render the artifact list and collections before the
+/// stores are queried.
+pub fn ffi_warm_snapshot() -> anyhow::Result<String> {
+    let runtime = crate::runtime()?;
+    Ok(serde_json::to_string(&runtime.warm_snapshot())?)
+}
@@ -0,0 +1,129 @@
+//! OS authentication before sensitive operations
+//!
+//! Operations that cannot be undone or that expose key material ask the
+//! user to authenticate first, through an `AuthGate` the app implements
+//! with the platform prompt (Face ID, fingerprint, Windows Hello, ...).
+//! `AuthPolicy` lists which operations are gated. Without a registered
+//! gate, as in the daemon and CLI, gated operations proceed: the OS
+//! account already authenticated the user.
+
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::runtime::executor;
+use crate::{CoreError, Result};
+
+/// Upper bound on a platform prompt, including the user's response
+const AUTH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Operation that may require the user to authenticate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensitiveOp {
+    /// Export the device identity key
+    ExportSecretKey,
+    /// Revoke a paired or enrolled device
+    RevokeDevice,
+    /// Remove the passphrase protecting keystore keys
+    DisableEncryption,
+}
+
+impl SensitiveOp {
+    /// Reason shown in the platform prompt
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::ExportSecretKey => "Export this device's secret key",
+            Self::RevokeDevice => "Revoke a device",
+            Self::DisableEncryption => "Turn off keystore encryption",
+        }
+    }
+}
+
+/// Which operations need authentication
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthPolicy {
+    pub gated: BTreeSet<SensitiveOp>,
+}
+
+impl AuthPolicy {
+    /// Whether `op` needs authentication
+    pub fn is_gated(&self, op: SensitiveOp) -> bool {
+        self.gated.contains(&op)
+    }
+}
+
+impl Default for AuthPolicy {
+    fn default() -> Self {
+        Self {
+            gated: [
+                SensitiveOp::ExportSecretKey,
+                SensitiveOp::RevokeDevice,
+                SensitiveOp::DisableEncryption,
+            ]
+            .into(),
+        }
+    }
+}
+
+/// Asks the user to authenticate with the platform
+pub trait AuthGate: Send + Sync {
+    /// Succeed once the user authenticated for `reason`
+    fn require_auth(&self, reason: &str) -> Result<()>;
+}
+
+/// Future resolving to whether the user authenticated
+pub type AuthFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+/// Callback showing the platform prompt with a reason
+pub type AuthFn = dyn Fn(String) -> AuthFuture + Send + Sync;
+
+static REGISTERED: Mutex<Option<Arc<PlatformAuthGate>>> = Mutex::new(None);
+
+/// `AuthGate` delegating to a platform callback
+pub struct PlatformAuthGate {
+    prompt: Arc<AuthFn>,
+}
+
+impl PlatformAuthGate {
+    /// Gate showing prompts through `prompt`
+    pub fn new(prompt: Arc<AuthFn>) -> Self {
+        Self { prompt }
+    }
+}
+
+impl AuthGate for PlatformAuthGate {
+    fn require_auth(&self, reason: &str) -> Result<()> {
+        // Wait off the executor, like platform signatures
+        let (tx, rx) = std::sync::mpsc::channel();
+        let prompt = (self.prompt)(reason.to_string());
+        executor().spawn(async move {
+            let _ = tx.send(prompt.await);
+        });
+        match rx.recv_timeout(AUTH_TIMEOUT) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(CoreError::AuthDenied(reason.to_string())),
+            Err(_) => Err(CoreError::AuthDenied(format!("{} (timed out)", reason))),
+        }
+    }
+}
+
+/// Use a platform gate for runtimes started from now on
+pub fn register(prompt: Arc<AuthFn>) {
+    *REGISTERED.lock().unwrap() = Some(Arc::new(PlatformAuthGate::new(prompt)));
+}
+
+/// Stop prompting for runtimes started from now on
+pub fn unregister() {
+    REGISTERED.lock().unwrap().take();
+}
+
+/// Registered platform gate, if any
+pub(crate) fn registered() -> Option<Arc<PlatformAuthGate>> {
+    REGISTERED.lock().unwrap().clone()
+}
@@ -0,0 +1,594 @@
+//! Runtime configuration
+//!
+//! `NomadeConfig` is supplied once at startup (from Flutter via `ffi_init`),
+//! validated up front, and then shared read-only with every subsystem
+//! through a `Context` handle.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use nomade_events::BatchConfig;
+use nomade_quic::{NetworkState, ProxyConfig, RateLimits, Timeouts};
+use nomade_storage::compress::DEFAULT_LEVEL;
+use nomade_storage::{Compression, StoreBackends};
+use nomade_sync::{ReencryptScope, TransferMode};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthPolicy;
+use crate::{CoreError, Result};
+
+/// Artifact database directory under the data directory
+pub(crate) const ARTIFACTS_DIR: &str = "artifacts";
+
+/// Artifact file store under the data directory, in builds without sled
+#[cfg(not(feature = "sled"))]
+pub(crate) const ARTIFACT_FILES_DIR: &str = "artifact_files";
+
+/// Where the runtime keeps its state
+///
+/// `Persistent` keeps the artifact store and all runtime state under the
+/// data directory; the artifact store is a sled database, or a file store
+/// in builds without the `sled` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Volatile, everything in memory (tests, previews)
+    Memory,
+    /// Persistent under the data directory
+    #[serde(alias = "sled")]
+    Persistent,
+}
+
+/// Log verbosity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// Level name understood by tracing filters
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+/// Network settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Address the QUIC listener binds to
+    pub bind_address: IpAddr,
+    /// QUIC listen port (0 picks a free port)
+    pub listen_port: u16,
+    /// Ports from `listen_port` on tried in turn while busy, before
+    /// letting the OS pick one; `discovery_port` is skipped
+    pub listen_port_range: u16,
+    /// Ask the router to forward the listen port (NAT-PMP or UPnP)
+    pub port_mapping: bool,
+    /// Local discovery port (0 disables discovery)
+    pub discovery_port: u16,
+    /// Rate limits and admission rules for incoming connections
+    pub limits: RateLimits,
+    /// Proxy for outbound connections; these then go over WebSocket
+    pub proxy: Option<ProxyConfig>,
+    /// Handshake, idle, request and chunk stall timeouts
+    pub timeouts: Timeouts,
+    /// Interval between keepalive pings on peer links (0 disables them)
+    pub keepalive_secs: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            listen_port: 8765,
+            listen_port_range: 10,
+            port_mapping: false,
+            discovery_port: 8766,
+            limits: RateLimits::default(),
+            proxy: None,
+            timeouts: Timeouts::default(),
+            keepalive_secs: 5,
+        }
+    }
+}
+
+/// When and how to sync with peers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncPolicy {
+    /// Sync automatically with connected peers
+    pub auto_sync: bool,
+    /// Interval between automatic syncs in seconds
+    pub interval_secs: u64,
+    /// Allow syncing over metered connections
+    pub allow_metered: bool,
+    /// Maximum number of concurrent artifact transfers
+    pub max_concurrent_transfers: usize,
+    /// zstd level for stored content and sync payloads; 0 disables compression
+    pub compression_level: i32,
+    /// Fetch modified large files as deltas of the local version
+    pub delta_transfer: bool,
+    /// Content whose data keys are rotated after revoking a device
+    pub reencrypt_scope: ReencryptScope,
+}
+
+impl SyncPolicy {
+    /// Compression to apply, if enabled
+    pub fn compression(&self) -> Option<Compression> {
+        (self.compression_level > 0).then(|| Compression::new(self.compression_level))
+    }
+
+    /// How transfers run on the given network
+    ///
+    /// Offline pauses them, as does a metered network unless allowed; an
+    /// allowed metered network fetches from one source at a time.
+    pub fn transfer_mode(&self, network: &NetworkState) -> TransferMode {
+        if !network.is_online() || (network.is_metered() && !self.allow_metered) {
+            TransferMode::Paused
+        } else if network.is_metered() {
+            TransferMode::Reduced
+        } else {
+            TransferMode::Full
+        }
+    }
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self {
+            auto_sync: true,
+            interval_secs: 300,
+            allow_metered: false,
+            max_concurrent_transfers: 4,
+            compression_level: DEFAULT_LEVEL,
+            delta_transfer: true,
+            reencrypt_scope: ReencryptScope::Modified,
+        }
+    }
+}
+
+/// How events are delivered to the app
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventConfig {
+    /// Window in milliseconds within which bursts of events are batched
+    pub batch_window_ms: u64,
+    /// Most events delivered in one batch
+    pub max_batch: usize,
+    /// Socket (pipe name on Windows) serving events to other local
+    /// processes; `None` disables the bridge
+    pub ipc_socket: Option<PathBuf>,
+}
+
+impl EventConfig {
+    /// Batching settings for the app's event stream
+    pub fn batch(&self) -> BatchConfig {
+        BatchConfig {
+            window: Duration::from_millis(self.batch_window_ms),
+            max_events: self.max_batch,
+        }
+    }
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        let batch = BatchConfig::default();
+        Self {
+            batch_window_ms: batch.window.as_millis() as u64,
+            max_batch: batch.max_events,
+            ipc_socket: None,
+        }
+    }
+}
+
+/// Desktop folder mirrored as artifacts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedFolder {
+    /// Absolute path of the folder
+    pub path: PathBuf,
+    /// Glob patterns (`*` and `?`) of file and directory names left alone
+    #[serde(default = "default_folder_ignore")]
+    pub ignore: Vec<String>,
+    /// Quiet time in milliseconds before changed files are ingested
+    #[serde(default = "default_folder_debounce_ms")]
+    pub debounce_ms: u64,
+}
+
+impl SyncedFolder {
+    /// Mirror `path` with the default ignore patterns and debounce
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            ignore: default_folder_ignore(),
+            debounce_ms: default_folder_debounce_ms(),
+        }
+    }
+}
+
+fn default_folder_ignore() -> Vec<String> {
+    [
+        ".git",
+        ".DS_Store",
+        "Thumbs.db",
+        "*.tmp",
+        "*.swp",
+        "*~",
+        "~$*",
+        ".#*",
+        ".nomade-*",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_folder_debounce_ms() -> u64 {
+    500
+}
+
+/// Nomade runtime configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NomadeConfig {
+    /// Directory holding all persistent state
+    pub data_dir: PathBuf,
+    #[serde(default = "default_storage_backend")]
+    pub storage_backend: StorageBackend,
+    /// Artifact store backend URL (`memory://`, `sled:///path`, ...)
+    /// overriding the `storage_backend` store under the data directory
+    #[serde(default)]
+    pub storage_url: Option<String>,
+    #[serde(default = "default_log_level")]
+    pub log_level: LogLevel,
+    /// Per-module overrides of `log_level`, keyed by target prefix
+    #[serde(default)]
+    pub log_filters: BTreeMap<String, LogLevel>,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub sync: SyncPolicy,
+    #[serde(default)]
+    pub events: EventConfig,
+    /// Bytes of content kept locally before unpinned content is evicted;
+    /// unlimited when `None`
+    #[serde(default)]
+    pub content_quota_bytes: Option<u64>,
+    /// Encrypt the `sqlite://` artifact store (titles, tags) with SQLCipher
+    /// under a key derived from the device identity
+    #[serde(default)]
+    pub encrypt_metadata: bool,
+    /// Operations that ask the user to authenticate
+    #[serde(default)]
+    pub auth: AuthPolicy,
+    /// Seconds keystore keys stay unlocked before they are zeroized
+    #[serde(default = "default_auto_lock_secs")]
+    pub auto_lock_secs: u64,
+    /// Days artifacts stay in the trash before they are deleted for good;
+    /// 0 keeps them until the trash is emptied
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Folder mirrored as artifacts (desktop builds with `folder-sync`)
+    #[serde(default)]
+    pub synced_folder: Option<SyncedFolder>,
+    /// Paranoid mode: check the crypto primitives against known-answer
+    /// vectors (`nomade_crypto::self_test`) before opening anything
+    #[serde(default)]
+    pub paranoid: bool,
+}
+
+impl NomadeConfig {
+    /// Create configuration with defaults for everything but the data directory
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            storage_backend: default_storage_backend(),
+            storage_url: None,
+            log_level: default_log_level(),
+            log_filters: BTreeMap::new(),
+            network: NetworkConfig::default(),
+            sync: SyncPolicy::default(),
+            events: EventConfig::default(),
+            content_quota_bytes: None,
+            encrypt_metadata: false,
+            auth: AuthPolicy::default(),
+            auto_lock_secs: default_auto_lock_secs(),
+            trash_retention_days: default_trash_retention_days(),
+            synced_folder: None,
+            paranoid: false,
+        }
+    }
+
+    /// URL of the artifact store the runtime opens
+    pub fn artifact_store_url(&self) -> String {
+        match (&self.storage_url, self.storage_backend) {
+            (Some(url), _) => url.clone(),
+            (None, StorageBackend::Memory) => "memory://".into(),
+            #[cfg(feature = "sled")]
+            (None, StorageBackend::Persistent) => {
+                format!("sled://{}", self.data_dir.join(ARTIFACTS_DIR).display())
+            }
+            #[cfg(not(feature = "sled"))]
+            (None, StorageBackend::Persistent) => {
+                format!("fs://{}", self.data_dir.join(ARTIFACT_FILES_DIR).display())
+            }
+        }
+    }
+
+    /// Time keystore keys stay unlocked
+    pub fn auto_lock(&self) -> Duration {
+        Duration::from_secs(self.auto_lock_secs)
+    }
+
+    /// Parse configuration from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|e| CoreError::InvalidConfig(e.to_string()))
+    }
+
+    /// Check the configuration for inconsistent or unusable values
+    pub fn validate(&self) -> Result<()> {
+        if self.data_dir.as_os_str().is_empty() {
+            return Err(CoreError::InvalidConfig("data_dir must be set".into()));
+        }
+        if !self.data_dir.is_absolute() {
+            return Err(CoreError::InvalidConfig(format!(
+                "data_dir must be absolute: {}",
+                self.data_dir.display()
+            )));
+        }
+        if self.data_dir.exists() && !self.data_dir.is_dir() {
+            return Err(CoreError::InvalidConfig(format!(
+                "data_dir is not a directory: {}",
+                self.data_dir.display()
+            )));
+        }
+
+        let url = self.artifact_store_url();
+        if !StoreBackends::builtin().supports(&url) {
+            return Err(CoreError::InvalidConfig(format!(
+                "storage backend is not available in this build: {}",
+                url
+            )));
+        }
+
+        if self.encrypt_metadata
+            && !self
+                .storage_url
+                .as_deref()
+                .is_some_and(|url| url.starts_with("sqlite://"))
+        {
+            return Err(CoreError::InvalidConfig(
+                "encrypt_metadata needs a sqlite:// storage_url".into(),
+            ));
+        }
+
+        if self.log_filters.keys().any(|target| target.is_empty()) {
+            return Err(CoreError::InvalidConfig(
+                "log_filters targets must not be empty".into(),
+            ));
+        }
+
+        let network = &self.network;
+        if network.listen_port != 0 && network.listen_port == network.discovery_port {
+            return Err(CoreError::InvalidConfig(format!(
+                "listen_port and discovery_port both set to {}",
+                network.listen_port
+            )));
+        }
+        if let Some(proxy) = &network.proxy {
+            proxy
+                .validate()
+                .map_err(|e| CoreError::InvalidConfig(format!("network.proxy: {}", e)))?;
+        }
+
+        let sync = &self.sync;
+        if sync.auto_sync && sync.interval_secs == 0 {
+            return Err(CoreError::InvalidConfig(
+                "sync.interval_secs must be positive when auto_sync is enabled".into(),
+            ));
+        }
+        if sync.max_concurrent_transfers == 0 {
+            return Err(CoreError::InvalidConfig(
+                "sync.max_concurrent_transfers must be at least 1".into(),
+            ));
+        }
+        if !(0..=22).contains(&sync.compression_level) {
+            return Err(CoreError::InvalidConfig(format!(
+                "sync.compression_level must be between 0 and 22, got {}",
+                sync.compression_level
+            )));
+        }
+        if let Some(folder) = &self.synced_folder {
+            if !folder.path.is_absolute() {
+                return Err(CoreError::InvalidConfig(format!(
+                    "synced_folder.path must be absolute: {}",
+                    folder.path.display()
+                )));
+            }
+            if folder.path.starts_with(&self.data_dir) || self.data_dir.starts_with(&folder.path) {
+                return Err(CoreError::InvalidConfig(
+                    "synced_folder.path must not overlap data_dir".into(),
+                ));
+            }
+        }
+        if self.auto_lock_secs == 0 {
+            return Err(CoreError::InvalidConfig(
+                "auto_lock_secs must be positive".into(),
+            ));
+        }
+        if self.events.max_batch == 0 {
+            return Err(CoreError::InvalidConfig(
+                "events.max_batch must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_storage_backend() -> StorageBackend {
+    StorageBackend::Persistent
+}
+
+fn default_log_level() -> LogLevel {
+    LogLevel::Info
+}
+
+fn default_auto_lock_secs() -> u64 {
+    nomade_crypto::vault::DEFAULT_AUTO_LOCK.as_secs()
+}
+
+fn default_trash_retention_days() -> u64 {
+    nomade_storage::trash::DEFAULT_RETENTION_DAYS
+}
+
+/// Shared handle giving subsystems access to the validated configuration
+#[derive(Debug, Clone)]
+pub struct Context {
+    config: Arc<NomadeConfig>,
+}
+
+impl Context {
+    /// Validate configuration and create a context
+    pub fn new(config: NomadeConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Validated configuration
+    pub fn config(&self) -> &NomadeConfig {
+        &self.config
+    }
+}
+
+static CONTEXT: RwLock<Option<Context>> = RwLock::new(None);
+
+/// Install the process-wide context
+pub(crate) fn install(context: Context) -> Result<()> {
+    let mut slot = CONTEXT.write().unwrap();
+    if slot.is_some() {
+        return Err(CoreError::AlreadyInitialized);
+    }
+    *slot = Some(context);
+    Ok(())
+}
+
+/// Remove the process-wide context
+pub(crate) fn uninstall() -> Option<Context> {
+    CONTEXT.write().unwrap().take()
+}
+
+/// Get the process-wide context installed by `init()`
+pub fn context() -> Result<Context> {
+    CONTEXT
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or(CoreError::NotInitialized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_quic::ProxyKind;
+
+    fn data_dir() -> PathBuf {
+        std::env::temp_dir().join("nomade-config-test")
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        let config = NomadeConfig::new(data_dir());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.storage_backend, StorageBackend::Persistent);
+        assert!(StoreBackends::builtin().supports(&config.artifact_store_url()));
+    }
+
+    #[test]
+    fn test_from_json_applies_defaults() {
+        let json = format!(
+            r#"{{"data_dir": {:?}, "storage_backend": "memory", "network": {{"listen_port": 0}}}}"#,
+            data_dir()
+        );
+        let config = NomadeConfig::from_json(&json).unwrap();
+
+        assert_eq!(config.storage_backend, StorageBackend::Memory);
+        assert_eq!(config.log_level, LogLevel::Info);
+        assert_eq!(config.network.listen_port, 0);
+        assert_eq!(config.network.discovery_port, 8766);
+        assert_eq!(config.sync, SyncPolicy::default());
+        assert_eq!(config.artifact_store_url(), "memory://");
+
+        // Configurations written before the switch was renamed
+        let json = format!(
+            r#"{{"data_dir": {:?}, "storage_backend": "sled"}}"#,
+            data_dir()
+        );
+        let config = NomadeConfig::from_json(&json).unwrap();
+        assert_eq!(config.storage_backend, StorageBackend::Persistent);
+    }
+
+    #[test]
+    fn test_storage_url_overrides_backend() {
+        let mut config = NomadeConfig::new(data_dir());
+        #[cfg(feature = "sled")]
+        assert!(config.artifact_store_url().starts_with("sled://"));
+        #[cfg(not(feature = "sled"))]
+        assert!(config.artifact_store_url().starts_with("fs://"));
+
+        config.storage_url = Some("memory://".into());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.artifact_store_url(), "memory://");
+
+        config.storage_url = Some("s3://bucket".into());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_values() {
+        assert!(NomadeConfig::new("").validate().is_err());
+        assert!(NomadeConfig::new("relative/dir").validate().is_err());
+
+        let mut config = NomadeConfig::new(data_dir());
+        config.network.discovery_port = config.network.listen_port;
+        assert!(config.validate().is_err());
+
+        let mut config = NomadeConfig::new(data_dir());
+        config.network.proxy = Some(ProxyConfig::new(ProxyKind::Socks5, "proxy.corp"));
+        assert!(config.validate().is_err());
+        config.network.proxy = Some(ProxyConfig::new(ProxyKind::Socks5, "proxy.corp:1080"));
+        assert!(config.validate().is_ok());
+
+        let mut config = NomadeConfig::new(data_dir());
+        config.sync.interval_secs = 0;
+        assert!(config.validate().is_err());
+        config.sync.auto_sync = false;
+        assert!(config.validate().is_ok());
+
+        let mut config = NomadeConfig::new(data_dir());
+        config.sync.compression_level = 23;
+        assert!(config.validate().is_err());
+        config.sync.compression_level = 0;
+        assert!(config.sync.compression().is_none());
+
+        let mut config = NomadeConfig::new(data_dir());
+        config.sync.max_concurrent_transfers = 0;
+        assert!(matches!(
+            Context::new(config),
+            Err(CoreError::InvalidConfig(_))
+        ));
+    }
+}
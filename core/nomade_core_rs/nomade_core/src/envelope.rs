@@ -0,0 +1,456 @@
+//! Signed envelopes on links
+//!
+//! When both devices negotiate `SIGNED_FRAMES`, the one that dialed seals
+//! a fresh link key to the other's identity key and sends it on the hello
+//! channel. Sync and event payloads then travel as `SignedEnvelope`s under
+//! that key, signed by the device that sent them. Received envelopes are
+//! opened with the sender's public key from the trust store, so a payload
+//! is attributable to the paired device rather than to whoever holds the
+//! connection, and a peer revoked mid-link is refused at once.
+//!
+//! Inside the envelope every payload is encrypted by a `RatchetSession`
+//! shared with the peer, so a key leaked from one link does not expose the
+//! payloads sent before or after it. The session is saved per peer when a
+//! link closes and taken back out when the next one attaches: both devices
+//! announce the session they hold next to the link key and resume it when
+//! they agree, otherwise a new one is seeded from the link key. A saved
+//! session is deleted as it is loaded, so one that outlives a crash is
+//! never resumed with message keys it already used. The dialing device
+//! then sends a first ratchet message, which the other must open before
+//! the link is up; only then can it send under a new session.
+//!
+//! Hardware-backed identity keys sign only on demand and cannot open
+//! sealed keys, so such devices do not offer the feature.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use nomade_crypto::fs::sync_parent;
+use nomade_crypto::{
+    open_sealed_key, seal_key, CryptoError, DeviceId, DeviceKeypair, KeyPurpose, RatchetMessage,
+    RatchetSession, SignedEnvelope, TrustStore,
+};
+use nomade_quic::{Channel, Direction, Frame, FrameSeal, MessageType, ProtocolError};
+use serde::{Deserialize, Serialize};
+
+use crate::runtime::NomadeRuntime;
+use crate::Result;
+
+/// Payload of the dialer's first ratchet message
+const RATCHET_CONFIRMATION: &[u8] = b"nomade/link";
+
+/// `FrameSeal` of one link, sealing envelopes for `peer`
+pub(crate) struct EnvelopeSeal {
+    keypair: DeviceKeypair,
+    peer: DeviceId,
+    trust: Arc<RwLock<TrustStore>>,
+    key: [u8; 32],
+    ratchet: Mutex<RatchetSession>,
+}
+
+impl EnvelopeSeal {
+    fn new(
+        keypair: DeviceKeypair,
+        peer: DeviceId,
+        trust: Arc<RwLock<TrustStore>>,
+        key: [u8; 32],
+        ratchet: RatchetSession,
+    ) -> Self {
+        Self {
+            keypair,
+            peer,
+            trust,
+            key,
+            ratchet: Mutex::new(ratchet),
+        }
+    }
+
+    /// Copy of the ratchet session, to keep for the next link
+    pub(crate) fn ratchet(&self) -> RatchetSession {
+        self.ratchet.lock().unwrap().clone()
+    }
+}
+
+impl FrameSeal for EnvelopeSeal {
+    fn seal(&self, payload: &[u8]) -> nomade_quic::Result<Vec<u8>> {
+        let message = self
+            .ratchet
+            .lock()
+            .unwrap()
+            .encrypt(payload)
+            .map_err(bad_seal)?;
+        let envelope =
+            SignedEnvelope::seal(&self.keypair, &self.peer, &self.key, &message.to_bytes())
+                .map_err(bad_seal)?;
+        Ok(envelope.to_bytes())
+    }
+
+    fn open(&self, sealed: &[u8]) -> nomade_quic::Result<Vec<u8>> {
+        let envelope = SignedEnvelope::from_bytes(sealed).map_err(bad_seal)?;
+        if envelope.sender != self.peer {
+            return Err(ProtocolError::BadSeal(format!(
+                "Sealed by {}, not {}",
+                envelope.sender, self.peer
+            )));
+        }
+        let public_key = {
+            let trust = self.trust.read().unwrap();
+            trust.check_handshake(&self.peer).map_err(bad_seal)?;
+            trust
+                .get(&self.peer)
+                .map(|device| device.public_key.clone())
+                .ok_or_else(|| bad_seal(CryptoError::UntrustedDevice(self.peer.clone())))?
+        };
+        let message = envelope
+            .open(&public_key, self.keypair.device_id(), &self.key)
+            .and_then(|opened| RatchetMessage::from_bytes(&opened))
+            .map_err(bad_seal)?;
+        self.ratchet
+            .lock()
+            .unwrap()
+            .decrypt(&message)
+            .map_err(bad_seal)
+    }
+}
+
+fn bad_seal(e: CryptoError) -> ProtocolError {
+    ProtocolError::BadSeal(e.to_string())
+}
+
+/// Link key offer the dialing device sends on the hello channel
+#[derive(Serialize, Deserialize)]
+struct LinkKeyOffer {
+    /// Ephemeral public key the link key is sealed with
+    ephemeral: [u8; 32],
+    /// ID of the ratchet session the dialer holds for the peer, if any
+    ratchet: Option<[u8; 16]>,
+}
+
+/// Ratchet sessions of peers between links
+///
+/// With a directory each session is a file encrypted under a device key
+/// (`KeyPurpose::RatchetState`), otherwise they only live in memory.
+pub(crate) struct RatchetStore {
+    sessions: Mutex<HashMap<DeviceId, RatchetSession>>,
+    dir: Option<PathBuf>,
+}
+
+impl RatchetStore {
+    /// Store kept in memory
+    pub(crate) fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            dir: None,
+        }
+    }
+
+    /// Store keeping sessions in `dir`, created if missing
+    pub(crate) fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            sessions: Mutex::new(HashMap::new()),
+            dir: Some(dir.as_ref().to_path_buf()),
+        })
+    }
+
+    /// Remove and return the session saved for `peer`
+    ///
+    /// The file is gone for good before the session is used, so a link
+    /// that crashes cannot leave it behind to be resumed again.
+    pub(crate) fn take(&self, peer: &DeviceId, key: &[u8; 32]) -> Result<Option<RatchetSession>> {
+        let Some(dir) = &self.dir else {
+            return Ok(self.sessions.lock().unwrap().remove(peer));
+        };
+        let path = session_path(dir, peer);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let loaded = RatchetSession::load(&path, key);
+        std::fs::remove_file(&path)?;
+        sync_parent(&path)?;
+        match loaded {
+            Ok(session) => Ok(Some(session)),
+            Err(e) => {
+                tracing::warn!("Dropped the unreadable ratchet session of {}: {}", peer, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// ID of the session kept in memory for `peer`
+    #[cfg(test)]
+    pub(crate) fn held(&self, peer: &DeviceId) -> Option<[u8; 16]> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(peer)
+            .map(RatchetSession::id)
+    }
+
+    /// Keep `session` for the next link to `peer`
+    pub(crate) fn save(
+        &self,
+        peer: &DeviceId,
+        session: RatchetSession,
+        key: &[u8; 32],
+    ) -> Result<()> {
+        match &self.dir {
+            Some(dir) => session.save(session_path(dir, peer), key)?,
+            None => {
+                self.sessions.lock().unwrap().insert(peer.clone(), session);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn session_path(dir: &Path, peer: &DeviceId) -> PathBuf {
+    dir.join(format!("{}.json", peer))
+}
+
+impl NomadeRuntime {
+    /// Whether this device can sign every frame it sends
+    pub(crate) fn can_sign_frames(&self) -> bool {
+        !self.keystore().keypair().is_hardware_backed()
+    }
+
+    /// Agree on the link key and ratchet session with `peer` on the hello
+    /// channel and return the link's seal
+    pub(crate) async fn exchange_link_key(
+        &self,
+        peer: &DeviceId,
+        hello: &mut Channel,
+        direction: Direction,
+    ) -> Result<Arc<EnvelopeSeal>> {
+        let keypair = self.keystore().keypair();
+        let saved = self
+            .ratchet_sessions()
+            .take(peer, &self.ratchet_state_key()?)?;
+        let held = saved.as_ref().map(RatchetSession::id);
+        let (key, ratchet) = match direction {
+            Direction::Outbound => {
+                let public_key = self
+                    .trust()
+                    .read()
+                    .unwrap()
+                    .get(peer)
+                    .map(|device| device.public_key.clone())
+                    .ok_or_else(|| CryptoError::UntrustedDevice(peer.clone()))?;
+                let (ephemeral, key) = seal_key(&public_key)?;
+                let offer = LinkKeyOffer {
+                    ephemeral,
+                    ratchet: held,
+                };
+                hello
+                    .send(&Frame::from_message(MessageType::Handshake, &offer)?)
+                    .await?;
+                let remote: Option<[u8; 16]> = recv_handshake(hello).await?.to_message()?;
+                let mut ratchet = match saved.filter(|_| held == remote) {
+                    Some(saved) => saved,
+                    None => RatchetSession::initiator(&key),
+                };
+                let confirmation = ratchet.encrypt(RATCHET_CONFIRMATION)?;
+                hello
+                    .send(&Frame::new(MessageType::Handshake, confirmation.to_bytes()))
+                    .await?;
+                (key, ratchet)
+            }
+            Direction::Inbound => {
+                let offer: LinkKeyOffer = recv_handshake(hello).await?.to_message()?;
+                let key = open_sealed_key(keypair, &offer.ephemeral)?;
+                hello
+                    .send(&Frame::from_message(MessageType::Handshake, &held)?)
+                    .await?;
+                let mut ratchet = match saved.filter(|_| held == offer.ratchet) {
+                    Some(saved) => saved,
+                    None => RatchetSession::responder(&key),
+                };
+                let confirmation =
+                    RatchetMessage::from_bytes(&recv_handshake(hello).await?.payload)?;
+                if ratchet.decrypt(&confirmation)? != RATCHET_CONFIRMATION {
+                    return Err(
+                        ProtocolError::BadSeal("Unexpected ratchet confirmation".into()).into(),
+                    );
+                }
+                (key, ratchet)
+            }
+        };
+        Ok(Arc::new(EnvelopeSeal::new(
+            keypair.clone(),
+            peer.clone(),
+            self.trust().clone(),
+            key,
+            ratchet,
+        )))
+    }
+
+    /// Keep the ratchet session of a closed link for the next one
+    pub(crate) fn save_ratchet(&self, peer: &DeviceId, seal: &EnvelopeSeal) -> Result<()> {
+        self.ratchet_sessions()
+            .save(peer, seal.ratchet(), &self.ratchet_state_key()?)
+    }
+
+    fn ratchet_state_key(&self) -> Result<[u8; 32]> {
+        Ok(self.keystore().device_key(KeyPurpose::RatchetState, b"")?)
+    }
+}
+
+async fn recv_handshake(hello: &mut Channel) -> Result<Frame> {
+    let frame = hello.recv().await?.ok_or(ProtocolError::Truncated)?;
+    if frame.message_type != MessageType::Handshake {
+        return Err(ProtocolError::UnexpectedMessage(frame.message_type).into());
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::generate_keypair;
+
+    /// Seals of both ends of a link between `laptop` and `phone`
+    fn link(laptop: &DeviceKeypair, phone: &DeviceKeypair) -> (EnvelopeSeal, EnvelopeSeal) {
+        let trusting = |device: &DeviceKeypair| {
+            let mut trust = TrustStore::new();
+            trust
+                .add_trusted(
+                    device.device_id().clone(),
+                    "Device".into(),
+                    device.public_key_bytes(),
+                )
+                .unwrap();
+            Arc::new(RwLock::new(trust))
+        };
+        let key = [7; 32];
+        (
+            EnvelopeSeal::new(
+                laptop.clone(),
+                phone.device_id().clone(),
+                trusting(phone),
+                key,
+                RatchetSession::initiator(&key),
+            ),
+            EnvelopeSeal::new(
+                phone.clone(),
+                laptop.device_id().clone(),
+                trusting(laptop),
+                key,
+                RatchetSession::responder(&key),
+            ),
+        )
+    }
+
+    #[test]
+    fn test_rejects_tampered_and_missigned_payloads() {
+        let (laptop, phone) = (generate_keypair(), generate_keypair());
+        let (on_laptop, on_phone) = link(&laptop, &phone);
+
+        let sealed = on_laptop.seal(b"manifest").unwrap();
+        assert_eq!(on_phone.open(&sealed).unwrap(), b"manifest");
+        // Nor is a payload sealed for the phone accepted back by the laptop
+        assert!(on_laptop.open(&sealed).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            on_phone.open(&tampered),
+            Err(ProtocolError::BadSeal(_))
+        ));
+
+        // A third device holding the link key cannot pass as the laptop
+        let mallory = generate_keypair();
+        let mut forged =
+            SignedEnvelope::seal(&mallory, phone.device_id(), &[7; 32], b"manifest").unwrap();
+        assert!(on_phone.open(&forged.to_bytes()).is_err());
+        forged.sender = laptop.device_id().clone();
+        assert!(on_phone.open(&forged.to_bytes()).is_err());
+
+        // Payloads of a revoked device are refused from then on
+        on_phone
+            .trust
+            .write()
+            .unwrap()
+            .revoke(&phone, laptop.device_id().clone(), "lost".into())
+            .unwrap();
+        assert!(on_phone
+            .open(&on_laptop.seal(b"manifest").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_opens_frames_out_of_order_once() {
+        let (laptop, phone) = (generate_keypair(), generate_keypair());
+        let (on_laptop, on_phone) = link(&laptop, &phone);
+
+        let frames: Vec<_> = (0..4u8).map(|i| on_laptop.seal(&[i]).unwrap()).collect();
+        for i in [2, 0, 3, 1] {
+            assert_eq!(on_phone.open(&frames[i]).unwrap(), [i as u8]);
+        }
+        // Every frame opens once, so a replay is refused
+        assert!(on_phone.open(&frames[2]).is_err());
+
+        // Once it has heard from the laptop the phone answers under new keys
+        let reply = on_phone.seal(b"index").unwrap();
+        let late = on_laptop.seal(b"late").unwrap();
+        assert_eq!(on_laptop.open(&reply).unwrap(), b"index");
+        assert_eq!(on_phone.open(&late).unwrap(), b"late");
+    }
+
+    #[test]
+    fn test_ratchet_sessions_resume_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone) = (generate_keypair(), generate_keypair());
+        let (on_laptop, on_phone) = link(&laptop, &phone);
+        on_phone
+            .open(&on_laptop.seal(b"manifest").unwrap())
+            .unwrap();
+        // Sealed, but lost with the link
+        on_phone.seal(b"chunk").unwrap();
+
+        let store = RatchetStore::open(dir.path()).unwrap();
+        let key = [3; 32];
+        store
+            .save(phone.device_id(), on_laptop.ratchet(), &key)
+            .unwrap();
+        store
+            .save(laptop.device_id(), on_phone.ratchet(), &key)
+            .unwrap();
+        let saved = store.take(phone.device_id(), &key).unwrap().unwrap();
+        assert_eq!(saved.id(), on_phone.ratchet().id());
+        // A session resumes on one link only
+        assert!(store.take(phone.device_id(), &key).unwrap().is_none());
+
+        // The next link picks up where the last one stopped, under its own
+        // link key
+        let resumed_laptop = EnvelopeSeal::new(
+            laptop.clone(),
+            phone.device_id().clone(),
+            on_laptop.trust.clone(),
+            [8; 32],
+            saved,
+        );
+        let resumed_phone = EnvelopeSeal::new(
+            phone.clone(),
+            laptop.device_id().clone(),
+            on_phone.trust.clone(),
+            [8; 32],
+            store.take(laptop.device_id(), &key).unwrap().unwrap(),
+        );
+        let index = resumed_phone.seal(b"index").unwrap();
+        assert_eq!(resumed_laptop.open(&index).unwrap(), b"index");
+        let chunk = resumed_laptop.seal(b"chunk").unwrap();
+        assert_eq!(resumed_phone.open(&chunk).unwrap(), b"chunk");
+        // Frames of the old link do not open on the new one
+        assert!(resumed_phone
+            .open(&on_laptop.seal(b"stale").unwrap())
+            .is_err());
+
+        // An unreadable session is dropped rather than failing every link
+        store
+            .save(phone.device_id(), resumed_laptop.ratchet(), &key)
+            .unwrap();
+        assert!(store.take(phone.device_id(), &[4; 32]).unwrap().is_none());
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,685 @@
+//! Desktop synced folder
+//!
+//! `FolderSync` mirrors a directory as artifacts of type `file`, titled with
+//! their path relative to the folder. A `notify` watcher reports changed
+//! paths; once the folder has been quiet for the debounce time, new and
+//! modified files are ingested and removed ones moved to the trash. A file that
+//! disappears while another with the same content appears was renamed and
+//! keeps its artifact. Names matching an ignore pattern (`.git`, editor
+//! temp files) are skipped at any depth.
+//!
+//! Artifact changes, local or synced, are materialized back into the
+//! folder through a temporary file renamed into place. A file edited
+//! locally since it was last synced is never overwritten: the incoming
+//! version is written next to it as `name (conflict).ext`, and both are
+//! ingested as usual.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nomade_crypto::fs::write_durable;
+use nomade_crypto::unix_time;
+use nomade_events::{Event, EventStream};
+use nomade_storage::{content_hash, trash, Artifact, ArtifactStore, ContentStore};
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::runtime::random_id;
+use crate::supervisor::Supervisor;
+use crate::Result;
+
+/// `artifact_type` of artifacts mirroring folder files
+pub const FILE_ARTIFACT_TYPE: &str = "file";
+
+/// Prefix of temporary files written while materializing
+const TEMP_PREFIX: &str = ".nomade-";
+
+/// File tracked in the folder
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Tracked {
+    artifact_id: String,
+    /// Content last ingested from or written to the file
+    content_hash: String,
+}
+
+/// What one pass over the folder changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FolderReport {
+    /// Relative paths of new and modified files
+    pub ingested: Vec<String>,
+    /// Renamed files, from and to
+    pub renamed: Vec<(String, String)>,
+    /// Relative paths of removed files
+    pub removed: Vec<String>,
+}
+
+/// Folder mirrored as artifacts
+pub struct FolderSync {
+    root: PathBuf,
+    ignore: Vec<String>,
+    artifacts: Arc<dyn ArtifactStore>,
+    content: Arc<dyn ContentStore>,
+    /// Tracked files by relative path
+    index: Mutex<BTreeMap<String, Tracked>>,
+    index_path: Option<PathBuf>,
+}
+
+impl FolderSync {
+    /// Mirror `root` with an in-memory index
+    pub fn new(
+        root: impl Into<PathBuf>,
+        ignore: &[String],
+        artifacts: Arc<dyn ArtifactStore>,
+        content: Arc<dyn ContentStore>,
+    ) -> Self {
+        Self {
+            root: root.into(),
+            ignore: ignore.to_vec(),
+            artifacts,
+            content,
+            index: Mutex::new(BTreeMap::new()),
+            index_path: None,
+        }
+    }
+
+    /// Mirror `root`, keeping the index of tracked files at `index_path`
+    pub fn open(
+        root: impl Into<PathBuf>,
+        ignore: &[String],
+        artifacts: Arc<dyn ArtifactStore>,
+        content: Arc<dyn ContentStore>,
+        index_path: impl AsRef<Path>,
+    ) -> anyhow::Result<Self> {
+        let index_path = index_path.as_ref().to_path_buf();
+        let index = match std::fs::read(&index_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            index: Mutex::new(index),
+            index_path: Some(index_path),
+            ..Self::new(root, ignore, artifacts, content)
+        })
+    }
+
+    /// Folder being mirrored
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Ingest the whole folder, catching up on changes made while not
+    /// watching
+    pub fn scan(&self) -> anyhow::Result<FolderReport> {
+        std::fs::create_dir_all(&self.root)?;
+        let mut paths = Vec::new();
+        self.walk(&self.root, &mut paths)?;
+        let tracked: Vec<PathBuf> = self
+            .index
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|rel| self.root.join(rel))
+            .collect();
+        paths.extend(tracked);
+        self.ingest(paths)
+    }
+
+    /// Ingest changes reported at `paths`
+    pub fn ingest(&self, paths: impl IntoIterator<Item = PathBuf>) -> anyhow::Result<FolderReport> {
+        let mut index = self.index.lock().unwrap();
+        let mut present = Vec::new();
+        let mut gone = Vec::new();
+        for path in paths {
+            let Some(rel) = self.relative(&path) else {
+                continue;
+            };
+            if path.is_file() {
+                present.push(rel);
+            } else if path.is_dir() {
+                let mut files = Vec::new();
+                self.walk(&path, &mut files)?;
+                present.extend(files.iter().filter_map(|file| self.relative(file)));
+            } else {
+                // A removed directory takes its files with it
+                let prefix = format!("{}/", rel);
+                gone.extend(
+                    index
+                        .keys()
+                        .filter(|tracked| **tracked == rel || tracked.starts_with(&prefix))
+                        .cloned(),
+                );
+            }
+        }
+        present.sort();
+        present.dedup();
+        gone.sort();
+        gone.dedup();
+
+        let mut missing: HashMap<String, Vec<String>> = HashMap::new();
+        for rel in &gone {
+            if let Some(tracked) = index.get(rel) {
+                missing
+                    .entry(tracked.content_hash.clone())
+                    .or_default()
+                    .push(rel.clone());
+            }
+        }
+
+        let mut report = FolderReport::default();
+        for rel in present {
+            let data = match std::fs::read(self.root.join(&rel)) {
+                Ok(data) => data,
+                // Removed again before we got to it
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let hash = content_hash(&data);
+            let artifact_id = match index.get(&rel) {
+                Some(tracked) if tracked.content_hash == hash => continue,
+                Some(tracked) => tracked.artifact_id.clone(),
+                None => match missing.get_mut(&hash).and_then(Vec::pop) {
+                    Some(from) => {
+                        let tracked = index.remove(&from).expect("missing files are tracked");
+                        report.renamed.push((from, rel.clone()));
+                        tracked.artifact_id
+                    }
+                    None => random_id(),
+                },
+            };
+            self.content.put_content(&hash, &data)?;
+            let now = unix_time();
+            let artifact = match self.artifacts.get(&artifact_id)? {
+                Some(existing) => Artifact {
+                    title: rel.clone(),
+                    modified_at: now,
+                    content_hash: hash.clone(),
+                    trashed_at: None,
+                    ..existing
+                },
+                None => Artifact {
+                    id: artifact_id.clone(),
+                    title: rel.clone(),
+                    created_at: now,
+                    modified_at: now,
+                    content_hash: hash.clone(),
+                    content_type: content_type(&rel).map(String::from),
+                    artifact_type: Some(FILE_ARTIFACT_TYPE.into()),
+                    ..Default::default()
+                },
+            };
+            self.artifacts.store(&artifact)?;
+            if !report.renamed.iter().any(|(_, to)| *to == rel) {
+                report.ingested.push(rel.clone());
+            }
+            index.insert(
+                rel,
+                Tracked {
+                    artifact_id,
+                    content_hash: hash,
+                },
+            );
+        }
+
+        for rel in gone {
+            if let Some(tracked) = index.remove(&rel) {
+                trash::trash(self.artifacts.as_ref(), &tracked.artifact_id, unix_time())?;
+                report.removed.push(rel);
+            }
+        }
+        self.save(&index)?;
+        Ok(report)
+    }
+
+    /// Bring the folder in line with artifact `id`
+    pub fn materialize(&self, id: &str) -> anyhow::Result<()> {
+        let mut index = self.index.lock().unwrap();
+        let current = index
+            .iter()
+            .find(|(_, tracked)| tracked.artifact_id == id)
+            .map(|(rel, tracked)| (rel.clone(), tracked.clone()));
+        let artifact = self
+            .artifacts
+            .get(id)?
+            .filter(|a| a.artifact_type.as_deref() == Some(FILE_ARTIFACT_TYPE))
+            .filter(|a| a.trashed_at.is_none());
+        let target = artifact
+            .as_ref()
+            .and_then(|a| safe_relative(&a.title))
+            .filter(|rel| !self.is_ignored(rel));
+
+        // Moved, trashed or deleted elsewhere: follow unless edited here since
+        if let Some((rel, tracked)) = &current {
+            if target.as_ref() != Some(rel) {
+                index.remove(rel);
+                let path = self.root.join(rel);
+                if file_hash(&path)?.as_ref() == Some(&tracked.content_hash) {
+                    match &target {
+                        Some(to) if !self.root.join(to).exists() => {
+                            let to_path = self.root.join(to);
+                            if let Some(parent) = to_path.parent() {
+                                std::fs::create_dir_all(parent)?;
+                            }
+                            std::fs::rename(&path, &to_path)?;
+                            index.insert(to.clone(), tracked.clone());
+                        }
+                        _ => std::fs::remove_file(&path)?,
+                    }
+                }
+            }
+        }
+
+        let (Some(artifact), Some(rel)) = (artifact, target) else {
+            return self.save(&index);
+        };
+        let path = self.root.join(&rel);
+        let on_disk = file_hash(&path)?;
+        if on_disk.as_ref() == Some(&artifact.content_hash) {
+            index.insert(
+                rel,
+                Tracked {
+                    artifact_id: artifact.id,
+                    content_hash: artifact.content_hash,
+                },
+            );
+            return self.save(&index);
+        }
+        let Some(data) = self.content.get_content(&artifact.content_hash)? else {
+            // Written once the content is fetched
+            return self.save(&index);
+        };
+        let unmodified = match (&on_disk, index.get(&rel)) {
+            (None, _) => true,
+            (Some(hash), Some(tracked)) => {
+                tracked.artifact_id == artifact.id && tracked.content_hash == *hash
+            }
+            (Some(_), None) => false,
+        };
+        if unmodified {
+            write_atomic(&path, &data)?;
+            index.insert(
+                rel,
+                Tracked {
+                    artifact_id: artifact.id,
+                    content_hash: artifact.content_hash,
+                },
+            );
+        } else {
+            let copy = conflict_path(&path);
+            tracing::info!(
+                "{} changed locally; writing synced version to {}",
+                rel,
+                copy.display()
+            );
+            write_atomic(&copy, &data)?;
+        }
+        self.save(&index)
+    }
+
+    /// Materialize every file artifact, and remove files of deleted ones
+    pub fn materialize_all(&self) -> anyhow::Result<()> {
+        let mut ids: Vec<String> = self
+            .artifacts
+            .list()?
+            .into_iter()
+            .filter(|a| a.artifact_type.as_deref() == Some(FILE_ARTIFACT_TYPE))
+            .map(|a| a.id)
+            .collect();
+        ids.extend(
+            self.index
+                .lock()
+                .unwrap()
+                .values()
+                .map(|tracked| tracked.artifact_id.clone()),
+        );
+        ids.sort();
+        ids.dedup();
+        for id in ids {
+            self.materialize(&id)?;
+        }
+        Ok(())
+    }
+
+    /// Path relative to the root with `/` separators, unless ignored
+    fn relative(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.root).ok()?;
+        let parts: Vec<&str> = rel
+            .components()
+            .map(|c| match c {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        let rel = parts.join("/");
+        (!rel.is_empty() && !self.is_ignored(&rel)).then_some(rel)
+    }
+
+    fn is_ignored(&self, rel: &str) -> bool {
+        rel.split('/').any(|name| {
+            name.starts_with(TEMP_PREFIX) || self.ignore.iter().any(|p| glob_match(p, name))
+        })
+    }
+
+    fn walk(&self, dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if self.relative(&path).is_none() {
+                continue;
+            }
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                self.walk(&path, files)?;
+            } else if kind.is_file() {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn save(&self, index: &BTreeMap<String, Tracked>) -> anyhow::Result<()> {
+        let Some(path) = &self.index_path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(index)?)?;
+        Ok(())
+    }
+}
+
+/// Watch the folder and keep it in line with its artifacts
+///
+/// Scans the folder once first, so changes made while not running are
+/// picked up.
+pub fn spawn(
+    supervisor: &Supervisor,
+    events: &EventStream,
+    folder: Arc<FolderSync>,
+    debounce: Duration,
+) -> Result<()> {
+    std::fs::create_dir_all(folder.root())?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+            Ok(event) => {
+                let _ = tx.send(event.paths);
+            }
+            Err(e) => tracing::warn!("Folder watcher error: {}", e),
+        })
+        .map_err(anyhow::Error::from)?;
+    watcher
+        .watch(folder.root(), RecursiveMode::Recursive)
+        .map_err(anyhow::Error::from)?;
+
+    if let Err(e) = folder.scan().and_then(|_| folder.materialize_all()) {
+        tracing::warn!("Failed to scan synced folder: {}", e);
+    }
+
+    supervisor.spawn("folder-watcher", {
+        let folder = folder.clone();
+        move |cancel| async move {
+            // Dropping the watcher stops it
+            let _watcher = watcher;
+            loop {
+                let mut paths = tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    paths = rx.recv() => match paths {
+                        Some(paths) => paths,
+                        None => break,
+                    },
+                };
+                // Wait for the folder to settle
+                while let Ok(Some(more)) = tokio::time::timeout(debounce, rx.recv()).await {
+                    paths.extend(more);
+                }
+                match folder.ingest(paths) {
+                    Ok(report) if report != FolderReport::default() => {
+                        tracing::debug!("Synced folder changes: {:?}", report)
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::warn!("Failed to ingest folder changes: {}", e),
+                }
+            }
+        }
+    })?;
+
+    let mut rx = events.subscribe();
+    supervisor.spawn("folder-materializer", move |cancel| async move {
+        loop {
+            let event = tokio::select! {
+                _ = cancel.cancelled() => break,
+                event = rx.recv() => event,
+            };
+            let result = match event {
+                Ok(
+                    Event::ArtifactCreated { id }
+                    | Event::ArtifactUpdated { id }
+                    | Event::ArtifactDeleted { id }
+                    | Event::ArtifactTrashed { id }
+                    | Event::ArtifactRestored { id },
+                ) => folder.materialize(&id),
+                Ok(Event::Batched { events }) => events.iter().try_for_each(|event| match event {
+                    Event::ArtifactCreated { id }
+                    | Event::ArtifactUpdated { id }
+                    | Event::ArtifactDeleted { id }
+                    | Event::ArtifactTrashed { id }
+                    | Event::ArtifactRestored { id } => folder.materialize(id),
+                    _ => Ok(()),
+                }),
+                Err(broadcast::error::RecvError::Lagged(_)) => folder.materialize_all(),
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to update synced folder: {}", e);
+            }
+        }
+    })
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any run of
+/// characters and `?` any one character
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*`, and the name position it matched up to
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((after_star, matched)) => {
+                    p = after_star;
+                    n = matched + 1;
+                    backtrack = Some((after_star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Relative path from an artifact title, if it stays inside the folder
+fn safe_relative(title: &str) -> Option<String> {
+    let parts: Vec<&str> = title.split('/').collect();
+    let safe = parts.iter().all(|part| {
+        !part.is_empty() && *part != "." && *part != ".." && !part.contains(['\\', ':', '\0'])
+    });
+    safe.then(|| parts.join("/"))
+}
+
+fn file_hash(path: &Path) -> anyhow::Result<Option<String>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(content_hash(&data))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write through a temporary file, so the watcher never sees partial content
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let parent = path.parent().expect("folder files have a parent");
+    std::fs::create_dir_all(parent)?;
+    let tmp = parent.join(format!("{}{}.tmp", TEMP_PREFIX, random_id()));
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// `notes (conflict).md`, numbered if that is taken too
+fn conflict_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| format!(".{}", e))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| match n {
+            1 => format!("{} (conflict){}", stem, ext),
+            n => format!("{} (conflict {}){}", stem, n, ext),
+        })
+        .map(|name| path.with_file_name(name))
+        .find(|candidate| !candidate.exists())
+        .expect("some conflict name is free")
+}
+
+fn content_type(rel: &str) -> Option<&'static str> {
+    let ext = rel.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "md" | "markdown" => "text/markdown",
+        "txt" => "text/plain",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_storage::InMemoryStore;
+
+    fn folder(root: &Path) -> (FolderSync, Arc<InMemoryStore>) {
+        let store = Arc::new(InMemoryStore::new());
+        let ignore = crate::config::SyncedFolder::new(root).ignore;
+        let folder = FolderSync::new(root, &ignore, store.clone(), store.clone());
+        (folder, store)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.tmp", "draft.tmp"));
+        assert!(glob_match("~$*", "~$report.docx"));
+        assert!(glob_match("a?c*", "abcdef"));
+        assert!(glob_match(".git", ".git"));
+        assert!(!glob_match("*.tmp", "draft.tmpl"));
+        assert!(!glob_match(".git", ".gitignore"));
+    }
+
+    #[test]
+    fn test_ingests_renames_and_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (folder, store) = folder(root);
+        std::fs::create_dir_all(root.join("notes")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join("notes/todo.md"), "milk").unwrap();
+        std::fs::write(root.join("draft.tmp"), "scratch").unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref").unwrap();
+
+        let report = folder.scan().unwrap();
+        assert_eq!(report.ingested, ["notes/todo.md"]);
+        let artifact = store.list().unwrap().pop().unwrap();
+        assert_eq!(artifact.title, "notes/todo.md");
+        assert_eq!(artifact.content_type.as_deref(), Some("text/markdown"));
+        assert_eq!(folder.scan().unwrap(), FolderReport::default());
+
+        std::fs::rename(root.join("notes/todo.md"), root.join("todo.md")).unwrap();
+        let report = folder
+            .ingest([root.join("notes/todo.md"), root.join("todo.md")])
+            .unwrap();
+        assert_eq!(report.renamed, [("notes/todo.md".into(), "todo.md".into())]);
+        let renamed = store.get(&artifact.id).unwrap().unwrap();
+        assert_eq!(renamed.title, "todo.md");
+
+        std::fs::remove_file(root.join("todo.md")).unwrap();
+        let report = folder.ingest([root.join("todo.md")]).unwrap();
+        assert_eq!(report.removed, ["todo.md"]);
+        let trashed = store.get(&artifact.id).unwrap().unwrap();
+        assert!(trashed.trashed_at.is_some());
+    }
+
+    #[test]
+    fn test_materializes_without_clobbering_local_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let (folder, store) = folder(root);
+        let put = |id: &str, title: &str, text: &str| {
+            let hash = content_hash(text.as_bytes());
+            store.put_content(&hash, text.as_bytes()).unwrap();
+            store
+                .store(&Artifact {
+                    id: id.into(),
+                    title: title.into(),
+                    content_hash: hash,
+                    artifact_type: Some(FILE_ARTIFACT_TYPE.into()),
+                    ..Default::default()
+                })
+                .unwrap();
+        };
+
+        put("a1", "plans/trip.md", "day 1");
+        folder.materialize("a1").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("plans/trip.md")).unwrap(),
+            "day 1"
+        );
+        // Writing it back is not a change
+        assert_eq!(folder.scan().unwrap(), FolderReport::default());
+
+        // Unedited files follow remote changes
+        put("a1", "plans/trip.md", "day 1, day 2");
+        folder.materialize("a1").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("plans/trip.md")).unwrap(),
+            "day 1, day 2"
+        );
+
+        // Edited files are kept, with the remote version next to them
+        std::fs::write(root.join("plans/trip.md"), "local plan").unwrap();
+        put("a1", "plans/trip.md", "remote plan");
+        folder.materialize("a1").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("plans/trip.md")).unwrap(),
+            "local plan"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("plans/trip (conflict).md")).unwrap(),
+            "remote plan"
+        );
+
+        // Titles cannot escape the folder
+        put("a2", "../escape.md", "nope");
+        folder.materialize("a2").unwrap();
+        assert!(!dir.path().parent().unwrap().join("escape.md").exists());
+    }
+}
@@ -0,0 +1,277 @@
+//! Device group of the runtime
+//!
+//! The runtime keeps at most one `DeviceGroup`, saved as its roster. Any
+//! device can found one and add the devices it paired with; every change
+//! is queued as a `Roster` frame for the connected members, and a link
+//! sends the whole roster when a member attaches. Received entries are
+//! merged and applied to the `TrustStore`: added members are trusted as if
+//! paired, removed ones are forgotten and disconnected. Adding a device
+//! once thus makes it trusted by every member.
+//!
+//! A device joins a group only through a roster that lists it, sent by a
+//! peer allowed to manage devices. From then on the roster's signatures
+//! decide who may change it.
+
+use std::path::{Path, PathBuf};
+
+use nomade_crypto::fs::write_durable;
+use nomade_crypto::{
+    CryptoError, DeviceGroup, DeviceId, DeviceKeypair, Member, Permissions, RosterEntry, RosterOp,
+    TrustState,
+};
+
+use crate::runtime::NomadeRuntime;
+use crate::Result;
+
+/// Group this device belongs to, if any, saved with every change
+#[derive(Default)]
+pub(crate) struct GroupRoster {
+    group: Option<DeviceGroup>,
+    path: Option<PathBuf>,
+}
+
+impl GroupRoster {
+    /// Create an in-memory roster
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a roster persisted at `path`
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let entries: Vec<RosterEntry> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let group = match entries.is_empty() {
+            true => None,
+            false => Some(DeviceGroup::from_entries(entries)?),
+        };
+        Ok(Self {
+            group,
+            path: Some(path),
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let (Some(path), Some(group)) = (&self.path, &self.group) else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(group.entries())?)?;
+        Ok(())
+    }
+}
+
+impl NomadeRuntime {
+    /// Found a device group with this device as its only member
+    ///
+    /// Returns the group ID.
+    pub fn create_group(&self, device_name: &str) -> Result<String> {
+        let mut roster = self.group_roster().lock().unwrap();
+        if let Some(group) = &roster.group {
+            return Err(
+                CryptoError::InvalidRoster(format!("Already in {}", group.group_id())).into(),
+            );
+        }
+        let group = DeviceGroup::create(self.keystore().keypair(), device_name.to_string())?;
+        let group_id = group.group_id().to_string();
+        roster.group = Some(group);
+        roster.save()?;
+        Ok(group_id)
+    }
+
+    /// Add a paired device to the group and tell the connected members
+    pub fn add_group_member(&self, device_id: &DeviceId) -> Result<()> {
+        let trusted = self
+            .trust()
+            .read()
+            .unwrap()
+            .get(device_id)
+            .filter(|device| device.state == TrustState::Trusted)
+            .cloned()
+            .ok_or_else(|| CryptoError::UntrustedDevice(device_id.clone()))?;
+        self.change_group(|group, signer| {
+            group.add_device(
+                signer,
+                Member {
+                    device_id: trusted.device_id,
+                    device_name: trusted.device_name,
+                    public_key: trusted.public_key,
+                },
+            )
+        })
+    }
+
+    /// Remove a device from the group and tell the remaining members
+    ///
+    /// Every member, this device included, forgets the device; it has to
+    /// be paired again to come back.
+    pub fn remove_group_member(&self, device_id: &DeviceId) -> Result<()> {
+        self.change_group(|group, signer| group.remove_device(signer, device_id.clone()))
+    }
+
+    /// ID of the group this device belongs to, if any
+    pub fn group_id(&self) -> Option<String> {
+        let roster = self.group_roster().lock().unwrap();
+        roster
+            .group
+            .as_ref()
+            .map(|group| group.group_id().to_string())
+    }
+
+    /// Current members of the group, this device included
+    pub fn group_members(&self) -> Vec<Member> {
+        let roster = self.group_roster().lock().unwrap();
+        roster
+            .group
+            .as_ref()
+            .map(|group| group.members().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Queue the whole roster for `peer` if it is a member
+    pub(crate) fn send_roster(&self, peer: &DeviceId) {
+        let roster = self.group_roster().lock().unwrap();
+        let Some(group) = roster.group.as_ref().filter(|group| group.is_member(peer)) else {
+            return;
+        };
+        if let Err(e) = self.connections().send_roster(peer, group.entries()) {
+            tracing::debug!("Failed to queue the roster for {}: {}", peer, e);
+        }
+    }
+
+    /// Merge roster entries received from `peer` and apply them
+    ///
+    /// New entries are passed on to the other connected members.
+    pub(crate) fn apply_roster(&self, peer: &DeviceId, entries: &[RosterEntry]) -> Result<()> {
+        let mut roster = self.group_roster().lock().unwrap();
+        let head = match &mut roster.group {
+            Some(group) => {
+                let head = group.head_seq();
+                if group.merge(entries)? == 0 {
+                    return Ok(());
+                }
+                Some(head)
+            }
+            None => {
+                let group = DeviceGroup::from_entries(entries.to_vec())?;
+                if !group.is_member(self.device_id()) {
+                    tracing::debug!("Ignoring a roster of {} without this device", peer);
+                    return Ok(());
+                }
+                let can_manage = self
+                    .trust()
+                    .read()
+                    .unwrap()
+                    .permissions(peer)
+                    .is_some_and(|permissions| permissions.can_add_devices);
+                if !can_manage {
+                    return Err(CryptoError::PermissionDenied(peer.clone()).into());
+                }
+                roster.group = Some(group);
+                None
+            }
+        };
+        roster.save()?;
+        let group = roster.group.as_ref().expect("group merged or joined");
+        let applied = match head {
+            Some(head) => group.entries_since(head),
+            None => group.entries(),
+        };
+        self.apply_membership(group, applied)?;
+        self.queue_roster(group, head, Some(peer));
+        Ok(())
+    }
+
+    /// Append an entry signed by this device, apply and send it
+    fn change_group(
+        &self,
+        change: impl FnOnce(&mut DeviceGroup, &DeviceKeypair) -> nomade_crypto::Result<RosterEntry>,
+    ) -> Result<()> {
+        let mut roster = self.group_roster().lock().unwrap();
+        let group = roster
+            .group
+            .as_mut()
+            .ok_or_else(|| CryptoError::InvalidRoster("Not in a group".into()))?;
+        let head = group.head_seq();
+        let entry = change(group, self.keystore().keypair())?;
+        roster.save()?;
+        let group = roster.group.as_ref().expect("group changed");
+        self.apply_membership(group, std::slice::from_ref(&entry))?;
+        self.queue_roster(group, Some(head), None);
+        Ok(())
+    }
+
+    /// Trust or forget the devices `entries` added or removed
+    ///
+    /// The group's current members decide, so a device added and removed
+    /// again in one batch ends up forgotten.
+    fn apply_membership(&self, group: &DeviceGroup, entries: &[RosterEntry]) -> Result<()> {
+        let mut added = false;
+        for entry in entries {
+            let device_id = match &entry.op {
+                RosterOp::Add(member) => &member.device_id,
+                RosterOp::Remove { device_id } => device_id,
+            };
+            if device_id == self.device_id() {
+                continue;
+            }
+            let mut trust = self.trust().write().unwrap();
+            match group.member(device_id) {
+                // Paired devices keep their permissions, revoked ones stay out
+                Some(member) if trust.get(device_id).is_none() => {
+                    trust.add_trusted(
+                        member.device_id.clone(),
+                        member.device_name.clone(),
+                        member.public_key.clone(),
+                    )?;
+                    self.sync()
+                        .set_permissions(&device_id.to_string(), Permissions::full());
+                    added = true;
+                }
+                Some(_) => {}
+                None => {
+                    if trust.remove(device_id)? {
+                        drop(trust);
+                        self.disconnect_peer(device_id);
+                    }
+                }
+            }
+        }
+        if added {
+            self.share_keys()?;
+        }
+        Ok(())
+    }
+
+    /// Queue the entries after `head` for the connected members but
+    /// `except`; members that joined since, or all if `head` is `None`,
+    /// get the whole roster
+    fn queue_roster(&self, group: &DeviceGroup, head: Option<u64>, except: Option<&DeviceId>) {
+        let since = head.map(|head| group.entries_since(head));
+        for member in group.members() {
+            let device_id = &member.device_id;
+            if device_id == self.device_id()
+                || Some(device_id) == except
+                || !self.connections().is_connected(device_id)
+            {
+                continue;
+            }
+            let entries = match since {
+                Some(since) if !joined(since, device_id) => since,
+                _ => group.entries(),
+            };
+            if let Err(e) = self.connections().send_roster(device_id, entries) {
+                tracing::debug!("Failed to queue the roster for {}: {}", device_id, e);
+            }
+        }
+    }
+}
+
+/// Whether `entries` add `device_id`
+fn joined(entries: &[RosterEntry], device_id: &DeviceId) -> bool {
+    entries
+        .iter()
+        .any(|entry| matches!(&entry.op, RosterOp::Add(member) if &member.device_id == device_id))
+}
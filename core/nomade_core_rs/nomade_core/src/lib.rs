@@ -4,27 +4,186 @@
 
 pub use nomade_crypto;
 pub use nomade_events;
+pub use nomade_metrics;
 pub use nomade_quic;
 pub use nomade_storage;
+pub use nomade_sync;
 
 pub mod api;
+pub mod auth;
+pub mod config;
 pub mod device;
+mod envelope;
+#[cfg(feature = "folder-sync")]
+pub mod folder;
+mod group;
+mod link;
+pub mod logging;
+#[cfg(feature = "sqlite")]
+pub mod metadata;
+pub mod migrations;
+mod pairing;
 pub mod protocol;
+pub mod runtime;
+pub mod signer;
+pub mod snapshot;
+pub mod supervisor;
 
 mod frb_generated;
 
-/// Initialize Nomade core with logging
-pub fn init() {
-    tracing_subscriber::fmt::init();
+pub use config::{context, Context, NomadeConfig};
+pub use runtime::{
+    runtime, AttestationInfo, ConnectionQuality, KeystoreStatus, NomadeRuntime,
+    NomadeRuntimeBuilder, RuntimeState, WakeOutcome, WakeReport,
+};
+pub use supervisor::Supervisor;
+
+/// Common error type for core operations
+#[derive(Debug, thiserror::Error)]
+pub enum CoreError {
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Nomade core already initialized")]
+    AlreadyInitialized,
+
+    #[error("Nomade core not initialized")]
+    NotInitialized,
+
+    #[error("Nomade core is shutting down")]
+    ShuttingDown,
+
+    #[error("{store} was written by a newer version (schema {found}, supported {supported})")]
+    SchemaTooNew {
+        store: String,
+        found: u32,
+        supported: u32,
+    },
+
+    #[error("Authentication required: {0}")]
+    AuthDenied(String),
+
+    #[error("Peer not connected: {0}")]
+    PeerNotConnected(String),
+
+    #[error("Content not available from any connected peer: {0}")]
+    ContentUnavailable(String),
+
+    #[error("Snippet of {0} bytes is too large")]
+    SnippetTooLarge(usize),
+
+    #[error("Sync error: {0}")]
+    Sync(#[from] nomade_sync::SyncError),
+
+    #[error("Network error: {0}")]
+    Network(#[from] nomade_quic::ProtocolError),
+
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] nomade_crypto::CryptoError),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] anyhow::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, CoreError>;
+
+/// Initialize Nomade core with the given configuration
+///
+/// Validates the configuration, creates the data directory, installs
+/// logging, and makes the configuration available through `context()`.
+pub fn init(config: NomadeConfig) -> Result<Context> {
+    let context = Context::new(config)?;
+    std::fs::create_dir_all(&context.config().data_dir)?;
+
+    logging::install(context.config())?;
+
+    config::install(context.clone())?;
     tracing::info!("Nomade core initialized");
+    Ok(context)
+}
+
+/// Build the runtime for the initialized context and install it
+///
+/// The runtime becomes reachable through `runtime()`. Its identity key is
+/// held by the registered platform signer, if any.
+pub fn start() -> Result<std::sync::Arc<NomadeRuntime>> {
+    let mut builder = NomadeRuntime::builder(context()?);
+    if let Some(signer) = signer::registered() {
+        let vault =
+            nomade_crypto::Vault::open(context()?.config().data_dir.join(runtime::VAULT_FILE))?;
+        builder =
+            builder.keystore(nomade_crypto::Keystore::with_provider(signer)?.with_vault(vault));
+    }
+    if let Some(gate) = auth::registered() {
+        builder = builder.auth_gate(gate);
+    }
+    let runtime = runtime::install(builder.build()?)?;
+    runtime.spawn_snapshotter()?;
+    runtime.spawn_scrubber()?;
+    runtime.spawn_trash_purger()?;
+    runtime.spawn_network_probe()?;
+    runtime.spawn_auto_lock()?;
+    Ok(runtime)
+}
+
+/// Destroy this device's keys, stores and journals
+///
+/// Stops the runtime and releases the context like `shutdown()`, then
+/// wipes the data directory. The device has to be set up and paired again.
+pub async fn wipe() -> Result<()> {
+    let data_dir = context()?.config().data_dir.clone();
+    shutdown().await?;
+    let shredded = nomade_crypto::wipe::wipe_dir(&data_dir)?;
+    tracing::warn!(
+        "Wiped {} ({} files overwritten)",
+        data_dir.display(),
+        shredded
+    );
+    Ok(())
+}
+
+/// Stop the runtime and release the process-wide context
+///
+/// `init()` can be called again afterwards.
+pub async fn shutdown() -> Result<()> {
+    if let Some(runtime) = runtime::uninstall() {
+        runtime.shutdown().await?;
+    }
+    if config::uninstall().is_some() {
+        tracing::info!("Nomade core shut down");
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_init() {
-        init();
+    #[tokio::test]
+    async fn test_init() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("data");
+        let context = init(NomadeConfig::new(&dir)).unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(context.config().data_dir, dir);
+        assert_eq!(crate::context().unwrap().config().data_dir, dir);
+
+        assert!(matches!(
+            init(NomadeConfig::new(&dir)),
+            Err(CoreError::AlreadyInitialized)
+        ));
+        let runtime = start().unwrap();
+        assert_eq!(crate::runtime().unwrap().device_id(), runtime.device_id());
+
+        shutdown().await.unwrap();
+        assert_eq!(runtime.state(), RuntimeState::Stopped);
+        assert!(matches!(crate::context(), Err(CoreError::NotInitialized)));
+        assert!(matches!(crate::runtime(), Err(CoreError::NotInitialized)));
     }
 }
@@ -0,0 +1,587 @@
+//! Links to connected peers
+//!
+//! A link is the runtime's side of one authenticated connection to a
+//! paired device. Whichever device dialed, the connection carries traffic
+//! both ways. Attaching it exchanges hellos on a first `Control` channel
+//! and admits the peer through the `ConnectionManager` with the negotiated
+//! features. With `CHUNKED_SYNC` the link then answers the peer's sync
+//! requests from its `sync_view` and registers a `RemotePeer` so this
+//! device pulls through the same connection. It forwards live events both
+//! ways and delivers the frames the manager queues for the peer on a
+//! `Control` channel, starting with the device group roster if the peer is
+//! a member; control frames from the peer (revocations, attestations,
+//! rosters and wipe commands) are applied as they arrive. With
+//! `KEEPALIVE` both sides ping on a dedicated uni stream each, and the
+//! link records round trips, losses and the peer's clock offset with the
+//! manager. With `SEQUENCED_FRAMES` every channel after the hello shares
+//! the peer's replay counters, resumed from the state both hellos announce
+//! and saved when the link closes. With
+//! `SIGNED_FRAMES` the dialing device sends a link key sealed to the
+//! peer's identity on the hello channel, and sync and event payloads
+//! travel as `SignedEnvelope`s checked against the trust store, encrypted
+//! by the peer's ratchet session, which is saved next to the replay
+//! counters when the link closes. The link
+//! ends when the connection closes, the peer stops answering pings,
+//! the manager drops the peer or the runtime shuts down, and takes down
+//! everything it set up.
+//!
+//! `serve` accepts peers on a transport in the background, `connect_peer`
+//! dials one.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use nomade_crypto::{Attestation, DeviceId, RevocationRecord, RosterEntry, WipeCommand};
+use nomade_quic::negotiation::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use nomade_quic::{
+    exchange_hello, forward_events, receive_events, start_keepalive, Channel, ChannelId,
+    ChannelRouter, Connection, ConnectionQueues, Direction, FeatureFlags, Frame, FrameSeal, Hello,
+    KeepaliveConfig, KeepaliveHandle, MessageType, ProtocolError, ReplayGuard, TimeoutPhase,
+    Transport,
+};
+use nomade_sync::{serve_channels, RemotePeer, SyncPeer};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::NetworkConfig;
+use crate::envelope::EnvelopeSeal;
+use crate::runtime::{executor, NomadeRuntime};
+use crate::Result;
+
+/// Protocol features the runtime implements, offered in its hello
+///
+/// `KEEPALIVE` is added unless the configuration disables keepalives,
+/// `SIGNED_FRAMES` unless the identity key is hardware-backed.
+const FEATURES: FeatureFlags = FeatureFlags::CHUNKED_SYNC.union(FeatureFlags::SEQUENCED_FRAMES);
+
+/// A link's share of an attached connection
+struct Link {
+    peer: DeviceId,
+    connection: Arc<dyn Connection>,
+    queues: ConnectionQueues,
+    /// Registered for pulling from the peer, if it negotiated sync
+    remote: Option<Arc<dyn SyncPeer>>,
+    /// Channel the hellos were exchanged on, open while the link runs
+    hello: Channel,
+    /// Pings on the connection, if the peer negotiated them
+    keepalive: Option<KeepaliveHandle>,
+    /// Session counters of the peer, if it negotiated sequenced frames
+    replay: Option<ReplayGuard>,
+    /// Seal of sync and event payloads, if the peer negotiated signed frames
+    seal: Option<Arc<EnvelopeSeal>>,
+}
+
+impl NomadeRuntime {
+    /// Accept peers on `transport` in the background until shutdown
+    ///
+    /// Each connection is admitted in its own supervised task; those that
+    /// fail admission are closed. In pairing mode an unpaired device pairs
+    /// with the shown code instead.
+    pub fn serve(self: &Arc<Self>, transport: Arc<dyn Transport>) -> Result<()> {
+        let runtime = Arc::downgrade(self);
+        self.supervisor()
+            .spawn("peer-listener", move |cancel| async move {
+                loop {
+                    let accepted = tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        accepted = transport.accept() => accepted,
+                    };
+                    let connection = match accepted {
+                        Ok(Some(connection)) => connection,
+                        Ok(None) => break,
+                        Err(e) => {
+                            tracing::debug!("Failed to accept a connection: {}", e);
+                            continue;
+                        }
+                    };
+                    let Some(runtime) = runtime.upgrade() else {
+                        break;
+                    };
+                    // A slow peer must not hold up the ones behind it
+                    let addr = connection.remote_addr();
+                    let closing = connection.clone();
+                    let accepting = runtime.clone();
+                    let spawned = runtime.supervisor().spawn(
+                        format!("accept-{}", addr),
+                        move |cancel| async move {
+                            let runtime = accepting;
+                            let admitted = async {
+                                if runtime.awaits_code_pairing(connection.as_ref()) {
+                                    match runtime.accept_code_pairing(connection).await {
+                                        Ok(peer) => {
+                                            tracing::info!("Paired with {} by code", peer)
+                                        }
+                                        Err(e) => {
+                                            tracing::info!("Pairing with {} failed: {}", addr, e)
+                                        }
+                                    }
+                                } else if let Err(e) =
+                                    runtime.attach(connection, Direction::Inbound).await
+                                {
+                                    tracing::info!("Refused connection from {}: {}", addr, e);
+                                }
+                            };
+                            tokio::select! {
+                                _ = cancel.cancelled() => {}
+                                _ = admitted => {}
+                            }
+                        },
+                    );
+                    if spawned.is_err() {
+                        closing.close();
+                        break;
+                    }
+                }
+            })
+    }
+
+    /// Dial the paired device `device_id` at `addr` and link to it
+    pub async fn connect_peer(
+        self: &Arc<Self>,
+        transport: &dyn Transport,
+        device_id: &DeviceId,
+        addr: &str,
+    ) -> Result<()> {
+        let connection = transport.connect(addr).await?;
+        if connection.peer_device_id().as_ref() != Some(device_id) {
+            connection.close();
+            return Err(
+                ProtocolError::PeerRejected(format!("{} is not {}", addr, device_id)).into(),
+            );
+        }
+        self.attach(connection, Direction::Outbound).await?;
+        Ok(())
+    }
+
+    /// Link to the peer at the other end of an authenticated connection
+    ///
+    /// Returns the peer once it is admitted and, if it negotiated
+    /// `CHUNKED_SYNC`, available for sync; the link then runs in the
+    /// background.
+    pub async fn attach(
+        self: &Arc<Self>,
+        connection: Arc<dyn Connection>,
+        direction: Direction,
+    ) -> Result<DeviceId> {
+        let Some(peer) = connection.peer_device_id() else {
+            connection.close();
+            return Err(ProtocolError::PeerRejected(format!(
+                "{} is not authenticated",
+                connection.remote_addr()
+            ))
+            .into());
+        };
+        let network = &self.context().config().network;
+        let timeouts = network.timeouts;
+        let keepalive = keepalive_config(network);
+        let mut features = FEATURES;
+        if keepalive.is_some() {
+            features |= FeatureFlags::KEEPALIVE;
+        }
+        if self.can_sign_frames() {
+            features |= FeatureFlags::SIGNED_FRAMES;
+        }
+        let session = match self.replay_counters().hello(&peer.to_string()) {
+            Ok(session) => session,
+            Err(e) => {
+                connection.close();
+                return Err(e.into());
+            }
+        };
+        let hello = Hello::new(
+            (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).collect(),
+            features,
+        )
+        .with_session(session);
+        let handshake = timeouts
+            .enforce(
+                TimeoutPhase::Handshake,
+                exchange_hello(connection.as_ref(), direction, &hello),
+            )
+            .await;
+        let (mut handshake, queues) = match handshake.and_then(|handshake| {
+            let queues = self.connections().admit_negotiated(
+                peer.clone(),
+                direction,
+                handshake.negotiated,
+            )?;
+            Ok((handshake, queues))
+        }) {
+            Ok(admitted) => admitted,
+            Err(e) => {
+                connection.close();
+                return Err(e.into());
+            }
+        };
+        let seal = match handshake
+            .negotiated
+            .features
+            .contains(FeatureFlags::SIGNED_FRAMES)
+        {
+            true => {
+                let exchanged = timeouts
+                    .enforce(
+                        TimeoutPhase::Handshake,
+                        self.exchange_link_key(&peer, &mut handshake.channel, direction),
+                    )
+                    .await;
+                match exchanged {
+                    Ok(seal) => Some(seal),
+                    Err(e) => {
+                        connection.close();
+                        if !queues.closed.is_cancelled() {
+                            self.connections().disconnect(&peer);
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            false => None,
+        };
+        let keepalive = match keepalive.filter(|_| {
+            handshake
+                .negotiated
+                .features
+                .contains(FeatureFlags::KEEPALIVE)
+        }) {
+            Some(config) => {
+                let started = timeouts
+                    .enforce(
+                        TimeoutPhase::Handshake,
+                        start_keepalive(
+                            connection.as_ref(),
+                            peer.to_string(),
+                            config,
+                            self.events().clone(),
+                        ),
+                    )
+                    .await;
+                match started {
+                    Ok(keepalive) => Some(keepalive),
+                    Err(e) => {
+                        connection.close();
+                        // A cancelled token means the manager already replaced it
+                        if !queues.closed.is_cancelled() {
+                            self.connections().disconnect(&peer);
+                        }
+                        return Err(e.into());
+                    }
+                }
+            }
+            None => None,
+        };
+        let replay = match handshake
+            .negotiated
+            .features
+            .contains(FeatureFlags::SEQUENCED_FRAMES)
+        {
+            true => {
+                let remote = handshake.remote.session.unwrap_or_default();
+                match self.replay_counters().resume(&peer.to_string(), &remote) {
+                    Ok(replay) => Some(replay),
+                    Err(e) => {
+                        connection.close();
+                        if !queues.closed.is_cancelled() {
+                            self.connections().disconnect(&peer);
+                        }
+                        return Err(e.into());
+                    }
+                }
+            }
+            false => None,
+        };
+        let remote = handshake
+            .negotiated
+            .features
+            .contains(FeatureFlags::CHUNKED_SYNC)
+            .then(|| -> Arc<dyn SyncPeer> {
+                let seal = seal.clone().map(|seal| -> Arc<dyn FrameSeal> { seal });
+                Arc::new(self.remote_peer(connection.clone(), replay.clone(), seal))
+            });
+        if let Some(remote) = &remote {
+            self.register_sync_peer(peer.clone(), remote.clone());
+        }
+        self.send_roster(&peer);
+
+        let link = Link {
+            peer: peer.clone(),
+            connection: connection.clone(),
+            queues,
+            remote,
+            hello: handshake.channel,
+            keepalive,
+            replay,
+            seal,
+        };
+        let runtime = self.clone();
+        let spawned = self
+            .supervisor()
+            .spawn(format!("link-{}", peer), move |cancel| async move {
+                runtime.run_link(link, cancel).await;
+            });
+        if let Err(e) = spawned {
+            connection.close();
+            self.disconnect_peer(&peer);
+            return Err(e);
+        }
+        Ok(peer)
+    }
+
+    /// Drop the link to a peer, if any
+    pub fn disconnect_peer(&self, device_id: &DeviceId) -> bool {
+        self.unregister_sync_peer(device_id);
+        self.connections().disconnect(device_id)
+    }
+
+    fn remote_peer(
+        &self,
+        connection: Arc<dyn Connection>,
+        replay: Option<ReplayGuard>,
+        seal: Option<Arc<dyn FrameSeal>>,
+    ) -> RemotePeer {
+        let config = self.context().config();
+        let mut peer = RemotePeer::new(connection).with_timeouts(config.network.timeouts);
+        if let Some(replay) = replay {
+            peer = peer.with_replay_guard(replay);
+        }
+        if let Some(seal) = seal {
+            peer = peer.with_seal(seal);
+        }
+        match config.sync.compression_level {
+            0 => peer,
+            level => peer.with_compression(level),
+        }
+    }
+
+    async fn run_link(self: &Arc<Self>, link: Link, cancel: CancellationToken) {
+        let Link {
+            peer,
+            connection,
+            mut queues,
+            remote,
+            hello: _hello,
+            keepalive,
+            replay,
+            seal,
+        } = link;
+        let peer = &peer;
+        let framing = seal.clone().map(|seal| -> Arc<dyn FrameSeal> { seal });
+        let router = ChannelRouter::new()
+            .with_replay_guard(replay.clone())
+            .with_seal(framing.clone());
+        // Without a route the peer's sync channels are reset
+        let requests = remote
+            .is_some()
+            .then(|| router.route(&[ChannelId::SyncMeta, ChannelId::ChunkTransfer]));
+        let control = router.route(&[ChannelId::Control]);
+        let live = router.route(&[ChannelId::LiveEvents]);
+        let closed = queues.closed.clone();
+        let serve = async {
+            match requests {
+                Some(requests) => serve_channels(self.sync_view(peer), requests).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        // Set once the keepalive reported the peer gone
+        let mut timed_out = false;
+        tokio::select! {
+            _ = cancel.cancelled() => {}
+            result = router.run(connection.as_ref()) => {
+                if let Err(e) = result {
+                    tracing::debug!("Connection to {} failed: {}", peer, e);
+                }
+            }
+            _ = serve => {}
+            _ = self.receive_control(peer, control) => {}
+            _ = self.receive_live(peer, live) => {}
+            dead = self.watch_keepalive(peer, keepalive) => timed_out = dead,
+            result = forward_events(connection.as_ref(), peer, self.events(), replay.clone(), framing) => {
+                if let Err(e) = result {
+                    tracing::debug!("Stopped forwarding events to {}: {}", peer, e);
+                }
+            }
+            result = send_queued(connection.as_ref(), &mut queues, replay.clone()) => {
+                if let Err(e) = result {
+                    tracing::debug!("Failed to send to {}: {}", peer, e);
+                }
+            }
+        }
+        connection.close();
+        // Saved before the peer can be seen disconnected and reconnect
+        if let Some(replay) = &replay {
+            if let Err(e) = self.replay_counters().save(&peer.to_string(), replay) {
+                tracing::warn!("Failed to save the replay counters of {}: {}", peer, e);
+            }
+        }
+        if let Some(seal) = &seal {
+            if let Err(e) = self.save_ratchet(peer, seal) {
+                tracing::warn!("Failed to save the ratchet session of {}: {}", peer, e);
+            }
+        }
+        // A cancelled token means the manager already dropped or replaced it
+        if !closed.is_cancelled() {
+            if timed_out {
+                self.connections().forget(peer);
+            } else {
+                self.connections().disconnect(peer);
+            }
+        }
+        if let Some(remote) = &remote {
+            self.unregister_sync_peer_if(peer, remote);
+        }
+        tracing::debug!("Link to {} closed", peer);
+    }
+
+    /// Apply the control frames the peer sends until it disconnects
+    async fn receive_control(
+        self: &Arc<Self>,
+        peer: &DeviceId,
+        mut channels: UnboundedReceiver<Channel>,
+    ) {
+        while let Some(mut channel) = channels.recv().await {
+            loop {
+                match channel.recv().await {
+                    Ok(Some(frame)) => {
+                        if let Err(e) = self.apply_control(peer, &frame) {
+                            tracing::warn!(
+                                "Dropped {:?} frame from {}: {}",
+                                frame.message_type,
+                                peer,
+                                e
+                            );
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::debug!("Control channel from {} failed: {}", peer, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record the peer's connection stats and clock offset until its
+    /// keepalive stops, which it does once the peer goes silent
+    ///
+    /// Returns whether the keepalive timed out and published
+    /// `DeviceDisconnected` itself.
+    async fn watch_keepalive(&self, peer: &DeviceId, keepalive: Option<KeepaliveHandle>) -> bool {
+        let (Some(mut keepalive), Some(config)) = (
+            keepalive,
+            keepalive_config(&self.context().config().network),
+        ) else {
+            return std::future::pending().await;
+        };
+        let mut ticker = tokio::time::interval(config.interval);
+        while !keepalive.is_finished() {
+            ticker.tick().await;
+            self.connections().record_stats(peer, keepalive.stats());
+            if let Some(sample) = keepalive.clock_offset() {
+                if let Err(e) = self.connections().set_clock_offset(peer, sample) {
+                    tracing::debug!("Failed to keep the clock offset of {}: {}", peer, e);
+                }
+            }
+        }
+        let result = keepalive.join().await;
+        tracing::info!("Keepalive with {} stopped", peer);
+        matches!(result, Err(ProtocolError::Timeout(TimeoutPhase::Idle)))
+    }
+
+    /// Publish the events the peer forwards until it disconnects
+    async fn receive_live(&self, peer: &DeviceId, mut channels: UnboundedReceiver<Channel>) {
+        while let Some(channel) = channels.recv().await {
+            if let Err(e) = receive_events(channel, peer, self.events()).await {
+                tracing::debug!("Live events from {} failed: {}", peer, e);
+            }
+        }
+    }
+
+    /// Apply one control frame from `peer`
+    ///
+    /// Revocations and attestations are checked, applied and passed on
+    /// to the other peers by the `ConnectionManager`. Rosters are merged
+    /// with `apply_roster`. A wipe command that passes `check_wipe` wipes
+    /// this device.
+    fn apply_control(self: &Arc<Self>, peer: &DeviceId, frame: &Frame) -> Result<()> {
+        match frame.message_type {
+            MessageType::Revocation => {
+                let record: RevocationRecord = frame.to_message()?;
+                self.connections().handle_revocation(&record, Some(peer))?;
+                self.unregister_sync_peer(&record.revoked);
+            }
+            MessageType::Attestation => {
+                let attestation: Attestation = frame.to_message()?;
+                self.connections()
+                    .handle_attestation(&attestation, Some(peer))?;
+            }
+            MessageType::Roster => {
+                let entries: Vec<RosterEntry> = frame.to_message()?;
+                self.apply_roster(peer, &entries)?;
+            }
+            MessageType::Wipe => {
+                let command: WipeCommand = frame.to_message()?;
+                self.check_wipe(&command)?;
+                self.wipe_self();
+            }
+            other => return Err(ProtocolError::UnexpectedMessage(other).into()),
+        }
+        Ok(())
+    }
+
+    /// Wipe this device after an accepted wipe command
+    ///
+    /// Only the runtime installed for the process owns the data directory
+    /// and is wiped; others just report the command.
+    fn wipe_self(self: &Arc<Self>) {
+        let installed = crate::runtime().is_ok_and(|runtime| Arc::ptr_eq(&runtime, self));
+        if !installed {
+            tracing::warn!("Accepted wipe command, but this runtime is not installed");
+            return;
+        }
+        // Detached: wiping shuts down the runtime and the link with it
+        executor().spawn(async {
+            if let Err(e) = crate::wipe().await {
+                tracing::error!("Failed to wipe this device: {}", e);
+            }
+        });
+    }
+}
+
+/// Keepalive settings for links, `None` if disabled
+///
+/// A peer is dead after the idle timeout; with that disabled, pings only
+/// measure the connection.
+fn keepalive_config(network: &NetworkConfig) -> Option<KeepaliveConfig> {
+    (network.keepalive_secs > 0).then(|| KeepaliveConfig {
+        interval: Duration::from_secs(network.keepalive_secs),
+        timeout: network
+            .timeouts
+            .get(TimeoutPhase::Idle)
+            .unwrap_or(Duration::MAX),
+    })
+}
+
+/// Send the frames queued for the peer, priority frames first
+///
+/// Returns once the manager drops the peer.
+async fn send_queued(
+    connection: &dyn Connection,
+    queues: &mut ConnectionQueues,
+    replay: Option<ReplayGuard>,
+) -> Result<()> {
+    let mut channel = None;
+    while let Some(frame) = queues.next_frame().await {
+        let channel = match &mut channel {
+            Some(channel) => channel,
+            None => {
+                let mut opened = Channel::open(connection, ChannelId::Control).await?;
+                opened.set_replay_guard(replay.clone());
+                channel.insert(opened)
+            }
+        };
+        channel.send(&frame).await?;
+    }
+    Ok(())
+}
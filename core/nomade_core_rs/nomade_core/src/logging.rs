@@ -0,0 +1,288 @@
+//! Logging subsystem
+//!
+//! Installs a tracing subscriber with three outputs: stderr, a size-rotated
+//! log file under `<data_dir>/logs`, and an in-process channel of
+//! structured `LogRecord`s that the FFI layer forwards to the in-app debug
+//! console. Levels are configured globally and per module through
+//! `NomadeConfig::log_level` and `NomadeConfig::log_filters`.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context as LayerContext, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use crate::config::LogLevel;
+use crate::{NomadeConfig, Result};
+
+/// Log directory under the data directory
+pub const LOG_DIR: &str = "logs";
+/// Active log file name
+const LOG_FILE: &str = "nomade.log";
+/// Size at which the active log file is rotated
+const MAX_LOG_FILE_SIZE: u64 = 5 * 1024 * 1024;
+/// Number of rotated files kept besides the active one
+const MAX_ROTATED_FILES: usize = 3;
+/// Records buffered for slow log subscribers
+const LOG_CHANNEL_SIZE: usize = 512;
+
+/// Structured log record delivered to the debug console
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub level: String,
+    /// Module path that emitted the record
+    pub target: String,
+    pub message: String,
+    /// Structured fields other than the message
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Layer turning tracing events into `LogRecord`s on a channel
+pub struct LogSinkLayer {
+    tx: broadcast::Sender<LogRecord>,
+}
+
+impl LogSinkLayer {
+    /// Create layer publishing to `tx`
+    pub fn new(tx: broadcast::Sender<LogRecord>) -> Self {
+        Self { tx }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogSinkLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: LayerContext<'_, S>) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let mut visitor = RecordVisitor::default();
+        event.record(&mut visitor);
+
+        let metadata = event.metadata();
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let _ = self.tx.send(LogRecord {
+            timestamp_ms,
+            level: metadata.level().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+#[derive(Default)]
+struct RecordVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for RecordVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, format!("{:?}", value));
+    }
+}
+
+impl RecordVisitor {
+    fn record(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = value;
+        } else {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+/// Log file writer rotating by size
+///
+/// `nomade.log` is renamed to `nomade.log.1` (shifting older files up)
+/// once it would exceed the size limit; the oldest file is dropped.
+pub struct RollingFile {
+    dir: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RollingFile {
+    /// Open the active log file in `dir`, creating the directory if needed
+    pub fn open(dir: impl AsRef<Path>, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let file = Self::open_active(&dir)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            dir,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn open_active(dir: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{}.{}", LOG_FILE, index))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+            self.size = 0;
+            return Ok(());
+        }
+        let _ = std::fs::remove_file(self.rotated_path(self.max_files));
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        std::fs::rename(self.dir.join(LOG_FILE), self.rotated_path(1))?;
+        self.file = Self::open_active(&self.dir)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn log_channel() -> &'static broadcast::Sender<LogRecord> {
+    static CHANNEL: OnceLock<broadcast::Sender<LogRecord>> = OnceLock::new();
+    CHANNEL.get_or_init(|| broadcast::channel(LOG_CHANNEL_SIZE).0)
+}
+
+/// Subscribe to structured log records
+pub fn subscribe() -> broadcast::Receiver<LogRecord> {
+    log_channel().subscribe()
+}
+
+fn level(level: LogLevel) -> Level {
+    level
+        .as_str()
+        .parse()
+        .expect("log levels map to tracing levels")
+}
+
+/// Build the level filter from the configuration
+pub fn targets(config: &NomadeConfig) -> Targets {
+    config.log_filters.iter().fold(
+        Targets::new().with_default(level(config.log_level)),
+        |t, (target, l)| t.with_target(target.clone(), level(*l)),
+    )
+}
+
+/// Install the global tracing subscriber
+///
+/// Does nothing if a subscriber is already installed (by the host, or by a
+/// previous `init()` in this process).
+pub fn install(config: &NomadeConfig) -> Result<()> {
+    let file = RollingFile::open(
+        config.data_dir.join(LOG_DIR),
+        MAX_LOG_FILE_SIZE,
+        MAX_ROTATED_FILES,
+    )?;
+    let result = tracing_subscriber::registry()
+        .with(targets(config))
+        .with(tracing_subscriber::fmt::layer())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(file)),
+        )
+        .with(LogSinkLayer::new(log_channel().clone()))
+        .try_init();
+    if result.is_err() {
+        tracing::debug!("Tracing subscriber already installed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_layer_emits_structured_records() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let subscriber = tracing_subscriber::registry().with(LogSinkLayer::new(tx));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(peer = "phone", bytes = 42, "sync stalled");
+        });
+
+        let record = rx.try_recv().unwrap();
+        assert_eq!(record.level, "WARN");
+        assert_eq!(record.message, "sync stalled");
+        assert_eq!(record.fields["peer"], "phone");
+        assert_eq!(record.fields["bytes"], "42");
+        assert!(record.target.ends_with("logging::tests"));
+    }
+
+    #[test]
+    fn test_per_module_levels() {
+        let mut config = NomadeConfig::new(std::env::temp_dir());
+        config.log_level = LogLevel::Warn;
+        config
+            .log_filters
+            .insert("nomade_quic".into(), LogLevel::Trace);
+        let targets = targets(&config);
+
+        assert!(targets.would_enable("nomade_quic::frame", &Level::TRACE));
+        assert!(!targets.would_enable("nomade_sync", &Level::INFO));
+        assert!(targets.would_enable("nomade_sync", &Level::WARN));
+    }
+
+    #[test]
+    fn test_rolling_file_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = RollingFile::open(dir.path(), 10, 2).unwrap();
+        for line in ["first---\n", "second--\n", "third---\n", "fourth--\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("nomade.log"), "fourth--\n");
+        assert_eq!(read("nomade.log.1"), "third---\n");
+        assert_eq!(read("nomade.log.2"), "second--\n");
+        assert!(!dir.path().join("nomade.log.3").exists());
+    }
+}
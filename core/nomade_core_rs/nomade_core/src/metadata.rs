@@ -0,0 +1,106 @@
+//! Encrypted SQLite metadata store
+//!
+//! With `encrypt_metadata`, the `sqlite://` artifact store is opened with
+//! SQLCipher under a key derived from the device identity
+//! (`KeyPurpose::StorageAtRest`). A plaintext database is encrypted on the
+//! first such start. Rotating the key bumps a generation, kept in a small
+//! state file, that is mixed into the derivation; the database is rekeyed
+//! first, so a rotation interrupted before the state is saved is completed
+//! on the next open.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use nomade_crypto::fs::write_durable;
+use nomade_crypto::{KeyPurpose, Keystore};
+use nomade_storage::backend::parse_url;
+use nomade_storage::SqliteStore;
+use serde::{Deserialize, Serialize};
+
+use crate::{CoreError, Result};
+
+/// Key generation of the metadata database under the data directory
+pub(crate) const METADATA_KEY_FILE: &str = "metadata_key.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeyState {
+    generation: u32,
+}
+
+/// SQLite store encrypted under a rotating device key
+pub struct EncryptedMetadata {
+    store: Arc<SqliteStore>,
+    state_path: PathBuf,
+    generation: Mutex<u32>,
+}
+
+impl EncryptedMetadata {
+    /// Open the `sqlite://` store at `url`, encrypting it if needed
+    pub fn open(keystore: &Keystore, url: &str, state_path: PathBuf) -> Result<Self> {
+        let (scheme, location) = parse_url(url)?;
+        if scheme != "sqlite" {
+            return Err(CoreError::InvalidConfig(format!(
+                "encrypt_metadata needs a sqlite:// storage_url, got {}",
+                url
+            )));
+        }
+        let state: KeyState = match std::fs::read(&state_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => KeyState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let path = Path::new(location);
+        let current = key(keystore, state.generation)?;
+        let (store, generation) = match SqliteStore::open_encrypted(path, &current) {
+            Ok(store) => (store, state.generation),
+            Err(e) => {
+                // Rekeyed by a rotation that did not get to save its state
+                let next = state.generation + 1;
+                match SqliteStore::open_encrypted(path, &key(keystore, next)?) {
+                    Ok(store) => {
+                        tracing::info!("Completing metadata key rotation to {}", next);
+                        save(&state_path, next)?;
+                        (store, next)
+                    }
+                    Err(_) => return Err(e.into()),
+                }
+            }
+        };
+        Ok(Self {
+            store: Arc::new(store),
+            state_path,
+            generation: Mutex::new(generation),
+        })
+    }
+
+    /// The encrypted store
+    pub fn store(&self) -> &Arc<SqliteStore> {
+        &self.store
+    }
+
+    /// Rotations so far
+    pub fn generation(&self) -> u32 {
+        *self.generation.lock().unwrap()
+    }
+
+    /// Rekey the database under the next key generation
+    pub fn rotate(&self, keystore: &Keystore) -> Result<u32> {
+        let mut generation = self.generation.lock().unwrap();
+        let next = *generation + 1;
+        self.store.rekey(&key(keystore, next)?)?;
+        save(&self.state_path, next)?;
+        *generation = next;
+        tracing::info!("Rotated metadata key to generation {}", next);
+        Ok(next)
+    }
+}
+
+fn key(keystore: &Keystore, generation: u32) -> Result<[u8; 32]> {
+    Ok(keystore.device_key(KeyPurpose::StorageAtRest, &generation.to_le_bytes())?)
+}
+
+fn save(path: &Path, generation: u32) -> Result<()> {
+    // The database is already rekeyed, so the generation must survive a crash
+    write_durable(path, &serde_json::to_vec(&KeyState { generation })?)?;
+    Ok(())
+}
@@ -0,0 +1,318 @@
+//! Schema migrations for persisted stores
+//!
+//! Every store under the data directory has a schema version, recorded in
+//! `schema.json`. At startup, before any store is opened, stores written
+//! by an older release are upgraded by running their migration steps in
+//! order. The store is copied to `<name>.v<from>.bak` first, and the
+//! recorded version advances after each step, so an interrupted upgrade
+//! resumes where it stopped. Data written by a newer release is refused
+//! instead of being misread. Files that predate versioning count as
+//! version 0.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use nomade_crypto::fs::write_durable;
+use nomade_crypto::{DeviceId, TrustedDevice};
+use nomade_storage::CollectionOp;
+use nomade_sync::Outbox;
+use serde::de::DeserializeOwned;
+
+use crate::config::ARTIFACTS_DIR;
+use crate::runtime::{CHUNK_INDEX_FILE, COLLECTIONS_FILE, OUTBOX_FILE, TRUST_STORE_FILE};
+use crate::{CoreError, Result};
+
+/// Schema versions file under the data directory
+pub const SCHEMA_FILE: &str = "schema.json";
+
+/// One upgrade step of a store
+pub struct Migration {
+    /// Version the store is at after this step
+    pub to: u32,
+    pub description: &'static str,
+    /// Rewrite the store at the given path in place
+    pub apply: fn(&Path) -> anyhow::Result<()>,
+}
+
+/// Persisted store and the steps that bring it to the current version
+pub struct StoreSchema {
+    pub name: &'static str,
+    /// File or directory relative to the data directory
+    pub path: &'static str,
+    /// Steps in ascending `to` order
+    pub migrations: Vec<Migration>,
+}
+
+impl StoreSchema {
+    /// Version this build reads and writes
+    pub fn current(&self) -> u32 {
+        self.migrations.last().map_or(0, |migration| migration.to)
+    }
+}
+
+/// Store upgraded by `migrate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upgrade {
+    pub store: String,
+    pub from: u32,
+    pub to: u32,
+    pub backup: PathBuf,
+}
+
+/// Stores persisted by the runtime
+pub fn stores() -> Vec<StoreSchema> {
+    vec![
+        StoreSchema {
+            name: "trust",
+            path: TRUST_STORE_FILE,
+            migrations: vec![
+                adopt("Adopt unversioned trust store", |path| {
+                    check_json::<HashMap<DeviceId, TrustedDevice>>(path)
+                }),
+                Migration {
+                    to: 2,
+                    description: "Move devices under a key, next to trusted users",
+                    apply: nest_trusted_devices,
+                },
+            ],
+        },
+        StoreSchema {
+            name: "collections",
+            path: COLLECTIONS_FILE,
+            migrations: vec![adopt("Adopt unversioned collection log", |path| {
+                check_json::<Vec<CollectionOp>>(path)
+            })],
+        },
+        StoreSchema {
+            name: "artifacts",
+            path: ARTIFACTS_DIR,
+            migrations: vec![adopt("Adopt unversioned artifact index", |_| Ok(()))],
+        },
+        StoreSchema {
+            name: "outbox",
+            path: OUTBOX_FILE,
+            migrations: vec![adopt("Adopt unversioned outbox", |path| {
+                Outbox::open(path).map(drop)
+            })],
+        },
+        StoreSchema {
+            name: "chunks",
+            path: CHUNK_INDEX_FILE,
+            migrations: vec![adopt("Adopt unversioned chunk index", |_| Ok(()))],
+        },
+    ]
+}
+
+/// Bring every store under `data_dir` to its current version
+///
+/// Stores that do not exist yet are recorded at their current version.
+pub fn migrate(data_dir: &Path, schemas: &[StoreSchema]) -> Result<Vec<Upgrade>> {
+    let schema_path = data_dir.join(SCHEMA_FILE);
+    let mut versions: BTreeMap<String, u32> = match std::fs::read(&schema_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut upgrades = Vec::new();
+
+    for schema in schemas {
+        let path = data_dir.join(schema.path);
+        let current = schema.current();
+        let from = match versions.get(schema.name) {
+            Some(version) if *version > current || path.exists() => *version,
+            // Never written, so there is nothing to upgrade
+            Some(_) => current,
+            None if path.exists() => 0,
+            None => current,
+        };
+        if from > current {
+            return Err(CoreError::SchemaTooNew {
+                store: schema.name.to_string(),
+                found: from,
+                supported: current,
+            });
+        }
+        if from < current {
+            let backup = backup_path(&path, from);
+            copy_recursive(&path, &backup)?;
+            for migration in schema.migrations.iter().filter(|m| m.to > from) {
+                tracing::info!(
+                    "Migrating {} to v{}: {}",
+                    schema.name,
+                    migration.to,
+                    migration.description
+                );
+                (migration.apply)(&path)?;
+                versions.insert(schema.name.to_string(), migration.to);
+                save(&schema_path, &versions)?;
+            }
+            upgrades.push(Upgrade {
+                store: schema.name.to_string(),
+                from,
+                to: current,
+                backup,
+            });
+        }
+        versions.insert(schema.name.to_string(), current);
+    }
+    save(&schema_path, &versions)?;
+    Ok(upgrades)
+}
+
+fn adopt(description: &'static str, apply: fn(&Path) -> anyhow::Result<()>) -> Migration {
+    Migration {
+        to: 1,
+        description,
+        apply,
+    }
+}
+
+/// Trust store v2: `{"devices": <v1 map>, "users": {}}`
+fn nest_trusted_devices(path: &Path) -> anyhow::Result<()> {
+    let devices: HashMap<DeviceId, TrustedDevice> = serde_json::from_slice(&std::fs::read(path)?)?;
+    let stored = serde_json::json!({ "devices": devices, "users": {} });
+    write_durable(path, &serde_json::to_vec(&stored)?)?;
+    Ok(())
+}
+
+/// Fail unless the file parses as `T`
+fn check_json<T: DeserializeOwned>(path: &Path) -> anyhow::Result<()> {
+    serde_json::from_slice::<T>(&std::fs::read(path)?)?;
+    Ok(())
+}
+
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    path.with_file_name(name)
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    if to.is_dir() {
+        std::fs::remove_dir_all(to)?;
+    }
+    if !from.is_dir() {
+        std::fs::copy(from, to)?;
+        return Ok(());
+    }
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+fn save(path: &Path, versions: &BTreeMap<String, u32>) -> Result<()> {
+    write_durable(path, &serde_json::to_vec_pretty(versions)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::TrustStore;
+    use nomade_storage::CollectionStore;
+
+    /// Files as written before schema versioning (version 0)
+    fn write_v0_fixtures(dir: &Path) {
+        let trust = serde_json::json!({
+            "a1b2": {
+                "device_id": "a1b2",
+                "device_name": "Phone",
+                "public_key": vec![7u8; 32],
+                "state": "Trusted"
+            }
+        });
+        std::fs::write(dir.join(TRUST_STORE_FILE), trust.to_string()).unwrap();
+        let collections = r#"[{"collection_id":"c1","stamp":{"counter":1,"replica":"a1b2"},
+            "kind":{"Create":{"name":"Notes","parent":null,"position":0}}}]"#;
+        std::fs::write(dir.join(COLLECTIONS_FILE), collections).unwrap();
+        std::fs::create_dir_all(dir.join(ARTIFACTS_DIR).join("snap")).unwrap();
+        std::fs::write(dir.join(ARTIFACTS_DIR).join("snap").join("db"), b"x").unwrap();
+    }
+
+    fn recorded(dir: &Path) -> BTreeMap<String, u32> {
+        serde_json::from_slice(&std::fs::read(dir.join(SCHEMA_FILE)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_migrates_unversioned_stores() {
+        let dir = tempfile::tempdir().unwrap();
+        write_v0_fixtures(dir.path());
+
+        let upgrades = migrate(dir.path(), &stores()).unwrap();
+        let upgraded: Vec<_> = upgrades.iter().map(|u| u.store.as_str()).collect();
+        assert_eq!(upgraded, ["trust", "collections", "artifacts"]);
+        assert!(upgrades.iter().all(|u| u.from == 0 && u.backup.exists()));
+        assert!(dir.path().join("artifacts.v0.bak/snap/db").is_file());
+        let versions = recorded(dir.path());
+        assert_eq!(versions["trust"], 2);
+        assert!(versions
+            .iter()
+            .all(|(store, version)| store == "trust" || *version == 1));
+
+        let trust = TrustStore::open(dir.path().join(TRUST_STORE_FILE)).unwrap();
+        assert_eq!(trust.list().count(), 1);
+        let collections = CollectionStore::open(dir.path().join(COLLECTIONS_FILE), "a1b2").unwrap();
+        assert_eq!(collections.list()[0].name, "Notes");
+
+        // Already current: nothing to do
+        assert!(migrate(dir.path(), &stores()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_runs_pending_steps_in_order_and_refuses_newer_data() {
+        fn append(path: &Path, step: &str) -> anyhow::Result<()> {
+            let mut data = std::fs::read_to_string(path)?;
+            data.push_str(step);
+            Ok(std::fs::write(path, data)?)
+        }
+        let schema = || StoreSchema {
+            name: "log",
+            path: "log.txt",
+            migrations: vec![
+                adopt("baseline", |_| Ok(())),
+                Migration {
+                    to: 2,
+                    description: "second",
+                    apply: |path| append(path, "2"),
+                },
+                Migration {
+                    to: 3,
+                    description: "third",
+                    apply: |path| append(path, "3"),
+                },
+            ],
+        };
+
+        // A fresh store starts at the current version
+        let dir = tempfile::tempdir().unwrap();
+        assert!(migrate(dir.path(), &[schema()]).unwrap().is_empty());
+        assert_eq!(recorded(dir.path())["log"], 3);
+        // So does one recorded at an old version but never written
+        std::fs::write(dir.path().join(SCHEMA_FILE), r#"{"log":1}"#).unwrap();
+        assert!(migrate(dir.path(), &[schema()]).unwrap().is_empty());
+        assert_eq!(recorded(dir.path())["log"], 3);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        std::fs::write(&path, "1").unwrap();
+        std::fs::write(dir.path().join(SCHEMA_FILE), r#"{"log":1}"#).unwrap();
+        let upgrades = migrate(dir.path(), &[schema()]).unwrap();
+        assert_eq!((upgrades[0].from, upgrades[0].to), (1, 3));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "123");
+        assert_eq!(std::fs::read_to_string(&upgrades[0].backup).unwrap(), "1");
+
+        std::fs::write(dir.path().join(SCHEMA_FILE), r#"{"log":4}"#).unwrap();
+        assert!(matches!(
+            migrate(dir.path(), &[schema()]),
+            Err(CoreError::SchemaTooNew {
+                found: 4,
+                supported: 3,
+                ..
+            })
+        ));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "123");
+    }
+}
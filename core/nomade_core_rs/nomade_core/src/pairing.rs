@@ -0,0 +1,147 @@
+//! Pairing from a short code
+//!
+//! The device showing the code enters pairing mode with
+//! `start_code_pairing`; while it is on, the listener lets unpaired
+//! devices in and runs the PAKE with the first one instead of linking to
+//! it. The other device dials with the typed code in `pair_with_code`.
+//! Once the key is confirmed both sides swap their signed `PairingOffer`s
+//! encrypted under it, and accept them as if the offers had been scanned.
+//!
+//! A code is good for one attempt, so an attacker gets a single online
+//! guess per code: the shown code is used up by the first device that
+//! tries it, and a typed code that fails is burned.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use nomade_crypto::pairing::pake::{normalize_pairing_code, MAX_CODE_LENGTH};
+use nomade_crypto::pairing::{generate_pairing_code, PakeRole};
+use nomade_crypto::{DeviceId, TrustState};
+use nomade_quic::pairing::{exchange_offers, pair_over};
+use nomade_quic::{Connection, ProtocolError, TimeoutPhase, Transport};
+
+use crate::runtime::NomadeRuntime;
+use crate::Result;
+
+/// Pairing codes shown and tried by this device
+#[derive(Default)]
+pub(crate) struct CodePairing {
+    /// Normalized code shown in pairing mode and the name to pair as
+    shown: Option<(String, String)>,
+    /// Normalized codes that failed when typed here
+    burned: HashSet<String>,
+}
+
+impl CodePairing {
+    /// Whether the device is in pairing mode
+    pub(crate) fn is_shown(&self) -> bool {
+        self.shown.is_some()
+    }
+}
+
+impl NomadeRuntime {
+    /// Enter pairing mode as `device_name`, returning the code to show
+    ///
+    /// The next unpaired device to reach the listener may pair with the
+    /// code; calling it again replaces the code.
+    pub fn start_code_pairing(&self, device_name: &str) -> Result<String> {
+        let code = generate_pairing_code(MAX_CODE_LENGTH)?;
+        self.code_pairing().lock().unwrap().shown =
+            Some((normalize_pairing_code(&code)?, device_name.to_string()));
+        Ok(code)
+    }
+
+    /// Leave pairing mode without pairing
+    pub fn cancel_code_pairing(&self) {
+        self.code_pairing().lock().unwrap().shown = None;
+    }
+
+    /// Pair as `device_name` with the device at `addr` showing `code`
+    ///
+    /// Returns the paired device. A code that fails is burned: trying it
+    /// again is refused without dialing.
+    pub async fn pair_with_code(
+        &self,
+        transport: &dyn Transport,
+        addr: &str,
+        code: &str,
+        device_name: &str,
+    ) -> Result<DeviceId> {
+        let code = normalize_pairing_code(code)?;
+        if self.code_pairing().lock().unwrap().burned.contains(&code) {
+            return Err(ProtocolError::PeerRejected("Pairing code already tried".into()).into());
+        }
+        let connection = transport.connect(addr).await?;
+        let paired = self
+            .pair_code(connection.as_ref(), PakeRole::Responder, &code, device_name)
+            .await;
+        connection.close();
+        if paired.is_err() {
+            self.code_pairing().lock().unwrap().burned.insert(code);
+        }
+        paired
+    }
+
+    /// Whether an accepted `connection` should pair with the shown code
+    /// rather than link
+    pub(crate) fn awaits_code_pairing(&self, connection: &dyn Connection) -> bool {
+        if !self.code_pairing().lock().unwrap().is_shown() {
+            return false;
+        }
+        connection.peer_device_id().is_none_or(|peer| {
+            self.trust().read().unwrap().state(&peer) != Some(&TrustState::Trusted)
+        })
+    }
+
+    /// Pair with the device on an accepted `connection` using the shown
+    /// code, which is used up whatever the outcome
+    pub(crate) async fn accept_code_pairing(
+        &self,
+        connection: Arc<dyn Connection>,
+    ) -> Result<DeviceId> {
+        let shown = self.code_pairing().lock().unwrap().shown.take();
+        let Some((code, device_name)) = shown else {
+            connection.close();
+            return Err(ProtocolError::PeerRejected("Not in pairing mode".into()).into());
+        };
+        let paired = self
+            .pair_code(
+                connection.as_ref(),
+                PakeRole::Initiator,
+                &code,
+                &device_name,
+            )
+            .await;
+        connection.close();
+        paired
+    }
+
+    /// Run the PAKE on `connection`, swap offers under its key and trust
+    /// the peer's, all within the handshake timeout
+    async fn pair_code(
+        &self,
+        connection: &dyn Connection,
+        role: PakeRole,
+        code: &str,
+        device_name: &str,
+    ) -> Result<DeviceId> {
+        let offer = self.signed_offer(device_name)?;
+        let timeouts = self.context().config().network.timeouts;
+        let offer = timeouts
+            .enforce(TimeoutPhase::Handshake, async {
+                let key = pair_over(connection, role, code, Some(self.connection_guard())).await?;
+                exchange_offers(connection, role, &key, &offer).await
+            })
+            .await?;
+        if let Some(peer) = connection.peer_device_id() {
+            if peer != offer.device_id {
+                return Err(ProtocolError::PeerRejected(format!(
+                    "{} offered {}",
+                    peer, offer.device_id
+                ))
+                .into());
+            }
+        }
+        self.trust_offer(offer)
+    }
+}
@@ -0,0 +1,4416 @@
+//! Nomade runtime
+//!
+//! `NomadeRuntime` owns every subsystem (keystore, stores, event stream,
+//! connection manager and sync engine) and wires them together. The
+//! builder brings subsystems up in dependency order; `shutdown` tears them
+//! down in reverse so no component outlives the ones it relies on.
+//! Background tasks are spawned through the runtime's `Supervisor`.
+//!
+//! One runtime is installed per process and reached by the FFI layer
+//! through `runtime()`.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, Weak};
+use std::time::Duration;
+
+use nomade_crypto::{
+    decode_pairing_offer, encode_pairing_offer, generate_key, unix_time, Attestation,
+    AttestationChain, CryptoError, DeviceId, DeviceKeypair, Endpoint, EnrollmentCertificate,
+    KeyContext, KeyRecipient, Keystore, NonceCache, OfferValidator, PairingOffer, Permissions,
+    RevocationRecord, ShareRegistry, ShareToken, TrustState, TrustStore, TrustedDevice,
+    UnlockProvider, UserIdentity, UserRevocation, ValidatorConfig, WakeToken, WakeValidator,
+    WipeCommand,
+};
+use nomade_events::{
+    run_batcher, Event, EventStream, IpcBridge, RepairStatus, Snippet, MAX_SNIPPET_LEN,
+};
+use nomade_metrics::{names, MetricsSnapshot};
+use nomade_quic::gather::interface_endpoints;
+use nomade_quic::{
+    bind_first_free, ConnectionGuard, ConnectionManager, ConnectionStats, FallbackTransport,
+    NetworkMonitor, NetworkState, PortMapConfig, PortMapper, ProtocolError, ReplayStore,
+};
+#[cfg(feature = "sqlite")]
+use nomade_storage::backend::parse_url;
+use nomade_storage::bulk;
+use nomade_storage::trash;
+use nomade_storage::{
+    collect_garbage, content_hash, default_processors, export_bundle, export_dir, import_bundle,
+    import_dir, scrub, store_health, Artifact, ArtifactStore, ArtifactSummary, BackendHealth,
+    BackupInfo, BulkProgress, BundleKey, BundleSeal, CacheTiers, CollectionStore, CompressedStore,
+    ContentStore, DedupStats, DedupStore, DerivedAssets, EvictionReport, ExportOptions,
+    ExportProgress, FieldQuery, GcReport, ImportReport, Maintenance, MetadataSchema, Page,
+    Projection, RestoreReport, Retag, SchemaRegistry, SchemaStore, ScrubReport, SortOrder,
+    StoreBackends, StoreBackups, StoreChange, StoreHealth, WatchedStore,
+};
+#[cfg(feature = "sqlite")]
+use nomade_storage::{SqliteStore, Stores};
+use nomade_sync::{
+    BudgetOutcome, BudgetReport, ChangeLog, ConflictInbox, ConflictRecord, CursorSink, EditIntents,
+    Outbox, PeerHints, ReencryptProgress, ReencryptScope, Reencryption, Resolution, RuleEvaluation,
+    SyncBudget, SyncCursor, SyncEngine, SyncError, SyncHandle, SyncPeer, SyncPreview, SyncProgress,
+    SyncRules, SyncState,
+};
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, Notify};
+
+use crate::auth::{AuthGate, SensitiveOp};
+use crate::config::StorageBackend;
+use crate::envelope::RatchetStore;
+use crate::group::GroupRoster;
+#[cfg(feature = "sqlite")]
+use crate::metadata::{EncryptedMetadata, METADATA_KEY_FILE};
+use crate::migrations;
+use crate::pairing::CodePairing;
+use crate::snapshot::{self, StateSnapshot, SNAPSHOT_FILE, SNAPSHOT_KEY_FILE};
+use crate::supervisor::Supervisor;
+use crate::{Context, CoreError, Result};
+
+/// Keystore file under the data directory
+const KEYSTORE_FILE: &str = "identity.key";
+/// Passphrase-protected keys next to the keystore file
+pub(crate) const VAULT_FILE: &str = "identity.vault";
+/// Trust store file under the data directory
+pub(crate) const TRUST_STORE_FILE: &str = "trust.json";
+/// Collection operation log under the data directory
+pub(crate) const COLLECTIONS_FILE: &str = "collections.json";
+/// Derived asset cache directory under the data directory
+const DERIVED_DIR: &str = "derived";
+/// Damaged content moved aside by scrubs, under the data directory
+const QUARANTINE_DIR: &str = "quarantine";
+/// Operations queued for unreachable peers, under the data directory
+pub(crate) const OUTBOX_FILE: &str = "outbox.json";
+/// Chunk reference index under the data directory
+pub(crate) const CHUNK_INDEX_FILE: &str = "chunks.json";
+/// Issued share tokens under the data directory
+const SHARES_FILE: &str = "shares.json";
+/// Access token of the event bridge under the data directory
+pub(crate) const IPC_TOKEN_FILE: &str = "events.token";
+/// Nonces of accepted pairing offers under the data directory
+const PAIRING_NONCES_FILE: &str = "pairing_nonces.json";
+/// IDs of accepted push wake tokens under the data directory
+const WAKE_TOKENS_FILE: &str = "wake_tokens.json";
+/// Roster of the device group this device belongs to under the data
+/// directory
+const GROUP_ROSTER_FILE: &str = "group.json";
+/// Frame sequence counters of linked peers under the data directory
+const REPLAY_COUNTERS_FILE: &str = "replay_counters.json";
+/// Ratchet sessions of linked peers under the data directory
+const RATCHETS_DIR: &str = "ratchets";
+/// Pinned artifacts and evicted content under the data directory
+const CACHE_TIERS_FILE: &str = "cache_tiers.json";
+/// Sync conflicts and versions agreed with peers under the data directory
+const CONFLICTS_FILE: &str = "conflicts.json";
+/// Sequence of local changes served to peers' cursors under the data directory
+const CHANGE_LOG_FILE: &str = "changes.json";
+/// Data keys left to rotate after a revocation under the data directory
+const REENCRYPT_FILE: &str = "reencrypt.json";
+/// Times of the last scrub and garbage collection under the data directory
+const MAINTENANCE_FILE: &str = "maintenance.json";
+/// Custom field schemas of artifact types under the data directory
+const SCHEMAS_FILE: &str = "schemas.json";
+/// Point-in-time backups of the store under the data directory
+const BACKUPS_DIR: &str = "backups";
+/// Files tracked in the synced folder under the data directory
+#[cfg(feature = "folder-sync")]
+const FOLDER_INDEX_FILE: &str = "folder.json";
+
+/// Trust store `peer_data` key holding a peer's `SyncRules`
+const SYNC_RULES_KEY: &str = "sync_rules";
+/// Trust store `peer_data` key holding the `SyncCursor` last acknowledged
+/// from a peer
+const SYNC_CURSOR_KEY: &str = "sync_cursor";
+
+/// Characters of a saved snippet's first line kept as its title
+const SNIPPET_TITLE_LEN: usize = 80;
+
+/// Interval between warm-start snapshots
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Interval between takes of local changes into the change log
+const CHANGE_LOG_INTERVAL: Duration = Duration::from_secs(30);
+/// Interval between batches of data keys rotated after a revocation
+const REENCRYPT_INTERVAL: Duration = Duration::from_secs(10);
+/// Data keys rotated per batch
+const REENCRYPT_BATCH: usize = 64;
+/// Interval between checks for keystore keys to auto-lock
+const AUTO_LOCK_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval between network interface probes
+const NETWORK_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// Interval between content scrubs
+const SCRUB_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Interval between purges of artifacts past the trash retention
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Time background tasks get to exit after cancellation
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+/// Time subscribers get to consume pending events at shutdown
+const EVENT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longest a push-triggered sync may run, whatever the platform allows
+pub const MAX_WAKE_BUDGET: Duration = Duration::from_secs(5 * 60);
+
+/// Lifecycle state of the runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeState {
+    Running,
+    Stopping,
+    Stopped,
+}
+
+/// How a push-triggered sync ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WakeOutcome {
+    Completed,
+    /// Sync failed or was cancelled; see `error`
+    Failed,
+    /// Budget ran out mid-sync; progress so far is kept
+    TimedOut,
+    /// The sender did not connect within the budget
+    PeerUnavailable,
+}
+
+/// Quality of a peer's connection, from keepalive round trips
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ConnectionQuality {
+    /// Exponentially smoothed round-trip time
+    pub rtt_ms: Option<u64>,
+    /// Lowest round-trip time observed
+    pub min_rtt_ms: Option<u64>,
+    /// Fraction of pings lost (0.0 - 1.0)
+    pub loss_rate: f64,
+}
+
+impl From<ConnectionStats> for ConnectionQuality {
+    fn from(stats: ConnectionStats) -> Self {
+        let ms = |rtt: Option<Duration>| rtt.map(|rtt| rtt.as_millis() as u64);
+        Self {
+            rtt_ms: ms(stats.smoothed_rtt),
+            min_rtt_ms: ms(stats.min_rtt),
+            loss_rate: stats.loss_rate(),
+        }
+    }
+}
+
+/// Result of `handle_push`
+/// A paired device's identity statement
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AttestationInfo {
+    pub attestation: Attestation,
+    /// Devices vouching from this one to the statement's device, or `None`
+    /// if no chain of countersignatures connects them
+    pub vouch_path: Option<Vec<DeviceId>>,
+}
+
+/// Lock state of the keystore
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeystoreStatus {
+    /// A passphrase protects keystore keys
+    pub protected: bool,
+    pub unlocked: bool,
+    /// Seconds before keys auto-lock, while unlocked
+    pub remaining_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WakeReport {
+    /// Device that sent the wake token
+    pub peer_id: String,
+    pub outcome: WakeOutcome,
+    pub artifacts_synced: usize,
+    pub error: Option<String>,
+}
+
+/// Builder assembling a `NomadeRuntime` from a context
+///
+/// Subsystems not supplied explicitly are created from the configuration.
+pub struct NomadeRuntimeBuilder {
+    context: Context,
+    keystore: Option<Keystore>,
+    artifacts: Option<Arc<dyn ArtifactStore>>,
+    content: Option<Arc<dyn ContentStore>>,
+    trust: Option<TrustStore>,
+    events: Option<EventStream>,
+    handle: Option<Handle>,
+    auth_gate: Option<Arc<dyn AuthGate>>,
+}
+
+impl NomadeRuntimeBuilder {
+    /// Use an existing keystore instead of the one under the data directory
+    pub fn keystore(mut self, keystore: Keystore) -> Self {
+        self.keystore = Some(keystore);
+        self
+    }
+
+    /// Use an existing artifact store instead of the configured backend
+    pub fn artifact_store(mut self, store: Arc<dyn ArtifactStore>) -> Self {
+        self.artifacts = Some(store);
+        self
+    }
+
+    /// Use an existing content store instead of the configured backend
+    pub fn content_store(mut self, store: Arc<dyn ContentStore>) -> Self {
+        self.content = Some(store);
+        self
+    }
+
+    /// Use an existing trust store
+    pub fn trust_store(mut self, trust: TrustStore) -> Self {
+        self.trust = Some(trust);
+        self
+    }
+
+    /// Publish to an existing event stream
+    pub fn event_stream(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Ask `gate` to authenticate the user before gated operations
+    pub fn auth_gate(mut self, gate: Arc<dyn AuthGate>) -> Self {
+        self.auth_gate = Some(gate);
+        self
+    }
+
+    /// Spawn background tasks onto the given tokio runtime
+    ///
+    /// Defaults to the current runtime, or a process-wide one when called
+    /// outside of tokio (e.g. from the FFI bridge).
+    pub fn handle(mut self, handle: Handle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Open all subsystems in dependency order
+    pub fn build(self) -> Result<NomadeRuntime> {
+        let config = self.context.config();
+        if config.paranoid {
+            nomade_crypto::self_test()?;
+            tracing::info!("Crypto self-test passed");
+        }
+        std::fs::create_dir_all(&config.data_dir)?;
+        for upgrade in migrations::migrate(&config.data_dir, &migrations::stores())? {
+            tracing::info!(
+                "Upgraded {} from v{} to v{} (backup at {})",
+                upgrade.store,
+                upgrade.from,
+                upgrade.to,
+                upgrade.backup.display()
+            );
+        }
+        let data_path = |name: &str| -> PathBuf { config.data_dir.join(name) };
+
+        let keystore = match self.keystore {
+            Some(keystore) => keystore,
+            None => Keystore::open(data_path(KEYSTORE_FILE))?,
+        }
+        .with_auto_lock(config.auto_lock());
+        #[cfg(feature = "sqlite")]
+        let mut metadata = None;
+        #[cfg(feature = "sqlite")]
+        let mut sqlite = None;
+        let (artifacts, content): (Arc<dyn ArtifactStore>, Arc<dyn ContentStore>) =
+            match (self.artifacts, self.content) {
+                (Some(artifacts), Some(content)) => (artifacts, content),
+                (artifacts, content) => {
+                    let url = config.artifact_store_url();
+                    #[cfg(feature = "sqlite")]
+                    let defaults = if config.encrypt_metadata {
+                        let opened = metadata.insert(EncryptedMetadata::open(
+                            &keystore,
+                            &url,
+                            data_path(METADATA_KEY_FILE),
+                        )?);
+                        sqlite = Some(opened.store().clone());
+                        Stores::shared_arc(opened.store().clone())
+                    } else if let ("sqlite", location) = parse_url(&url)? {
+                        let store = Arc::new(SqliteStore::open(location)?);
+                        sqlite = Some(store.clone());
+                        Stores::shared_arc(store)
+                    } else {
+                        StoreBackends::builtin().open(&url)?
+                    };
+                    #[cfg(not(feature = "sqlite"))]
+                    let defaults = match config.encrypt_metadata {
+                        true => {
+                            return Err(CoreError::InvalidConfig(
+                                "encrypt_metadata needs a build with the sqlite feature".into(),
+                            ))
+                        }
+                        false => StoreBackends::builtin().open(&url)?,
+                    };
+                    #[cfg(feature = "sqlite")]
+                    if artifacts.is_some() {
+                        sqlite = None;
+                    }
+                    (
+                        artifacts.unwrap_or(defaults.artifacts),
+                        content.unwrap_or(defaults.content),
+                    )
+                }
+            };
+        let content: Arc<dyn ContentStore> = match config.sync.compression() {
+            Some(compression) => Arc::new(CompressedStore::new(content, compression)),
+            None => content,
+        };
+        let dedup = Arc::new(match config.storage_backend {
+            StorageBackend::Memory => DedupStore::new(content),
+            StorageBackend::Persistent => DedupStore::open(content, data_path(CHUNK_INDEX_FILE))?,
+        });
+        let content: Arc<dyn ContentStore> = dedup.clone();
+        let trust = match self.trust {
+            Some(trust) => trust,
+            None => TrustStore::open(data_path(TRUST_STORE_FILE))?,
+        };
+        let offers = Mutex::new(OfferValidator::new(
+            ValidatorConfig::default(),
+            match config.storage_backend {
+                StorageBackend::Memory => NonceCache::new(),
+                StorageBackend::Persistent => NonceCache::open(data_path(PAIRING_NONCES_FILE))?,
+            },
+        ));
+        let wakes = Mutex::new(match config.storage_backend {
+            StorageBackend::Memory => WakeValidator::new(keystore.keypair().device_id().clone()),
+            StorageBackend::Persistent => WakeValidator::open(
+                keystore.keypair().device_id().clone(),
+                data_path(WAKE_TOKENS_FILE),
+            )?,
+        });
+        let group = Mutex::new(match config.storage_backend {
+            StorageBackend::Memory => GroupRoster::new(),
+            StorageBackend::Persistent => GroupRoster::open(data_path(GROUP_ROSTER_FILE))?,
+        });
+        let replay = match config.storage_backend {
+            StorageBackend::Memory => ReplayStore::new(),
+            StorageBackend::Persistent => ReplayStore::open(data_path(REPLAY_COUNTERS_FILE))?,
+        };
+        let ratchets = match config.storage_backend {
+            StorageBackend::Memory => RatchetStore::new(),
+            StorageBackend::Persistent => RatchetStore::open(data_path(RATCHETS_DIR))?,
+        };
+        let cache = Arc::new(Mutex::new(match config.storage_backend {
+            StorageBackend::Memory => CacheTiers::new(),
+            StorageBackend::Persistent => CacheTiers::open(data_path(CACHE_TIERS_FILE))?,
+        }));
+        let issuer = *keystore.keypair().verifying_key();
+        let shares = Arc::new(RwLock::new(match config.storage_backend {
+            StorageBackend::Memory => ShareRegistry::new(issuer),
+            StorageBackend::Persistent => ShareRegistry::open(issuer, data_path(SHARES_FILE))?,
+        }));
+        let snapshot_path = match config.storage_backend {
+            StorageBackend::Memory => None,
+            StorageBackend::Persistent => Some(data_path(SNAPSHOT_FILE)),
+        };
+        let snapshot_key = snapshot::snapshot_context(
+            &keystore,
+            snapshot_path
+                .is_some()
+                .then(|| data_path(SNAPSHOT_KEY_FILE))
+                .as_deref(),
+        )?;
+        let quarantine_dir = match config.storage_backend {
+            StorageBackend::Memory => None,
+            StorageBackend::Persistent => Some(data_path(QUARANTINE_DIR)),
+        };
+        let maintenance = match config.storage_backend {
+            StorageBackend::Memory => Maintenance::new(),
+            StorageBackend::Persistent => Maintenance::open(data_path(MAINTENANCE_FILE))?,
+        };
+        let warm_snapshot = snapshot_path.as_deref().and_then(|path| {
+            match snapshot::read(path, &snapshot_key, &keystore.device_id().0) {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    tracing::warn!("Discarding unusable snapshot, rebuilding state: {}", e);
+                    let _ = std::fs::remove_file(path);
+                    None
+                }
+            }
+        });
+        let schemas = Arc::new(match config.storage_backend {
+            StorageBackend::Memory => SchemaRegistry::new(),
+            StorageBackend::Persistent => SchemaRegistry::open(data_path(SCHEMAS_FILE))?,
+        });
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &sqlite {
+            sqlite.index_fields(schemas.clone())?;
+        }
+        let backups = Arc::new(match config.storage_backend {
+            StorageBackend::Memory => StoreBackups::new(),
+            StorageBackend::Persistent => StoreBackups::open(data_path(BACKUPS_DIR))?,
+        });
+        let artifacts: Arc<dyn ArtifactStore> =
+            Arc::new(SchemaStore::new(artifacts, schemas.clone()));
+        let events = self.events.unwrap_or_default();
+        let watched = Arc::new(WatchedStore::new(artifacts).with_events(events.clone()));
+        let artifacts: Arc<dyn ArtifactStore> = watched.clone();
+        let replica = keystore.device_id().to_string();
+        let collections = match config.storage_backend {
+            StorageBackend::Memory => CollectionStore::new(replica),
+            StorageBackend::Persistent => {
+                CollectionStore::open(data_path(COLLECTIONS_FILE), replica)?
+            }
+        }
+        .with_events(events.clone());
+        let derived = Arc::new(match config.storage_backend {
+            StorageBackend::Memory => DerivedAssets::new(content.clone()),
+            StorageBackend::Persistent => {
+                DerivedAssets::open(data_path(DERIVED_DIR), content.clone())?
+            }
+        });
+        for processor in default_processors() {
+            derived.register(processor);
+        }
+        let outbox = Arc::new(
+            match config.storage_backend {
+                StorageBackend::Memory => Outbox::new(),
+                StorageBackend::Persistent => Outbox::open(data_path(OUTBOX_FILE))?,
+            }
+            .with_events(events.clone()),
+        );
+        let conflicts = match config.storage_backend {
+            StorageBackend::Memory => ConflictInbox::new(),
+            StorageBackend::Persistent => ConflictInbox::open(data_path(CONFLICTS_FILE))?,
+        };
+        let change_log = match config.storage_backend {
+            StorageBackend::Memory => ChangeLog::new(),
+            StorageBackend::Persistent => ChangeLog::open(data_path(CHANGE_LOG_FILE))?,
+        }
+        .with_feed(watched.watch());
+        let devices: Vec<TrustedDevice> = trust.list().cloned().collect();
+        let trust = Arc::new(RwLock::new(trust));
+        let persist_cursor: CursorSink = {
+            let trust = trust.clone();
+            Arc::new(move |peer_id, cursor| {
+                let value = serde_json::to_value(cursor).expect("cursors always serialize");
+                let device_id = DeviceId(peer_id.to_string());
+                let result =
+                    trust
+                        .write()
+                        .unwrap()
+                        .set_peer_data(&device_id, SYNC_CURSOR_KEY, value);
+                if let Err(e) = result {
+                    tracing::debug!("Not persisting sync cursor for {}: {}", peer_id, e);
+                }
+            })
+        };
+        let sync = Arc::new(
+            SyncEngine::new(artifacts.clone(), content.clone(), events.clone())
+                .with_conflicts(conflicts)
+                .with_change_log(change_log)
+                .with_cursor_sink(persist_cursor)
+                .with_delta_transfer(config.sync.delta_transfer),
+        );
+        for device in devices {
+            let peer_id = device.device_id.to_string();
+            sync.set_permissions(&peer_id, device.permissions.clone());
+            if let Some(value) = device.peer_data.get(SYNC_RULES_KEY) {
+                match serde_json::from_value::<SyncRules>(value.clone()) {
+                    Ok(rules) => {
+                        sync.set_rules(&peer_id, rules)?;
+                    }
+                    Err(e) => tracing::warn!("Ignoring bad sync rules for {}: {}", peer_id, e),
+                }
+            }
+            if let Some(value) = device.peer_data.get(SYNC_CURSOR_KEY) {
+                match serde_json::from_value::<Option<SyncCursor>>(value.clone()) {
+                    Ok(Some(cursor)) => sync.set_cursor(&peer_id, cursor),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("Ignoring bad sync cursor for {}: {}", peer_id, e),
+                }
+            }
+        }
+        let code_pairing = Arc::new(Mutex::new(CodePairing::default()));
+        let guard = Arc::new(
+            ConnectionGuard::new(config.network.limits.clone())
+                .with_events(events.clone())
+                .with_filter({
+                    let trust = trust.clone();
+                    let code_pairing = code_pairing.clone();
+                    // Unpaired devices may come in to pair with a shown code
+                    Arc::new(move |device_id| {
+                        trust.read().unwrap().check_handshake(device_id).is_ok()
+                            || code_pairing.lock().unwrap().is_shown()
+                    })
+                }),
+        );
+        let network = Arc::new(NetworkMonitor::new().with_events(events.clone()));
+        let connections = ConnectionManager::new(trust.clone(), events.clone())
+            .with_guard(guard.clone())
+            .with_network(network.clone())
+            .with_local_device(keystore.device_id().clone());
+        let handle = self
+            .handle
+            .or_else(|| Handle::try_current().ok())
+            .unwrap_or_else(|| executor().handle().clone());
+        let bridge = match &config.events.ipc_socket {
+            // Binding registers the socket with the executor's reactor
+            Some(socket) => {
+                let _entered = handle.enter();
+                Some(IpcBridge::bind(socket, data_path(IPC_TOKEN_FILE))?)
+            }
+            None => None,
+        };
+        let supervisor = Supervisor::new(handle, events.clone());
+        spawn_derived_pruner(&supervisor, &events, derived.clone(), artifacts.clone())?;
+        if let Some(quota) = config.content_quota_bytes {
+            spawn_quota_enforcer(
+                &supervisor,
+                &events,
+                cache.clone(),
+                artifacts.clone(),
+                dedup.clone(),
+                quota,
+            )?;
+        }
+        let ui_events = EventStream::new();
+        supervisor.spawn("event-batcher", {
+            let rx = events.subscribe();
+            let out = ui_events.clone();
+            let batch = config.events.batch();
+            move |cancel| async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => {}
+                    _ = run_batcher(rx, out, batch) => {}
+                }
+            }
+        })?;
+        if let Some(bridge) = bridge {
+            supervisor.spawn("event-bridge", {
+                let events = events.clone();
+                move |cancel| async move {
+                    tokio::select! {
+                        _ = cancel.cancelled() => {}
+                        result = bridge.run(events) => {
+                            if let Err(e) = result {
+                                tracing::warn!("Event bridge stopped: {}", e);
+                            }
+                        }
+                    }
+                }
+            })?;
+        }
+        let edit_intents = Arc::new(EditIntents::new(events.clone()));
+        supervisor.spawn("edit-intents", {
+            let edit_intents = edit_intents.clone();
+            move |cancel| async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => {}
+                    _ = edit_intents.run() => {}
+                }
+            }
+        })?;
+
+        supervisor.spawn("change-log", {
+            let sync = sync.clone();
+            move |cancel| async move {
+                let mut interval = tokio::time::interval(CHANGE_LOG_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                    if let Err(e) = sync.change_log().drain() {
+                        tracing::warn!("Failed to update change log: {}", e);
+                    }
+                }
+            }
+        })?;
+
+        supervisor.spawn("network-policy", {
+            let sync = sync.clone();
+            let connections = connections.clone();
+            let policy = config.sync.clone();
+            let mut state = network.subscribe();
+            move |cancel| async move {
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        changed = state.changed() => if changed.is_err() { break },
+                    }
+                    let current = *state.borrow_and_update();
+                    sync.set_transfer_mode(policy.transfer_mode(&current));
+                    let closed = connections.apply_network(&current);
+                    if closed > 0 {
+                        tracing::info!("Closed {} connections while offline", closed);
+                    }
+                }
+            }
+        })?;
+
+        let reencryption = Arc::new(match config.storage_backend {
+            StorageBackend::Memory => Reencryption::new(),
+            StorageBackend::Persistent => Reencryption::open(data_path(REENCRYPT_FILE))?,
+        });
+        spawn_key_manager(
+            &supervisor,
+            &events,
+            keystore.keypair().clone(),
+            trust.clone(),
+            sync.clone(),
+            reencryption.clone(),
+            config.sync.reencrypt_scope,
+        )?;
+
+        if let Some(folder) = &config.synced_folder {
+            #[cfg(feature = "folder-sync")]
+            {
+                let sync = Arc::new(match config.storage_backend {
+                    StorageBackend::Memory => crate::folder::FolderSync::new(
+                        &folder.path,
+                        &folder.ignore,
+                        artifacts.clone(),
+                        content.clone(),
+                    ),
+                    StorageBackend::Persistent => crate::folder::FolderSync::open(
+                        &folder.path,
+                        &folder.ignore,
+                        artifacts.clone(),
+                        content.clone(),
+                        data_path(FOLDER_INDEX_FILE),
+                    )?,
+                });
+                crate::folder::spawn(
+                    &supervisor,
+                    &events,
+                    sync,
+                    Duration::from_millis(folder.debounce_ms),
+                )?;
+            }
+            #[cfg(not(feature = "folder-sync"))]
+            tracing::warn!(
+                "Ignoring synced folder {}: built without folder-sync",
+                folder.path.display()
+            );
+        }
+
+        supervisor.spawn("conflict-resolutions", {
+            let sync = sync.clone();
+            move |cancel| async move {
+                tokio::select! {
+                    _ = cancel.cancelled() => {}
+                    _ = sync.watch_resolutions() => {}
+                }
+            }
+        })?;
+
+        tracing::info!("Nomade runtime started as {}", keystore.device_id());
+        Ok(NomadeRuntime {
+            context: self.context,
+            keystore_locked: Mutex::new(!keystore.vault().is_unlocked()),
+            #[cfg(feature = "sqlite")]
+            metadata,
+            #[cfg(feature = "sqlite")]
+            sqlite,
+            schemas,
+            backups,
+            auth_gate: self.auth_gate,
+            keystore: Arc::new(keystore),
+            artifacts,
+            watched,
+            content,
+            dedup,
+            cache,
+            collections: Mutex::new(collections),
+            derived,
+            trust,
+            offers,
+            code_pairing,
+            wakes,
+            group,
+            replay,
+            ratchets,
+            shares,
+            events,
+            ui_events,
+            connections,
+            guard,
+            listen_port: Mutex::new(None),
+            listener: Mutex::new(None),
+            port_mapper: Arc::new(PortMapper::new()),
+            network,
+            sync,
+            reencryption,
+            sync_peers: Mutex::new(HashMap::new()),
+            peer_registered: Notify::new(),
+            hydrating: Mutex::new(HashMap::new()),
+            outbox,
+            edit_intents,
+            supervisor,
+            snapshot_path,
+            quarantine_dir,
+            maintenance,
+            snapshot_key: Mutex::new(snapshot_key),
+            warm_snapshot,
+            state: Mutex::new(RuntimeState::Running),
+        })
+    }
+}
+
+/// Drop derived assets of replaced or deleted content as artifacts change
+fn spawn_derived_pruner(
+    supervisor: &Supervisor,
+    events: &EventStream,
+    derived: Arc<DerivedAssets>,
+    artifacts: Arc<dyn ArtifactStore>,
+) -> Result<()> {
+    let mut rx = events.subscribe();
+    supervisor.spawn("derived-pruner", move |cancel| async move {
+        loop {
+            let event = tokio::select! {
+                _ = cancel.cancelled() => break,
+                event = rx.recv() => event,
+            };
+            match event {
+                Ok(
+                    Event::ArtifactUpdated { .. }
+                    | Event::ArtifactDeleted { .. }
+                    | Event::Batched { .. },
+                )
+                | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if let Err(e) = derived.prune(artifacts.as_ref()) {
+                        tracing::warn!("Failed to prune derived assets: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Reseal data keys to the trusted devices at start and after
+/// revocations, and rotate the keys revoked devices may know
+///
+/// Keys are rotated only by the device coordinating the revocation.
+fn spawn_key_manager(
+    supervisor: &Supervisor,
+    events: &EventStream,
+    identity: DeviceKeypair,
+    trust: Arc<RwLock<TrustStore>>,
+    sync: Arc<SyncEngine>,
+    reencryption: Arc<Reencryption>,
+    scope: ReencryptScope,
+) -> Result<()> {
+    let mut rx = events.subscribe();
+    supervisor.spawn("key-manager", move |cancel| async move {
+        let share = || {
+            let shared = share_keys(
+                &identity,
+                &trust,
+                sync.content().as_ref(),
+                sync.store().as_ref(),
+            );
+            match shared {
+                Ok(0) => {}
+                Ok(resealed) => tracing::info!("Resealed {} data keys to paired devices", resealed),
+                Err(e) => tracing::warn!("Failed to share data keys: {}", e),
+            }
+        };
+        share();
+        let mut interval = tokio::time::interval(REENCRYPT_INTERVAL);
+        loop {
+            let event = tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = interval.tick() => {
+                    if reencryption.progress().remaining > 0 {
+                        if let Err(e) = sync.reencrypt(&reencryption, REENCRYPT_BATCH) {
+                            tracing::warn!("Failed to rotate data keys: {}", e);
+                        }
+                    }
+                    continue;
+                }
+                event = rx.recv() => event,
+            };
+            match event {
+                Ok(Event::DeviceRevoked { device_id }) => {
+                    // New keys must not be sealed to the revoked device
+                    share();
+                    let revoked = DeviceId(device_id);
+                    if let Err(e) = schedule_reencryption(
+                        &identity,
+                        &trust,
+                        &sync,
+                        &reencryption,
+                        &revoked,
+                        scope,
+                    ) {
+                        tracing::warn!("Failed to queue data keys of {}: {}", revoked, e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => share(),
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Queue the keys `revoked` may know if this device coordinates its
+/// revocation
+fn schedule_reencryption(
+    identity: &DeviceKeypair,
+    trust: &RwLock<TrustStore>,
+    sync: &SyncEngine,
+    reencryption: &Reencryption,
+    revoked: &DeviceId,
+    scope: ReencryptScope,
+) -> Result<()> {
+    let (revoked_by, at, remaining) = {
+        let trust = trust.read().unwrap();
+        let Some(TrustState::Revoked { revoked_by, at }) = trust.state(revoked).cloned() else {
+            return Ok(());
+        };
+        let mut remaining: Vec<DeviceId> = trust
+            .list()
+            .filter(|device| device.state == TrustState::Trusted)
+            .map(|device| device.device_id.clone())
+            .collect();
+        remaining.push(identity.device_id().clone());
+        (revoked_by, at, remaining)
+    };
+    if !Reencryption::coordinates(revoked, &revoked_by, identity.device_id(), &remaining) {
+        return Ok(());
+    }
+    let queued = reencryption.schedule(revoked, at, scope, sync.store().as_ref())?;
+    tracing::info!("Rotating {} data keys {} may know", queued, revoked);
+    Ok(())
+}
+
+/// Share data keys with this device and every trusted one
+fn share_keys(
+    identity: &DeviceKeypair,
+    trust: &RwLock<TrustStore>,
+    content: &dyn ContentStore,
+    artifacts: &dyn ArtifactStore,
+) -> Result<usize> {
+    let mut recipients = vec![KeyRecipient {
+        device_id: identity.device_id().clone(),
+        public_key: identity.public_key_bytes(),
+    }];
+    recipients.extend(
+        trust
+            .read()
+            .unwrap()
+            .list()
+            .filter(|device| device.state == TrustState::Trusted)
+            .map(|device| KeyRecipient {
+                device_id: device.device_id.clone(),
+                public_key: device.public_key.clone(),
+            }),
+    );
+    Ok(content.share_keys(&recipients, artifacts)?)
+}
+
+/// Evict unpinned content once artifact changes push it over `quota`
+fn spawn_quota_enforcer(
+    supervisor: &Supervisor,
+    events: &EventStream,
+    cache: Arc<Mutex<CacheTiers>>,
+    artifacts: Arc<dyn ArtifactStore>,
+    dedup: Arc<DedupStore>,
+    quota: u64,
+) -> Result<()> {
+    let mut rx = events.subscribe();
+    supervisor.spawn("quota-enforcer", move |cancel| async move {
+        loop {
+            let event = tokio::select! {
+                _ = cancel.cancelled() => break,
+                event = rx.recv() => event,
+            };
+            match event {
+                Ok(
+                    Event::ArtifactCreated { .. }
+                    | Event::ArtifactUpdated { .. }
+                    | Event::Batched { .. },
+                )
+                | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let evicted = cache
+                        .lock()
+                        .unwrap()
+                        .evict(artifacts.as_ref(), &dedup, quota);
+                    match evicted {
+                        Ok(report) if !report.evicted.is_empty() => tracing::info!(
+                            "Evicted content of {} artifacts ({} bytes) to stay within quota",
+                            report.evicted.len(),
+                            report.freed_bytes
+                        ),
+                        Ok(_) => {}
+                        Err(e) => tracing::warn!("Failed to enforce content quota: {}", e),
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Owner of all Nomade subsystems
+pub struct NomadeRuntime {
+    context: Context,
+    keystore: Arc<Keystore>,
+    /// Lock state last published as `KeystoreLockChanged`
+    keystore_locked: Mutex<bool>,
+    /// Encrypted SQLite store, with `encrypt_metadata`
+    #[cfg(feature = "sqlite")]
+    metadata: Option<EncryptedMetadata>,
+    /// Default artifact store when it is SQLite, for indexed field queries
+    #[cfg(feature = "sqlite")]
+    sqlite: Option<Arc<SqliteStore>>,
+    /// Custom field schemas checked on every artifact write
+    schemas: Arc<SchemaRegistry>,
+    /// Point-in-time backups, whose content garbage collection keeps
+    backups: Arc<StoreBackups>,
+    /// Platform prompt before operations gated by `config.auth`
+    auth_gate: Option<Arc<dyn AuthGate>>,
+    artifacts: Arc<dyn ArtifactStore>,
+    /// Same store as `artifacts`, for its change feed
+    watched: Arc<WatchedStore>,
+    content: Arc<dyn ContentStore>,
+    /// Same store as `content`, for its savings
+    dedup: Arc<DedupStore>,
+    /// Pinned artifacts and evicted content
+    cache: Arc<Mutex<CacheTiers>>,
+    collections: Mutex<CollectionStore>,
+    derived: Arc<DerivedAssets>,
+    trust: Arc<RwLock<TrustStore>>,
+    /// Replay protection for pairing offers accepted by this device
+    offers: Mutex<OfferValidator>,
+    /// Pairing code shown or tried by this device
+    code_pairing: Arc<Mutex<CodePairing>>,
+    /// Replay protection for push wake tokens
+    wakes: Mutex<WakeValidator>,
+    /// Device group this device belongs to, if any
+    group: Mutex<GroupRoster>,
+    /// Frame sequence counters per peer, kept between links
+    replay: ReplayStore,
+    /// Ratchet sessions per peer, kept between links
+    ratchets: RatchetStore,
+    /// Share tokens issued for guests
+    shares: Arc<RwLock<ShareRegistry>>,
+    events: EventStream,
+    /// `events` with bursts batched, for the app
+    ui_events: EventStream,
+    connections: ConnectionManager,
+    /// Rate limits for the listener, shared with `connections`
+    guard: Arc<ConnectionGuard>,
+    /// Port the listener bound, once `bind_listener` ran
+    listen_port: Mutex<Option<u16>>,
+    /// Listener accepting peers, once `listen` ran
+    listener: Mutex<Option<Arc<FallbackTransport>>>,
+    /// Router mapping of the listen port, if enabled
+    port_mapper: Arc<PortMapper>,
+    /// Connectivity, steering connections and sync transfers
+    network: Arc<NetworkMonitor>,
+    sync: Arc<SyncEngine>,
+    /// Data keys to rotate after revocations this device coordinates
+    reencryption: Arc<Reencryption>,
+    /// Sync endpoints of connected peers, registered by the transport
+    sync_peers: Mutex<HashMap<DeviceId, Arc<dyn SyncPeer>>>,
+    /// Woken when a sync peer is registered
+    peer_registered: Notify,
+    /// Artifacts being fetched by `retrieve_or_fetch`, one fetch each
+    hydrating: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    outbox: Arc<Outbox>,
+    edit_intents: Arc<EditIntents>,
+    supervisor: Supervisor,
+    /// Warm-start snapshot file; `None` for volatile storage
+    snapshot_path: Option<PathBuf>,
+    /// Where scrubs move damaged content; `None` for volatile storage
+    quarantine_dir: Option<PathBuf>,
+    maintenance: Maintenance,
+    /// Encrypts warm-start snapshots
+    snapshot_key: Mutex<KeyContext>,
+    warm_snapshot: Option<StateSnapshot>,
+    state: Mutex<RuntimeState>,
+}
+
+impl NomadeRuntime {
+    /// Start building a runtime for the given context
+    pub fn builder(context: Context) -> NomadeRuntimeBuilder {
+        NomadeRuntimeBuilder {
+            context,
+            keystore: None,
+            artifacts: None,
+            content: None,
+            trust: None,
+            events: None,
+            handle: None,
+            auth_gate: None,
+        }
+    }
+
+    /// Shared configuration context
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Local device keystore
+    pub fn keystore(&self) -> &Arc<Keystore> {
+        &self.keystore
+    }
+
+    /// Set or change the keystore passphrase, leaving keys unlocked
+    ///
+    /// Changing it needs the keystore unlocked.
+    pub fn set_keystore_passphrase(&self, passphrase: &str) -> Result<()> {
+        self.keystore.vault().set_passphrase(passphrase)?;
+        self.publish_lock_state();
+        Ok(())
+    }
+
+    /// Unlock keystore keys with the passphrase for `auto_lock_secs`
+    pub fn unlock_keystore(&self, passphrase: &str) -> Result<()> {
+        self.keystore.vault().unlock_with_passphrase(passphrase)?;
+        self.publish_lock_state();
+        Ok(())
+    }
+
+    /// Unlock keystore keys with a key released by a platform prompt
+    pub fn unlock_keystore_with(&self, provider: &dyn UnlockProvider) -> Result<()> {
+        self.keystore.vault().unlock_with(provider)?;
+        self.publish_lock_state();
+        Ok(())
+    }
+
+    /// Zeroize keystore keys now
+    pub fn lock_keystore(&self) {
+        self.keystore.vault().lock();
+        self.publish_lock_state();
+    }
+
+    /// Remove the keystore passphrase and the keys it protected
+    ///
+    /// Needs the keystore unlocked; gated as `DisableEncryption`.
+    pub fn remove_keystore_passphrase(&self) -> Result<()> {
+        self.authorize(SensitiveOp::DisableEncryption)?;
+        self.keystore.vault().clear_passphrase()?;
+        self.publish_lock_state();
+        Ok(())
+    }
+
+    /// Raw identity secret key, for a backup the user keeps
+    ///
+    /// Fails for hardware-backed keys, while the keystore is locked, or
+    /// unless the user authenticates (`ExportSecretKey`).
+    pub fn export_secret_key(&self) -> Result<Vec<u8>> {
+        self.keystore.vault().check_unlocked()?;
+        self.authorize(SensitiveOp::ExportSecretKey)?;
+        let secret = self
+            .keystore
+            .keypair()
+            .secret_key_bytes()
+            .ok_or(CryptoError::HardwareBacked)?;
+        tracing::warn!("Identity secret key exported");
+        Ok(secret)
+    }
+
+    /// Have the user authenticate if the policy gates `op`
+    pub fn authorize(&self, op: SensitiveOp) -> Result<()> {
+        if !self.context.config().auth.is_gated(op) {
+            return Ok(());
+        }
+        match &self.auth_gate {
+            Some(gate) => gate.require_auth(op.reason()),
+            None => Ok(()),
+        }
+    }
+
+    /// Rekey the encrypted metadata store, returning the new generation
+    pub fn rotate_metadata_key(&self) -> Result<u32> {
+        #[cfg(feature = "sqlite")]
+        if let Some(metadata) = &self.metadata {
+            return metadata.rotate(&self.keystore);
+        }
+        Err(CoreError::InvalidConfig(
+            "encrypt_metadata is not enabled".into(),
+        ))
+    }
+
+    /// Up to `limit` artifacts after `cursor` in `sort` order
+    pub fn list_page(&self, cursor: Option<&str>, limit: usize, sort: SortOrder) -> Result<Page> {
+        Ok(self.artifacts.list_page(cursor, limit, sort)?)
+    }
+
+    /// Page of artifacts reduced to the fields of `projection`
+    pub fn list_projected(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+        projection: &Projection,
+    ) -> Result<Page<ArtifactSummary>> {
+        Ok(self
+            .artifacts
+            .list_projected(cursor, limit, sort, projection)?)
+    }
+
+    /// Store artifacts in one transaction, published as one batched event
+    pub fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: impl FnMut(&BulkProgress),
+    ) -> Result<BulkProgress> {
+        Ok(bulk::store_many(
+            self.artifacts.as_ref(),
+            artifacts,
+            progress,
+        )?)
+    }
+
+    /// Delete artifacts in one transaction, published as one batched event
+    pub fn delete_many(
+        &self,
+        ids: &[String],
+        progress: impl FnMut(&BulkProgress),
+    ) -> Result<BulkProgress> {
+        Ok(bulk::delete_many(self.artifacts.as_ref(), ids, progress)?)
+    }
+
+    /// Add and remove tags on artifacts in one transaction
+    ///
+    /// Progress counts the artifacts whose tags change.
+    pub fn retag_many(
+        &self,
+        ids: &[String],
+        retag: &Retag,
+        progress: impl FnMut(&BulkProgress),
+    ) -> Result<BulkProgress> {
+        Ok(bulk::retag_many(
+            self.artifacts.as_ref(),
+            ids,
+            retag,
+            unix_time(),
+            progress,
+        )?)
+    }
+
+    /// Move an artifact to the trash, from where it can be restored
+    ///
+    /// The move syncs, so peers trash their copy as well.
+    pub fn trash_artifact(&self, id: &str) -> Result<Artifact> {
+        Ok(trash::trash(self.artifacts.as_ref(), id, unix_time())?)
+    }
+
+    /// Take an artifact back out of the trash, on peers too
+    pub fn restore_artifact(&self, id: &str) -> Result<Artifact> {
+        Ok(trash::restore(self.artifacts.as_ref(), id, unix_time())?)
+    }
+
+    /// Artifacts in the trash, most recently trashed first
+    pub fn trash(&self) -> Result<Vec<Artifact>> {
+        Ok(trash::trashed(self.artifacts.as_ref())?)
+    }
+
+    /// Delete every artifact in the trash for good, returning their IDs
+    pub fn empty_trash(&self) -> Result<Vec<String>> {
+        Ok(trash::empty_trash(self.artifacts.as_ref(), u64::MAX)?)
+    }
+
+    /// Delete artifacts in the trash for longer than `trash_retention_days`
+    pub fn purge_trash(&self) -> Result<Vec<String>> {
+        let days = self.context.config().trash_retention_days;
+        if days == 0 {
+            return Ok(Vec::new());
+        }
+        let cutoff = unix_time().saturating_sub(days * 24 * 60 * 60);
+        let purged = trash::empty_trash(self.artifacts.as_ref(), cutoff)?;
+        if !purged.is_empty() {
+            tracing::info!("Purged {} artifacts from the trash", purged.len());
+        }
+        Ok(purged)
+    }
+
+    /// Add or replace the custom field schema of an artifact type
+    pub fn register_schema(&self, schema: MetadataSchema) -> Result<()> {
+        self.schemas.register(schema)?;
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &self.sqlite {
+            sqlite.index_fields(self.schemas.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Registered custom field schemas
+    pub fn schemas(&self) -> Vec<MetadataSchema> {
+        self.schemas.list()
+    }
+
+    /// Artifacts matching `query` on an indexed custom field
+    ///
+    /// Uses the SQLite field index when artifacts are stored in SQLite and
+    /// scans the store otherwise.
+    pub fn query_artifacts(&self, query: &FieldQuery) -> Result<Vec<Artifact>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(sqlite) = &self.sqlite {
+            return Ok(sqlite.query(query)?);
+        }
+        self.schemas.check_query(query)?;
+        let mut found: Vec<_> = self
+            .artifacts
+            .list()?
+            .into_iter()
+            .filter(|artifact| query.matches(artifact))
+            .collect();
+        found.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(found)
+    }
+
+    /// Artifacts matching `query`, reduced to the fields of `projection`
+    pub fn query_projected(
+        &self,
+        query: &FieldQuery,
+        projection: &Projection,
+    ) -> Result<Vec<ArtifactSummary>> {
+        Ok(self
+            .query_artifacts(query)?
+            .into_iter()
+            .map(|artifact| projection.apply(artifact))
+            .collect())
+    }
+
+    /// Back up the metadata of every artifact as of now
+    ///
+    /// Content is shared with the store rather than copied and is kept
+    /// until the backup is deleted.
+    pub fn create_backup(&self, label: &str) -> Result<BackupInfo> {
+        let info = self
+            .backups
+            .create(self.artifacts.as_ref(), &self.dedup, label, unix_time())?;
+        tracing::info!("Backed up {} artifacts as {}", info.artifact_count, info.id);
+        Ok(info)
+    }
+
+    /// Backups taken so far, oldest first
+    pub fn backups(&self) -> Vec<BackupInfo> {
+        self.backups.list()
+    }
+
+    /// Roll the whole store back to the backup `id`
+    ///
+    /// Artifacts changed or deleted since are written back as new edits and
+    /// artifacts created since are deleted, so the rollback syncs to peers.
+    pub fn restore_backup(
+        &self,
+        id: &str,
+        progress: impl FnMut(&BulkProgress),
+    ) -> Result<RestoreReport> {
+        let report = self.backups.restore(
+            id,
+            self.artifacts.as_ref(),
+            self.dedup.as_ref(),
+            unix_time(),
+            progress,
+        )?;
+        tracing::info!(
+            "Restored backup {}: {} restored, {} removed",
+            id,
+            report.restored,
+            report.removed
+        );
+        Ok(report)
+    }
+
+    /// Delete the backup `id`, letting content only it kept be collected
+    pub fn delete_backup(&self, id: &str) -> Result<()> {
+        Ok(self.backups.delete(id)?)
+    }
+
+    /// Whether a passphrase is set and how long keys stay unlocked
+    pub fn keystore_status(&self) -> KeystoreStatus {
+        let vault = self.keystore.vault();
+        KeystoreStatus {
+            protected: vault.is_protected(),
+            unlocked: vault.is_unlocked(),
+            remaining_secs: vault.remaining().map(|left| left.as_secs()),
+        }
+    }
+
+    /// Lock keystore keys once their period elapsed, in the background
+    pub fn spawn_auto_lock(self: &Arc<Self>) -> Result<()> {
+        let runtime: Weak<Self> = Arc::downgrade(self);
+        self.supervisor
+            .spawn("auto-lock", move |cancel| async move {
+                let mut interval = tokio::time::interval(AUTO_LOCK_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                    let Some(runtime) = runtime.upgrade() else {
+                        break;
+                    };
+                    runtime.keystore.vault().expire();
+                    runtime.publish_lock_state();
+                }
+            })
+    }
+
+    /// Publish `KeystoreLockChanged` if the lock state changed
+    fn publish_lock_state(&self) {
+        let locked = !self.keystore.vault().is_unlocked();
+        let mut published = self.keystore_locked.lock().unwrap();
+        if *published != locked {
+            *published = locked;
+            self.events.publish(Event::KeystoreLockChanged { locked });
+        }
+    }
+
+    /// Local device ID
+    pub fn device_id(&self) -> &DeviceId {
+        self.keystore.device_id()
+    }
+
+    /// Artifact store
+    pub fn artifacts(&self) -> &Arc<dyn ArtifactStore> {
+        &self.artifacts
+    }
+
+    /// Changes committed to the artifact store from now on
+    pub fn watch_artifacts(&self) -> std::sync::mpsc::Receiver<StoreChange> {
+        self.watched.watch()
+    }
+
+    /// Content store
+    pub fn content(&self) -> &Arc<dyn ContentStore> {
+        &self.content
+    }
+
+    /// Space saved by storing shared chunks once
+    pub fn dedup_stats(&self) -> DedupStats {
+        self.dedup.stats()
+    }
+
+    /// Collection hierarchy
+    pub fn collections(&self) -> &Mutex<CollectionStore> {
+        &self.collections
+    }
+
+    /// Derived assets (thumbnails, excerpts)
+    pub fn derived(&self) -> &Arc<DerivedAssets> {
+        &self.derived
+    }
+
+    /// Trust store of paired devices
+    pub fn trust(&self) -> &Arc<RwLock<TrustStore>> {
+        &self.trust
+    }
+
+    /// Event stream
+    pub fn events(&self) -> &EventStream {
+        &self.events
+    }
+
+    /// Event stream for the app, with bursts coalesced into batches
+    pub fn ui_events(&self) -> &EventStream {
+        &self.ui_events
+    }
+
+    /// Connection manager
+    pub fn connections(&self) -> &ConnectionManager {
+        &self.connections
+    }
+
+    /// Pairing codes shown and tried by this device
+    pub(crate) fn code_pairing(&self) -> &Mutex<CodePairing> {
+        &self.code_pairing
+    }
+
+    /// Device group this device belongs to
+    pub(crate) fn group_roster(&self) -> &Mutex<GroupRoster> {
+        &self.group
+    }
+
+    /// Frame sequence counters of peers that negotiated `SEQUENCED_FRAMES`
+    pub(crate) fn replay_counters(&self) -> &ReplayStore {
+        &self.replay
+    }
+
+    /// Ratchet sessions of peers that negotiated `SIGNED_FRAMES`
+    pub(crate) fn ratchet_sessions(&self) -> &RatchetStore {
+        &self.ratchets
+    }
+
+    /// Guard to bind the QUIC listener with (`QuicTransport::bind_guarded`)
+    pub fn connection_guard(&self) -> &Arc<ConnectionGuard> {
+        &self.guard
+    }
+
+    /// Bind the guarded QUIC and WebSocket listener
+    ///
+    /// Tries `listen_port` and the following ports of the configured range
+    /// while they are busy, then any free port. With `port_mapping`, the
+    /// router is asked to forward the bound port until shutdown.
+    pub fn bind_listener(&self) -> Result<FallbackTransport> {
+        let network = &self.context.config().network;
+        let keypair = self.keystore.keypair();
+        let transport = bind_first_free(
+            network.bind_address,
+            network.listen_port,
+            network.listen_port_range,
+            |addr| {
+                if addr.port() == network.discovery_port {
+                    return Err(ProtocolError::Io(std::io::ErrorKind::AddrInUse.into()));
+                }
+                FallbackTransport::bind_guarded(addr, keypair, self.guard.clone(), network.timeouts)
+            },
+        )?;
+        let port = transport.socket_addr()?.port();
+        tracing::info!("Listening on port {}", port);
+        *self.listen_port.lock().unwrap() = Some(port);
+        if network.port_mapping {
+            let mapper = self.port_mapper.clone();
+            self.supervisor.spawn("port-mapper", move |cancel| {
+                mapper.run(port, PortMapConfig::default(), cancel)
+            })?;
+        }
+        Ok(transport)
+    }
+
+    /// Bind the listener and link to the peers that connect to it
+    ///
+    /// Returns the bound port; `dial_peer` dials from the same endpoint.
+    /// Calling it again returns the port already bound.
+    pub fn listen(self: &Arc<Self>) -> Result<u16> {
+        let mut listener = self.listener.lock().unwrap();
+        if let Some(transport) = listener.as_ref() {
+            return Ok(transport.socket_addr()?.port());
+        }
+        let transport = Arc::new(self.bind_listener()?);
+        self.serve(transport.clone())?;
+        let port = transport.socket_addr()?.port();
+        *listener = Some(transport);
+        Ok(port)
+    }
+
+    /// Dial a paired device at `addr` from the listener and link to it
+    pub async fn dial_peer(self: &Arc<Self>, device_id: &DeviceId, addr: &str) -> Result<()> {
+        let transport = self.bound_listener()?;
+        self.connect_peer(transport.as_ref(), device_id, addr).await
+    }
+
+    /// Pair from the listener with the device at `addr` showing `code`
+    pub async fn dial_with_code(
+        &self,
+        addr: &str,
+        code: &str,
+        device_name: &str,
+    ) -> Result<DeviceId> {
+        let transport = self.bound_listener()?;
+        self.pair_with_code(transport.as_ref(), addr, code, device_name)
+            .await
+    }
+
+    /// Listener bound by `listen`
+    fn bound_listener(&self) -> Result<Arc<FallbackTransport>> {
+        let transport = self.listener.lock().unwrap().clone();
+        Ok(transport.ok_or_else(|| ProtocolError::Transport("Not listening".into()))?)
+    }
+
+    /// Endpoint the router forwards to the listener, if mapped
+    pub fn external_endpoint(&self) -> Option<Endpoint> {
+        self.port_mapper.external()
+    }
+
+    /// Sync engine
+    pub fn sync(&self) -> &Arc<SyncEngine> {
+        &self.sync
+    }
+
+    /// Operations queued for peers that are currently unreachable
+    pub fn outbox(&self) -> &Arc<Outbox> {
+        &self.outbox
+    }
+
+    /// Advisory edit intents of this device and its peers
+    pub fn edit_intents(&self) -> &Arc<EditIntents> {
+        &self.edit_intents
+    }
+
+    /// Record the network state the platform reported
+    ///
+    /// Offline closes all connections and pauses transfers, as does a
+    /// metered network unless `sync.allow_metered`; a metered network
+    /// that is allowed gets one download source at a time.
+    pub fn set_network_state(&self, state: NetworkState) {
+        self.network.report(state);
+    }
+
+    /// Current network state, from platform reports and probes
+    pub fn network_state(&self) -> NetworkState {
+        self.network.state()
+    }
+
+    /// Probe the network interfaces periodically in the background
+    ///
+    /// Catches connectivity lost while platform notifications were missed.
+    pub fn spawn_network_probe(&self) -> Result<()> {
+        let network = self.network.clone();
+        self.supervisor.spawn("network-probe", move |cancel| {
+            network.run(NETWORK_PROBE_INTERVAL, cancel)
+        })
+    }
+
+    /// Make a connected peer available for sync
+    pub fn register_sync_peer(&self, device_id: DeviceId, peer: Arc<dyn SyncPeer>) {
+        self.sync_peers.lock().unwrap().insert(device_id, peer);
+        self.peer_registered.notify_waiters();
+    }
+
+    /// Record battery and network state a connected peer reported
+    ///
+    /// Low-battery and metered peers are the last choice as download sources.
+    pub fn set_peer_hints(&self, device_id: &DeviceId, hints: PeerHints) {
+        self.sync.scores().set_hints(&device_id.to_string(), hints);
+    }
+
+    /// Remove a peer's sync endpoint, e.g. after it disconnects
+    pub fn unregister_sync_peer(&self, device_id: &DeviceId) {
+        self.sync_peers.lock().unwrap().remove(device_id);
+    }
+
+    /// Remove a peer's sync endpoint unless another one replaced `peer`
+    pub(crate) fn unregister_sync_peer_if(&self, device_id: &DeviceId, peer: &Arc<dyn SyncPeer>) {
+        let mut peers = self.sync_peers.lock().unwrap();
+        if peers
+            .get(device_id)
+            .is_some_and(|registered| Arc::ptr_eq(registered, peer))
+        {
+            peers.remove(device_id);
+        }
+    }
+
+    /// Round-trip time and loss on a linked peer's connection, once its
+    /// keepalive has reported
+    pub fn connection_quality(&self, device_id: &DeviceId) -> Option<ConnectionQuality> {
+        self.connections.stats(device_id).map(Into::into)
+    }
+
+    /// Connected peers available for sync
+    pub fn linked_peers(&self) -> Vec<DeviceId> {
+        self.sync_peers.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Start pulling changes from a connected peer
+    pub fn start_sync(&self, device_id: &DeviceId) -> Result<SyncHandle> {
+        let peer = self.sync_peer(device_id)?;
+        Ok(self.sync.start_sync(device_id.to_string(), peer)?)
+    }
+
+    /// What syncing with a connected peer would transfer, without syncing
+    pub async fn plan_sync(&self, device_id: &DeviceId) -> Result<SyncPreview> {
+        let peer = self.sync_peer(device_id)?;
+        Ok(self.sync.preview(&device_id.0, peer.as_ref()).await?)
+    }
+
+    fn sync_peer(&self, device_id: &DeviceId) -> Result<Arc<dyn SyncPeer>> {
+        self.sync_peers
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .cloned()
+            .ok_or_else(|| CoreError::PeerNotConnected(device_id.to_string()))
+    }
+
+    /// Sync with every connected peer within one shared budget
+    ///
+    /// For platform background tasks: peers are synced one after another,
+    /// each with what the earlier ones left of the budget. Peers not
+    /// reached before the time ran out are not listed.
+    pub async fn background_sync(&self, budget: SyncBudget) -> Result<Vec<BudgetReport>> {
+        let deadline = budget
+            .time_ms
+            .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+        let mut peers: Vec<(DeviceId, Arc<dyn SyncPeer>)> = self
+            .sync_peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, peer)| (id.clone(), peer.clone()))
+            .collect();
+        peers.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+
+        let mut bytes = budget.bytes;
+        let mut reports = Vec::with_capacity(peers.len());
+        for (device_id, peer) in peers {
+            let time_ms = match deadline {
+                Some(deadline) => {
+                    match deadline.checked_duration_since(tokio::time::Instant::now()) {
+                        Some(left) if !left.is_zero() => Some(left.as_millis() as u64),
+                        _ => break,
+                    }
+                }
+                None => None,
+            };
+            let budget = SyncBudget { time_ms, bytes };
+            let report = self
+                .sync
+                .sync_within(device_id.to_string(), peer.as_ref(), budget)
+                .await?;
+            bytes = bytes.map(|b| b.saturating_sub(report.bytes_transferred));
+            let stop = matches!(
+                report.outcome,
+                BudgetOutcome::OutOfTime | BudgetOutcome::Cancelled
+            );
+            reports.push(report);
+            if stop {
+                break;
+            }
+        }
+        Ok(reports)
+    }
+
+    /// Signed pairing offer URL for another device to scan or paste
+    pub fn pairing_offer(&self, device_name: &str) -> Result<String> {
+        Ok(encode_pairing_offer(&self.signed_offer(device_name)?)?)
+    }
+
+    /// Single-use offer of this device, signed and listing its endpoints
+    pub(crate) fn signed_offer(&self, device_name: &str) -> Result<PairingOffer> {
+        let keypair = self.keystore.keypair();
+        let port = self
+            .listen_port
+            .lock()
+            .unwrap()
+            .unwrap_or(self.context.config().network.listen_port);
+        let mut endpoints = interface_endpoints(port, false).unwrap_or_else(|e| {
+            tracing::warn!("Offering no endpoints: {}", e);
+            vec![]
+        });
+        endpoints.extend(self.external_endpoint());
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            device_name.to_string(),
+            keypair.public_key_bytes(),
+            endpoints,
+        )
+        .with_single_use(true);
+        offer.sign(keypair)?;
+        Ok(offer)
+    }
+
+    /// Trust the device that made a pairing offer
+    pub fn accept_pairing_offer(&self, url: &str) -> Result<DeviceId> {
+        self.trust_offer(decode_pairing_offer(url)?)
+    }
+
+    /// Check a received offer and trust the device that made it
+    pub(crate) fn trust_offer(&self, offer: PairingOffer) -> Result<DeviceId> {
+        self.offers.lock().unwrap().accept(&offer)?;
+        self.trust.write().unwrap().add_trusted(
+            offer.device_id.clone(),
+            offer.device_name,
+            offer.public_key,
+        )?;
+        self.sync
+            .set_permissions(&offer.device_id.to_string(), Permissions::full());
+        self.share_keys()?;
+        Ok(offer.device_id)
+    }
+
+    /// Revoke a paired device, propagating the signed record to peers
+    ///
+    /// The device is disconnected and loses its share of data keys; the
+    /// keys it may know are rotated in the background.
+    pub fn revoke_device(&self, device_id: &DeviceId, reason: &str) -> Result<RevocationRecord> {
+        self.authorize(SensitiveOp::RevokeDevice)?;
+        let record = self.trust.write().unwrap().revoke(
+            self.keystore.keypair(),
+            device_id.clone(),
+            reason.to_string(),
+        )?;
+        self.unregister_sync_peer(device_id);
+        // Already revoked locally; the record is returned for the app to resend
+        if let Err(e) = self.connections.handle_revocation(&record, None) {
+            tracing::warn!("Failed to propagate revocation of {}: {}", device_id, e);
+        }
+        Ok(record)
+    }
+
+    /// Enroll this device or a paired one under the user of `passphrase`
+    ///
+    /// This device trusts the user from then on. The certificate is
+    /// returned for the enrolled device to present to other peers.
+    pub fn enroll_device(
+        &self,
+        passphrase: &str,
+        device_id: &DeviceId,
+        device_name: &str,
+    ) -> Result<EnrollmentCertificate> {
+        let user = UserIdentity::from_passphrase(passphrase)?;
+        let public_key = if device_id == self.device_id() {
+            self.keystore.keypair().public_key_bytes()
+        } else {
+            self.trust
+                .read()
+                .unwrap()
+                .get(device_id)
+                .map(|device| device.public_key.clone())
+                .ok_or_else(|| CryptoError::UntrustedDevice(device_id.clone()))?
+        };
+        let certificate = user.enroll(device_id.clone(), device_name.to_string(), public_key)?;
+        let mut trust = self.trust.write().unwrap();
+        if trust.user(user.user_id()).is_none() {
+            trust.trust_user(
+                user.user_id().clone(),
+                String::new(),
+                user.public_key_bytes(),
+            )?;
+        }
+        if device_id == self.device_id() {
+            trust.set_own_user(user.user_id())?;
+        } else {
+            trust.admit_enrolled(&certificate)?;
+        }
+        Ok(certificate)
+    }
+
+    /// Withdraw a device from the user of `passphrase`
+    ///
+    /// Applied locally; the signed revocation is returned for the app to
+    /// send to the user's other devices.
+    pub fn revoke_enrolled(
+        &self,
+        passphrase: &str,
+        device_id: &DeviceId,
+        reason: &str,
+    ) -> Result<UserRevocation> {
+        self.authorize(SensitiveOp::RevokeDevice)?;
+        let user = UserIdentity::from_passphrase(passphrase)?;
+        let revocation = user.revoke(device_id.clone(), reason.to_string());
+        let mut trust = self.trust.write().unwrap();
+        if trust.user(user.user_id()).is_none() {
+            trust.trust_user(
+                user.user_id().clone(),
+                String::new(),
+                user.public_key_bytes(),
+            )?;
+        }
+        trust.apply_user_revocation(&revocation)?;
+        drop(trust);
+        self.unregister_sync_peer(device_id);
+        Ok(revocation)
+    }
+
+    /// Trust the user who enrolled a device confirmed in person
+    ///
+    /// The device itself is admitted, and so is every other device of
+    /// the user once it presents its certificate.
+    pub fn trust_user(&self, certificate: &EnrollmentCertificate, name: &str) -> Result<bool> {
+        certificate.verify()?;
+        self.trust.write().unwrap().trust_user(
+            certificate.user_id.clone(),
+            name.to_string(),
+            certificate.user_public_key.clone(),
+        )?;
+        self.admit_enrolled(certificate)
+    }
+
+    /// Admit a device enrolled by a trusted user
+    ///
+    /// Returns `false` if the device was already trusted.
+    pub fn admit_enrolled(&self, certificate: &EnrollmentCertificate) -> Result<bool> {
+        if !self.trust.write().unwrap().admit_enrolled(certificate)? {
+            return Ok(false);
+        }
+        self.sync
+            .set_permissions(&certificate.device_id.to_string(), Permissions::full());
+        self.share_keys()?;
+        Ok(true)
+    }
+
+    /// Apply a user's withdrawal of one of their devices
+    pub fn apply_user_revocation(&self, revocation: &UserRevocation) -> Result<bool> {
+        let applied = self
+            .trust
+            .write()
+            .unwrap()
+            .apply_user_revocation(revocation)?;
+        self.unregister_sync_peer(&revocation.device_id);
+        Ok(applied)
+    }
+
+    /// Ask another device of this device's user to wipe itself
+    ///
+    /// Sent right away if the device is connected; the signed command is
+    /// returned for the app to deliver otherwise.
+    pub fn wipe_device(&self, device_id: &DeviceId) -> Result<WipeCommand> {
+        self.trust.read().unwrap().check_handshake(device_id)?;
+        let command = WipeCommand::new(self.keystore.keypair(), device_id.clone())?;
+        if let Err(e) = self.connections.send_wipe(&command) {
+            tracing::info!("Wipe command for {} not sent: {}", device_id, e);
+        }
+        Ok(command)
+    }
+
+    /// Check a wipe command received from another device
+    ///
+    /// On success the caller wipes this device with `crate::wipe`.
+    pub fn check_wipe(&self, command: &WipeCommand) -> Result<()> {
+        let now = self.connections.peer_time(&command.issued_by);
+        self.trust
+            .read()
+            .unwrap()
+            .check_wipe(command, self.device_id(), now)?;
+        tracing::warn!("Accepted wipe command from {}", command.issued_by);
+        Ok(())
+    }
+
+    /// Sign and send this device's identity statement to connected peers
+    pub fn publish_attestation(
+        &self,
+        device_name: &str,
+        user: &str,
+        ttl: Duration,
+    ) -> Result<Attestation> {
+        let attestation = Attestation::new(
+            self.keystore.keypair(),
+            device_name.to_string(),
+            user.to_string(),
+            ttl,
+        )?;
+        if let Err(e) = self.connections.handle_attestation(&attestation, None) {
+            tracing::warn!("Failed to send attestation: {}", e);
+        }
+        Ok(attestation)
+    }
+
+    /// Vouch for a paired device's statement after checking it in person
+    pub fn countersign_attestation(&self, device_id: &DeviceId) -> Result<Attestation> {
+        let mut attestation = self
+            .trust
+            .read()
+            .unwrap()
+            .get(device_id)
+            .and_then(|device| device.attestation.clone())
+            .ok_or_else(|| SyncError::NotFound(format!("Attestation of {}", device_id)))?;
+        attestation.countersign(self.keystore.keypair())?;
+        self.trust
+            .write()
+            .unwrap()
+            .apply_attestation(&attestation)?;
+        if let Err(e) = self.connections.handle_attestation(&attestation, None) {
+            tracing::warn!("Failed to send attestation of {}: {}", device_id, e);
+        }
+        Ok(attestation)
+    }
+
+    /// Statements of paired devices with who vouched for them
+    pub fn attestations(&self) -> Vec<AttestationInfo> {
+        let trust = self.trust.read().unwrap();
+        let chain =
+            AttestationChain::new(self.device_id().clone()).with_all(trust.attestations().cloned());
+        trust
+            .attestations()
+            .map(|attestation| AttestationInfo {
+                vouch_path: chain.path(&attestation.device_id),
+                attestation: attestation.clone(),
+            })
+            .collect()
+    }
+
+    /// Progress of rotating data keys after revocations
+    pub fn reencryption_progress(&self) -> ReencryptProgress {
+        self.reencryption.progress()
+    }
+
+    /// Reseal data keys to this device and the trusted ones
+    ///
+    /// Returns the number of keys resealed; zero unless the content store
+    /// encrypts at rest.
+    pub fn share_keys(&self) -> Result<usize> {
+        share_keys(
+            self.keystore.keypair(),
+            &self.trust,
+            self.content.as_ref(),
+            self.artifacts.as_ref(),
+        )
+    }
+
+    /// Signed wake token for the push service reaching a paired device
+    pub fn wake_token(&self, device_id: &DeviceId) -> Result<String> {
+        self.trust.read().unwrap().check_handshake(device_id)?;
+        let token = WakeToken::issue(self.keystore.keypair(), device_id.clone())?;
+        Ok(token.encode()?)
+    }
+
+    /// Sync with the paired device that sent a push wake token
+    ///
+    /// The app reconnects to peers after waking; this waits for the sender
+    /// to connect, then syncs with it. Both share `budget`, capped at
+    /// `MAX_WAKE_BUDGET`, so the platform can end its background task once
+    /// this returns.
+    pub async fn handle_push(&self, payload: &str, budget: Duration) -> Result<WakeReport> {
+        let token = WakeToken::decode(payload)?;
+        let public_key = {
+            let trust = self.trust.read().unwrap();
+            trust.check_handshake(&token.from)?;
+            trust.get(&token.from).expect("trusted").public_key.clone()
+        };
+        // Judge the token's age on the sender's clock
+        let now = self.connections.peer_time(&token.from);
+        self.wakes
+            .lock()
+            .unwrap()
+            .accept_at(&token, &public_key, now)?;
+        let deadline = tokio::time::Instant::now() + budget.min(MAX_WAKE_BUDGET);
+        let report = |outcome, progress: Option<SyncProgress>| WakeReport {
+            peer_id: token.from.to_string(),
+            outcome,
+            artifacts_synced: progress.as_ref().map_or(0, |p| p.artifacts_synced),
+            error: progress.and_then(|p| p.error),
+        };
+
+        let handle = loop {
+            // Created before the check so a registration in between still wakes it
+            let registered = self.peer_registered.notified();
+            match self.start_sync(&token.from) {
+                Ok(handle) => break handle,
+                Err(CoreError::PeerNotConnected(_)) => {}
+                Err(e) => return Err(e),
+            }
+            if tokio::time::timeout_at(deadline, registered).await.is_err() {
+                return Ok(report(WakeOutcome::PeerUnavailable, None));
+            }
+        };
+        let id = handle.id;
+        let progress = handle.subscribe();
+        let Ok(mut done) = tokio::time::timeout_at(deadline, handle.wait()).await else {
+            self.sync.cancel_sync(id);
+            let progress = progress.borrow().clone();
+            return Ok(report(WakeOutcome::TimedOut, Some(progress)));
+        };
+        let outcome = match done.state {
+            SyncState::Completed => WakeOutcome::Completed,
+            SyncState::Cancelled => {
+                done.error.get_or_insert_with(|| "Cancelled".into());
+                WakeOutcome::Failed
+            }
+            _ => WakeOutcome::Failed,
+        };
+        Ok(report(outcome, Some(done)))
+    }
+
+    /// Send a text snippet to a connected paired device
+    ///
+    /// The snippet rides the peer's live event channel and is not stored
+    /// on either side unless kept with `save_snippet`.
+    pub fn send_snippet(&self, device_id: &DeviceId, text: &str) -> Result<Snippet> {
+        if text.len() > MAX_SNIPPET_LEN {
+            return Err(CoreError::SnippetTooLarge(text.len()));
+        }
+        self.trust.read().unwrap().check_handshake(device_id)?;
+        if !self.connections.is_connected(device_id) {
+            return Err(CoreError::PeerNotConnected(device_id.to_string()));
+        }
+        let id = random_id();
+        let sent_at = unix_time();
+        let snippet = Snippet {
+            id,
+            text: text.to_string(),
+            sent_at,
+        };
+        self.events.publish(Event::SnippetSent {
+            to: device_id.to_string(),
+            snippet: snippet.clone(),
+        });
+        Ok(snippet)
+    }
+
+    /// Keep a snippet as a text artifact, which then syncs as usual
+    pub fn save_snippet(&self, snippet: &Snippet) -> Result<Artifact> {
+        let hash = content_hash(snippet.text.as_bytes());
+        self.content.put_content(&hash, snippet.text.as_bytes())?;
+        let title = snippet.text.lines().next().unwrap_or_default();
+        let artifact = Artifact {
+            id: format!("snippet-{}", snippet.id),
+            title: title.chars().take(SNIPPET_TITLE_LEN).collect(),
+            created_at: snippet.sent_at,
+            modified_at: snippet.sent_at,
+            content_hash: hash,
+            content_type: Some("text/plain".into()),
+            artifact_type: Some("snippet".into()),
+            ..Default::default()
+        };
+        self.artifacts.store(&artifact)?;
+        Ok(artifact)
+    }
+
+    /// Share tokens issued by this device, for `serve_shares`
+    pub fn shares(&self) -> &Arc<RwLock<ShareRegistry>> {
+        &self.shares
+    }
+
+    /// Issue a token letting a guest fetch one artifact for `ttl`
+    pub fn share_artifact(&self, artifact_id: &str, ttl: Duration) -> Result<ShareToken> {
+        if self.artifacts.get(artifact_id)?.is_none() {
+            return Err(SyncError::NotFound(artifact_id.to_string()).into());
+        }
+        Ok(self.shares.write().unwrap().issue(
+            self.keystore.keypair(),
+            artifact_id.to_string(),
+            ttl,
+        )?)
+    }
+
+    /// Revoke a share token, returning `false` if it was unknown or revoked
+    pub fn revoke_share(&self, token_id: &str) -> Result<bool> {
+        Ok(self.shares.write().unwrap().revoke(token_id)?)
+    }
+
+    /// Local sync view to serve to a peer, limited by its permissions
+    pub fn sync_view(&self, device_id: &DeviceId) -> Arc<dyn SyncPeer> {
+        self.sync.scoped(&device_id.to_string())
+    }
+
+    /// Permissions granted to a paired device
+    pub fn device_permissions(&self, device_id: &DeviceId) -> Result<Permissions> {
+        self.trust
+            .read()
+            .unwrap()
+            .permissions(device_id)
+            .cloned()
+            .ok_or_else(|| nomade_crypto::CryptoError::UntrustedDevice(device_id.clone()).into())
+    }
+
+    /// Persist new permissions for a paired device and enforce them
+    pub fn set_device_permissions(
+        &self,
+        device_id: &DeviceId,
+        permissions: Permissions,
+    ) -> Result<()> {
+        self.trust
+            .write()
+            .unwrap()
+            .set_permissions(device_id, permissions.clone())?;
+        self.sync
+            .set_permissions(&device_id.to_string(), permissions);
+        Ok(())
+    }
+
+    /// Selective sync rules for a peer
+    pub fn sync_rules(&self, device_id: &DeviceId) -> SyncRules {
+        self.sync.rules(&device_id.to_string())
+    }
+
+    /// Persist new selective sync rules for a peer and apply them
+    ///
+    /// If the change includes artifacts that were previously excluded and
+    /// the peer is connected, a sync is started to pull them.
+    pub fn set_sync_rules(&self, device_id: &DeviceId, rules: SyncRules) -> Result<RuleEvaluation> {
+        self.trust.write().unwrap().set_peer_data(
+            device_id,
+            SYNC_RULES_KEY,
+            serde_json::to_value(&rules)?,
+        )?;
+        let evaluation = self.sync.set_rules(&device_id.to_string(), rules)?;
+
+        let connected = self.sync_peers.lock().unwrap().contains_key(device_id);
+        if connected && !evaluation.newly_included.is_empty() {
+            self.start_sync(device_id)?;
+        }
+        Ok(evaluation)
+    }
+
+    /// Sync conflicts waiting for the user, sorted by artifact ID
+    pub fn conflicts(&self) -> Vec<ConflictRecord> {
+        self.sync.conflicts().list()
+    }
+
+    /// Settle a sync conflict; paired devices converge on the choice
+    pub fn resolve_conflict(&self, artifact_id: &str, resolution: Resolution) -> Result<Artifact> {
+        Ok(self.sync.resolve_conflict(artifact_id, resolution)?)
+    }
+
+    /// Supervisor for background tasks
+    pub fn supervisor(&self) -> &Supervisor {
+        &self.supervisor
+    }
+
+    /// Export artifacts as an encrypted bundle
+    ///
+    /// Like the other bulk exports and imports, needs the keystore
+    /// unlocked once a passphrase is set.
+    pub fn export_bundle(&self, ids: &[String], seal: &BundleSeal) -> Result<Vec<u8>> {
+        self.keystore.vault().check_unlocked()?;
+        Ok(export_bundle(
+            self.artifacts.as_ref(),
+            self.content.as_ref(),
+            ids,
+            seal,
+        )?)
+    }
+
+    /// Import a bundle, opened with `password` or else this device's key
+    pub fn import_bundle(&self, bundle: &[u8], password: Option<&str>) -> Result<ImportReport> {
+        self.keystore.vault().check_unlocked()?;
+        let key = match password {
+            Some(password) => BundleKey::Password(password),
+            None => BundleKey::Device(self.keystore.keypair()),
+        };
+        Ok(import_bundle(
+            bundle,
+            key,
+            self.artifacts.as_ref(),
+            self.content.as_ref(),
+        )?)
+    }
+
+    /// Write artifacts `ids` (all if empty) as plain files into `dir`
+    ///
+    /// `progress` is called after each artifact.
+    pub fn export_dir(
+        &self,
+        ids: &[String],
+        dir: &Path,
+        options: &ExportOptions,
+        progress: impl FnMut(&ExportProgress),
+    ) -> Result<ExportProgress> {
+        self.keystore.vault().check_unlocked()?;
+        let collections = self.collections.lock().unwrap();
+        Ok(export_dir(
+            self.artifacts.as_ref(),
+            self.content.as_ref(),
+            &collections,
+            ids,
+            dir,
+            options,
+            progress,
+        )?)
+    }
+
+    /// Import a directory written by `export_dir`, keeping artifact IDs
+    pub fn import_dir(&self, dir: &Path) -> Result<ImportReport> {
+        let mut collections = self.collections.lock().unwrap();
+        Ok(import_dir(
+            dir,
+            self.artifacts.as_ref(),
+            self.content.as_ref(),
+            &mut collections,
+        )?)
+    }
+
+    /// Snapshot loaded at start, for rendering before stores are queried
+    pub fn warm_snapshot(&self) -> Option<&StateSnapshot> {
+        self.warm_snapshot.as_ref()
+    }
+
+    /// Capture the current aggregate state
+    pub fn capture_snapshot(&self) -> Result<StateSnapshot> {
+        let trusted_devices: Vec<_> = self.trust.read().unwrap().list().cloned().collect();
+        let sync_rules: BTreeMap<_, _> = trusted_devices
+            .iter()
+            .map(|device| {
+                let peer_id = device.device_id.to_string();
+                let rules = self.sync.rules(&peer_id);
+                (peer_id, rules)
+            })
+            .collect();
+        Ok(StateSnapshot::new(
+            self.device_id().0.clone(),
+            self.artifacts.list()?,
+            self.collections.lock().unwrap().list(),
+            trusted_devices,
+            sync_rules,
+        ))
+    }
+
+    /// Write a warm-start snapshot; `false` if storage is volatile
+    pub fn save_snapshot(&self) -> Result<bool> {
+        let Some(path) = &self.snapshot_path else {
+            return Ok(false);
+        };
+        let snapshot = self.capture_snapshot()?;
+        snapshot::write(path, &mut self.snapshot_key.lock().unwrap(), &snapshot)?;
+        Ok(true)
+    }
+
+    /// Refresh the warm-start snapshot periodically in the background
+    pub fn spawn_snapshotter(self: &Arc<Self>) -> Result<()> {
+        if self.snapshot_path.is_none() {
+            return Ok(());
+        }
+        let runtime: Weak<Self> = Arc::downgrade(self);
+        self.supervisor
+            .spawn("snapshotter", move |cancel| async move {
+                let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+                interval.tick().await;
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                    let Some(runtime) = runtime.upgrade() else {
+                        break;
+                    };
+                    if let Err(e) = runtime.save_snapshot() {
+                        tracing::warn!("Failed to write snapshot: {}", e);
+                    }
+                }
+            })
+    }
+
+    /// Verify stored content, quarantining damaged blobs
+    ///
+    /// Affected artifacts are reported as `ArtifactCorrupted` and fetched
+    /// again from the next peer that syncs.
+    pub fn scrub(&self) -> Result<ScrubReport> {
+        let mut report = scrub(
+            self.artifacts.as_ref(),
+            self.content.as_ref(),
+            self.quarantine_dir.as_deref(),
+        )?;
+        {
+            // Evicted content is missing on purpose, not lost
+            let mut cache = self.cache.lock().unwrap();
+            cache.refresh(self.content.as_ref())?;
+            report
+                .corrupt
+                .retain(|c| c.quarantined || !cache.is_evicted(&c.content_hash));
+        }
+        let damaged = report.damaged_artifacts();
+        for id in &damaged {
+            tracing::warn!("Content of artifact {} is corrupt", id);
+            self.events.publish(Event::ArtifactCorrupted {
+                id: id.clone(),
+                status: RepairStatus::Quarantined,
+            });
+        }
+        self.sync.request_repair(damaged);
+        if let Err(e) = self.maintenance.scrubbed(unix_time()) {
+            tracing::warn!("Failed to record scrub time: {}", e);
+        }
+        Ok(report)
+    }
+
+    /// Keep an artifact's content on this device
+    ///
+    /// Evicted content is fetched again by the next sync.
+    pub fn pin_artifact(&self, artifact_id: &str) -> Result<()> {
+        let artifact = self
+            .artifacts
+            .get(artifact_id)?
+            .ok_or_else(|| SyncError::NotFound(artifact_id.to_string()))?;
+        self.cache.lock().unwrap().pin(artifact_id)?;
+        if !self.content.has_content(&artifact.content_hash)? {
+            self.sync.request_repair([artifact_id.to_string()]);
+        }
+        Ok(())
+    }
+
+    /// Let an artifact's content be evicted when over quota
+    pub fn unpin_artifact(&self, artifact_id: &str) -> Result<bool> {
+        Ok(self.cache.lock().unwrap().unpin(artifact_id)?)
+    }
+
+    /// Pinned artifact IDs, sorted
+    pub fn pinned_artifacts(&self) -> Vec<String> {
+        self.cache.lock().unwrap().pinned()
+    }
+
+    /// Evict unpinned content until the configured quota is met
+    ///
+    /// Runs on its own as artifacts change when a quota is configured.
+    pub fn enforce_quota(&self) -> Result<EvictionReport> {
+        let Some(quota) = self.context.config().content_quota_bytes else {
+            return Ok(EvictionReport::default());
+        };
+        Ok(self
+            .cache
+            .lock()
+            .unwrap()
+            .evict(self.artifacts.as_ref(), &self.dedup, quota)?)
+    }
+
+    /// Content of an artifact, fetched from a connected peer if not local
+    ///
+    /// Chunks are verified against the content hash tree as they arrive.
+    /// Concurrent calls for the same artifact share one fetch.
+    pub async fn retrieve_or_fetch(&self, artifact_id: &str) -> Result<Vec<u8>> {
+        if let Some(data) = self.local_content(artifact_id)? {
+            return Ok(data);
+        }
+        let in_flight = self
+            .hydrating
+            .lock()
+            .unwrap()
+            .entry(artifact_id.to_string())
+            .or_default()
+            .clone();
+        let result = async {
+            let _fetching = in_flight.lock().await;
+            // Whoever held the lock may have fetched it already
+            if let Some(data) = self.local_content(artifact_id)? {
+                return Ok(data);
+            }
+            self.fetch_from_peers(artifact_id).await
+        }
+        .await;
+        let mut hydrating = self.hydrating.lock().unwrap();
+        // Last caller out removes the entry; counts only change under the lock
+        if Arc::strong_count(&in_flight) == 2 {
+            hydrating.remove(artifact_id);
+        }
+        drop(in_flight);
+        result
+    }
+
+    fn local_content(&self, artifact_id: &str) -> Result<Option<Vec<u8>>> {
+        let artifact = self
+            .artifacts
+            .get(artifact_id)?
+            .ok_or_else(|| SyncError::NotFound(artifact_id.to_string()))?;
+        Ok(self.content.get_content(&artifact.content_hash)?)
+    }
+
+    /// Fetch from every connected trusted peer holding the artifact at once
+    async fn fetch_from_peers(&self, artifact_id: &str) -> Result<Vec<u8>> {
+        let sources: Vec<(String, Arc<dyn SyncPeer>)> = {
+            let trust = self.trust.read().unwrap();
+            self.sync_peers
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(id, _)| trust.check_handshake(id).is_ok())
+                .map(|(id, peer)| (id.to_string(), peer.clone()))
+                .collect()
+        };
+        if let Err(e) = self.sync.fetch_content(&sources, artifact_id).await {
+            tracing::debug!("Could not fetch {}: {}", artifact_id, e);
+        }
+        match self.local_content(artifact_id)? {
+            Some(data) => {
+                self.cache.lock().unwrap().refresh(self.content.as_ref())?;
+                Ok(data)
+            }
+            None => Err(CoreError::ContentUnavailable(artifact_id.to_string())),
+        }
+    }
+
+    /// Delete content and derived assets no artifact refers to
+    ///
+    /// Versions the conflict inbox keeps for merging and content of backups
+    /// stay. Only safe while no sync is running: content fetched ahead of its
+    /// artifact would be collected.
+    pub fn collect_garbage(&self) -> Result<GcReport> {
+        let mut keep = self.sync.conflicts().retained_content();
+        keep.extend(self.backups.retained_content());
+        let report = collect_garbage(self.artifacts.as_ref(), &self.dedup, &keep)?;
+        let derived = self.derived.prune(self.artifacts.as_ref())?;
+        tracing::info!(
+            "Collected {} blobs ({} bytes) and {} derived assets",
+            report.removed.len(),
+            report.freed_bytes,
+            derived
+        );
+        if let Err(e) = self.maintenance.collected(unix_time()) {
+            tracing::warn!("Failed to record collection time: {}", e);
+        }
+        Ok(report)
+    }
+
+    /// Storage statistics and consistency checks for a settings screen
+    ///
+    /// Reads every chunk's presence, so it takes a moment on large stores.
+    pub fn store_health(&self) -> Result<StoreHealth> {
+        let mut keep = self.sync.conflicts().retained_content();
+        keep.extend(self.backups.retained_content());
+        let mut health = store_health(self.artifacts.as_ref(), &self.dedup, &keep)?;
+        self.maintenance.report(&mut health);
+        let config = self.context.config();
+        let url = config.artifact_store_url();
+        let kind = |name: &str| BackendHealth {
+            name: name.into(),
+            kind: match config.storage_backend {
+                StorageBackend::Memory => "memory".into(),
+                StorageBackend::Persistent => "file".into(),
+            },
+            disk_bytes: 0,
+        };
+        let file_size = |path: PathBuf| std::fs::metadata(path).map_or(0, |m| m.len());
+        let chunk_index = config.data_dir.join(CHUNK_INDEX_FILE);
+        health.backends = vec![
+            BackendHealth {
+                name: "artifacts".into(),
+                kind: url
+                    .split_once("://")
+                    .map_or(&*url, |(scheme, _)| scheme)
+                    .into(),
+                disk_bytes: self.artifacts.disk_usage()?,
+            },
+            BackendHealth {
+                disk_bytes: file_size(chunk_index.with_extension("journal"))
+                    + file_size(chunk_index),
+                ..kind("chunk_index")
+            },
+        ];
+        if let Some(dir) = &self.quarantine_dir {
+            let disk_bytes = match std::fs::read_dir(dir) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok()?.metadata().ok())
+                    .map(|metadata| metadata.len())
+                    .sum(),
+                Err(_) => 0,
+            };
+            health.backends.push(BackendHealth {
+                disk_bytes,
+                ..kind("quarantine")
+            });
+        }
+        Ok(health)
+    }
+
+    /// Scrub stored content periodically in the background
+    pub fn spawn_scrubber(self: &Arc<Self>) -> Result<()> {
+        let runtime: Weak<Self> = Arc::downgrade(self);
+        self.supervisor.spawn("scrubber", move |cancel| async move {
+            let mut interval = tokio::time::interval(SCRUB_INTERVAL);
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                let Some(runtime) = runtime.upgrade() else {
+                    break;
+                };
+                if let Err(e) = runtime.scrub() {
+                    tracing::warn!("Content scrub failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Purge expired artifacts from the trash periodically in the background
+    pub fn spawn_trash_purger(self: &Arc<Self>) -> Result<()> {
+        let runtime: Weak<Self> = Arc::downgrade(self);
+        self.supervisor
+            .spawn("trash-purger", move |cancel| async move {
+                let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = interval.tick() => {}
+                    }
+                    let Some(runtime) = runtime.upgrade() else {
+                        break;
+                    };
+                    if let Err(e) = runtime.purge_trash() {
+                        tracing::warn!("Trash purge failed: {}", e);
+                    }
+                }
+            })
+    }
+
+    /// Snapshot of all metrics, refreshing storage size first
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        let registry = nomade_metrics::global();
+        match self.artifacts.disk_usage() {
+            Ok(bytes) => registry
+                .gauge(
+                    names::STORAGE_BYTES,
+                    "Size of the artifact store on disk in bytes",
+                )
+                .set(bytes as i64),
+            Err(e) => tracing::warn!("Failed to measure storage size: {}", e),
+        }
+        registry.snapshot()
+    }
+
+    /// Current lifecycle state
+    pub fn state(&self) -> RuntimeState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Stop all subsystems in reverse startup order
+    ///
+    /// Background tasks are cancelled first and the sync engine stopped so
+    /// no new writes arrive. Storage is then flushed, connections closed,
+    /// and pending events drained to subscribers. Calling it again is a
+    /// no-op.
+    pub async fn shutdown(&self) -> Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if *state != RuntimeState::Running {
+                return Ok(());
+            }
+            *state = RuntimeState::Stopping;
+        }
+
+        let aborted = self.supervisor.shutdown(SHUTDOWN_GRACE).await;
+        if aborted > 0 {
+            tracing::warn!("Aborted {} background tasks", aborted);
+        }
+        if let Some(listener) = self.listener.lock().unwrap().take() {
+            listener.close();
+        }
+        self.sync.stop();
+        let flushed = self.artifacts.flush();
+        if flushed.is_ok() {
+            // Peers' cursors stay valid across the restart
+            if let Err(e) = self.sync.change_log().flush() {
+                tracing::warn!("Failed to save change log: {}", e);
+            }
+        }
+        if let Err(e) = self.save_snapshot() {
+            tracing::warn!("Failed to write snapshot: {}", e);
+        }
+        self.sync_peers.lock().unwrap().clear();
+        for peer in self.connections.connected_peers() {
+            self.connections.disconnect(&peer);
+        }
+        if !self.events.drain(EVENT_DRAIN_TIMEOUT).await {
+            tracing::warn!("Shut down with undelivered events");
+        }
+
+        *self.state.lock().unwrap() = RuntimeState::Stopped;
+        flushed?;
+        tracing::info!("Nomade runtime stopped");
+        Ok(())
+    }
+}
+
+/// Random 128-bit hex identifier
+pub(crate) fn random_id() -> String {
+    generate_key()[..16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Process-wide tokio runtime for callers outside of tokio
+pub(crate) fn executor() -> &'static tokio::runtime::Runtime {
+    static EXECUTOR: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    EXECUTOR.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("nomade-runtime")
+            .build()
+            .expect("failed to build tokio runtime")
+    })
+}
+
+static RUNTIME: RwLock<Option<Arc<NomadeRuntime>>> = RwLock::new(None);
+
+/// Install the process-wide runtime handle
+pub(crate) fn install(runtime: NomadeRuntime) -> Result<Arc<NomadeRuntime>> {
+    let mut slot = RUNTIME.write().unwrap();
+    if slot.is_some() {
+        return Err(CoreError::AlreadyInitialized);
+    }
+    let runtime = Arc::new(runtime);
+    *slot = Some(runtime.clone());
+    Ok(runtime)
+}
+
+/// Remove the process-wide runtime handle
+pub(crate) fn uninstall() -> Option<Arc<NomadeRuntime>> {
+    RUNTIME.write().unwrap().take()
+}
+
+/// Get the process-wide runtime installed by `start()`
+pub fn runtime() -> Result<Arc<NomadeRuntime>> {
+    RUNTIME
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or(CoreError::NotInitialized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NomadeConfig;
+    use nomade_quic::{Direction, FeatureFlags};
+    use nomade_storage::backend::parse_url;
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    fn context(dir: &std::path::Path, backend: StorageBackend) -> Context {
+        let mut config = NomadeConfig::new(dir);
+        config.storage_backend = backend;
+        Context::new(config).unwrap()
+    }
+
+    /// Memory-backed runtime for the device `name`, in its own directory
+    fn device(dir: &Path, name: &str) -> Arc<NomadeRuntime> {
+        device_with(dir, name, |_| {})
+    }
+
+    /// `device` with `configure` applied to its configuration first
+    fn device_with(
+        dir: &Path,
+        name: &str,
+        configure: impl FnOnce(&mut NomadeConfig),
+    ) -> Arc<NomadeRuntime> {
+        Arc::new(
+            NomadeRuntime::builder(context_with(&dir.join(name), configure))
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn context_with(dir: &Path, configure: impl FnOnce(&mut NomadeConfig)) -> Context {
+        let mut config = NomadeConfig::new(dir);
+        config.storage_backend = StorageBackend::Memory;
+        configure(&mut config);
+        Context::new(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build_opens_subsystems_under_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Persistent))
+            .build()
+            .unwrap();
+        assert_eq!(runtime.state(), RuntimeState::Running);
+        assert!(dir.path().join(KEYSTORE_FILE).is_file());
+        let url = runtime.context().config().artifact_store_url();
+        let (_, artifacts) = parse_url(&url).unwrap();
+        assert!(Path::new(artifacts).starts_with(dir.path()));
+        assert!(Path::new(artifacts).is_dir());
+        assert!(runtime.warm_snapshot().is_none());
+        let device_id = runtime.device_id().clone();
+        runtime
+            .artifacts()
+            .store(&Artifact {
+                id: "note".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        runtime.shutdown().await.unwrap();
+        drop(runtime);
+
+        // Identity survives restarts and the shutdown snapshot warms the next start
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Persistent))
+            .build()
+            .unwrap();
+        assert_eq!(runtime.device_id(), &device_id);
+        assert_eq!(runtime.warm_snapshot().unwrap().artifacts[0].id, "note");
+        runtime.shutdown().await.unwrap();
+        drop(runtime);
+
+        // A corrupted snapshot is discarded
+        std::fs::write(dir.path().join(SNAPSHOT_FILE), b"garbage").unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Persistent))
+            .build()
+            .unwrap();
+        assert!(runtime.warm_snapshot().is_none());
+
+        let snapshot = runtime.metrics_snapshot();
+        assert!(matches!(
+            snapshot.get(names::STORAGE_BYTES),
+            Some(nomade_metrics::MetricValue::Gauge { value }) if *value > 0
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_peer_settings_persist_in_trust_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer = nomade_crypto::generate_keypair();
+        let build = || {
+            NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+                .build()
+                .unwrap()
+        };
+        let rules = SyncRules {
+            rules: vec![nomade_sync::SyncRule::exclude(
+                nomade_sync::RuleMatcher::Tag("private".into()),
+            )],
+            ..Default::default()
+        };
+
+        let runtime = build();
+        assert!(runtime
+            .set_sync_rules(peer.device_id(), rules.clone())
+            .is_err());
+        runtime
+            .trust()
+            .write()
+            .unwrap()
+            .add_trusted(peer.device_id().clone(), "Peer".into(), vec![])
+            .unwrap();
+        runtime
+            .set_sync_rules(peer.device_id(), rules.clone())
+            .unwrap();
+        assert_eq!(
+            runtime.device_permissions(peer.device_id()).unwrap(),
+            Permissions::full()
+        );
+        runtime
+            .set_device_permissions(peer.device_id(), Permissions::read_only())
+            .unwrap();
+        drop(runtime);
+
+        let runtime = build();
+        assert_eq!(runtime.sync_rules(peer.device_id()), rules);
+        assert_eq!(
+            runtime.device_permissions(peer.device_id()).unwrap(),
+            Permissions::read_only()
+        );
+        assert!(!runtime
+            .sync()
+            .permissions(&peer.device_id().to_string())
+            .can_write());
+    }
+
+    #[tokio::test]
+    async fn test_pairing_offer_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let laptop = device(dir.path(), "laptop");
+        let phone = device(dir.path(), "phone");
+
+        let offer = laptop.pairing_offer("Laptop").unwrap();
+        let paired = phone.accept_pairing_offer(&offer).unwrap();
+        assert_eq!(&paired, laptop.device_id());
+        assert_eq!(
+            phone
+                .trust()
+                .read()
+                .unwrap()
+                .get(&paired)
+                .unwrap()
+                .device_name,
+            "Laptop"
+        );
+        assert_eq!(
+            phone.device_permissions(&paired).unwrap(),
+            Permissions::full()
+        );
+        // Offers are single use
+        assert!(phone.accept_pairing_offer(&offer).is_err());
+        assert!(phone.accept_pairing_offer("nomade://garbage").is_err());
+
+        let record = phone.revoke_device(&paired, "lost").unwrap();
+        record
+            .verify(&phone.keystore().keypair().public_key_bytes())
+            .unwrap();
+        assert!(phone.device_permissions(&paired).is_err());
+        assert!(phone.wake_token(&paired).is_err());
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_listener_skips_busy_ports() {
+        let dir = tempfile::tempdir().unwrap();
+        let taken = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let busy = taken.local_addr().unwrap().port();
+        let context = context_with(dir.path(), |config| {
+            config.network.bind_address = "127.0.0.1".parse().unwrap();
+            config.network.listen_port = busy;
+            config.network.discovery_port = busy + 1;
+        });
+        let runtime = NomadeRuntime::builder(context).build().unwrap();
+
+        let listener = runtime.bind_listener().unwrap();
+        let port = listener.socket_addr().unwrap().port();
+        assert!(port != busy && port != busy + 1);
+        let offer = decode_pairing_offer(&runtime.pairing_offer("Laptop").unwrap()).unwrap();
+        assert!(offer
+            .endpoints
+            .iter()
+            .all(|endpoint| endpoint.socket_addr().unwrap().port() == port));
+        assert_eq!(runtime.external_endpoint(), None);
+
+        listener.close();
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dialed_peers_sync_both_ways() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = |name: &str| {
+            device_with(dir.path(), name, |config| {
+                config.network.bind_address = "127.0.0.1".parse().unwrap();
+                config.network.listen_port = 0;
+            })
+        };
+        let (laptop, phone) = (build("laptop"), build("phone"));
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        let port = laptop.listen().unwrap();
+        assert_eq!(laptop.listen().unwrap(), port);
+        phone.listen().unwrap();
+        assert!(phone
+            .dial_peer(phone.device_id(), &format!("127.0.0.1:{}", port))
+            .await
+            .is_err());
+
+        let content = b"dialed";
+        let hash = content_hash(content);
+        laptop.content().put_content(&hash, content).unwrap();
+        laptop
+            .artifacts()
+            .store(&Artifact {
+                id: "note".into(),
+                content_hash: hash,
+                modified_at: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        phone
+            .dial_peer(laptop.device_id(), &format!("127.0.0.1:{}", port))
+            .await
+            .unwrap();
+        let progress = phone.start_sync(laptop.device_id()).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert!(phone.artifacts().get("note").unwrap().is_some());
+        let negotiated = phone.connections().negotiated(laptop.device_id()).unwrap();
+        assert!(negotiated.features.contains(FeatureFlags::CHUNKED_SYNC));
+
+        // The listening side syncs through the connection it accepted
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !laptop.linked_peers().contains(phone.device_id()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let progress = laptop.start_sync(phone.device_id()).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+
+        phone.disconnect_peer(laptop.device_id());
+        assert!(matches!(
+            phone.start_sync(laptop.device_id()),
+            Err(CoreError::PeerNotConnected(_))
+        ));
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_code_pairing_allows_one_attempt() {
+        use nomade_quic::MemoryNetwork;
+
+        let dir = tempfile::tempdir().unwrap();
+        let laptop = device_with(dir.path(), "laptop", |config| {
+            config.network.timeouts.handshake_secs = 1;
+        });
+        let (phone, tablet) = (device(dir.path(), "phone"), device(dir.path(), "tablet"));
+        let network = MemoryNetwork::new();
+        let listener = Arc::new(
+            network
+                .bind_device("laptop", laptop.device_id().clone())
+                .unwrap(),
+        );
+        laptop.serve(listener).unwrap();
+        let phone_dialer = network
+            .bind_device("phone", phone.device_id().clone())
+            .unwrap();
+        let tablet_dialer = network
+            .bind_device("tablet", tablet.device_id().clone())
+            .unwrap();
+        let mistyped = |code: &str| {
+            let first = if code.starts_with('0') { "1" } else { "0" };
+            format!("{}{}", first, &code[1..])
+        };
+
+        // A wrong code fails, and is burned on the device it was typed on
+        let code = laptop.start_code_pairing("Laptop").unwrap();
+        let wrong = mistyped(&code);
+        assert!(phone
+            .pair_with_code(&phone_dialer, "laptop", &wrong, "Phone")
+            .await
+            .is_err());
+        let retried = phone
+            .pair_with_code(&phone_dialer, "laptop", &wrong, "Phone")
+            .await;
+        assert!(
+            matches!(retried, Err(CoreError::Network(ProtocolError::PeerRejected(ref e))) if e.contains("already tried"))
+        );
+        // The shown code was used up by the failed attempt
+        assert!(tablet
+            .pair_with_code(&tablet_dialer, "laptop", &code, "Tablet")
+            .await
+            .is_err());
+        assert!(laptop.trust().read().unwrap().list().next().is_none());
+
+        let code = laptop.start_code_pairing("Laptop").unwrap();
+        let paired = phone
+            .pair_with_code(&phone_dialer, "laptop", &code, "Phone")
+            .await
+            .unwrap();
+        assert_eq!(&paired, laptop.device_id());
+        assert_eq!(
+            phone.device_permissions(&paired).unwrap(),
+            Permissions::full()
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while laptop.device_permissions(phone.device_id()).is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            laptop
+                .trust()
+                .read()
+                .unwrap()
+                .get(phone.device_id())
+                .unwrap()
+                .device_name,
+            "Phone"
+        );
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+        tablet.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_links_gate_sync_on_negotiated_features() {
+        use nomade_quic::negotiation::PROTOCOL_VERSION;
+        use nomade_quic::{exchange_hello, Hello, MemoryNetwork, Transport};
+
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone) = (device(dir.path(), "laptop"), device(dir.path(), "phone"));
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        let network = MemoryNetwork::new();
+        let listener = Arc::new(
+            network
+                .bind_device("laptop", laptop.device_id().clone())
+                .unwrap(),
+        );
+        laptop.serve(listener).unwrap();
+
+        // An older peer without chunked sync is linked, but not synced with
+        let dialer = network
+            .bind_device("phone", phone.device_id().clone())
+            .unwrap();
+        let connection = dialer.connect("laptop").await.unwrap();
+        let hello = Hello::new(vec![PROTOCOL_VERSION], FeatureFlags::empty());
+        let handshake = exchange_hello(connection.as_ref(), Direction::Outbound, &hello)
+            .await
+            .unwrap();
+        assert_eq!(handshake.negotiated.features, FeatureFlags::empty());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !laptop.connections().is_connected(phone.device_id()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let negotiated = laptop.connections().negotiated(phone.device_id()).unwrap();
+        assert_eq!(negotiated.features, FeatureFlags::empty());
+        assert!(!laptop.linked_peers().contains(phone.device_id()));
+        assert!(matches!(
+            laptop.start_sync(phone.device_id()),
+            Err(CoreError::PeerNotConnected(_))
+        ));
+
+        connection.close();
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_links_measure_and_drop_silent_peers() {
+        use nomade_quic::keepalive::KEEPALIVE_STREAM_TAG;
+        use nomade_quic::negotiation::PROTOCOL_VERSION;
+        use nomade_quic::{exchange_hello, Hello, MemoryNetwork, Transport};
+        use tokio::io::AsyncWriteExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = |name: &str| {
+            device_with(dir.path(), name, |config| {
+                config.network.keepalive_secs = 1;
+                config.network.timeouts.idle_secs = 2;
+            })
+        };
+        let (laptop, phone, tablet) = (runtime("laptop"), runtime("phone"), runtime("tablet"));
+        for peer in [&phone, &tablet] {
+            laptop
+                .accept_pairing_offer(&peer.pairing_offer("Peer").unwrap())
+                .unwrap();
+        }
+        phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        let network = MemoryNetwork::new();
+        let listener = Arc::new(
+            network
+                .bind_device("laptop", laptop.device_id().clone())
+                .unwrap(),
+        );
+        laptop.serve(listener).unwrap();
+        let mut events = laptop.events().subscribe();
+
+        // Linked runtimes ping each other and report the round trips
+        let dialer = network
+            .bind_device("phone", phone.device_id().clone())
+            .unwrap();
+        phone
+            .connect_peer(&dialer, laptop.device_id(), "laptop")
+            .await
+            .unwrap();
+        let negotiated = phone.connections().negotiated(laptop.device_id()).unwrap();
+        assert!(negotiated.features.contains(FeatureFlags::KEEPALIVE));
+        let quality = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match phone.connection_quality(laptop.device_id()) {
+                    Some(quality) if quality.rtt_ms.is_some() => break quality,
+                    _ => tokio::time::sleep(Duration::from_millis(50)).await,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(quality.loss_rate, 0.0);
+        assert!(phone
+            .connections()
+            .clock_offset(laptop.device_id())
+            .is_some());
+
+        // A peer that negotiated keepalives but never answers is dropped
+        let dialer = network
+            .bind_device("tablet", tablet.device_id().clone())
+            .unwrap();
+        let connection = dialer.connect("laptop").await.unwrap();
+        let hello = Hello::new(vec![PROTOCOL_VERSION], FeatureFlags::KEEPALIVE);
+        let _handshake = exchange_hello(connection.as_ref(), Direction::Outbound, &hello)
+            .await
+            .unwrap();
+        let mut pings = connection.open_uni().await.unwrap();
+        pings.write_all(&[KEEPALIVE_STREAM_TAG]).await.unwrap();
+        let disconnected = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Event::DeviceDisconnected { device_id } = events.recv().await.unwrap() {
+                    break device_id;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(disconnected, tablet.device_id().to_string());
+        // The link drops it once it notices the keepalive stopped
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while laptop.connections().is_connected(tablet.device_id()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // The answering peer stays linked
+        assert!(laptop.connections().is_connected(phone.device_id()));
+
+        connection.close();
+        for runtime in [laptop, phone, tablet] {
+            runtime.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_links_resume_ratchet_sessions_after_reconnecting() {
+        use nomade_quic::MemoryNetwork;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone) = (device(dir.path(), "laptop"), device(dir.path(), "phone"));
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        let network = MemoryNetwork::new();
+        let listener = Arc::new(
+            network
+                .bind_device("laptop", laptop.device_id().clone())
+                .unwrap(),
+        );
+        laptop.serve(listener).unwrap();
+        let dialer = network
+            .bind_device("phone", phone.device_id().clone())
+            .unwrap();
+        let put = |id: &str, content: &[u8]| {
+            let hash = content_hash(content);
+            laptop.content().put_content(&hash, content).unwrap();
+            laptop
+                .artifacts()
+                .store(&Artifact {
+                    id: id.into(),
+                    content_hash: hash,
+                    modified_at: 1,
+                    ..Default::default()
+                })
+                .unwrap();
+        };
+        // Drops the link once both sides kept their ratchet session
+        let disconnect = || async {
+            phone.disconnect_peer(laptop.device_id());
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    let held = (
+                        laptop.ratchet_sessions().held(phone.device_id()),
+                        phone.ratchet_sessions().held(laptop.device_id()),
+                    );
+                    match held {
+                        (Some(on_laptop), Some(on_phone)) => break (on_laptop, on_phone),
+                        _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                    }
+                }
+            })
+            .await
+            .unwrap()
+        };
+
+        put("note", b"first link");
+        phone
+            .connect_peer(&dialer, laptop.device_id(), "laptop")
+            .await
+            .unwrap();
+        let negotiated = phone.connections().negotiated(laptop.device_id()).unwrap();
+        assert!(negotiated.features.contains(FeatureFlags::SIGNED_FRAMES));
+        let progress = phone.start_sync(laptop.device_id()).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+        let (session, on_phone) = disconnect().await;
+        assert_eq!(session, on_phone);
+
+        // The next link takes the sessions back and keeps ratcheting them
+        put("draft", b"second link");
+        phone
+            .connect_peer(&dialer, laptop.device_id(), "laptop")
+            .await
+            .unwrap();
+        assert_eq!(laptop.ratchet_sessions().held(phone.device_id()), None);
+        let progress = phone.start_sync(laptop.device_id()).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert!(phone.artifacts().get("draft").unwrap().is_some());
+        assert_eq!(disconnect().await, (session, session));
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_group_members_trust_each_other_through_links() {
+        use nomade_crypto::TrustState;
+        use nomade_quic::MemoryNetwork;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone, tablet) = (
+            device(dir.path(), "laptop"),
+            device(dir.path(), "phone"),
+            device(dir.path(), "tablet"),
+        );
+        // Phone and tablet are each paired with the laptop only
+        for peer in [&phone, &tablet] {
+            laptop
+                .accept_pairing_offer(&peer.pairing_offer("Peer").unwrap())
+                .unwrap();
+            peer.accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+                .unwrap();
+        }
+        let group_id = laptop.create_group("Laptop").unwrap();
+        laptop.add_group_member(phone.device_id()).unwrap();
+        laptop.add_group_member(tablet.device_id()).unwrap();
+        assert_eq!(laptop.group_members().len(), 3);
+
+        let network = MemoryNetwork::new();
+        let listener = Arc::new(
+            network
+                .bind_device("laptop", laptop.device_id().clone())
+                .unwrap(),
+        );
+        laptop.serve(listener).unwrap();
+        for (name, peer) in [("phone", &phone), ("tablet", &tablet)] {
+            let dialer = network.bind_device(name, peer.device_id().clone()).unwrap();
+            peer.connect_peer(&dialer, laptop.device_id(), "laptop")
+                .await
+                .unwrap();
+        }
+        let trusts = |runtime: &NomadeRuntime, device_id: &DeviceId| {
+            runtime.trust().read().unwrap().state(device_id) == Some(&TrustState::Trusted)
+        };
+        async fn settle(check: impl Fn() -> bool) {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while !check() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap()
+        }
+
+        // The roster reaches both members when they link
+        settle(|| trusts(&phone, tablet.device_id()) && trusts(&tablet, phone.device_id())).await;
+        assert_eq!(phone.group_id(), Some(group_id.clone()));
+        assert_eq!(tablet.group_id(), Some(group_id));
+
+        // Every remaining member forgets a removed device
+        laptop.remove_group_member(tablet.device_id()).unwrap();
+        assert!(laptop
+            .trust()
+            .read()
+            .unwrap()
+            .get(tablet.device_id())
+            .is_none());
+        settle(&|| {
+            phone
+                .trust()
+                .read()
+                .unwrap()
+                .get(tablet.device_id())
+                .is_none()
+        })
+        .await;
+        assert_eq!(phone.group_members().len(), 2);
+        assert!(trusts(&phone, laptop.device_id()));
+
+        for runtime in [laptop, phone, tablet] {
+            runtime.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_links_reject_replayed_frames() {
+        use nomade_quic::negotiation::PROTOCOL_VERSION;
+        use nomade_quic::{
+            exchange_hello, Channel, ChannelId, Connection, Frame, Hello, MemoryNetwork,
+            MessageType, ReplayGuard, ReplayStore, Transport,
+        };
+
+        /// Send `frame` on a fresh `LiveEvents` channel
+        async fn send(connection: &dyn Connection, guard: Option<ReplayGuard>, frame: &Frame) {
+            let mut channel = Channel::open(connection, ChannelId::LiveEvents)
+                .await
+                .unwrap();
+            channel.set_replay_guard(guard);
+            channel.send(frame).await.unwrap();
+            channel.finish().await.unwrap();
+        }
+
+        fn deleted(id: &str) -> Frame {
+            Frame::from_message(
+                MessageType::Event,
+                &Event::ArtifactDeleted { id: id.into() },
+            )
+            .unwrap()
+        }
+
+        async fn next_remote(events: &mut nomade_events::EventReceiver) -> Event {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Event::Remote { event, .. } = events.recv().await.unwrap() {
+                        break *event;
+                    }
+                }
+            })
+            .await
+            .unwrap()
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone) = (device(dir.path(), "laptop"), device(dir.path(), "phone"));
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        let network = MemoryNetwork::new();
+        let listener = Arc::new(
+            network
+                .bind_device("laptop", laptop.device_id().clone())
+                .unwrap(),
+        );
+        laptop.serve(listener).unwrap();
+        let dialer = network
+            .bind_device("phone", phone.device_id().clone())
+            .unwrap();
+        let counters = ReplayStore::new();
+        let session = laptop.device_id().to_string();
+        let hello = || {
+            Hello::new(vec![PROTOCOL_VERSION], FeatureFlags::SEQUENCED_FRAMES)
+                .with_session(counters.hello(&session).unwrap())
+        };
+        let mut events = laptop.events().subscribe();
+
+        let connection = dialer.connect("laptop").await.unwrap();
+        let handshake = exchange_hello(connection.as_ref(), Direction::Outbound, &hello())
+            .await
+            .unwrap();
+        assert!(handshake
+            .negotiated
+            .features
+            .contains(FeatureFlags::SEQUENCED_FRAMES));
+        let guard = counters
+            .resume(&session, &handshake.remote.session.unwrap())
+            .unwrap();
+        let captured = deleted("first").with_sequence(guard.next_sequence().unwrap());
+        send(connection.as_ref(), None, &captured).await;
+        assert_eq!(
+            next_remote(&mut events).await,
+            Event::ArtifactDeleted { id: "first".into() }
+        );
+
+        // The replayed frame is dropped, later frames still arrive
+        send(connection.as_ref(), None, &captured).await;
+        send(connection.as_ref(), Some(guard.clone()), &deleted("second")).await;
+        assert_eq!(
+            next_remote(&mut events).await,
+            Event::ArtifactDeleted {
+                id: "second".into()
+            }
+        );
+
+        // The counters outlive the link
+        connection.close();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while laptop.connections().is_connected(phone.device_id()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        counters.save(&session, &guard).unwrap();
+        let connection = dialer.connect("laptop").await.unwrap();
+        let handshake = exchange_hello(connection.as_ref(), Direction::Outbound, &hello())
+            .await
+            .unwrap();
+        let guard = counters
+            .resume(&session, &handshake.remote.session.unwrap())
+            .unwrap();
+        send(connection.as_ref(), None, &captured).await;
+        send(connection.as_ref(), Some(guard), &deleted("third")).await;
+        assert_eq!(
+            next_remote(&mut events).await,
+            Event::ArtifactDeleted { id: "third".into() }
+        );
+
+        // A peer that lost its counters starts a new epoch from 1
+        connection.close();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while laptop.connections().is_connected(phone.device_id()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let counters = ReplayStore::new();
+        let hello = Hello::new(vec![PROTOCOL_VERSION], FeatureFlags::SEQUENCED_FRAMES)
+            .with_session(counters.hello(&session).unwrap());
+        let connection = dialer.connect("laptop").await.unwrap();
+        let handshake = exchange_hello(connection.as_ref(), Direction::Outbound, &hello)
+            .await
+            .unwrap();
+        let guard = counters
+            .resume(&session, &handshake.remote.session.unwrap())
+            .unwrap();
+        assert_eq!(guard.counters().sent, 0);
+        send(connection.as_ref(), Some(guard), &deleted("fourth")).await;
+        assert_eq!(
+            next_remote(&mut events).await,
+            Event::ArtifactDeleted {
+                id: "fourth".into()
+            }
+        );
+
+        connection.close();
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_locked_keystore_blocks_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .build()
+            .unwrap();
+        let mut events = runtime.events().subscribe();
+        let export = || {
+            let out = tempfile::tempdir().unwrap();
+            runtime.export_dir(&[], out.path(), &ExportOptions::default(), |_| {})
+        };
+        assert!(!runtime.keystore_status().protected);
+        export().unwrap();
+
+        runtime.set_keystore_passphrase("hunter2").unwrap();
+        let status = runtime.keystore_status();
+        assert!(status.protected && status.unlocked);
+        assert!(status.remaining_secs.unwrap() <= runtime.context().config().auto_lock_secs);
+        let master = runtime.keystore().vault().master_key().unwrap();
+        runtime.lock_keystore();
+        assert!(matches!(
+            export(),
+            Err(CoreError::Crypto(CryptoError::KeystoreLocked))
+        ));
+
+        assert!(runtime.unlock_keystore("hunter3").is_err());
+        runtime.unlock_keystore("hunter2").unwrap();
+        export().unwrap();
+        runtime.lock_keystore();
+        runtime.unlock_keystore_with(&move || Ok(master)).unwrap();
+        export().unwrap();
+
+        let changes: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                Event::KeystoreLockChanged { locked } => Some(locked),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(changes, [false, true, false, true, false]);
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sensitive_operations_ask_the_gate() {
+        struct Prompt(Mutex<Vec<String>>, bool);
+
+        impl AuthGate for Prompt {
+            fn require_auth(&self, reason: &str) -> Result<()> {
+                self.0.lock().unwrap().push(reason.to_string());
+                if self.1 {
+                    Ok(())
+                } else {
+                    Err(CoreError::AuthDenied(reason.to_string()))
+                }
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let refusing = Arc::new(Prompt(Mutex::new(Vec::new()), false));
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .auth_gate(refusing.clone())
+            .build()
+            .unwrap();
+        let stranger = Keystore::in_memory().device_id().clone();
+        assert!(matches!(
+            runtime.revoke_device(&stranger, "lost"),
+            Err(CoreError::AuthDenied(_))
+        ));
+        assert!(runtime.export_secret_key().is_err());
+        runtime.set_keystore_passphrase("hunter2").unwrap();
+        assert!(runtime.remove_keystore_passphrase().is_err());
+        assert!(runtime.keystore_status().protected);
+        assert_eq!(
+            *refusing.0.lock().unwrap(),
+            [
+                SensitiveOp::RevokeDevice.reason(),
+                SensitiveOp::ExportSecretKey.reason(),
+                SensitiveOp::DisableEncryption.reason(),
+            ]
+        );
+        runtime.shutdown().await.unwrap();
+
+        // Operations left out of the policy skip the prompt
+        let context = context_with(dir.path(), |config| {
+            config.auth.gated.remove(&SensitiveOp::ExportSecretKey);
+        });
+        let accepting = Arc::new(Prompt(Mutex::new(Vec::new()), true));
+        let runtime = NomadeRuntime::builder(context)
+            .auth_gate(accepting.clone())
+            .build()
+            .unwrap();
+        assert!(
+            runtime.export_secret_key().is_err(),
+            "keystore still locked"
+        );
+        runtime.unlock_keystore("hunter2").unwrap();
+        assert_eq!(runtime.export_secret_key().unwrap().len(), 32);
+        runtime.remove_keystore_passphrase().unwrap();
+        assert!(!runtime.keystore_status().protected);
+        assert_eq!(accepting.0.lock().unwrap().len(), 1);
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_metadata_store_encrypted_and_rotated() {
+        use nomade_storage::sqlite_store::is_encrypted;
+        use nomade_storage::SqliteStore;
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("artifacts.db");
+        let note = Artifact {
+            id: "note".into(),
+            title: "Salary review".into(),
+            tags: vec!["hr".into()],
+            ..Default::default()
+        };
+        SqliteStore::open(&db).unwrap().store(&note).unwrap();
+        let build = || {
+            let mut config = NomadeConfig::new(dir.path());
+            config.storage_url = Some(format!("sqlite://{}", db.display()));
+            config.encrypt_metadata = true;
+            NomadeRuntime::builder(Context::new(config).unwrap())
+                .build()
+                .unwrap()
+        };
+
+        let runtime = build();
+        assert!(is_encrypted(&db).unwrap());
+        assert_eq!(runtime.artifacts().get("note").unwrap().unwrap(), note);
+        assert_eq!(runtime.rotate_metadata_key().unwrap(), 1);
+        assert_eq!(runtime.rotate_metadata_key().unwrap(), 2);
+        runtime.shutdown().await.unwrap();
+        drop(runtime);
+
+        // A rotation interrupted before saving its generation completes
+        let state = dir.path().join(METADATA_KEY_FILE);
+        std::fs::write(&state, br#"{"generation": 1}"#).unwrap();
+        let runtime = build();
+        assert_eq!(runtime.artifacts().get("note").unwrap().unwrap(), note);
+        assert_eq!(runtime.rotate_metadata_key().unwrap(), 3);
+        runtime.shutdown().await.unwrap();
+        drop(runtime);
+        assert!(SqliteStore::open(&db).is_err());
+
+        let plain = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .build()
+            .unwrap();
+        assert!(plain.rotate_metadata_key().is_err());
+        plain.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_schemas_checked_and_queried() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = |backend| {
+            let mut config = NomadeConfig::new(dir.path());
+            config.storage_backend = backend;
+            if backend == StorageBackend::Persistent {
+                config.storage_url = Some(format!(
+                    "sqlite://{}",
+                    dir.path().join("artifacts.db").display()
+                ));
+            }
+            NomadeRuntime::builder(Context::new(config).unwrap())
+                .build()
+                .unwrap()
+        };
+        let photo = |id: &str, fields: serde_json::Value| Artifact {
+            id: id.into(),
+            artifact_type: Some("photo".into()),
+            fields: serde_json::from_value(fields).unwrap(),
+            ..Default::default()
+        };
+        let schema: MetadataSchema = serde_json::from_str(
+            r#"{"artifact_type": "photo", "fields": [
+                {"name": "camera", "type": "text", "indexed": true},
+                {"name": "taken_at", "type": "timestamp", "indexed": true, "required": true}
+            ]}"#,
+        )
+        .unwrap();
+        let query = FieldQuery {
+            artifact_type: "photo".into(),
+            field: "taken_at".into(),
+            equals: None,
+            min: Some(1_700_000_000.0),
+            max: None,
+        };
+
+        for backend in [StorageBackend::Persistent, StorageBackend::Memory] {
+            let runtime = build(backend);
+            runtime.register_schema(schema.clone()).unwrap();
+            let artifacts = runtime.artifacts();
+            artifacts
+                .store(&photo(
+                    "old",
+                    serde_json::json!({"taken_at": 1_600_000_000}),
+                ))
+                .unwrap();
+            artifacts
+                .store(&photo(
+                    "new",
+                    serde_json::json!({"camera": "X100", "taken_at": 1_750_000_000}),
+                ))
+                .unwrap();
+            assert!(artifacts
+                .store(&photo("bad", serde_json::json!({"camera": "X100"})))
+                .is_err());
+            let found = runtime.query_artifacts(&query).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].id, "new");
+            let projection = Projection {
+                fields: Default::default(),
+                custom: ["camera".to_string()].into(),
+            };
+            let rows = runtime.query_projected(&query, &projection).unwrap();
+            assert_eq!(rows[0].fields["camera"], "X100");
+            assert!(rows[0].title.is_none());
+            runtime.shutdown().await.unwrap();
+        }
+
+        // Schemas and the field index survive a restart
+        let runtime = build(StorageBackend::Persistent);
+        assert_eq!(runtime.schemas(), [schema]);
+        assert_eq!(runtime.query_artifacts(&query).unwrap().len(), 1);
+        assert!(runtime
+            .query_artifacts(&FieldQuery {
+                field: "lens".into(),
+                ..query
+            })
+            .is_err());
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_network_state_steers_transfers() {
+        use nomade_quic::NetworkKind;
+        use nomade_sync::TransferMode;
+
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .build()
+            .unwrap();
+        let expect = |state: NetworkState, mode: TransferMode| {
+            runtime.set_network_state(state);
+            assert_eq!(runtime.network_state(), state);
+            let sync = runtime.sync().clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(5), async {
+                    while sync.transfer_mode() != mode {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+                .await
+                .expect("transfer mode follows the network");
+            }
+        };
+        expect(NetworkState::OFFLINE, TransferMode::Paused).await;
+        let cellular = NetworkState {
+            kind: NetworkKind::Cellular,
+            metered: false,
+        };
+        expect(cellular, TransferMode::Paused).await;
+        let wifi = NetworkState {
+            kind: NetworkKind::Wifi,
+            metered: false,
+        };
+        expect(wifi, TransferMode::Full).await;
+
+        let policy = crate::config::SyncPolicy {
+            allow_metered: true,
+            ..Default::default()
+        };
+        assert_eq!(policy.transfer_mode(&cellular), TransferMode::Reduced);
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_attestations_are_countersigned() {
+        let dir = tempfile::tempdir().unwrap();
+        let laptop = device(dir.path(), "laptop");
+        let phone = device(dir.path(), "phone");
+        let phone_id = laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        assert!(laptop.countersign_attestation(&phone_id).is_err());
+
+        let statement = phone.publish_attestation("Phone", "alice", DAY).unwrap();
+        laptop
+            .connections()
+            .handle_attestation(&statement, Some(&phone_id))
+            .unwrap();
+        assert_eq!(laptop.attestations()[0].vouch_path, None);
+
+        let vouched = laptop.countersign_attestation(&phone_id).unwrap();
+        assert_eq!(vouched.vouchers().collect::<Vec<_>>(), [laptop.device_id()]);
+        assert_eq!(
+            laptop.attestations()[0].vouch_path,
+            Some(vec![laptop.device_id().clone(), phone_id])
+        );
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_devices_of_a_trusted_user_are_admitted() {
+        let dir = tempfile::tempdir().unwrap();
+        let (phone, laptop, friend) = (
+            device(dir.path(), "phone"),
+            device(dir.path(), "laptop"),
+            device(dir.path(), "friend"),
+        );
+        let passphrase = "orbit maple lantern";
+
+        // Alice enrolls her laptop from the phone, after pairing the two
+        let laptop_id = phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        let phone_cert = phone
+            .enroll_device(passphrase, phone.device_id(), "Phone")
+            .unwrap();
+        let laptop_cert = phone
+            .enroll_device(passphrase, &laptop_id, "Laptop")
+            .unwrap();
+
+        // A friend confirms Alice once and then trusts all her devices
+        assert!(friend.admit_enrolled(&laptop_cert).is_err());
+        assert!(friend.trust_user(&phone_cert, "Alice").unwrap());
+        assert!(friend.admit_enrolled(&laptop_cert).unwrap());
+        assert_eq!(
+            friend.device_permissions(&laptop_id).unwrap(),
+            Permissions::full()
+        );
+
+        let revocation = phone
+            .revoke_enrolled(passphrase, &laptop_id, "stolen")
+            .unwrap();
+        assert!(phone.device_permissions(&laptop_id).is_err());
+        assert!(friend.apply_user_revocation(&revocation).unwrap());
+        assert!(friend.device_permissions(&laptop_id).is_err());
+
+        for runtime in [phone, laptop, friend] {
+            runtime.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wipe_commands_from_own_devices_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let (phone, laptop, friend) = (
+            device(dir.path(), "phone"),
+            device(dir.path(), "laptop"),
+            device(dir.path(), "friend"),
+        );
+        let pair = |from: &NomadeRuntime, to: &NomadeRuntime| {
+            from.accept_pairing_offer(&to.pairing_offer("Device").unwrap())
+                .unwrap()
+        };
+        let (laptop_id, phone_id) = (pair(&phone, &laptop), pair(&laptop, &phone));
+        pair(&friend, &phone);
+
+        // Both of Alice's devices enroll themselves and each other
+        let passphrase = "orbit maple lantern";
+        let phone_cert = phone.enroll_device(passphrase, &phone_id, "Phone").unwrap();
+        phone
+            .enroll_device(passphrase, &laptop_id, "Laptop")
+            .unwrap();
+        laptop
+            .enroll_device(passphrase, &laptop_id, "Laptop")
+            .unwrap();
+        laptop
+            .enroll_device(passphrase, &phone_id, "Phone")
+            .unwrap();
+
+        let command = phone.wipe_device(&laptop_id).unwrap();
+        laptop.check_wipe(&command).unwrap();
+        assert!(phone.check_wipe(&command).is_err());
+
+        // A friend trusting Alice is not one of her devices
+        friend.trust_user(&phone_cert, "Alice").unwrap();
+        let command =
+            WipeCommand::new(phone.keystore().keypair(), friend.device_id().clone()).unwrap();
+        assert!(friend.check_wipe(&command).is_err());
+
+        for runtime in [phone, laptop, friend] {
+            runtime.shutdown().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snippets_reach_connected_peers_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let laptop = device(dir.path(), "laptop");
+        let phone = device(dir.path(), "phone");
+        let offer = laptop.pairing_offer("Laptop").unwrap();
+        let paired = phone.accept_pairing_offer(&offer).unwrap();
+
+        assert!(matches!(
+            phone.send_snippet(&paired, "https://example.com"),
+            Err(CoreError::PeerNotConnected(_))
+        ));
+        let _queues = phone
+            .connections()
+            .admit(paired.clone(), Direction::Inbound)
+            .unwrap();
+        let mut rx = phone.events().subscribe();
+        let snippet = phone.send_snippet(&paired, "https://example.com").unwrap();
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            Event::SnippetSent {
+                to: paired.to_string(),
+                snippet: snippet.clone(),
+            }
+        );
+        let oversized = "x".repeat(MAX_SNIPPET_LEN + 1);
+        assert!(matches!(
+            phone.send_snippet(&paired, &oversized),
+            Err(CoreError::SnippetTooLarge(_))
+        ));
+        assert!(phone.artifacts().list().unwrap().is_empty());
+
+        let artifact = phone.save_snippet(&snippet).unwrap();
+        assert_eq!(artifact.title, "https://example.com");
+        assert_eq!(artifact.artifact_type.as_deref(), Some("snippet"));
+        assert_eq!(
+            phone.content().get_content(&artifact.content_hash).unwrap(),
+            Some(b"https://example.com".to_vec())
+        );
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_push_wake_syncs_with_sender() {
+        let dir = tempfile::tempdir().unwrap();
+        let laptop = device(dir.path(), "laptop");
+        let phone = device(dir.path(), "phone");
+        assert!(laptop.wake_token(phone.device_id()).is_err());
+        phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        let hash = nomade_storage::content_hash(b"hello");
+        laptop.content().put_content(&hash, b"hello").unwrap();
+        laptop
+            .artifacts()
+            .store(&Artifact {
+                id: "note".into(),
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+
+        // Laptop never reconnects
+        let token = laptop.wake_token(phone.device_id()).unwrap();
+        let report = phone
+            .handle_push(&token, Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert_eq!(report.outcome, WakeOutcome::PeerUnavailable);
+        assert_eq!(report.peer_id, laptop.device_id().to_string());
+
+        // Laptop reconnects while the phone waits
+        let token = laptop.wake_token(phone.device_id()).unwrap();
+        let reconnect = tokio::spawn({
+            let (laptop, phone) = (laptop.clone(), phone.clone());
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                phone.register_sync_peer(
+                    laptop.device_id().clone(),
+                    laptop.sync_view(phone.device_id()),
+                );
+            }
+        });
+        let report = phone
+            .handle_push(&token, Duration::from_secs(10))
+            .await
+            .unwrap();
+        reconnect.await.unwrap();
+        assert_eq!(report.outcome, WakeOutcome::Completed);
+        assert_eq!(report.artifacts_synced, 1);
+        assert!(phone.artifacts().get("note").unwrap().is_some());
+        // The next sync with the laptop only catches up on changes
+        let cursor = phone.sync().cursor(&laptop.device_id().to_string());
+        assert!(cursor.is_some());
+        let stored = phone
+            .trust()
+            .read()
+            .unwrap()
+            .get(laptop.device_id())
+            .unwrap()
+            .peer_data[SYNC_CURSOR_KEY]
+            .clone();
+        assert_eq!(
+            serde_json::from_value::<Option<SyncCursor>>(stored).unwrap(),
+            cursor
+        );
+
+        // Replayed, forged and garbled payloads are refused
+        assert!(phone.handle_push(&token, DAY).await.is_err());
+        let stranger = nomade_crypto::generate_keypair();
+        let forged = WakeToken::issue(&stranger, phone.device_id().clone()).unwrap();
+        assert!(phone
+            .handle_push(&forged.encode().unwrap(), DAY)
+            .await
+            .is_err());
+        assert!(phone.handle_push("garbage", DAY).await.is_err());
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_background_sync_reports_what_remains() {
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone) = (device(dir.path(), "laptop"), device(dir.path(), "phone"));
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        for (id, content) in [("note", b"hi".to_vec()), ("video", vec![0u8; 4096])] {
+            let hash = nomade_storage::content_hash(&content);
+            laptop.content().put_content(&hash, &content).unwrap();
+            laptop
+                .artifacts()
+                .store(&Artifact {
+                    id: id.into(),
+                    content_hash: hash,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        phone.register_sync_peer(
+            laptop.device_id().clone(),
+            laptop.sync_view(phone.device_id()),
+        );
+
+        let budget = SyncBudget::time(Duration::from_secs(10)).with_bytes(1024);
+        let reports = phone.background_sync(budget).await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].outcome, BudgetOutcome::OutOfBytes);
+        assert_eq!(reports[0].artifacts_synced, 1);
+        assert_eq!(reports[0].remaining[0].id, "video");
+        assert!(phone.artifacts().get("note").unwrap().is_some());
+
+        let reports = phone.background_sync(SyncBudget::default()).await.unwrap();
+        assert_eq!(reports[0].outcome, BudgetOutcome::Completed);
+        assert!(phone.artifacts().get("video").unwrap().is_some());
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_evicted_content_is_fetched_on_demand() {
+        let dir = tempfile::tempdir().unwrap();
+        let laptop = device(dir.path(), "laptop");
+        let phone = device_with(dir.path(), "phone", |config| {
+            config.content_quota_bytes = Some(150);
+        });
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        for (id, byte) in [("old", 1u8), ("new", 2u8)] {
+            let content = vec![byte; 100];
+            let hash = nomade_storage::content_hash(&content);
+            laptop.content().put_content(&hash, &content).unwrap();
+            laptop
+                .artifacts()
+                .store(&Artifact {
+                    id: id.into(),
+                    modified_at: byte as u64,
+                    content_hash: hash,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        phone.register_sync_peer(
+            laptop.device_id().clone(),
+            laptop.sync_view(phone.device_id()),
+        );
+        phone.start_sync(laptop.device_id()).unwrap().wait().await;
+
+        // Over quota: the least recently modified content goes, metadata stays
+        phone.enforce_quota().unwrap();
+        let old = phone.artifacts().get("old").unwrap().unwrap();
+        assert!(!phone.content().has_content(&old.content_hash).unwrap());
+        assert!(phone.scrub().unwrap().corrupt.is_empty());
+
+        assert_eq!(
+            phone.retrieve_or_fetch("old").await.unwrap(),
+            vec![1u8; 100]
+        );
+        phone.unregister_sync_peer(laptop.device_id());
+        phone.pin_artifact("old").unwrap();
+        assert_eq!(phone.pinned_artifacts(), ["old"]);
+        let report = phone.enforce_quota().unwrap();
+        assert_eq!(report.evicted, ["new"]);
+        assert!(matches!(
+            phone.retrieve_or_fetch("new").await,
+            Err(CoreError::ContentUnavailable(_))
+        ));
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reads_share_one_fetch() {
+        use nomade_sync::{BoxFuture, ManifestEntry, RemoteArtifact};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Counts metadata requests, one per fetch
+        struct CountingPeer {
+            inner: Arc<dyn SyncPeer>,
+            fetches: AtomicUsize,
+        }
+
+        impl SyncPeer for CountingPeer {
+            fn manifest(&self) -> BoxFuture<'_, nomade_sync::Result<Vec<ManifestEntry>>> {
+                self.inner.manifest()
+            }
+
+            fn fetch_artifact<'a>(
+                &'a self,
+                id: &'a str,
+            ) -> BoxFuture<'a, nomade_sync::Result<RemoteArtifact>> {
+                self.fetches.fetch_add(1, Ordering::SeqCst);
+                self.inner.fetch_artifact(id)
+            }
+
+            fn fetch_chunk<'a>(
+                &'a self,
+                hash: &'a str,
+                index: u32,
+            ) -> BoxFuture<'a, nomade_sync::Result<Vec<u8>>> {
+                self.inner.fetch_chunk(hash, index)
+            }
+
+            fn fetch_hash_tree<'a>(
+                &'a self,
+                hash: &'a str,
+            ) -> BoxFuture<'a, nomade_sync::Result<nomade_storage::HashTree>> {
+                self.inner.fetch_hash_tree(hash)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop, phone) = (device(dir.path(), "laptop"), device(dir.path(), "phone"));
+        laptop
+            .accept_pairing_offer(&phone.pairing_offer("Phone").unwrap())
+            .unwrap();
+        let content = vec![9u8; 3 * nomade_sync::CHUNK_SIZE];
+        let artifact = nomade_storage::Artifact {
+            id: "video".into(),
+            content_hash: nomade_storage::content_hash(&content),
+            ..Default::default()
+        };
+        laptop
+            .content()
+            .put_content(&artifact.content_hash, &content)
+            .unwrap();
+        laptop.artifacts().store(&artifact).unwrap();
+        // The phone has the metadata only, as after an eviction
+        phone.artifacts().store(&artifact).unwrap();
+
+        // Connected but not trusted: never asked
+        let peer = Arc::new(CountingPeer {
+            inner: laptop.sync_view(phone.device_id()),
+            fetches: AtomicUsize::new(0),
+        });
+        phone.register_sync_peer(laptop.device_id().clone(), peer.clone());
+        assert!(matches!(
+            phone.retrieve_or_fetch("video").await,
+            Err(CoreError::ContentUnavailable(_))
+        ));
+        assert_eq!(peer.fetches.load(Ordering::SeqCst), 0);
+
+        phone
+            .accept_pairing_offer(&laptop.pairing_offer("Laptop").unwrap())
+            .unwrap();
+        let reads: Vec<_> = (0..4)
+            .map(|_| {
+                let phone = phone.clone();
+                tokio::spawn(async move { phone.retrieve_or_fetch("video").await.unwrap() })
+            })
+            .collect();
+        for read in reads {
+            assert_eq!(read.await.unwrap(), content);
+        }
+        assert_eq!(peer.fetches.load(Ordering::SeqCst), 1);
+        assert!(phone.hydrating.lock().unwrap().is_empty());
+
+        laptop.shutdown().await.unwrap();
+        phone.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_share_tokens_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = || {
+            NomadeRuntime::builder(context(dir.path(), StorageBackend::Persistent))
+                .build()
+                .unwrap()
+        };
+
+        let runtime = build();
+        assert!(runtime.share_artifact("missing", DAY).is_err());
+        runtime
+            .artifacts()
+            .store(&Artifact {
+                id: "note".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let token = runtime.share_artifact("note", DAY).unwrap();
+        runtime.shutdown().await.unwrap();
+        drop(runtime);
+
+        let runtime = build();
+        assert_eq!(
+            runtime.shares().read().unwrap().check(&token).unwrap(),
+            "note"
+        );
+        assert!(runtime.revoke_share(&token.id).unwrap());
+        assert!(runtime.shares().read().unwrap().check(&token).is_err());
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_app_events_are_batched() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .keystore(Keystore::in_memory())
+            .build()
+            .unwrap();
+        let mut ui_events = runtime.ui_events().subscribe();
+        for id in ["a", "b", "c"] {
+            runtime
+                .artifacts()
+                .store(&Artifact {
+                    id: id.into(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let Event::Batched { events } = ui_events.recv().await.unwrap() else {
+            panic!("Expected one batch");
+        };
+        let ids: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                Event::ArtifactCreated { id } => id.as_str(),
+                other => panic!("Unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bulk_operations_publish_one_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Persistent))
+            .keystore(Keystore::in_memory())
+            .build()
+            .unwrap();
+        let mut ui_events = runtime.ui_events().subscribe();
+        let artifacts: Vec<_> = (0..1200)
+            .map(|i| Artifact {
+                id: format!("n{:04}", i),
+                tags: vec!["inbox".into()],
+                ..Default::default()
+            })
+            .collect();
+        let ids: Vec<_> = artifacts.iter().map(|a| a.id.clone()).collect();
+
+        let mut reports = 0;
+        runtime.store_many(&artifacts, |_| reports += 1).unwrap();
+        // The file store commits a batch at once and reports it once
+        assert_eq!(reports, if cfg!(feature = "sled") { 12 } else { 1 });
+        let Event::Batched { events } = ui_events.recv().await.unwrap() else {
+            panic!("Expected one batch");
+        };
+        assert_eq!(events.len(), 1200);
+
+        let retag = Retag {
+            add: vec!["done".into()],
+            remove: vec!["inbox".into()],
+        };
+        let progress = runtime.retag_many(&ids, &retag, |_| {}).unwrap();
+        assert_eq!(progress.done, 1200);
+        assert_eq!(
+            runtime.artifacts().get("n0042").unwrap().unwrap().tags,
+            ["done"]
+        );
+        assert!(matches!(
+            ui_events.recv().await.unwrap(),
+            Event::Batched { events } if matches!(events[0], Event::ArtifactUpdated { .. })
+        ));
+
+        runtime.delete_many(&ids[..1000], |_| {}).unwrap();
+        assert_eq!(runtime.artifacts().list().unwrap().len(), 200);
+        assert!(matches!(
+            ui_events.recv().await.unwrap(),
+            Event::Batched { events } if events.len() == 1000
+        ));
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trash_keeps_artifacts_until_emptied() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .keystore(Keystore::in_memory())
+            .build()
+            .unwrap();
+        let mut events = runtime.events().subscribe();
+        for id in ["a", "b"] {
+            runtime
+                .artifacts()
+                .store(&Artifact {
+                    id: id.into(),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        runtime.trash_artifact("a").unwrap();
+        runtime.trash_artifact("b").unwrap();
+        assert_eq!(runtime.trash().unwrap().len(), 2);
+        // Trashed just now, well within the retention
+        assert!(runtime.purge_trash().unwrap().is_empty());
+        let restored = runtime.restore_artifact("a").unwrap();
+        assert_eq!(restored.trashed_at, None);
+        let published: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert!(published
+            .iter()
+            .any(|e| matches!(e, Event::ArtifactTrashed { id } if id == "b")));
+        assert!(published
+            .iter()
+            .any(|e| matches!(e, Event::ArtifactRestored { id } if id == "a")));
+
+        assert_eq!(runtime.empty_trash().unwrap(), ["b"]);
+        assert!(runtime.trash().unwrap().is_empty());
+        assert!(runtime.artifacts().get("a").unwrap().is_some());
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_backup_restores_deleted_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        let build = || {
+            NomadeRuntime::builder(context(dir.path(), StorageBackend::Persistent))
+                .keystore(Keystore::in_memory())
+                .build()
+                .unwrap()
+        };
+        let runtime = build();
+        let mut ids = Vec::new();
+        for i in 0..3u8 {
+            let data = vec![i; 64];
+            runtime
+                .content()
+                .put_content(&content_hash(&data), &data)
+                .unwrap();
+            let artifact = Artifact {
+                id: format!("n{}", i),
+                content_hash: content_hash(&data),
+                ..Default::default()
+            };
+            runtime.artifacts().store(&artifact).unwrap();
+            ids.push(artifact.id);
+        }
+        let info = runtime.create_backup("nightly").unwrap();
+        runtime.delete_many(&ids, |_| {}).unwrap();
+        assert!(runtime.collect_garbage().unwrap().removed.is_empty());
+        runtime.shutdown().await.unwrap();
+        drop(runtime);
+
+        let runtime = build();
+        assert_eq!(runtime.backups(), std::slice::from_ref(&info));
+        let report = runtime.restore_backup(&info.id, |_| {}).unwrap();
+        assert_eq!(report.restored, 3);
+        assert_eq!(runtime.artifacts().list().unwrap().len(), 3);
+
+        runtime.delete_backup(&info.id).unwrap();
+        runtime.delete_many(&ids, |_| {}).unwrap();
+        assert_eq!(runtime.collect_garbage().unwrap().removed.len(), 3);
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_event_bridge_serves_local_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("events.sock");
+        let context = context_with(dir.path(), |config| {
+            config.events.ipc_socket = Some(socket.clone());
+        });
+        let runtime = NomadeRuntime::builder(context)
+            .keystore(Keystore::in_memory())
+            .build()
+            .unwrap();
+
+        let mut subscriber =
+            nomade_events::IpcSubscriber::connect(&socket, dir.path().join(IPC_TOKEN_FILE))
+                .await
+                .unwrap();
+        runtime.events().publish(Event::SyncStarted);
+        assert!(matches!(
+            subscriber.recv().await.unwrap(),
+            Some(Event::SyncStarted)
+        ));
+        runtime.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scrub_quarantines_corrupt_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .keystore(Keystore::in_memory())
+            .build()
+            .unwrap();
+        let hash = nomade_storage::content_hash(b"body");
+        runtime.content().put_content(&hash, b"b0dy").unwrap();
+        runtime
+            .artifacts()
+            .store(&Artifact {
+                id: "note".into(),
+                content_hash: hash.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+        let mut events = runtime.events().subscribe();
+        assert_eq!(runtime.store_health().unwrap().last_scrub, None);
+
+        let report = runtime.scrub().unwrap();
+        assert_eq!(report.damaged_artifacts(), ["note"]);
+        let health = runtime.store_health().unwrap();
+        assert!(health.last_scrub.is_some());
+        assert_eq!(health.artifacts_without_content, 1);
+        assert!(!runtime.content().has_content(&hash).unwrap());
+        assert_eq!(runtime.sync().pending_repairs(), ["note"]);
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            Event::ArtifactCorrupted {
+                status: RepairStatus::Quarantined,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_subsystems() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer = nomade_crypto::generate_keypair();
+        let mut trust = TrustStore::new();
+        trust
+            .add_trusted(
+                peer.device_id().clone(),
+                "Peer".into(),
+                peer.public_key_bytes(),
+            )
+            .unwrap();
+        let runtime = NomadeRuntime::builder(context(dir.path(), StorageBackend::Memory))
+            .keystore(Keystore::in_memory())
+            .trust_store(trust)
+            .build()
+            .unwrap();
+        let queues = runtime
+            .connections()
+            .admit(peer.device_id().clone(), Direction::Inbound)
+            .unwrap();
+
+        runtime
+            .supervisor()
+            .spawn("sync-loop", |token| async move { token.cancelled().await })
+            .unwrap();
+
+        runtime.shutdown().await.unwrap();
+        assert_eq!(runtime.state(), RuntimeState::Stopped);
+        assert!(runtime.supervisor().running().is_empty());
+        assert!(runtime.sync().is_stopped());
+        assert!(queues.closed.is_cancelled());
+        assert!(runtime.connections().connected_peers().is_empty());
+        runtime.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! Platform-held identity keys
+//!
+//! On iOS and Android the identity key can live in the Secure Enclave or
+//! StrongBox. The app registers a signing callback before `start()`; the
+//! runtime then builds its keystore around a `PlatformSigner`, so only the
+//! public key and signatures ever cross the FFI boundary. Device ID
+//! derivation and signature verification stay in Rust.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nomade_crypto::{CryptoError, SigningProvider};
+
+use crate::runtime::executor;
+use crate::Result;
+
+/// Upper bound on a platform signature, including user-presence prompts
+const SIGN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Future resolving to a signature, empty if signing failed or was refused
+pub type SignFuture = Pin<Box<dyn Future<Output = Vec<u8>> + Send>>;
+
+/// Callback asking the platform keystore to sign a message
+pub type SignFn = dyn Fn(Vec<u8>) -> SignFuture + Send + Sync;
+
+static REGISTERED: Mutex<Option<Arc<PlatformSigner>>> = Mutex::new(None);
+
+/// `SigningProvider` delegating to a platform callback
+pub struct PlatformSigner {
+    public_key: [u8; 32],
+    sign: Arc<SignFn>,
+}
+
+impl PlatformSigner {
+    /// Signer for the Ed25519 `public_key` held by the platform
+    pub fn new(public_key: &[u8], sign: Arc<SignFn>) -> Result<Self> {
+        let public_key = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        Ok(Self { public_key, sign })
+    }
+}
+
+impl SigningProvider for PlatformSigner {
+    fn public_key(&self) -> [u8; 32] {
+        self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> nomade_crypto::Result<Vec<u8>> {
+        // The callback resolves on the platform side; wait for it off the
+        // executor so a caller inside the runtime does not starve it
+        let (tx, rx) = std::sync::mpsc::channel();
+        let signing = (self.sign)(message.to_vec());
+        executor().spawn(async move {
+            let _ = tx.send(signing.await);
+        });
+        match rx.recv_timeout(SIGN_TIMEOUT) {
+            Ok(signature) if !signature.is_empty() => Ok(signature),
+            Ok(_) => Err(CryptoError::SigningFailed("Refused by platform".into())),
+            Err(_) => Err(CryptoError::SigningFailed(
+                "Platform signer timed out".into(),
+            )),
+        }
+    }
+}
+
+/// Use a platform signer for the identity of runtimes started from now on
+pub fn register(public_key: &[u8], sign: Arc<SignFn>) -> Result<()> {
+    let signer = PlatformSigner::new(public_key, sign)?;
+    *REGISTERED.lock().unwrap() = Some(Arc::new(signer));
+    Ok(())
+}
+
+/// Go back to the file-backed identity key
+pub fn unregister() {
+    REGISTERED.lock().unwrap().take();
+}
+
+/// Registered platform signer, if any
+pub(crate) fn registered() -> Option<Arc<PlatformSigner>> {
+    REGISTERED.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::{generate_keypair, Keystore};
+
+    #[test]
+    fn test_platform_signer_backs_keystore() {
+        let platform = Arc::new(generate_keypair());
+        let key = platform.clone();
+        let signer = PlatformSigner::new(
+            &platform.public_key_bytes(),
+            Arc::new(move |message: Vec<u8>| -> SignFuture {
+                let key = key.clone();
+                Box::pin(async move {
+                    if message == b"refuse" {
+                        return Vec::new();
+                    }
+                    key.sign(&message).unwrap().to_bytes().to_vec()
+                })
+            }),
+        )
+        .unwrap();
+
+        let keystore = Keystore::with_provider(Arc::new(signer)).unwrap();
+        assert_eq!(keystore.device_id(), platform.device_id());
+        let signature = keystore.keypair().sign(b"roster").unwrap();
+        assert!(platform.verify(b"roster", &signature).is_ok());
+        assert!(matches!(
+            keystore.keypair().sign(b"refuse"),
+            Err(CryptoError::SigningFailed(_))
+        ));
+        assert!(crate::snapshot::snapshot_key(&keystore).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+//! Warm-start state snapshots
+//!
+//! Rebuilding aggregate state (artifact index, collection tree, trust and
+//! sync rules) from the stores is slow on mobile. The runtime periodically
+//! writes it to an encrypted snapshot file and reads it back at start so
+//! the app can render immediately. A missing, foreign or corrupted
+//! snapshot is discarded and state is rebuilt from the stores instead.
+//!
+//! The snapshot key lives as long as the device identity, so snapshots are
+//! encrypted through a `KeyContext` whose counter nonces are reserved in
+//! `snapshot_key.json` and never repeat across restarts.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nomade_crypto::fs::write_durable;
+use nomade_crypto::{
+    derive_for, CryptoError, EncryptedData, KeyContext, KeyPurpose, Keystore, NonceSequence,
+    TrustedDevice,
+};
+use nomade_storage::{Artifact, Collection};
+use nomade_sync::SyncRules;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Snapshot file under the data directory
+pub const SNAPSHOT_FILE: &str = "state.snapshot";
+/// Nonce state of the snapshot key under the data directory
+pub const SNAPSHOT_KEY_FILE: &str = "snapshot_key.json";
+/// Current snapshot format
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Aggregate state captured for warm starts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub version: u32,
+    /// Milliseconds since the Unix epoch
+    pub taken_at_ms: u64,
+    pub device_id: String,
+    pub artifacts: Vec<Artifact>,
+    pub collections: Vec<Collection>,
+    pub trusted_devices: Vec<TrustedDevice>,
+    /// Selective sync rules by peer ID
+    pub sync_rules: BTreeMap<String, SyncRules>,
+}
+
+impl StateSnapshot {
+    /// Stamp a snapshot of the given state with the current time
+    pub fn new(
+        device_id: String,
+        artifacts: Vec<Artifact>,
+        collections: Vec<Collection>,
+        trusted_devices: Vec<TrustedDevice>,
+        sync_rules: BTreeMap<String, SyncRules>,
+    ) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            taken_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            device_id,
+            artifacts,
+            collections,
+            trusted_devices,
+            sync_rules,
+        }
+    }
+}
+
+/// Key encrypting this device's snapshots
+pub fn snapshot_key(keystore: &Keystore) -> Result<[u8; 32]> {
+    let keypair = keystore.keypair();
+    // A hardware-backed key never leaves the platform keystore; Ed25519
+    // signatures are deterministic, so one over a fixed label is a stable
+    // secret only this device can produce
+    let secret = match keypair.secret_key_bytes() {
+        Some(secret) => secret,
+        None => keypair.sign(b"nomade-snapshot-key")?.to_bytes().to_vec(),
+    };
+    Ok(derive_for(
+        KeyPurpose::Snapshot,
+        &secret,
+        keystore.device_id().0.as_bytes(),
+    ))
+}
+
+/// Context encrypting this device's snapshots
+///
+/// Persisted at `path` if given; state left by another identity is
+/// replaced, as its snapshots are unreadable anyway.
+pub fn snapshot_context(keystore: &Keystore, path: Option<&Path>) -> Result<KeyContext> {
+    let key = snapshot_key(keystore)?;
+    let Some(path) = path else {
+        return Ok(KeyContext::new(key, NonceSequence::Random));
+    };
+    match KeyContext::open(path, key) {
+        Err(CryptoError::InvalidKey) => {
+            std::fs::remove_file(path)?;
+            Ok(KeyContext::open(path, key)?)
+        }
+        opened => Ok(opened?),
+    }
+}
+
+/// Encrypt and atomically write a snapshot
+pub fn write(path: &Path, key: &mut KeyContext, snapshot: &StateSnapshot) -> Result<()> {
+    let encrypted = key.encrypt(&serde_json::to_vec(snapshot)?)?;
+    write_durable(path, &serde_json::to_vec(&encrypted)?)?;
+    Ok(())
+}
+
+/// Read a snapshot written for `device_id`
+///
+/// Returns `Ok(None)` if there is no snapshot and an error if it cannot be
+/// decrypted, parsed, or belongs to another format or device.
+pub fn read(path: &Path, key: &KeyContext, device_id: &str) -> Result<Option<StateSnapshot>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let encrypted: EncryptedData = serde_json::from_slice(&bytes)?;
+    let snapshot: StateSnapshot = serde_json::from_slice(&key.decrypt(&encrypted)?)?;
+    if snapshot.version != SNAPSHOT_VERSION || snapshot.device_id != device_id {
+        return Err(crate::CoreError::InvalidConfig(format!(
+            "Snapshot v{} for {} does not match this device",
+            snapshot.version, snapshot.device_id
+        )));
+    }
+    Ok(Some(snapshot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_and_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SNAPSHOT_FILE);
+        let state = dir.path().join(SNAPSHOT_KEY_FILE);
+        let keystore = Keystore::in_memory();
+        let mut key = snapshot_context(&keystore, Some(&state)).unwrap();
+        let device_id = keystore.device_id().0.clone();
+        assert!(read(&path, &key, &device_id).unwrap().is_none());
+
+        let snapshot = StateSnapshot::new(
+            device_id.clone(),
+            vec![Artifact {
+                id: "a".into(),
+                ..Default::default()
+            }],
+            Vec::new(),
+            Vec::new(),
+            BTreeMap::from([("phone".to_string(), SyncRules::all())]),
+        );
+        write(&path, &mut key, &snapshot).unwrap();
+        let first = std::fs::read(&path).unwrap();
+        assert_eq!(
+            read(&path, &key, &device_id).unwrap(),
+            Some(snapshot.clone())
+        );
+
+        // A restarted runtime encrypts under a fresh nonce
+        let mut key = snapshot_context(&keystore, Some(&state)).unwrap();
+        write(&path, &mut key, &snapshot).unwrap();
+        let nonce = |bytes: &[u8]| {
+            serde_json::from_slice::<EncryptedData>(bytes)
+                .unwrap()
+                .nonce
+        };
+        assert_ne!(nonce(&first), nonce(&std::fs::read(&path).unwrap()));
+
+        // Another identity cannot read it, and replaces the key state
+        let other = snapshot_context(&Keystore::in_memory(), Some(&state)).unwrap();
+        assert!(read(&path, &other, &device_id).is_err());
+        std::fs::write(&path, b"{garbage").unwrap();
+        assert!(read(&path, &key, &device_id).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Background task supervision
+//!
+//! Long-running tasks (listeners, sync loops, GC) are spawned through the
+//! `Supervisor`, which hands each one a cancellation token and watches it.
+//! A panicking task is reported as `Event::TaskFailed` instead of dying
+//! silently. `shutdown` cancels every task and waits for it to finish,
+//! aborting those that overrun the grace period.
+
+use std::any::Any;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use nomade_events::{Event, EventStream};
+use tokio::runtime::Handle;
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::{CoreError, Result};
+
+struct SupervisedTask {
+    name: String,
+    abort: AbortHandle,
+    watcher: JoinHandle<()>,
+}
+
+/// Spawns and tracks background tasks
+pub struct Supervisor {
+    handle: Handle,
+    events: EventStream,
+    token: CancellationToken,
+    tasks: Mutex<Vec<SupervisedTask>>,
+}
+
+impl Supervisor {
+    /// Create supervisor spawning onto the given runtime
+    pub fn new(handle: Handle, events: EventStream) -> Self {
+        Self {
+            handle,
+            events,
+            token: CancellationToken::new(),
+            tasks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Spawn a supervised task
+    ///
+    /// The task receives a token cancelled at shutdown and should return
+    /// promptly once it fires.
+    pub fn spawn<F, Fut>(&self, name: impl Into<String>, task: F) -> Result<()>
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if self.token.is_cancelled() {
+            return Err(CoreError::ShuttingDown);
+        }
+        let name = name.into();
+        let inner = self.handle.spawn(task(self.token.child_token()));
+        let abort = inner.abort_handle();
+
+        let events = self.events.clone();
+        let task_name = name.clone();
+        let watcher = self.handle.spawn(async move {
+            match inner.await {
+                Err(e) if e.is_panic() => {
+                    let reason = panic_message(e.into_panic());
+                    tracing::error!("Task {} panicked: {}", task_name, reason);
+                    events.publish(Event::TaskFailed {
+                        task: task_name,
+                        reason,
+                    });
+                }
+                _ => tracing::debug!("Task {} finished", task_name),
+            }
+        });
+
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|t| !t.watcher.is_finished());
+        tasks.push(SupervisedTask {
+            name,
+            abort,
+            watcher,
+        });
+        Ok(())
+    }
+
+    /// Names of tasks still running
+    pub fn running(&self) -> Vec<String> {
+        self.tasks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| !t.watcher.is_finished())
+            .map(|t| t.name.clone())
+            .collect()
+    }
+
+    /// Whether shutdown has started
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Cancel all tasks and wait up to `grace` for them to exit
+    ///
+    /// Returns the number of tasks that had to be aborted.
+    pub async fn shutdown(&self, grace: Duration) -> usize {
+        self.token.cancel();
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let deadline = tokio::time::Instant::now() + grace;
+
+        let mut aborted = 0;
+        for mut task in tasks {
+            if tokio::time::timeout_at(deadline, &mut task.watcher)
+                .await
+                .is_err()
+            {
+                tracing::warn!("Task {} ignored cancellation, aborting", task.name);
+                task.abort.abort();
+                let _ = task.watcher.await;
+                aborted += 1;
+            }
+        }
+        aborted
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panic_reported_as_event() {
+        let events = EventStream::new();
+        let mut rx = events.subscribe();
+        let supervisor = Supervisor::new(Handle::current(), events);
+
+        supervisor
+            .spawn("gc", |_| async { panic!("disk on fire") })
+            .unwrap();
+        match rx.recv().await.unwrap() {
+            Event::TaskFailed { task, reason } => {
+                assert_eq!(task, "gc");
+                assert_eq!(reason, "disk on fire");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_and_aborts() {
+        let supervisor = Supervisor::new(Handle::current(), EventStream::new());
+        supervisor
+            .spawn("listener", |token| async move { token.cancelled().await })
+            .unwrap();
+        supervisor
+            .spawn("stubborn", |_| std::future::pending())
+            .unwrap();
+        assert_eq!(supervisor.running().len(), 2);
+
+        assert_eq!(supervisor.shutdown(Duration::from_millis(50)).await, 1);
+        assert!(supervisor.running().is_empty());
+        assert!(matches!(
+            supervisor.spawn("late", |_| async {}),
+            Err(CoreError::ShuttingDown)
+        ));
+    }
+}
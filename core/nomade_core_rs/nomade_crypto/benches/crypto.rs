@@ -0,0 +1,27 @@
+//! AES-256-GCM throughput for message and chunk sizes
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nomade_crypto::{decrypt_data, encrypt_data};
+
+/// A small message, one sync chunk, and a large artifact
+const SIZES: [usize; 3] = [1024, 64 * 1024, 1024 * 1024];
+
+fn aes_gcm(c: &mut Criterion) {
+    let key = [7u8; 32];
+    let mut group = c.benchmark_group("aes_gcm");
+    for size in SIZES {
+        let plaintext = vec![0x5a; size];
+        let encrypted = encrypt_data(&plaintext, &key).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt", size), &plaintext, |b, data| {
+            b.iter(|| encrypt_data(data, &key).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("decrypt", size), &encrypted, |b, data| {
+            b.iter(|| decrypt_data(data, &key).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aes_gcm);
+criterion_main!(benches);
@@ -0,0 +1,361 @@
+//! Identity attestations and cross-signing
+//!
+//! A device publishes an `Attestation`: a statement, signed with its own
+//! key, that the key belongs to a named device of a user. Once someone has
+//! checked in person that the statement is right (for example by comparing
+//! the device ID on both screens), their devices countersign it. Peers
+//! verify every signature and the expiry before storing a statement.
+//!
+//! `AttestationChain` links statements into "who vouched for whom": a
+//! device is vouched for by the root if the root countersigned it, or
+//! countersigned a device that did, and so on.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::{CryptoError, DeviceId, DeviceKeypair, Result};
+
+/// Signature of another device vouching for an attestation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Countersignature {
+    pub signer: DeviceId,
+    pub public_key: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub signed_at: u64,
+    pub signature: Vec<u8>,
+}
+
+/// Signed statement "key K belongs to device N of user U at time T"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub device_id: DeviceId,
+    pub device_name: String,
+    pub user: String,
+    pub public_key: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    pub expires_at: u64,
+    /// Self-signature by `public_key`
+    pub signature: Vec<u8>,
+    #[serde(default)]
+    pub countersignatures: Vec<Countersignature>,
+}
+
+impl Attestation {
+    /// Sign a statement about `keypair`, valid for `ttl`
+    pub fn new(
+        keypair: &DeviceKeypair,
+        device_name: String,
+        user: String,
+        ttl: Duration,
+    ) -> Result<Self> {
+        let issued_at = unix_time();
+        let mut attestation = Self {
+            device_id: keypair.device_id().clone(),
+            device_name,
+            user,
+            public_key: keypair.public_key_bytes(),
+            issued_at,
+            expires_at: issued_at + ttl.as_secs(),
+            signature: vec![],
+            countersignatures: vec![],
+        };
+        attestation.signature = keypair
+            .sign(&attestation.signing_payload())?
+            .to_bytes()
+            .to_vec();
+        Ok(attestation)
+    }
+
+    /// Payload signed by the device and its countersigners
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-attestation-v1");
+        payload.extend_from_slice(self.device_id.0.as_bytes());
+        for field in [&self.device_name, &self.user] {
+            payload.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            payload.extend_from_slice(field.as_bytes());
+        }
+        payload.extend_from_slice(&self.public_key);
+        payload.extend_from_slice(&self.issued_at.to_le_bytes());
+        payload.extend_from_slice(&self.expires_at.to_le_bytes());
+        payload
+    }
+
+    fn countersigning_payload(&self, signed_at: u64) -> Vec<u8> {
+        let mut payload = b"nomade-countersign-v1".to_vec();
+        payload.extend_from_slice(&self.signing_payload());
+        payload.extend_from_slice(&signed_at.to_le_bytes());
+        payload
+    }
+
+    /// Whether the statement is past its expiry
+    pub fn is_expired(&self) -> bool {
+        unix_time() >= self.expires_at
+    }
+
+    /// Check the self-signature, every countersignature and the expiry
+    pub fn verify(&self) -> Result<()> {
+        let key = verifying_key(&self.public_key)?;
+        if DeviceId::from_public_key(&key) != self.device_id {
+            return Err(CryptoError::InvalidAttestation(
+                "Key does not match the device ID".into(),
+            ));
+        }
+        check_signature(&key, &self.signing_payload(), &self.signature)?;
+        for countersignature in &self.countersignatures {
+            let key = verifying_key(&countersignature.public_key)?;
+            if DeviceId::from_public_key(&key) != countersignature.signer {
+                return Err(CryptoError::InvalidAttestation(format!(
+                    "Countersigner key does not match {}",
+                    countersignature.signer
+                )));
+            }
+            check_signature(
+                &key,
+                &self.countersigning_payload(countersignature.signed_at),
+                &countersignature.signature,
+            )?;
+        }
+        if self.is_expired() {
+            return Err(CryptoError::AttestationExpired);
+        }
+        Ok(())
+    }
+
+    /// Vouch for the statement after checking it in person
+    ///
+    /// Replaces an earlier countersignature by the same device.
+    pub fn countersign(&mut self, signer: &DeviceKeypair) -> Result<()> {
+        self.verify()?;
+        if signer.device_id() == &self.device_id {
+            return Err(CryptoError::InvalidAttestation(
+                "A device cannot vouch for itself".into(),
+            ));
+        }
+        let signed_at = unix_time();
+        let signature = signer.sign(&self.countersigning_payload(signed_at))?;
+        self.countersignatures
+            .retain(|c| &c.signer != signer.device_id());
+        self.countersignatures.push(Countersignature {
+            signer: signer.device_id().clone(),
+            public_key: signer.public_key_bytes(),
+            signed_at,
+            signature: signature.to_bytes().to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Devices that countersigned the statement
+    pub fn vouchers(&self) -> impl Iterator<Item = &DeviceId> {
+        self.countersignatures.iter().map(|c| &c.signer)
+    }
+
+    /// Add countersignatures from another copy of the same statement
+    ///
+    /// Both copies must be verified. Returns whether anything was added.
+    pub fn merge(&mut self, other: &Attestation) -> bool {
+        if self.signing_payload() != other.signing_payload() {
+            return false;
+        }
+        let mut changed = false;
+        for countersignature in &other.countersignatures {
+            match self
+                .countersignatures
+                .iter_mut()
+                .find(|c| c.signer == countersignature.signer)
+            {
+                Some(existing) if existing.signed_at >= countersignature.signed_at => {}
+                Some(existing) => {
+                    *existing = countersignature.clone();
+                    changed = true;
+                }
+                None => {
+                    self.countersignatures.push(countersignature.clone());
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Who vouched for whom, as seen from one device
+#[derive(Debug, Clone)]
+pub struct AttestationChain {
+    root: DeviceId,
+    attestations: HashMap<DeviceId, Attestation>,
+}
+
+impl AttestationChain {
+    /// Start a chain vouched for by `root`, normally this device
+    pub fn new(root: DeviceId) -> Self {
+        Self {
+            root,
+            attestations: HashMap::new(),
+        }
+    }
+
+    /// Add a statement; invalid and expired ones are skipped
+    pub fn with(mut self, attestation: Attestation) -> Self {
+        match attestation.verify() {
+            Ok(()) => {
+                self.attestations
+                    .insert(attestation.device_id.clone(), attestation);
+            }
+            Err(e) => tracing::debug!("Skipping attestation of {}: {}", attestation.device_id, e),
+        }
+        self
+    }
+
+    /// Add several statements
+    pub fn with_all(self, attestations: impl IntoIterator<Item = Attestation>) -> Self {
+        attestations.into_iter().fold(self, Self::with)
+    }
+
+    /// Statement of `device_id`, if added and valid
+    pub fn get(&self, device_id: &DeviceId) -> Option<&Attestation> {
+        self.attestations.get(device_id)
+    }
+
+    /// Shortest chain of vouches from the root to `device_id`
+    ///
+    /// Starts with the root and ends with `device_id`; `None` if no chain
+    /// of countersignatures connects them.
+    pub fn path(&self, device_id: &DeviceId) -> Option<Vec<DeviceId>> {
+        // Who each device vouched for
+        let mut vouched: HashMap<&DeviceId, Vec<&DeviceId>> = HashMap::new();
+        for attestation in self.attestations.values() {
+            for voucher in attestation.vouchers() {
+                vouched
+                    .entry(voucher)
+                    .or_default()
+                    .push(&attestation.device_id);
+            }
+        }
+
+        let mut previous: HashMap<&DeviceId, &DeviceId> = HashMap::new();
+        let mut seen = HashSet::from([&self.root]);
+        let mut queue = VecDeque::from([&self.root]);
+        while let Some(current) = queue.pop_front() {
+            if current == device_id {
+                let mut path = vec![current.clone()];
+                let mut at = current;
+                while let Some(prev) = previous.get(at) {
+                    path.push((*prev).clone());
+                    at = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next in vouched.get(current).into_iter().flatten() {
+                if seen.insert(next) {
+                    previous.insert(next, current);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+}
+
+fn verifying_key(public_key: &[u8]) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)
+}
+
+fn check_signature(key: &VerifyingKey, payload: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::from_slice(signature).map_err(|_| CryptoError::InvalidSignature)?;
+    key.verify(payload, &signature)
+        .map_err(|_| CryptoError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn attest(keypair: &DeviceKeypair) -> Attestation {
+        Attestation::new(keypair, "Device".into(), "alice".into(), DAY).unwrap()
+    }
+
+    #[test]
+    fn test_countersigned_attestation_verifies() {
+        let (phone, laptop) = (generate_keypair(), generate_keypair());
+        let mut attestation = attest(&phone);
+        attestation.countersign(&laptop).unwrap();
+        attestation.countersign(&laptop).unwrap();
+        assert!(attestation.verify().is_ok());
+        assert_eq!(
+            attestation.vouchers().collect::<Vec<_>>(),
+            [laptop.device_id()]
+        );
+        assert!(attestation.countersign(&phone).is_err());
+
+        let mut renamed = attestation.clone();
+        renamed.user = "mallory".into();
+        assert!(matches!(
+            renamed.verify(),
+            Err(CryptoError::InvalidSignature)
+        ));
+        let mut forged = attestation.clone();
+        forged.countersignatures[0].signed_at += 1;
+        assert!(forged.verify().is_err());
+
+        let expired =
+            Attestation::new(&phone, "Phone".into(), "alice".into(), Duration::ZERO).unwrap();
+        assert!(matches!(
+            expired.verify(),
+            Err(CryptoError::AttestationExpired)
+        ));
+    }
+
+    #[test]
+    fn test_merge_collects_countersignatures() {
+        let (phone, laptop, tablet) = (generate_keypair(), generate_keypair(), generate_keypair());
+        let original = attest(&phone);
+        let (mut a, mut b) = (original.clone(), original);
+        a.countersign(&laptop).unwrap();
+        b.countersign(&tablet).unwrap();
+        assert!(a.merge(&b));
+        assert!(!a.merge(&b));
+        assert_eq!(a.countersignatures.len(), 2);
+        assert!(a.verify().is_ok());
+        assert!(!a.merge(&attest(&laptop)));
+    }
+
+    #[test]
+    fn test_chain_follows_vouches() {
+        let (me, laptop, phone, stranger) = (
+            generate_keypair(),
+            generate_keypair(),
+            generate_keypair(),
+            generate_keypair(),
+        );
+        let mut laptop_statement = attest(&laptop);
+        laptop_statement.countersign(&me).unwrap();
+        let mut phone_statement = attest(&phone);
+        phone_statement.countersign(&laptop).unwrap();
+        let mut tampered = attest(&stranger);
+        tampered.countersign(&phone).unwrap();
+        tampered.device_name = "Laptop".into();
+
+        let chain = AttestationChain::new(me.device_id().clone()).with_all([
+            laptop_statement,
+            phone_statement,
+            tampered,
+        ]);
+        assert_eq!(
+            chain.path(phone.device_id()).unwrap(),
+            [me.device_id(), laptop.device_id(), phone.device_id()].map(Clone::clone)
+        );
+        assert!(chain.get(stranger.device_id()).is_none());
+        assert!(chain.path(stranger.device_id()).is_none());
+    }
+}
@@ -0,0 +1,57 @@
+//! Time sources that also work in the browser
+//!
+//! `std::time` panics on `wasm32-unknown-unknown`, which has no clock of
+//! its own; there JavaScript's `Date` stands in for both the wall clock
+//! and timers.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod imp {
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    pub fn unix_time() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Stopwatch(Instant);
+
+    impl Stopwatch {
+        pub fn start() -> Self {
+            Self(Instant::now())
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            self.0.elapsed()
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod imp {
+    use std::time::Duration;
+
+    pub fn unix_time() -> u64 {
+        (js_sys::Date::now() / 1000.0) as u64
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Stopwatch(f64);
+
+    impl Stopwatch {
+        pub fn start() -> Self {
+            Self(js_sys::Date::now())
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Duration::from_secs_f64((js_sys::Date::now() - self.0).max(0.0) / 1000.0)
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+pub use imp::unix_time;
+/// Time elapsed since `Stopwatch::start`
+pub(crate) use imp::Stopwatch;
@@ -0,0 +1,27 @@
+//! Constant-time comparisons
+//!
+//! `==` on byte slices returns at the first differing byte, so its timing
+//! tells an attacker how much of a guessed MAC, token or key ID was right.
+//! Anything derived from a secret is compared with `ct_eq` instead.
+
+use subtle::ConstantTimeEq;
+
+/// Whether `a` and `b` are equal, in time depending only on their lengths
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(ct_eq(&[7u8; 32], &[7u8; 32]));
+        assert!(!ct_eq(&[7u8; 32], &[7u8; 31]));
+        let mut other = [7u8; 32];
+        other[31] ^= 1;
+        assert!(!ct_eq(&[7u8; 32], &other));
+    }
+}
@@ -1,12 +1,21 @@
 //! Encryption helpers using AES-256-GCM
 
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
+
+use nomade_metrics::names;
 use serde::{Deserialize, Serialize};
 
-use crate::{CryptoError, Result};
+use crate::clock::Stopwatch;
+use crate::{CryptoError, DecryptError, Result};
+
+/// AES-GCM nonce size in bytes
+pub(crate) const NONCE_SIZE: usize = 12;
+
+/// AES-GCM tag size in bytes
+const TAG_SIZE: usize = 16;
 
 /// Encrypted data with nonce
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,20 +25,41 @@ pub struct EncryptedData {
     pub algorithm: String,
 }
 
-/// Encrypt data with AES-256-GCM
+/// Encrypt data with AES-256-GCM under a random nonce
+///
+/// Suited to keys used for a handful of messages; long-lived keys should
+/// go through a `KeyContext`, which enforces per-key usage limits.
 pub fn encrypt_data(plaintext: &[u8], key: &[u8; 32]) -> Result<EncryptedData> {
-    let cipher = Aes256Gcm::new(key.into());
-
     // Generate random nonce (96 bits for GCM)
-    let mut nonce_bytes = [0u8; 12];
+    let mut nonce = [0u8; NONCE_SIZE];
     use rand::RngCore;
-    rand::thread_rng().fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    seal(plaintext, key, &nonce)
+}
+
+/// Encrypt under a caller-chosen nonce, for known-answer tests only
+#[cfg(test)]
+pub(crate) fn encrypt_with_nonce(
+    plaintext: &[u8],
+    key: &[u8; 32],
+    nonce: &[u8; NONCE_SIZE],
+) -> Result<EncryptedData> {
+    seal(plaintext, key, nonce)
+}
+
+/// Encrypt with a nonce the caller guarantees is unique for `key`
+pub(crate) fn seal(
+    plaintext: &[u8],
+    key: &[u8; 32],
+    nonce: &[u8; NONCE_SIZE],
+) -> Result<EncryptedData> {
+    let started = Stopwatch::start();
+    let cipher = Aes256Gcm::new(key.into());
 
-    // Encrypt
     let ciphertext = cipher
-        .encrypt(nonce, plaintext)
+        .encrypt(Nonce::from_slice(nonce), plaintext)
         .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    record_throughput(plaintext.len(), started);
 
     Ok(EncryptedData {
         ciphertext,
@@ -41,21 +71,81 @@ pub fn encrypt_data(plaintext: &[u8], key: &[u8; 32]) -> Result<EncryptedData> {
 /// Decrypt data with AES-256-GCM
 pub fn decrypt_data(encrypted: &EncryptedData, key: &[u8; 32]) -> Result<Vec<u8>> {
     if encrypted.algorithm != "AES-256-GCM" {
-        return Err(CryptoError::DecryptionFailed(
-            "Unsupported algorithm".into(),
+        return Err(decrypt_error(
+            DecryptError::UnsupportedAlgorithm,
+            &encrypted.algorithm,
         ));
     }
 
-    let cipher = Aes256Gcm::new(key.into());
-    let nonce = Nonce::from_slice(&encrypted.nonce);
+    let started = Stopwatch::start();
+    let plaintext = open_aead(key, &encrypted.nonce, &encrypted.ciphertext, &[])?;
+    record_throughput(plaintext.len(), started);
+    Ok(plaintext)
+}
+
+/// Decrypt and authenticate one AES-256-GCM message
+pub(crate) fn open_aead(
+    key: &[u8; 32],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    if nonce.len() != NONCE_SIZE {
+        return Err(decrypt_error(
+            DecryptError::Truncated,
+            format_args!("nonce of {} bytes", nonce.len()),
+        ));
+    }
+    if ciphertext.len() < TAG_SIZE {
+        return Err(decrypt_error(
+            DecryptError::Truncated,
+            format_args!("ciphertext of {} bytes", ciphertext.len()),
+        ));
+    }
+    Aes256Gcm::new(key.into())
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| {
+            decrypt_error(
+                DecryptError::Tampered,
+                format_args!("tag mismatch on {} bytes", ciphertext.len()),
+            )
+        })
+}
+
+/// Error for a failed decryption, logging `detail` locally only
+pub(crate) fn decrypt_error(reason: DecryptError, detail: impl std::fmt::Display) -> CryptoError {
+    tracing::debug!("Decryption failed ({}): {}", reason, detail);
+    CryptoError::DecryptionFailed(reason)
+}
 
-    cipher
-        .decrypt(nonce, encrypted.ciphertext.as_ref())
-        .map_err(|e| CryptoError::DecryptionFailed(e.to_string()))
+/// Record bytes processed and time taken by an AEAD operation
+fn record_throughput(bytes: usize, started: Stopwatch) {
+    let registry = nomade_metrics::global();
+    registry
+        .counter(
+            names::CRYPTO_BYTES,
+            "Plaintext bytes encrypted or decrypted",
+        )
+        .add(bytes as u64);
+    registry
+        .histogram(
+            names::CRYPTO_SECONDS,
+            "Duration of encrypt/decrypt calls in seconds",
+            nomade_metrics::DEFAULT_BUCKETS,
+        )
+        .observe(started.elapsed().as_secs_f64());
 }
 
 /// Derive key using HKDF-SHA256
-pub fn derive_key(master_key: &[u8], salt: &[u8], info: &[u8]) -> [u8; 32] {
+///
+/// Callers outside this module name a `KeyPurpose` via `derive_for`.
+pub(crate) fn derive_key(master_key: &[u8], salt: &[u8], info: &[u8]) -> [u8; 32] {
     use hkdf::Hkdf;
     use sha2::Sha256;
 
@@ -65,9 +155,18 @@ pub fn derive_key(master_key: &[u8], salt: &[u8], info: &[u8]) -> [u8; 32] {
     okm
 }
 
+/// Short non-secret identifier of a key
+pub fn key_id(key: &[u8; 32]) -> String {
+    blake3::derive_key("nomade key id v1", key)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_encrypt_decrypt() {
@@ -80,6 +179,21 @@ mod tests {
         assert_eq!(plaintext.as_slice(), decrypted.as_slice());
     }
 
+    #[test]
+    fn test_known_answer() {
+        // NIST GCM test case 14: zero key, zero nonce, one zero block
+        let encrypted = encrypt_with_nonce(&[0u8; 16], &[0u8; 32], &[0u8; NONCE_SIZE]).unwrap();
+        let hex: String = encrypted
+            .ciphertext
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            hex,
+            "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919"
+        );
+    }
+
     #[test]
     fn test_derive_key() {
         let master_key = b"master secret key";
@@ -94,4 +208,26 @@ mod tests {
         let key3 = derive_key(master_key, b"different salt", info);
         assert_ne!(key1, key3); // Different salt = different key
     }
+
+    proptest! {
+        #[test]
+        fn encryption_roundtrip(plaintext in prop::collection::vec(any::<u8>(), 0..1024)) {
+            let key = [7u8; 32];
+            let encrypted = encrypt_data(&plaintext, &key).unwrap();
+            prop_assert_eq!(decrypt_data(&encrypted, &key).unwrap(), plaintext);
+        }
+
+        #[test]
+        fn decrypt_never_panics(
+            ciphertext in prop::collection::vec(any::<u8>(), 0..64),
+            nonce in prop::collection::vec(any::<u8>(), 0..24),
+        ) {
+            let encrypted = EncryptedData {
+                ciphertext,
+                nonce,
+                algorithm: "AES-256-GCM".into(),
+            };
+            prop_assert!(decrypt_data(&encrypted, &[0u8; 32]).is_err());
+        }
+    }
 }
@@ -0,0 +1,225 @@
+//! Network endpoints advertised by devices
+//!
+//! Endpoints travel as strings (`192.168.1.10:8765`, `[fd00::1]:8765`,
+//! `laptop.local:8765`, `relay:<id>`) so offers stay readable, but are
+//! parsed into an `Endpoint` on the way in so IPv6 literals, hostnames and
+//! relay addresses are never confused.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::CryptoError;
+
+/// Prefix of a relay endpoint
+const RELAY_PREFIX: &str = "relay:";
+/// Longest hostname accepted (RFC 1035)
+const MAX_HOSTNAME_LEN: usize = 253;
+
+/// Address a peer can be reached at
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Endpoint {
+    DirectV4(SocketAddrV4),
+    DirectV6(SocketAddrV6),
+    Hostname {
+        host: String,
+        port: u16,
+    },
+    /// Reachable only through a relay server
+    Relay {
+        relay_id: String,
+    },
+}
+
+impl Endpoint {
+    /// Direct endpoint for a socket address
+    pub fn direct(addr: SocketAddr) -> Self {
+        match addr {
+            SocketAddr::V4(addr) => Self::DirectV4(addr),
+            SocketAddr::V6(addr) => Self::DirectV6(addr),
+        }
+    }
+
+    /// Socket address of a direct endpoint
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::DirectV4(addr) => Some(SocketAddr::V4(*addr)),
+            Self::DirectV6(addr) => Some(SocketAddr::V6(*addr)),
+            _ => None,
+        }
+    }
+
+    /// Dialing preference; higher is tried first
+    ///
+    /// Private addresses beat public ones (same LAN is likely), IPv4 beats
+    /// IPv6 within each class, then hostnames (need DNS), then relays.
+    pub fn preference(&self) -> u8 {
+        match self {
+            Self::DirectV4(addr) if is_private(&IpAddr::V4(*addr.ip())) => 6,
+            Self::DirectV6(addr) if is_private(&IpAddr::V6(*addr.ip())) => 5,
+            Self::DirectV4(_) => 4,
+            Self::DirectV6(_) => 3,
+            Self::Hostname { .. } => 2,
+            Self::Relay { .. } => 1,
+        }
+    }
+
+    /// Sort endpoints most preferred first, keeping order among equals
+    pub fn sort_by_preference(endpoints: &mut [Endpoint]) {
+        endpoints.sort_by_key(|endpoint| Reverse(endpoint.preference()));
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DirectV4(addr) => write!(f, "{}", addr),
+            Self::DirectV6(addr) => write!(f, "{}", addr),
+            Self::Hostname { host, port } => write!(f, "{}:{}", host, port),
+            Self::Relay { relay_id } => write!(f, "{}{}", RELAY_PREFIX, relay_id),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CryptoError::InvalidEndpoint(s.to_string());
+        if let Some(relay_id) = s.strip_prefix(RELAY_PREFIX) {
+            if relay_id.is_empty() || !relay_id.bytes().all(is_label_byte) {
+                return Err(invalid());
+            }
+            return Ok(Self::Relay {
+                relay_id: relay_id.to_string(),
+            });
+        }
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::direct(addr));
+        }
+
+        let (host, port) = s.rsplit_once(':').ok_or_else(invalid)?;
+        let port = port.parse().map_err(|_| invalid())?;
+        let valid_host = !host.is_empty()
+            && host.len() <= MAX_HOSTNAME_LEN
+            && host.split('.').all(|label| {
+                !label.is_empty() && label.bytes().all(is_label_byte) && !label.starts_with('-')
+            })
+            // All-numeric names are malformed IPv4 literals, not hosts
+            && !host.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+        if !valid_host {
+            return Err(invalid());
+        }
+        Ok(Self::Hostname {
+            host: host.to_ascii_lowercase(),
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = CryptoError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Endpoint> for String {
+    fn from(endpoint: Endpoint) -> Self {
+        endpoint.to_string()
+    }
+}
+
+fn is_label_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-'
+}
+
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        // Unique local (fc00::/7) and link-local (fe80::/10)
+        IpAddr::V6(ip) => {
+            (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        for (input, expected) in [
+            ("192.168.1.10:8765", "192.168.1.10:8765"),
+            ("[fd00::1]:8765", "[fd00::1]:8765"),
+            ("Laptop.local:8765", "laptop.local:8765"),
+            ("relay:eu-1", "relay:eu-1"),
+        ] {
+            let endpoint: Endpoint = input.parse().unwrap();
+            assert_eq!(endpoint.to_string(), expected);
+            assert_eq!(expected.parse::<Endpoint>().unwrap(), endpoint);
+        }
+        assert!(matches!(
+            "[fd00::1]:8765".parse(),
+            Ok(Endpoint::DirectV6(_))
+        ));
+
+        for bad in [
+            "",
+            "fd00::1",
+            "192.168.1.10",
+            "300.1.1.1:80",
+            "host:99999",
+            "bad host:80",
+            "relay:",
+            "-host:80",
+        ] {
+            assert!(bad.parse::<Endpoint>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_serde_as_string() {
+        let endpoint: Endpoint = "[fd00::1]:8765".parse().unwrap();
+        let json = serde_json::to_string(&endpoint).unwrap();
+        assert_eq!(json, "\"[fd00::1]:8765\"");
+        assert_eq!(serde_json::from_str::<Endpoint>(&json).unwrap(), endpoint);
+        assert!(serde_json::from_str::<Endpoint>("\"nope\"").is_err());
+    }
+
+    #[test]
+    fn test_preference_order() {
+        let mut endpoints: Vec<Endpoint> = [
+            "relay:eu-1",
+            "laptop.local:8765",
+            "[2001:db8::1]:8765",
+            "203.0.113.5:8765",
+            "[fd00::1]:8765",
+            "10.0.0.2:8765",
+            "192.168.1.10:8765",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        Endpoint::sort_by_preference(&mut endpoints);
+        let sorted: Vec<String> = endpoints.iter().map(Endpoint::to_string).collect();
+        assert_eq!(
+            sorted,
+            [
+                "10.0.0.2:8765",
+                "192.168.1.10:8765",
+                "[fd00::1]:8765",
+                "203.0.113.5:8765",
+                "[2001:db8::1]:8765",
+                "laptop.local:8765",
+                "relay:eu-1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Signed and encrypted envelopes
+//!
+//! A `SignedEnvelope` carries a payload encrypted (AES-256-GCM) under a key
+//! shared with the recipient, plus the sender device's Ed25519 signature
+//! over the header and ciphertext. The header is also bound to the
+//! ciphertext as associated data, so neither can be swapped; the signature
+//! attributes the message to one device rather than to anyone holding the
+//! shared key.
+//!
+//! Links seal sync and event frames in envelopes, sent in the compact
+//! `to_bytes` encoding. Messages that must be signed on their own
+//! (revocations, attestations, wipe commands, rosters) carry their own
+//! signatures.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::encryption::{decrypt_error, open_aead, NONCE_SIZE};
+use crate::{CryptoError, DecryptError, DeviceId, DeviceKeypair, Result};
+
+/// Encrypted payload signed by its sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedEnvelope {
+    pub sender: DeviceId,
+    pub recipient: DeviceId,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl SignedEnvelope {
+    /// Encrypt `plaintext` for `recipient` and sign it
+    pub fn seal(
+        signer: &DeviceKeypair,
+        recipient: &DeviceId,
+        key: &[u8; 32],
+        plaintext: &[u8],
+    ) -> Result<Self> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut envelope = Self {
+            sender: signer.device_id().clone(),
+            recipient: recipient.clone(),
+            timestamp: unix_time(),
+            nonce: nonce.to_vec(),
+            ciphertext: vec![],
+            signature: vec![],
+        };
+        envelope.ciphertext = Aes256Gcm::new(key.into())
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &envelope.header(),
+                },
+            )
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+        envelope.signature = signer
+            .sign(&envelope.signing_payload())?
+            .to_bytes()
+            .to_vec();
+        Ok(envelope)
+    }
+
+    /// Length-prefixed sender, recipient and timestamp
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(b"nomade-envelope-v1");
+        for id in [&self.sender, &self.recipient] {
+            header.extend_from_slice(&(id.0.len() as u32).to_le_bytes());
+            header.extend_from_slice(id.0.as_bytes());
+        }
+        header.extend_from_slice(&self.timestamp.to_le_bytes());
+        header
+    }
+
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = self.header();
+        payload.extend_from_slice(&self.nonce);
+        payload.extend_from_slice(&self.ciphertext);
+        payload
+    }
+
+    /// Compact binary encoding: length-prefixed sender, recipient, nonce
+    /// and signature, the timestamp, then the ciphertext
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.ciphertext.len() + 256);
+        for field in [
+            self.sender.0.as_bytes(),
+            self.recipient.0.as_bytes(),
+            &self.nonce,
+            &self.signature,
+        ] {
+            bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(field);
+        }
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    /// Decode an envelope written with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let truncated = || decrypt_error(DecryptError::Truncated, "short envelope");
+        let mut rest = bytes;
+        let mut field = || -> Result<Vec<u8>> {
+            let (len, tail) = rest.split_first_chunk::<4>().ok_or_else(truncated)?;
+            let len = u32::from_le_bytes(*len) as usize;
+            if tail.len() < len {
+                return Err(truncated());
+            }
+            let (field, tail) = tail.split_at(len);
+            rest = tail;
+            Ok(field.to_vec())
+        };
+        let id = |bytes: Vec<u8>| {
+            String::from_utf8(bytes)
+                .map(DeviceId)
+                .map_err(|_| decrypt_error(DecryptError::Tampered, "device ID is not UTF-8"))
+        };
+        let sender = id(field()?)?;
+        let recipient = id(field()?)?;
+        let nonce = field()?;
+        let signature = field()?;
+        let (timestamp, ciphertext) = rest.split_first_chunk::<8>().ok_or_else(truncated)?;
+        Ok(Self {
+            sender,
+            recipient,
+            timestamp: u64::from_le_bytes(*timestamp),
+            nonce,
+            ciphertext: ciphertext.to_vec(),
+            signature,
+        })
+    }
+
+    /// Verify signature with the sending device's public key
+    pub fn verify(&self, public_key: &[u8]) -> Result<()> {
+        let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)?;
+        if DeviceId::from_public_key(&key) != self.sender {
+            return Err(CryptoError::InvalidSignature);
+        }
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::InvalidSignature)?;
+        key.verify(&self.signing_payload(), &signature)
+            .map_err(|_| CryptoError::InvalidSignature)
+    }
+
+    /// Verify the sender, then decrypt a payload addressed to `recipient`
+    pub fn open(
+        &self,
+        sender_public_key: &[u8],
+        recipient: &DeviceId,
+        key: &[u8; 32],
+    ) -> Result<Vec<u8>> {
+        self.verify(sender_public_key)?;
+        if self.recipient != *recipient {
+            return Err(decrypt_error(
+                DecryptError::WrongKey,
+                format_args!("envelope addressed to {}", self.recipient),
+            ));
+        }
+        open_aead(key, &self.nonce, &self.ciphertext, &self.header())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn test_seal_and_open() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let key = [3u8; 32];
+
+        let envelope = SignedEnvelope::seal(&laptop, phone.device_id(), &key, b"manifest").unwrap();
+        let public_key = laptop.public_key_bytes();
+        assert_eq!(
+            envelope.open(&public_key, phone.device_id(), &key).unwrap(),
+            b"manifest"
+        );
+
+        // Wrong sender key, wrong recipient, and tampering are all rejected
+        assert!(matches!(
+            envelope.open(&phone.public_key_bytes(), phone.device_id(), &key),
+            Err(CryptoError::InvalidSignature)
+        ));
+        assert!(envelope
+            .open(&public_key, laptop.device_id(), &key)
+            .is_err());
+        let mut tampered = envelope.clone();
+        tampered.timestamp += 1;
+        assert!(matches!(
+            tampered.open(&public_key, phone.device_id(), &key),
+            Err(CryptoError::InvalidSignature)
+        ));
+
+        // A key holder other than the sender cannot forge the signature
+        let mut forged = SignedEnvelope::seal(&phone, phone.device_id(), &key, b"fake").unwrap();
+        forged.sender = laptop.device_id().clone();
+        assert!(forged.open(&public_key, phone.device_id(), &key).is_err());
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let envelope =
+            SignedEnvelope::seal(&laptop, phone.device_id(), &[3; 32], b"event").unwrap();
+
+        let bytes = envelope.to_bytes();
+        assert_eq!(SignedEnvelope::from_bytes(&bytes).unwrap(), envelope);
+        for len in [0, 3, 20, bytes.len() - envelope.ciphertext.len() - 1] {
+            assert!(SignedEnvelope::from_bytes(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Crash-safe file writes shared by the stores of every crate
+//!
+//! A store saved by writing a temporary file and renaming it over the old
+//! one is atomic, but not durable: until the data and the rename reach the
+//! disk, a power loss can leave the old file, an empty one, or none. Writes
+//! here sync the temporary file before the rename and the directory after.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use rand::RngCore;
+
+/// Replace the file at `path` with `data`, durably
+///
+/// A crash leaves either the old or the new contents. The temporary file is
+/// uniquely named, so concurrent writers never share it.
+pub fn write_durable(path: &Path, data: &[u8]) -> io::Result<()> {
+    replace(path, data, OpenOptions::new())
+}
+
+/// Like [`write_durable`], for files only the owner may read
+///
+/// The temporary file is created owner-only on unix, so the data is never
+/// readable by others, not even before the rename.
+pub fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    replace(path, data, options)
+}
+
+fn replace(path: &Path, data: &[u8], mut options: OpenOptions) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp{:016x}", rand::thread_rng().next_u64()));
+    let written = options
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&tmp, path));
+    if let Err(e) = written {
+        std::fs::remove_file(&tmp).ok();
+        return Err(e);
+    }
+    sync_parent(path)
+}
+
+/// Sync the directory holding `path`, so a rename or removal in it lasts
+pub fn sync_parent(path: &Path) -> io::Result<()> {
+    // Directories cannot be opened as files on Windows
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_durable_replaces_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        write_durable(&path, b"old").unwrap();
+        write_durable(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // No temporary file is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let missing = dir.path().join("missing").join("state.json");
+        assert!(write_durable(&missing, b"data").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret");
+        write_private(&path, b"secret").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,392 @@
+//! Multi-device group membership
+//!
+//! A `DeviceGroup` is defined by a signed roster: an append-only, hash-chained
+//! log of add/remove operations. Every entry is signed by a device that is a
+//! member at that point in the log, so a device added once by any member is
+//! trusted by all members after the roster syncs.
+
+use std::collections::HashMap;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::{CryptoError, DeviceId, DeviceKeypair, Result};
+
+/// Group member
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub device_id: DeviceId,
+    pub device_name: String,
+    pub public_key: Vec<u8>,
+}
+
+/// Roster operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RosterOp {
+    Add(Member),
+    Remove { device_id: DeviceId },
+}
+
+/// Signed roster log entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RosterEntry {
+    /// Position in the log, starting at 0 for the genesis entry
+    pub seq: u64,
+    /// Hash of the previous entry (all zeros for genesis)
+    pub prev_hash: [u8; 32],
+    pub op: RosterOp,
+    pub signer: DeviceId,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+impl RosterEntry {
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Result<Vec<u8>> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-roster-v1");
+        payload.extend_from_slice(&self.seq.to_le_bytes());
+        payload.extend_from_slice(&self.prev_hash);
+        payload.extend_from_slice(&serde_json::to_vec(&self.op)?);
+        payload.extend_from_slice(self.signer.0.as_bytes());
+        payload.extend_from_slice(&self.timestamp.to_le_bytes());
+        Ok(payload)
+    }
+
+    /// Hash chaining this entry to the next one
+    pub fn hash(&self) -> Result<[u8; 32]> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.signing_payload()?);
+        hasher.update(&self.signature);
+        Ok(*hasher.finalize().as_bytes())
+    }
+}
+
+/// Group of devices sharing a signed roster
+#[derive(Debug, Clone)]
+pub struct DeviceGroup {
+    group_id: String,
+    entries: Vec<RosterEntry>,
+    members: HashMap<DeviceId, Member>,
+}
+
+impl DeviceGroup {
+    /// Create a new group with `founder` as the only member
+    pub fn create(founder: &DeviceKeypair, device_name: String) -> Result<Self> {
+        let genesis = sign_entry(
+            founder,
+            0,
+            [0u8; 32],
+            RosterOp::Add(Member {
+                device_id: founder.device_id().clone(),
+                device_name,
+                public_key: founder.public_key_bytes(),
+            }),
+        )?;
+        Self::from_entries(vec![genesis])
+    }
+
+    /// Rebuild a group from a full roster, verifying every entry
+    pub fn from_entries(entries: Vec<RosterEntry>) -> Result<Self> {
+        let genesis = entries
+            .first()
+            .ok_or_else(|| CryptoError::InvalidRoster("Empty roster".into()))?;
+        let group_id = format!("group-{}", hex(&genesis.hash()?));
+
+        let mut group = Self {
+            group_id,
+            entries: Vec::with_capacity(entries.len()),
+            members: HashMap::new(),
+        };
+        for entry in entries {
+            group.apply(entry)?;
+        }
+        Ok(group)
+    }
+
+    /// Group identifier (derived from the genesis entry)
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    /// Whether the device is currently a member
+    pub fn is_member(&self, device_id: &DeviceId) -> bool {
+        self.members.contains_key(device_id)
+    }
+
+    /// Current member
+    pub fn member(&self, device_id: &DeviceId) -> Option<&Member> {
+        self.members.get(device_id)
+    }
+
+    /// Current members
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values()
+    }
+
+    /// Full roster log
+    pub fn entries(&self) -> &[RosterEntry] {
+        &self.entries
+    }
+
+    /// Entries after `seq`, for syncing to a peer that has the log up to `seq`
+    pub fn entries_since(&self, seq: u64) -> &[RosterEntry] {
+        let start = (seq as usize + 1).min(self.entries.len());
+        &self.entries[start..]
+    }
+
+    /// Sequence number of the latest entry
+    pub fn head_seq(&self) -> u64 {
+        self.entries.len() as u64 - 1
+    }
+
+    /// Add a device, signed by an existing member
+    pub fn add_device(&mut self, signer: &DeviceKeypair, member: Member) -> Result<RosterEntry> {
+        self.append(signer, RosterOp::Add(member))
+    }
+
+    /// Remove a device, signed by an existing member
+    pub fn remove_device(
+        &mut self,
+        signer: &DeviceKeypair,
+        device_id: DeviceId,
+    ) -> Result<RosterEntry> {
+        self.append(signer, RosterOp::Remove { device_id })
+    }
+
+    /// Apply entries received from a peer, returning how many were new
+    ///
+    /// Entries already present are skipped; a different entry at a known
+    /// position means the rosters diverged and is rejected.
+    pub fn merge(&mut self, entries: &[RosterEntry]) -> Result<usize> {
+        let mut applied = 0;
+        for entry in entries {
+            match self.entries.get(entry.seq as usize) {
+                Some(existing) if existing == entry => continue,
+                Some(_) => {
+                    return Err(CryptoError::InvalidRoster(format!(
+                        "Conflicting roster entry at seq {}",
+                        entry.seq
+                    )))
+                }
+                None => {
+                    self.apply(entry.clone())?;
+                    applied += 1;
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    fn append(&mut self, signer: &DeviceKeypair, op: RosterOp) -> Result<RosterEntry> {
+        let prev_hash = self.entries.last().expect("roster has genesis").hash()?;
+        let entry = sign_entry(signer, self.entries.len() as u64, prev_hash, op)?;
+        self.apply(entry.clone())?;
+        Ok(entry)
+    }
+
+    /// Verify and apply the next entry
+    fn apply(&mut self, entry: RosterEntry) -> Result<()> {
+        if entry.seq != self.entries.len() as u64 {
+            return Err(CryptoError::InvalidRoster(format!(
+                "Expected seq {}, got {}",
+                self.entries.len(),
+                entry.seq
+            )));
+        }
+        let expected_prev = match self.entries.last() {
+            Some(prev) => prev.hash()?,
+            None => [0u8; 32],
+        };
+        if entry.prev_hash != expected_prev {
+            return Err(CryptoError::InvalidRoster("Broken hash chain".into()));
+        }
+
+        // Genesis is self-signed by the founder; later entries by a member
+        let signer_key = match (&entry.op, self.entries.is_empty()) {
+            (RosterOp::Add(member), true) if member.device_id == entry.signer => {
+                member.public_key.clone()
+            }
+            (_, true) => {
+                return Err(CryptoError::InvalidRoster(
+                    "Genesis must add its signer".into(),
+                ))
+            }
+            (_, false) => self
+                .members
+                .get(&entry.signer)
+                .ok_or_else(|| CryptoError::InvalidRoster("Signer is not a member".into()))?
+                .public_key
+                .clone(),
+        };
+        verify_entry(&entry, &signer_key)?;
+
+        match &entry.op {
+            RosterOp::Add(member) => {
+                check_member_key(member)?;
+                self.members
+                    .insert(member.device_id.clone(), member.clone());
+            }
+            RosterOp::Remove { device_id } => {
+                if !self.members.contains_key(device_id) {
+                    return Err(CryptoError::InvalidRoster(format!(
+                        "{} is not a member",
+                        device_id
+                    )));
+                }
+                if self.members.len() == 1 {
+                    return Err(CryptoError::InvalidRoster(
+                        "Cannot remove the last member".into(),
+                    ));
+                }
+                self.members.remove(device_id);
+            }
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+// Helper functions
+
+fn sign_entry(
+    signer: &DeviceKeypair,
+    seq: u64,
+    prev_hash: [u8; 32],
+    op: RosterOp,
+) -> Result<RosterEntry> {
+    let mut entry = RosterEntry {
+        seq,
+        prev_hash,
+        op,
+        signer: signer.device_id().clone(),
+        timestamp: unix_time(),
+        signature: vec![],
+    };
+    entry.signature = signer.sign(&entry.signing_payload()?)?.to_bytes().to_vec();
+    Ok(entry)
+}
+
+fn verify_entry(entry: &RosterEntry, public_key: &[u8]) -> Result<()> {
+    let key = verifying_key(public_key)?;
+    let signature =
+        Signature::from_slice(&entry.signature).map_err(|_| CryptoError::InvalidSignature)?;
+    key.verify(&entry.signing_payload()?, &signature)
+        .map_err(|_| CryptoError::InvalidSignature)
+}
+
+fn check_member_key(member: &Member) -> Result<()> {
+    let key = verifying_key(&member.public_key)?;
+    if DeviceId::from_public_key(&key) != member.device_id {
+        return Err(CryptoError::InvalidRoster(
+            "Device ID does not match public key".into(),
+        ));
+    }
+    Ok(())
+}
+
+fn verifying_key(public_key: &[u8]) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    fn member(keypair: &DeviceKeypair, name: &str) -> Member {
+        Member {
+            device_id: keypair.device_id().clone(),
+            device_name: name.into(),
+            public_key: keypair.public_key_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_third_device_trusted_by_all_members() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let tablet = generate_keypair();
+
+        let mut laptop_group = DeviceGroup::create(&laptop, "Laptop".into()).unwrap();
+        laptop_group
+            .add_device(&laptop, member(&phone, "Phone"))
+            .unwrap();
+
+        // Phone receives the roster, then adds the tablet
+        let mut phone_group = DeviceGroup::from_entries(laptop_group.entries().to_vec()).unwrap();
+        assert_eq!(phone_group.group_id(), laptop_group.group_id());
+        phone_group
+            .add_device(&phone, member(&tablet, "Tablet"))
+            .unwrap();
+
+        // Laptop syncs the new entries and now trusts the tablet too
+        let new_entries = phone_group.entries_since(laptop_group.head_seq());
+        assert_eq!(laptop_group.merge(new_entries).unwrap(), 1);
+        assert!(laptop_group.is_member(tablet.device_id()));
+        assert_eq!(laptop_group.members().count(), 3);
+    }
+
+    #[test]
+    fn test_non_member_cannot_sign() {
+        let laptop = generate_keypair();
+        let outsider = generate_keypair();
+
+        let mut group = DeviceGroup::create(&laptop, "Laptop".into()).unwrap();
+        let result = group.add_device(&outsider, member(&outsider, "Outsider"));
+        assert!(matches!(result, Err(CryptoError::InvalidRoster(_))));
+        assert!(!group.is_member(outsider.device_id()));
+    }
+
+    #[test]
+    fn test_remove_device() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+
+        let mut group = DeviceGroup::create(&laptop, "Laptop".into()).unwrap();
+        group.add_device(&laptop, member(&phone, "Phone")).unwrap();
+        group
+            .remove_device(&laptop, phone.device_id().clone())
+            .unwrap();
+        assert!(!group.is_member(phone.device_id()));
+
+        // Removed device can no longer sign roster changes
+        assert!(group.add_device(&phone, member(&phone, "Phone")).is_err());
+        // Last member cannot be removed
+        assert!(group
+            .remove_device(&laptop, laptop.device_id().clone())
+            .is_err());
+    }
+
+    #[test]
+    fn test_reject_tampered_and_conflicting_entries() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let tablet = generate_keypair();
+
+        let mut group = DeviceGroup::create(&laptop, "Laptop".into()).unwrap();
+        group.add_device(&laptop, member(&phone, "Phone")).unwrap();
+
+        let mut tampered = group.entries().to_vec();
+        if let RosterOp::Add(m) = &mut tampered[1].op {
+            m.device_name = "Evil".into();
+        }
+        assert!(DeviceGroup::from_entries(tampered).is_err());
+
+        // Two members appending concurrently produce conflicting logs
+        let mut other = DeviceGroup::from_entries(group.entries().to_vec()).unwrap();
+        group
+            .add_device(&laptop, member(&tablet, "Tablet"))
+            .unwrap();
+        other
+            .remove_device(&phone, phone.device_id().clone())
+            .unwrap();
+        assert!(group.merge(other.entries()).is_err());
+    }
+}
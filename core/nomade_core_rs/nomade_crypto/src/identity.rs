@@ -1,5 +1,7 @@
 //! Device identity management
 
+use std::sync::Arc;
+
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
@@ -24,10 +26,28 @@ impl std::fmt::Display for DeviceId {
     }
 }
 
+/// Signer keeping the identity key outside Rust memory
+///
+/// Implemented over platform keystores (Secure Enclave, StrongBox) that
+/// hold an Ed25519 key and only expose signing.
+pub trait SigningProvider: Send + Sync {
+    /// Ed25519 public key of the held key
+    fn public_key(&self) -> [u8; 32];
+
+    /// Ed25519 signature over `message`
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>>;
+}
+
+#[derive(Clone)]
+enum SecretKey {
+    Software(Box<SigningKey>),
+    Provider(Arc<dyn SigningProvider>),
+}
+
 /// Device keypair for identity and signing
 #[derive(Clone)]
 pub struct DeviceKeypair {
-    signing_key: SigningKey,
+    secret: SecretKey,
     verifying_key: VerifyingKey,
     device_id: DeviceId,
 }
@@ -38,12 +58,29 @@ impl DeviceKeypair {
         let verifying_key = signing_key.verifying_key();
         let device_id = DeviceId::from_public_key(&verifying_key);
         Self {
-            signing_key,
+            secret: SecretKey::Software(Box::new(signing_key)),
             verifying_key,
             device_id,
         }
     }
 
+    /// Keypair whose secret key is held by a platform provider
+    pub fn from_provider(provider: Arc<dyn SigningProvider>) -> Result<Self> {
+        let verifying_key = VerifyingKey::from_bytes(&provider.public_key())
+            .map_err(|_| CryptoError::InvalidKey)?;
+        let device_id = DeviceId::from_public_key(&verifying_key);
+        Ok(Self {
+            secret: SecretKey::Provider(provider),
+            verifying_key,
+            device_id,
+        })
+    }
+
+    /// Whether the secret key lives outside Rust
+    pub fn is_hardware_backed(&self) -> bool {
+        matches!(self.secret, SecretKey::Provider(_))
+    }
+
     /// Get device ID
     pub fn device_id(&self) -> &DeviceId {
         &self.device_id
@@ -55,8 +92,18 @@ impl DeviceKeypair {
     }
 
     /// Sign message
-    pub fn sign(&self, message: &[u8]) -> Signature {
-        self.signing_key.sign(message)
+    ///
+    /// Signatures from a provider are verified before being returned.
+    pub fn sign(&self, message: &[u8]) -> Result<Signature> {
+        match &self.secret {
+            SecretKey::Software(key) => Ok(key.sign(message)),
+            SecretKey::Provider(provider) => {
+                let signature = Signature::from_slice(&provider.sign(message)?)
+                    .map_err(|_| CryptoError::InvalidSignature)?;
+                self.verify(message, &signature)?;
+                Ok(signature)
+            }
+        }
     }
 
     /// Verify signature
@@ -71,9 +118,25 @@ impl DeviceKeypair {
         self.verifying_key.as_bytes().to_vec()
     }
 
+    /// Signing key, for key agreement inside this crate
+    pub(crate) fn signing_key(&self) -> Result<&SigningKey> {
+        match &self.secret {
+            SecretKey::Software(key) => Ok(key),
+            SecretKey::Provider(_) => Err(CryptoError::HardwareBacked),
+        }
+    }
+
     /// Serialize secret key to bytes (use carefully!)
-    pub fn secret_key_bytes(&self) -> Vec<u8> {
-        self.signing_key.to_bytes().to_vec()
+    ///
+    /// `None` if the key is hardware-backed.
+    pub fn secret_key_bytes(&self) -> Option<Vec<u8>> {
+        self.signing_key().ok().map(|key| key.to_bytes().to_vec())
+    }
+
+    /// Restore a software keypair from its secret bytes
+    pub fn from_secret_bytes(secret: &[u8]) -> Result<Self> {
+        let secret: [u8; 32] = secret.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        Ok(Self::new(SigningKey::from_bytes(&secret)))
     }
 }
 
@@ -102,10 +165,54 @@ mod tests {
         let keypair = generate_keypair();
         let message = b"Hello, Nomade!";
 
-        let signature = keypair.sign(message);
+        let signature = keypair.sign(message).unwrap();
         assert!(keypair.verify(message, &signature).is_ok());
 
         let wrong_message = b"Wrong message";
         assert!(keypair.verify(wrong_message, &signature).is_err());
     }
+
+    /// Provider backed by a software key, optionally returning bad signatures
+    struct TestProvider {
+        key: SigningKey,
+        corrupt: bool,
+    }
+
+    impl SigningProvider for TestProvider {
+        fn public_key(&self) -> [u8; 32] {
+            self.key.verifying_key().to_bytes()
+        }
+
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+            let mut signature = self.key.sign(message).to_bytes().to_vec();
+            if self.corrupt {
+                signature[0] ^= 1;
+            }
+            Ok(signature)
+        }
+    }
+
+    #[test]
+    fn test_provider_backed_keypair() {
+        let software = generate_keypair();
+        let key = SigningKey::from_bytes(&software.secret_key_bytes().unwrap().try_into().unwrap());
+        let keypair = DeviceKeypair::from_provider(Arc::new(TestProvider {
+            key: key.clone(),
+            corrupt: false,
+        }))
+        .unwrap();
+
+        assert!(keypair.is_hardware_backed());
+        assert_eq!(keypair.device_id(), software.device_id());
+        assert!(keypair.secret_key_bytes().is_none());
+        let signature = keypair.sign(b"roster").unwrap();
+        assert!(software.verify(b"roster", &signature).is_ok());
+
+        let faulty =
+            DeviceKeypair::from_provider(Arc::new(TestProvider { key, corrupt: true })).unwrap();
+        assert!(matches!(
+            faulty.sign(b"roster"),
+            Err(CryptoError::InvalidSignature)
+        ));
+    }
 }
@@ -0,0 +1,117 @@
+//! Registry of key-derivation purposes
+//!
+//! Every HKDF derivation names a `KeyPurpose`, whose info string is unique
+//! and versioned (`nomade/<purpose>/v<n>`). Two subsystems can therefore
+//! never derive the same key from the same secret by accident, and a
+//! purpose's derivation can change by bumping its version.
+
+use crate::encryption::derive_key;
+
+/// What a derived key is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyPurpose {
+    /// Master key encrypting content at rest
+    StorageAtRest,
+    /// Warm-start state snapshots
+    Snapshot,
+    /// Session traffic sent by this device
+    SessionTx,
+    /// Session traffic received by this device
+    SessionRx,
+    /// Exported backups
+    Backup,
+    /// Ephemeral keys exchanged through pairing QR codes
+    QrEphemeral,
+    /// Session key agreed by the pairing PAKE
+    PakeSession,
+    /// PAKE key confirmation sent by the initiator
+    PakeConfirmInitiator,
+    /// PAKE key confirmation sent by the responder
+    PakeConfirmResponder,
+    /// Keys sealed to a device's identity key
+    Seal,
+    /// Responder's initial ratchet key
+    RatchetResponder,
+    /// Double-ratchet root chain
+    RatchetRoot,
+    /// Double-ratchet per-message cipher keys
+    RatchetMessage,
+    /// Identifier both sides of a ratchet session derive
+    RatchetId,
+    /// Ratchet sessions saved between links
+    RatchetState,
+}
+
+impl KeyPurpose {
+    /// Every registered purpose
+    pub const ALL: [KeyPurpose; 15] = [
+        Self::StorageAtRest,
+        Self::Snapshot,
+        Self::SessionTx,
+        Self::SessionRx,
+        Self::Backup,
+        Self::QrEphemeral,
+        Self::PakeSession,
+        Self::PakeConfirmInitiator,
+        Self::PakeConfirmResponder,
+        Self::Seal,
+        Self::RatchetResponder,
+        Self::RatchetRoot,
+        Self::RatchetMessage,
+        Self::RatchetId,
+        Self::RatchetState,
+    ];
+
+    /// HKDF info string of this purpose
+    pub fn info(self) -> &'static [u8] {
+        match self {
+            Self::StorageAtRest => b"nomade/storage-at-rest/v1",
+            Self::Snapshot => b"nomade/snapshot/v1",
+            Self::SessionTx => b"nomade/session-tx/v1",
+            Self::SessionRx => b"nomade/session-rx/v1",
+            Self::Backup => b"nomade/backup/v1",
+            Self::QrEphemeral => b"nomade/qr-ephemeral/v1",
+            Self::PakeSession => b"nomade/pake-session/v1",
+            Self::PakeConfirmInitiator => b"nomade/pake-confirm-initiator/v1",
+            Self::PakeConfirmResponder => b"nomade/pake-confirm-responder/v1",
+            Self::Seal => b"nomade/seal/v1",
+            Self::RatchetResponder => b"nomade/ratchet-responder/v1",
+            Self::RatchetRoot => b"nomade/ratchet-root/v1",
+            Self::RatchetMessage => b"nomade/ratchet-message/v1",
+            Self::RatchetId => b"nomade/ratchet-id/v1",
+            Self::RatchetState => b"nomade/ratchet-state/v1",
+        }
+    }
+}
+
+/// Derive a 256-bit key for `purpose` with HKDF-SHA256
+///
+/// `salt` binds the key to its context (device ID, transcript, ...).
+pub fn derive_for(purpose: KeyPurpose, secret: &[u8], salt: &[u8]) -> [u8; 32] {
+    derive_key(secret, salt, purpose.info())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_info_strings_are_unique_and_versioned() {
+        let infos: HashSet<_> = KeyPurpose::ALL.iter().map(|p| p.info()).collect();
+        assert_eq!(infos.len(), KeyPurpose::ALL.len());
+        for info in infos {
+            let info = std::str::from_utf8(info).unwrap();
+            let parts: Vec<_> = info.split('/').collect();
+            assert_eq!(parts.len(), 3, "{}", info);
+            assert_eq!(parts[0], "nomade");
+            assert!(parts[2].strip_prefix('v').unwrap().parse::<u32>().is_ok());
+        }
+
+        let secret = [1u8; 32];
+        assert_ne!(
+            derive_for(KeyPurpose::SessionTx, &secret, b""),
+            derive_for(KeyPurpose::SessionRx, &secret, b"")
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Data keys shared between paired devices
+//!
+//! Content encrypted at rest on one device must be readable on the others.
+//! Its data key (DEK) is sealed to every trusted device: an ephemeral X25519
+//! agreement with the Montgomery form of the device's Ed25519 identity key
+//! (`seal_key`) yields a key-encryption key that wraps the DEK. The
+//! resulting `KeyShares` travel with artifact metadata; a device opens its
+//! own share and keeps the DEK wrapped under its master key. When the
+//! device list changes, the shares are resealed to the new list.
+
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::decrypt_error;
+use crate::seal::{open_sealed_key, seal_key};
+use crate::{
+    unwrap_key, wrap_key, CryptoError, DecryptError, DeviceId, DeviceKeypair, Result, WrappedKey,
+};
+
+/// Device a data key is shared with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRecipient {
+    pub device_id: DeviceId,
+    /// Ed25519 identity key
+    pub public_key: Vec<u8>,
+}
+
+/// Data key sealed to one device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedKey {
+    pub recipient: DeviceId,
+    /// Ephemeral X25519 public key of the agreement
+    pub ephemeral_public: Vec<u8>,
+    pub wrapped: WrappedKey,
+}
+
+/// One data key sealed to each device that may read the content
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyShares {
+    /// Sorted by recipient
+    pub keys: Vec<SealedKey>,
+}
+
+impl KeyShares {
+    /// Seal `dek` to each recipient
+    pub fn seal(dek: &[u8; 32], recipients: &[KeyRecipient]) -> Result<Self> {
+        Self::default().reseal(dek, recipients)
+    }
+
+    /// Shares for exactly `recipients`
+    ///
+    /// Existing shares are kept, new recipients get one and devices no
+    /// longer listed lose theirs. `dek` must be the key already shared.
+    pub fn reseal(&self, dek: &[u8; 32], recipients: &[KeyRecipient]) -> Result<Self> {
+        let mut keys = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            match self.get(&recipient.device_id) {
+                Some(existing) => keys.push(existing.clone()),
+                None => {
+                    let (ephemeral_public, kek) = seal_key(&recipient.public_key)?;
+                    keys.push(SealedKey {
+                        recipient: recipient.device_id.clone(),
+                        ephemeral_public: ephemeral_public.to_vec(),
+                        wrapped: wrap_key(&kek, dek)?,
+                    });
+                }
+            }
+        }
+        keys.sort_by(|a, b| a.recipient.0.cmp(&b.recipient.0));
+        keys.dedup_by(|a, b| a.recipient == b.recipient);
+        Ok(Self { keys })
+    }
+
+    /// Share sealed to `device_id`
+    pub fn get(&self, device_id: &DeviceId) -> Option<&SealedKey> {
+        self.keys.iter().find(|key| key.recipient == *device_id)
+    }
+
+    /// Recover the data key from this device's share
+    pub fn open(&self, keypair: &DeviceKeypair) -> Result<[u8; 32]> {
+        let sealed = self.get(keypair.device_id()).ok_or_else(|| {
+            decrypt_error(
+                DecryptError::WrongKey,
+                format_args!("no key share for {}", keypair.device_id()),
+            )
+        })?;
+        let ephemeral_public: [u8; 32] = sealed
+            .ephemeral_public
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidKey)?;
+        let kek = open_sealed_key(keypair, &ephemeral_public)?;
+        unwrap_key(&kek, &sealed.wrapped)
+    }
+
+    /// Whether exactly these devices hold a share
+    pub fn is_shared_with(&self, recipients: &[KeyRecipient]) -> bool {
+        self.keys.len() == recipients.len()
+            && recipients.iter().all(|r| self.get(&r.device_id).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_key, generate_keypair};
+
+    fn recipient(keypair: &DeviceKeypair) -> KeyRecipient {
+        KeyRecipient {
+            device_id: keypair.device_id().clone(),
+            public_key: keypair.public_key_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_shares_open_for_recipients_only() {
+        let (laptop, phone, tablet) = (generate_keypair(), generate_keypair(), generate_keypair());
+        let dek = generate_key();
+        let shares = KeyShares::seal(&dek, &[recipient(&laptop), recipient(&phone)]).unwrap();
+        assert_eq!(shares.open(&laptop).unwrap(), dek);
+        assert_eq!(shares.open(&phone).unwrap(), dek);
+        assert!(shares.open(&tablet).is_err());
+
+        // Adding the tablet and dropping the phone keeps the laptop's share
+        let resealed = shares
+            .reseal(&dek, &[recipient(&laptop), recipient(&tablet)])
+            .unwrap();
+        assert_eq!(
+            resealed.get(laptop.device_id()),
+            shares.get(laptop.device_id())
+        );
+        assert_eq!(resealed.open(&tablet).unwrap(), dek);
+        assert!(resealed.open(&phone).is_err());
+        assert!(resealed.is_shared_with(&[recipient(&tablet), recipient(&laptop)]));
+
+        // A share moved to another recipient does not open
+        let mut stolen = resealed.clone();
+        stolen.keys[0].recipient = phone.device_id().clone();
+        assert!(stolen.open(&phone).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Device keystore
+//!
+//! Holds this device's identity keypair. The persistent variant keeps the
+//! Ed25519 secret key in a file under the data directory and generates it
+//! on first open; a platform `SigningProvider` can hold it instead.
+//!
+//! Next to the identity, a `Vault` holds keys protected by the user's
+//! passphrase, stored beside the key file with a `vault` extension.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use zeroize::Zeroizing;
+
+use crate::fs::write_private;
+use crate::kdf::{derive_for, KeyPurpose};
+use crate::{
+    generate_keypair, CryptoError, DeviceId, DeviceKeypair, Result, SigningProvider, Vault,
+};
+
+/// Store for the local device identity
+pub struct Keystore {
+    keypair: DeviceKeypair,
+    path: Option<PathBuf>,
+    vault: Vault,
+}
+
+impl Keystore {
+    /// Create keystore with a fresh, non-persisted identity
+    pub fn in_memory() -> Self {
+        Self {
+            keypair: generate_keypair(),
+            path: None,
+            vault: Vault::in_memory(),
+        }
+    }
+
+    /// Open keystore at `path`, generating an identity if missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let keypair = match std::fs::read(&path) {
+            Ok(bytes) => DeviceKeypair::from_secret_bytes(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let keypair = generate_keypair();
+                let secret = keypair
+                    .secret_key_bytes()
+                    .ok_or(CryptoError::HardwareBacked)?;
+                write_private(&path, &secret)?;
+                keypair
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            keypair,
+            vault: Vault::open(path.with_extension("vault"))?,
+            path: Some(path),
+        })
+    }
+
+    /// Keystore whose identity key is held by a platform provider
+    pub fn with_provider(provider: Arc<dyn SigningProvider>) -> Result<Self> {
+        Ok(Self {
+            keypair: DeviceKeypair::from_provider(provider)?,
+            path: None,
+            vault: Vault::in_memory(),
+        })
+    }
+
+    /// Use `vault`, e.g. a persisted one next to a provider-held identity
+    pub fn with_vault(mut self, vault: Vault) -> Self {
+        self.vault = vault.with_auto_lock(self.vault.auto_lock());
+        self
+    }
+
+    /// Keep vault keys unlocked for `period`
+    pub fn with_auto_lock(mut self, period: Duration) -> Self {
+        self.vault = self.vault.with_auto_lock(period);
+        self
+    }
+
+    /// Local device keypair
+    pub fn keypair(&self) -> &DeviceKeypair {
+        &self.keypair
+    }
+
+    /// Storage key bound to this device's identity, for `purpose`
+    ///
+    /// `context` tells keys of one purpose apart, e.g. a rotation count. A
+    /// hardware-backed identity never leaves the platform keystore; its
+    /// deterministic Ed25519 signature over the purpose label stands in
+    /// for the secret.
+    pub fn device_key(&self, purpose: KeyPurpose, context: &[u8]) -> Result<[u8; 32]> {
+        let secret = match self.keypair.secret_key_bytes() {
+            Some(secret) => Zeroizing::new(secret),
+            None => Zeroizing::new(self.keypair.sign(purpose.info())?.to_bytes().to_vec()),
+        };
+        let salt = [self.device_id().0.as_bytes(), context].concat();
+        Ok(derive_for(purpose, &secret, &salt))
+    }
+
+    /// Local device ID
+    pub fn device_id(&self) -> &DeviceId {
+        self.keypair.device_id()
+    }
+
+    /// Passphrase-protected keys
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
+    /// File backing the keystore, if persisted
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore_persists_identity() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.key");
+
+        let first = Keystore::open(&path).unwrap();
+        let second = Keystore::open(&path).unwrap();
+        assert_eq!(first.device_id(), second.device_id());
+        assert_ne!(Keystore::in_memory().device_id(), first.device_id());
+
+        std::fs::write(&path, b"short").unwrap();
+        assert!(matches!(
+            Keystore::open(&path),
+            Err(CryptoError::InvalidKey)
+        ));
+    }
+}
@@ -1,18 +1,83 @@
 //! Cryptography primitives for Nomade
 //!
 //! This crate provides:
-//! - Device identity keys (Ed25519)
+//! - Device identity keys (Ed25519) and the local keystore
+//! - Passphrase-protected keys with auto-lock
 //! - QR code payload encoding/decoding
-//! - Encryption helpers (AES-256-GCM)
-//! - Key derivation (HKDF)
+//! - Encryption helpers (AES-256-GCM) with managed nonces
+//! - Double-ratchet session encryption and signed envelopes between devices
+//! - Key derivation (HKDF, Argon2id), key wrapping and keys sealed to a device
+//! - Data keys shared with every paired device
+//! - Pairing offer validation and PAKE pairing from short codes
+//! - Multi-device groups with a signed membership roster
+//! - Trust store with signed device revocations
+//! - Identity attestations cross-signed by other devices
+//! - User root keys enrolling and revoking a person's devices
+//! - Secure local wipe and signed remote-wipe commands
+//! - Expiring share tokens for single artifacts
+//! - Constant-time comparison of secret-derived values
+//! - A known-answer self-test of the primitives against published vectors
+//!
+//! The crate builds for `wasm32-unknown-unknown`; the `web` feature adds
+//! JavaScript bindings for a browser client.
 
+pub mod attestation;
+mod clock;
+pub mod ct;
 pub mod encryption;
+pub mod endpoint;
+pub mod envelope;
+pub mod fs;
+pub mod group;
 pub mod identity;
+pub mod kdf;
+pub mod keyshare;
+pub mod keystore;
+pub mod nonce;
+pub mod pairing;
 pub mod qr_payload;
+pub mod ratchet;
+pub mod seal;
+pub mod self_test;
+pub mod share;
+pub mod trust;
+pub mod user;
+pub mod vault;
+pub mod wake;
+#[cfg(feature = "web")]
+pub mod web;
+pub mod wipe;
+pub mod wrap;
 
+pub use attestation::{Attestation, AttestationChain, Countersignature};
+pub use clock::unix_time;
+pub use ct::ct_eq;
 pub use encryption::{decrypt_data, encrypt_data, EncryptedData};
-pub use identity::{generate_keypair, DeviceId, DeviceKeypair};
-pub use qr_payload::{decode_pairing_offer, encode_pairing_offer, PairingOffer};
+pub use endpoint::Endpoint;
+pub use envelope::SignedEnvelope;
+pub use group::{DeviceGroup, Member, RosterEntry, RosterOp};
+pub use identity::{generate_keypair, DeviceId, DeviceKeypair, SigningProvider};
+pub use kdf::{derive_for, KeyPurpose};
+pub use keyshare::{KeyRecipient, KeyShares, SealedKey};
+pub use keystore::Keystore;
+pub use nonce::{KeyContext, NonceSequence};
+pub use pairing::{NonceCache, OfferValidator, ValidatorConfig};
+pub use qr_payload::{
+    decode_pairing_offer, encode_pairing_offer, encode_pairing_offer_parts, PairingOffer,
+    QrReassembler,
+};
+pub use ratchet::{RatchetMessage, RatchetSession};
+pub use seal::{open_sealed_key, password_key, seal_key};
+pub use self_test::self_test;
+pub use share::{IssuedShare, ShareRegistry, ShareToken};
+pub use trust::{
+    Access, Permissions, RevocationRecord, TrustState, TrustStore, TrustedDevice, TrustedUser,
+};
+pub use user::{EnrollmentCertificate, UserId, UserIdentity, UserRevocation};
+pub use vault::{UnlockProvider, Vault};
+pub use wake::{WakeToken, WakeValidator};
+pub use wipe::WipeCommand;
+pub use wrap::{generate_key, unwrap_key, unwrap_key_with, wrap_key, wrap_key_with, WrappedKey};
 
 /// Common error type for crypto operations
 #[derive(Debug, thiserror::Error)]
@@ -24,13 +89,110 @@ pub enum CryptoError {
     EncryptionFailed(String),
 
     #[error("Decryption failed: {0}")]
-    DecryptionFailed(String),
+    DecryptionFailed(DecryptError),
+
+    #[error("Key exhausted after {0} messages; rotate it")]
+    KeyExhausted(u64),
 
     #[error("Invalid signature")]
     InvalidSignature,
 
+    #[error("Operation needs the secret key, which is hardware-backed")]
+    HardwareBacked,
+
+    #[error("Keystore is locked")]
+    KeystoreLocked,
+
+    #[error("Signing failed: {0}")]
+    SigningFailed(String),
+
+    #[error("Invalid pairing offer: {0}")]
+    InvalidOffer(String),
+
+    #[error("Invalid endpoint: {0}")]
+    InvalidEndpoint(String),
+
+    #[error("Pairing offer expired ({age_secs}s old)")]
+    OfferExpired { age_secs: u64 },
+
+    #[error("Pairing offer nonce already used")]
+    NonceReplayed,
+
+    #[error("Invalid pairing code: {0}")]
+    InvalidPairingCode(String),
+
+    #[error("PAKE authentication failed")]
+    PakeFailed,
+
+    #[error("Invalid roster: {0}")]
+    InvalidRoster(String),
+
+    #[error("Device revoked: {0}")]
+    DeviceRevoked(DeviceId),
+
+    #[error("Untrusted device: {0}")]
+    UntrustedDevice(DeviceId),
+
+    #[error("Permission denied for device: {0}")]
+    PermissionDenied(DeviceId),
+
+    #[error("Invalid attestation: {0}")]
+    InvalidAttestation(String),
+
+    #[error("Attestation expired")]
+    AttestationExpired,
+
+    #[error("Invalid enrollment certificate: {0}")]
+    InvalidEnrollment(String),
+
+    #[error("Untrusted user: {0}")]
+    UntrustedUser(UserId),
+
+    #[error("Invalid wipe command: {0}")]
+    InvalidWipe(String),
+
+    #[error("Invalid share token: {0}")]
+    InvalidShareToken(String),
+
+    #[error("Share token expired")]
+    ShareTokenExpired,
+
+    #[error("Share token revoked")]
+    ShareTokenRevoked,
+
+    #[error("Invalid wake token: {0}")]
+    InvalidWakeToken(String),
+
+    #[error("Crypto self-test failed: {0}")]
+    SelfTestFailed(String),
+
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, CryptoError>;
+
+/// Why decryption failed
+///
+/// Deliberately coarse: AEAD cannot tell a wrong key from tampering, and
+/// finer detail could serve as an oracle. Details are logged locally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum DecryptError {
+    /// Encrypted for another key or device
+    #[error("wrong key")]
+    WrongKey,
+
+    /// Authentication failed
+    #[error("tampered or corrupt")]
+    Tampered,
+
+    #[error("unsupported algorithm")]
+    UnsupportedAlgorithm,
+
+    /// Too short to hold a nonce and tag
+    #[error("truncated")]
+    Truncated,
+}
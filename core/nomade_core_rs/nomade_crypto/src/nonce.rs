@@ -0,0 +1,229 @@
+//! Nonce management for AES-256-GCM
+//!
+//! Reusing a GCM nonce under the same key leaks the authentication key, so
+//! callers never choose nonces. A `KeyContext` owns a key together with its
+//! `NonceSequence` and enforces the NIST SP 800-38D usage limits:
+//! - random 96-bit nonces: at most 2^32 messages per key
+//! - counter nonces (random 32-bit prefix, 64-bit counter): until the
+//!   counter is exhausted
+//!
+//! File-backed contexts use counter nonces and persist a reserved
+//! high-water mark before handing nonces out, so a crash can skip counter
+//! values but never reuse one. The mark is written to a synced temporary
+//! file renamed over the state, and the directory is synced before any of
+//! the reserved nonces is used.
+
+use std::path::{Path, PathBuf};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{decrypt_data, key_id, seal, NONCE_SIZE};
+use crate::fs::write_durable;
+use crate::{ct_eq, CryptoError, EncryptedData, Result};
+
+/// Maximum messages under one key with random nonces
+pub const RANDOM_NONCE_LIMIT: u64 = 1 << 32;
+
+/// Counter values reserved on disk at a time
+const COUNTER_RESERVATION: u64 = 1024;
+
+/// How a key's nonces are generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum NonceSequence {
+    /// Fresh random nonce per message
+    Random,
+    /// Fixed prefix followed by a big-endian message counter
+    Counter { prefix: [u8; 4], next: u64 },
+}
+
+impl NonceSequence {
+    /// Counter sequence with a random prefix starting at zero
+    pub fn counter() -> Self {
+        let mut prefix = [0u8; 4];
+        rand::thread_rng().fill_bytes(&mut prefix);
+        Self::Counter { prefix, next: 0 }
+    }
+
+    fn next_nonce(&mut self) -> [u8; NONCE_SIZE] {
+        let mut nonce = [0u8; NONCE_SIZE];
+        match self {
+            Self::Random => rand::thread_rng().fill_bytes(&mut nonce),
+            Self::Counter { prefix, next } => {
+                nonce[..4].copy_from_slice(prefix);
+                nonce[4..].copy_from_slice(&next.to_be_bytes());
+                *next += 1;
+            }
+        }
+        nonce
+    }
+}
+
+/// Persisted state of a file-backed `KeyContext`
+#[derive(Serialize, Deserialize)]
+struct ContextState {
+    key_id: String,
+    sequence: NonceSequence,
+}
+
+/// Key with the nonce sequence and usage count it is bound to
+pub struct KeyContext {
+    key: [u8; 32],
+    sequence: NonceSequence,
+    messages: u64,
+    path: Option<PathBuf>,
+    reserved: u64,
+}
+
+impl KeyContext {
+    /// In-memory context
+    pub fn new(key: [u8; 32], sequence: NonceSequence) -> Self {
+        Self {
+            key,
+            sequence,
+            messages: 0,
+            path: None,
+            reserved: 0,
+        }
+    }
+
+    /// Counter context persisted at `path`, created if missing
+    pub fn open(path: impl AsRef<Path>, key: [u8; 32]) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let key_id = key_id(&key);
+        let sequence = match std::fs::read(&path) {
+            Ok(bytes) => {
+                let state: ContextState = serde_json::from_slice(&bytes)?;
+                if !ct_eq(state.key_id.as_bytes(), key_id.as_bytes()) {
+                    return Err(CryptoError::InvalidKey);
+                }
+                state.sequence
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => NonceSequence::counter(),
+            Err(e) => return Err(e.into()),
+        };
+        let NonceSequence::Counter { next, .. } = sequence else {
+            return Err(CryptoError::InvalidKey);
+        };
+        Ok(Self {
+            key,
+            sequence,
+            messages: 0,
+            path: Some(path),
+            reserved: next,
+        })
+    }
+
+    /// Encrypt a message under the next nonce
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedData> {
+        if self.remaining() == 0 {
+            return Err(CryptoError::KeyExhausted(self.messages));
+        }
+        if let (Some(path), NonceSequence::Counter { prefix, next }) = (&self.path, self.sequence) {
+            if next >= self.reserved {
+                let reserved = next.saturating_add(COUNTER_RESERVATION);
+                let state = ContextState {
+                    key_id: key_id(&self.key),
+                    sequence: NonceSequence::Counter {
+                        prefix,
+                        next: reserved,
+                    },
+                };
+                write_durable(path, &serde_json::to_vec(&state)?)?;
+                self.reserved = reserved;
+            }
+        }
+
+        let nonce = self.sequence.next_nonce();
+        self.messages += 1;
+        seal(plaintext, &self.key, &nonce)
+    }
+
+    /// `key_id` of the key, to tell which key wrapped or sealed data
+    pub fn key_id(&self) -> String {
+        key_id(&self.key)
+    }
+
+    pub(crate) fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Decrypt a message encrypted under this key
+    pub fn decrypt(&self, encrypted: &EncryptedData) -> Result<Vec<u8>> {
+        decrypt_data(encrypted, &self.key)
+    }
+
+    /// Messages encrypted through this context since it was created
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    /// Messages left before the key must be rotated
+    pub fn remaining(&self) -> u64 {
+        match self.sequence {
+            NonceSequence::Random => RANDOM_NONCE_LIMIT.saturating_sub(self.messages),
+            NonceSequence::Counter { next, .. } => u64::MAX - next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_nonces_are_sequential() {
+        let mut context = KeyContext::new([1u8; 32], NonceSequence::counter());
+        let first = context.encrypt(b"one").unwrap();
+        let second = context.encrypt(b"two").unwrap();
+        assert_eq!(first.nonce[..4], second.nonce[..4]);
+        assert_eq!(first.nonce[4..], 0u64.to_be_bytes());
+        assert_eq!(second.nonce[4..], 1u64.to_be_bytes());
+        assert_eq!(context.decrypt(&second).unwrap(), b"two");
+        assert_eq!(context.messages(), 2);
+
+        let mut random = KeyContext::new([1u8; 32], NonceSequence::Random);
+        random.encrypt(b"one").unwrap();
+        assert_eq!(random.remaining(), RANDOM_NONCE_LIMIT - 1);
+    }
+
+    #[test]
+    fn test_exhausted_key_is_rejected() {
+        let sequence = NonceSequence::Counter {
+            prefix: [0; 4],
+            next: u64::MAX - 1,
+        };
+        let mut context = KeyContext::new([1u8; 32], sequence);
+        context.encrypt(b"last").unwrap();
+        assert!(matches!(
+            context.encrypt(b"too many"),
+            Err(CryptoError::KeyExhausted(1))
+        ));
+    }
+
+    #[test]
+    fn test_persisted_counter_never_reuses_nonces() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.ctx");
+        let key = [9u8; 32];
+
+        let mut context = KeyContext::open(&path, key).unwrap();
+        let before = context.encrypt(b"a").unwrap();
+        context.encrypt(b"b").unwrap();
+        drop(context);
+
+        // Reopening skips the rest of the reservation
+        let mut context = KeyContext::open(&path, key).unwrap();
+        let after = context.encrypt(b"c").unwrap();
+        assert_eq!(before.nonce[..4], after.nonce[..4]);
+        assert_eq!(after.nonce[4..], COUNTER_RESERVATION.to_be_bytes());
+
+        assert!(matches!(
+            KeyContext::open(&path, [8u8; 32]),
+            Err(CryptoError::InvalidKey)
+        ));
+        // Only the state file is left
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,12 @@
+//! Device pairing
+//!
+//! Acceptance checks for pairing offers received out of band (QR code, BLE,
+//! NFC, copy-paste), and a PAKE handshake for pairing from a short code.
+
+pub mod pake;
+pub mod transport;
+pub mod validation;
+
+pub use pake::{generate_pairing_code, PakeMessage, PakeRole, PakeSession, Spake2};
+pub use transport::{BleTransport, PairingChannel, PairingTransport, UrlTransport};
+pub use validation::{NonceCache, OfferValidator, ValidatorConfig};
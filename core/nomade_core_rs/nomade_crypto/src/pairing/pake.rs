@@ -0,0 +1,314 @@
+//! PAKE-based pairing from a short code
+//!
+//! When scanning a QR code isn't possible, two devices can pair from a short
+//! code typed by the user. The code is never sent over the network; instead
+//! both sides run a SPAKE2 exchange over the Ristretto group, so an attacker
+//! observing or relaying the messages gets at most one online guess per run
+//! and cannot brute-force the code offline.
+//!
+//! Flow:
+//! 1. Both sides call [`Spake2::start`] and exchange the resulting `PakeMessage`s.
+//! 2. Both sides call [`Spake2::finish`] with the peer's message and exchange
+//!    [`PakeSession::confirmation`] values.
+//! 3. [`PakeSession::confirm`] checks the peer's confirmation and yields the
+//!    shared session key, used e.g. to exchange `PairingOffer`s privately.
+
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::kdf::{derive_for, KeyPurpose};
+use crate::{CryptoError, Result};
+
+/// Characters used in pairing codes (Crockford base32, no I/L/O/U)
+const CODE_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Minimum pairing code length
+pub const MIN_CODE_LENGTH: usize = 6;
+
+/// Maximum pairing code length
+pub const MAX_CODE_LENGTH: usize = 8;
+
+/// Domain separation labels for the SPAKE2 blinding points
+const M_LABEL: &[u8] = b"nomade-spake2-ristretto255-M";
+const N_LABEL: &[u8] = b"nomade-spake2-ristretto255-N";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Side of the PAKE exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PakeRole {
+    /// Device that displays the code
+    Initiator,
+    /// Device where the code is typed
+    Responder,
+}
+
+impl PakeRole {
+    fn peer(self) -> Self {
+        match self {
+            Self::Initiator => Self::Responder,
+            Self::Responder => Self::Initiator,
+        }
+    }
+}
+
+/// Message exchanged in the first PAKE round
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PakeMessage {
+    pub role: PakeRole,
+    pub element: Vec<u8>,
+}
+
+/// Generate a random pairing code of the given length
+pub fn generate_pairing_code(length: usize) -> Result<String> {
+    use rand::Rng;
+    check_code_length(length)?;
+
+    let mut rng = rand::thread_rng();
+    Ok((0..length)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect())
+}
+
+/// Normalize user-typed code (case, separators, ambiguous characters)
+pub fn normalize_pairing_code(code: &str) -> Result<String> {
+    let normalized: String = code
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-'))
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            other => other,
+        })
+        .collect();
+
+    check_code_length(normalized.len())?;
+    if let Some(c) = normalized.bytes().find(|b| !CODE_ALPHABET.contains(b)) {
+        return Err(CryptoError::InvalidPairingCode(format!(
+            "Invalid character '{}'",
+            c as char
+        )));
+    }
+    Ok(normalized)
+}
+
+/// SPAKE2 state after the first message was produced
+pub struct Spake2 {
+    role: PakeRole,
+    password: Scalar,
+    secret: Scalar,
+    element: [u8; 32],
+}
+
+impl Spake2 {
+    /// Start exchange from a pairing code
+    pub fn start(role: PakeRole, code: &str) -> Result<(Self, PakeMessage)> {
+        let password = password_scalar(&normalize_pairing_code(code)?);
+        let secret = random_scalar();
+
+        let blinded = RISTRETTO_BASEPOINT_POINT * secret + blinding_point(role) * password;
+        let element = blinded.compress().to_bytes();
+
+        let message = PakeMessage {
+            role,
+            element: element.to_vec(),
+        };
+        Ok((
+            Self {
+                role,
+                password,
+                secret,
+                element,
+            },
+            message,
+        ))
+    }
+
+    /// Process peer message and derive the session
+    pub fn finish(self, peer: &PakeMessage) -> Result<PakeSession> {
+        if peer.role != self.role.peer() {
+            return Err(CryptoError::PakeFailed);
+        }
+        let peer_point = CompressedRistretto::from_slice(&peer.element)
+            .ok()
+            .and_then(|c| c.decompress())
+            .ok_or(CryptoError::PakeFailed)?;
+
+        let unblinded = peer_point - blinding_point(peer.role) * self.password;
+        let shared = (unblinded * self.secret).compress().to_bytes();
+
+        let (initiator_element, responder_element) = match self.role {
+            PakeRole::Initiator => (self.element.as_slice(), peer.element.as_slice()),
+            PakeRole::Responder => (peer.element.as_slice(), self.element.as_slice()),
+        };
+
+        // Transcript binds both messages, the shared point and the password
+        let mut transcript = Sha256::new();
+        for part in [
+            initiator_element,
+            responder_element,
+            shared.as_slice(),
+            self.password.as_bytes().as_slice(),
+        ] {
+            transcript.update((part.len() as u64).to_le_bytes());
+            transcript.update(part);
+        }
+        let transcript = transcript.finalize();
+
+        let session_key = derive_for(KeyPurpose::PakeSession, &transcript, b"");
+        let initiator_confirm = confirmation_mac(&transcript, KeyPurpose::PakeConfirmInitiator);
+        let responder_confirm = confirmation_mac(&transcript, KeyPurpose::PakeConfirmResponder);
+
+        let (local_confirmation, peer_confirmation) = match self.role {
+            PakeRole::Initiator => (initiator_confirm, responder_confirm),
+            PakeRole::Responder => (responder_confirm, initiator_confirm),
+        };
+
+        Ok(PakeSession {
+            session_key,
+            local_confirmation,
+            peer_confirmation,
+        })
+    }
+}
+
+/// Unconfirmed PAKE session
+pub struct PakeSession {
+    session_key: [u8; 32],
+    local_confirmation: HmacSha256,
+    peer_confirmation: HmacSha256,
+}
+
+impl PakeSession {
+    /// Key confirmation value to send to the peer
+    pub fn confirmation(&self) -> Vec<u8> {
+        self.local_confirmation
+            .clone()
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    /// Verify peer's confirmation and return the shared session key
+    pub fn confirm(self, peer_confirmation: &[u8]) -> Result<[u8; 32]> {
+        // verify_slice compares in constant time
+        self.peer_confirmation
+            .verify_slice(peer_confirmation)
+            .map_err(|_| CryptoError::PakeFailed)?;
+        Ok(self.session_key)
+    }
+}
+
+// Helper functions
+
+fn check_code_length(length: usize) -> Result<()> {
+    if !(MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&length) {
+        return Err(CryptoError::InvalidPairingCode(format!(
+            "Code must be {}-{} characters",
+            MIN_CODE_LENGTH, MAX_CODE_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn hash_to_point(label: &[u8]) -> RistrettoPoint {
+    let hash: [u8; 64] = Sha512::digest(label).into();
+    RistrettoPoint::from_uniform_bytes(&hash)
+}
+
+fn blinding_point(role: PakeRole) -> RistrettoPoint {
+    match role {
+        PakeRole::Initiator => hash_to_point(M_LABEL),
+        PakeRole::Responder => hash_to_point(N_LABEL),
+    }
+}
+
+fn password_scalar(code: &str) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update(b"nomade-pake-password");
+    hasher.update(code.as_bytes());
+    Scalar::from_bytes_mod_order_wide(&hasher.finalize().into())
+}
+
+fn random_scalar() -> Scalar {
+    use rand::RngCore;
+    let mut bytes = [0u8; 64];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+fn confirmation_mac(transcript: &[u8], purpose: KeyPurpose) -> HmacSha256 {
+    let key = derive_for(purpose, transcript, b"");
+    let mut mac = HmacSha256::new_from_slice(&key).expect("HMAC accepts any key length");
+    mac.update(transcript);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_exchange(initiator_code: &str, responder_code: &str) -> (PakeSession, PakeSession) {
+        let (initiator, initiator_msg) =
+            Spake2::start(PakeRole::Initiator, initiator_code).unwrap();
+        let (responder, responder_msg) =
+            Spake2::start(PakeRole::Responder, responder_code).unwrap();
+        (
+            initiator.finish(&responder_msg).unwrap(),
+            responder.finish(&initiator_msg).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_matching_codes_derive_same_key() {
+        let code = generate_pairing_code(8).unwrap();
+        let (initiator, responder) = run_exchange(&code, &code.to_lowercase());
+
+        let initiator_confirmation = initiator.confirmation();
+        let responder_confirmation = responder.confirmation();
+        let initiator_key = initiator.confirm(&responder_confirmation).unwrap();
+        let responder_key = responder.confirm(&initiator_confirmation).unwrap();
+
+        assert_eq!(initiator_key, responder_key);
+    }
+
+    #[test]
+    fn test_mismatched_codes_fail_confirmation() {
+        let (initiator, responder) = run_exchange("ABC123", "ABC124");
+
+        let responder_confirmation = responder.confirmation();
+        assert!(matches!(
+            initiator.confirm(&responder_confirmation),
+            Err(CryptoError::PakeFailed)
+        ));
+    }
+
+    #[test]
+    fn test_reject_same_role_and_bad_element() {
+        let (initiator, _) = Spake2::start(PakeRole::Initiator, "ABC123").unwrap();
+        let (_, other_initiator_msg) = Spake2::start(PakeRole::Initiator, "ABC123").unwrap();
+        assert!(initiator.finish(&other_initiator_msg).is_err());
+
+        let (initiator, _) = Spake2::start(PakeRole::Initiator, "ABC123").unwrap();
+        let bogus = PakeMessage {
+            role: PakeRole::Responder,
+            element: vec![0xff; 32],
+        };
+        assert!(initiator.finish(&bogus).is_err());
+    }
+
+    #[test]
+    fn test_normalize_pairing_code() {
+        assert_eq!(normalize_pairing_code("abc-d0o1").unwrap(), "ABCD001");
+        assert!(normalize_pairing_code("ABC12").is_err());
+        assert!(normalize_pairing_code("ABC123456").is_err());
+        assert!(normalize_pairing_code("ABC12U").is_err());
+
+        let code = generate_pairing_code(6).unwrap();
+        assert_eq!(normalize_pairing_code(&code).unwrap(), code);
+    }
+}
@@ -0,0 +1,315 @@
+//! Transport-agnostic pairing payload exchange
+//!
+//! A signed `PairingOffer` can travel over several out-of-band channels
+//! (QR code, BLE, NFC, copy-paste). Each channel implements
+//! `PairingTransport`, carrying the offer as one or more opaque chunks.
+//! Channels with small frames (BLE) split the payload into fragments that
+//! are reassembled on the receiving side.
+
+use serde::{Deserialize, Serialize};
+
+use crate::qr_payload::{encode_pairing_offer_parts, QrReassembler, DEFAULT_QR_BUDGET};
+use crate::{CryptoError, PairingOffer, Result};
+
+/// Out-of-band channel used for pairing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PairingChannel {
+    Qr,
+    Ble,
+    Nfc,
+    CopyPaste,
+}
+
+/// Carries pairing payloads over an out-of-band channel
+pub trait PairingTransport {
+    /// Channel implemented by this transport
+    fn channel(&self) -> PairingChannel;
+
+    /// Split an offer into chunks ready for transmission
+    fn encode_offer(&self, offer: &PairingOffer) -> Result<Vec<Vec<u8>>>;
+
+    /// Feed a received chunk, returning the offer once complete
+    fn receive_chunk(&mut self, chunk: &[u8]) -> Result<Option<PairingOffer>>;
+}
+
+/// Transport for text channels (QR, NFC, copy-paste)
+///
+/// The offer is carried as its `nomade://pair` URL. On the QR channel,
+/// offers over the size budget become a sequence of part URLs.
+#[derive(Debug, Clone)]
+pub struct UrlTransport {
+    channel: PairingChannel,
+    budget: usize,
+    reassembler: QrReassembler,
+}
+
+impl UrlTransport {
+    /// Create transport for a text-based channel
+    pub fn new(channel: PairingChannel) -> Self {
+        let budget = match channel {
+            PairingChannel::Qr => DEFAULT_QR_BUDGET,
+            _ => usize::MAX,
+        };
+        Self {
+            channel,
+            budget,
+            reassembler: QrReassembler::new(),
+        }
+    }
+
+    /// Override the URL bytes allowed per chunk
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        self.budget = budget;
+        self
+    }
+}
+
+impl PairingTransport for UrlTransport {
+    fn channel(&self) -> PairingChannel {
+        self.channel
+    }
+
+    fn encode_offer(&self, offer: &PairingOffer) -> Result<Vec<Vec<u8>>> {
+        Ok(encode_pairing_offer_parts(offer, self.budget)?
+            .into_iter()
+            .map(String::into_bytes)
+            .collect())
+    }
+
+    fn receive_chunk(&mut self, chunk: &[u8]) -> Result<Option<PairingOffer>> {
+        let url = std::str::from_utf8(chunk)
+            .map_err(|_| CryptoError::InvalidOffer("Payload is not valid UTF-8".into()))?;
+        self.reassembler.push(url.trim())
+    }
+}
+
+/// Size of the BLE fragment header
+///
+/// Layout: message id (u16 BE), fragment index (u8), fragment count (u8).
+pub const BLE_FRAGMENT_HEADER_SIZE: usize = 4;
+
+/// Smallest usable ATT MTU (BLE 4.0 default is 23, minus 3 bytes ATT overhead)
+pub const BLE_MIN_MTU: usize = 20;
+
+/// Maximum number of fragments per offer
+const BLE_MAX_FRAGMENTS: usize = u8::MAX as usize;
+
+/// BLE transport splitting the offer into MTU-sized fragments
+#[derive(Debug)]
+pub struct BleTransport {
+    mtu: usize,
+    reassembly: Option<Reassembly>,
+}
+
+#[derive(Debug)]
+struct Reassembly {
+    message_id: u16,
+    fragments: Vec<Option<Vec<u8>>>,
+}
+
+impl BleTransport {
+    /// Create BLE transport for the negotiated ATT payload size
+    pub fn new(mtu: usize) -> Result<Self> {
+        if mtu < BLE_MIN_MTU {
+            return Err(CryptoError::InvalidOffer(format!(
+                "BLE MTU {} below minimum {}",
+                mtu, BLE_MIN_MTU
+            )));
+        }
+        Ok(Self {
+            mtu,
+            reassembly: None,
+        })
+    }
+
+    /// Split raw payload into fragments
+    pub fn fragment(&self, payload: &[u8], message_id: u16) -> Result<Vec<Vec<u8>>> {
+        let chunk_size = self.mtu - BLE_FRAGMENT_HEADER_SIZE;
+        let count = payload.len().div_ceil(chunk_size).max(1);
+        if count > BLE_MAX_FRAGMENTS {
+            return Err(CryptoError::InvalidOffer(format!(
+                "Payload needs {} fragments (max {})",
+                count, BLE_MAX_FRAGMENTS
+            )));
+        }
+
+        let chunks: Vec<&[u8]> = if payload.is_empty() {
+            vec![&[]]
+        } else {
+            payload.chunks(chunk_size).collect()
+        };
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut fragment = Vec::with_capacity(BLE_FRAGMENT_HEADER_SIZE + chunk.len());
+                fragment.extend_from_slice(&message_id.to_be_bytes());
+                fragment.push(index as u8);
+                fragment.push(count as u8);
+                fragment.extend_from_slice(chunk);
+                fragment
+            })
+            .collect())
+    }
+
+    /// Feed a fragment, returning the payload once all fragments arrived
+    pub fn reassemble(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>> {
+        if fragment.len() < BLE_FRAGMENT_HEADER_SIZE || fragment.len() > self.mtu {
+            return Err(CryptoError::InvalidOffer("Malformed BLE fragment".into()));
+        }
+        let message_id = u16::from_be_bytes([fragment[0], fragment[1]]);
+        let index = fragment[2] as usize;
+        let count = fragment[3] as usize;
+        if count == 0 || index >= count {
+            return Err(CryptoError::InvalidOffer("Malformed BLE fragment".into()));
+        }
+
+        // A fragment from a new message restarts reassembly
+        let reassembly = match &mut self.reassembly {
+            Some(r) if r.message_id == message_id && r.fragments.len() == count => r,
+            slot => slot.insert(Reassembly {
+                message_id,
+                fragments: vec![None; count],
+            }),
+        };
+        reassembly.fragments[index] = Some(fragment[BLE_FRAGMENT_HEADER_SIZE..].to_vec());
+
+        if reassembly.fragments.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let payload = std::mem::take(&mut reassembly.fragments)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        self.reassembly = None;
+        Ok(Some(payload))
+    }
+}
+
+impl PairingTransport for BleTransport {
+    fn channel(&self) -> PairingChannel {
+        PairingChannel::Ble
+    }
+
+    fn encode_offer(&self, offer: &PairingOffer) -> Result<Vec<Vec<u8>>> {
+        // Raw JSON: BLE is binary-safe so the URL encoding is unnecessary
+        let payload = serde_json::to_vec(offer)?;
+        let message_id = offer
+            .nonce
+            .iter()
+            .take(2)
+            .fold(0u16, |id, b| (id << 8) | *b as u16);
+        self.fragment(&payload, message_id)
+    }
+
+    fn receive_chunk(&mut self, chunk: &[u8]) -> Result<Option<PairingOffer>> {
+        match self.reassemble(chunk)? {
+            Some(payload) => Ok(Some(serde_json::from_slice(&payload)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+    use proptest::prelude::*;
+
+    fn signed_offer() -> PairingOffer {
+        let keypair = generate_keypair();
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            "Test Device".into(),
+            keypair.public_key_bytes(),
+            vec![
+                "192.168.1.100:8765".parse().unwrap(),
+                "[fe80::1]:8765".parse().unwrap(),
+            ],
+        );
+        offer.sign(&keypair).unwrap();
+        offer
+    }
+
+    fn exchange(transport: &mut dyn PairingTransport, chunks: Vec<Vec<u8>>) -> PairingOffer {
+        let mut result = None;
+        for chunk in chunks {
+            assert!(result.is_none(), "offer completed before last chunk");
+            result = transport.receive_chunk(&chunk).unwrap();
+        }
+        result.expect("offer incomplete")
+    }
+
+    #[test]
+    fn test_url_transport_roundtrip() {
+        let offer = signed_offer();
+        let mut transport = UrlTransport::new(PairingChannel::CopyPaste);
+
+        let chunks = transport.encode_offer(&offer).unwrap();
+        assert_eq!(chunks.len(), 1);
+
+        let received = exchange(&mut transport, chunks);
+        assert!(received.verify_signature().is_ok());
+    }
+
+    #[test]
+    fn test_ble_fragments_fit_mtu() {
+        let offer = signed_offer();
+        let mut transport = BleTransport::new(BLE_MIN_MTU).unwrap();
+
+        let chunks = transport.encode_offer(&offer).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.len() <= BLE_MIN_MTU));
+
+        let received = exchange(&mut transport, chunks);
+        assert!(received.verify_signature().is_ok());
+    }
+
+    #[test]
+    fn test_ble_reassembly_out_of_order() {
+        let offer = signed_offer();
+        let mut transport = BleTransport::new(64).unwrap();
+
+        let mut chunks = transport.encode_offer(&offer).unwrap();
+        chunks.reverse();
+        let received = exchange(&mut transport, chunks);
+        assert_eq!(received.nonce, offer.nonce);
+    }
+
+    #[test]
+    fn test_ble_rejects_malformed_fragments() {
+        let mut transport = BleTransport::new(BLE_MIN_MTU).unwrap();
+        assert!(transport.reassemble(&[0, 1]).is_err());
+        assert!(transport.reassemble(&[0, 1, 3, 2]).is_err());
+        assert!(transport.reassemble(&[0, 1, 0, 0]).is_err());
+        assert!(BleTransport::new(BLE_MIN_MTU - 1).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn ble_fragment_roundtrip(
+            payload in prop::collection::vec(any::<u8>(), 0..2048),
+            mtu in BLE_MIN_MTU..256,
+            message_id in any::<u16>(),
+        ) {
+            let mut transport = BleTransport::new(mtu).unwrap();
+            let mut result = None;
+            for fragment in transport.fragment(&payload, message_id).unwrap() {
+                prop_assert!(fragment.len() <= mtu);
+                result = transport.reassemble(&fragment).unwrap();
+            }
+            prop_assert_eq!(result, Some(payload));
+        }
+
+        #[test]
+        fn ble_receive_never_panics(
+            fragments in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..64), 0..8),
+        ) {
+            let mut transport = BleTransport::new(BLE_MIN_MTU).unwrap();
+            for fragment in fragments {
+                let _ = transport.receive_chunk(&fragment);
+            }
+        }
+    }
+}
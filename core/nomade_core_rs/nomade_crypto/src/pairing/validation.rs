@@ -0,0 +1,292 @@
+//! Pairing offer acceptance checks
+//!
+//! An offer is only accepted if it is correctly signed, fresh, and its nonce
+//! has not been seen before. Seen nonces are kept in a `NonceCache` that can
+//! be persisted to disk so replays are rejected across restarts.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::fs::write_durable;
+use crate::{CryptoError, PairingOffer, Result};
+
+/// Supported pairing offer version
+const SUPPORTED_OFFER_VERSION: u8 = 1;
+
+/// Validator configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidatorConfig {
+    /// Maximum age of an offer
+    pub max_age: Duration,
+    /// Tolerated clock difference for offers timestamped in the future
+    pub max_clock_skew: Duration,
+}
+
+impl Default for ValidatorConfig {
+    fn default() -> Self {
+        Self {
+            max_age: Duration::from_secs(5 * 60),
+            max_clock_skew: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Seen nonce entry
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SeenNonce {
+    /// When the offer was accepted (seconds since UNIX epoch)
+    accepted_at: u64,
+    /// Single-use offers are never pruned
+    single_use: bool,
+}
+
+/// Cache of recently seen offer nonces
+#[derive(Debug, Default)]
+pub struct NonceCache {
+    seen: HashMap<String, SeenNonce>,
+    path: Option<PathBuf>,
+}
+
+impl NonceCache {
+    /// Create in-memory cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open cache persisted at `path`, creating it if missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let seen = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            seen,
+            path: Some(path),
+        })
+    }
+
+    /// Whether the nonce has been seen
+    pub fn contains(&self, nonce: &[u8]) -> bool {
+        self.seen.contains_key(&nonce_key(nonce))
+    }
+
+    /// Number of remembered nonces
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Whether no nonces are remembered
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Record nonce, persisting the cache if backed by a file
+    fn insert(&mut self, nonce: &[u8], accepted_at: u64, single_use: bool) -> Result<()> {
+        self.seen.insert(
+            nonce_key(nonce),
+            SeenNonce {
+                accepted_at,
+                single_use,
+            },
+        );
+        self.save()
+    }
+
+    /// Forget reusable-offer nonces older than `max_age`
+    ///
+    /// Offers older than the maximum age are rejected by the age check, so
+    /// their nonces no longer need to be remembered. Single-use nonces are
+    /// kept so the offer stays consumed even if the clock or the configured
+    /// maximum age later changes.
+    fn prune(&mut self, now: u64, max_age: Duration) {
+        let cutoff = now.saturating_sub(max_age.as_secs());
+        self.seen
+            .retain(|_, entry| entry.single_use || entry.accepted_at >= cutoff);
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // A consumed nonce lost to a crash would let its offer pair again
+        write_durable(path, &serde_json::to_vec(&self.seen)?)?;
+        Ok(())
+    }
+}
+
+/// Validates pairing offers before accepting them
+#[derive(Debug)]
+pub struct OfferValidator {
+    config: ValidatorConfig,
+    nonces: NonceCache,
+}
+
+impl OfferValidator {
+    /// Create new validator
+    pub fn new(config: ValidatorConfig, nonces: NonceCache) -> Self {
+        Self { config, nonces }
+    }
+
+    /// Validate and accept an offer, recording its nonce
+    pub fn accept(&mut self, offer: &PairingOffer) -> Result<()> {
+        self.accept_at(offer, unix_time())
+    }
+
+    /// Validate and accept an offer at the given time (seconds since UNIX epoch)
+    pub fn accept_at(&mut self, offer: &PairingOffer, now: u64) -> Result<()> {
+        self.check(offer, now)?;
+
+        self.nonces.prune(now, self.config.max_age);
+        self.nonces.insert(&offer.nonce, now, offer.single_use)
+    }
+
+    /// Run all checks without recording the nonce
+    pub fn check(&self, offer: &PairingOffer, now: u64) -> Result<()> {
+        if offer.version != SUPPORTED_OFFER_VERSION {
+            return Err(CryptoError::InvalidOffer(format!(
+                "Unsupported offer version {}",
+                offer.version
+            )));
+        }
+        if offer.nonce.is_empty() {
+            return Err(CryptoError::InvalidOffer("Missing nonce".into()));
+        }
+
+        offer.verify_signature()?;
+
+        if offer.timestamp > now.saturating_add(self.config.max_clock_skew.as_secs()) {
+            return Err(CryptoError::InvalidOffer(
+                "Offer timestamp is in the future".into(),
+            ));
+        }
+        let age = now.saturating_sub(offer.timestamp);
+        if age > self.config.max_age.as_secs() {
+            return Err(CryptoError::OfferExpired { age_secs: age });
+        }
+
+        if self.nonces.contains(&offer.nonce) {
+            return Err(CryptoError::NonceReplayed);
+        }
+        Ok(())
+    }
+
+    /// Access the nonce cache
+    pub fn nonces(&self) -> &NonceCache {
+        &self.nonces
+    }
+}
+
+// Helper functions
+
+fn nonce_key(nonce: &[u8]) -> String {
+    use base64::{engine::general_purpose, Engine as _};
+    general_purpose::STANDARD_NO_PAD.encode(nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    fn signed_offer(single_use: bool) -> PairingOffer {
+        let keypair = generate_keypair();
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            "Test Device".into(),
+            keypair.public_key_bytes(),
+            vec!["192.168.1.100:8765".parse().unwrap()],
+        )
+        .with_single_use(single_use);
+        offer.sign(&keypair).unwrap();
+        offer
+    }
+
+    #[test]
+    fn test_accept_fresh_offer() {
+        let offer = signed_offer(false);
+        let mut validator = OfferValidator::new(ValidatorConfig::default(), NonceCache::new());
+
+        validator.accept_at(&offer, offer.timestamp + 10).unwrap();
+        assert!(validator.nonces().contains(&offer.nonce));
+    }
+
+    #[test]
+    fn test_reject_expired_and_future_offers() {
+        let offer = signed_offer(false);
+        let mut validator = OfferValidator::new(ValidatorConfig::default(), NonceCache::new());
+
+        assert!(matches!(
+            validator.accept_at(&offer, offer.timestamp + 301),
+            Err(CryptoError::OfferExpired { age_secs: 301 })
+        ));
+        assert!(matches!(
+            validator.accept_at(&offer, offer.timestamp - 60),
+            Err(CryptoError::InvalidOffer(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_replayed_nonce() {
+        let offer = signed_offer(false);
+        let mut validator = OfferValidator::new(ValidatorConfig::default(), NonceCache::new());
+
+        validator.accept_at(&offer, offer.timestamp).unwrap();
+        assert!(matches!(
+            validator.accept_at(&offer, offer.timestamp + 1),
+            Err(CryptoError::NonceReplayed)
+        ));
+    }
+
+    #[test]
+    fn test_reject_tampered_offer() {
+        let mut offer = signed_offer(false);
+        offer.endpoints.push("10.0.0.66:8765".parse().unwrap());
+        let mut validator = OfferValidator::new(ValidatorConfig::default(), NonceCache::new());
+
+        assert!(matches!(
+            validator.accept_at(&offer, offer.timestamp),
+            Err(CryptoError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_single_use_nonce_survives_pruning() {
+        let reusable = signed_offer(false);
+        let single_use = signed_offer(true);
+        let mut validator = OfferValidator::new(ValidatorConfig::default(), NonceCache::new());
+
+        let now = reusable.timestamp;
+        validator.accept_at(&reusable, now).unwrap();
+        validator.accept_at(&single_use, now).unwrap();
+
+        // Only the expired reusable nonce is forgotten
+        validator.nonces.prune(now + 400, validator.config.max_age);
+        assert!(!validator.nonces().contains(&reusable.nonce));
+        assert!(validator.nonces().contains(&single_use.nonce));
+    }
+
+    #[test]
+    fn test_nonce_cache_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nonces.json");
+        let offer = signed_offer(true);
+
+        let mut validator =
+            OfferValidator::new(ValidatorConfig::default(), NonceCache::open(&path).unwrap());
+        validator.accept_at(&offer, offer.timestamp).unwrap();
+
+        // Replay after restart is still rejected
+        let mut validator =
+            OfferValidator::new(ValidatorConfig::default(), NonceCache::open(&path).unwrap());
+        assert!(matches!(
+            validator.accept_at(&offer, offer.timestamp),
+            Err(CryptoError::NonceReplayed)
+        ));
+    }
+}
@@ -1,8 +1,32 @@
 //! QR code payload encoding/decoding for device pairing
+//!
+//! An offer is carried as a `nomade://pair` URL. Offers that exceed the QR
+//! size budget are first trimmed (device name, then extra endpoints) before
+//! signing, and if still too large split into a sequence of part URLs
+//! (`nomade://pair?v=1&p=2/3&id=…&d=…`) shown as an animated QR code.
 
 use serde::{Deserialize, Serialize};
 
-use crate::{DeviceId, Result};
+use crate::clock::unix_time;
+use crate::{CryptoError, DeviceId, DeviceKeypair, Endpoint, Result};
+
+/// Prefix of a single-URL offer
+const PAYLOAD_PREFIX: &str = "nomade://pair?v=1&d=";
+/// Prefix of one part of a multi-part offer
+const PART_PREFIX: &str = "nomade://pair?v=1&p=";
+
+/// Byte capacity of the largest QR code (version 40, level M, byte mode)
+pub const QR_MAX_BYTES: usize = 2331;
+/// URL bytes per QR code that stay easy to scan from a phone screen
+pub const DEFAULT_QR_BUDGET: usize = 600;
+/// Most parts in an animated QR sequence
+pub const MAX_QR_PARTS: usize = 16;
+/// Shortest device name `trim_to_budget` produces
+pub const MIN_DEVICE_NAME_CHARS: usize = 16;
+/// Longest offer URL decoded, so a hostile URL cannot force large allocations
+pub const MAX_OFFER_URL_LEN: usize = QR_MAX_BYTES * MAX_QR_PARTS;
+
+const SIGNATURE_LEN: usize = 64;
 
 /// Pairing offer for QR code
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,22 +35,27 @@ pub struct PairingOffer {
     pub device_id: DeviceId,
     pub device_name: String,
     pub public_key: Vec<u8>,
-    pub endpoints: Vec<String>,
+    /// Most preferred first
+    pub endpoints: Vec<Endpoint>,
     pub nonce: Vec<u8>,
     pub timestamp: u64,
+    /// Offer may only be accepted once
+    #[serde(default)]
+    pub single_use: bool,
     pub signature: Vec<u8>,
 }
 
 impl PairingOffer {
-    /// Create new pairing offer
+    /// Create new pairing offer, ordering endpoints by preference
     pub fn new(
         device_id: DeviceId,
         device_name: String,
         public_key: Vec<u8>,
-        endpoints: Vec<String>,
+        mut endpoints: Vec<Endpoint>,
     ) -> Self {
+        Endpoint::sort_by_preference(&mut endpoints);
         let nonce = generate_nonce();
-        let timestamp = current_timestamp();
+        let timestamp = unix_time();
 
         Self {
             version: 1,
@@ -36,10 +65,79 @@ impl PairingOffer {
             endpoints,
             nonce,
             timestamp,
+            single_use: false,
             signature: vec![], // Will be signed separately
         }
     }
 
+    /// Length of the encoded URL once signed
+    pub fn estimated_url_len(&self) -> Result<usize> {
+        let mut signed = self.clone();
+        if signed.signature.is_empty() {
+            // Worst case JSON for a 64-byte signature
+            signed.signature = vec![u8::MAX; SIGNATURE_LEN];
+        }
+        Ok(encode_pairing_offer(&signed)?.len())
+    }
+
+    /// Trim optional detail until the offer fits `budget` URL bytes
+    ///
+    /// Shortens the device name to `MIN_DEVICE_NAME_CHARS`, then drops the
+    /// least preferred endpoints, keeping the first. Must run before
+    /// signing; returns whether the offer now fits.
+    pub fn trim_to_budget(&mut self, budget: usize) -> Result<bool> {
+        let name_len = self.device_name.chars().count();
+        if self.estimated_url_len()? > budget && name_len > MIN_DEVICE_NAME_CHARS {
+            let mut name: String = self
+                .device_name
+                .chars()
+                .take(MIN_DEVICE_NAME_CHARS - 1)
+                .collect();
+            name.push('…');
+            self.device_name = name;
+        }
+        while self.estimated_url_len()? > budget && self.endpoints.len() > 1 {
+            self.endpoints.pop();
+        }
+        Ok(self.estimated_url_len()? <= budget)
+    }
+
+    /// Mark offer as single-use
+    pub fn with_single_use(mut self, single_use: bool) -> Self {
+        self.single_use = single_use;
+        self
+    }
+
+    /// Sign offer with the offering device's keypair
+    pub fn sign(&mut self, keypair: &DeviceKeypair) -> Result<()> {
+        self.signature = keypair.sign(&self.signing_payload())?.to_bytes().to_vec();
+        Ok(())
+    }
+
+    /// Verify signature against the embedded public key and device ID
+    pub fn verify_signature(&self) -> Result<()> {
+        use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+        let public_key: [u8; 32] = self
+            .public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidKey)?;
+        let verifying_key =
+            VerifyingKey::from_bytes(&public_key).map_err(|_| CryptoError::InvalidKey)?;
+        if DeviceId::from_public_key(&verifying_key) != self.device_id {
+            return Err(CryptoError::InvalidOffer(
+                "Device ID does not match public key".into(),
+            ));
+        }
+
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::InvalidSignature)?;
+        verifying_key
+            .verify(&self.signing_payload(), &signature)
+            .map_err(|_| CryptoError::InvalidSignature)
+    }
+
     /// Get signing payload
     pub fn signing_payload(&self) -> Vec<u8> {
         let mut payload = Vec::new();
@@ -48,10 +146,11 @@ impl PairingOffer {
         payload.extend_from_slice(self.device_name.as_bytes());
         payload.extend_from_slice(&self.public_key);
         for endpoint in &self.endpoints {
-            payload.extend_from_slice(endpoint.as_bytes());
+            payload.extend_from_slice(endpoint.to_string().as_bytes());
         }
         payload.extend_from_slice(&self.nonce);
         payload.extend_from_slice(&self.timestamp.to_le_bytes());
+        payload.push(self.single_use as u8);
         payload
     }
 }
@@ -61,15 +160,22 @@ pub fn encode_pairing_offer(offer: &PairingOffer) -> Result<String> {
     let json = serde_json::to_string(offer)?;
     let compressed = compress_data(json.as_bytes());
     let encoded = base64_encode(&compressed);
-    Ok(format!("nomade://pair?v=1&d={}", encoded))
+    Ok(format!("{}{}", PAYLOAD_PREFIX, encoded))
 }
 
 /// Decode pairing offer from URL
 pub fn decode_pairing_offer(url: &str) -> Result<PairingOffer> {
     // Extract data parameter from URL
     let data = url
-        .strip_prefix("nomade://pair?v=1&d=")
+        .strip_prefix(PAYLOAD_PREFIX)
         .ok_or_else(|| crate::CryptoError::EncryptionFailed("Invalid URL format".into()))?;
+    if url.len() > MAX_OFFER_URL_LEN {
+        return Err(CryptoError::InvalidOffer(format!(
+            "Offer URL of {} bytes exceeds {}",
+            url.len(),
+            MAX_OFFER_URL_LEN
+        )));
+    }
 
     let compressed = base64_decode(data)?;
     let json = decompress_data(&compressed)?;
@@ -77,6 +183,116 @@ pub fn decode_pairing_offer(url: &str) -> Result<PairingOffer> {
     Ok(offer)
 }
 
+/// Encode an offer as one URL, or several part URLs if over `budget` bytes
+pub fn encode_pairing_offer_parts(offer: &PairingOffer, budget: usize) -> Result<Vec<String>> {
+    let url = encode_pairing_offer(offer)?;
+    if url.len() <= budget {
+        return Ok(vec![url]);
+    }
+
+    let data = &url[PAYLOAD_PREFIX.len()..];
+    let id: String = offer
+        .nonce
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    // "nomade://pair?v=1&p=NN/NN&id=xxxxxxxx&d="
+    let overhead = PART_PREFIX.len() + "NN/NN&id=".len() + id.len() + "&d=".len();
+    let chunk_size = budget
+        .checked_sub(overhead)
+        .filter(|n| *n > 0)
+        .ok_or_else(|| CryptoError::InvalidOffer(format!("QR budget {} too small", budget)))?;
+    let count = data.len().div_ceil(chunk_size);
+    if count > MAX_QR_PARTS {
+        return Err(CryptoError::InvalidOffer(format!(
+            "Offer needs {} QR parts (max {})",
+            count, MAX_QR_PARTS
+        )));
+    }
+
+    // Base64url data is ASCII, so byte chunks are valid strings
+    Ok(data
+        .as_bytes()
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            format!(
+                "{}{}/{}&id={}&d={}",
+                PART_PREFIX,
+                index + 1,
+                count,
+                id,
+                std::str::from_utf8(chunk).expect("base64 is ASCII")
+            )
+        })
+        .collect())
+}
+
+/// Collects scanned QR codes until a complete offer is available
+#[derive(Debug, Default, Clone)]
+pub struct QrReassembler {
+    id: String,
+    parts: Vec<Option<String>>,
+}
+
+impl QrReassembler {
+    /// Create an empty reassembler
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a scanned URL, returning the offer once every part was seen
+    ///
+    /// Single-part URLs decode immediately; a part from a different offer
+    /// restarts reassembly.
+    pub fn push(&mut self, url: &str) -> Result<Option<PairingOffer>> {
+        let Some(rest) = url.strip_prefix(PART_PREFIX) else {
+            return decode_pairing_offer(url).map(Some);
+        };
+        let malformed = || CryptoError::InvalidOffer("Malformed QR part".into());
+        let (position, rest) = rest.split_once("&id=").ok_or_else(malformed)?;
+        let (id, data) = rest.split_once("&d=").ok_or_else(malformed)?;
+        let (index, count) = position.split_once('/').ok_or_else(malformed)?;
+        let index: usize = index.parse().map_err(|_| malformed())?;
+        let count: usize = count.parse().map_err(|_| malformed())?;
+        if count == 0
+            || count > MAX_QR_PARTS
+            || index == 0
+            || index > count
+            || data.len() > QR_MAX_BYTES
+        {
+            return Err(malformed());
+        }
+
+        if self.id != id || self.parts.len() != count {
+            self.id = id.to_string();
+            self.parts = vec![None; count];
+        }
+        self.parts[index - 1] = Some(data.to_string());
+        if self.parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+
+        let data: String = std::mem::take(&mut self.parts)
+            .into_iter()
+            .flatten()
+            .collect();
+        self.id.clear();
+        decode_pairing_offer(&format!("{}{}", PAYLOAD_PREFIX, data)).map(Some)
+    }
+
+    /// Parts scanned so far and the total, if reassembly is in progress
+    pub fn progress(&self) -> Option<(usize, usize)> {
+        (!self.parts.is_empty()).then(|| {
+            (
+                self.parts.iter().filter(|p| p.is_some()).count(),
+                self.parts.len(),
+            )
+        })
+    }
+}
+
 // Helper functions
 
 fn generate_nonce() -> Vec<u8> {
@@ -86,14 +302,6 @@ fn generate_nonce() -> Vec<u8> {
     nonce
 }
 
-fn current_timestamp() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
-
 fn compress_data(data: &[u8]) -> Vec<u8> {
     // Placeholder: In production, use zstd compression
     // For now, just return data as-is
@@ -120,6 +328,7 @@ fn base64_decode(data: &str) -> Result<Vec<u8>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_encode_decode_pairing_offer() {
@@ -127,7 +336,7 @@ mod tests {
             DeviceId("test-device".into()),
             "Test Device".into(),
             vec![1, 2, 3, 4],
-            vec!["192.168.1.100:8765".into()],
+            vec!["192.168.1.100:8765".parse().unwrap()],
         );
 
         let encoded = encode_pairing_offer(&offer).unwrap();
@@ -135,6 +344,145 @@ mod tests {
 
         let decoded = decode_pairing_offer(&encoded).unwrap();
         assert_eq!(decoded.device_name, "Test Device");
-        assert_eq!(decoded.endpoints, vec!["192.168.1.100:8765"]);
+        assert_eq!(decoded.endpoints[0].to_string(), "192.168.1.100:8765");
+    }
+
+    #[test]
+    fn test_sign_and_verify_offer() {
+        let keypair = crate::generate_keypair();
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            "Test Device".into(),
+            keypair.public_key_bytes(),
+            vec![],
+        )
+        .with_single_use(true);
+
+        offer.sign(&keypair).unwrap();
+        assert!(offer.verify_signature().is_ok());
+
+        // Stripping the single-use flag invalidates the signature
+        offer.single_use = false;
+        assert!(offer.verify_signature().is_err());
+    }
+
+    #[test]
+    fn test_trim_to_budget() {
+        let keypair = crate::generate_keypair();
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            "A very long device name that nobody will read in full".into(),
+            keypair.public_key_bytes(),
+            (0..8)
+                .map(|i| format!("192.168.1.{}:8765", i).parse().unwrap())
+                .collect(),
+        );
+        let budget = offer.estimated_url_len().unwrap() - 200;
+        assert!(offer.trim_to_budget(budget).unwrap());
+        assert_eq!(offer.device_name.chars().count(), MIN_DEVICE_NAME_CHARS);
+        assert_eq!(offer.endpoints[0].to_string(), "192.168.1.0:8765");
+        assert!(offer.endpoints.len() < 8);
+
+        offer.sign(&keypair).unwrap();
+        assert!(encode_pairing_offer(&offer).unwrap().len() <= budget);
+        assert!(!offer.clone().trim_to_budget(10).unwrap());
+    }
+
+    #[test]
+    fn test_multi_part_qr_roundtrip() {
+        let keypair = crate::generate_keypair();
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            "Laptop".into(),
+            keypair.public_key_bytes(),
+            (0..20)
+                .map(|i| format!("[2001:db8::{}]:8765", i).parse().unwrap())
+                .collect(),
+        );
+        offer.sign(&keypair).unwrap();
+
+        let single = encode_pairing_offer_parts(&offer, QR_MAX_BYTES).unwrap();
+        assert_eq!(single.len(), 1);
+        let parts = encode_pairing_offer_parts(&offer, 300).unwrap();
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|p| p.len() <= 300));
+        assert!(parts[0].starts_with("nomade://pair?v=1&p=1/"));
+
+        // Parts may be scanned in any order, with repeats and stray codes
+        let mut reassembler = QrReassembler::new();
+        assert!(reassembler.push(&parts[1]).unwrap().is_none());
+        assert!(reassembler.push(&parts[1]).unwrap().is_none());
+        assert_eq!(reassembler.progress(), Some((1, parts.len())));
+        let mut decoded = None;
+        for part in parts.iter().rev() {
+            decoded = reassembler.push(part).unwrap().or(decoded);
+        }
+        let decoded = decoded.unwrap();
+        assert!(decoded.verify_signature().is_ok());
+        assert_eq!(decoded.endpoints, offer.endpoints);
+        assert!(reassembler.progress().is_none());
+
+        assert!(reassembler.push("nomade://pair?v=1&p=0/2&id=x&d=").is_err());
+        assert!(matches!(
+            encode_pairing_offer_parts(&offer, 40),
+            Err(CryptoError::InvalidOffer(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn offer_roundtrip(
+            device_name in ".{0,32}",
+            endpoints in prop::collection::vec(
+                prop_oneof![
+                    (any::<[u8; 4]>(), any::<u16>())
+                        .prop_map(|(ip, port)| format!("{}:{}", std::net::Ipv4Addr::from(ip), port)),
+                    (any::<[u16; 8]>(), any::<u16>())
+                        .prop_map(|(ip, port)| format!("[{}]:{}", std::net::Ipv6Addr::from(ip), port)),
+                    "[a-z][a-z0-9-]{0,11}\\.local:[0-9]{1,4}",
+                    "relay:[a-z0-9-]{1,12}",
+                ]
+                .prop_map(|s| s.parse::<Endpoint>().unwrap()),
+                0..4,
+            ),
+            single_use in any::<bool>(),
+        ) {
+            let keypair = crate::generate_keypair();
+            let mut offer = PairingOffer::new(
+                keypair.device_id().clone(),
+                device_name,
+                keypair.public_key_bytes(),
+                endpoints,
+            )
+            .with_single_use(single_use);
+            offer.sign(&keypair).unwrap();
+
+            let decoded = decode_pairing_offer(&encode_pairing_offer(&offer).unwrap()).unwrap();
+            prop_assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&offer).unwrap()
+            );
+            prop_assert!(decoded.verify_signature().is_ok());
+        }
+
+        #[test]
+        fn offer_decode_never_panics(data in ".{0,128}") {
+            let _ = decode_pairing_offer(&data);
+            let _ = decode_pairing_offer(&format!("nomade://pair?v=1&d={}", data));
+            let _ = QrReassembler::new().push(&format!("nomade://pair?v=1&p={}", data));
+        }
+    }
+
+    #[test]
+    fn test_oversized_offers_are_rejected() {
+        let huge = format!("{}{}", PAYLOAD_PREFIX, "A".repeat(MAX_OFFER_URL_LEN));
+        assert!(matches!(
+            decode_pairing_offer(&huge),
+            Err(CryptoError::InvalidOffer(_))
+        ));
+        let part = format!("{}1/2&id=x&d={}", PART_PREFIX, "A".repeat(QR_MAX_BYTES + 1));
+        let mut reassembler = QrReassembler::new();
+        assert!(reassembler.push(&part).is_err());
+        assert_eq!(reassembler.progress(), None);
     }
 }
@@ -0,0 +1,423 @@
+//! Double-ratchet session encryption
+//!
+//! A paired session starts from one shared secret (e.g. the PAKE session
+//! key) and derives a fresh key for every message:
+//! - a symmetric ratchet (HMAC-SHA256 chain) advances per message
+//! - a DH ratchet (X25519) mixes new key agreements into the root key each
+//!   time the speaking direction changes
+//!
+//! Compromising the current state therefore exposes neither past messages
+//! nor, after the next round trip, future ones. Messages may arrive out of
+//! order: keys for skipped messages are kept (bounded) until used.
+//!
+//! Both sides derive the same session `id` from the shared secret, so they
+//! can tell whether they still hold the same session. Links seed one from
+//! their link key and keep it per peer between connections (see
+//! `nomade_core`'s envelope module); callers persist sessions themselves.
+
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::encryption::{decrypt_error, open_aead};
+use crate::fs::write_durable;
+use crate::kdf::{derive_for, KeyPurpose};
+use crate::{decrypt_data, encrypt_data, CryptoError, DecryptError, EncryptedData, Result};
+
+/// Most message keys skipped in one chain before a message is rejected
+pub const MAX_SKIP: u32 = 1000;
+
+/// Most skipped message keys kept across chains
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+/// Encoded size of a `RatchetHeader`
+const HEADER_SIZE: usize = 40;
+
+/// Cleartext header authenticating each ratchet message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatchetHeader {
+    /// Sender's current ratchet public key
+    pub dh: [u8; 32],
+    /// Messages sent in the sender's previous chain
+    pub previous: u32,
+    /// Index of this message in the current chain
+    pub n: u32,
+}
+
+impl RatchetHeader {
+    fn to_bytes(self) -> Vec<u8> {
+        [
+            self.dh.as_slice(),
+            &self.previous.to_be_bytes(),
+            &self.n.to_be_bytes(),
+        ]
+        .concat()
+    }
+}
+
+/// Encrypted message produced by a `RatchetSession`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatchetMessage {
+    pub header: RatchetHeader,
+    pub ciphertext: Vec<u8>,
+}
+
+impl RatchetMessage {
+    /// Compact binary encoding: the header, then the ciphertext
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.header.to_bytes(), self.ciphertext.clone()].concat()
+    }
+
+    /// Decode a message written with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(decrypt_error(
+                DecryptError::Truncated,
+                "short ratchet message",
+            ));
+        }
+        let (header, ciphertext) = bytes.split_at(HEADER_SIZE);
+        Ok(Self {
+            header: RatchetHeader {
+                dh: header[..32].try_into().unwrap(),
+                previous: u32::from_be_bytes(header[32..36].try_into().unwrap()),
+                n: u32::from_be_bytes(header[36..].try_into().unwrap()),
+            },
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    dh: [u8; 32],
+    n: u32,
+    key: [u8; 32],
+}
+
+/// One side of a ratcheting session; serializable for persistence
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RatchetSession {
+    id: [u8; 16],
+    root: [u8; 32],
+    dh_secret: [u8; 32],
+    dh_public: [u8; 32],
+    remote_dh: Option<[u8; 32]>,
+    send_chain: Option<[u8; 32]>,
+    recv_chain: Option<[u8; 32]>,
+    sent: u32,
+    received: u32,
+    previous_sent: u32,
+    skipped: Vec<SkippedKey>,
+}
+
+impl RatchetSession {
+    /// Session for the side that sends first
+    pub fn initiator(shared_secret: &[u8; 32]) -> Self {
+        let (_, responder_public) = responder_keypair(shared_secret);
+        let dh_secret = random_secret();
+        let (root, send_chain) = kdf_root(shared_secret, &dh(&dh_secret, &responder_public));
+        Self {
+            id: session_id(shared_secret),
+            root,
+            dh_secret,
+            dh_public: MontgomeryPoint::mul_base_clamped(dh_secret).to_bytes(),
+            remote_dh: Some(responder_public),
+            send_chain: Some(send_chain),
+            recv_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Session for the side that receives first
+    ///
+    /// It can send once the initiator's first message was decrypted.
+    pub fn responder(shared_secret: &[u8; 32]) -> Self {
+        let (dh_secret, dh_public) = responder_keypair(shared_secret);
+        Self {
+            id: session_id(shared_secret),
+            root: *shared_secret,
+            dh_secret,
+            dh_public,
+            remote_dh: None,
+            send_chain: None,
+            recv_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Identifier of the session, the same on both sides
+    pub fn id(&self) -> [u8; 16] {
+        self.id
+    }
+
+    /// Encrypt a message under the next message key
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage> {
+        let chain = self.send_chain.ok_or_else(|| {
+            CryptoError::EncryptionFailed("Ratchet has not received a message yet".into())
+        })?;
+        let (chain, message_key) = kdf_chain(&chain);
+        let header = RatchetHeader {
+            dh: self.dh_public,
+            previous: self.previous_sent,
+            n: self.sent,
+        };
+        let (key, nonce) = message_cipher(&message_key);
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &header.to_bytes(),
+                },
+            )
+            .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+
+        self.send_chain = Some(chain);
+        self.sent += 1;
+        Ok(RatchetMessage { header, ciphertext })
+    }
+
+    /// Decrypt a message, advancing the ratchets
+    ///
+    /// The session is unchanged if the message is rejected.
+    pub fn decrypt(&mut self, message: &RatchetMessage) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let plaintext = next.decrypt_in_place(message)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn decrypt_in_place(&mut self, message: &RatchetMessage) -> Result<Vec<u8>> {
+        let header = message.header;
+        if let Some(pos) = self
+            .skipped
+            .iter()
+            .position(|s| s.dh == header.dh && s.n == header.n)
+        {
+            let skipped = self.skipped.remove(pos);
+            return open(&skipped.key, message);
+        }
+
+        if self.remote_dh != Some(header.dh) {
+            self.skip_until(header.previous)?;
+            self.dh_ratchet(&header.dh);
+        }
+        self.skip_until(header.n)?;
+
+        let chain = self
+            .recv_chain
+            .ok_or_else(|| decrypt_error(DecryptError::WrongKey, "no receiving chain"))?;
+        let (chain, message_key) = kdf_chain(&chain);
+        let plaintext = open(&message_key, message)?;
+        self.recv_chain = Some(chain);
+        self.received += 1;
+        Ok(plaintext)
+    }
+
+    /// Store keys of messages up to `until` in the current receiving chain
+    fn skip_until(&mut self, until: u32) -> Result<()> {
+        let (Some(mut chain), Some(remote)) = (self.recv_chain, self.remote_dh) else {
+            return Ok(());
+        };
+        if until > self.received.saturating_add(MAX_SKIP) {
+            return Err(decrypt_error(
+                DecryptError::Tampered,
+                format_args!("message {} skips past {}", until, self.received),
+            ));
+        }
+        while self.received < until {
+            let (next, key) = kdf_chain(&chain);
+            self.skipped.push(SkippedKey {
+                dh: remote,
+                n: self.received,
+                key,
+            });
+            chain = next;
+            self.received += 1;
+        }
+        self.recv_chain = Some(chain);
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            let excess = self.skipped.len() - MAX_SKIPPED_KEYS;
+            self.skipped.drain(..excess);
+        }
+        Ok(())
+    }
+
+    fn dh_ratchet(&mut self, remote: &[u8; 32]) {
+        self.previous_sent = self.sent;
+        self.sent = 0;
+        self.received = 0;
+        self.remote_dh = Some(*remote);
+
+        let (root, recv_chain) = kdf_root(&self.root, &dh(&self.dh_secret, remote));
+        self.dh_secret = random_secret();
+        self.dh_public = MontgomeryPoint::mul_base_clamped(self.dh_secret).to_bytes();
+        let (root, send_chain) = kdf_root(&root, &dh(&self.dh_secret, remote));
+
+        self.root = root;
+        self.recv_chain = Some(recv_chain);
+        self.send_chain = Some(send_chain);
+    }
+
+    /// Encrypt the session state under `key` and write it durably
+    pub fn save(&self, path: impl AsRef<Path>, key: &[u8; 32]) -> Result<()> {
+        let encrypted = encrypt_data(&serde_json::to_vec(self)?, key)?;
+        write_durable(path.as_ref(), &serde_json::to_vec(&encrypted)?)?;
+        Ok(())
+    }
+
+    /// Load a session written with `save`
+    pub fn load(path: impl AsRef<Path>, key: &[u8; 32]) -> Result<Self> {
+        let encrypted: EncryptedData = serde_json::from_slice(&std::fs::read(path)?)?;
+        Ok(serde_json::from_slice(&decrypt_data(&encrypted, key)?)?)
+    }
+}
+
+fn open(message_key: &[u8; 32], message: &RatchetMessage) -> Result<Vec<u8>> {
+    let (key, nonce) = message_cipher(message_key);
+    open_aead(
+        &key,
+        &nonce,
+        &message.ciphertext,
+        &message.header.to_bytes(),
+    )
+}
+
+fn session_id(shared_secret: &[u8; 32]) -> [u8; 16] {
+    derive_for(KeyPurpose::RatchetId, shared_secret, &[])[..16]
+        .try_into()
+        .unwrap()
+}
+
+/// Initial ratchet keypair of the responder, known to both sides
+fn responder_keypair(shared_secret: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let secret = derive_for(KeyPurpose::RatchetResponder, shared_secret, &[]);
+    (secret, MontgomeryPoint::mul_base_clamped(secret).to_bytes())
+}
+
+fn random_secret() -> [u8; 32] {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+fn dh(secret: &[u8; 32], public: &[u8; 32]) -> [u8; 32] {
+    MontgomeryPoint(*public).mul_clamped(*secret).to_bytes()
+}
+
+/// Mix a DH output into the root key, returning (root, chain)
+fn kdf_root(root: &[u8; 32], dh_output: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let okm = hkdf_expand::<64>(dh_output, root, KeyPurpose::RatchetRoot);
+    (okm[..32].try_into().unwrap(), okm[32..].try_into().unwrap())
+}
+
+/// Advance a chain key, returning (chain, message key)
+fn kdf_chain(chain: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let step = |byte: u8| -> [u8; 32] {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(chain).expect("HMAC accepts any key length");
+        mac.update(&[byte]);
+        mac.finalize().into_bytes().into()
+    };
+    (step(0x02), step(0x01))
+}
+
+/// AES key and nonce for one message key
+fn message_cipher(message_key: &[u8; 32]) -> ([u8; 32], [u8; 12]) {
+    let okm = hkdf_expand::<44>(message_key, &[0u8; 32], KeyPurpose::RatchetMessage);
+    (okm[..32].try_into().unwrap(), okm[32..].try_into().unwrap())
+}
+
+fn hkdf_expand<const N: usize>(ikm: &[u8], salt: &[u8], purpose: KeyPurpose) -> [u8; N] {
+    let mut okm = [0u8; N];
+    hkdf::Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(purpose.info(), &mut okm)
+        .expect("HKDF output length is valid");
+    okm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (RatchetSession, RatchetSession) {
+        let shared = [5u8; 32];
+        (
+            RatchetSession::initiator(&shared),
+            RatchetSession::responder(&shared),
+        )
+    }
+
+    #[test]
+    fn test_conversation_ratchets_keys() {
+        let (mut laptop, mut phone) = pair();
+        assert!(phone.encrypt(b"too early").is_err());
+
+        let first = laptop.encrypt(b"hello").unwrap();
+        let second = laptop.encrypt(b"hello").unwrap();
+        assert_ne!(first.ciphertext, second.ciphertext);
+        assert_eq!(phone.decrypt(&first).unwrap(), b"hello");
+        assert_eq!(phone.decrypt(&second).unwrap(), b"hello");
+
+        let reply = phone.encrypt(b"hi").unwrap();
+        assert_ne!(reply.header.dh, first.header.dh);
+        assert_eq!(laptop.decrypt(&reply).unwrap(), b"hi");
+        let next = laptop.encrypt(b"again").unwrap();
+        assert_ne!(next.header.dh, first.header.dh);
+        assert_eq!(phone.decrypt(&next).unwrap(), b"again");
+
+        // Replays find no key
+        assert!(phone.decrypt(&next).is_err());
+    }
+
+    #[test]
+    fn test_out_of_order_and_tampering() {
+        let (mut laptop, mut phone) = pair();
+        let messages: Vec<_> = (0..4).map(|i| laptop.encrypt(&[i]).unwrap()).collect();
+
+        let mut tampered = messages[3].clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(phone.decrypt(&tampered).is_err());
+
+        for i in [3usize, 0, 2, 1] {
+            assert_eq!(phone.decrypt(&messages[i]).unwrap(), vec![i as u8]);
+        }
+
+        let mut far = laptop.encrypt(b"far").unwrap();
+        far.header.n += MAX_SKIP + 1;
+        assert!(phone.decrypt(&far).is_err());
+
+        let message = laptop.encrypt(b"bytes").unwrap();
+        let bytes = message.to_bytes();
+        assert_eq!(RatchetMessage::from_bytes(&bytes).unwrap(), message);
+        assert!(RatchetMessage::from_bytes(&bytes[..HEADER_SIZE - 1]).is_err());
+        assert_eq!(laptop.id(), phone.id());
+    }
+
+    #[test]
+    fn test_session_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("peer.ratchet");
+        let storage_key = [9u8; 32];
+        let (mut laptop, phone) = pair();
+
+        phone.save(&path, &storage_key).unwrap();
+        let mut phone = RatchetSession::load(&path, &storage_key).unwrap();
+        let message = laptop.encrypt(b"after restart").unwrap();
+        assert_eq!(phone.decrypt(&message).unwrap(), b"after restart");
+        assert!(RatchetSession::load(&path, &[1u8; 32]).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! Keys for data handed over outside a paired session
+//!
+//! Two ways to obtain a 256-bit content key without a live connection:
+//! - from a password, stretched with Argon2id and a random salt
+//! - sealed to a device's public identity key: an ephemeral X25519 key
+//!   agreement against the Montgomery form of the recipient's Ed25519 key
+
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use rand::RngCore;
+
+use crate::kdf::{derive_for, KeyPurpose};
+use crate::{ct_eq, CryptoError, DeviceKeypair, Result};
+
+/// Bytes of salt for `password_key`
+pub const SALT_LEN: usize = 16;
+
+/// Random salt for `password_key`
+pub fn generate_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+/// Derive a key from a password with Argon2id (default parameters)
+pub fn password_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| CryptoError::EncryptionFailed(e.to_string()))?;
+    Ok(key)
+}
+
+/// Key sealed to a recipient's Ed25519 public key
+///
+/// Returns the ephemeral public key to transmit alongside the data and
+/// the derived content key.
+pub fn seal_key(recipient_public_key: &[u8]) -> Result<([u8; 32], [u8; 32])> {
+    let recipient = montgomery(recipient_public_key)?;
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let ephemeral = Scalar::from_bytes_mod_order(clamp(secret));
+    let ephemeral_public = MontgomeryPoint::mul_base(&ephemeral).to_bytes();
+    let shared = (ephemeral * recipient).to_bytes();
+    Ok((
+        ephemeral_public,
+        seal_derive(&shared, &ephemeral_public, recipient_public_key),
+    ))
+}
+
+/// Recover a key sealed to this device with `seal_key`
+pub fn open_sealed_key(keypair: &DeviceKeypair, ephemeral_public: &[u8; 32]) -> Result<[u8; 32]> {
+    let scalar = keypair.signing_key()?.to_scalar();
+    let shared = (scalar * MontgomeryPoint(*ephemeral_public)).to_bytes();
+    if ct_eq(&shared, &[0u8; 32]) {
+        return Err(CryptoError::InvalidKey);
+    }
+    Ok(seal_derive(
+        &shared,
+        ephemeral_public,
+        &keypair.public_key_bytes(),
+    ))
+}
+
+fn seal_derive(shared: &[u8; 32], ephemeral_public: &[u8; 32], recipient: &[u8]) -> [u8; 32] {
+    let salt = [ephemeral_public.as_slice(), recipient].concat();
+    derive_for(KeyPurpose::Seal, shared, &salt)
+}
+
+fn montgomery(ed25519_public_key: &[u8]) -> Result<MontgomeryPoint> {
+    let bytes: [u8; 32] = ed25519_public_key
+        .try_into()
+        .map_err(|_| CryptoError::InvalidKey)?;
+    CompressedEdwardsY(bytes)
+        .decompress()
+        .map(|point| point.to_montgomery())
+        .ok_or(CryptoError::InvalidKey)
+}
+
+fn clamp(mut bytes: [u8; 32]) -> [u8; 32] {
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn test_sealed_key_opens_only_for_recipient() {
+        let recipient = generate_keypair();
+        let (ephemeral, key) = seal_key(&recipient.public_key_bytes()).unwrap();
+        assert_eq!(open_sealed_key(&recipient, &ephemeral).unwrap(), key);
+        assert_ne!(
+            open_sealed_key(&generate_keypair(), &ephemeral).unwrap(),
+            key
+        );
+        assert!(seal_key(&[0u8; 5]).is_err());
+    }
+
+    #[test]
+    fn test_password_key() {
+        let salt = generate_salt();
+        let key = password_key("correct horse", &salt).unwrap();
+        assert_eq!(password_key("correct horse", &salt).unwrap(), key);
+        assert_ne!(password_key("wrong horse", &salt).unwrap(), key);
+    }
+}
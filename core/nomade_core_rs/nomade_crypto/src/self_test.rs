@@ -0,0 +1,276 @@
+//! Known-answer self-test of the cryptographic primitives
+//!
+//! `self_test` runs the primitives Nomade relies on against published
+//! test vectors: AES-256-GCM (NIST GCM specification test cases),
+//! HKDF-SHA256 (RFC 5869), Ed25519 (RFC 8032) and BLAKE3 (reference
+//! implementation). It goes through the same wrappers the rest of the
+//! crate uses, so a miscompiled or swapped-out backend is caught before
+//! any key or message is handled.
+//!
+//! ChaCha20-Poly1305 is not used in Nomade, so `self_test` skips it and the
+//! crate does not ship it; the tests check the RFC 8439 vector against the
+//! `chacha20poly1305` crate instead, a dev-dependency.
+
+use ed25519_dalek::Signature;
+
+use crate::encryption::{derive_key, open_aead, seal, NONCE_SIZE};
+use crate::{CryptoError, DeviceKeypair, Result};
+
+/// AES-256-GCM encryption without associated data
+struct GcmSeal {
+    name: &'static str,
+    key: &'static str,
+    nonce: &'static str,
+    plaintext: &'static str,
+    /// Ciphertext followed by the tag
+    sealed: &'static str,
+}
+
+const GCM_SEAL: &[GcmSeal] = &[
+    GcmSeal {
+        name: "AES-256-GCM test case 14",
+        key: "0000000000000000000000000000000000000000000000000000000000000000",
+        nonce: "000000000000000000000000",
+        plaintext: "00000000000000000000000000000000",
+        sealed: "cea7403d4d606b6e074ec5d3baf39d18d0d1c8a799996bf0265b98b5d48ab919",
+    },
+    GcmSeal {
+        name: "AES-256-GCM test case 15",
+        key: "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+        nonce: "cafebabefacedbaddecaf888",
+        plaintext: "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                    1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255",
+        sealed: "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+                 8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad\
+                 b094dac5d93471bdec1a502270e3cc6c",
+    },
+];
+
+/// AES-256-GCM decryption with associated data
+struct GcmOpen {
+    name: &'static str,
+    key: &'static str,
+    nonce: &'static str,
+    aad: &'static str,
+    sealed: &'static str,
+    plaintext: &'static str,
+}
+
+const GCM_OPEN: &[GcmOpen] = &[GcmOpen {
+    name: "AES-256-GCM test case 16",
+    key: "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+    nonce: "cafebabefacedbaddecaf888",
+    aad: "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+    sealed: "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+             8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662\
+             76fc6ece0f4e1768cddf8853bb2d551b",
+    plaintext: "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+                1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+}];
+
+/// HKDF-SHA256, first 32 bytes of the output keying material
+struct Hkdf {
+    name: &'static str,
+    ikm: &'static str,
+    salt: &'static str,
+    info: &'static str,
+    okm: &'static str,
+}
+
+const HKDF: &[Hkdf] = &[
+    Hkdf {
+        name: "HKDF-SHA256 RFC 5869 test case 1",
+        ikm: "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        salt: "000102030405060708090a0b0c",
+        info: "f0f1f2f3f4f5f6f7f8f9",
+        okm: "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf",
+    },
+    Hkdf {
+        name: "HKDF-SHA256 RFC 5869 test case 3",
+        ikm: "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b",
+        salt: "",
+        info: "",
+        okm: "8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d",
+    },
+];
+
+/// Ed25519 key derivation and signature
+struct Ed25519 {
+    name: &'static str,
+    secret: &'static str,
+    public: &'static str,
+    message: &'static str,
+    signature: &'static str,
+}
+
+const ED25519: &[Ed25519] = &[
+    Ed25519 {
+        name: "Ed25519 RFC 8032 test 1",
+        secret: "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        public: "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        message: "",
+        signature: "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+                    5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+    },
+    Ed25519 {
+        name: "Ed25519 RFC 8032 test 2",
+        secret: "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        public: "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+        message: "72",
+        signature: "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+                    085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+    },
+];
+
+/// BLAKE3 hash of the empty input
+const BLAKE3_EMPTY: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+/// Check every primitive against its test vectors
+///
+/// Fails with `CryptoError::SelfTestFailed` naming the first vector that
+/// does not match.
+pub fn self_test() -> Result<()> {
+    for vector in GCM_SEAL {
+        let key: [u8; 32] = fixed(vector.name, vector.key)?;
+        let nonce: [u8; NONCE_SIZE] = fixed(vector.name, vector.nonce)?;
+        let sealed = seal(&unhex(vector.plaintext), &key, &nonce)
+            .map_err(|e| failed(vector.name, e))?
+            .ciphertext;
+        expect(vector.name, sealed == unhex(vector.sealed))?;
+    }
+    for vector in GCM_OPEN {
+        let key: [u8; 32] = fixed(vector.name, vector.key)?;
+        let opened = open_aead(
+            &key,
+            &unhex(vector.nonce),
+            &unhex(vector.sealed),
+            &unhex(vector.aad),
+        )
+        .map_err(|e| failed(vector.name, e))?;
+        expect(vector.name, opened == unhex(vector.plaintext))?;
+        // One flipped bit in the associated data must fail authentication
+        let mut aad = unhex(vector.aad);
+        aad[0] ^= 1;
+        let forged = open_aead(&key, &unhex(vector.nonce), &unhex(vector.sealed), &aad);
+        expect(vector.name, forged.is_err())?;
+    }
+    for vector in HKDF {
+        let okm = derive_key(&unhex(vector.ikm), &unhex(vector.salt), &unhex(vector.info));
+        expect(vector.name, okm.to_vec() == unhex(vector.okm))?;
+    }
+    for vector in ED25519 {
+        let keypair = DeviceKeypair::from_secret_bytes(&unhex(vector.secret))
+            .map_err(|e| failed(vector.name, e))?;
+        expect(
+            vector.name,
+            keypair.public_key_bytes() == unhex(vector.public),
+        )?;
+        let message = unhex(vector.message);
+        let signature = keypair.sign(&message).map_err(|e| failed(vector.name, e))?;
+        expect(
+            vector.name,
+            signature.to_bytes().to_vec() == unhex(vector.signature),
+        )?;
+        let expected = Signature::from_bytes(&fixed(vector.name, vector.signature)?);
+        expect(vector.name, keypair.verify(&message, &expected).is_ok())?;
+        expect(vector.name, keypair.verify(b"forged", &expected).is_err())?;
+    }
+    expect(
+        "BLAKE3 empty input",
+        blake3::hash(b"").to_hex().as_str() == BLAKE3_EMPTY,
+    )?;
+    tracing::debug!("Crypto self-test passed");
+    Ok(())
+}
+
+fn expect(name: &str, passed: bool) -> Result<()> {
+    if passed {
+        Ok(())
+    } else {
+        Err(failed(name, "output does not match"))
+    }
+}
+
+fn failed(name: &str, reason: impl std::fmt::Display) -> CryptoError {
+    tracing::error!("Crypto self-test failed on {}: {}", name, reason);
+    CryptoError::SelfTestFailed(name.to_string())
+}
+
+fn fixed<const N: usize>(name: &str, hex: &str) -> Result<[u8; N]> {
+    unhex(hex)
+        .try_into()
+        .map_err(|_| failed(name, "vector has the wrong length"))
+}
+
+/// Decode a vector; whitespace from line continuations is skipped
+fn unhex(hex: &str) -> Vec<u8> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).expect("vectors are ASCII");
+            u8::from_str_radix(pair, 16).expect("vectors are valid hex")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        self_test().unwrap();
+    }
+
+    #[test]
+    fn test_mismatch_names_the_vector() {
+        let error = expect("HKDF-SHA256 RFC 5869 test case 1", false).unwrap_err();
+        assert!(matches!(
+            error,
+            CryptoError::SelfTestFailed(ref name) if name.contains("RFC 5869")
+        ));
+        assert_eq!(unhex("00ff\n    10"), [0x00, 0xff, 0x10]);
+    }
+
+    #[test]
+    fn test_chacha20_poly1305_rfc_8439() {
+        use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+        use chacha20poly1305::ChaCha20Poly1305;
+
+        // RFC 8439 section 2.8.2
+        let key = unhex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = unhex("070000004041424344454647");
+        let aad = unhex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+                          only one tip for the future, sunscreen would be it.";
+        let sealed = unhex(
+            "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d6\
+             3dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b36\
+             92ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc\
+             3ff4def08e4b7a9de576d26586cec64b6116\
+             1ae10b594f09e26a7e902ecbd0600691",
+        );
+
+        let cipher = ChaCha20Poly1305::new_from_slice(&key).unwrap();
+        let nonce = chacha20poly1305::Nonce::from_slice(&nonce);
+        let payload = Payload {
+            msg: plaintext,
+            aad: &aad,
+        };
+        assert_eq!(cipher.encrypt(nonce, payload).unwrap(), sealed);
+        let payload = Payload {
+            msg: &sealed,
+            aad: &aad,
+        };
+        assert_eq!(cipher.decrypt(nonce, payload).unwrap(), plaintext);
+        // One flipped bit in the associated data must fail authentication
+        let mut forged = aad.clone();
+        forged[0] ^= 1;
+        let payload = Payload {
+            msg: &sealed,
+            aad: &forged,
+        };
+        assert!(cipher.decrypt(nonce, payload).is_err());
+    }
+}
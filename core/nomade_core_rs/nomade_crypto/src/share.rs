@@ -0,0 +1,306 @@
+//! Expiring share tokens for single artifacts
+//!
+//! A `ShareToken` is a capability letting someone who is not a paired
+//! device fetch one artifact. It names the artifact and an expiry, carries
+//! a fresh key the content is encrypted under in transit, and is signed by
+//! the issuing device. The issuer keeps a `ShareRegistry` of the tokens it
+//! handed out so they can be listed and revoked before they expire.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::fs::write_durable;
+use crate::{generate_key, CryptoError, DeviceId, DeviceKeypair, Result};
+
+/// Prefix of encoded tokens, so they can be recognized when pasted
+const TOKEN_PREFIX: &str = "nomade-share:";
+/// Longest encoded token decoded
+const MAX_TOKEN_LEN: usize = 4096;
+
+/// Signed capability to fetch one artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareToken {
+    pub id: String,
+    pub artifact_id: String,
+    pub issuer: DeviceId,
+    /// Seconds since the Unix epoch
+    pub expires_at: u64,
+    /// Key the shared content is encrypted under
+    pub key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl ShareToken {
+    /// Issue a token for `artifact_id` valid for `ttl`
+    pub fn issue(signer: &DeviceKeypair, artifact_id: String, ttl: Duration) -> Result<Self> {
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let mut token = Self {
+            id: id.iter().map(|b| format!("{:02x}", b)).collect(),
+            artifact_id,
+            issuer: signer.device_id().clone(),
+            expires_at: unix_time() + ttl.as_secs(),
+            key: generate_key().to_vec(),
+            signature: vec![],
+        };
+        token.signature = signer.sign(&token.signing_payload())?.to_bytes().to_vec();
+        Ok(token)
+    }
+
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-share-v1");
+        payload.extend_from_slice(self.id.as_bytes());
+        payload.extend_from_slice(&(self.artifact_id.len() as u32).to_le_bytes());
+        payload.extend_from_slice(self.artifact_id.as_bytes());
+        payload.extend_from_slice(self.issuer.0.as_bytes());
+        payload.extend_from_slice(&self.expires_at.to_le_bytes());
+        payload.extend_from_slice(&self.key);
+        payload
+    }
+
+    /// Verify the issuer's signature and that the token has not expired
+    pub fn verify(&self, issuer: &VerifyingKey) -> Result<()> {
+        if DeviceId::from_public_key(issuer) != self.issuer {
+            return Err(CryptoError::InvalidSignature);
+        }
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::InvalidSignature)?;
+        issuer
+            .verify(&self.signing_payload(), &signature)
+            .map_err(|_| CryptoError::InvalidSignature)?;
+        if self.is_expired() {
+            return Err(CryptoError::ShareTokenExpired);
+        }
+        Ok(())
+    }
+
+    /// Whether the token is past its expiry
+    pub fn is_expired(&self) -> bool {
+        unix_time() >= self.expires_at
+    }
+
+    /// Content key carried by the token
+    pub fn content_key(&self) -> Result<[u8; 32]> {
+        self.key
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidKey)
+    }
+
+    /// Encode as a string that can be sent as a link
+    pub fn encode(&self) -> Result<String> {
+        use base64::{engine::general_purpose, Engine as _};
+        let json = serde_json::to_vec(self)?;
+        Ok(format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            general_purpose::URL_SAFE_NO_PAD.encode(json)
+        ))
+    }
+
+    /// Decode a string produced by `encode`
+    pub fn decode(encoded: &str) -> Result<Self> {
+        use base64::{engine::general_purpose, Engine as _};
+        if encoded.len() > MAX_TOKEN_LEN {
+            return Err(CryptoError::InvalidShareToken("Token too long".into()));
+        }
+        let data = encoded
+            .trim()
+            .strip_prefix(TOKEN_PREFIX)
+            .ok_or_else(|| CryptoError::InvalidShareToken("Missing prefix".into()))?;
+        let json = general_purpose::URL_SAFE_NO_PAD
+            .decode(data)
+            .map_err(|e| CryptoError::InvalidShareToken(e.to_string()))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// Issued token as remembered by the issuer, without its key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuedShare {
+    pub id: String,
+    pub artifact_id: String,
+    pub expires_at: u64,
+    pub revoked: bool,
+}
+
+/// Tokens issued by this device
+#[derive(Debug)]
+pub struct ShareRegistry {
+    issuer: VerifyingKey,
+    shares: BTreeMap<String, IssuedShare>,
+    path: Option<PathBuf>,
+}
+
+impl ShareRegistry {
+    /// Create in-memory registry for tokens signed by `issuer`
+    pub fn new(issuer: VerifyingKey) -> Self {
+        Self {
+            issuer,
+            shares: BTreeMap::new(),
+            path: None,
+        }
+    }
+
+    /// Open registry persisted at `path`, creating it if missing
+    pub fn open(issuer: VerifyingKey, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let shares = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            issuer,
+            shares,
+            path: Some(path),
+        })
+    }
+
+    /// Issue and record a token for `artifact_id`
+    pub fn issue(
+        &mut self,
+        signer: &DeviceKeypair,
+        artifact_id: String,
+        ttl: Duration,
+    ) -> Result<ShareToken> {
+        if *signer.device_id() != DeviceId::from_public_key(&self.issuer) {
+            return Err(CryptoError::InvalidKey);
+        }
+        let token = ShareToken::issue(signer, artifact_id, ttl)?;
+        self.shares.insert(
+            token.id.clone(),
+            IssuedShare {
+                id: token.id.clone(),
+                artifact_id: token.artifact_id.clone(),
+                expires_at: token.expires_at,
+                revoked: false,
+            },
+        );
+        self.save()?;
+        Ok(token)
+    }
+
+    /// Revoke a token, returning `false` if it was unknown or already revoked
+    pub fn revoke(&mut self, id: &str) -> Result<bool> {
+        match self.shares.get_mut(id) {
+            Some(share) if !share.revoked => share.revoked = true,
+            _ => return Ok(false),
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Check that a presented token is valid, returning the shared artifact ID
+    pub fn check(&self, token: &ShareToken) -> Result<&str> {
+        token.verify(&self.issuer)?;
+        let share = self
+            .shares
+            .get(&token.id)
+            .filter(|share| share.artifact_id == token.artifact_id)
+            .ok_or_else(|| CryptoError::InvalidShareToken("Unknown token".into()))?;
+        if share.revoked {
+            return Err(CryptoError::ShareTokenRevoked);
+        }
+        Ok(&share.artifact_id)
+    }
+
+    /// List issued tokens, including expired and revoked ones
+    pub fn list(&self) -> impl Iterator<Item = &IssuedShare> {
+        self.shares.values()
+    }
+
+    /// Forget expired tokens, returning how many were removed
+    pub fn prune_expired(&mut self) -> Result<usize> {
+        let now = unix_time();
+        let before = self.shares.len();
+        self.shares.retain(|_, share| share.expires_at > now);
+        let removed = before - self.shares.len();
+        if removed > 0 {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(&self.shares)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    const DAY: Duration = Duration::from_secs(86_400);
+
+    #[test]
+    fn test_issue_check_and_revoke() {
+        let laptop = generate_keypair();
+        let mut registry = ShareRegistry::new(*laptop.verifying_key());
+        let token = registry.issue(&laptop, "notes".into(), DAY).unwrap();
+
+        let decoded = ShareToken::decode(&token.encode().unwrap()).unwrap();
+        assert_eq!(decoded, token);
+        assert_eq!(registry.check(&decoded).unwrap(), "notes");
+
+        // Tampering with the artifact breaks the signature
+        let mut forged = decoded.clone();
+        forged.artifact_id = "diary".into();
+        assert!(matches!(
+            registry.check(&forged),
+            Err(CryptoError::InvalidSignature)
+        ));
+
+        // Tokens signed by another device are rejected
+        let stranger = generate_keypair();
+        let foreign = ShareToken::issue(&stranger, "notes".into(), DAY).unwrap();
+        assert!(registry.check(&foreign).is_err());
+
+        assert!(registry.revoke(&token.id).unwrap());
+        assert!(!registry.revoke(&token.id).unwrap());
+        assert!(matches!(
+            registry.check(&token),
+            Err(CryptoError::ShareTokenRevoked)
+        ));
+    }
+
+    #[test]
+    fn test_expired_tokens_are_rejected_and_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shares.json");
+        let laptop = generate_keypair();
+        let mut registry = ShareRegistry::open(*laptop.verifying_key(), &path).unwrap();
+        let expired = registry
+            .issue(&laptop, "notes".into(), Duration::ZERO)
+            .unwrap();
+        let live = registry.issue(&laptop, "notes".into(), DAY).unwrap();
+        assert!(matches!(
+            registry.check(&expired),
+            Err(CryptoError::ShareTokenExpired)
+        ));
+
+        let mut registry = ShareRegistry::open(*laptop.verifying_key(), &path).unwrap();
+        assert_eq!(registry.list().count(), 2);
+        assert_eq!(registry.prune_expired().unwrap(), 1);
+        assert!(registry.check(&live).is_ok());
+        assert!(ShareToken::decode("garbage").is_err());
+        let long = format!("{}{}", TOKEN_PREFIX, "A".repeat(MAX_TOKEN_LEN));
+        assert!(matches!(
+            ShareToken::decode(&long),
+            Err(CryptoError::InvalidShareToken(_))
+        ));
+    }
+}
@@ -0,0 +1,966 @@
+//! Trust store for paired devices
+//!
+//! Records which devices are trusted and which have been revoked. Revocations
+//! are signed `RevocationRecord`s so they can be propagated to other devices,
+//! which verify them before applying. The store is consulted on every
+//! handshake. Each device carries the `Permissions` it was granted, and
+//! other subsystems can attach per-peer settings (such as sync rules) to a
+//! trusted device as opaque JSON. Devices also keep the identity
+//! `Attestation` they published, with the countersignatures of devices
+//! vouching for it.
+//!
+//! Users confirmed once are kept as `TrustedUser`s: any device presenting
+//! an `EnrollmentCertificate` from a trusted user is admitted, and the
+//! user's `UserRevocation`s withdraw their devices.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::fs::write_durable;
+use crate::{
+    Attestation, CryptoError, DeviceId, DeviceKeypair, EnrollmentCertificate, Result, UserId,
+    UserRevocation, WipeCommand,
+};
+
+/// Trust state of a known device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrustState {
+    Trusted,
+    Revoked { revoked_by: DeviceId, at: u64 },
+}
+
+/// Whether a device may change synced data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Access {
+    ReadOnly,
+    #[default]
+    ReadWrite,
+}
+
+/// What a paired device is allowed to do
+///
+/// Devices paired before permissions existed keep full access.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permissions {
+    #[serde(default)]
+    pub access: Access,
+    /// Collections the device may see, or every artifact if `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collections: Option<BTreeSet<String>>,
+    /// Whether the device may manage the device list (pair or revoke others)
+    #[serde(default = "default_true")]
+    pub can_add_devices: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Permissions {
+    /// Read-write access to everything, including device management
+    pub fn full() -> Self {
+        Self {
+            access: Access::ReadWrite,
+            collections: None,
+            can_add_devices: true,
+        }
+    }
+
+    /// Read access to everything, without device management
+    pub fn read_only() -> Self {
+        Self {
+            access: Access::ReadOnly,
+            collections: None,
+            can_add_devices: false,
+        }
+    }
+
+    /// Restrict access to the given collections
+    pub fn with_collections(mut self, collections: impl IntoIterator<Item = String>) -> Self {
+        self.collections = Some(collections.into_iter().collect());
+        self
+    }
+
+    /// Whether the device may change synced data
+    pub fn can_write(&self) -> bool {
+        self.access == Access::ReadWrite
+    }
+
+    /// Whether the device may see artifacts in `collection`
+    ///
+    /// Artifacts outside any collection are only visible to unrestricted
+    /// devices.
+    pub fn can_access(&self, collection: Option<&str>) -> bool {
+        match (&self.collections, collection) {
+            (None, _) => true,
+            (Some(allowed), Some(collection)) => allowed.contains(collection),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+/// Known paired device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedDevice {
+    pub device_id: DeviceId,
+    pub device_name: String,
+    pub public_key: Vec<u8>,
+    pub state: TrustState,
+    #[serde(default)]
+    pub permissions: Permissions,
+    /// Per-peer settings owned by other subsystems, keyed by subsystem
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_data: BTreeMap<String, serde_json::Value>,
+    /// Identity statement the device published, with its countersignatures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<Attestation>,
+    /// User whose root key enrolled the device, if admitted that way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserId>,
+}
+
+/// User confirmed once, whose enrolled devices are trusted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedUser {
+    pub user_id: UserId,
+    pub name: String,
+    pub public_key: Vec<u8>,
+    /// Devices the user withdrew
+    #[serde(default)]
+    pub revoked: Vec<DeviceId>,
+    /// Whether this device is enrolled under the user
+    #[serde(default)]
+    pub owns_this_device: bool,
+}
+
+/// Signed statement revoking a device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationRecord {
+    pub revoked: DeviceId,
+    pub revoked_by: DeviceId,
+    pub reason: String,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+impl RevocationRecord {
+    /// Create and sign a revocation
+    pub fn new(signer: &DeviceKeypair, revoked: DeviceId, reason: String) -> Result<Self> {
+        let mut record = Self {
+            revoked,
+            revoked_by: signer.device_id().clone(),
+            reason,
+            timestamp: unix_time(),
+            signature: vec![],
+        };
+        record.signature = signer.sign(&record.signing_payload())?.to_bytes().to_vec();
+        Ok(record)
+    }
+
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-revocation-v1");
+        payload.extend_from_slice(self.revoked.0.as_bytes());
+        payload.extend_from_slice(self.revoked_by.0.as_bytes());
+        payload.extend_from_slice(self.reason.as_bytes());
+        payload.extend_from_slice(&self.timestamp.to_le_bytes());
+        payload
+    }
+
+    /// Verify signature with the revoking device's public key
+    pub fn verify(&self, public_key: &[u8]) -> Result<()> {
+        let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)?;
+        if DeviceId::from_public_key(&key) != self.revoked_by {
+            return Err(CryptoError::InvalidSignature);
+        }
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::InvalidSignature)?;
+        key.verify(&self.signing_payload(), &signature)
+            .map_err(|_| CryptoError::InvalidSignature)
+    }
+}
+
+/// Store of paired devices and their trust state
+#[derive(Debug, Default)]
+pub struct TrustStore {
+    devices: HashMap<DeviceId, TrustedDevice>,
+    users: HashMap<UserId, TrustedUser>,
+    path: Option<PathBuf>,
+}
+
+/// On-disk layout of a `TrustStore`
+#[derive(Default, Serialize, Deserialize)]
+struct StoredTrust {
+    devices: HashMap<DeviceId, TrustedDevice>,
+    #[serde(default)]
+    users: HashMap<UserId, TrustedUser>,
+}
+
+impl TrustStore {
+    /// Create in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open store persisted at `path`, creating it if missing
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let stored: StoredTrust = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredTrust::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            devices: stored.devices,
+            users: stored.users,
+            path: Some(path),
+        })
+    }
+
+    /// Trust a newly paired device with full permissions
+    ///
+    /// A revoked device cannot be trusted again under the same identity.
+    pub fn add_trusted(
+        &mut self,
+        device_id: DeviceId,
+        device_name: String,
+        public_key: Vec<u8>,
+    ) -> Result<()> {
+        self.add_with_permissions(device_id, device_name, public_key, Permissions::full())
+    }
+
+    /// Trust a newly paired device with the given permissions
+    pub fn add_with_permissions(
+        &mut self,
+        device_id: DeviceId,
+        device_name: String,
+        public_key: Vec<u8>,
+        permissions: Permissions,
+    ) -> Result<()> {
+        if let Some(TrustState::Revoked { .. }) = self.state(&device_id) {
+            return Err(CryptoError::DeviceRevoked(device_id));
+        }
+        self.devices.insert(
+            device_id.clone(),
+            TrustedDevice {
+                device_id,
+                device_name,
+                public_key,
+                state: TrustState::Trusted,
+                permissions,
+                peer_data: BTreeMap::new(),
+                attestation: None,
+                user: None,
+            },
+        );
+        self.save()
+    }
+
+    /// Forget a trusted device, e.g. one removed from the device group
+    ///
+    /// Unlike `revoke` this leaves no record, so the device can be paired
+    /// again; revoked devices stay revoked. Returns whether it was trusted.
+    pub fn remove(&mut self, device_id: &DeviceId) -> Result<bool> {
+        if self.state(device_id) != Some(&TrustState::Trusted) {
+            return Ok(false);
+        }
+        self.devices.remove(device_id);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Get known device
+    pub fn get(&self, device_id: &DeviceId) -> Option<&TrustedDevice> {
+        self.devices.get(device_id)
+    }
+
+    /// Trust state of a device, if known
+    pub fn state(&self, device_id: &DeviceId) -> Option<&TrustState> {
+        self.devices.get(device_id).map(|d| &d.state)
+    }
+
+    /// List all known devices
+    pub fn list(&self) -> impl Iterator<Item = &TrustedDevice> {
+        self.devices.values()
+    }
+
+    /// Permissions of a trusted device
+    ///
+    /// Unknown and revoked devices get none.
+    pub fn permissions(&self, device_id: &DeviceId) -> Option<&Permissions> {
+        self.devices
+            .get(device_id)
+            .filter(|d| d.state == TrustState::Trusted)
+            .map(|d| &d.permissions)
+    }
+
+    /// Replace the permissions of a trusted device
+    pub fn set_permissions(
+        &mut self,
+        device_id: &DeviceId,
+        permissions: Permissions,
+    ) -> Result<()> {
+        let device = self
+            .devices
+            .get_mut(device_id)
+            .filter(|d| d.state == TrustState::Trusted)
+            .ok_or_else(|| CryptoError::UntrustedDevice(device_id.clone()))?;
+        device.permissions = permissions;
+        self.save()
+    }
+
+    /// Per-peer setting stored under `key`
+    pub fn peer_data(&self, device_id: &DeviceId, key: &str) -> Option<&serde_json::Value> {
+        self.devices.get(device_id)?.peer_data.get(key)
+    }
+
+    /// Store a per-peer setting for a known device
+    pub fn set_peer_data(
+        &mut self,
+        device_id: &DeviceId,
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<()> {
+        let device = self
+            .devices
+            .get_mut(device_id)
+            .ok_or_else(|| CryptoError::UntrustedDevice(device_id.clone()))?;
+        device.peer_data.insert(key.to_string(), value);
+        self.save()
+    }
+
+    /// Store an identity statement about a known device
+    ///
+    /// The statement must verify and be about the device's paired key.
+    /// Countersignatures are merged into any statement already stored;
+    /// returns whether anything changed.
+    pub fn apply_attestation(&mut self, attestation: &Attestation) -> Result<bool> {
+        attestation.verify()?;
+        let device = self
+            .devices
+            .get_mut(&attestation.device_id)
+            .filter(|d| d.state == TrustState::Trusted)
+            .ok_or_else(|| CryptoError::UntrustedDevice(attestation.device_id.clone()))?;
+        if device.public_key != attestation.public_key {
+            return Err(CryptoError::InvalidAttestation(
+                "Key differs from the paired key".into(),
+            ));
+        }
+        let changed = match &mut device.attestation {
+            Some(stored) if stored.issued_at >= attestation.issued_at => stored.merge(attestation),
+            // A newer statement replaces the old one and its countersignatures
+            stored => {
+                *stored = Some(attestation.clone());
+                true
+            }
+        };
+        if changed {
+            self.save()?;
+        }
+        Ok(changed)
+    }
+
+    /// Identity statements of trusted devices
+    pub fn attestations(&self) -> impl Iterator<Item = &Attestation> {
+        self.devices
+            .values()
+            .filter(|d| d.state == TrustState::Trusted)
+            .filter_map(|d| d.attestation.as_ref())
+    }
+
+    /// Check whether a device may complete a handshake
+    pub fn check_handshake(&self, device_id: &DeviceId) -> Result<()> {
+        match self.state(device_id) {
+            Some(TrustState::Trusted) => Ok(()),
+            Some(TrustState::Revoked { .. }) => Err(CryptoError::DeviceRevoked(device_id.clone())),
+            None => Err(CryptoError::UntrustedDevice(device_id.clone())),
+        }
+    }
+
+    /// Revoke a device locally, returning the record to propagate
+    pub fn revoke(
+        &mut self,
+        signer: &DeviceKeypair,
+        device_id: DeviceId,
+        reason: String,
+    ) -> Result<RevocationRecord> {
+        let record = RevocationRecord::new(signer, device_id, reason)?;
+        self.mark_revoked(&record)?;
+        Ok(record)
+    }
+
+    /// Apply a revocation received from a peer
+    ///
+    /// The record must be signed by a trusted device allowed to manage
+    /// devices, or by the revoked device itself. Returns `false` if the
+    /// device was already revoked.
+    pub fn apply_revocation(&mut self, record: &RevocationRecord) -> Result<bool> {
+        let signer = self
+            .devices
+            .get(&record.revoked_by)
+            .filter(|d| d.state == TrustState::Trusted)
+            .ok_or_else(|| CryptoError::UntrustedDevice(record.revoked_by.clone()))?;
+        if !signer.permissions.can_add_devices && record.revoked != record.revoked_by {
+            return Err(CryptoError::PermissionDenied(record.revoked_by.clone()));
+        }
+        record.verify(&signer.public_key)?;
+
+        if let Some(TrustState::Revoked { .. }) = self.state(&record.revoked) {
+            return Ok(false);
+        }
+        self.mark_revoked(record)?;
+        Ok(true)
+    }
+
+    /// Trust every device the user enrolls
+    ///
+    /// Call once the user was confirmed, for example from the certificate
+    /// of a device paired in person. Devices the user already enrolled are
+    /// admitted with their certificates as they present them.
+    pub fn trust_user(&mut self, user_id: UserId, name: String, public_key: Vec<u8>) -> Result<()> {
+        let bytes: [u8; 32] = public_key
+            .as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidKey)?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)?;
+        if UserId::from_public_key(&key) != user_id {
+            return Err(CryptoError::InvalidKey);
+        }
+        let (revoked, owns_this_device) = self
+            .users
+            .remove(&user_id)
+            .map(|user| (user.revoked, user.owns_this_device))
+            .unwrap_or_default();
+        self.users.insert(
+            user_id.clone(),
+            TrustedUser {
+                user_id,
+                name,
+                public_key,
+                revoked,
+                owns_this_device,
+            },
+        );
+        self.save()
+    }
+
+    /// Record that this device is enrolled under a trusted user
+    pub fn set_own_user(&mut self, user_id: &UserId) -> Result<()> {
+        let user = self
+            .users
+            .get_mut(user_id)
+            .ok_or_else(|| CryptoError::UntrustedUser(user_id.clone()))?;
+        if !user.owns_this_device {
+            user.owns_this_device = true;
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Check a remote-wipe command addressed to `own_device`
+    ///
+    /// The issuer must be a trusted device enrolled under the same user as
+    /// this device, and the command signed by it and fresh at `now` (the
+    /// issuer's current time, in seconds since the Unix epoch).
+    pub fn check_wipe(&self, command: &WipeCommand, own_device: &DeviceId, now: u64) -> Result<()> {
+        if &command.target != own_device {
+            return Err(CryptoError::InvalidWipe(
+                "Addressed to another device".into(),
+            ));
+        }
+        let issuer = self
+            .devices
+            .get(&command.issued_by)
+            .filter(|d| d.state == TrustState::Trusted)
+            .ok_or_else(|| CryptoError::UntrustedDevice(command.issued_by.clone()))?;
+        let same_user = issuer
+            .user
+            .as_ref()
+            .and_then(|user_id| self.users.get(user_id))
+            .is_some_and(|user| user.owns_this_device);
+        if !same_user {
+            return Err(CryptoError::PermissionDenied(command.issued_by.clone()));
+        }
+        command.verify_at(&issuer.public_key, now)
+    }
+
+    /// Get trusted user
+    pub fn user(&self, user_id: &UserId) -> Option<&TrustedUser> {
+        self.users.get(user_id)
+    }
+
+    /// List trusted users
+    pub fn users(&self) -> impl Iterator<Item = &TrustedUser> {
+        self.users.values()
+    }
+
+    /// Admit a device enrolled by a trusted user, with full permissions
+    ///
+    /// Returns `false` if the device was already trusted.
+    pub fn admit_enrolled(&mut self, certificate: &EnrollmentCertificate) -> Result<bool> {
+        certificate.verify()?;
+        let user = self
+            .users
+            .get(&certificate.user_id)
+            .ok_or_else(|| CryptoError::UntrustedUser(certificate.user_id.clone()))?;
+        if user.public_key != certificate.user_public_key {
+            return Err(CryptoError::InvalidEnrollment(
+                "Key differs from the trusted user key".into(),
+            ));
+        }
+        if user.revoked.contains(&certificate.device_id) {
+            return Err(CryptoError::DeviceRevoked(certificate.device_id.clone()));
+        }
+        if let Some(device) = self.devices.get_mut(&certificate.device_id) {
+            if device.state != TrustState::Trusted {
+                return Err(CryptoError::DeviceRevoked(certificate.device_id.clone()));
+            }
+            if device.public_key != certificate.device_public_key {
+                return Err(CryptoError::InvalidEnrollment(
+                    "Key differs from the paired key".into(),
+                ));
+            }
+            // Already paired directly; remember whose device it is
+            if device.user.is_none() {
+                device.user = Some(certificate.user_id.clone());
+                self.save()?;
+            }
+            return Ok(false);
+        }
+        self.devices.insert(
+            certificate.device_id.clone(),
+            TrustedDevice {
+                device_id: certificate.device_id.clone(),
+                device_name: certificate.device_name.clone(),
+                public_key: certificate.device_public_key.clone(),
+                state: TrustState::Trusted,
+                permissions: Permissions::full(),
+                peer_data: BTreeMap::new(),
+                attestation: None,
+                user: Some(certificate.user_id.clone()),
+            },
+        );
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Apply a trusted user's withdrawal of one of their devices
+    ///
+    /// The device stays refused even if it presents its certificate again.
+    /// Returns `false` if the user had already withdrawn it.
+    pub fn apply_user_revocation(&mut self, revocation: &UserRevocation) -> Result<bool> {
+        let user = self
+            .users
+            .get_mut(&revocation.user_id)
+            .ok_or_else(|| CryptoError::UntrustedUser(revocation.user_id.clone()))?;
+        revocation.verify(&user.public_key)?;
+        if user.revoked.contains(&revocation.device_id) {
+            return Ok(false);
+        }
+        user.revoked.push(revocation.device_id.clone());
+        if let Some(device) = self
+            .devices
+            .get_mut(&revocation.device_id)
+            .filter(|d| d.state == TrustState::Trusted)
+        {
+            // The user acts for the device, as if it revoked itself
+            device.state = TrustState::Revoked {
+                revoked_by: revocation.device_id.clone(),
+                at: revocation.timestamp,
+            };
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn mark_revoked(&mut self, record: &RevocationRecord) -> Result<()> {
+        let state = TrustState::Revoked {
+            revoked_by: record.revoked_by.clone(),
+            at: record.timestamp,
+        };
+        self.devices
+            .entry(record.revoked.clone())
+            .and_modify(|d| d.state = state.clone())
+            .or_insert_with(|| TrustedDevice {
+                device_id: record.revoked.clone(),
+                device_name: String::new(),
+                public_key: vec![],
+                state,
+                permissions: Permissions::read_only(),
+                peer_data: BTreeMap::new(),
+                attestation: None,
+                user: None,
+            });
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // A revocation lost to a crash would bring the device back
+        let stored = serde_json::json!({ "devices": &self.devices, "users": &self.users });
+        write_durable(path, &serde_json::to_vec(&stored)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_keypair, UserIdentity};
+
+    fn trust(store: &mut TrustStore, keypair: &DeviceKeypair) {
+        store
+            .add_trusted(
+                keypair.device_id().clone(),
+                "Device".into(),
+                keypair.public_key_bytes(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_handshake_checks_trust_state() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let stranger = generate_keypair();
+
+        let mut store = TrustStore::new();
+        trust(&mut store, &phone);
+        assert!(store.check_handshake(phone.device_id()).is_ok());
+        assert!(matches!(
+            store.check_handshake(stranger.device_id()),
+            Err(CryptoError::UntrustedDevice(_))
+        ));
+
+        store
+            .revoke(&laptop, phone.device_id().clone(), "Lost".into())
+            .unwrap();
+        assert!(matches!(
+            store.check_handshake(phone.device_id()),
+            Err(CryptoError::DeviceRevoked(_))
+        ));
+    }
+
+    #[test]
+    fn test_removed_devices_can_pair_again() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+
+        let mut store = TrustStore::new();
+        trust(&mut store, &phone);
+        assert!(store.remove(phone.device_id()).unwrap());
+        assert!(!store.remove(phone.device_id()).unwrap());
+        assert!(store.get(phone.device_id()).is_none());
+        trust(&mut store, &phone);
+        assert!(store.check_handshake(phone.device_id()).is_ok());
+
+        // Revocations are not undone
+        store
+            .revoke(&laptop, phone.device_id().clone(), "Lost".into())
+            .unwrap();
+        assert!(!store.remove(phone.device_id()).unwrap());
+        assert!(matches!(
+            store.check_handshake(phone.device_id()),
+            Err(CryptoError::DeviceRevoked(_))
+        ));
+    }
+
+    #[test]
+    fn test_propagated_revocation() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+
+        // Laptop revokes the phone
+        let mut laptop_store = TrustStore::new();
+        trust(&mut laptop_store, &phone);
+        let record = laptop_store
+            .revoke(&laptop, phone.device_id().clone(), "Lost".into())
+            .unwrap();
+
+        // Tablet trusts the laptop and applies the propagated record once
+        let mut tablet_store = TrustStore::new();
+        trust(&mut tablet_store, &laptop);
+        trust(&mut tablet_store, &phone);
+        assert!(tablet_store.apply_revocation(&record).unwrap());
+        assert!(!tablet_store.apply_revocation(&record).unwrap());
+        assert!(tablet_store.check_handshake(phone.device_id()).is_err());
+
+        // Revoked devices cannot be re-added
+        assert!(tablet_store
+            .add_trusted(phone.device_id().clone(), "Phone".into(), vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn test_reject_untrusted_or_forged_revocation() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let attacker = generate_keypair();
+
+        let mut store = TrustStore::new();
+        trust(&mut store, &laptop);
+        trust(&mut store, &phone);
+
+        let record =
+            RevocationRecord::new(&attacker, phone.device_id().clone(), "Evil".into()).unwrap();
+        assert!(store.apply_revocation(&record).is_err());
+
+        let mut forged =
+            RevocationRecord::new(&laptop, laptop.device_id().clone(), "".into()).unwrap();
+        forged.revoked = phone.device_id().clone();
+        assert!(matches!(
+            store.apply_revocation(&forged),
+            Err(CryptoError::InvalidSignature)
+        ));
+        assert!(store.check_handshake(phone.device_id()).is_ok());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let mut store = TrustStore::new();
+        trust(&mut store, &laptop);
+        trust(&mut store, &phone);
+        assert_eq!(
+            store.permissions(phone.device_id()),
+            Some(&Permissions::full())
+        );
+
+        let scoped = Permissions::read_only().with_collections(["work".to_string()]);
+        store
+            .set_permissions(laptop.device_id(), scoped.clone())
+            .unwrap();
+        assert!(!scoped.can_write());
+        assert!(scoped.can_access(Some("work")));
+        assert!(!scoped.can_access(Some("personal")));
+        assert!(!scoped.can_access(None));
+
+        // Only devices allowed to manage devices may revoke others
+        let record =
+            RevocationRecord::new(&laptop, phone.device_id().clone(), "Lost".into()).unwrap();
+        assert!(matches!(
+            store.apply_revocation(&record),
+            Err(CryptoError::PermissionDenied(_))
+        ));
+        let own =
+            RevocationRecord::new(&laptop, laptop.device_id().clone(), "Reset".into()).unwrap();
+        assert!(store.apply_revocation(&own).unwrap());
+        assert_eq!(store.permissions(laptop.device_id()), None);
+        assert!(store
+            .set_permissions(laptop.device_id(), Permissions::full())
+            .is_err());
+
+        // Stores written before permissions existed grant full access
+        let legacy: TrustedDevice = serde_json::from_value(serde_json::json!({
+            "device_id": phone.device_id(),
+            "device_name": "Phone",
+            "public_key": [],
+            "state": "Trusted",
+        }))
+        .unwrap();
+        assert_eq!(legacy.permissions, Permissions::full());
+    }
+
+    #[test]
+    fn test_attestations_merge_per_device() {
+        let (laptop, phone, tablet) = (generate_keypair(), generate_keypair(), generate_keypair());
+        let mut store = TrustStore::new();
+        trust(&mut store, &phone);
+        let statement = Attestation::new(
+            &phone,
+            "Phone".into(),
+            "alice".into(),
+            std::time::Duration::from_secs(3600),
+        )
+        .unwrap();
+        let (mut by_laptop, mut by_tablet) = (statement.clone(), statement);
+        by_laptop.countersign(&laptop).unwrap();
+        by_tablet.countersign(&tablet).unwrap();
+
+        assert!(store.apply_attestation(&by_laptop).unwrap());
+        assert!(store.apply_attestation(&by_tablet).unwrap());
+        assert!(!store.apply_attestation(&by_tablet).unwrap());
+        let stored: Vec<_> = store.attestations().collect();
+        assert_eq!(stored[0].vouchers().count(), 2);
+
+        // Statements about unknown devices or other keys are refused
+        let laptop_statement = Attestation::new(
+            &laptop,
+            "Laptop".into(),
+            "alice".into(),
+            std::time::Duration::from_secs(3600),
+        )
+        .unwrap();
+        assert!(matches!(
+            store.apply_attestation(&laptop_statement),
+            Err(CryptoError::UntrustedDevice(_))
+        ));
+    }
+
+    #[test]
+    fn test_enrolled_devices_of_trusted_users() {
+        let (alice, mallory) = (UserIdentity::generate(), UserIdentity::generate());
+        let (phone, laptop) = (generate_keypair(), generate_keypair());
+        let enroll = |user: &UserIdentity, device: &DeviceKeypair| {
+            user.enroll(
+                device.device_id().clone(),
+                "Device".into(),
+                device.public_key_bytes(),
+            )
+            .unwrap()
+        };
+        let mut store = TrustStore::new();
+        assert!(matches!(
+            store.admit_enrolled(&enroll(&alice, &phone)),
+            Err(CryptoError::UntrustedUser(_))
+        ));
+        assert!(store
+            .trust_user(
+                alice.user_id().clone(),
+                "Alice".into(),
+                mallory.public_key_bytes()
+            )
+            .is_err());
+        store
+            .trust_user(
+                alice.user_id().clone(),
+                "Alice".into(),
+                alice.public_key_bytes(),
+            )
+            .unwrap();
+
+        // Every device Alice enrolled is trusted at once
+        assert!(store.admit_enrolled(&enroll(&alice, &phone)).unwrap());
+        assert!(store.admit_enrolled(&enroll(&alice, &laptop)).unwrap());
+        assert!(!store.admit_enrolled(&enroll(&alice, &laptop)).unwrap());
+        assert!(store.check_handshake(laptop.device_id()).is_ok());
+        assert_eq!(
+            store.get(phone.device_id()).unwrap().user.as_ref(),
+            Some(alice.user_id())
+        );
+        assert!(store.admit_enrolled(&enroll(&mallory, &phone)).is_err());
+
+        let revocation = alice.revoke(laptop.device_id().clone(), "Stolen".into());
+        assert!(store.apply_user_revocation(&revocation).unwrap());
+        assert!(!store.apply_user_revocation(&revocation).unwrap());
+        assert!(store.check_handshake(laptop.device_id()).is_err());
+        assert!(store.admit_enrolled(&enroll(&alice, &laptop)).is_err());
+        assert!(store
+            .apply_user_revocation(&mallory.revoke(phone.device_id().clone(), "".into()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_wipe_only_from_devices_of_own_user() {
+        let (alice, bob) = (UserIdentity::generate(), UserIdentity::generate());
+        let (me, phone, friend) = (generate_keypair(), generate_keypair(), generate_keypair());
+        let mut store = TrustStore::new();
+        for (user, device) in [(&alice, &phone), (&bob, &friend)] {
+            store
+                .trust_user(
+                    user.user_id().clone(),
+                    "User".into(),
+                    user.public_key_bytes(),
+                )
+                .unwrap();
+            store
+                .admit_enrolled(
+                    &user
+                        .enroll(
+                            device.device_id().clone(),
+                            "Device".into(),
+                            device.public_key_bytes(),
+                        )
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        let wipe = |from: &DeviceKeypair| WipeCommand::new(from, me.device_id().clone()).unwrap();
+        let now = unix_time();
+
+        // Trusting Alice is not enough; this device must be one of hers
+        assert!(matches!(
+            store.check_wipe(&wipe(&phone), me.device_id(), now),
+            Err(CryptoError::PermissionDenied(_))
+        ));
+        store.set_own_user(alice.user_id()).unwrap();
+        store
+            .check_wipe(&wipe(&phone), me.device_id(), now)
+            .unwrap();
+        assert!(store
+            .check_wipe(&wipe(&friend), me.device_id(), now)
+            .is_err());
+        assert!(store
+            .check_wipe(&wipe(&phone), phone.device_id(), now)
+            .is_err());
+        assert!(store
+            .check_wipe(&wipe(&generate_keypair()), me.device_id(), now)
+            .is_err());
+    }
+
+    #[test]
+    fn test_trust_store_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trust.json");
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+
+        let mut store = TrustStore::open(&path).unwrap();
+        trust(&mut store, &phone);
+        store
+            .revoke(&laptop, phone.device_id().clone(), "Lost".into())
+            .unwrap();
+
+        let tablet = generate_keypair();
+        trust(&mut store, &tablet);
+        store
+            .set_peer_data(tablet.device_id(), "sync", serde_json::json!({"limit": 5}))
+            .unwrap();
+        store
+            .set_permissions(tablet.device_id(), Permissions::read_only())
+            .unwrap();
+        assert!(store
+            .set_peer_data(laptop.device_id(), "sync", serde_json::json!(null))
+            .is_err());
+
+        let alice = UserIdentity::generate();
+        store
+            .trust_user(
+                alice.user_id().clone(),
+                "Alice".into(),
+                alice.public_key_bytes(),
+            )
+            .unwrap();
+
+        let store = TrustStore::open(&path).unwrap();
+        assert_eq!(store.user(alice.user_id()).unwrap().name, "Alice");
+        assert!(matches!(
+            store.state(phone.device_id()),
+            Some(TrustState::Revoked { .. })
+        ));
+        assert_eq!(
+            store.peer_data(tablet.device_id(), "sync").unwrap()["limit"],
+            5
+        );
+        assert_eq!(
+            store.permissions(tablet.device_id()),
+            Some(&Permissions::read_only())
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! User identity above device identities
+//!
+//! A person's devices share one root `UserIdentity`: an Ed25519 key that
+//! can be generated or derived from a passphrase written down at setup.
+//! The root key signs an `EnrollmentCertificate` for each of the user's
+//! devices and a `UserRevocation` when one is lost.
+//!
+//! A peer that confirmed the user once (for example by comparing the user
+//! ID on two screens) trusts every device holding a certificate from that
+//! user, without pairing with each device separately.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::{password_key, CryptoError, DeviceId, Result};
+
+/// Salt stretching a passphrase into a root key
+///
+/// Fixed so the same passphrase always gives the same user.
+const PASSPHRASE_SALT: &[u8] = b"nomade-user-root-v1";
+
+/// User ID derived from the root public key
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct UserId(pub String);
+
+impl UserId {
+    /// Create user ID from the root public key
+    pub fn from_public_key(public_key: &VerifyingKey) -> Self {
+        let hash = blake3::hash(public_key.as_bytes());
+        Self(format!("user-blake3-{}", hash.to_hex()))
+    }
+}
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Root key of a user, signing the enrollment of their devices
+pub struct UserIdentity {
+    signing_key: SigningKey,
+    user_id: UserId,
+}
+
+impl UserIdentity {
+    /// Generate a random root key
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self::new(SigningKey::from_bytes(&secret))
+    }
+
+    /// Derive the root key from a passphrase with Argon2id
+    pub fn from_passphrase(passphrase: &str) -> Result<Self> {
+        let passphrase = passphrase.split_whitespace().collect::<Vec<_>>().join(" ");
+        if passphrase.is_empty() {
+            return Err(CryptoError::InvalidKey);
+        }
+        Ok(Self::new(SigningKey::from_bytes(&password_key(
+            &passphrase,
+            PASSPHRASE_SALT,
+        )?)))
+    }
+
+    /// Restore a root key from its secret bytes
+    pub fn from_secret_bytes(secret: &[u8]) -> Result<Self> {
+        let secret: [u8; 32] = secret.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        Ok(Self::new(SigningKey::from_bytes(&secret)))
+    }
+
+    fn new(signing_key: SigningKey) -> Self {
+        let user_id = UserId::from_public_key(&signing_key.verifying_key());
+        Self {
+            signing_key,
+            user_id,
+        }
+    }
+
+    /// Get user ID
+    pub fn user_id(&self) -> &UserId {
+        &self.user_id
+    }
+
+    /// Serialize the root public key to bytes
+    pub fn public_key_bytes(&self) -> Vec<u8> {
+        self.signing_key.verifying_key().as_bytes().to_vec()
+    }
+
+    /// Serialize the root secret key to bytes (use carefully!)
+    pub fn secret_key_bytes(&self) -> Vec<u8> {
+        self.signing_key.to_bytes().to_vec()
+    }
+
+    /// Certify that a device belongs to this user
+    pub fn enroll(
+        &self,
+        device_id: DeviceId,
+        device_name: String,
+        device_public_key: Vec<u8>,
+    ) -> Result<EnrollmentCertificate> {
+        let key = verifying_key(&device_public_key)?;
+        if DeviceId::from_public_key(&key) != device_id {
+            return Err(CryptoError::InvalidEnrollment(
+                "Key does not match the device ID".into(),
+            ));
+        }
+        let mut certificate = EnrollmentCertificate {
+            user_id: self.user_id.clone(),
+            user_public_key: self.public_key_bytes(),
+            device_id,
+            device_name,
+            device_public_key,
+            issued_at: unix_time(),
+            signature: vec![],
+        };
+        certificate.signature = self
+            .signing_key
+            .sign(&certificate.signing_payload())
+            .to_bytes()
+            .to_vec();
+        Ok(certificate)
+    }
+
+    /// Withdraw a device from this user
+    pub fn revoke(&self, device_id: DeviceId, reason: String) -> UserRevocation {
+        let mut revocation = UserRevocation {
+            user_id: self.user_id.clone(),
+            device_id,
+            reason,
+            timestamp: unix_time(),
+            signature: vec![],
+        };
+        revocation.signature = self
+            .signing_key
+            .sign(&revocation.signing_payload())
+            .to_bytes()
+            .to_vec();
+        revocation
+    }
+}
+
+/// Statement by a user's root key that a device is theirs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnrollmentCertificate {
+    pub user_id: UserId,
+    pub user_public_key: Vec<u8>,
+    pub device_id: DeviceId,
+    pub device_name: String,
+    pub device_public_key: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+impl EnrollmentCertificate {
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-enrollment-v1");
+        payload.extend_from_slice(&self.user_public_key);
+        payload.extend_from_slice(&self.device_public_key);
+        payload.extend_from_slice(&(self.device_name.len() as u32).to_le_bytes());
+        payload.extend_from_slice(self.device_name.as_bytes());
+        payload.extend_from_slice(&self.issued_at.to_le_bytes());
+        payload
+    }
+
+    /// Check both IDs against their keys and the root signature
+    pub fn verify(&self) -> Result<()> {
+        let user_key = verifying_key(&self.user_public_key)?;
+        if UserId::from_public_key(&user_key) != self.user_id {
+            return Err(CryptoError::InvalidEnrollment(
+                "Key does not match the user ID".into(),
+            ));
+        }
+        let device_key = verifying_key(&self.device_public_key)?;
+        if DeviceId::from_public_key(&device_key) != self.device_id {
+            return Err(CryptoError::InvalidEnrollment(
+                "Key does not match the device ID".into(),
+            ));
+        }
+        check_signature(&user_key, &self.signing_payload(), &self.signature)
+    }
+}
+
+/// Statement by a user's root key withdrawing one of their devices
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRevocation {
+    pub user_id: UserId,
+    pub device_id: DeviceId,
+    pub reason: String,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+impl UserRevocation {
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-user-revocation-v1");
+        payload.extend_from_slice(self.user_id.0.as_bytes());
+        payload.extend_from_slice(self.device_id.0.as_bytes());
+        payload.extend_from_slice(self.reason.as_bytes());
+        payload.extend_from_slice(&self.timestamp.to_le_bytes());
+        payload
+    }
+
+    /// Verify signature with the user's root public key
+    pub fn verify(&self, user_public_key: &[u8]) -> Result<()> {
+        let key = verifying_key(user_public_key)?;
+        if UserId::from_public_key(&key) != self.user_id {
+            return Err(CryptoError::InvalidSignature);
+        }
+        check_signature(&key, &self.signing_payload(), &self.signature)
+    }
+}
+
+fn verifying_key(public_key: &[u8]) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)
+}
+
+fn check_signature(key: &VerifyingKey, payload: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::from_slice(signature).map_err(|_| CryptoError::InvalidSignature)?;
+    key.verify(payload, &signature)
+        .map_err(|_| CryptoError::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn test_passphrase_derives_the_same_user() {
+        let user = UserIdentity::from_passphrase("correct horse battery staple").unwrap();
+        let again = UserIdentity::from_passphrase("  correct horse\nbattery   staple ").unwrap();
+        assert_eq!(user.user_id(), again.user_id());
+        assert_ne!(user.user_id(), UserIdentity::generate().user_id());
+        assert!(UserIdentity::from_passphrase(" ").is_err());
+
+        let restored = UserIdentity::from_secret_bytes(&user.secret_key_bytes()).unwrap();
+        assert_eq!(restored.user_id(), user.user_id());
+    }
+
+    #[test]
+    fn test_enrollment_certificates_verify() {
+        let user = UserIdentity::generate();
+        let phone = generate_keypair();
+        let certificate = user
+            .enroll(
+                phone.device_id().clone(),
+                "Phone".into(),
+                phone.public_key_bytes(),
+            )
+            .unwrap();
+        certificate.verify().unwrap();
+
+        let mut renamed = certificate.clone();
+        renamed.device_name = "Laptop".into();
+        assert!(matches!(
+            renamed.verify(),
+            Err(CryptoError::InvalidSignature)
+        ));
+        let mut swapped = certificate.clone();
+        swapped.device_id = generate_keypair().device_id().clone();
+        assert!(matches!(
+            swapped.verify(),
+            Err(CryptoError::InvalidEnrollment(_))
+        ));
+        assert!(user
+            .enroll(
+                phone.device_id().clone(),
+                "Phone".into(),
+                generate_keypair().public_key_bytes()
+            )
+            .is_err());
+
+        let revocation = user.revoke(phone.device_id().clone(), "Lost".into());
+        revocation.verify(&user.public_key_bytes()).unwrap();
+        assert!(revocation
+            .verify(&UserIdentity::generate().public_key_bytes())
+            .is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Keys available only while the keystore is unlocked
+//!
+//! The vault holds a random master key wrapped under a key stretched from
+//! the user's passphrase. Unlocking, with the passphrase or with the master
+//! key released by a platform biometric prompt (`UnlockProvider`), keeps
+//! the master key in memory for the auto-lock period; then it is zeroized
+//! and key requests fail with `CryptoError::KeystoreLocked` until the user
+//! unlocks again.
+//!
+//! Keys derived from the master key (`Vault::key`) protect local data; the
+//! identity key stays usable while locked so the device keeps syncing.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::clock::Stopwatch;
+use crate::encryption::key_id;
+use crate::fs::write_durable;
+use crate::kdf::{derive_for, KeyPurpose};
+use crate::seal::{generate_salt, password_key};
+use crate::{ct_eq, generate_key, unwrap_key, wrap_key, CryptoError, Result, WrappedKey};
+
+/// Default time keys stay unlocked
+pub const DEFAULT_AUTO_LOCK: Duration = Duration::from_secs(5 * 60);
+
+/// Platform prompt releasing the master key, e.g. behind biometrics
+///
+/// The app stores the key from `Vault::master_key` in the platform
+/// keystore with user-presence protection after the user opts in.
+pub trait UnlockProvider: Send + Sync {
+    /// Master key, once the user authenticated
+    fn release_key(&self) -> Result<[u8; 32]>;
+}
+
+impl<F> UnlockProvider for F
+where
+    F: Fn() -> Result<[u8; 32]> + Send + Sync,
+{
+    fn release_key(&self) -> Result<[u8; 32]> {
+        self()
+    }
+}
+
+/// Persisted part of the vault
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SealedVault {
+    salt: Vec<u8>,
+    /// Master key wrapped under the passphrase key
+    master: WrappedKey,
+    /// `key_id` of the master key, to check keys released by a provider
+    master_id: String,
+}
+
+struct Unlocked {
+    master: Zeroizing<[u8; 32]>,
+    since: Stopwatch,
+}
+
+/// Passphrase-protected keys with auto-lock
+pub struct Vault {
+    path: Option<PathBuf>,
+    sealed: Mutex<Option<SealedVault>>,
+    unlocked: Mutex<Option<Unlocked>>,
+    auto_lock: Duration,
+}
+
+impl Vault {
+    /// Create a vault without a passphrase, kept in memory
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            sealed: Mutex::new(None),
+            unlocked: Mutex::new(None),
+            auto_lock: DEFAULT_AUTO_LOCK,
+        }
+    }
+
+    /// Open the vault at `path`; it starts locked
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let sealed = match std::fs::read(&path) {
+            Ok(bytes) => Some(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            sealed: Mutex::new(sealed),
+            ..Self::in_memory()
+        })
+    }
+
+    /// Keep keys unlocked for `period`
+    pub fn with_auto_lock(mut self, period: Duration) -> Self {
+        self.auto_lock = period;
+        self
+    }
+
+    /// Time keys stay unlocked
+    pub fn auto_lock(&self) -> Duration {
+        self.auto_lock
+    }
+
+    /// Whether a passphrase was set
+    pub fn is_protected(&self) -> bool {
+        self.sealed.lock().unwrap().is_some()
+    }
+
+    /// Set or change the passphrase
+    ///
+    /// The first call creates the master key; later ones need the vault
+    /// unlocked and rewrap the same key. Leaves the vault unlocked.
+    pub fn set_passphrase(&self, passphrase: &str) -> Result<()> {
+        let mut sealed = self.sealed.lock().unwrap();
+        let master = match &*sealed {
+            None => Zeroizing::new(generate_key()),
+            Some(_) => self.master()?,
+        };
+        let salt = generate_salt();
+        let kek = Zeroizing::new(password_key(passphrase, &salt)?);
+        let next = SealedVault {
+            salt: salt.to_vec(),
+            master: wrap_key(&kek, &master)?,
+            master_id: key_id(&master),
+        };
+        if let Some(path) = &self.path {
+            write_durable(path, &serde_json::to_vec(&next)?)?;
+        }
+        *sealed = Some(next);
+        self.hold(master);
+        Ok(())
+    }
+
+    /// Remove the passphrase; needs the vault unlocked
+    ///
+    /// The master key is zeroized, so keys derived from it are gone.
+    pub fn clear_passphrase(&self) -> Result<()> {
+        let mut sealed = self.sealed.lock().unwrap();
+        if sealed.is_none() {
+            return Ok(());
+        }
+        self.master()?;
+        if let Some(path) = &self.path {
+            std::fs::remove_file(path)?;
+        }
+        *sealed = None;
+        self.lock();
+        Ok(())
+    }
+
+    /// Unlock with the passphrase
+    pub fn unlock_with_passphrase(&self, passphrase: &str) -> Result<()> {
+        let sealed = self.sealed()?;
+        let kek = Zeroizing::new(password_key(passphrase, &sealed.salt)?);
+        let master = Zeroizing::new(unwrap_key(&kek, &sealed.master)?);
+        self.hold(master);
+        Ok(())
+    }
+
+    /// Unlock with the master key released by a platform prompt
+    pub fn unlock_with(&self, provider: &dyn UnlockProvider) -> Result<()> {
+        let sealed = self.sealed()?;
+        let master = Zeroizing::new(provider.release_key()?);
+        if !ct_eq(key_id(&master).as_bytes(), sealed.master_id.as_bytes()) {
+            return Err(CryptoError::InvalidKey);
+        }
+        self.hold(master);
+        Ok(())
+    }
+
+    /// Fail with `KeystoreLocked` if a passphrase is set and keys are locked
+    pub fn check_unlocked(&self) -> Result<()> {
+        if self.is_protected() && !self.is_unlocked() {
+            return Err(CryptoError::KeystoreLocked);
+        }
+        Ok(())
+    }
+
+    /// Zeroize the keys held in memory
+    pub fn lock(&self) {
+        if self.unlocked.lock().unwrap().take().is_some() {
+            tracing::info!("Keystore locked");
+        }
+    }
+
+    /// Lock if the auto-lock period elapsed; returns whether it did
+    pub fn expire(&self) -> bool {
+        let mut unlocked = self.unlocked.lock().unwrap();
+        match &*unlocked {
+            Some(held) if held.since.elapsed() >= self.auto_lock => {
+                *unlocked = None;
+                tracing::info!("Keystore auto-locked");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether keys are available
+    pub fn is_unlocked(&self) -> bool {
+        self.expire();
+        self.unlocked.lock().unwrap().is_some()
+    }
+
+    /// Time left before keys are zeroized, if unlocked
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expire();
+        self.unlocked
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|held| self.auto_lock.saturating_sub(held.since.elapsed()))
+    }
+
+    /// Key for `purpose` derived from the master key
+    pub fn key(&self, purpose: KeyPurpose) -> Result<[u8; 32]> {
+        Ok(derive_for(purpose, &*self.master()?, b"nomade-vault"))
+    }
+
+    /// Master key, for the app to place behind a platform prompt
+    pub fn master_key(&self) -> Result<[u8; 32]> {
+        Ok(*self.master()?)
+    }
+
+    fn master(&self) -> Result<Zeroizing<[u8; 32]>> {
+        self.expire();
+        self.unlocked
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|held| held.master.clone())
+            .ok_or(CryptoError::KeystoreLocked)
+    }
+
+    fn sealed(&self) -> Result<SealedVault> {
+        self.sealed
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(CryptoError::KeystoreLocked)
+    }
+
+    fn hold(&self, master: Zeroizing<[u8; 32]>) {
+        *self.unlocked.lock().unwrap() = Some(Unlocked {
+            master,
+            since: Stopwatch::start(),
+        });
+        tracing::info!("Keystore unlocked for {:?}", self.auto_lock);
+    }
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault")
+            .field("protected", &self.is_protected())
+            .field("unlocked", &self.is_unlocked())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Biometrics([u8; 32]);
+
+    impl UnlockProvider for Biometrics {
+        fn release_key(&self) -> Result<[u8; 32]> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn test_vault_unlocks_and_auto_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keystore.vault");
+        let vault = Vault::open(&path).unwrap();
+        assert!(!vault.is_protected());
+        assert!(matches!(
+            vault.unlock_with_passphrase("hunter2"),
+            Err(CryptoError::KeystoreLocked)
+        ));
+        vault.set_passphrase("hunter2").unwrap();
+        let key = vault.key(KeyPurpose::StorageAtRest).unwrap();
+        let master = vault.master_key().unwrap();
+
+        // Reopened vaults start locked and need the passphrase
+        let vault = Vault::open(&path)
+            .unwrap()
+            .with_auto_lock(Duration::from_millis(50));
+        assert!(vault.is_protected() && !vault.is_unlocked());
+        assert!(matches!(
+            vault.key(KeyPurpose::StorageAtRest),
+            Err(CryptoError::KeystoreLocked)
+        ));
+        assert!(vault.unlock_with_passphrase("hunter3").is_err());
+        vault.unlock_with_passphrase("hunter2").unwrap();
+        assert_eq!(vault.key(KeyPurpose::StorageAtRest).unwrap(), key);
+        assert_ne!(vault.key(KeyPurpose::Backup).unwrap(), key);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(vault.remaining().is_none());
+        assert!(matches!(
+            vault.key(KeyPurpose::StorageAtRest),
+            Err(CryptoError::KeystoreLocked)
+        ));
+
+        // A platform prompt releases the same master key
+        assert!(vault.unlock_with(&Biometrics([7; 32])).is_err());
+        vault.unlock_with(&Biometrics(master)).unwrap();
+        assert_eq!(vault.key(KeyPurpose::StorageAtRest).unwrap(), key);
+
+        // Changing the passphrase keeps the keys
+        vault.set_passphrase("correct horse").unwrap();
+        vault.lock();
+        assert!(!vault.is_unlocked());
+        let vault = Vault::open(&path).unwrap();
+        vault.unlock_with_passphrase("correct horse").unwrap();
+        assert_eq!(vault.key(KeyPurpose::StorageAtRest).unwrap(), key);
+    }
+}
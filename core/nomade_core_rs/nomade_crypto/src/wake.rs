@@ -0,0 +1,238 @@
+//! Signed push wake tokens
+//!
+//! Mobile platforms kill background sockets, so a device with changes for
+//! a sleeping phone asks a push service to deliver a `WakeToken` to it.
+//! The token names sender and recipient, when it was issued and a random
+//! ID, and is signed by the sender. Push services and relays only carry
+//! it: they can neither forge a token nor wake a device with one that was
+//! addressed elsewhere, and `WakeValidator` rejects replays.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::fs::write_durable;
+use crate::{CryptoError, DeviceId, DeviceKeypair, Result};
+
+/// Prefix of encoded tokens
+const TOKEN_PREFIX: &str = "nomade-wake:";
+/// Longest encoded token decoded
+const MAX_TOKEN_LEN: usize = 2048;
+
+/// Push delivery can lag; older tokens are rejected
+pub const MAX_WAKE_AGE: Duration = Duration::from_secs(15 * 60);
+
+/// Tolerated clock difference for tokens issued in the future
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Signed request for a device to wake up and sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WakeToken {
+    pub id: String,
+    pub from: DeviceId,
+    pub to: DeviceId,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+impl WakeToken {
+    /// Issue a token asking `to` to sync with the signer
+    pub fn issue(signer: &DeviceKeypair, to: DeviceId) -> Result<Self> {
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let mut token = Self {
+            id: id.iter().map(|b| format!("{:02x}", b)).collect(),
+            from: signer.device_id().clone(),
+            to,
+            issued_at: unix_time(),
+            signature: vec![],
+        };
+        token.signature = signer.sign(&token.signing_payload())?.to_bytes().to_vec();
+        Ok(token)
+    }
+
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-wake-v1");
+        payload.extend_from_slice(self.id.as_bytes());
+        payload.extend_from_slice(&(self.from.0.len() as u32).to_le_bytes());
+        payload.extend_from_slice(self.from.0.as_bytes());
+        payload.extend_from_slice(&(self.to.0.len() as u32).to_le_bytes());
+        payload.extend_from_slice(self.to.0.as_bytes());
+        payload.extend_from_slice(&self.issued_at.to_le_bytes());
+        payload
+    }
+
+    /// Verify the sender's signature with its public key
+    pub fn verify(&self, public_key: &[u8]) -> Result<()> {
+        let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)?;
+        if DeviceId::from_public_key(&key) != self.from {
+            return Err(CryptoError::InvalidSignature);
+        }
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::InvalidSignature)?;
+        key.verify(&self.signing_payload(), &signature)
+            .map_err(|_| CryptoError::InvalidSignature)
+    }
+
+    /// Encode for a push payload
+    pub fn encode(&self) -> Result<String> {
+        use base64::{engine::general_purpose, Engine as _};
+        let json = serde_json::to_vec(self)?;
+        Ok(format!(
+            "{}{}",
+            TOKEN_PREFIX,
+            general_purpose::URL_SAFE_NO_PAD.encode(json)
+        ))
+    }
+
+    /// Decode a string produced by `encode`
+    pub fn decode(encoded: &str) -> Result<Self> {
+        use base64::{engine::general_purpose, Engine as _};
+        if encoded.len() > MAX_TOKEN_LEN {
+            return Err(CryptoError::InvalidWakeToken("Token too long".into()));
+        }
+        let data = encoded
+            .trim()
+            .strip_prefix(TOKEN_PREFIX)
+            .ok_or_else(|| CryptoError::InvalidWakeToken("Missing prefix".into()))?;
+        let json = general_purpose::URL_SAFE_NO_PAD
+            .decode(data)
+            .map_err(|e| CryptoError::InvalidWakeToken(e.to_string()))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+/// Checks wake tokens addressed to this device, rejecting replays
+#[derive(Debug)]
+pub struct WakeValidator {
+    device_id: DeviceId,
+    /// IDs of accepted tokens and when they were issued
+    seen: HashMap<String, u64>,
+    path: Option<PathBuf>,
+}
+
+impl WakeValidator {
+    /// In-memory validator for tokens addressed to `device_id`
+    pub fn new(device_id: DeviceId) -> Self {
+        Self {
+            device_id,
+            seen: HashMap::new(),
+            path: None,
+        }
+    }
+
+    /// Open validator whose seen tokens persist at `path`
+    pub fn open(device_id: DeviceId, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let seen = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            device_id,
+            seen,
+            path: Some(path),
+        })
+    }
+
+    /// Accept a token signed by `public_key`, recording it
+    pub fn accept(&mut self, token: &WakeToken, public_key: &[u8]) -> Result<()> {
+        self.accept_at(token, public_key, unix_time())
+    }
+
+    /// Accept a token at the given time (seconds since UNIX epoch)
+    pub fn accept_at(&mut self, token: &WakeToken, public_key: &[u8], now: u64) -> Result<()> {
+        token.verify(public_key)?;
+        if token.to != self.device_id {
+            return Err(CryptoError::InvalidWakeToken(format!(
+                "addressed to {}",
+                token.to
+            )));
+        }
+        if token.issued_at > now + MAX_CLOCK_SKEW.as_secs() {
+            return Err(CryptoError::InvalidWakeToken("issued in the future".into()));
+        }
+        if now.saturating_sub(token.issued_at) > MAX_WAKE_AGE.as_secs() {
+            return Err(CryptoError::InvalidWakeToken("expired".into()));
+        }
+        if self.seen.contains_key(&token.id) {
+            return Err(CryptoError::InvalidWakeToken("already used".into()));
+        }
+        // Expired tokens fail the age check, so their IDs can go
+        let cutoff = now.saturating_sub(MAX_WAKE_AGE.as_secs() + MAX_CLOCK_SKEW.as_secs());
+        self.seen.retain(|_, issued_at| *issued_at >= cutoff);
+        self.seen.insert(token.id.clone(), token.issued_at);
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // A token ID lost to a crash would let the token wake the device again
+        write_durable(path, &serde_json::to_vec(&self.seen)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn test_accept_checks_signature_recipient_and_age() {
+        let (laptop, phone) = (generate_keypair(), generate_keypair());
+        let mut validator = WakeValidator::new(phone.device_id().clone());
+        let token = WakeToken::issue(&laptop, phone.device_id().clone()).unwrap();
+        let decoded = WakeToken::decode(&token.encode().unwrap()).unwrap();
+        assert_eq!(decoded, token);
+
+        let public_key = laptop.public_key_bytes();
+        assert!(matches!(
+            validator.accept(&token, &phone.public_key_bytes()),
+            Err(CryptoError::InvalidSignature)
+        ));
+        let mut forged = token.clone();
+        forged.issued_at += 1;
+        assert!(validator.accept(&forged, &public_key).is_err());
+
+        let elsewhere = WakeToken::issue(&laptop, laptop.device_id().clone()).unwrap();
+        assert!(validator.accept(&elsewhere, &public_key).is_err());
+
+        let late = token.issued_at + MAX_WAKE_AGE.as_secs() + 1;
+        assert!(validator.accept_at(&token, &public_key, late).is_err());
+        validator.accept(&token, &public_key).unwrap();
+        assert!(validator.accept(&token, &public_key).is_err());
+        assert!(WakeToken::decode("nomade-share:abc").is_err());
+        let long = format!("{}{}", TOKEN_PREFIX, "A".repeat(MAX_TOKEN_LEN));
+        assert!(WakeToken::decode(&long).is_err());
+    }
+
+    #[test]
+    fn test_replays_rejected_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wake.json");
+        let (laptop, phone) = (generate_keypair(), generate_keypair());
+        let token = WakeToken::issue(&laptop, phone.device_id().clone()).unwrap();
+        WakeValidator::open(phone.device_id().clone(), &path)
+            .unwrap()
+            .accept(&token, &laptop.public_key_bytes())
+            .unwrap();
+
+        let mut validator = WakeValidator::open(phone.device_id().clone(), &path).unwrap();
+        assert!(validator
+            .accept(&token, &laptop.public_key_bytes())
+            .is_err());
+    }
+}
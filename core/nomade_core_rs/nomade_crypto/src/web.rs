@@ -0,0 +1,98 @@
+//! JavaScript bindings for a web client
+//!
+//! Lets a browser viewer decode pairing offers and check signatures
+//! without a native bridge. As with the app bridge, structured values
+//! cross as JSON strings.
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use wasm_bindgen::prelude::*;
+
+use crate::{qr_payload, DeviceId, QrReassembler};
+
+/// Decode a `nomade://pair` URL and check its signature
+///
+/// Returns the JSON-encoded `PairingOffer`. Freshness and replay checks
+/// are left to the device that completes the pairing.
+#[wasm_bindgen(js_name = decodePairingOffer)]
+pub fn decode_pairing_offer(url: &str) -> Result<String, JsError> {
+    let offer = qr_payload::decode_pairing_offer(url)?;
+    offer.verify_signature()?;
+    Ok(serde_json::to_string(&offer)?)
+}
+
+/// Whether `signature` over `message` was made by `public_key`
+#[wasm_bindgen(js_name = verifySignature)]
+pub fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    VerifyingKey::from_bytes(&public_key).is_ok_and(|key| key.verify(message, &signature).is_ok())
+}
+
+/// Device ID of a raw Ed25519 public key
+#[wasm_bindgen(js_name = deviceIdFromPublicKey)]
+pub fn device_id_from_public_key(public_key: &[u8]) -> Result<String, JsError> {
+    let public_key = <[u8; 32]>::try_from(public_key)
+        .map_err(|_| JsError::new("Public key must be 32 bytes"))?;
+    let key = VerifyingKey::from_bytes(&public_key)?;
+    Ok(DeviceId::from_public_key(&key).to_string())
+}
+
+/// Collects the parts of an animated pairing QR code
+#[wasm_bindgen(js_name = PairingOfferScanner)]
+#[derive(Default)]
+pub struct PairingOfferScanner {
+    parts: QrReassembler,
+}
+
+#[wasm_bindgen(js_class = PairingOfferScanner)]
+impl PairingOfferScanner {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a scanned part, returning the JSON-encoded offer once complete
+    pub fn add(&mut self, url: &str) -> Result<Option<String>, JsError> {
+        let Some(offer) = self.parts.push(url)? else {
+            return Ok(None);
+        };
+        offer.verify_signature()?;
+        Ok(Some(serde_json::to_string(&offer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_pairing_offer, generate_keypair, PairingOffer};
+
+    #[test]
+    fn test_decode_and_verify() {
+        let keypair = generate_keypair();
+        let mut offer = PairingOffer::new(
+            keypair.device_id().clone(),
+            "Laptop".into(),
+            keypair.public_key_bytes(),
+            vec![],
+        );
+        offer.sign(&keypair).unwrap();
+        let json = decode_pairing_offer(&encode_pairing_offer(&offer).unwrap()).unwrap();
+        let decoded: PairingOffer = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.device_id, *keypair.device_id());
+        assert_eq!(
+            device_id_from_public_key(&decoded.public_key).unwrap(),
+            keypair.device_id().to_string()
+        );
+
+        let public_key = keypair.public_key_bytes();
+        let signature = keypair.sign(b"hello").unwrap().to_bytes();
+        assert!(verify_signature(&public_key, b"hello", &signature));
+        assert!(!verify_signature(&public_key, b"hellp", &signature));
+        assert!(!verify_signature(&public_key[..31], b"hello", &signature));
+        assert!(!verify_signature(&public_key, b"hello", &signature[..63]));
+    }
+}
@@ -0,0 +1,184 @@
+//! Local and remote wipe
+//!
+//! `wipe_dir` destroys everything a device stored: small files (keys,
+//! stores, journals) are overwritten with random bytes before being
+//! removed, so the identity key and store keys cannot be recovered from
+//! the disk; large files only hold data encrypted under those keys and are
+//! removed directly to keep a panic wipe fast.
+//!
+//! A `WipeCommand` asks another device to wipe itself. It is signed by the
+//! sending device and honored only if fresh and sent by a trusted device
+//! of the user this device is enrolled under (see `TrustStore::check_wipe`).
+
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::unix_time;
+use crate::{CryptoError, DeviceId, DeviceKeypair, Result};
+
+/// How long a wipe command stays valid after being issued
+pub const WIPE_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Tolerated clock difference for commands issued "in the future"
+const CLOCK_SKEW: u64 = 60;
+
+/// Files up to this size are overwritten before removal
+pub const SHRED_MAX_BYTES: u64 = 1024 * 1024;
+
+/// Signed request for a device to wipe itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WipeCommand {
+    pub target: DeviceId,
+    pub issued_by: DeviceId,
+    /// Seconds since the Unix epoch
+    pub issued_at: u64,
+    pub signature: Vec<u8>,
+}
+
+impl WipeCommand {
+    /// Create and sign a wipe command for `target`
+    pub fn new(signer: &DeviceKeypair, target: DeviceId) -> Result<Self> {
+        let mut command = Self {
+            target,
+            issued_by: signer.device_id().clone(),
+            issued_at: unix_time(),
+            signature: vec![],
+        };
+        command.signature = signer.sign(&command.signing_payload())?.to_bytes().to_vec();
+        Ok(command)
+    }
+
+    /// Get signing payload
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"nomade-wipe-v1");
+        payload.extend_from_slice(self.target.0.as_bytes());
+        payload.extend_from_slice(self.issued_by.0.as_bytes());
+        payload.extend_from_slice(&self.issued_at.to_le_bytes());
+        payload
+    }
+
+    /// Verify the signature with the issuing device's key and the age
+    pub fn verify(&self, public_key: &[u8]) -> Result<()> {
+        self.verify_at(public_key, unix_time())
+    }
+
+    /// Verify at the given time (seconds since UNIX epoch)
+    pub fn verify_at(&self, public_key: &[u8], now: u64) -> Result<()> {
+        let bytes: [u8; 32] = public_key.try_into().map_err(|_| CryptoError::InvalidKey)?;
+        let key = VerifyingKey::from_bytes(&bytes).map_err(|_| CryptoError::InvalidKey)?;
+        if DeviceId::from_public_key(&key) != self.issued_by {
+            return Err(CryptoError::InvalidSignature);
+        }
+        let signature =
+            Signature::from_slice(&self.signature).map_err(|_| CryptoError::InvalidSignature)?;
+        key.verify(&self.signing_payload(), &signature)
+            .map_err(|_| CryptoError::InvalidSignature)?;
+
+        if self.issued_at > now + CLOCK_SKEW
+            || now.saturating_sub(self.issued_at) > WIPE_MAX_AGE.as_secs()
+        {
+            return Err(CryptoError::InvalidWipe("Command is stale".into()));
+        }
+        Ok(())
+    }
+}
+
+/// Overwrite a file with random bytes, then remove it
+pub fn shred_file(path: &Path) -> std::io::Result<()> {
+    let len = match std::fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    let mut block = vec![0u8; 64 * 1024];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(block.len() as u64) as usize;
+        rand::thread_rng().fill_bytes(&mut block[..n]);
+        file.write_all(&block[..n])?;
+        left -= n as u64;
+    }
+    file.sync_all()?;
+    drop(file);
+    std::fs::remove_file(path)
+}
+
+/// Destroy everything under `dir`, and `dir` itself
+///
+/// Returns the number of files overwritten before removal.
+pub fn wipe_dir(dir: &Path) -> std::io::Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut shredded = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            shredded += wipe_dir(&entry.path())?;
+        } else if file_type.is_file() && entry.metadata()?.len() <= SHRED_MAX_BYTES {
+            shred_file(&entry.path())?;
+            shredded += 1;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    std::fs::remove_dir(dir)?;
+    Ok(shredded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_keypair;
+
+    #[test]
+    fn test_wipe_commands_are_signed_and_fresh() {
+        let (phone, laptop) = (generate_keypair(), generate_keypair());
+        let command = WipeCommand::new(&phone, laptop.device_id().clone()).unwrap();
+        command.verify(&phone.public_key_bytes()).unwrap();
+        assert!(matches!(
+            command.verify(&laptop.public_key_bytes()),
+            Err(CryptoError::InvalidSignature)
+        ));
+
+        let mut retargeted = command.clone();
+        retargeted.target = phone.device_id().clone();
+        assert!(retargeted.verify(&phone.public_key_bytes()).is_err());
+
+        let mut stale = command;
+        stale.issued_at -= WIPE_MAX_AGE.as_secs() + 1;
+        stale.signature = phone
+            .sign(&stale.signing_payload())
+            .unwrap()
+            .to_bytes()
+            .to_vec();
+        assert!(matches!(
+            stale.verify(&phone.public_key_bytes()),
+            Err(CryptoError::InvalidWipe(_))
+        ));
+    }
+
+    #[test]
+    fn test_wipe_dir_removes_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir_all(data.join("artifacts")).unwrap();
+        std::fs::write(data.join("identity.key"), [7u8; 32]).unwrap();
+        std::fs::write(data.join("artifacts").join("db"), b"journal").unwrap();
+        std::fs::write(data.join("blob"), vec![1u8; SHRED_MAX_BYTES as usize + 1]).unwrap();
+
+        assert_eq!(wipe_dir(&data).unwrap(), 2);
+        assert!(!data.exists());
+        assert_eq!(wipe_dir(&data).unwrap(), 0);
+    }
+}
@@ -0,0 +1,117 @@
+//! Envelope encryption
+//!
+//! Data is encrypted under random data keys (DEKs), and each DEK is
+//! wrapped (AES-256-GCM) by a key-encryption key (KEK) such as the device
+//! master key. Rotating the KEK only rewraps the small DEKs; the data
+//! ciphertext is untouched.
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{decrypt_error, key_id};
+use crate::{
+    ct_eq, decrypt_data, encrypt_data, CryptoError, DecryptError, EncryptedData, KeyContext, Result,
+};
+
+/// Data key encrypted under a key-encryption key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrappedKey {
+    /// `key_id` of the KEK that wrapped this key
+    pub kek_id: String,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Random 256-bit data key
+pub fn generate_key() -> [u8; 32] {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+/// Wrap a data key under `kek`
+pub fn wrap_key(kek: &[u8; 32], dek: &[u8; 32]) -> Result<WrappedKey> {
+    let encrypted = encrypt_data(dek, kek)?;
+    Ok(WrappedKey {
+        kek_id: key_id(kek),
+        nonce: encrypted.nonce,
+        ciphertext: encrypted.ciphertext,
+    })
+}
+
+/// Wrap a data key under a long-lived KEK, drawing the context's next nonce
+pub fn wrap_key_with(kek: &mut KeyContext, dek: &[u8; 32]) -> Result<WrappedKey> {
+    let encrypted = kek.encrypt(dek)?;
+    Ok(WrappedKey {
+        kek_id: kek.key_id(),
+        nonce: encrypted.nonce,
+        ciphertext: encrypted.ciphertext,
+    })
+}
+
+/// Recover a data key wrapped under the key of `kek`
+pub fn unwrap_key_with(kek: &KeyContext, wrapped: &WrappedKey) -> Result<[u8; 32]> {
+    unwrap_key(kek.key(), wrapped)
+}
+
+/// Recover a data key wrapped under `kek`
+pub fn unwrap_key(kek: &[u8; 32], wrapped: &WrappedKey) -> Result<[u8; 32]> {
+    if !ct_eq(wrapped.kek_id.as_bytes(), key_id(kek).as_bytes()) {
+        return Err(decrypt_error(
+            DecryptError::WrongKey,
+            format_args!("wrapped under key {}", wrapped.kek_id),
+        ));
+    }
+    let dek = decrypt_data(
+        &EncryptedData {
+            ciphertext: wrapped.ciphertext.clone(),
+            nonce: wrapped.nonce.clone(),
+            algorithm: "AES-256-GCM".into(),
+        },
+        kek,
+    )?;
+    dek.try_into().map_err(|_| CryptoError::InvalidKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_roundtrip() {
+        let kek = generate_key();
+        let dek = generate_key();
+        let wrapped = wrap_key(&kek, &dek).unwrap();
+        assert_eq!(wrapped.kek_id, key_id(&kek));
+        assert_eq!(unwrap_key(&kek, &wrapped).unwrap(), dek);
+
+        assert!(matches!(
+            unwrap_key(&generate_key(), &wrapped),
+            Err(CryptoError::DecryptionFailed(DecryptError::WrongKey))
+        ));
+        let mut tampered = wrapped;
+        tampered.ciphertext[0] ^= 1;
+        assert!(matches!(
+            unwrap_key(&kek, &tampered),
+            Err(CryptoError::DecryptionFailed(DecryptError::Tampered))
+        ));
+        tampered.ciphertext.truncate(8);
+        assert!(matches!(
+            unwrap_key(&kek, &tampered),
+            Err(CryptoError::DecryptionFailed(DecryptError::Truncated))
+        ));
+    }
+
+    #[test]
+    fn test_wrap_with_key_context() {
+        let kek = generate_key();
+        let mut context = KeyContext::new(kek, crate::NonceSequence::counter());
+        let dek = generate_key();
+        let first = wrap_key_with(&mut context, &dek).unwrap();
+        let second = wrap_key_with(&mut context, &dek).unwrap();
+        assert_ne!(first.nonce, second.nonce);
+        assert_eq!(context.messages(), 2);
+        assert_eq!(unwrap_key(&kek, &first).unwrap(), dek);
+        assert_eq!(unwrap_key_with(&context, &second).unwrap(), dek);
+    }
+}
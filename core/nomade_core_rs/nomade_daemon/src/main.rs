@@ -0,0 +1,151 @@
+//! Headless Nomade daemon
+//!
+//! Runs the Nomade runtime without a UI, for servers and always-on
+//! machines. Paired devices connect to its network listener, or are dialed
+//! on request, and sync both ways over that connection. JSON-RPC requests
+//! on a local socket let scripts pair devices, list artifacts and trigger
+//! syncs.
+//!
+//! ```text
+//! nomaded (--config <config.json> | --data-dir <dir>) [--socket <path>]
+//! ```
+//!
+//! The socket defaults to `daemon.sock` in the data directory.
+
+#[cfg_attr(not(unix), allow(dead_code))]
+mod rpc;
+#[cfg(unix)]
+mod server;
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail};
+use nomade_core::{api, NomadeConfig};
+
+/// Control socket under the data directory unless `--socket` is given
+const SOCKET_FILE: &str = "daemon.sock";
+
+const USAGE: &str = "usage: nomaded (--config <config.json> | --data-dir <dir>) [--socket <path>]";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    config: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+    socket: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            let slot = match arg.as_str() {
+                "--config" => &mut parsed.config,
+                "--data-dir" => &mut parsed.data_dir,
+                "--socket" => &mut parsed.socket,
+                "-h" | "--help" => bail!(USAGE),
+                other => bail!("Unknown argument {}\n{}", other, USAGE),
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{} needs a value\n{}", arg, USAGE))?;
+            *slot = Some(value.into());
+        }
+        if parsed.config.is_some() == parsed.data_dir.is_some() {
+            bail!(USAGE);
+        }
+        Ok(parsed)
+    }
+
+    /// JSON-encoded `NomadeConfig` to start the runtime with
+    fn config_json(&self) -> anyhow::Result<String> {
+        match (&self.config, &self.data_dir) {
+            (Some(path), _) => Ok(std::fs::read_to_string(path)?),
+            (None, Some(dir)) => Ok(serde_json::to_string(&NomadeConfig::new(dir))?),
+            (None, None) => bail!(USAGE),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse(std::env::args().skip(1))?;
+    let config_json = args.config_json()?;
+    let config = NomadeConfig::from_json(&config_json)?;
+    let socket = args
+        .socket
+        .clone()
+        .unwrap_or_else(|| config.data_dir.join(SOCKET_FILE));
+
+    api::ffi_init(config_json)?;
+    let result = api::ffi_listen().and_then(|port| {
+        tracing::info!("Accepting peers on port {}", port);
+        run(socket)
+    });
+    api::ffi_shutdown()?;
+    result
+}
+
+#[cfg(unix)]
+fn run(socket: PathBuf) -> anyhow::Result<()> {
+    let (stop, stopped) = std::sync::mpsc::channel();
+    let control = server::ControlSocket::bind(&socket)?;
+    control.spawn(stop.clone())?;
+
+    // Wait for Ctrl-C on a runtime of its own; the core executor belongs to the bridge
+    std::thread::spawn(move || {
+        let signals = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("signal runtime");
+        if signals.block_on(tokio::signal::ctrl_c()).is_ok() {
+            stop.send(()).ok();
+        }
+    });
+
+    tracing::info!("Nomade daemon listening on {}", socket.display());
+    stopped.recv().ok();
+    tracing::info!("Nomade daemon stopping");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn run(_socket: PathBuf) -> anyhow::Result<()> {
+    bail!("nomaded needs Unix domain sockets")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&[
+                "--data-dir",
+                "/var/lib/nomade",
+                "--socket",
+                "/run/nomade.sock"
+            ])
+            .unwrap(),
+            Args {
+                config: None,
+                data_dir: Some("/var/lib/nomade".into()),
+                socket: Some("/run/nomade.sock".into()),
+            }
+        );
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--data-dir"]).is_err());
+        assert!(parse(&["--config", "a.json", "--data-dir", "b"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let json = parse(&["--data-dir", dir.path().to_str().unwrap()])
+            .unwrap()
+            .config_json()
+            .unwrap();
+        assert_eq!(NomadeConfig::from_json(&json).unwrap().data_dir, dir.path());
+    }
+}
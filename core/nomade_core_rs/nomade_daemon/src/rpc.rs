@@ -0,0 +1,258 @@
+//! JSON-RPC 2.0 control API
+//!
+//! Requests and responses are single lines of JSON. Each method forwards to
+//! the `ffi_*` bridge function the apps call, so the daemon exposes exactly
+//! what the UI can do.
+
+use nomade_core::api;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Request line is not valid JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// JSON is not a request object
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Method ran and failed
+pub const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(SERVER_ERROR, e)
+    }
+}
+
+/// Response to one request line
+pub struct Handled {
+    /// Response line, without the trailing newline
+    pub response: String,
+    /// Client asked the daemon to stop
+    pub shutdown: bool,
+}
+
+/// Answer one request line
+pub fn handle(line: &str) -> Handled {
+    let mut shutdown = false;
+    let (id, result) = match serde_json::from_str::<Value>(line) {
+        Err(e) => (Value::Null, Err(RpcError::new(PARSE_ERROR, e))),
+        Ok(value) => match serde_json::from_value::<Request>(value) {
+            Err(e) => (Value::Null, Err(RpcError::new(INVALID_REQUEST, e))),
+            Ok(request) if request.jsonrpc != "2.0" => (
+                request.id,
+                Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+            ),
+            Ok(request) => {
+                shutdown = request.method == "shutdown";
+                (request.id, call(&request.method, request.params))
+            }
+        },
+    };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => {
+            shutdown = false;
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": e.code, "message": e.message },
+            })
+        }
+    };
+    Handled {
+        response: response.to_string(),
+        shutdown,
+    }
+}
+
+#[derive(Deserialize)]
+struct PairingOfferParams {
+    device_name: String,
+}
+
+#[derive(Deserialize)]
+struct AcceptPairingParams {
+    offer: String,
+}
+
+#[derive(Deserialize)]
+struct PeerParams {
+    peer_id: String,
+}
+
+#[derive(Deserialize)]
+struct ConnectParams {
+    peer_id: String,
+    address: String,
+}
+
+#[derive(Deserialize)]
+struct SnippetParams {
+    peer_id: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct RevokeParams {
+    peer_id: String,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct ResolveParams {
+    artifact_id: String,
+    resolution: Value,
+}
+
+#[derive(Deserialize)]
+struct HandleParams {
+    handle: u64,
+}
+
+fn call(method: &str, params: Value) -> Result<Value, RpcError> {
+    Ok(match method {
+        "device_id" => json!(api::ffi_device_id()?),
+        "pairing_offer" => {
+            let params: PairingOfferParams = parse(params)?;
+            json!(api::ffi_pairing_offer(params.device_name)?)
+        }
+        "accept_pairing_offer" => {
+            let params: AcceptPairingParams = parse(params)?;
+            json!(api::ffi_accept_pairing_offer(params.offer)?)
+        }
+        "devices" => embed(&api::ffi_trusted_devices()?)?,
+        "revoke_device" => {
+            let params: RevokeParams = parse(params)?;
+            embed(&api::ffi_revoke_device(params.peer_id, params.reason)?)?
+        }
+        "send_snippet" => {
+            let params: SnippetParams = parse(params)?;
+            embed(&api::ffi_send_snippet(params.peer_id, params.text)?)?
+        }
+        "listen" => json!(api::ffi_listen()?),
+        "connect_peer" => {
+            let params: ConnectParams = parse(params)?;
+            api::ffi_connect_peer(params.peer_id, params.address)?;
+            Value::Null
+        }
+        "linked_peers" => embed(&api::ffi_linked_peers()?)?,
+        "reencryption_progress" => embed(&api::ffi_reencryption_progress()?)?,
+        "artifacts" => embed(&api::ffi_artifacts()?)?,
+        "start_sync" => {
+            let params: PeerParams = parse(params)?;
+            json!(api::ffi_start_sync(params.peer_id)?)
+        }
+        "plan_sync" => {
+            let params: PeerParams = parse(params)?;
+            embed(&api::ffi_plan_sync(params.peer_id)?)?
+        }
+        "wake_token" => {
+            let params: PeerParams = parse(params)?;
+            json!(api::ffi_wake_token(params.peer_id)?)
+        }
+        "cancel_sync" => {
+            let params: HandleParams = parse(params)?;
+            json!(api::ffi_cancel_sync(params.handle)?)
+        }
+        "conflicts" => embed(&api::ffi_conflicts()?)?,
+        "resolve_conflict" => {
+            let params: ResolveParams = parse(params)?;
+            embed(&api::ffi_resolve_conflict(
+                params.artifact_id,
+                params.resolution.to_string(),
+            )?)?
+        }
+        "metrics" => embed(&api::ffi_metrics_snapshot()?)?,
+        "store_health" => embed(&api::ffi_store_health()?)?,
+        "shutdown" => Value::Null,
+        other => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {}", other),
+            ))
+        }
+    })
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+/// Inline a JSON string returned by the bridge
+fn embed(json: &str) -> Result<Value, RpcError> {
+    serde_json::from_str(json).map_err(|e| RpcError::new(SERVER_ERROR, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_code(line: &str) -> (Value, i64) {
+        let response: Value = serde_json::from_str(&handle(line).response).unwrap();
+        (
+            response["id"].clone(),
+            response["error"]["code"].as_i64().unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_request_errors() {
+        assert_eq!(error_code("{not json").1, PARSE_ERROR);
+        assert_eq!(error_code(r#"{"id": 1}"#).1, INVALID_REQUEST);
+        assert_eq!(
+            error_code(r#"{"jsonrpc": "1.0", "id": 2, "method": "devices"}"#),
+            (json!(2), INVALID_REQUEST)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc": "2.0", "id": "a", "method": "reboot"}"#),
+            (json!("a"), METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error_code(r#"{"jsonrpc": "2.0", "id": 3, "method": "cancel_sync"}"#).1,
+            INVALID_PARAMS
+        );
+        // The bridge refuses calls before the runtime starts
+        assert_eq!(
+            error_code(r#"{"jsonrpc": "2.0", "id": 4, "method": "device_id"}"#).1,
+            SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_shutdown_request() {
+        let handled = handle(r#"{"jsonrpc": "2.0", "id": 1, "method": "shutdown"}"#);
+        assert!(handled.shutdown);
+        assert_eq!(
+            serde_json::from_str::<Value>(&handled.response).unwrap(),
+            json!({ "jsonrpc": "2.0", "id": 1, "result": null })
+        );
+        assert!(!handle(r#"{"jsonrpc": "1.0", "id": 2, "method": "shutdown"}"#).shutdown);
+    }
+}
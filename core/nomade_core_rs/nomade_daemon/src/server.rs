@@ -0,0 +1,159 @@
+//! Local control socket
+//!
+//! A Unix domain socket readable only by the owner, so only processes of
+//! the user running the daemon can control it. Each client gets its own
+//! thread; the bridge functions block on the core executor, which must not
+//! happen from inside another async runtime.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use anyhow::bail;
+
+use crate::rpc;
+
+/// Longest request line accepted from a client
+const MAX_REQUEST: u64 = 1024 * 1024;
+
+/// Bound control socket
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Bind at `path`, replacing a stale socket left by a crashed daemon
+    ///
+    /// The socket is bound inside a private directory and only moved to
+    /// `path` once its permissions are restricted, so no other user can
+    /// connect in between.
+    pub fn bind(path: &Path) -> anyhow::Result<Self> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("Another daemon is listening on {}", path.display());
+            }
+            std::fs::remove_file(path)?;
+        }
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let private = parent.join(format!(".nomaded-{}", std::process::id()));
+        std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+        let bound = bind_private(&private, path);
+        std::fs::remove_dir_all(&private).ok();
+        Ok(Self {
+            listener: bound?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Serve clients on background threads
+    ///
+    /// `stop` receives a message when a client calls `shutdown`. The socket
+    /// file is removed when `self` is dropped.
+    pub fn spawn(&self, stop: Sender<()>) -> std::io::Result<()> {
+        let listener = self.listener.try_clone()?;
+        std::thread::Builder::new()
+            .name("nomaded-control".into())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let stop = stop.clone();
+                            std::thread::spawn(move || serve_client(stream, stop));
+                        }
+                        Err(e) => tracing::warn!("Failed to accept control client: {}", e),
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Bind in the 0700 directory `private`, restrict and move to `path`
+fn bind_private(private: &Path, path: &Path) -> std::io::Result<UnixListener> {
+    let staged = private.join("daemon.sock");
+    let listener = UnixListener::bind(&staged)?;
+    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(&staged, path)?;
+    Ok(listener)
+}
+
+fn serve_client(stream: UnixStream, stop: Sender<()>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        match (&mut reader).take(MAX_REQUEST).read_line(&mut line) {
+            Ok(0) => return,
+            Ok(_) if !line.ends_with('\n') => {
+                tracing::debug!("Dropping control client after an oversized request");
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::debug!("Control client read failed: {}", e);
+                return;
+            }
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let handled = rpc::handle(line.trim_end());
+        if writeln!(writer, "{}", handled.response).is_err() {
+            return;
+        }
+        if handled.shutdown {
+            stop.send(()).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_control_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daemon.sock");
+        // Stale socket from a crashed daemon
+        drop(UnixListener::bind(&path).unwrap());
+
+        let socket = ControlSocket::bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let (stop, stopped) = mpsc::channel();
+        socket.spawn(stop).unwrap();
+        assert!(ControlSocket::bind(&path).is_err());
+
+        let client = UnixStream::connect(&path).unwrap();
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut writer = client;
+        let mut call = |request: &str| {
+            writeln!(writer, "{}", request).unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<serde_json::Value>(&line).unwrap()
+        };
+        assert_eq!(call("nope")["error"]["code"], rpc::PARSE_ERROR);
+        assert!(stopped.try_recv().is_err());
+        assert!(call(r#"{"jsonrpc": "2.0", "id": 1, "method": "shutdown"}"#)["result"].is_null());
+        stopped.recv().unwrap();
+        drop(socket);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,142 @@
+//! Coalescing bursts of events
+//!
+//! Bulk operations publish thousands of events in a row. `run_batcher`
+//! republishes a stream with bursts collapsed: events arriving within
+//! `BatchConfig::window` of the first one are sent together as one
+//! `Event::Batched`, with repeats of the same event reduced to their last
+//! occurrence. Events keep their relative order, and a lone event is passed
+//! through as-is. Control events skip batching and go out immediately.
+
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::{Event, EventPriority, EventReceiver, EventStream};
+
+/// How bursts are collected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// How long to wait for more events after the first of a burst
+    pub window: Duration,
+    /// Most events in one batch; larger bursts are split
+    pub max_events: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(50),
+            max_events: 500,
+        }
+    }
+}
+
+/// Republish events from `rx` into `out` in batches
+///
+/// Returns once the source stream closes, after flushing what it holds.
+pub async fn run_batcher(mut rx: EventReceiver, out: EventStream, config: BatchConfig) {
+    let mut pending = Vec::new();
+    loop {
+        let received = if pending.is_empty() {
+            rx.recv().await
+        } else {
+            let deadline = Instant::now() + config.window;
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(received) => received,
+                Err(_) => {
+                    flush(&out, &mut pending);
+                    continue;
+                }
+            }
+        };
+        match received {
+            Ok(event) if event.priority() == EventPriority::Control => out.publish(event),
+            Ok(event) => {
+                pending.push(event);
+                if pending.len() >= config.max_events.max(1) {
+                    flush(&out, &mut pending);
+                }
+            }
+            // Subscribers reload state when they see a gap anyway
+            Err(broadcast::error::RecvError::Lagged(_)) => flush(&out, &mut pending),
+            Err(broadcast::error::RecvError::Closed) => {
+                flush(&out, &mut pending);
+                return;
+            }
+        }
+    }
+}
+
+fn flush(out: &EventStream, pending: &mut Vec<Event>) {
+    let mut events = coalesce(std::mem::take(pending));
+    match events.len() {
+        0 => {}
+        1 => out.publish(events.remove(0)),
+        _ => out.publish(Event::Batched { events }),
+    }
+}
+
+/// Drop events repeated later in the burst, keeping order otherwise
+fn coalesce(events: Vec<Event>) -> Vec<Event> {
+    let mut kept: Vec<Event> = Vec::with_capacity(events.len());
+    for event in events.into_iter().rev() {
+        if !kept.contains(&event) {
+            kept.push(event);
+        }
+    }
+    kept.reverse();
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn created(id: &str) -> Event {
+        Event::ArtifactCreated { id: id.into() }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bursts_become_ordered_batches() {
+        let source = EventStream::new();
+        let out = EventStream::new();
+        let mut batches = out.subscribe();
+        let config = BatchConfig {
+            window: Duration::from_millis(50),
+            max_events: 3,
+        };
+        let batcher = tokio::spawn(run_batcher(source.subscribe(), out.clone(), config));
+
+        let updated = Event::ArtifactUpdated { id: "a".into() };
+        source.publish(updated.clone());
+        source.publish(created("b"));
+        source.publish(updated.clone());
+        source.publish(created("c"));
+        source.publish(Event::SyncStarted);
+        source.publish(created("d"));
+        // The control event overtakes the burst it was published in
+        assert_eq!(batches.recv().await.unwrap(), Event::SyncStarted);
+        assert_eq!(
+            batches.recv().await.unwrap(),
+            Event::Batched {
+                events: vec![created("b"), updated]
+            }
+        );
+        assert_eq!(
+            batches.recv().await.unwrap(),
+            Event::Batched {
+                events: vec![created("c"), created("d")]
+            }
+        );
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        source.publish(created("e"));
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        source.publish(created("f"));
+        drop(source);
+        batcher.await.unwrap();
+        assert_eq!(batches.recv().await.unwrap(), created("e"));
+        assert_eq!(batches.recv().await.unwrap(), created("f"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Event stream bridge for other local processes
+//!
+//! `IpcBridge` serves an `EventStream` over a Unix domain socket (a named
+//! pipe on Windows) so tools running outside the app, such as a CLI, can
+//! subscribe. Access is limited to processes that can read the token file
+//! written next to it, which only the owning user can: a client sends the
+//! token as its first line, the bridge answers `ok` and then writes one
+//! JSON-encoded `Event` per line.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nomade_crypto::fs::write_private;
+use rand::RngCore;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines,
+};
+use tokio::sync::broadcast;
+
+use crate::{Event, EventStream};
+
+/// Reply to a client that presented the right token
+const ACCEPTED: &str = "ok";
+
+/// Longest token line a client may send
+const MAX_TOKEN_LINE: usize = 256;
+
+/// Listener serving an event stream to local processes
+pub struct IpcBridge {
+    endpoint: PathBuf,
+    token: String,
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+}
+
+impl IpcBridge {
+    /// Listen at `endpoint`, writing a fresh access token to `token_path`
+    ///
+    /// On Windows the file name of `endpoint` names a pipe under `\\.\pipe\`.
+    pub fn bind(endpoint: impl AsRef<Path>, token_path: impl AsRef<Path>) -> io::Result<Self> {
+        let endpoint = endpoint.as_ref().to_path_buf();
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write_private(token_path.as_ref(), token.as_bytes())?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // A socket left behind by a crashed process blocks binding
+            match std::fs::remove_file(&endpoint) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            let listener = tokio::net::UnixListener::bind(&endpoint)?;
+            std::fs::set_permissions(&endpoint, std::fs::Permissions::from_mode(0o600))?;
+            Ok(Self {
+                endpoint,
+                token,
+                listener,
+            })
+        }
+        #[cfg(windows)]
+        Ok(Self { endpoint, token })
+    }
+
+    /// Serve `events` to authenticated clients until accepting fails
+    pub async fn run(self, events: EventStream) -> io::Result<()> {
+        #[cfg(unix)]
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            tokio::spawn(serve_client(stream, self.token.clone(), events.clone()));
+        }
+        #[cfg(windows)]
+        {
+            use tokio::net::windows::named_pipe::ServerOptions;
+            let name = pipe_name(&self.endpoint);
+            let mut server = ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(&name)?;
+            loop {
+                server.connect().await?;
+                let client = std::mem::replace(&mut server, ServerOptions::new().create(&name)?);
+                tokio::spawn(serve_client(client, self.token.clone(), events.clone()));
+            }
+        }
+    }
+}
+
+impl Drop for IpcBridge {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.endpoint);
+    }
+}
+
+async fn serve_client<S>(stream: S, token: String, events: EventStream)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Subscribe before answering so the client misses nothing after `ok`
+    let mut rx = events.subscribe();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader.take(MAX_TOKEN_LINE as u64)).lines();
+    let presented = lines.next_line().await.ok().flatten().unwrap_or_default();
+    if !same_token(presented.trim().as_bytes(), token.as_bytes()) {
+        return;
+    }
+    if send_line(&mut writer, ACCEPTED).await.is_err() {
+        return;
+    }
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            // The subscriber is expected to refresh its state on gaps
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let Ok(json) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if send_line(&mut writer, &json).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn send_line(writer: &mut (impl AsyncWrite + Unpin), line: &str) -> io::Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+/// Compare tokens without leaking how much of a guess was right
+fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(windows)]
+fn pipe_name(endpoint: &Path) -> String {
+    let name = endpoint
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "nomade-events".into());
+    format!(r"\\.\pipe\{}", name)
+}
+
+/// Client subscribed to an `IpcBridge`
+pub struct IpcSubscriber {
+    lines: Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>,
+}
+
+impl IpcSubscriber {
+    /// Connect to the bridge at `endpoint` with the token in `token_path`
+    pub async fn connect(
+        endpoint: impl AsRef<Path>,
+        token_path: impl AsRef<Path>,
+    ) -> io::Result<Self> {
+        let token = std::fs::read_to_string(token_path)?;
+        #[cfg(unix)]
+        let stream = tokio::net::UnixStream::connect(endpoint.as_ref()).await?;
+        #[cfg(windows)]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new()
+            .open(pipe_name(endpoint.as_ref()))?;
+
+        let (reader, mut writer) = tokio::io::split(stream);
+        send_line(&mut writer, token.trim()).await?;
+        let reader: Box<dyn AsyncRead + Send + Unpin> = Box::new(reader);
+        let mut lines = BufReader::new(reader).lines();
+        match lines.next_line().await? {
+            Some(line) if line == ACCEPTED => Ok(Self { lines }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "event bridge refused the token",
+            )),
+        }
+    }
+
+    /// Next event, or `None` once the bridge closed the connection
+    pub async fn recv(&mut self) -> io::Result<Option<Event>> {
+        let Some(line) = self.lines.next_line().await? else {
+            return Ok(None);
+        };
+        serde_json::from_str(&line)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribers_need_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("events.sock");
+        let token = dir.path().join("events.token");
+        let events = EventStream::new();
+        let bridge = IpcBridge::bind(&socket, &token).unwrap();
+        tokio::spawn(bridge.run(events.clone()));
+
+        let mut subscriber = IpcSubscriber::connect(&socket, &token).await.unwrap();
+        events.publish(Event::ArtifactCreated { id: "a".into() });
+        assert!(matches!(
+            subscriber.recv().await.unwrap(),
+            Some(Event::ArtifactCreated { id }) if id == "a"
+        ));
+
+        let forged = dir.path().join("forged.token");
+        std::fs::write(&forged, "0".repeat(64)).unwrap();
+        let refused = IpcSubscriber::connect(&socket, &forged).await;
+        assert_eq!(
+            refused.err().unwrap().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&token).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -1,42 +1,477 @@
 //! Event stream system for Nomade
 //!
 //! Provides pub/sub event system for real-time updates
+//!
+//! Events cross process and version boundaries (to the Flutter app and to
+//! paired devices), so they serialize as a versioned envelope:
+//! `{"v":2,"type":"artifact_created","payload":{"id":"a"}}`. Readers turn
+//! types they don't know into `Event::Unknown` instead of failing, and still
+//! accept the externally tagged form written by schema version 1.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use tokio::sync::{broadcast, Notify};
 
-use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast;
+mod batch;
+#[cfg(feature = "ipc")]
+mod ipc;
+
+pub use batch::{run_batcher, BatchConfig};
+#[cfg(feature = "ipc")]
+pub use ipc::{IpcBridge, IpcSubscriber};
+
+/// Version of the event envelope written by this build
+pub const EVENT_SCHEMA_VERSION: u32 = 2;
+
+/// Progress repairing corrupted artifact content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepairStatus {
+    /// Damaged content was removed; waiting for a peer to provide it
+    Quarantined,
+    /// Content was fetched again from a peer
+    Repaired,
+}
+
+/// Largest snippet text sent between devices, in bytes
+pub const MAX_SNIPPET_LEN: usize = 64 * 1024;
+
+/// Short text (a URL, a note) sent to one paired device
+///
+/// Snippets only travel as events on the live channel and are not stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snippet {
+    pub id: String,
+    pub text: String,
+    /// Unix time the sender sent it, in seconds
+    pub sent_at: u64,
+}
 
 /// Event types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// New fields on existing variants must be `#[serde(default)]` so events
+/// from older builds still decode.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    remote = "Self",
+    tag = "type",
+    content = "payload",
+    rename_all = "snake_case"
+)]
 pub enum Event {
-    ArtifactCreated { id: String },
-    ArtifactUpdated { id: String },
-    ArtifactDeleted { id: String },
-    DeviceConnected { device_id: String },
-    DeviceDisconnected { device_id: String },
+    ArtifactCreated {
+        id: String,
+    },
+    ArtifactUpdated {
+        id: String,
+    },
+    ArtifactDeleted {
+        id: String,
+    },
+    /// An artifact was moved to the trash, here or by a peer
+    ArtifactTrashed {
+        id: String,
+    },
+    /// An artifact was taken back out of the trash
+    ArtifactRestored {
+        id: String,
+    },
+    /// Artifact content failed verification
+    ArtifactCorrupted {
+        id: String,
+        status: RepairStatus,
+    },
+    CollectionCreated {
+        id: String,
+    },
+    CollectionRenamed {
+        id: String,
+        name: String,
+    },
+    CollectionMoved {
+        id: String,
+        parent: Option<String>,
+    },
+    CollectionDeleted {
+        id: String,
+    },
+    /// This device started (`editing`) or stopped editing an artifact
+    EditIntent {
+        artifact_id: String,
+        editing: bool,
+    },
+    /// A peer's edit intent lapsed without a heartbeat
+    EditIntentExpired {
+        artifact_id: String,
+        device_id: String,
+    },
+    /// Concurrent edits of an artifact wait in the conflict inbox
+    ConflictDetected {
+        artifact_id: String,
+    },
+    /// A conflict was settled with a new version replacing `superseded`
+    ConflictResolved {
+        artifact_id: String,
+        content_hash: String,
+        modified_at: u64,
+        /// Content hashes of the versions the resolution replaces
+        superseded: Vec<String>,
+    },
+    DeviceConnected {
+        device_id: String,
+    },
+    DeviceDisconnected {
+        device_id: String,
+    },
+    DeviceRevoked {
+        device_id: String,
+    },
+    /// Round-trip time or loss on a peer's connection changed noticeably
+    ConnectionQuality {
+        device_id: String,
+        /// Smoothed round-trip time
+        rtt_ms: Option<u64>,
+        /// Fraction of keepalive pings lost (0.0 - 1.0)
+        loss_rate: f64,
+    },
+    /// Identity statement of a device published or newly countersigned
+    DeviceAttested {
+        device_id: String,
+    },
+    /// Snippet for the connected peer `to`, forwarded on its live channel
+    SnippetSent {
+        to: String,
+        snippet: Snippet,
+    },
+    /// Snippet a connected peer sent to this device
+    SnippetReceived {
+        from: String,
+        snippet: Snippet,
+    },
+    /// Incoming connection refused by rate limits or admission checks
+    ConnectionRejected {
+        /// Address or device ID of the rejected peer
+        source: String,
+        reason: String,
+    },
+    /// Connectivity of this device changed
+    NetworkStateChanged {
+        /// `wifi`, `ethernet`, `cellular`, `offline` or `unknown`
+        kind: String,
+        metered: bool,
+    },
+    /// Keys were unlocked, locked, or auto-locked after their period
+    KeystoreLockChanged {
+        locked: bool,
+    },
     SyncStarted,
-    SyncCompleted { artifacts_synced: usize },
+    SyncCompleted {
+        artifacts_synced: usize,
+    },
+    TaskFailed {
+        task: String,
+        reason: String,
+    },
+    /// Operations queued for an unreachable peer changed
+    OutboxChanged {
+        peer: String,
+        depth: usize,
+    },
+    /// Event that happened on a paired device
+    Remote {
+        origin: String,
+        event: Box<Event>,
+    },
+    /// Burst of events coalesced by `run_batcher`, in order
+    Batched {
+        events: Vec<Event>,
+    },
+    /// Event of a type this build does not know, from a newer version
+    #[serde(skip)]
+    Unknown {
+        event_type: String,
+        payload: Value,
+    },
+}
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut envelope = match self {
+            Self::Unknown {
+                event_type,
+                payload,
+            } => {
+                let mut envelope = Map::new();
+                envelope.insert("type".into(), event_type.clone().into());
+                if !payload.is_null() {
+                    envelope.insert("payload".into(), payload.clone());
+                }
+                envelope
+            }
+            known => match Event::serialize(known, serde_json::value::Serializer) {
+                Ok(Value::Object(envelope)) => envelope,
+                Ok(_) => return Err(S::Error::custom("event is not an object")),
+                Err(e) => return Err(S::Error::custom(e)),
+            },
+        };
+        envelope.insert("v".into(), EVENT_SCHEMA_VERSION.into());
+        envelope.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (event_type, payload) = match Value::deserialize(deserializer)? {
+            Value::Object(mut envelope) if envelope.contains_key("type") => {
+                let Some(Value::String(event_type)) = envelope.remove("type") else {
+                    return Err(D::Error::custom("event type is not a string"));
+                };
+                (
+                    event_type,
+                    envelope.remove("payload").unwrap_or(Value::Null),
+                )
+            }
+            // Schema version 1: `"SyncStarted"` or `{"ArtifactCreated": {...}}`
+            Value::String(name) => (snake_case(&name), Value::Null),
+            Value::Object(legacy) if legacy.len() == 1 => {
+                let (name, payload) = legacy.into_iter().next().expect("one entry");
+                (snake_case(&name), payload)
+            }
+            _ => return Err(D::Error::custom("invalid event")),
+        };
+
+        let mut known = Map::new();
+        known.insert("type".into(), event_type.clone().into());
+        if !payload.is_null() {
+            known.insert("payload".into(), payload.clone());
+        }
+        Ok(
+            Event::deserialize(Value::Object(known)).unwrap_or(Self::Unknown {
+                event_type,
+                payload,
+            }),
+        )
+    }
+}
+
+/// `ArtifactCreated` to `artifact_created`
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+impl Event {
+    /// Whether the event describes shared data and is worth telling peers
+    ///
+    /// Connection, sync and task events are local state; remote events are
+    /// never relayed further.
+    pub fn is_forwardable(&self) -> bool {
+        matches!(
+            self,
+            Self::ArtifactCreated { .. }
+                | Self::ArtifactUpdated { .. }
+                | Self::ArtifactDeleted { .. }
+                | Self::ArtifactTrashed { .. }
+                | Self::ArtifactRestored { .. }
+                | Self::CollectionCreated { .. }
+                | Self::CollectionRenamed { .. }
+                | Self::CollectionMoved { .. }
+                | Self::CollectionDeleted { .. }
+                | Self::EditIntent { .. }
+                | Self::ConflictResolved { .. }
+        )
+    }
+}
+
+/// Delivery class of an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPriority {
+    /// Rare state changes; delivered first and never dropped
+    Control,
+    /// High-volume data changes; may be dropped for lagging subscribers
+    Bulk,
+}
+
+impl Event {
+    /// Delivery class of the event
+    pub fn priority(&self) -> EventPriority {
+        match self {
+            Self::SyncStarted
+            | Self::SyncCompleted { .. }
+            | Self::DeviceConnected { .. }
+            | Self::DeviceDisconnected { .. }
+            | Self::DeviceRevoked { .. }
+            | Self::DeviceAttested { .. }
+            | Self::NetworkStateChanged { .. }
+            | Self::KeystoreLockChanged { .. }
+            | Self::ConflictDetected { .. }
+            | Self::ConflictResolved { .. }
+            | Self::SnippetSent { .. }
+            | Self::SnippetReceived { .. }
+            | Self::TaskFailed { .. } => EventPriority::Control,
+            Self::Remote { event, .. } => event.priority(),
+            _ => EventPriority::Bulk,
+        }
+    }
+}
+
+/// Control events not yet received by one subscriber
+#[derive(Default)]
+struct ControlQueue {
+    events: Mutex<VecDeque<Event>>,
+    notify: Notify,
+}
+
+struct Lanes {
+    bulk: broadcast::Sender<Event>,
+    control: Mutex<Vec<Weak<ControlQueue>>>,
 }
 
 /// Event stream for subscribing to events
+///
+/// Events travel in two lanes by `EventPriority`. Bulk events share a
+/// bounded broadcast channel, so a slow subscriber can miss some and sees
+/// `RecvError::Lagged`. Control events are queued for every subscriber
+/// without bound and overtake pending bulk events, so a burst of artifact
+/// changes never delays or drops them.
+///
+/// Cloning yields another handle publishing to the same subscribers.
+#[derive(Clone)]
 pub struct EventStream {
-    tx: broadcast::Sender<Event>,
+    lanes: Arc<Lanes>,
 }
 
 impl EventStream {
     /// Create new event stream
     pub fn new() -> Self {
-        let (tx, _) = broadcast::channel(100);
-        Self { tx }
+        let (bulk, _) = broadcast::channel(100);
+        Self {
+            lanes: Arc::new(Lanes {
+                bulk,
+                control: Mutex::new(Vec::new()),
+            }),
+        }
     }
 
     /// Publish an event
     pub fn publish(&self, event: Event) {
-        let _ = self.tx.send(event); // Ignore if no subscribers
+        match event.priority() {
+            EventPriority::Control => {
+                let mut queues = self.lanes.control.lock().unwrap();
+                queues.retain(|queue| match queue.upgrade() {
+                    Some(queue) => {
+                        queue.events.lock().unwrap().push_back(event.clone());
+                        queue.notify.notify_one();
+                        true
+                    }
+                    None => false,
+                });
+            }
+            EventPriority::Bulk => {
+                let _ = self.lanes.bulk.send(event); // Ignore if no subscribers
+            }
+        }
     }
 
     /// Subscribe to events
-    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
-        self.tx.subscribe()
+    pub fn subscribe(&self) -> EventReceiver {
+        let control = Arc::new(ControlQueue::default());
+        self.lanes
+            .control
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&control));
+        EventReceiver {
+            control,
+            bulk: self.lanes.bulk.subscribe(),
+        }
+    }
+
+    /// Wait until every subscriber has received all published events
+    ///
+    /// Returns `false` if events are still pending after `timeout`.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !self.is_drained() {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        true
+    }
+
+    fn is_drained(&self) -> bool {
+        self.lanes.bulk.is_empty()
+            && self
+                .lanes
+                .control
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(Weak::upgrade)
+                .all(|queue| queue.events.lock().unwrap().is_empty())
+    }
+}
+
+/// Subscriber view merging both lanes of an `EventStream`
+pub struct EventReceiver {
+    control: Arc<ControlQueue>,
+    bulk: broadcast::Receiver<Event>,
+}
+
+impl EventReceiver {
+    /// Receive the next event, control events first
+    ///
+    /// Fails with `RecvError::Lagged` when bulk events were dropped, and
+    /// with `RecvError::Closed` once every `EventStream` handle is gone and
+    /// all events were received.
+    pub async fn recv(&mut self) -> Result<Event, broadcast::error::RecvError> {
+        loop {
+            if let Some(event) = self.pop_control() {
+                return Ok(event);
+            }
+            tokio::select! {
+                biased;
+                _ = self.control.notify.notified() => {}
+                received = self.bulk.recv() => match received {
+                    Err(broadcast::error::RecvError::Closed) => {
+                        // Control events published just before closing
+                        return self
+                            .pop_control()
+                            .ok_or(broadcast::error::RecvError::Closed);
+                    }
+                    received => return received,
+                },
+            }
+        }
+    }
+
+    /// Receive an event if one is ready, control events first
+    pub fn try_recv(&mut self) -> Result<Event, broadcast::error::TryRecvError> {
+        match self.pop_control() {
+            Some(event) => Ok(event),
+            None => self.bulk.try_recv(),
+        }
+    }
+
+    fn pop_control(&self) -> Option<Event> {
+        self.control.events.lock().unwrap().pop_front()
     }
 }
 
@@ -65,4 +500,132 @@ mod tests {
             _ => panic!("Wrong event type"),
         }
     }
+
+    fn round_trip(event: &Event) -> Event {
+        serde_json::from_str(&serde_json::to_string(event).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let event = Event::Remote {
+            origin: "phone".into(),
+            event: Box::new(Event::ArtifactCorrupted {
+                id: "a".into(),
+                status: RepairStatus::Repaired,
+            }),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["v"], EVENT_SCHEMA_VERSION);
+        assert_eq!(json["type"], "remote");
+        assert_eq!(json["payload"]["event"]["type"], "artifact_corrupted");
+        assert!(matches!(
+            round_trip(&event),
+            Event::Remote { event, .. } if matches!(*event, Event::ArtifactCorrupted { .. })
+        ));
+
+        let json = serde_json::to_value(Event::SyncStarted).unwrap();
+        assert_eq!(json, serde_json::json!({"v": 2, "type": "sync_started"}));
+        assert!(matches!(
+            round_trip(&Event::SyncStarted),
+            Event::SyncStarted
+        ));
+    }
+
+    #[test]
+    fn test_reads_schema_version_1() {
+        let created: Event = serde_json::from_str(r#"{"ArtifactCreated":{"id":"a"}}"#).unwrap();
+        assert!(matches!(created, Event::ArtifactCreated { id } if id == "a"));
+        let started: Event = serde_json::from_str(r#""SyncStarted""#).unwrap();
+        assert!(matches!(started, Event::SyncStarted));
+        let remote: Event = serde_json::from_str(
+            r#"{"Remote":{"origin":"phone","event":{"EditIntentExpired":{"artifact_id":"a","device_id":"d"}}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            remote,
+            Event::Remote { event, .. } if matches!(*event, Event::EditIntentExpired { .. })
+        ));
+    }
+
+    #[test]
+    fn test_tolerates_events_from_newer_versions() {
+        // A variant added in version 3, and a known type whose payload changed
+        let json = r#"{"v":3,"type":"artifact_pinned","payload":{"id":"a","pinned":true}}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            &event,
+            Event::Unknown { event_type, payload }
+                if event_type == "artifact_pinned" && payload["pinned"] == true
+        ));
+        assert!(!event.is_forwardable());
+
+        // Unknown events re-encode unchanged apart from the version
+        let mut reencoded = serde_json::to_value(&event).unwrap();
+        reencoded["v"] = 3.into();
+        assert_eq!(reencoded, serde_json::from_str::<Value>(json).unwrap());
+
+        let extra = r#"{"v":3,"type":"artifact_created","payload":{"id":"a","origin":"x"}}"#;
+        assert!(matches!(
+            serde_json::from_str::<Event>(extra).unwrap(),
+            Event::ArtifactCreated { id } if id == "a"
+        ));
+        let changed = r#"{"v":3,"type":"sync_completed","payload":{"count":2}}"#;
+        assert!(matches!(
+            serde_json::from_str::<Event>(changed).unwrap(),
+            Event::Unknown { .. }
+        ));
+        assert!(serde_json::from_str::<Event>("42").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_control_events_overtake_and_survive_bulk_floods() {
+        let stream = EventStream::new();
+        let mut rx = stream.subscribe();
+        for i in 0..1000 {
+            stream.publish(Event::ArtifactCreated { id: i.to_string() });
+        }
+        stream.publish(Event::DeviceDisconnected {
+            device_id: "phone".into(),
+        });
+        stream.publish(Event::SyncCompleted {
+            artifacts_synced: 1000,
+        });
+
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::DeviceDisconnected { .. }
+        ));
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::SyncCompleted { .. }
+        ));
+        // The flood overflowed the bulk lane
+        assert!(matches!(
+            rx.recv().await,
+            Err(broadcast::error::RecvError::Lagged(_))
+        ));
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::ArtifactCreated { .. }
+        ));
+
+        // Control events published right before the stream closes still arrive
+        stream.publish(Event::SyncStarted);
+        drop(stream);
+        assert!(matches!(rx.recv().await.unwrap(), Event::SyncStarted));
+        while !matches!(rx.recv().await, Err(broadcast::error::RecvError::Closed)) {}
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_subscribers() {
+        let stream = EventStream::new();
+        let mut rx = stream.subscribe();
+        stream.publish(Event::SyncStarted);
+        assert!(!stream.drain(Duration::from_millis(20)).await);
+
+        let reader = tokio::spawn(async move { rx.recv().await.unwrap() });
+        assert!(stream.drain(Duration::from_secs(1)).await);
+        reader.await.unwrap();
+    }
 }
@@ -0,0 +1,364 @@
+//! Metrics for Nomade
+//!
+//! In-process registry of counters, gauges and histograms. Subsystems
+//! record into the process-wide `global()` registry; the core exports
+//! periodic `MetricsSnapshot`s over FFI, and the relay server can expose
+//! the same registry in Prometheus text format.
+//!
+//! This crate has no dependencies beyond serde so that every other crate,
+//! including `nomade_crypto`, can be instrumented.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+pub mod names;
+
+/// Default histogram buckets for durations in seconds
+pub const DEFAULT_BUCKETS: &[f64] = &[0.0001, 0.001, 0.01, 0.1, 0.5, 1.0, 5.0];
+
+/// Monotonically increasing counter
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    /// Increment by one
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Increment by `n`
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Current value
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that can go up and down
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    /// Set the value
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    /// Add `delta` (may be negative)
+    pub fn add(&self, delta: i64) {
+        self.0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    /// Current value
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Distribution of observed values over fixed buckets
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    /// Per-bucket counts; the last slot counts values above every bound
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    /// `f64` bits of the running sum
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    /// Create histogram with the given upper bounds
+    pub fn new(bounds: &[f64]) -> Self {
+        let mut bounds = bounds.to_vec();
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        Self {
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            bounds,
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    /// Record a value
+    pub fn observe(&self, value: f64) {
+        let index = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    /// Number of observations
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Sum of observed values
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Ordering::Relaxed))
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        // Prometheus buckets are cumulative
+        let mut cumulative = 0;
+        let buckets = self
+            .bounds
+            .iter()
+            .zip(&self.buckets)
+            .map(|(bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (*bound, cumulative)
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            count: self.count(),
+            sum: self.sum(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    help: String,
+    metric: Metric,
+}
+
+/// Point-in-time histogram state
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistogramSnapshot {
+    /// Cumulative counts per upper bound
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+/// Point-in-time value of a metric
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MetricValue {
+    Counter { value: u64 },
+    Gauge { value: i64 },
+    Histogram(HistogramSnapshot),
+}
+
+/// One metric in a snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricSample {
+    pub name: String,
+    pub help: String,
+    pub value: MetricValue,
+}
+
+/// All metrics at a point in time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub metrics: Vec<MetricSample>,
+}
+
+impl MetricsSnapshot {
+    /// Look up a sample by name
+    pub fn get(&self, name: &str) -> Option<&MetricValue> {
+        self.metrics
+            .iter()
+            .find(|sample| sample.name == name)
+            .map(|sample| &sample.value)
+    }
+}
+
+/// Registry of named metrics
+///
+/// Metrics are created on first use; later lookups of the same name return
+/// the existing metric. Looking a name up as a different kind panics, as
+/// that is a programming error.
+#[derive(Debug, Default)]
+pub struct Registry {
+    metrics: RwLock<BTreeMap<String, Entry>>,
+}
+
+impl Registry {
+    /// Create empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get_or_insert(&self, name: &str, help: &str, create: impl FnOnce() -> Metric) -> Metric {
+        if let Some(entry) = self.metrics.read().unwrap().get(name) {
+            return entry.metric.clone();
+        }
+        self.metrics
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Entry {
+                help: help.to_string(),
+                metric: create(),
+            })
+            .metric
+            .clone()
+    }
+
+    /// Get or create a counter
+    pub fn counter(&self, name: &str, help: &str) -> Arc<Counter> {
+        match self.get_or_insert(name, help, || Metric::Counter(Arc::default())) {
+            Metric::Counter(counter) => counter,
+            _ => panic!("metric {} is not a counter", name),
+        }
+    }
+
+    /// Get or create a gauge
+    pub fn gauge(&self, name: &str, help: &str) -> Arc<Gauge> {
+        match self.get_or_insert(name, help, || Metric::Gauge(Arc::default())) {
+            Metric::Gauge(gauge) => gauge,
+            _ => panic!("metric {} is not a gauge", name),
+        }
+    }
+
+    /// Get or create a histogram with the given bucket bounds
+    pub fn histogram(&self, name: &str, help: &str, bounds: &[f64]) -> Arc<Histogram> {
+        let create = || Metric::Histogram(Arc::new(Histogram::new(bounds)));
+        match self.get_or_insert(name, help, create) {
+            Metric::Histogram(histogram) => histogram,
+            _ => panic!("metric {} is not a histogram", name),
+        }
+    }
+
+    /// Capture the current value of every metric
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let metrics = self
+            .metrics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| MetricSample {
+                name: name.clone(),
+                help: entry.help.clone(),
+                value: match &entry.metric {
+                    Metric::Counter(c) => MetricValue::Counter { value: c.get() },
+                    Metric::Gauge(g) => MetricValue::Gauge { value: g.get() },
+                    Metric::Histogram(h) => MetricValue::Histogram(h.snapshot()),
+                },
+            })
+            .collect();
+        MetricsSnapshot {
+            timestamp_ms,
+            metrics,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::new();
+        for sample in self.snapshot().metrics {
+            let name = &sample.name;
+            let kind = match sample.value {
+                MetricValue::Counter { .. } => "counter",
+                MetricValue::Gauge { .. } => "gauge",
+                MetricValue::Histogram(_) => "histogram",
+            };
+            let _ = writeln!(out, "# HELP {} {}", name, sample.help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            match sample.value {
+                MetricValue::Counter { value } => {
+                    let _ = writeln!(out, "{} {}", name, value);
+                }
+                MetricValue::Gauge { value } => {
+                    let _ = writeln!(out, "{} {}", name, value);
+                }
+                MetricValue::Histogram(h) => {
+                    for (bound, count) in &h.buckets {
+                        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+                    }
+                    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, h.count);
+                    let _ = writeln!(out, "{}_sum {}", name, h.sum);
+                    let _ = writeln!(out, "{}_count {}", name, h.count);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Process-wide registry
+pub fn global() -> &'static Registry {
+    static GLOBAL: OnceLock<Registry> = OnceLock::new();
+    GLOBAL.get_or_init(Registry::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_reuses_metrics() {
+        let registry = Registry::new();
+        registry.counter("requests", "Requests").add(2);
+        registry.counter("requests", "Requests").inc();
+        registry.gauge("size", "Size").set(-4);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(
+            snapshot.get("requests"),
+            Some(&MetricValue::Counter { value: 3 })
+        );
+        assert_eq!(
+            snapshot.get("size"),
+            Some(&MetricValue::Gauge { value: -4 })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "not a gauge")]
+    fn test_kind_mismatch_panics() {
+        let registry = Registry::new();
+        registry.counter("requests", "Requests");
+        registry.gauge("requests", "Requests");
+    }
+
+    #[test]
+    fn test_histogram_buckets_and_prometheus() {
+        let registry = Registry::new();
+        let latency = registry.histogram("latency_seconds", "Latency", &[0.1, 1.0]);
+        for value in [0.05, 0.5, 0.7, 3.0] {
+            latency.observe(value);
+        }
+        assert_eq!(latency.count(), 4);
+        assert!((latency.sum() - 4.25).abs() < 1e-9);
+
+        let text = registry.render_prometheus();
+        assert!(text.contains("# TYPE latency_seconds histogram"));
+        assert!(text.contains("latency_seconds_bucket{le=\"0.1\"} 1"));
+        assert!(text.contains("latency_seconds_bucket{le=\"1\"} 3"));
+        assert!(text.contains("latency_seconds_bucket{le=\"+Inf\"} 4"));
+        assert!(text.contains("latency_seconds_count 4"));
+    }
+}
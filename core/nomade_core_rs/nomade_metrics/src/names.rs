@@ -0,0 +1,34 @@
+//! Names of the metrics recorded by Nomade subsystems
+
+/// Payload bytes transferred to peers during sync (counter)
+pub const BYTES_SYNCED: &str = "nomade_bytes_synced_total";
+/// Chunks transferred to peers (counter)
+pub const CHUNKS_TRANSFERRED: &str = "nomade_chunks_transferred_total";
+/// Plaintext bytes encrypted or decrypted (counter)
+pub const CRYPTO_BYTES: &str = "nomade_crypto_bytes_total";
+/// Duration of encrypt/decrypt calls in seconds (histogram)
+pub const CRYPTO_SECONDS: &str = "nomade_crypto_seconds";
+/// Incoming connection attempts (counter)
+pub const CONNECTION_ATTEMPTS: &str = "nomade_connection_attempts_total";
+/// Rejected or failed connection attempts (counter)
+pub const CONNECTION_FAILURES: &str = "nomade_connection_failures_total";
+/// Connection attempts rejected by rate limits or admission (counter)
+pub const CONNECTIONS_REJECTED: &str = "nomade_connections_rejected_total";
+/// Currently connected peers (gauge)
+pub const CONNECTED_PEERS: &str = "nomade_connected_peers";
+/// Size of the artifact store on disk in bytes (gauge)
+pub const STORAGE_BYTES: &str = "nomade_storage_bytes";
+/// Operations queued for unreachable peers (gauge)
+pub const OUTBOX_DEPTH: &str = "nomade_outbox_depth";
+/// Content bytes written to compressed storage (counter)
+pub const COMPRESSION_INPUT_BYTES: &str = "nomade_compression_input_bytes_total";
+/// Bytes stored after compression (counter)
+pub const COMPRESSION_OUTPUT_BYTES: &str = "nomade_compression_output_bytes_total";
+/// Stored size as a percentage of content size (gauge)
+pub const COMPRESSION_RATIO: &str = "nomade_compression_ratio_percent";
+/// Content bytes not stored thanks to shared chunks (gauge)
+pub const DEDUP_SAVED_BYTES: &str = "nomade_dedup_saved_bytes";
+/// Network operations retried after a transient failure (counter)
+pub const NETWORK_RETRIES: &str = "nomade_network_retries_total";
+/// Network operations that failed after every retry (counter)
+pub const NETWORK_RETRIES_EXHAUSTED: &str = "nomade_network_retries_exhausted_total";
@@ -0,0 +1,522 @@
+//! Typed channels over connection streams
+//!
+//! Subsystems never share a stream: each logical channel runs on its own
+//! bidirectional stream, so a stalled chunk transfer cannot block control
+//! traffic (QUIC flow control is per stream). The opener writes the
+//! channel's tag as the first byte; frames follow. Streams are scheduled
+//! by channel priority, with chunk transfer lowest. Payloads on
+//! `SyncMeta` and `LiveEvents` channels can be sealed end to end (see
+//! `seal`).
+//!
+//! ```text
+//! +-------------+---------+---------+-----
+//! | channel tag | frame 1 | frame 2 | ...
+//! +-------------+---------+---------+-----
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+use crate::frame::{read_frame, write_frame, write_frame_compressed, Frame, MessageType};
+use crate::replay::ReplayGuard;
+use crate::seal::FrameSeal;
+use crate::transport::{Connection, RecvStream, SendStream};
+use crate::{ProtocolError, Result};
+
+/// Logical channel carried on a dedicated stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ChannelId {
+    /// Handshakes, keepalives, revocations, attestations and rosters
+    Control = 0,
+    /// Manifests and artifact metadata
+    SyncMeta = 1,
+    /// Content chunks
+    ChunkTransfer = 2,
+    /// Events forwarded as they happen
+    LiveEvents = 3,
+}
+
+impl ChannelId {
+    /// Wire tag for this channel
+    pub fn tag(self) -> u8 {
+        self as u8
+    }
+
+    /// Send priority of the channel's streams; higher goes first
+    pub fn priority(self) -> i32 {
+        match self {
+            Self::Control => 3,
+            Self::LiveEvents => 2,
+            Self::SyncMeta => 1,
+            Self::ChunkTransfer => 0,
+        }
+    }
+
+    /// Whether payloads on the channel are sealed when the link seals
+    /// frames
+    pub fn sealed(self) -> bool {
+        matches!(self, Self::SyncMeta | Self::LiveEvents)
+    }
+
+    /// Whether frames of this type may travel on the channel
+    pub fn carries(self, message_type: MessageType) -> bool {
+        match self {
+            Self::Control => matches!(
+                message_type,
+                MessageType::Handshake
+                    | MessageType::Ping
+                    | MessageType::Pong
+                    | MessageType::Revocation
+                    | MessageType::Attestation
+                    | MessageType::Wipe
+                    | MessageType::Roster
+            ),
+            Self::SyncMeta => message_type == MessageType::SyncRequest,
+            // Chunk requests and errors are sync messages
+            Self::ChunkTransfer => matches!(
+                message_type,
+                MessageType::SyncRequest | MessageType::ChunkData
+            ),
+            Self::LiveEvents => message_type == MessageType::Event,
+        }
+    }
+}
+
+impl TryFrom<u8> for ChannelId {
+    type Error = ProtocolError;
+
+    fn try_from(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Self::Control),
+            1 => Ok(Self::SyncMeta),
+            2 => Ok(Self::ChunkTransfer),
+            3 => Ok(Self::LiveEvents),
+            other => Err(ProtocolError::UnknownChannel(other)),
+        }
+    }
+}
+
+/// One logical channel: a prioritized stream carrying typed frames
+pub struct Channel {
+    id: ChannelId,
+    send: SendStream,
+    recv: RecvStream,
+    /// zstd level for outgoing payloads; `None` sends them uncompressed
+    compression: Option<i32>,
+    /// Session counters stamping and checking frames
+    replay: Option<ReplayGuard>,
+    /// Seal of the peer's payloads, on sealed channels
+    seal: Option<Arc<dyn FrameSeal>>,
+}
+
+impl Channel {
+    /// Open a channel to the peer
+    pub async fn open(connection: &dyn Connection, id: ChannelId) -> Result<Self> {
+        let (mut send, recv) = connection.open_bi().await?;
+        send.set_priority(id.priority());
+        send.write_all(&[id.tag()]).await?;
+        Ok(Self {
+            id,
+            send,
+            recv,
+            compression: None,
+            replay: None,
+            seal: None,
+        })
+    }
+
+    /// Wait for the peer to open a channel; `None` once the connection closed
+    pub async fn accept(connection: &dyn Connection) -> Result<Option<Self>> {
+        let Some((mut send, mut recv)) = connection.accept_bi().await? else {
+            return Ok(None);
+        };
+        let id = ChannelId::try_from(recv.read_u8().await?)?;
+        send.set_priority(id.priority());
+        Ok(Some(Self {
+            id,
+            send,
+            recv,
+            compression: None,
+            replay: None,
+            seal: None,
+        }))
+    }
+
+    /// Logical channel this stream carries
+    pub fn id(&self) -> ChannelId {
+        self.id
+    }
+
+    /// Compress payloads of frames sent from now on at zstd `level`
+    ///
+    /// Only enable when the peer asked for it: older peers cannot decode
+    /// compressed frames.
+    pub fn set_compression(&mut self, level: Option<i32>) {
+        self.compression = level;
+    }
+
+    /// Sequence frames with the session's counters, rejecting replays
+    ///
+    /// Only enable when both peers negotiated
+    /// `FeatureFlags::SEQUENCED_FRAMES`.
+    pub fn set_replay_guard(&mut self, guard: Option<ReplayGuard>) {
+        self.replay = guard;
+    }
+
+    /// Seal payloads sent and open payloads received with `seal`
+    ///
+    /// Only enable when both peers negotiated
+    /// `FeatureFlags::SIGNED_FRAMES`. Channels that are not
+    /// `ChannelId::sealed` keep their payloads as they are.
+    pub fn set_seal(&mut self, seal: Option<Arc<dyn FrameSeal>>) {
+        self.seal = seal.filter(|_| self.id.sealed());
+    }
+
+    /// Send a frame
+    pub async fn send(&mut self, frame: &Frame) -> Result<()> {
+        self.check(frame.message_type)?;
+        let sealed;
+        let frame = match &self.seal {
+            Some(seal) => {
+                sealed = Frame {
+                    payload: seal.seal(&frame.payload)?,
+                    ..frame.clone()
+                };
+                &sealed
+            }
+            None => frame,
+        };
+        let sequenced;
+        let frame = match &self.replay {
+            Some(guard) => {
+                sequenced = frame.clone().with_sequence(guard.next_sequence()?);
+                &sequenced
+            }
+            None => frame,
+        };
+        match self.compression {
+            Some(level) => write_frame_compressed(&mut self.send, frame, level).await,
+            None => write_frame(&mut self.send, frame).await,
+        }
+    }
+
+    /// Receive the next frame; `None` once the peer finished sending
+    pub async fn recv(&mut self) -> Result<Option<Frame>> {
+        let mut frame = read_frame(&mut self.recv).await?;
+        if let Some(frame) = &mut frame {
+            self.check(frame.message_type)?;
+            if let Some(guard) = &self.replay {
+                guard.accept(frame.sequence)?;
+            }
+            if let Some(seal) = &self.seal {
+                frame.payload = seal.open(&frame.payload)?;
+            }
+        }
+        Ok(frame)
+    }
+
+    /// Signal that no more frames will be sent
+    pub async fn finish(&mut self) -> Result<()> {
+        Ok(self.send.shutdown().await?)
+    }
+
+    fn check(&self, message_type: MessageType) -> Result<()> {
+        if self.id.carries(message_type) {
+            Ok(())
+        } else {
+            Err(ProtocolError::UnexpectedMessage(message_type))
+        }
+    }
+}
+
+/// Hands channels opened by the peer to the subsystem that serves them
+#[derive(Default)]
+pub struct ChannelRouter {
+    routes: Mutex<HashMap<ChannelId, mpsc::UnboundedSender<Channel>>>,
+    /// Session counters installed on every channel it routes
+    replay: Option<ReplayGuard>,
+    /// Seal installed on every channel it routes
+    seal: Option<Arc<dyn FrameSeal>>,
+}
+
+impl ChannelRouter {
+    /// Create a router with no routes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check and sequence frames of routed channels with `guard`
+    ///
+    /// See `Channel::set_replay_guard`.
+    pub fn with_replay_guard(mut self, guard: Option<ReplayGuard>) -> Self {
+        self.replay = guard;
+        self
+    }
+
+    /// Open the payloads of routed channels with `seal`
+    ///
+    /// See `Channel::set_seal`.
+    pub fn with_seal(mut self, seal: Option<Arc<dyn FrameSeal>>) -> Self {
+        self.seal = seal;
+        self
+    }
+
+    /// Receive incoming channels with any of `ids`, replacing earlier routes
+    pub fn route(&self, ids: &[ChannelId]) -> mpsc::UnboundedReceiver<Channel> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut routes = self.routes.lock().unwrap();
+        for id in ids {
+            routes.insert(*id, tx.clone());
+        }
+        rx
+    }
+
+    /// Accept and route channels until the connection closes
+    ///
+    /// Channels without a live route are dropped, which resets them.
+    pub async fn run(&self, connection: &dyn Connection) -> Result<()> {
+        while let Some(mut channel) = Channel::accept(connection).await? {
+            channel.set_replay_guard(self.replay.clone());
+            channel.set_seal(self.seal.clone());
+            let id = channel.id();
+            let route = self.routes.lock().unwrap().get(&id).cloned();
+            if route.is_none_or(|route| route.send(channel).is_err()) {
+                tracing::debug!("No route for incoming {:?} channel", id);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryNetwork, Transport};
+
+    #[tokio::test]
+    async fn test_channels_are_independent_streams() {
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+
+        let mut chunks = Channel::open(dialed.as_ref(), ChannelId::ChunkTransfer)
+            .await
+            .unwrap();
+        let mut control = Channel::open(dialed.as_ref(), ChannelId::Control)
+            .await
+            .unwrap();
+        control
+            .send(&Frame::new(MessageType::Ping, vec![1]))
+            .await
+            .unwrap();
+
+        let mut incoming_chunks = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        let mut incoming_control = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        assert_eq!(incoming_chunks.id(), ChannelId::ChunkTransfer);
+        assert_eq!(incoming_control.id(), ChannelId::Control);
+
+        // Control traffic arrives while the chunk channel is idle
+        let ping = incoming_control.recv().await.unwrap().unwrap();
+        assert_eq!(ping.message_type, MessageType::Ping);
+        incoming_control
+            .send(&Frame::new(MessageType::Pong, vec![1]))
+            .await
+            .unwrap();
+        assert_eq!(
+            control.recv().await.unwrap().unwrap().message_type,
+            MessageType::Pong
+        );
+
+        chunks
+            .send(&Frame::new(MessageType::ChunkData, vec![0; 64]))
+            .await
+            .unwrap();
+        chunks.finish().await.unwrap();
+        assert_eq!(
+            incoming_chunks.recv().await.unwrap().unwrap().payload.len(),
+            64
+        );
+        assert!(incoming_chunks.recv().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_frames_for_other_channels() {
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+
+        let mut events = Channel::open(dialed.as_ref(), ChannelId::LiveEvents)
+            .await
+            .unwrap();
+        assert!(matches!(
+            events
+                .send(&Frame::new(MessageType::ChunkData, Vec::new()))
+                .await,
+            Err(ProtocolError::UnexpectedMessage(MessageType::ChunkData))
+        ));
+
+        let (mut send, _recv) = dialed.open_bi().await.unwrap();
+        send.write_all(&[9]).await.unwrap();
+        Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        assert!(matches!(
+            Channel::accept(accepted.as_ref()).await,
+            Err(ProtocolError::UnknownChannel(9))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replayed_frames_are_rejected() {
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+        let (sender, receiver) = (ReplayGuard::new(), ReplayGuard::new());
+
+        let mut control = Channel::open(dialed.as_ref(), ChannelId::Control)
+            .await
+            .unwrap();
+        control.set_replay_guard(Some(sender.clone()));
+        let ping = Frame::new(MessageType::Ping, vec![1]);
+        control.send(&ping).await.unwrap();
+        let mut incoming = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        incoming.set_replay_guard(Some(receiver.clone()));
+        assert_eq!(incoming.recv().await.unwrap().unwrap().sequence, Some(1));
+
+        // The same frame captured and sent again on a new stream
+        let (mut send, _recv) = dialed.open_bi().await.unwrap();
+        send.write_all(&[ChannelId::Control.tag()]).await.unwrap();
+        send.write_all(&ping.with_sequence(1).encode().unwrap())
+            .await
+            .unwrap();
+        let mut replayed = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        replayed.set_replay_guard(Some(receiver));
+        assert!(matches!(
+            replayed.recv().await,
+            Err(ProtocolError::Replayed(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sealed_channels_open_payloads() {
+        /// Flips every bit, refusing payloads that do not start flipped
+        struct Flip;
+
+        impl FrameSeal for Flip {
+            fn seal(&self, payload: &[u8]) -> Result<Vec<u8>> {
+                Ok([&[0xff], payload].concat().iter().map(|b| !b).collect())
+            }
+
+            fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+                match sealed.split_first() {
+                    Some((0, rest)) => Ok(rest.iter().map(|b| !b).collect()),
+                    _ => Err(ProtocolError::BadSeal("not flipped".into())),
+                }
+            }
+        }
+
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+        let seal: Arc<dyn FrameSeal> = Arc::new(Flip);
+
+        let mut events = Channel::open(dialed.as_ref(), ChannelId::LiveEvents)
+            .await
+            .unwrap();
+        events.set_seal(Some(seal.clone()));
+        events
+            .send(&Frame::new(MessageType::Event, vec![1, 2]))
+            .await
+            .unwrap();
+        let mut incoming = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        incoming.set_seal(Some(seal.clone()));
+        assert_eq!(incoming.recv().await.unwrap().unwrap().payload, [1, 2]);
+
+        // An unsealed payload is refused on a sealed channel
+        let mut unsealed = Channel::open(dialed.as_ref(), ChannelId::LiveEvents)
+            .await
+            .unwrap();
+        unsealed
+            .send(&Frame::new(MessageType::Event, vec![1, 2]))
+            .await
+            .unwrap();
+        let mut incoming = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        incoming.set_seal(Some(seal.clone()));
+        assert!(matches!(
+            incoming.recv().await,
+            Err(ProtocolError::BadSeal(_))
+        ));
+
+        // Chunks pass as they are
+        let mut chunks = Channel::open(dialed.as_ref(), ChannelId::ChunkTransfer)
+            .await
+            .unwrap();
+        chunks.set_seal(Some(seal.clone()));
+        chunks
+            .send(&Frame::new(MessageType::ChunkData, vec![3]))
+            .await
+            .unwrap();
+        let mut incoming = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        assert_eq!(incoming.recv().await.unwrap().unwrap().payload, [3]);
+    }
+
+    #[tokio::test]
+    async fn test_router_routes_by_channel() {
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+
+        let router = std::sync::Arc::new(ChannelRouter::new());
+        let mut sync = router.route(&[ChannelId::SyncMeta, ChannelId::ChunkTransfer]);
+        let mut events = router.route(&[ChannelId::LiveEvents]);
+        let running = tokio::spawn({
+            let router = router.clone();
+            async move { router.run(accepted.as_ref()).await }
+        });
+
+        for id in [
+            ChannelId::Control,
+            ChannelId::ChunkTransfer,
+            ChannelId::LiveEvents,
+            ChannelId::SyncMeta,
+        ] {
+            Channel::open(dialed.as_ref(), id).await.unwrap();
+        }
+        assert_eq!(sync.recv().await.unwrap().id(), ChannelId::ChunkTransfer);
+        assert_eq!(sync.recv().await.unwrap().id(), ChannelId::SyncMeta);
+        assert_eq!(events.recv().await.unwrap().id(), ChannelId::LiveEvents);
+
+        dialed.close();
+        running.await.unwrap().unwrap();
+        assert!(sync.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_chunk_transfer_has_lowest_priority() {
+        let channels = [
+            ChannelId::Control,
+            ChannelId::SyncMeta,
+            ChannelId::ChunkTransfer,
+            ChannelId::LiveEvents,
+        ];
+        for id in channels {
+            assert_eq!(ChannelId::try_from(id.tag()).unwrap(), id);
+            if id != ChannelId::ChunkTransfer {
+                assert!(id.priority() > ChannelId::ChunkTransfer.priority());
+            }
+        }
+        assert!(ChannelId::Control.priority() > ChannelId::SyncMeta.priority());
+    }
+}
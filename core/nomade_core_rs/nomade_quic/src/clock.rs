@@ -0,0 +1,121 @@
+//! Clock-offset estimation between peers
+//!
+//! Freshness checks on signed messages (wake tokens, wipe commands) compare
+//! the sender's timestamp with the local clock, which breaks when the two
+//! clocks disagree. Keepalive pings therefore double as an NTP-like
+//! exchange: the ping carries its send time `t1`, the pong echoes it with
+//! the peer's receive and send times `t2` and `t3`, and the pinging side
+//! notes the arrival time `t4`. Then
+//!
+//! - offset = ((t2 - t1) + (t3 - t4)) / 2, the peer's clock minus ours
+//! - delay = (t4 - t1) - (t3 - t2), the network round trip
+//!
+//! As in NTP, the sample with the lowest delay among the recent ones is
+//! the most trustworthy, since queuing delays skew the offset.
+
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Samples kept to pick the estimate from
+pub const CLOCK_SAMPLES: usize = 8;
+
+/// One clock exchange with a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockSample {
+    /// Peer's clock minus ours, in milliseconds
+    pub offset_ms: i64,
+    /// Network round trip, in milliseconds
+    pub delay_ms: u64,
+}
+
+impl ClockSample {
+    /// Sample from the four timestamps of an exchange, in milliseconds
+    pub fn from_exchange(t1: u64, t2: u64, t3: u64, t4: u64) -> Self {
+        let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+        Self {
+            offset_ms: ((t2 - t1) + (t3 - t4)) / 2,
+            delay_ms: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+        }
+    }
+
+    /// Local time in seconds translated to the peer's clock
+    pub fn peer_time(&self, local_secs: u64) -> u64 {
+        let local_ms = local_secs as i64 * 1000;
+        (local_ms.saturating_add(self.offset_ms).max(0) / 1000) as u64
+    }
+}
+
+/// Recent samples of one peer
+#[derive(Debug, Clone, Default)]
+pub struct ClockEstimator {
+    samples: VecDeque<ClockSample>,
+}
+
+impl ClockEstimator {
+    /// Create an estimator without samples
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample, forgetting the oldest beyond `CLOCK_SAMPLES`
+    pub fn record(&mut self, sample: ClockSample) {
+        if self.samples.len() == CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Lowest-delay recent sample, if any
+    pub fn estimate(&self) -> Option<ClockSample> {
+        self.samples.iter().min_by_key(|s| s.delay_ms).copied()
+    }
+}
+
+/// Milliseconds since the Unix epoch on the local clock
+pub fn wall_clock_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_from_exchange() {
+        // Peer runs 5s ahead; 40ms each way, 10ms to answer
+        let sample = ClockSample::from_exchange(1_000, 6_040, 6_050, 1_090);
+        assert_eq!(sample.offset_ms, 5_000);
+        assert_eq!(sample.delay_ms, 80);
+        assert_eq!(sample.peer_time(100), 105);
+
+        let behind = ClockSample {
+            offset_ms: -120_000,
+            delay_ms: 0,
+        };
+        assert_eq!(behind.peer_time(1_000), 880);
+        assert_eq!(behind.peer_time(0), 0);
+    }
+
+    #[test]
+    fn test_estimate_prefers_lowest_delay() {
+        let mut clock = ClockEstimator::new();
+        assert_eq!(clock.estimate(), None);
+        // A queued exchange: asymmetric delay skews its offset
+        clock.record(ClockSample::from_exchange(0, 900, 900, 1_000));
+        clock.record(ClockSample::from_exchange(2_000, 2_010, 2_010, 2_020));
+        assert_eq!(clock.estimate().unwrap().offset_ms, 0);
+
+        for i in 0..CLOCK_SAMPLES as u64 {
+            clock.record(ClockSample {
+                offset_ms: 1,
+                delay_ms: 100 + i,
+            });
+        }
+        assert_eq!(clock.estimate().unwrap().delay_ms, 100);
+    }
+}
@@ -0,0 +1,790 @@
+//! Connection manager for active peer connections
+//!
+//! Tracks one entry per connected peer, admits new connections only after
+//! checking the `TrustStore`, and propagates device revocations: a revoked
+//! device's connection is terminated immediately and the signed revocation
+//! record is forwarded to every other connected peer on its priority queue,
+//! ahead of any queued sync traffic. Identity attestations spread the same
+//! way, behind sync traffic, and device group rosters go to the members
+//! among the connected peers. Each peer's estimated clock offset is kept in
+//! the trust store, for checking the freshness of its signed messages.
+//! While the device is offline, frames are refused instead of queued.
+//!
+//! There is at most one connection per peer. When two devices dial each
+//! other at the same time, both keep the connection dialed by the device
+//! with the lower ID, so they agree on which one to drop without talking.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+use nomade_crypto::{
+    Attestation, DeviceId, Endpoint, RevocationRecord, RosterEntry, TrustStore, WipeCommand,
+};
+use nomade_events::{Event, EventStream};
+use nomade_metrics::names;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::clock::{wall_clock_ms, ClockSample};
+use crate::frame::{Frame, MessageType};
+use crate::keepalive::ConnectionStats;
+use crate::limits::ConnectionGuard;
+use crate::negotiation::Negotiated;
+use crate::network::{NetworkMonitor, NetworkState};
+use crate::{ProtocolError, Result};
+
+/// Capacity of each per-connection outbound queue
+const OUTBOUND_QUEUE_SIZE: usize = 64;
+
+/// Trust store `peer_data` key holding a peer's `ClockSample`
+pub const CLOCK_OFFSET_KEY: &str = "clock_offset";
+
+/// Stored offsets are only rewritten once the estimate moves this much
+const CLOCK_OFFSET_TOLERANCE_MS: i64 = 1_000;
+
+/// Outbound side of a connection, drained by the connection task
+pub struct ConnectionQueues {
+    /// Priority frames (revocations, control); drain before `bulk`
+    pub priority: mpsc::Receiver<Frame>,
+    /// Regular sync traffic
+    pub bulk: mpsc::Receiver<Frame>,
+    /// Cancelled when the connection must be closed
+    pub closed: CancellationToken,
+}
+
+impl ConnectionQueues {
+    /// Receive the next outbound frame, priority frames first
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn next_frame(&mut self) -> Option<Frame> {
+        tokio::select! {
+            biased;
+            _ = self.closed.cancelled() => None,
+            Some(frame) = self.priority.recv() => Some(frame),
+            Some(frame) = self.bulk.recv() => Some(frame),
+            else => None,
+        }
+    }
+}
+
+/// Which side dialed a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// This device dialed the peer
+    Outbound,
+    /// The peer dialed this device
+    Inbound,
+}
+
+struct PeerEntry {
+    direction: Direction,
+    priority: mpsc::Sender<Frame>,
+    bulk: mpsc::Sender<Frame>,
+    closed: CancellationToken,
+    /// Outcome of the hello exchange, if the connection had one
+    negotiated: Option<Negotiated>,
+    /// Latest keepalive statistics
+    stats: Option<ConnectionStats>,
+}
+
+/// Registry of active peer connections
+#[derive(Clone)]
+pub struct ConnectionManager {
+    trust: Arc<RwLock<TrustStore>>,
+    events: EventStream,
+    peers: Arc<Mutex<HashMap<DeviceId, PeerEntry>>>,
+    /// Where each device can be dialed, most preferred first
+    endpoints: Arc<Mutex<HashMap<DeviceId, Vec<Endpoint>>>>,
+    /// Connection cap and rejection reporting
+    guard: Option<Arc<ConnectionGuard>>,
+    network: Option<Arc<NetworkMonitor>>,
+    /// This device, for breaking ties between simultaneous dials
+    local: Option<DeviceId>,
+}
+
+impl ConnectionManager {
+    /// Create new connection manager
+    pub fn new(trust: Arc<RwLock<TrustStore>>, events: EventStream) -> Self {
+        Self {
+            trust,
+            events,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            endpoints: Arc::new(Mutex::new(HashMap::new())),
+            guard: None,
+            network: None,
+            local: None,
+        }
+    }
+
+    /// Enforce the guard's connection cap on admission
+    pub fn with_guard(mut self, guard: Arc<ConnectionGuard>) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    /// Refuse to queue frames while `network` reports the device offline
+    pub fn with_network(mut self, network: Arc<NetworkMonitor>) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Break ties between simultaneous dials using this device's ID
+    ///
+    /// Without it, the newest connection to a peer always wins.
+    pub fn with_local_device(mut self, device_id: DeviceId) -> Self {
+        self.local = Some(device_id);
+        self
+    }
+
+    /// React to a network change: going offline closes every connection
+    ///
+    /// Returns how many peers were disconnected.
+    pub fn apply_network(&self, state: &NetworkState) -> usize {
+        if state.is_online() {
+            return 0;
+        }
+        let peers = self.connected_peers();
+        for peer in &peers {
+            self.disconnect(peer);
+        }
+        peers.len()
+    }
+
+    /// Remember where a device can be reached, e.g. from its pairing offer
+    pub fn set_endpoints(&self, device_id: DeviceId, mut endpoints: Vec<Endpoint>) {
+        let mut seen = HashSet::new();
+        endpoints.retain(|endpoint| seen.insert(endpoint.clone()));
+        Endpoint::sort_by_preference(&mut endpoints);
+        self.endpoints.lock().unwrap().insert(device_id, endpoints);
+    }
+
+    /// Known endpoints of a device, most preferred first
+    pub fn endpoints(&self, device_id: &DeviceId) -> Vec<Endpoint> {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Admit an authenticated peer after its handshake
+    ///
+    /// Revoked and unknown devices are rejected, as are new devices once
+    /// the guard's connection cap is reached. An existing connection to the
+    /// same device is replaced if it was dialed in the same direction (a
+    /// reconnect). Otherwise both devices dialed at once, and the one
+    /// dialed by the lower device ID is kept: the loser is closed, or
+    /// refused with `ProtocolError::DuplicateConnection`.
+    pub fn admit(&self, device_id: DeviceId, direction: Direction) -> Result<ConnectionQueues> {
+        self.admit_entry(device_id, direction, None)
+    }
+
+    /// Admit a peer like `admit`, keeping what its hello exchange negotiated
+    pub fn admit_negotiated(
+        &self,
+        device_id: DeviceId,
+        direction: Direction,
+        negotiated: Negotiated,
+    ) -> Result<ConnectionQueues> {
+        self.admit_entry(device_id, direction, Some(negotiated))
+    }
+
+    fn admit_entry(
+        &self,
+        device_id: DeviceId,
+        direction: Direction,
+        negotiated: Option<Negotiated>,
+    ) -> Result<ConnectionQueues> {
+        let metrics = nomade_metrics::global();
+        metrics
+            .counter(names::CONNECTION_ATTEMPTS, "Incoming connection attempts")
+            .inc();
+        if let Err(e) = self.trust.read().unwrap().check_handshake(&device_id) {
+            metrics
+                .counter(
+                    names::CONNECTION_FAILURES,
+                    "Rejected or failed connection attempts",
+                )
+                .inc();
+            return Err(ProtocolError::PeerRejected(e.to_string()));
+        }
+        let (priority_tx, priority) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let (bulk_tx, bulk) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let closed = CancellationToken::new();
+
+        let previous = {
+            let mut peers = self.peers.lock().unwrap();
+            match peers.get(&device_id) {
+                Some(existing) if !self.supersedes(&device_id, direction, existing.direction) => {
+                    tracing::debug!(
+                        "Keeping {:?} connection to {}, dropping {:?} duplicate",
+                        existing.direction,
+                        device_id,
+                        direction
+                    );
+                    return Err(ProtocolError::DuplicateConnection(device_id.to_string()));
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(guard) = &self.guard {
+                        guard.check_capacity(&device_id, peers.len())?;
+                    }
+                }
+            }
+            let previous = peers.insert(
+                device_id.clone(),
+                PeerEntry {
+                    direction,
+                    priority: priority_tx,
+                    bulk: bulk_tx,
+                    closed: closed.clone(),
+                    negotiated,
+                    stats: None,
+                },
+            );
+            record_connected(peers.len());
+            previous
+        };
+        if let Some(previous) = previous {
+            previous.closed.cancel();
+        }
+
+        self.events.publish(Event::DeviceConnected {
+            device_id: device_id.to_string(),
+        });
+        Ok(ConnectionQueues {
+            priority,
+            bulk,
+            closed,
+        })
+    }
+
+    /// Whether a new connection to `peer` replaces the existing one
+    fn supersedes(&self, peer: &DeviceId, new: Direction, existing: Direction) -> bool {
+        let Some(local) = &self.local else {
+            return true;
+        };
+        if new == existing {
+            return true;
+        }
+        // Keep the connection dialed by the lower device ID
+        let local_dialer_wins = local.0 < peer.0;
+        (new == Direction::Outbound) == local_dialer_wins
+    }
+
+    /// Whether the device has an active connection
+    pub fn is_connected(&self, device_id: &DeviceId) -> bool {
+        self.peers.lock().unwrap().contains_key(device_id)
+    }
+
+    /// Protocol version and features negotiated with a connected device
+    pub fn negotiated(&self, device_id: &DeviceId) -> Option<Negotiated> {
+        self.peers.lock().unwrap().get(device_id)?.negotiated
+    }
+
+    /// Keep the latest keepalive statistics of a connected device
+    pub fn record_stats(&self, device_id: &DeviceId, stats: ConnectionStats) {
+        if let Some(entry) = self.peers.lock().unwrap().get_mut(device_id) {
+            entry.stats = Some(stats);
+        }
+    }
+
+    /// Round trips and losses measured on a device's connection
+    pub fn stats(&self, device_id: &DeviceId) -> Option<ConnectionStats> {
+        self.peers.lock().unwrap().get(device_id)?.stats
+    }
+
+    /// Connected devices
+    pub fn connected_peers(&self) -> Vec<DeviceId> {
+        self.peers.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Close connection to a device
+    pub fn disconnect(&self, device_id: &DeviceId) -> bool {
+        if !self.forget(device_id) {
+            return false;
+        }
+        self.events.publish(Event::DeviceDisconnected {
+            device_id: device_id.to_string(),
+        });
+        true
+    }
+
+    /// Drop a connected device like `disconnect`, without publishing
+    /// `DeviceDisconnected`, e.g. because its keepalive already did
+    pub fn forget(&self, device_id: &DeviceId) -> bool {
+        let entry = {
+            let mut peers = self.peers.lock().unwrap();
+            let Some(entry) = peers.remove(device_id) else {
+                return false;
+            };
+            record_connected(peers.len());
+            entry
+        };
+        entry.closed.cancel();
+        true
+    }
+
+    /// Queue a frame for a peer
+    pub async fn send(&self, device_id: &DeviceId, frame: Frame) -> Result<()> {
+        if let Some(network) = &self.network {
+            if !network.state().is_online() {
+                return Err(ProtocolError::NotConnected(format!(
+                    "{} (offline)",
+                    device_id
+                )));
+            }
+        }
+        let sender = {
+            let peers = self.peers.lock().unwrap();
+            let entry = peers
+                .get(device_id)
+                .ok_or_else(|| ProtocolError::NotConnected(device_id.to_string()))?;
+            match frame.message_type {
+                MessageType::Revocation | MessageType::Wipe => entry.priority.clone(),
+                _ => entry.bulk.clone(),
+            }
+        };
+        let chunk_bytes =
+            (frame.message_type == MessageType::ChunkData).then_some(frame.payload.len());
+        sender
+            .send(frame)
+            .await
+            .map_err(|_| ProtocolError::NotConnected(device_id.to_string()))?;
+
+        if let Some(bytes) = chunk_bytes {
+            let metrics = nomade_metrics::global();
+            metrics
+                .counter(names::CHUNKS_TRANSFERRED, "Chunks transferred to peers")
+                .inc();
+            metrics
+                .counter(
+                    names::BYTES_SYNCED,
+                    "Payload bytes transferred to peers during sync",
+                )
+                .add(bytes as u64);
+        }
+        Ok(())
+    }
+
+    /// Apply a revocation and propagate it
+    ///
+    /// Used both for records received from a peer (`from`) and for local
+    /// revocations (`from` is `None`), which must already have been applied
+    /// with `TrustStore::revoke`. The revoked device is disconnected
+    /// immediately, and the record is forwarded to all other peers if new.
+    pub fn handle_revocation(
+        &self,
+        record: &RevocationRecord,
+        from: Option<&DeviceId>,
+    ) -> Result<()> {
+        let is_new = match from {
+            Some(_) => self
+                .trust
+                .write()
+                .unwrap()
+                .apply_revocation(record)
+                .map_err(|e| ProtocolError::PeerRejected(e.to_string()))?,
+            None => true,
+        };
+
+        self.disconnect(&record.revoked);
+        if !is_new {
+            return Ok(());
+        }
+        self.events.publish(Event::DeviceRevoked {
+            device_id: record.revoked.to_string(),
+        });
+
+        let frame = Frame::from_message(MessageType::Revocation, record)?;
+        let peers = self.peers.lock().unwrap();
+        for (device_id, entry) in peers.iter() {
+            if Some(device_id) == from {
+                continue;
+            }
+            // Never block revocation propagation on a slow peer
+            if entry.priority.try_send(frame.clone()).is_err() {
+                tracing::warn!("Failed to queue revocation for {}", device_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep a peer's clock offset, e.g. from `KeepaliveHandle::clock_offset`
+    pub fn set_clock_offset(&self, device_id: &DeviceId, sample: ClockSample) -> Result<()> {
+        let unchanged = self.clock_offset(device_id).is_some_and(|stored| {
+            (stored.offset_ms - sample.offset_ms).abs() < CLOCK_OFFSET_TOLERANCE_MS
+        });
+        if unchanged {
+            return Ok(());
+        }
+        self.trust
+            .write()
+            .unwrap()
+            .set_peer_data(device_id, CLOCK_OFFSET_KEY, serde_json::to_value(sample)?)
+            .map_err(|e| ProtocolError::PeerRejected(e.to_string()))
+    }
+
+    /// Last known clock offset of a peer
+    pub fn clock_offset(&self, device_id: &DeviceId) -> Option<ClockSample> {
+        let trust = self.trust.read().unwrap();
+        let value = trust.peer_data(device_id, CLOCK_OFFSET_KEY)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    /// Current Unix time in seconds on a peer's clock
+    ///
+    /// The local time if the peer's offset is unknown.
+    pub fn peer_time(&self, device_id: &DeviceId) -> u64 {
+        let now = wall_clock_ms() / 1000;
+        self.clock_offset(device_id)
+            .map_or(now, |offset| offset.peer_time(now))
+    }
+
+    /// Ask a connected device to wipe itself
+    pub fn send_wipe(&self, command: &WipeCommand) -> Result<()> {
+        let frame = Frame::from_message(MessageType::Wipe, command)?;
+        let peers = self.peers.lock().unwrap();
+        let entry = peers
+            .get(&command.target)
+            .ok_or_else(|| ProtocolError::NotConnected(command.target.to_string()))?;
+        entry
+            .priority
+            .try_send(frame)
+            .map_err(|_| ProtocolError::NotConnected(command.target.to_string()))
+    }
+
+    /// Queue device group roster entries for a connected member
+    pub fn send_roster(&self, device_id: &DeviceId, entries: &[RosterEntry]) -> Result<()> {
+        let frame = Frame::from_message(MessageType::Roster, &entries)?;
+        let peers = self.peers.lock().unwrap();
+        let entry = peers
+            .get(device_id)
+            .ok_or_else(|| ProtocolError::NotConnected(device_id.to_string()))?;
+        entry
+            .bulk
+            .try_send(frame)
+            .map_err(|_| ProtocolError::NotConnected(device_id.to_string()))
+    }
+
+    /// Store an attestation and propagate it
+    ///
+    /// Statements received from a peer (`from`) are verified and merged
+    /// into the trust store; local ones (this device's own statement, or
+    /// one it just countersigned and stored) are forwarded as is. Only
+    /// statements that added something are forwarded, so they stop
+    /// spreading once every device has them.
+    pub fn handle_attestation(
+        &self,
+        attestation: &Attestation,
+        from: Option<&DeviceId>,
+    ) -> Result<()> {
+        if from.is_some() {
+            let changed = self
+                .trust
+                .write()
+                .unwrap()
+                .apply_attestation(attestation)
+                .map_err(|e| ProtocolError::PeerRejected(e.to_string()))?;
+            if !changed {
+                return Ok(());
+            }
+        }
+        self.events.publish(Event::DeviceAttested {
+            device_id: attestation.device_id.to_string(),
+        });
+
+        let frame = Frame::from_message(MessageType::Attestation, attestation)?;
+        let peers = self.peers.lock().unwrap();
+        for (device_id, entry) in peers.iter() {
+            if Some(device_id) == from {
+                continue;
+            }
+            if entry.bulk.try_send(frame.clone()).is_err() {
+                tracing::debug!("Failed to queue attestation for {}", device_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn record_connected(count: usize) {
+    nomade_metrics::global()
+        .gauge(names::CONNECTED_PEERS, "Currently connected peers")
+        .set(count as i64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::{generate_keypair, DeviceKeypair};
+
+    fn trust_store(devices: &[&DeviceKeypair]) -> Arc<RwLock<TrustStore>> {
+        let mut store = TrustStore::new();
+        for keypair in devices {
+            store
+                .add_trusted(
+                    keypair.device_id().clone(),
+                    "Device".into(),
+                    keypair.public_key_bytes(),
+                )
+                .unwrap();
+        }
+        Arc::new(RwLock::new(store))
+    }
+
+    #[tokio::test]
+    async fn test_admit_checks_trust_store() {
+        let phone = generate_keypair();
+        let stranger = generate_keypair();
+        let manager = ConnectionManager::new(trust_store(&[&phone]), EventStream::new());
+
+        assert!(manager
+            .admit(phone.device_id().clone(), Direction::Inbound)
+            .is_ok());
+        assert!(manager.is_connected(phone.device_id()));
+        assert_eq!(manager.negotiated(phone.device_id()), None);
+        assert!(matches!(
+            manager.admit(stranger.device_id().clone(), Direction::Inbound),
+            Err(ProtocolError::PeerRejected(_))
+        ));
+
+        // A reconnect after a hello exchange keeps what was negotiated
+        let negotiated = Negotiated {
+            version: 1,
+            features: crate::FeatureFlags::CHUNKED_SYNC,
+        };
+        manager
+            .admit_negotiated(phone.device_id().clone(), Direction::Inbound, negotiated)
+            .unwrap();
+        assert_eq!(manager.negotiated(phone.device_id()), Some(negotiated));
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_dials_keep_one_connection() {
+        let (laptop, phone) = (generate_keypair(), generate_keypair());
+        let (low, high) = if laptop.device_id().0 < phone.device_id().0 {
+            (&laptop, &phone)
+        } else {
+            (&phone, &laptop)
+        };
+        let on_low = ConnectionManager::new(trust_store(&[high]), EventStream::new())
+            .with_local_device(low.device_id().clone());
+        let on_high = ConnectionManager::new(trust_store(&[low]), EventStream::new())
+            .with_local_device(high.device_id().clone());
+
+        // Both dial; each side sees its own outbound dial and the peer's,
+        // in opposite orders
+        let low_dial = on_low
+            .admit(high.device_id().clone(), Direction::Outbound)
+            .unwrap();
+        assert!(matches!(
+            on_low.admit(high.device_id().clone(), Direction::Inbound),
+            Err(ProtocolError::DuplicateConnection(_))
+        ));
+        let high_dial = on_high
+            .admit(low.device_id().clone(), Direction::Outbound)
+            .unwrap();
+        let _low_dial_accepted = on_high
+            .admit(low.device_id().clone(), Direction::Inbound)
+            .unwrap();
+        assert!(high_dial.closed.is_cancelled());
+        assert!(!low_dial.closed.is_cancelled());
+        assert_eq!(on_high.connected_peers().len(), 1);
+
+        // A reconnect in the same direction replaces the old connection
+        let redial = on_low
+            .admit(high.device_id().clone(), Direction::Outbound)
+            .unwrap();
+        assert!(low_dial.closed.is_cancelled());
+        assert!(!redial.closed.is_cancelled());
+        assert_eq!(on_low.connected_peers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_offline_closes_connections() {
+        let phone = generate_keypair();
+        let network = Arc::new(NetworkMonitor::new());
+        let manager = ConnectionManager::new(trust_store(&[&phone]), EventStream::new())
+            .with_network(network.clone());
+        let queues = manager
+            .admit(phone.device_id().clone(), Direction::Inbound)
+            .unwrap();
+        assert_eq!(manager.apply_network(&network.state()), 0);
+
+        network.record_probe(false);
+        assert_eq!(manager.apply_network(&network.state()), 1);
+        assert!(queues.closed.is_cancelled());
+        manager
+            .admit(phone.device_id().clone(), Direction::Inbound)
+            .unwrap();
+        let frame = Frame::new(MessageType::ChunkData, vec![0; 8]);
+        assert!(matches!(
+            manager.send(phone.device_id(), frame).await,
+            Err(ProtocolError::NotConnected(_))
+        ));
+    }
+
+    #[test]
+    fn test_endpoints_ordered_by_preference() {
+        let phone = generate_keypair();
+        let manager = ConnectionManager::new(trust_store(&[&phone]), EventStream::new());
+        assert!(manager.endpoints(phone.device_id()).is_empty());
+
+        let endpoints = ["relay:eu-1", "phone.local:8765", "192.168.1.20:8765"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        manager.set_endpoints(phone.device_id().clone(), endpoints);
+        let known: Vec<String> = manager
+            .endpoints(phone.device_id())
+            .iter()
+            .map(Endpoint::to_string)
+            .collect();
+        assert_eq!(
+            known,
+            ["192.168.1.20:8765", "phone.local:8765", "relay:eu-1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revocation_terminates_and_propagates() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let tablet = generate_keypair();
+        let trust = trust_store(&[&phone, &tablet]);
+        let manager = ConnectionManager::new(trust.clone(), EventStream::new());
+
+        let phone_queues = manager
+            .admit(phone.device_id().clone(), Direction::Inbound)
+            .unwrap();
+        let mut tablet_queues = manager
+            .admit(tablet.device_id().clone(), Direction::Inbound)
+            .unwrap();
+
+        // Queue bulk traffic first; the revocation must overtake it
+        manager
+            .send(
+                tablet.device_id(),
+                Frame::new(MessageType::ChunkData, vec![0; 8]),
+            )
+            .await
+            .unwrap();
+
+        let record = trust
+            .write()
+            .unwrap()
+            .revoke(&laptop, phone.device_id().clone(), "Lost".into())
+            .unwrap();
+        manager.handle_revocation(&record, None).unwrap();
+
+        assert!(phone_queues.closed.is_cancelled());
+        assert!(!manager.is_connected(phone.device_id()));
+        assert!(manager
+            .admit(phone.device_id().clone(), Direction::Inbound)
+            .is_err());
+
+        let frame = tablet_queues.next_frame().await.unwrap();
+        assert_eq!(frame.message_type, MessageType::Revocation);
+        assert_eq!(frame.to_message::<RevocationRecord>().unwrap(), record);
+        let frame = tablet_queues.next_frame().await.unwrap();
+        assert_eq!(frame.message_type, MessageType::ChunkData);
+    }
+
+    #[test]
+    fn test_clock_offsets_shift_peer_time() {
+        let (phone, stranger) = (generate_keypair(), generate_keypair());
+        let trust = trust_store(&[&phone]);
+        let manager = ConnectionManager::new(trust.clone(), EventStream::new());
+        let phone_id = phone.device_id();
+        let now = wall_clock_ms() / 1000;
+        assert!(manager.peer_time(phone_id).abs_diff(now) <= 1);
+
+        let ahead = ClockSample {
+            offset_ms: 600_000,
+            delay_ms: 30,
+        };
+        manager.set_clock_offset(phone_id, ahead).unwrap();
+        assert!(manager.peer_time(phone_id).abs_diff(now + 600) <= 1);
+        // Small moves of the estimate are not written back
+        let jitter = ClockSample {
+            offset_ms: 600_200,
+            delay_ms: 20,
+        };
+        manager.set_clock_offset(phone_id, jitter).unwrap();
+        assert_eq!(manager.clock_offset(phone_id), Some(ahead));
+        assert!(manager
+            .set_clock_offset(stranger.device_id(), ahead)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_attestations_spread_until_known() {
+        let (laptop, phone, tablet) = (generate_keypair(), generate_keypair(), generate_keypair());
+        let trust = trust_store(&[&laptop, &phone, &tablet]);
+        let manager = ConnectionManager::new(trust.clone(), EventStream::new());
+        let mut laptop_queues = manager
+            .admit(laptop.device_id().clone(), Direction::Inbound)
+            .unwrap();
+        let mut tablet_queues = manager
+            .admit(tablet.device_id().clone(), Direction::Inbound)
+            .unwrap();
+
+        let mut statement = Attestation::new(
+            &phone,
+            "Phone".into(),
+            "alice".into(),
+            std::time::Duration::from_secs(3600),
+        )
+        .unwrap();
+        statement.countersign(&laptop).unwrap();
+        manager
+            .handle_attestation(&statement, Some(laptop.device_id()))
+            .unwrap();
+        let frame = tablet_queues.next_frame().await.unwrap();
+        assert_eq!(frame.message_type, MessageType::Attestation);
+        assert_eq!(frame.to_message::<Attestation>().unwrap(), statement);
+
+        // Known statements are not forwarded again; forged ones are refused
+        manager
+            .handle_attestation(&statement, Some(tablet.device_id()))
+            .unwrap();
+        assert!(laptop_queues.bulk.try_recv().is_err());
+        statement.user = "mallory".into();
+        assert!(manager
+            .handle_attestation(&statement, Some(tablet.device_id()))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_received_revocation_applied_once() {
+        let laptop = generate_keypair();
+        let phone = generate_keypair();
+        let trust = trust_store(&[&laptop, &phone]);
+        let manager = ConnectionManager::new(trust.clone(), EventStream::new());
+        let _laptop_queues = manager
+            .admit(laptop.device_id().clone(), Direction::Inbound)
+            .unwrap();
+
+        let record =
+            RevocationRecord::new(&laptop, phone.device_id().clone(), "Lost".into()).unwrap();
+        manager
+            .handle_revocation(&record, Some(laptop.device_id()))
+            .unwrap();
+        assert!(trust
+            .read()
+            .unwrap()
+            .check_handshake(phone.device_id())
+            .is_err());
+
+        // Forged record from an untrusted device is rejected
+        let attacker = generate_keypair();
+        let forged =
+            RevocationRecord::new(&attacker, laptop.device_id().clone(), "".into()).unwrap();
+        assert!(manager
+            .handle_revocation(&forged, Some(attacker.device_id()))
+            .is_err());
+        assert!(manager.is_connected(laptop.device_id()));
+    }
+}
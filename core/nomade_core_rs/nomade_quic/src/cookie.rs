@@ -0,0 +1,275 @@
+//! Stateless cookies and client puzzles for pairing
+//!
+//! A listener in pairing mode accepts connections from devices it does not
+//! know yet, so anyone can make it start a PAKE. Before doing any work for
+//! such a peer, the listener sends a `PairingPuzzle`: a cookie, keyed by a
+//! secret only the listener knows and bound to the peer's address, the
+//! current time window and a random salt, plus a difficulty. The peer must find a nonce such
+//! that `blake3(cookie || nonce)` starts with that many zero bits.
+//!
+//! The listener keeps no state per challenge: it recomputes the cookie
+//! from the address and the salt it carries to check the answer, so a flood of bogus attempts
+//! costs the attacker far more CPU than the listener, and no memory at
+//! all. Only accepted cookies are remembered, until they expire, so one
+//! solved puzzle buys a single attempt; the salt gives every challenge a
+//! new cookie, so a peer retrying from the same address is not refused.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use nomade_crypto::unix_time;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{ProtocolError, Result};
+
+/// Seconds a cookie stays valid (one window, plus the previous one)
+pub const COOKIE_WINDOW_SECS: u64 = 60;
+
+/// Default leading zero bits, about 65k hashes to solve
+pub const DEFAULT_PUZZLE_BITS: u8 = 16;
+
+/// Hardest puzzle a peer agrees to solve
+pub const MAX_PUZZLE_BITS: u8 = 24;
+
+/// Accepted cookies remembered at once; further answers wait for expiry
+pub const MAX_SPENT_COOKIES: usize = 4096;
+
+/// Random bytes leading every cookie
+const COOKIE_SALT_LEN: usize = 16;
+
+/// Challenge sent to a device asking to pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairingPuzzle {
+    pub cookie: Vec<u8>,
+    /// Leading zero bits the answer must produce
+    pub difficulty: u8,
+}
+
+/// Answer to a `PairingPuzzle`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PuzzleSolution {
+    pub cookie: Vec<u8>,
+    pub nonce: u64,
+}
+
+impl PairingPuzzle {
+    /// Search for a nonce; refuses puzzles above `MAX_PUZZLE_BITS`
+    pub fn solve(&self) -> Result<PuzzleSolution> {
+        if self.difficulty > MAX_PUZZLE_BITS {
+            return Err(ProtocolError::PeerRejected(format!(
+                "Pairing puzzle too hard: {} bits",
+                self.difficulty
+            )));
+        }
+        let nonce = (0..=u64::MAX)
+            .find(|nonce| leading_zero_bits(&self.cookie, *nonce) >= self.difficulty as u32)
+            .expect("a nonce exists");
+        Ok(PuzzleSolution {
+            cookie: self.cookie.clone(),
+            nonce,
+        })
+    }
+}
+
+/// Issues and checks puzzles without keeping per-peer state
+pub struct CookieIssuer {
+    secret: [u8; 32],
+    difficulty: u8,
+    /// Accepted cookies, with the window they were issued in
+    spent: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl CookieIssuer {
+    /// Create an issuer with a fresh secret
+    pub fn new(difficulty: u8) -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self {
+            secret,
+            difficulty,
+            spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Difficulty of the puzzles issued
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty
+    }
+
+    /// Puzzle for a peer connecting from `source`
+    pub fn challenge(&self, source: &str) -> PairingPuzzle {
+        let mut salt = [0u8; COOKIE_SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        PairingPuzzle {
+            cookie: self.cookie(source, window(unix_time()), &salt),
+            difficulty: self.difficulty,
+        }
+    }
+
+    /// Check an answer from `source`, once per cookie
+    pub fn verify(&self, source: &str, solution: &PuzzleSolution) -> Result<()> {
+        self.verify_at(source, solution, unix_time())
+    }
+
+    /// Check an answer at the given time (seconds since UNIX epoch)
+    pub fn verify_at(&self, source: &str, solution: &PuzzleSolution, now: u64) -> Result<()> {
+        let current = window(now);
+        let oldest = current.saturating_sub(1);
+        let salt = solution.cookie.get(..COOKIE_SALT_LEN).unwrap_or_default();
+        let Some(issued) = [current, oldest]
+            .into_iter()
+            .find(|w| nomade_crypto::ct_eq(&self.cookie(source, *w, salt), &solution.cookie))
+        else {
+            return Err(ProtocolError::PeerRejected(
+                "Unknown or expired pairing cookie".into(),
+            ));
+        };
+        if leading_zero_bits(&solution.cookie, solution.nonce) < self.difficulty as u32 {
+            return Err(ProtocolError::PeerRejected(
+                "Pairing puzzle not solved".into(),
+            ));
+        }
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, w| *w >= oldest);
+        if spent.contains_key(&solution.cookie) {
+            return Err(ProtocolError::PeerRejected(
+                "Pairing cookie already used".into(),
+            ));
+        }
+        if spent.len() >= MAX_SPENT_COOKIES {
+            return Err(ProtocolError::PeerRejected(
+                "Too many pairing attempts".into(),
+            ));
+        }
+        spent.insert(solution.cookie.clone(), issued);
+        Ok(())
+    }
+
+    /// `salt` followed by the keyed hash of the window, `source` and salt
+    fn cookie(&self, source: &str, window: u64, salt: &[u8]) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new_keyed(&self.secret);
+        hasher.update(b"nomade-pairing-cookie-v2");
+        hasher.update(&window.to_le_bytes());
+        hasher.update(salt);
+        hasher.update(source.as_bytes());
+        [salt, hasher.finalize().as_bytes()].concat()
+    }
+}
+
+impl std::fmt::Debug for CookieIssuer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CookieIssuer")
+            .field("difficulty", &self.difficulty)
+            .finish_non_exhaustive()
+    }
+}
+
+fn leading_zero_bits(cookie: &[u8], nonce: u64) -> u32 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(cookie);
+    hasher.update(&nonce.to_le_bytes());
+    let hash = hasher.finalize();
+    let mut bits = 0;
+    for byte in hash.as_bytes() {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn window(secs: u64) -> u64 {
+    secs / COOKIE_WINDOW_SECS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_puzzles_bind_source_and_time() {
+        let issuer = CookieIssuer::new(8);
+        let puzzle = issuer.challenge("10.0.0.1:4433");
+        let solution = puzzle.solve().unwrap();
+        assert!(issuer.verify("10.0.0.2:4433", &solution).is_err());
+        assert!(CookieIssuer::new(8)
+            .verify("10.0.0.1:4433", &solution)
+            .is_err());
+
+        let now = unix_time();
+        issuer
+            .verify_at("10.0.0.1:4433", &solution, now + COOKIE_WINDOW_SECS)
+            .unwrap();
+        assert!(issuer
+            .verify_at("10.0.0.1:4433", &solution, now + 2 * COOKIE_WINDOW_SECS)
+            .is_err());
+    }
+
+    #[test]
+    fn test_solutions_spent_once() {
+        let issuer = CookieIssuer::new(4);
+        let now = unix_time();
+        let puzzle = issuer.challenge("peer");
+        let solution = puzzle.solve().unwrap();
+        issuer.verify_at("peer", &solution, now).unwrap();
+        assert!(issuer.verify_at("peer", &solution, now).is_err());
+        // Another answer to the same challenge is refused as well
+        let other = PuzzleSolution {
+            nonce: (solution.nonce + 1..)
+                .find(|n| leading_zero_bits(&puzzle.cookie, *n) >= 4)
+                .unwrap(),
+            ..solution.clone()
+        };
+        assert!(issuer.verify_at("peer", &other, now).is_err());
+
+        // Expired cookies are forgotten, bounding the set
+        let later = now + 2 * COOKIE_WINDOW_SECS;
+        let fresh = PairingPuzzle {
+            cookie: issuer.cookie("peer", window(later), &[7; COOKIE_SALT_LEN]),
+            difficulty: 4,
+        }
+        .solve()
+        .unwrap();
+        issuer.verify_at("peer", &fresh, later).unwrap();
+        assert_eq!(issuer.spent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_retry_gets_fresh_puzzle() {
+        let issuer = CookieIssuer::new(4);
+        let first = issuer.challenge("peer");
+        let second = issuer.challenge("peer");
+        assert_ne!(first.cookie, second.cookie);
+        issuer.verify("peer", &first.solve().unwrap()).unwrap();
+        issuer.verify("peer", &second.solve().unwrap()).unwrap();
+
+        // The salt cannot be swapped for another
+        let mut forged = issuer.challenge("peer").solve().unwrap();
+        forged.cookie[0] ^= 1;
+        assert!(issuer.verify("peer", &forged).is_err());
+    }
+
+    #[test]
+    fn test_unsolved_and_oversized_puzzles_rejected() {
+        let issuer = CookieIssuer::new(12);
+        let puzzle = issuer.challenge("peer");
+        let solution = puzzle.solve().unwrap();
+        assert!(leading_zero_bits(&solution.cookie, solution.nonce) >= 12);
+        // Some nonce below the answer fails the work check
+        let lazy = PuzzleSolution {
+            nonce: (0..solution.nonce)
+                .find(|n| leading_zero_bits(&puzzle.cookie, *n) < 12)
+                .unwrap_or(solution.nonce + 1),
+            ..solution
+        };
+        assert!(issuer.verify("peer", &lazy).is_err());
+
+        let greedy = PairingPuzzle {
+            cookie: puzzle.cookie,
+            difficulty: MAX_PUZZLE_BITS + 1,
+        };
+        assert!(greedy.solve().is_err());
+    }
+}
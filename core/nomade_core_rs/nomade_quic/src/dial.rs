@@ -0,0 +1,292 @@
+//! Racing connection attempts across a peer's endpoints
+//!
+//! Follows Happy Eyeballs (RFC 8305): endpoints are tried in order,
+//! alternating address families, with a new attempt started every
+//! `stagger` or as soon as the previous one fails. The first connection to
+//! complete wins and the remaining attempts are cancelled. Outcomes are
+//! recorded per endpoint so endpoints that worked before are tried first.
+//! When every endpoint failed transiently, the race is run again under the
+//! dialer's `RetryPolicy`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nomade_crypto::Endpoint;
+use tokio::task::JoinSet;
+
+use crate::transport::{Connection, Transport};
+use crate::{ProtocolError, Result, RetryPolicy};
+
+/// Delay before starting the next attempt (RFC 8305 recommendation)
+pub const DEFAULT_STAGGER: Duration = Duration::from_millis(250);
+
+/// Dial outcomes observed for one endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EndpointStats {
+    pub successes: u32,
+    pub failures: u32,
+    /// Time to connect on the last success
+    pub last_connect_time: Option<Duration>,
+}
+
+impl EndpointStats {
+    /// Successes minus failures; higher is tried first
+    fn score(&self) -> i64 {
+        i64::from(self.successes) - i64::from(self.failures)
+    }
+}
+
+/// Dials peers by racing their endpoints
+#[derive(Clone)]
+pub struct Dialer {
+    transport: Arc<dyn Transport>,
+    stagger: Duration,
+    retry: RetryPolicy,
+    stats: Arc<Mutex<HashMap<Endpoint, EndpointStats>>>,
+}
+
+impl Dialer {
+    /// Create a dialer with the default stagger and retry policy
+    pub fn new(transport: Arc<dyn Transport>) -> Self {
+        Self {
+            transport,
+            stagger: DEFAULT_STAGGER,
+            retry: RetryPolicy::default(),
+            stats: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Override the delay between attempts
+    pub fn with_stagger(mut self, stagger: Duration) -> Self {
+        self.stagger = stagger;
+        self
+    }
+
+    /// Override how failed races are retried
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Outcomes recorded for an endpoint
+    pub fn stats(&self, endpoint: &Endpoint) -> EndpointStats {
+        self.stats
+            .lock()
+            .unwrap()
+            .get(endpoint)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Order in which `connect` tries endpoints
+    ///
+    /// Endpoints with a better track record come first, then by
+    /// preference; IPv6 and IPv4 addresses are then interleaved so one
+    /// broken family cannot delay the other by more than one stagger.
+    pub fn order(&self, endpoints: &[Endpoint]) -> Vec<Endpoint> {
+        let mut seen = HashSet::new();
+        let mut ordered = endpoints.to_vec();
+        ordered.retain(|endpoint| seen.insert(endpoint.clone()));
+        {
+            let stats = self.stats.lock().unwrap();
+            ordered.sort_by_key(|endpoint| {
+                let score = stats.get(endpoint).map_or(0, EndpointStats::score);
+                std::cmp::Reverse((score, endpoint.preference()))
+            });
+        }
+        interleave_families(ordered)
+    }
+
+    /// Connect to whichever endpoint answers first
+    ///
+    /// Returns the winning endpoint with its connection, or the last error
+    /// once every attempt of every retry failed.
+    pub async fn connect(&self, endpoints: &[Endpoint]) -> Result<(Endpoint, Arc<dyn Connection>)> {
+        if endpoints.is_empty() {
+            return Err(ProtocolError::Transport("No endpoints to dial".into()));
+        }
+        self.retry.run("Dial", || self.race(endpoints)).await
+    }
+
+    async fn race(&self, endpoints: &[Endpoint]) -> Result<(Endpoint, Arc<dyn Connection>)> {
+        let mut pending = self.order(endpoints).into_iter();
+        let mut attempts = JoinSet::new();
+        let mut last_error = None;
+        let mut next_start = tokio::time::Instant::now();
+
+        loop {
+            if attempts.is_empty() && pending.len() == 0 {
+                return Err(last_error
+                    .unwrap_or_else(|| ProtocolError::Transport("No endpoints to dial".into())));
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep_until(next_start), if pending.len() > 0 => {
+                    let endpoint = pending.next().expect("pending endpoint");
+                    let transport = self.transport.clone();
+                    attempts.spawn(async move {
+                        let started = Instant::now();
+                        let result = transport.connect(&endpoint.to_string()).await;
+                        (endpoint, started.elapsed(), result)
+                    });
+                    next_start = tokio::time::Instant::now() + self.stagger;
+                }
+                Some(joined) = attempts.join_next(), if !attempts.is_empty() => {
+                    let (endpoint, elapsed, result) = joined
+                        .map_err(|e| ProtocolError::Transport(e.to_string()))?;
+                    match result {
+                        Ok(connection) => {
+                            self.record(&endpoint, Some(elapsed));
+                            // Cancel the losing attempts
+                            attempts.abort_all();
+                            return Ok((endpoint, connection));
+                        }
+                        Err(e) => {
+                            tracing::debug!("Dial {} failed: {}", endpoint, e);
+                            self.record(&endpoint, None);
+                            last_error = Some(e);
+                            next_start = tokio::time::Instant::now();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn record(&self, endpoint: &Endpoint, connect_time: Option<Duration>) {
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(endpoint.clone()).or_default();
+        match connect_time {
+            Some(elapsed) => {
+                entry.successes += 1;
+                entry.last_connect_time = Some(elapsed);
+            }
+            None => entry.failures += 1,
+        }
+    }
+}
+
+/// Alternate IPv6 and IPv4 endpoints, starting with the first one's family
+///
+/// Hostnames and relays keep their position after the direct endpoints.
+fn interleave_families(ordered: Vec<Endpoint>) -> Vec<Endpoint> {
+    let (direct, other): (Vec<_>, Vec<_>) = ordered
+        .into_iter()
+        .partition(|endpoint| endpoint.socket_addr().is_some());
+    let first_is_v6 = matches!(direct.first(), Some(Endpoint::DirectV6(_)));
+    let (first, second): (Vec<_>, Vec<_>) = direct
+        .into_iter()
+        .partition(|endpoint| matches!(endpoint, Endpoint::DirectV6(_)) == first_is_v6);
+
+    let mut interleaved = Vec::with_capacity(first.len() + second.len() + other.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+    interleaved.extend(other);
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{BoxFuture, MemoryNetwork, MemoryTransport};
+
+    /// Memory transport whose dials to `hang` never complete
+    struct Hanging {
+        inner: MemoryTransport,
+        hang: String,
+    }
+
+    impl Transport for Hanging {
+        fn local_addr(&self) -> String {
+            self.inner.local_addr()
+        }
+
+        fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<Arc<dyn Connection>>> {
+            if addr == self.hang {
+                Box::pin(std::future::pending())
+            } else {
+                self.inner.connect(addr)
+            }
+        }
+
+        fn accept(&self) -> BoxFuture<'_, Result<Option<Arc<dyn Connection>>>> {
+            self.inner.accept()
+        }
+    }
+
+    fn endpoints(addrs: &[&str]) -> Vec<Endpoint> {
+        addrs.iter().map(|addr| addr.parse().unwrap()).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_endpoint_falls_through_and_is_demoted() {
+        let network = MemoryNetwork::new();
+        let _peer = network.bind("192.168.1.20:8765").unwrap();
+        let dialer = Dialer::new(Arc::new(network.bind("client").unwrap()))
+            .with_stagger(Duration::from_secs(60));
+        let candidates = endpoints(&["10.0.0.9:8765", "192.168.1.20:8765"]);
+
+        // The dead endpoint fails fast, so the stagger is never waited out
+        let (winner, _) = dialer.connect(&candidates).await.unwrap();
+        assert_eq!(winner.to_string(), "192.168.1.20:8765");
+        assert_eq!(dialer.stats(&candidates[0]).failures, 1);
+        assert_eq!(dialer.stats(&winner).successes, 1);
+        assert_eq!(dialer.order(&candidates)[0], winner);
+
+        assert!(matches!(
+            dialer.connect(&candidates[..1]).await,
+            Err(ProtocolError::Transport(_))
+        ));
+        // Retried under the default policy before giving up
+        assert_eq!(dialer.stats(&candidates[0]).failures, 5);
+        assert!(dialer.connect(&[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hanging_endpoint_is_raced() {
+        let network = MemoryNetwork::new();
+        let _peer = network.bind("[fd00::2]:8765").unwrap();
+        let dialer = Dialer::new(Arc::new(Hanging {
+            inner: network.bind("client").unwrap(),
+            hang: "10.0.0.2:8765".into(),
+        }))
+        .with_stagger(Duration::from_millis(20));
+
+        let candidates = endpoints(&["10.0.0.2:8765", "[fd00::2]:8765"]);
+        let started = Instant::now();
+        let (winner, _) = dialer.connect(&candidates).await.unwrap();
+        assert_eq!(winner.to_string(), "[fd00::2]:8765");
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        assert_eq!(dialer.stats(&candidates[0]), EndpointStats::default());
+    }
+
+    #[test]
+    fn test_interleaves_address_families() {
+        let ordered = interleave_families(endpoints(&[
+            "10.0.0.1:1",
+            "10.0.0.2:1",
+            "10.0.0.3:1",
+            "[fd00::1]:1",
+            "host.local:1",
+            "[fd00::2]:1",
+        ]));
+        let ordered: Vec<String> = ordered.iter().map(Endpoint::to_string).collect();
+        assert_eq!(
+            ordered,
+            [
+                "10.0.0.1:1",
+                "[fd00::1]:1",
+                "10.0.0.2:1",
+                "[fd00::2]:1",
+                "10.0.0.3:1",
+                "host.local:1",
+            ]
+        );
+    }
+}
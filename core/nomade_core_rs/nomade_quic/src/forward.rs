@@ -0,0 +1,186 @@
+//! Live event forwarding between connected devices
+//!
+//! Each side pushes its forwardable events (artifact and collection
+//! changes) on a `LiveEvents` channel as they happen, so peers can react
+//! before the next full sync. Received events are published locally as
+//! `Event::Remote`, tagged with the sender's device ID.
+//!
+//! Snippets ride the same channel but go only to the peer they are
+//! addressed to, and arrive as `Event::SnippetReceived`.
+
+use std::sync::Arc;
+
+use nomade_crypto::DeviceId;
+use nomade_events::{Event, EventStream, MAX_SNIPPET_LEN};
+use tokio::sync::broadcast;
+
+use crate::channel::{Channel, ChannelId};
+use crate::frame::{Frame, MessageType};
+use crate::replay::ReplayGuard;
+use crate::seal::FrameSeal;
+use crate::transport::Connection;
+use crate::Result;
+
+/// Push local events to the peer `peer`
+///
+/// Runs until the event stream closes or sending fails; cancel the task
+/// to stop forwarding earlier. Frames are sequenced with `replay` and
+/// sealed with `seal` if the peer negotiated them.
+pub async fn forward_events(
+    connection: &dyn Connection,
+    peer: &DeviceId,
+    events: &EventStream,
+    replay: Option<ReplayGuard>,
+    seal: Option<Arc<dyn FrameSeal>>,
+) -> Result<()> {
+    // Subscribe first so nothing published while opening is missed
+    let mut rx = events.subscribe();
+    let mut channel = Channel::open(connection, ChannelId::LiveEvents).await?;
+    channel.set_replay_guard(replay);
+    channel.set_seal(seal);
+    loop {
+        match rx.recv().await {
+            Ok(event) if event.is_forwardable() => {
+                channel
+                    .send(&Frame::from_message(MessageType::Event, &event)?)
+                    .await?;
+            }
+            Ok(event @ Event::SnippetSent { .. }) => {
+                if matches!(&event, Event::SnippetSent { to, .. } if *to == peer.0) {
+                    channel
+                        .send(&Frame::from_message(MessageType::Event, &event)?)
+                        .await?;
+                }
+            }
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                // The peer catches up on the next sync
+                tracing::debug!("Dropped {} events for {}", missed, connection.remote_addr());
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+    channel.finish().await
+}
+
+/// Publish events received from `origin` on `channel` into `events`
+///
+/// Returns once the peer stops forwarding. Events a peer has no business
+/// forwarding (local state, or events relayed from a third device) are
+/// dropped.
+pub async fn receive_events(
+    mut channel: Channel,
+    origin: &DeviceId,
+    events: &EventStream,
+) -> Result<()> {
+    while let Some(frame) = channel.recv().await? {
+        let event: Event = frame.to_message()?;
+        if let Event::SnippetSent { snippet, .. } = event {
+            if snippet.text.len() > MAX_SNIPPET_LEN {
+                tracing::debug!("Dropping oversized snippet from {}", origin);
+                continue;
+            }
+            events.publish(Event::SnippetReceived {
+                from: origin.to_string(),
+                snippet,
+            });
+            continue;
+        }
+        if !event.is_forwardable() {
+            tracing::debug!("Ignoring {:?} forwarded by {}", event, origin);
+            continue;
+        }
+        events.publish(Event::Remote {
+            origin: origin.to_string(),
+            event: Box::new(event),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryNetwork, Transport};
+    use nomade_events::Snippet;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_events_reach_peer_tagged_with_origin() {
+        let network = MemoryNetwork::new();
+        let laptop = network.bind("laptop").unwrap();
+        let phone = network.bind("phone").unwrap();
+        let dialed = laptop.connect("phone").await.unwrap();
+        let accepted = phone.accept().await.unwrap().unwrap();
+
+        let laptop_events = EventStream::new();
+        let forwarding = tokio::spawn({
+            let events = laptop_events.clone();
+            async move {
+                forward_events(
+                    dialed.as_ref(),
+                    &DeviceId("phone-id".into()),
+                    &events,
+                    None,
+                    None,
+                )
+                .await
+            }
+        });
+
+        let phone_events = EventStream::new();
+        let mut received = phone_events.subscribe();
+        let channel = Channel::accept(accepted.as_ref()).await.unwrap().unwrap();
+        assert_eq!(channel.id(), ChannelId::LiveEvents);
+        let origin = DeviceId("laptop-id".into());
+        let receiving = tokio::spawn({
+            let events = phone_events.clone();
+            async move { receive_events(channel, &origin, &events).await }
+        });
+
+        laptop_events.publish(Event::DeviceConnected {
+            device_id: "tablet".into(),
+        });
+        laptop_events.publish(Event::ArtifactCreated { id: "a1".into() });
+
+        let event = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match event {
+            Event::Remote { origin, event } => {
+                assert_eq!(origin, "laptop-id");
+                assert!(matches!(*event, Event::ArtifactCreated { ref id } if id == "a1"));
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        // Only the snippet addressed to the phone arrives
+        let snippet = |to: &str| Event::SnippetSent {
+            to: to.into(),
+            snippet: Snippet {
+                id: to.into(),
+                text: "https://example.com".into(),
+                sent_at: 0,
+            },
+        };
+        laptop_events.publish(snippet("tablet-id"));
+        laptop_events.publish(snippet("phone-id"));
+        let event = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match event {
+            Event::SnippetReceived { from, snippet } => {
+                assert_eq!(from, "laptop-id");
+                assert_eq!(snippet.id, "phone-id");
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        // Stopping the forwarder closes the channel and ends the receiver
+        forwarding.abort();
+        receiving.await.unwrap().unwrap();
+        assert!(received.try_recv().is_err());
+    }
+}
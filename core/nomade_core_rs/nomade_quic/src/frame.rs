@@ -0,0 +1,525 @@
+//! Message framing over QUIC streams
+//!
+//! Every message is sent as a length-prefixed, versioned envelope:
+//!
+//! ```text
+//! +----------------+---------+--------------+-----------------+
+//! | length (u32 BE)| version | message type | payload (JSON)  |
+//! +----------------+---------+--------------+-----------------+
+//! ```
+//!
+//! `length` covers everything after the length prefix itself. A message
+//! type with `COMPRESSED_FLAG` set carries a zstd-compressed payload, which
+//! is decompressed on decode; only senders asked for compression set it.
+//! With `SEQUENCED_FLAG` set, a session sequence number (u64 BE) sits
+//! between the message type and the payload, for replay protection (see
+//! `replay`).
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{ProtocolError, Result};
+
+/// Current frame format version
+pub const FRAME_VERSION: u8 = 1;
+
+/// Maximum encoded frame size (header + payload)
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// Size of the length prefix
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// Size of the version and message type header
+const HEADER_SIZE: usize = 2;
+
+/// Bit of the message type tag marking a compressed payload
+pub const COMPRESSED_FLAG: u8 = 0x80;
+
+/// Bit of the message type tag marking a sequence number after the header
+pub const SEQUENCED_FLAG: u8 = 0x40;
+
+/// Size of the optional sequence number
+const SEQUENCE_SIZE: usize = 8;
+
+/// Payloads shorter than this are sent uncompressed
+const MIN_COMPRESS_SIZE: usize = 256;
+
+/// Message type tags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum MessageType {
+    Handshake = 1,
+    SyncRequest = 2,
+    ChunkData = 3,
+    Event = 4,
+    Ping = 5,
+    Pong = 6,
+    Revocation = 7,
+    Attestation = 8,
+    Wipe = 9,
+    Roster = 10,
+}
+
+impl MessageType {
+    /// Wire tag for this message type
+    pub fn tag(self) -> u8 {
+        self as u8
+    }
+}
+
+impl TryFrom<u8> for MessageType {
+    type Error = ProtocolError;
+
+    fn try_from(tag: u8) -> Result<Self> {
+        match tag {
+            1 => Ok(Self::Handshake),
+            2 => Ok(Self::SyncRequest),
+            3 => Ok(Self::ChunkData),
+            4 => Ok(Self::Event),
+            5 => Ok(Self::Ping),
+            6 => Ok(Self::Pong),
+            7 => Ok(Self::Revocation),
+            8 => Ok(Self::Attestation),
+            9 => Ok(Self::Wipe),
+            10 => Ok(Self::Roster),
+            other => Err(ProtocolError::UnknownMessageType(other)),
+        }
+    }
+}
+
+/// Versioned message envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub version: u8,
+    pub message_type: MessageType,
+    /// Session sequence number, set by channels with replay protection
+    pub sequence: Option<u64>,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Create frame from raw payload bytes
+    pub fn new(message_type: MessageType, payload: Vec<u8>) -> Self {
+        Self {
+            version: FRAME_VERSION,
+            message_type,
+            sequence: None,
+            payload,
+        }
+    }
+
+    /// Set the session sequence number
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    /// Create frame by serializing a message
+    pub fn from_message<T: Serialize>(message_type: MessageType, message: &T) -> Result<Self> {
+        let payload = serde_json::to_vec(message)?;
+        Ok(Self::new(message_type, payload))
+    }
+
+    /// Deserialize the payload into a message
+    pub fn to_message<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// Total encoded size including the length prefix
+    pub fn encoded_len(&self) -> usize {
+        self.header_len() + self.payload.len()
+    }
+
+    fn header_len(&self) -> usize {
+        LENGTH_PREFIX_SIZE + HEADER_SIZE + self.sequence.map_or(0, |_| SEQUENCE_SIZE)
+    }
+
+    /// Encode frame to bytes
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_payload(self.message_type.tag(), &self.payload)
+    }
+
+    /// Encode frame with its payload compressed at zstd `level`
+    ///
+    /// Falls back to `encode` when compression does not make the payload
+    /// smaller, e.g. for media or encrypted content.
+    pub fn encode_compressed(&self, level: i32) -> Result<Vec<u8>> {
+        if self.payload.len() >= MIN_COMPRESS_SIZE {
+            let compressed = zstd::bulk::compress(&self.payload, level)?;
+            if compressed.len() < self.payload.len() {
+                let tag = self.message_type.tag() | COMPRESSED_FLAG;
+                return self.encode_payload(tag, &compressed);
+            }
+        }
+        self.encode()
+    }
+
+    fn encode_payload(&self, tag: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let size = self.header_len() + payload.len();
+        if size > MAX_FRAME_SIZE {
+            return Err(ProtocolError::FrameTooLarge {
+                size,
+                max: MAX_FRAME_SIZE,
+            });
+        }
+
+        let body_len = (size - LENGTH_PREFIX_SIZE) as u32;
+        let mut buf = Vec::with_capacity(size);
+        buf.extend_from_slice(&body_len.to_be_bytes());
+        buf.push(self.version);
+        match self.sequence {
+            Some(sequence) => {
+                buf.push(tag | SEQUENCED_FLAG);
+                buf.extend_from_slice(&sequence.to_be_bytes());
+            }
+            None => buf.push(tag),
+        }
+        buf.extend_from_slice(payload);
+        Ok(buf)
+    }
+
+    /// Decode a single frame from the start of `buf`
+    ///
+    /// Returns `Ok(None)` if `buf` does not yet hold a complete frame, or the
+    /// frame together with the number of bytes consumed.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Frame, usize)>> {
+        let Some((prefix, _)) = buf.split_first_chunk::<LENGTH_PREFIX_SIZE>() else {
+            return Ok(None);
+        };
+        let body_len = read_body_len(*prefix)?;
+
+        let total = LENGTH_PREFIX_SIZE + body_len;
+        let Some(body) = buf.get(LENGTH_PREFIX_SIZE..total) else {
+            return Ok(None);
+        };
+
+        Ok(Some((decode_body(body)?, total)))
+    }
+}
+
+/// Incremental decoder for frames arriving in arbitrary chunks
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Create new decoder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append received bytes
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Pop the next complete frame, if any
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        match Frame::decode(&self.buf)? {
+            Some((frame, consumed)) => {
+                self.buf.drain(..consumed);
+                Ok(Some(frame))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Number of buffered bytes not yet decoded
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+/// Write a frame to an async stream
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &Frame) -> Result<()> {
+    let bytes = frame.encode()?;
+    writer.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Write a frame with its payload compressed at zstd `level`
+pub async fn write_frame_compressed<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    frame: &Frame,
+    level: i32,
+) -> Result<()> {
+    let bytes = frame.encode_compressed(level)?;
+    writer.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Read a frame from an async stream
+///
+/// Returns `Ok(None)` on a clean end of stream before any frame bytes.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Frame>> {
+    let mut prefix = [0u8; LENGTH_PREFIX_SIZE];
+    match reader.read_exact(&mut prefix).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    // Size is validated before allocating
+    let body_len = read_body_len(prefix)?;
+    let mut body = vec![0u8; body_len];
+    reader.read_exact(&mut body).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            ProtocolError::Truncated
+        } else {
+            e.into()
+        }
+    })?;
+
+    decode_body(&body).map(Some)
+}
+
+// Helper functions
+
+fn read_body_len(prefix: [u8; LENGTH_PREFIX_SIZE]) -> Result<usize> {
+    let body_len = u32::from_be_bytes(prefix) as usize;
+    let size = LENGTH_PREFIX_SIZE + body_len;
+    if size > MAX_FRAME_SIZE {
+        return Err(ProtocolError::FrameTooLarge {
+            size,
+            max: MAX_FRAME_SIZE,
+        });
+    }
+    if body_len < HEADER_SIZE {
+        return Err(ProtocolError::Truncated);
+    }
+    Ok(body_len)
+}
+
+fn decode_body(body: &[u8]) -> Result<Frame> {
+    let version = body[0];
+    if version != FRAME_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    let message_type = MessageType::try_from(body[1] & !(COMPRESSED_FLAG | SEQUENCED_FLAG))
+        .map_err(|_| ProtocolError::UnknownMessageType(body[1]))?;
+    let (sequence, payload) = if body[1] & SEQUENCED_FLAG == 0 {
+        (None, &body[HEADER_SIZE..])
+    } else {
+        let (sequence, payload) = body[HEADER_SIZE..]
+            .split_first_chunk::<SEQUENCE_SIZE>()
+            .ok_or(ProtocolError::Truncated)?;
+        (Some(u64::from_be_bytes(*sequence)), payload)
+    };
+    let payload = if body[1] & COMPRESSED_FLAG == 0 {
+        payload.to_vec()
+    } else {
+        // Bounded like uncompressed frames, so small frames cannot expand without limit
+        zstd::bulk::decompress(payload, MAX_FRAME_SIZE)
+            .map_err(|e| ProtocolError::Decompression(e.to_string()))?
+    };
+
+    Ok(Frame {
+        version,
+        message_type,
+        sequence,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TestMessage {
+        id: String,
+        count: u32,
+    }
+
+    #[test]
+    fn test_encode_decode_frame() {
+        let message = TestMessage {
+            id: "test-123".into(),
+            count: 7,
+        };
+        let frame = Frame::from_message(MessageType::SyncRequest, &message).unwrap();
+        let bytes = frame.encode().unwrap();
+
+        let (decoded, consumed) = Frame::decode(&bytes).unwrap().unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(decoded, frame);
+        assert_eq!(decoded.to_message::<TestMessage>().unwrap(), message);
+    }
+
+    #[test]
+    fn test_decode_partial_frame() {
+        let frame = Frame::new(MessageType::Ping, b"ping".to_vec());
+        let bytes = frame.encode().unwrap();
+
+        let mut decoder = FrameDecoder::new();
+        for byte in &bytes[..bytes.len() - 1] {
+            decoder.extend(std::slice::from_ref(byte));
+            assert!(decoder.next_frame().unwrap().is_none());
+        }
+        decoder.extend(&bytes[bytes.len() - 1..]);
+        assert_eq!(decoder.next_frame().unwrap().unwrap(), frame);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn test_reject_oversized_frame() {
+        let frame = Frame::new(MessageType::ChunkData, vec![0u8; MAX_FRAME_SIZE]);
+        assert!(matches!(
+            frame.encode(),
+            Err(ProtocolError::FrameTooLarge { .. })
+        ));
+
+        // Length prefix claiming more than the maximum is rejected before reading the body
+        let prefix = (MAX_FRAME_SIZE as u32).to_be_bytes();
+        assert!(matches!(
+            Frame::decode(&prefix),
+            Err(ProtocolError::FrameTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reject_unknown_type_and_version() {
+        let mut bytes = Frame::new(MessageType::Event, vec![]).encode().unwrap();
+        bytes[5] = 0xff;
+        assert!(matches!(
+            Frame::decode(&bytes),
+            Err(ProtocolError::UnknownMessageType(0xff))
+        ));
+
+        bytes[4] = 99;
+        assert!(matches!(
+            Frame::decode(&bytes),
+            Err(ProtocolError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn test_sequenced_frames() {
+        let frame = Frame::new(MessageType::SyncRequest, b"{}".to_vec()).with_sequence(42);
+        let bytes = frame.encode().unwrap();
+        assert_eq!(bytes.len(), frame.encoded_len());
+        assert_eq!(bytes[5], MessageType::SyncRequest.tag() | SEQUENCED_FLAG);
+        assert_eq!(Frame::decode(&bytes).unwrap().unwrap().0, frame);
+
+        let text = b"sync sync sync ".repeat(100);
+        let frame = Frame::new(MessageType::ChunkData, text).with_sequence(u64::MAX);
+        let bytes = frame.encode_compressed(3).unwrap();
+        assert_eq!(Frame::decode(&bytes).unwrap().unwrap().0, frame);
+
+        // A sequenced tag without room for the sequence number
+        let mut bytes = Frame::new(MessageType::Ping, vec![]).encode().unwrap();
+        bytes[5] |= SEQUENCED_FLAG;
+        assert!(matches!(
+            Frame::decode(&bytes),
+            Err(ProtocolError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_fuzz_decode_random_input() {
+        let mut rng = StdRng::seed_from_u64(0x6e6f6d616465);
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0..64);
+            let mut input = vec![0u8; len];
+            rng.fill_bytes(&mut input);
+
+            // Bias towards plausible headers to reach deeper decode paths
+            if len >= 6 && rng.gen_bool(0.5) {
+                input[..4].copy_from_slice(&((len - 4) as u32).to_be_bytes());
+                input[4] = FRAME_VERSION;
+            }
+
+            let _ = Frame::decode(&input);
+        }
+    }
+
+    #[test]
+    fn test_compressed_frames() {
+        let text = b"sync sync sync ".repeat(100);
+        let frame = Frame::new(MessageType::ChunkData, text.clone());
+        let bytes = frame.encode_compressed(3).unwrap();
+        assert!(bytes.len() < text.len() / 4);
+        assert_eq!(bytes[5], MessageType::ChunkData.tag() | COMPRESSED_FLAG);
+        assert_eq!(Frame::decode(&bytes).unwrap().unwrap().0, frame);
+
+        // Incompressible payloads go out as is
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut random = vec![0u8; 1024];
+        rng.fill_bytes(&mut random);
+        let frame = Frame::new(MessageType::ChunkData, random);
+        assert_eq!(frame.encode_compressed(3).unwrap(), frame.encode().unwrap());
+
+        // Payloads decompressing past the frame limit are rejected
+        let bomb = zstd::bulk::compress(&vec![0u8; MAX_FRAME_SIZE + 1], 3).unwrap();
+        let mut bytes = ((HEADER_SIZE + bomb.len()) as u32).to_be_bytes().to_vec();
+        bytes.extend([
+            FRAME_VERSION,
+            MessageType::ChunkData.tag() | COMPRESSED_FLAG,
+        ]);
+        bytes.extend(bomb);
+        assert!(matches!(
+            Frame::decode(&bytes),
+            Err(ProtocolError::Decompression(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_read_write_frame() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let frame = Frame::new(MessageType::Handshake, b"hello".to_vec());
+
+        write_frame(&mut client, &frame).await.unwrap();
+        drop(client);
+
+        assert_eq!(read_frame(&mut server).await.unwrap().unwrap(), frame);
+        assert!(read_frame(&mut server).await.unwrap().is_none());
+    }
+
+    fn message_type() -> impl Strategy<Value = MessageType> {
+        prop::sample::select(vec![
+            MessageType::Handshake,
+            MessageType::SyncRequest,
+            MessageType::ChunkData,
+            MessageType::Event,
+            MessageType::Ping,
+            MessageType::Pong,
+            MessageType::Revocation,
+            MessageType::Attestation,
+            MessageType::Wipe,
+            MessageType::Roster,
+        ])
+    }
+
+    proptest! {
+        #[test]
+        fn frame_roundtrip(
+            message_type in message_type(),
+            payload in prop::collection::vec(any::<u8>(), 0..512),
+            split in any::<prop::sample::Index>(),
+        ) {
+            let frame = Frame::new(message_type, payload);
+            let bytes = frame.encode().unwrap();
+            prop_assert_eq!(Frame::decode(&bytes).unwrap(), Some((frame.clone(), bytes.len())));
+
+            // Arbitrary delivery boundaries do not change the result
+            let (head, tail) = bytes.split_at(split.index(bytes.len()));
+            let mut decoder = FrameDecoder::new();
+            decoder.extend(head);
+            let early = decoder.next_frame().unwrap();
+            decoder.extend(tail);
+            let frame_out = early.or_else(|| decoder.next_frame().unwrap());
+            prop_assert_eq!(frame_out, Some(frame));
+        }
+
+        #[test]
+        fn frame_decode_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            let _ = Frame::decode(&bytes);
+            let mut decoder = FrameDecoder::new();
+            decoder.extend(&bytes);
+            while let Ok(Some(_)) = decoder.next_frame() {}
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Local endpoint gathering
+//!
+//! Builds the ordered endpoint list a device advertises in pairing offers
+//! and tries during connectivity checks: one direct endpoint per usable
+//! interface address (IPv4 and IPv6), plus optional server-reflexive
+//! addresses learned from STUN servers (RFC 8489 Binding requests).
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use nomade_crypto::Endpoint;
+use rand::RngCore;
+use tokio::net::UdpSocket;
+
+use crate::{ProtocolError, Result};
+
+/// STUN magic cookie
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const STUN_HEADER_LEN: usize = 20;
+
+/// What to include when gathering endpoints
+#[derive(Debug, Clone)]
+pub struct GatherConfig {
+    /// Advertise global and unique-local IPv6 addresses
+    pub include_ipv6: bool,
+    /// STUN servers (`host:port`) queried for reflexive addresses;
+    /// empty by default so nothing leaves the LAN unless configured
+    pub stun_servers: Vec<String>,
+    /// How long to wait for each STUN server
+    pub stun_timeout: Duration,
+}
+
+impl Default for GatherConfig {
+    fn default() -> Self {
+        Self {
+            include_ipv6: true,
+            stun_servers: Vec::new(),
+            stun_timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Gather this device's endpoints for `port`, most preferred first
+///
+/// Unreachable STUN servers are skipped. Reflexive addresses are reported
+/// with `port`, assuming the NAT preserves the QUIC socket's port.
+pub async fn gather_endpoints(port: u16, config: &GatherConfig) -> Result<Vec<Endpoint>> {
+    let mut endpoints = interface_endpoints(port, config.include_ipv6)?;
+    for server in &config.stun_servers {
+        match stun_query(server, config.stun_timeout).await {
+            Ok(reflexive) => {
+                if config.include_ipv6 || reflexive.is_ipv4() {
+                    endpoints.push(Endpoint::direct(SocketAddr::new(reflexive.ip(), port)));
+                }
+            }
+            Err(e) => tracing::debug!("STUN query to {} failed: {}", server, e),
+        }
+    }
+
+    let mut seen = HashSet::new();
+    endpoints.retain(|endpoint| seen.insert(endpoint.clone()));
+    Endpoint::sort_by_preference(&mut endpoints);
+    Ok(endpoints)
+}
+
+/// Direct endpoints for every usable interface address
+pub fn interface_endpoints(port: u16, include_ipv6: bool) -> Result<Vec<Endpoint>> {
+    Ok(if_addrs::get_if_addrs()?
+        .into_iter()
+        .map(|interface| interface.ip())
+        .filter(|ip| is_usable(ip) && (include_ipv6 || ip.is_ipv4()))
+        .map(|ip| Endpoint::direct(SocketAddr::new(ip, port)))
+        .collect())
+}
+
+/// Whether a peer on another host could dial this address
+///
+/// Loopback, unspecified, multicast and link-local addresses are skipped:
+/// IPv6 link-local needs a scope ID that is meaningless to the peer, and
+/// IPv4 link-local means the interface has no DHCP lease.
+fn is_usable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || ip.is_broadcast()
+                || ip.is_link_local())
+        }
+        IpAddr::V6(ip) => {
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (ip.segments()[0] & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Ask a STUN server which address our packets arrive from
+pub async fn stun_query(server: &str, timeout: Duration) -> Result<SocketAddr> {
+    let server_addr = tokio::net::lookup_host(server)
+        .await?
+        .next()
+        .ok_or_else(|| ProtocolError::Transport(format!("Cannot resolve {}", server)))?;
+    let local: SocketAddr = match server_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+
+    let mut transaction_id = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut transaction_id);
+    let mut request = Vec::with_capacity(STUN_HEADER_LEN);
+    request.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    request.extend_from_slice(&0u16.to_be_bytes());
+    request.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    request.extend_from_slice(&transaction_id);
+    socket.send_to(&request, server_addr).await?;
+
+    let mut buf = [0u8; 512];
+    tokio::time::timeout(timeout, async {
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await?;
+            if from != server_addr {
+                continue;
+            }
+            if let Some(addr) = parse_binding_response(&buf[..len], &transaction_id) {
+                return Ok(addr);
+            }
+        }
+    })
+    .await
+    .map_err(|_| ProtocolError::PeerTimeout)?
+}
+
+/// Mapped address from a Binding success response to our transaction
+fn parse_binding_response(buf: &[u8], transaction_id: &[u8; 12]) -> Option<SocketAddr> {
+    let header = buf.get(..STUN_HEADER_LEN)?;
+    let message_type = u16::from_be_bytes([header[0], header[1]]);
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    if message_type != BINDING_SUCCESS
+        || header[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || header[8..20] != transaction_id[..]
+    {
+        return None;
+    }
+
+    let mut attributes = buf.get(STUN_HEADER_LEN..STUN_HEADER_LEN + length)?;
+    let mut mapped = None;
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let len = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes.get(4..4 + len)?;
+        match kind {
+            ATTR_XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction_id)),
+            ATTR_MAPPED_ADDRESS => mapped = parse_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of four bytes
+        attributes = attributes
+            .get((4 + len).next_multiple_of(4)..)
+            .unwrap_or(&[]);
+    }
+    mapped
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS value; `xor` carries the transaction ID
+fn parse_address(value: &[u8], xor: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    let family = *value.get(1)?;
+    let mut port = u16::from_be_bytes([*value.get(2)?, *value.get(3)?]);
+    let mut mask = [0u8; 16];
+    if let Some(transaction_id) = xor {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(transaction_id);
+    }
+    let ip = match family {
+        0x01 => {
+            let bytes: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+            let bytes: [u8; 4] = std::array::from_fn(|i| bytes[i] ^ mask[i]);
+            IpAddr::V4(bytes.into())
+        }
+        0x02 => {
+            let bytes: [u8; 16] = value.get(4..20)?.try_into().ok()?;
+            let bytes: [u8; 16] = std::array::from_fn(|i| bytes[i] ^ mask[i]);
+            IpAddr::V6(bytes.into())
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal STUN server answering one Binding request
+    async fn stun_server() -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (_, from) = socket.recv_from(&mut buf).await.unwrap();
+            let SocketAddr::V4(from) = from else {
+                unreachable!()
+            };
+
+            let mut response = Vec::new();
+            response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+            response.extend_from_slice(&12u16.to_be_bytes());
+            response.extend_from_slice(&buf[4..20]);
+            response.extend_from_slice(&ATTR_XOR_MAPPED_ADDRESS.to_be_bytes());
+            response.extend_from_slice(&8u16.to_be_bytes());
+            response.extend_from_slice(&[0, 0x01]);
+            response.extend_from_slice(&(from.port() ^ (MAGIC_COOKIE >> 16) as u16).to_be_bytes());
+            response.extend_from_slice(&(u32::from(*from.ip()) ^ MAGIC_COOKIE).to_be_bytes());
+            socket.send_to(&response, from).await.unwrap();
+        });
+        (addr, task)
+    }
+
+    #[tokio::test]
+    async fn test_stun_query_returns_mapped_address() {
+        let (server, task) = stun_server().await;
+        let reflexive = stun_query(&server.to_string(), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(reflexive.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_ne!(reflexive.port(), 0);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gather_skips_unreachable_stun_servers() {
+        // Nothing listens on this socket once it is dropped
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = GatherConfig {
+            stun_servers: vec![silent.local_addr().unwrap().to_string()],
+            stun_timeout: Duration::from_millis(50),
+            ..GatherConfig::default()
+        };
+        drop(silent);
+
+        let endpoints = gather_endpoints(8765, &config).await.unwrap();
+        assert!(endpoints.iter().all(|endpoint| {
+            let addr = endpoint.socket_addr().unwrap();
+            addr.port() == 8765 && is_usable(&addr.ip())
+        }));
+        let preferences: Vec<u8> = endpoints.iter().map(Endpoint::preference).collect();
+        assert!(preferences.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn test_usable_addresses() {
+        for usable in ["192.168.1.10", "203.0.113.5", "fd00::1", "2001:db8::1"] {
+            assert!(is_usable(&usable.parse().unwrap()), "{}", usable);
+        }
+        for skipped in [
+            "127.0.0.1",
+            "0.0.0.0",
+            "169.254.3.4",
+            "::1",
+            "fe80::1",
+            "ff02::1",
+        ] {
+            assert!(!is_usable(&skipped.parse().unwrap()), "{}", skipped);
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_foreign_responses() {
+        let transaction_id = [7u8; 12];
+        let mut response = Vec::new();
+        response.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        response.extend_from_slice(&12u16.to_be_bytes());
+        response.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        response.extend_from_slice(&transaction_id);
+        response.extend_from_slice(&ATTR_MAPPED_ADDRESS.to_be_bytes());
+        response.extend_from_slice(&8u16.to_be_bytes());
+        response.extend_from_slice(&[0, 0x01, 0x1f, 0x90, 203, 0, 113, 5]);
+        assert_eq!(
+            parse_binding_response(&response, &transaction_id),
+            Some("203.0.113.5:8080".parse().unwrap())
+        );
+
+        assert_eq!(parse_binding_response(&response, &[8u8; 12]), None);
+        assert_eq!(
+            parse_binding_response(&response[..30], &transaction_id),
+            None
+        );
+        assert_eq!(parse_binding_response(&[0u8; 4], &transaction_id), None);
+    }
+}
@@ -0,0 +1,518 @@
+//! Keepalive, liveness detection and RTT metrics
+//!
+//! Each side sends `Ping` frames on its own dedicated uni stream and answers
+//! the peer's pings with `Pong` frames on the same outbound stream
+//! (`start_keepalive` sets both up on a connection). A peer that stays
+//! silent for longer than the configured timeout is considered dead and a
+//! `DeviceDisconnected` event is published. Noticeable changes in round-trip
+//! time or loss are published as `ConnectionQuality` events.
+//!
+//! Pings and pongs also carry wall-clock timestamps, from which each side
+//! estimates the peer's clock offset (see `clock`).
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nomade_events::{Event, EventStream};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+use crate::clock::{wall_clock_ms, ClockEstimator, ClockSample};
+use crate::frame::{write_frame, Frame, FrameDecoder, MessageType};
+use crate::timeout::TimeoutPhase;
+use crate::transport::Connection;
+use crate::{ProtocolError, Result};
+
+/// First byte of a keepalive stream, telling it apart from other uni streams
+pub const KEEPALIVE_STREAM_TAG: u8 = 0x4b;
+
+/// Keepalive configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveConfig {
+    /// Interval between pings
+    pub interval: Duration,
+    /// Silence after which the peer is considered dead
+    pub timeout: Duration,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+/// Ping message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ping {
+    pub seq: u64,
+    /// Wall-clock send time in milliseconds, 0 from older peers
+    #[serde(default)]
+    pub sent_at_ms: u64,
+}
+
+/// Pong message echoing the ping sequence number
+///
+/// The timestamps are 0 when the ping or the answering peer had none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pong {
+    pub seq: u64,
+    /// `sent_at_ms` of the ping
+    #[serde(default)]
+    pub origin_ms: u64,
+    /// Wall-clock time the ping arrived, in milliseconds
+    #[serde(default)]
+    pub received_at_ms: u64,
+    /// Wall-clock time the pong was sent, in milliseconds
+    #[serde(default)]
+    pub sent_at_ms: u64,
+}
+
+impl Pong {
+    /// Clock sample of the exchange, given the pong's arrival time
+    pub fn clock_sample(&self, arrived_at_ms: u64) -> Option<ClockSample> {
+        if self.origin_ms == 0 || self.received_at_ms == 0 || self.sent_at_ms == 0 {
+            return None;
+        }
+        Some(ClockSample::from_exchange(
+            self.origin_ms,
+            self.received_at_ms,
+            self.sent_at_ms,
+            arrived_at_ms,
+        ))
+    }
+}
+
+/// Per-connection quality statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Most recent round-trip time sample
+    pub latest_rtt: Option<Duration>,
+    /// Exponentially smoothed round-trip time
+    pub smoothed_rtt: Option<Duration>,
+    /// Lowest round-trip time observed
+    pub min_rtt: Option<Duration>,
+    /// Pings sent
+    pub pings_sent: u64,
+    /// Pongs received for our pings
+    pub pongs_received: u64,
+    /// Pings that timed out without a pong
+    pub pings_lost: u64,
+}
+
+impl ConnectionStats {
+    /// Fraction of resolved pings that were lost (0.0 - 1.0)
+    pub fn loss_rate(&self) -> f64 {
+        let resolved = self.pongs_received + self.pings_lost;
+        if resolved == 0 {
+            return 0.0;
+        }
+        self.pings_lost as f64 / resolved as f64
+    }
+
+    /// Whether these stats differ enough from `reported` to tell the UI:
+    /// the first round trip, any new loss or a smoothed RTT off by a quarter
+    fn changed_since(&self, reported: &ConnectionStats) -> bool {
+        if self.pings_lost != reported.pings_lost {
+            return true;
+        }
+        match (reported.smoothed_rtt, self.smoothed_rtt) {
+            (Some(before), Some(after)) => after.abs_diff(before) * 4 > before,
+            (before, after) => before != after,
+        }
+    }
+}
+
+/// Liveness state machine, independent of any I/O
+#[derive(Debug)]
+pub struct Liveness {
+    config: KeepaliveConfig,
+    next_seq: u64,
+    outstanding: VecDeque<(u64, Instant)>,
+    last_seen: Instant,
+    stats: ConnectionStats,
+}
+
+impl Liveness {
+    /// Create new liveness tracker
+    pub fn new(config: KeepaliveConfig, now: Instant) -> Self {
+        Self {
+            config,
+            next_seq: 0,
+            outstanding: VecDeque::new(),
+            last_seen: now,
+            stats: ConnectionStats::default(),
+        }
+    }
+
+    /// Record a ping being sent and return it
+    pub fn send_ping(&mut self, now: Instant) -> Ping {
+        self.expire_outstanding(now);
+
+        let ping = Ping {
+            seq: self.next_seq,
+            sent_at_ms: wall_clock_ms(),
+        };
+        self.next_seq += 1;
+        self.outstanding.push_back((ping.seq, now));
+        self.stats.pings_sent += 1;
+        ping
+    }
+
+    /// Record a pong received from the peer
+    pub fn on_pong(&mut self, pong: Pong, now: Instant) {
+        self.on_activity(now);
+
+        let Some(pos) = self
+            .outstanding
+            .iter()
+            .position(|(seq, _)| *seq == pong.seq)
+        else {
+            return; // Late or unknown pong
+        };
+        let (_, sent_at) = self.outstanding.remove(pos).expect("position is valid");
+        let rtt = now.saturating_duration_since(sent_at);
+
+        self.stats.pongs_received += 1;
+        self.stats.latest_rtt = Some(rtt);
+        self.stats.min_rtt = Some(self.stats.min_rtt.map_or(rtt, |min| min.min(rtt)));
+        // RFC 6298 style smoothing
+        self.stats.smoothed_rtt = Some(match self.stats.smoothed_rtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+
+    /// Record any inbound traffic from the peer
+    pub fn on_activity(&mut self, now: Instant) {
+        self.last_seen = now;
+    }
+
+    /// Whether the peer has been silent for longer than the timeout
+    pub fn is_dead(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_seen) > self.config.timeout
+    }
+
+    /// Current statistics
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    fn expire_outstanding(&mut self, now: Instant) {
+        while let Some((_, sent_at)) = self.outstanding.front() {
+            if now.saturating_duration_since(*sent_at) <= self.config.timeout {
+                break;
+            }
+            self.outstanding.pop_front();
+            self.stats.pings_lost += 1;
+        }
+    }
+}
+
+/// Handle to a running keepalive task
+pub struct KeepaliveHandle {
+    stats: Arc<Mutex<ConnectionStats>>,
+    clock: Arc<Mutex<ClockEstimator>>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl KeepaliveHandle {
+    /// Latest statistics for this connection
+    pub fn stats(&self) -> ConnectionStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// Best estimate of the peer's clock offset so far
+    pub fn clock_offset(&self) -> Option<ClockSample> {
+        self.clock.lock().unwrap().estimate()
+    }
+
+    /// Whether the keepalive task has stopped
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stop the keepalive task
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Wait for the keepalive task to stop
+    pub async fn join(&mut self) -> Result<()> {
+        (&mut self.task)
+            .await
+            .map_err(|e| ProtocolError::Io(std::io::Error::other(e)))?
+    }
+}
+
+impl Drop for KeepaliveHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Run keepalive with the peer at the other end of `connection`
+///
+/// Opens this side's keepalive stream and waits for the peer's; both sides
+/// have to call it.
+pub async fn start_keepalive(
+    connection: &dyn Connection,
+    device_id: String,
+    config: KeepaliveConfig,
+    events: EventStream,
+) -> Result<KeepaliveHandle> {
+    let mut writer = connection.open_uni().await?;
+    // Written right away: QUIC peers only see a stream once it has data
+    writer.write_all(&[KEEPALIVE_STREAM_TAG]).await?;
+    let mut reader = connection
+        .accept_uni()
+        .await?
+        .ok_or(ProtocolError::Truncated)?;
+    let tag = reader.read_u8().await?;
+    if tag != KEEPALIVE_STREAM_TAG {
+        return Err(ProtocolError::PeerRejected(format!(
+            "Expected a keepalive stream, got tag {}",
+            tag
+        )));
+    }
+    Ok(spawn_keepalive(device_id, reader, writer, config, events))
+}
+
+/// Spawn keepalive over a pair of uni streams (inbound, outbound)
+///
+/// The task ends with `ProtocolError::Timeout(TimeoutPhase::Idle)`
+/// after publishing `Event::DeviceDisconnected` once the peer is
+/// considered dead.
+pub fn spawn_keepalive<R, W>(
+    device_id: String,
+    reader: R,
+    writer: W,
+    config: KeepaliveConfig,
+    events: EventStream,
+) -> KeepaliveHandle
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let stats = Arc::new(Mutex::new(ConnectionStats::default()));
+    let clock = Arc::new(Mutex::new(ClockEstimator::new()));
+    let task = tokio::spawn(run_keepalive(
+        device_id,
+        reader,
+        writer,
+        config,
+        events,
+        stats.clone(),
+        clock.clone(),
+    ));
+    KeepaliveHandle { stats, clock, task }
+}
+
+async fn run_keepalive<R, W>(
+    device_id: String,
+    mut reader: R,
+    mut writer: W,
+    config: KeepaliveConfig,
+    events: EventStream,
+    stats: Arc<Mutex<ConnectionStats>>,
+    clock: Arc<Mutex<ClockEstimator>>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut liveness = Liveness::new(config, Instant::now());
+    let mut decoder = FrameDecoder::new();
+    let mut buf = [0u8; 1024];
+    let mut ticker = tokio::time::interval(config.interval);
+    let mut reported = ConnectionStats::default();
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let now = Instant::now();
+                if liveness.is_dead(now) {
+                    tracing::warn!("Peer {} timed out", device_id);
+                    events.publish(Event::DeviceDisconnected { device_id });
+                    return Err(ProtocolError::Timeout(TimeoutPhase::Idle));
+                }
+                let ping = liveness.send_ping(now);
+                write_frame(&mut writer, &Frame::from_message(MessageType::Ping, &ping)?).await?;
+            }
+            read = reader.read(&mut buf) => {
+                let n = read?;
+                if n == 0 {
+                    // The peer closed the connection; its owner reports that
+                    tracing::debug!("Keepalive stream from {} closed", device_id);
+                    return Ok(());
+                }
+                let arrived_at_ms = wall_clock_ms();
+                decoder.extend(&buf[..n]);
+                while let Some(frame) = decoder.next_frame()? {
+                    let now = Instant::now();
+                    match frame.message_type {
+                        MessageType::Ping => {
+                            liveness.on_activity(now);
+                            let ping: Ping = frame.to_message()?;
+                            let pong = Pong {
+                                seq: ping.seq,
+                                origin_ms: ping.sent_at_ms,
+                                received_at_ms: arrived_at_ms,
+                                sent_at_ms: wall_clock_ms(),
+                            };
+                            write_frame(&mut writer, &Frame::from_message(MessageType::Pong, &pong)?)
+                                .await?;
+                        }
+                        MessageType::Pong => {
+                            let pong: Pong = frame.to_message()?;
+                            if let Some(sample) = pong.clock_sample(arrived_at_ms) {
+                                clock.lock().unwrap().record(sample);
+                            }
+                            liveness.on_pong(pong, now);
+                        }
+                        other => return Err(ProtocolError::UnexpectedMessage(other)),
+                    }
+                }
+            }
+        }
+        let latest = liveness.stats();
+        *stats.lock().unwrap() = latest;
+        if latest.changed_since(&reported) {
+            reported = latest;
+            events.publish(Event::ConnectionQuality {
+                device_id: device_id.clone(),
+                rtt_ms: latest.smoothed_rtt.map(|rtt| rtt.as_millis() as u64),
+                loss_rate: latest.loss_rate(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> KeepaliveConfig {
+        KeepaliveConfig {
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(50),
+        }
+    }
+
+    #[test]
+    fn test_rtt_and_loss_stats() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(config(), start);
+
+        let ping = liveness.send_ping(start);
+        liveness.on_pong(
+            Pong {
+                seq: ping.seq,
+                ..Default::default()
+            },
+            start + Duration::from_millis(8),
+        );
+
+        let stats = liveness.stats();
+        assert_eq!(stats.latest_rtt, Some(Duration::from_millis(8)));
+        assert_eq!(stats.min_rtt, Some(Duration::from_millis(8)));
+
+        // Unanswered ping is counted as lost once it exceeds the timeout
+        liveness.send_ping(start + Duration::from_millis(10));
+        liveness.send_ping(start + Duration::from_millis(100));
+
+        let stats = liveness.stats();
+        assert_eq!(stats.pings_sent, 3);
+        assert_eq!(stats.pings_lost, 1);
+        assert_eq!(stats.loss_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_dead_peer_detection() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(config(), start);
+        assert!(!liveness.is_dead(start + Duration::from_millis(40)));
+
+        liveness.on_activity(start + Duration::from_millis(40));
+        assert!(!liveness.is_dead(start + Duration::from_millis(80)));
+        assert!(liveness.is_dead(start + Duration::from_millis(100)));
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_between_peers() {
+        let (a_out, b_in) = tokio::io::duplex(1024);
+        let (b_out, a_in) = tokio::io::duplex(1024);
+        let events = EventStream::new();
+
+        let a = spawn_keepalive("b".into(), a_in, a_out, config(), events.clone());
+        let b = spawn_keepalive("a".into(), b_in, b_out, config(), events.clone());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(a.stats().pongs_received > 0);
+        assert!(a.stats().smoothed_rtt.is_some());
+        // Same clock on both ends
+        assert!(a.clock_offset().unwrap().offset_ms.abs() < 1_000);
+        assert!(!a.is_finished());
+
+        a.abort();
+        b.abort();
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_streams_on_connection() {
+        use crate::transport::{MemoryNetwork, Transport};
+
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+        let events = EventStream::new();
+        let mut rx = events.subscribe();
+
+        let (a, b) = tokio::join!(
+            start_keepalive(dialed.as_ref(), "server".into(), config(), events.clone()),
+            start_keepalive(accepted.as_ref(), "client".into(), config(), events.clone()),
+        );
+        let (a, _b) = (a.unwrap(), b.unwrap());
+        // Nothing travels on bidirectional streams
+        let bi = tokio::time::timeout(Duration::from_millis(30), accepted.accept_bi()).await;
+        assert!(bi.is_err());
+
+        match rx.recv().await.unwrap() {
+            Event::ConnectionQuality {
+                rtt_ms, loss_rate, ..
+            } => {
+                assert!(rtt_ms.is_some());
+                assert_eq!(loss_rate, 0.0);
+            }
+            other => panic!("Wrong event type: {:?}", other),
+        }
+        assert!(a.stats().pongs_received > 0);
+        dialed.close();
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_triggers_disconnect() {
+        // Keep the peer's side open but never answer
+        let (a_out, _b_in) = tokio::io::duplex(1024);
+        let (_b_out, a_in) = tokio::io::duplex(1024);
+        let events = EventStream::new();
+        let mut rx = events.subscribe();
+
+        let mut a = spawn_keepalive("silent".into(), a_in, a_out, config(), events);
+        assert!(matches!(
+            a.join().await,
+            Err(ProtocolError::Timeout(TimeoutPhase::Idle))
+        ));
+
+        match rx.recv().await.unwrap() {
+            Event::DeviceDisconnected { device_id } => assert_eq!(device_id, "silent"),
+            other => panic!("Wrong event type: {:?}", other),
+        }
+    }
+}
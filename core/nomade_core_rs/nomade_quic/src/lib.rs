@@ -1,55 +1,112 @@
-//! QUIC client/server implementation
+//! QUIC transport and wire protocol
 //!
 //! Provides secure, multiplexed transport for device sync
 
-use std::net::SocketAddr;
+pub mod channel;
+pub mod clock;
+pub mod connection;
+pub mod cookie;
+pub mod dial;
+pub mod forward;
+pub mod frame;
+pub mod gather;
+pub mod keepalive;
+pub mod limits;
+pub mod negotiation;
+pub mod network;
+pub mod pairing;
+pub mod portmap;
+pub mod proxy;
+pub mod replay;
+pub mod retry;
+pub mod seal;
+pub mod timeout;
+pub mod transport;
 
-/// QUIC server skeleton
-pub struct QuicServer {
-    addr: SocketAddr,
-}
+pub use channel::{Channel, ChannelId, ChannelRouter};
+pub use clock::{ClockEstimator, ClockSample};
+pub use connection::{ConnectionManager, ConnectionQueues, Direction};
+pub use cookie::{CookieIssuer, PairingPuzzle, PuzzleSolution};
+pub use dial::{Dialer, EndpointStats};
+pub use forward::{forward_events, receive_events};
+pub use frame::{Frame, FrameDecoder, MessageType};
+pub use gather::{gather_endpoints, GatherConfig};
+pub use keepalive::{start_keepalive, ConnectionStats, KeepaliveConfig, KeepaliveHandle};
+pub use limits::{ConnectionGuard, PeerFilter, RateLimits, RejectReason};
+pub use negotiation::{exchange_hello, negotiate, FeatureFlags, Handshake, Hello, Negotiated};
+pub use network::{NetworkKind, NetworkMonitor, NetworkState};
+pub use portmap::{PortMapConfig, PortMapper, PortMapping};
+pub use proxy::{ProxyConfig, ProxyKind};
+pub use replay::{ReplayGuard, ReplayStore, ReplayWindow, SessionCounters, SessionHello};
+pub use retry::{RetryPolicy, Retryable};
+pub use seal::FrameSeal;
+pub use timeout::{TimeoutPhase, Timeouts};
+pub use transport::{
+    bind_first_free, Connection, FallbackTransport, MemoryNetwork, MemoryTransport, QuicTransport,
+    Transport, WebSocketTransport,
+};
 
-impl QuicServer {
-    /// Create new QUIC server
-    pub fn new(addr: SocketAddr) -> Self {
-        Self { addr }
-    }
-
-    /// Start listening (skeleton implementation)
-    pub async fn listen(&self) -> anyhow::Result<()> {
-        tracing::info!("QUIC server listening on {}", self.addr);
-        // TODO: Implement actual QUIC server with quinn
-        Ok(())
-    }
-}
+/// Common error type for protocol operations
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("Frame too large: {size} bytes (max {max})")]
+    FrameTooLarge { size: usize, max: usize },
 
-/// QUIC client skeleton
-pub struct QuicClient {
-    server_addr: SocketAddr,
-}
+    #[error("Unsupported frame version: {0}")]
+    UnsupportedVersion(u8),
 
-impl QuicClient {
-    /// Create new QUIC client
-    pub fn new(server_addr: SocketAddr) -> Self {
-        Self { server_addr }
-    }
-
-    /// Connect to server (skeleton implementation)
-    pub async fn connect(&self) -> anyhow::Result<()> {
-        tracing::info!("QUIC client connecting to {}", self.server_addr);
-        // TODO: Implement actual QUIC client with quinn
-        Ok(())
-    }
-}
+    #[error("Unknown message type: {0}")]
+    UnknownMessageType(u8),
+
+    #[error("Unknown channel: {0}")]
+    UnknownChannel(u8),
+
+    #[error("Truncated frame")]
+    Truncated,
+
+    #[error("Replayed frame: sequence {0}")]
+    Replayed(u64),
+
+    #[error("Bad compressed payload: {0}")]
+    Decompression(String),
+
+    #[error("Bad sealed payload: {0}")]
+    BadSeal(String),
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[error("Unexpected message: {0:?}")]
+    UnexpectedMessage(MessageType),
 
-    #[test]
-    fn test_quic_server_creation() {
-        let addr: SocketAddr = "127.0.0.1:8765".parse().unwrap();
-        let server = QuicServer::new(addr);
-        assert_eq!(server.addr, addr);
-    }
+    #[error(
+        "Incompatible peer: local versions {local_versions:?}, remote versions {remote_versions:?}"
+    )]
+    IncompatiblePeer {
+        local_versions: Vec<u16>,
+        remote_versions: Vec<u16>,
+    },
+
+    #[error("Peer timed out")]
+    PeerTimeout,
+
+    #[error("Timed out during {0}")]
+    Timeout(timeout::TimeoutPhase),
+
+    #[error("Peer rejected: {0}")]
+    PeerRejected(String),
+
+    #[error("Duplicate connection to {0}")]
+    DuplicateConnection(String),
+
+    #[error("Peer not connected: {0}")]
+    NotConnected(String),
+
+    #[error("Transport error: {0}")]
+    Transport(String),
+
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
+
+pub type Result<T> = std::result::Result<T, ProtocolError>;
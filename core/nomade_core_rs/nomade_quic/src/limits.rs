@@ -0,0 +1,418 @@
+//! Rate limits and abuse protection for incoming connections
+//!
+//! A `ConnectionGuard` shields the listener from misbehaving peers:
+//!
+//! - QUIC handshakes from unvalidated addresses get a stateless retry, so
+//!   spoofed sources cost nothing and are never counted
+//! - handshakes are rate limited per source address and globally, and
+//!   refused before any TLS work is done
+//! - in paired-only mode, devices the filter does not know are rejected
+//!   during the TLS handshake, before a connection exists
+//! - connections are capped, as are concurrent streams per connection
+//! - bytes read from each connection are throttled
+//! - devices asking to pair must first solve a puzzle (see `cookie`)
+//!
+//! Every rejection is counted in metrics and published as
+//! `Event::ConnectionRejected`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use nomade_crypto::DeviceId;
+use nomade_events::{Event, EventStream};
+use nomade_metrics::names;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::cookie::{CookieIssuer, DEFAULT_PUZZLE_BITS};
+use crate::transport::RecvStream;
+use crate::{ProtocolError, Result};
+
+/// Source buckets kept before idle ones are dropped
+const MAX_TRACKED_SOURCES: usize = 1024;
+
+/// Limits applied to incoming connections; zero disables a limit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    /// Handshakes a single source address may start per minute
+    pub handshakes_per_minute: u32,
+    /// Handshakes accepted per minute across all sources
+    pub global_handshakes_per_minute: u32,
+    /// Simultaneously connected peers
+    pub max_connections: usize,
+    /// Concurrent streams a peer may open on one connection
+    pub max_concurrent_streams: u32,
+    /// Bytes per second read from one connection
+    pub bytes_per_second: u64,
+    /// Reject devices that are not paired before completing the handshake
+    pub paired_only: bool,
+    /// Validate source addresses with a QUIC retry before any handshake work
+    pub stateless_retry: bool,
+    /// Leading zero bits of the puzzle a device must solve to pair
+    pub pairing_puzzle_bits: u8,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            handshakes_per_minute: 30,
+            global_handshakes_per_minute: 300,
+            max_connections: 64,
+            max_concurrent_streams: 100,
+            bytes_per_second: 0,
+            paired_only: true,
+            stateless_retry: true,
+            pairing_puzzle_bits: DEFAULT_PUZZLE_BITS,
+        }
+    }
+}
+
+/// Why an incoming connection was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    HandshakeRate,
+    GlobalHandshakeRate,
+    ConnectionLimit,
+    UnknownDevice,
+    PairingPuzzle,
+}
+
+impl RejectReason {
+    /// Name used in events
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::HandshakeRate => "handshake_rate",
+            Self::GlobalHandshakeRate => "global_handshake_rate",
+            Self::ConnectionLimit => "connection_limit",
+            Self::UnknownDevice => "unknown_device",
+            Self::PairingPuzzle => "pairing_puzzle",
+        }
+    }
+}
+
+/// Token bucket allowing short bursts up to its capacity
+///
+/// Tokens may go negative: a large read is let through and paid back
+/// before the next one.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(capacity: f64, per_second: f64) -> Self {
+        Self {
+            capacity,
+            per_second,
+            tokens: capacity,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn per_minute(count: u32) -> Self {
+        Self::new(count as f64, count as f64 / 60.0)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.refilled = now;
+    }
+
+    /// Take one token if available
+    fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.refill();
+        self.tokens -= amount as f64;
+    }
+
+    /// Time until tokens are available again
+    fn wait_time(&mut self) -> Duration {
+        self.refill();
+        if self.tokens > 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.per_second)
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+/// Whether a device may connect in paired-only mode
+pub type PeerFilter = Arc<dyn Fn(&DeviceId) -> bool + Send + Sync>;
+
+/// Enforces `RateLimits` on incoming connections
+pub struct ConnectionGuard {
+    limits: RateLimits,
+    filter: Option<PeerFilter>,
+    events: Option<EventStream>,
+    global: Mutex<TokenBucket>,
+    sources: Mutex<HashMap<String, TokenBucket>>,
+    cookies: CookieIssuer,
+}
+
+impl ConnectionGuard {
+    /// Create guard enforcing `limits`
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            global: Mutex::new(TokenBucket::per_minute(limits.global_handshakes_per_minute)),
+            cookies: CookieIssuer::new(limits.pairing_puzzle_bits),
+            limits,
+            filter: None,
+            events: None,
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Devices accepted in paired-only mode, normally the trust store
+    pub fn with_filter(mut self, filter: PeerFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Publish `ConnectionRejected` events
+    pub fn with_events(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Limits being enforced
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Puzzles for devices asking to pair (`pairing::pair_over`)
+    pub fn cookies(&self) -> &CookieIssuer {
+        &self.cookies
+    }
+
+    /// Account for a handshake from `source`, rejecting it over the limits
+    pub fn check_handshake(&self, source: &str) -> Result<()> {
+        if self.limits.global_handshakes_per_minute > 0 && !self.global.lock().unwrap().try_take() {
+            return Err(self.reject(source, RejectReason::GlobalHandshakeRate));
+        }
+        if self.limits.handshakes_per_minute > 0 {
+            let mut sources = self.sources.lock().unwrap();
+            if sources.len() >= MAX_TRACKED_SOURCES {
+                sources.retain(|_, bucket| !bucket.is_full());
+            }
+            let allowed = sources
+                .entry(source.to_string())
+                .or_insert_with(|| TokenBucket::per_minute(self.limits.handshakes_per_minute))
+                .try_take();
+            if !allowed {
+                return Err(self.reject(source, RejectReason::HandshakeRate));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `device_id` may connect; always true unless paired-only
+    pub fn allows_device(&self, device_id: &DeviceId) -> bool {
+        let allowed = match &self.filter {
+            Some(filter) if self.limits.paired_only => filter(device_id),
+            _ => true,
+        };
+        if !allowed {
+            self.reject(&device_id.to_string(), RejectReason::UnknownDevice);
+        }
+        allowed
+    }
+
+    /// Check the connection cap, given how many peers are connected
+    pub fn check_capacity(&self, device_id: &DeviceId, connected: usize) -> Result<()> {
+        if self.limits.max_connections > 0 && connected >= self.limits.max_connections {
+            return Err(self.reject(&device_id.to_string(), RejectReason::ConnectionLimit));
+        }
+        Ok(())
+    }
+
+    /// Record a rejection
+    pub fn reject(&self, source: &str, reason: RejectReason) -> ProtocolError {
+        tracing::debug!("Rejected connection from {}: {}", source, reason.as_str());
+        let metrics = nomade_metrics::global();
+        metrics
+            .counter(
+                names::CONNECTION_FAILURES,
+                "Rejected or failed connection attempts",
+            )
+            .inc();
+        metrics
+            .counter(
+                names::CONNECTIONS_REJECTED,
+                "Connection attempts rejected by rate limits or admission",
+            )
+            .inc();
+        if let Some(events) = &self.events {
+            events.publish(Event::ConnectionRejected {
+                source: source.to_string(),
+                reason: reason.as_str().to_string(),
+            });
+        }
+        ProtocolError::PeerRejected(format!("{} ({})", source, reason.as_str()))
+    }
+
+    /// Transport settings applying the stream limit
+    pub(crate) fn transport_config(&self) -> quinn::TransportConfig {
+        let mut config = quinn::TransportConfig::default();
+        if self.limits.max_concurrent_streams > 0 {
+            config.max_concurrent_bidi_streams(self.limits.max_concurrent_streams.into());
+        }
+        config
+    }
+
+    /// Byte budget for a new connection, if throttling is on
+    pub(crate) fn connection_budget(&self) -> Option<Arc<Mutex<TokenBucket>>> {
+        let rate = self.limits.bytes_per_second as f64;
+        (rate > 0.0).then(|| Arc::new(Mutex::new(TokenBucket::new(rate, rate))))
+    }
+}
+
+/// Throttle a stream of a connection to the connection's byte budget
+pub(crate) fn throttled(budget: &Option<Arc<Mutex<TokenBucket>>>, recv: RecvStream) -> RecvStream {
+    match budget {
+        Some(bucket) => Box::new(Throttled {
+            inner: recv,
+            bucket: bucket.clone(),
+            delay: None,
+        }),
+        None => recv,
+    }
+}
+
+impl std::fmt::Debug for ConnectionGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionGuard")
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stream reading no faster than its bucket allows
+struct Throttled {
+    inner: RecvStream,
+    bucket: Arc<Mutex<TokenBucket>>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncRead for Throttled {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+            }
+            let wait = self.bucket.lock().unwrap().wait_time();
+            if wait.is_zero() {
+                break;
+            }
+            self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let read = buf.filled().len() - before;
+        self.bucket.lock().unwrap().consume(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::generate_keypair;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handshake_limits() {
+        let events = EventStream::new();
+        let mut rejected = events.subscribe();
+        let guard = ConnectionGuard::new(RateLimits {
+            handshakes_per_minute: 2,
+            global_handshakes_per_minute: 3,
+            ..Default::default()
+        })
+        .with_events(events);
+
+        assert!(guard.check_handshake("10.0.0.1").is_ok());
+        assert!(guard.check_handshake("10.0.0.1").is_ok());
+        assert!(guard.check_handshake("10.0.0.1").is_err());
+        assert!(matches!(
+            rejected.recv().await.unwrap(),
+            Event::ConnectionRejected { reason, .. } if reason == "handshake_rate"
+        ));
+        // The flood used up the global budget too
+        assert!(guard.check_handshake("10.0.0.2").is_err());
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert!(guard.check_handshake("10.0.0.1").is_ok());
+    }
+
+    #[test]
+    fn test_paired_only_and_capacity() {
+        let paired = generate_keypair().device_id().clone();
+        let stranger = generate_keypair().device_id().clone();
+        let limits = RateLimits {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let allowed = paired.clone();
+        let guard = ConnectionGuard::new(limits.clone())
+            .with_filter(Arc::new(move |device_id| *device_id == allowed));
+        assert!(guard.allows_device(&paired));
+        assert!(!guard.allows_device(&stranger));
+        assert!(guard.check_capacity(&paired, 0).is_ok());
+        assert!(guard.check_capacity(&paired, 1).is_err());
+
+        let open = ConnectionGuard::new(RateLimits {
+            paired_only: false,
+            ..limits
+        })
+        .with_filter(Arc::new(|_| false));
+        assert!(open.allows_device(&stranger));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_throttles_reads() {
+        let guard = ConnectionGuard::new(RateLimits {
+            bytes_per_second: 1000,
+            ..Default::default()
+        });
+        let budget = guard.connection_budget();
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let mut recv = throttled(&budget, Box::new(server));
+        client.write_all(&[0u8; 3000]).await.unwrap();
+        drop(client);
+
+        let start = Instant::now();
+        let mut received = Vec::new();
+        recv.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received.len(), 3000);
+        // One second of burst, then the rest at the configured rate
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,337 @@
+//! Protocol version and feature negotiation
+//!
+//! Right after a connection is established both sides exchange a `Hello`
+//! handshake frame listing the protocol versions and features they support.
+//! The highest common version is selected and only features supported by
+//! both peers are enabled.
+//!
+//! On a connection the hellos travel on the first channel: the dialer
+//! opens a `Control` channel and speaks first, the acceptor answers on it
+//! (see `exchange_hello`).
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::channel::{Channel, ChannelId};
+use crate::connection::Direction;
+use crate::frame::{read_frame, write_frame, Frame, MessageType};
+use crate::replay::SessionHello;
+use crate::transport::Connection;
+use crate::{ProtocolError, Result};
+
+/// Current protocol version
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Oldest protocol version still supported
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+bitflags::bitflags! {
+    /// Optional protocol features
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct FeatureFlags: u32 {
+        /// Content-defined chunked artifact transfer
+        const CHUNKED_SYNC = 1 << 0;
+        /// CRDT-based collaborative text artifacts
+        const CRDT_TEXT = 1 << 1;
+        /// Connections relayed through a relay server
+        const RELAY = 1 << 2;
+        /// Sequence numbers on session frames, checked against replays
+        const SEQUENCED_FRAMES = 1 << 3;
+        /// Keepalive pings and pongs on the channel the hellos used
+        const KEEPALIVE = 1 << 4;
+        /// Sync and event payloads sealed and signed by the sending device
+        const SIGNED_FRAMES = 1 << 5;
+    }
+}
+
+/// Handshake message announcing local capabilities
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Supported protocol versions
+    pub versions: Vec<u16>,
+    /// Raw feature bits (unknown bits from newer peers are ignored)
+    pub features: u32,
+    /// Replay counters of the sender, with `SEQUENCED_FRAMES`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<SessionHello>,
+}
+
+impl Hello {
+    /// Create hello message
+    pub fn new(versions: Vec<u16>, features: FeatureFlags) -> Self {
+        Self {
+            versions,
+            features: features.bits(),
+            session: None,
+        }
+    }
+
+    /// Announce the state of the replay counters shared with the peer
+    pub fn with_session(mut self, session: SessionHello) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Known features announced in this hello
+    pub fn feature_flags(&self) -> FeatureFlags {
+        FeatureFlags::from_bits_truncate(self.features)
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self::new(
+            (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).collect(),
+            FeatureFlags::all(),
+        )
+    }
+}
+
+/// Outcome of a successful negotiation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u16,
+    pub features: FeatureFlags,
+}
+
+/// Select highest common version and shared features
+pub fn negotiate(local: &Hello, remote: &Hello) -> Result<Negotiated> {
+    let version = local
+        .versions
+        .iter()
+        .filter(|v| remote.versions.contains(v))
+        .max()
+        .copied()
+        .ok_or_else(|| ProtocolError::IncompatiblePeer {
+            local_versions: local.versions.clone(),
+            remote_versions: remote.versions.clone(),
+        })?;
+
+    Ok(Negotiated {
+        version,
+        features: local.feature_flags() & remote.feature_flags(),
+    })
+}
+
+/// Exchange hello messages over a stream and negotiate
+pub async fn perform_negotiation<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    local: &Hello,
+) -> Result<Negotiated>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let frame = Frame::from_message(MessageType::Handshake, local)?;
+    write_frame(writer, &frame).await?;
+
+    let frame = read_frame(reader).await?.ok_or(ProtocolError::Truncated)?;
+    if frame.message_type != MessageType::Handshake {
+        return Err(ProtocolError::UnexpectedMessage(frame.message_type));
+    }
+    let remote: Hello = frame.to_message()?;
+
+    let negotiated = negotiate(local, &remote)?;
+    tracing::debug!(
+        "Negotiated protocol v{} with features {:?}",
+        negotiated.version,
+        negotiated.features
+    );
+    Ok(negotiated)
+}
+
+/// Hello exchange on a new connection
+pub struct Handshake {
+    /// Hello the peer sent
+    pub remote: Hello,
+    pub negotiated: Negotiated,
+    /// Channel the hellos travelled on, still open
+    pub channel: Channel,
+}
+
+/// Exchange hellos on a freshly established connection and negotiate
+///
+/// The acceptor takes the hello from the first channel the peer opens, so
+/// it must call this before routing the connection's channels. It answers
+/// even when the versions are incompatible, so both sides learn why the
+/// connection fails.
+pub async fn exchange_hello(
+    connection: &dyn Connection,
+    direction: Direction,
+    local: &Hello,
+) -> Result<Handshake> {
+    let hello = Frame::from_message(MessageType::Handshake, local)?;
+    let (channel, remote) = match direction {
+        Direction::Outbound => {
+            let mut channel = Channel::open(connection, ChannelId::Control).await?;
+            channel.send(&hello).await?;
+            let remote = recv_hello(&mut channel).await?;
+            (channel, remote)
+        }
+        Direction::Inbound => {
+            let mut channel = Channel::accept(connection)
+                .await?
+                .ok_or(ProtocolError::Truncated)?;
+            if channel.id() != ChannelId::Control {
+                return Err(ProtocolError::PeerRejected(format!(
+                    "Expected a hello, got a {:?} channel",
+                    channel.id()
+                )));
+            }
+            let remote = recv_hello(&mut channel).await?;
+            channel.send(&hello).await?;
+            (channel, remote)
+        }
+    };
+
+    let negotiated = negotiate(local, &remote)?;
+    tracing::debug!(
+        "Negotiated protocol v{} with features {:?} with {}",
+        negotiated.version,
+        negotiated.features,
+        connection.remote_addr()
+    );
+    Ok(Handshake {
+        remote,
+        negotiated,
+        channel,
+    })
+}
+
+async fn recv_hello(channel: &mut Channel) -> Result<Hello> {
+    let frame = channel.recv().await?.ok_or(ProtocolError::Truncated)?;
+    if frame.message_type != MessageType::Handshake {
+        return Err(ProtocolError::UnexpectedMessage(frame.message_type));
+    }
+    frame.to_message()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_highest_common_version() {
+        let local = Hello::new(vec![1, 2, 3], FeatureFlags::all());
+        let remote = Hello::new(
+            vec![2, 3, 4],
+            FeatureFlags::CHUNKED_SYNC | FeatureFlags::RELAY,
+        );
+
+        let negotiated = negotiate(&local, &remote).unwrap();
+        assert_eq!(negotiated.version, 3);
+        assert_eq!(
+            negotiated.features,
+            FeatureFlags::CHUNKED_SYNC | FeatureFlags::RELAY
+        );
+    }
+
+    #[test]
+    fn test_negotiate_incompatible_peer() {
+        let local = Hello::new(vec![1], FeatureFlags::empty());
+        let remote = Hello::new(vec![2, 3], FeatureFlags::empty());
+
+        match negotiate(&local, &remote) {
+            Err(ProtocolError::IncompatiblePeer {
+                local_versions,
+                remote_versions,
+            }) => {
+                assert_eq!(local_versions, vec![1]);
+                assert_eq!(remote_versions, vec![2, 3]);
+            }
+            other => panic!("Expected IncompatiblePeer, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_feature_bits_ignored() {
+        let remote = Hello {
+            versions: vec![PROTOCOL_VERSION],
+            features: FeatureFlags::CRDT_TEXT.bits() | 1 << 31,
+            session: None,
+        };
+
+        let negotiated = negotiate(&Hello::default(), &remote).unwrap();
+        assert_eq!(negotiated.features, FeatureFlags::CRDT_TEXT);
+    }
+
+    #[tokio::test]
+    async fn test_perform_negotiation() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (mut client_rx, mut client_tx) = tokio::io::split(client);
+        let (mut server_rx, mut server_tx) = tokio::io::split(server);
+
+        let server_hello = Hello::new(vec![1], FeatureFlags::RELAY);
+        let server = tokio::spawn(async move {
+            perform_negotiation(&mut server_rx, &mut server_tx, &server_hello).await
+        });
+
+        let client = perform_negotiation(&mut client_rx, &mut client_tx, &Hello::default())
+            .await
+            .unwrap();
+        let server = server.await.unwrap().unwrap();
+
+        assert_eq!(client, server);
+        assert_eq!(client.features, FeatureFlags::RELAY);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_hello_on_connection() {
+        use crate::transport::{MemoryNetwork, Transport};
+
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+
+        let server_hello = Hello::new(vec![1], FeatureFlags::CHUNKED_SYNC);
+        let server = tokio::spawn(async move {
+            exchange_hello(accepted.as_ref(), Direction::Inbound, &server_hello).await
+        });
+        let mut client = exchange_hello(dialed.as_ref(), Direction::Outbound, &Hello::default())
+            .await
+            .unwrap();
+        let mut server = server.await.unwrap().unwrap();
+        assert_eq!(client.negotiated, server.negotiated);
+        assert_eq!(client.negotiated.features, FeatureFlags::CHUNKED_SYNC);
+        assert_eq!(server.remote, Hello::default());
+
+        // The channel stays usable after the exchange
+        client
+            .channel
+            .send(&Frame::new(MessageType::Ping, vec![1]))
+            .await
+            .unwrap();
+        let ping = server.channel.recv().await.unwrap().unwrap();
+        assert_eq!(ping.message_type, MessageType::Ping);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_hello_incompatible() {
+        use crate::transport::{MemoryNetwork, Transport};
+
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+
+        let server = tokio::spawn(async move {
+            let hello = Hello::new(vec![2], FeatureFlags::empty());
+            exchange_hello(accepted.as_ref(), Direction::Inbound, &hello)
+                .await
+                .err()
+        });
+        let client = exchange_hello(dialed.as_ref(), Direction::Outbound, &Hello::default()).await;
+        assert!(matches!(
+            client,
+            Err(ProtocolError::IncompatiblePeer { .. })
+        ));
+        assert!(matches!(
+            server.await.unwrap(),
+            Some(ProtocolError::IncompatiblePeer { .. })
+        ));
+    }
+}
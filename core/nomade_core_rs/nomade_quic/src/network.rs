@@ -0,0 +1,226 @@
+//! Network condition of this device
+//!
+//! The platform knows best what kind of network the device is on, and
+//! reports it through `NetworkMonitor::report` (wired to
+//! `ffi_set_network_state`). Probing the local interfaces complements it:
+//! a device without any usable address is offline whatever was reported
+//! last, e.g. when the app missed the platform's notification while
+//! suspended.
+//!
+//! The combined state is published on a watch channel for the connection
+//! manager and sync policies, and as `Event::NetworkStateChanged`.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nomade_events::{Event, EventStream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::gather::interface_endpoints;
+
+/// Kind of network the device is on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkKind {
+    /// Nothing reported and nothing known
+    #[default]
+    Unknown,
+    Wifi,
+    Ethernet,
+    Cellular,
+    Offline,
+}
+
+impl NetworkKind {
+    /// Name used in events
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Wifi => "wifi",
+            Self::Ethernet => "ethernet",
+            Self::Cellular => "cellular",
+            Self::Offline => "offline",
+        }
+    }
+}
+
+/// Connectivity of the device
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkState {
+    pub kind: NetworkKind,
+    /// Traffic is billed or capped, e.g. a hotspot or roaming
+    #[serde(default)]
+    pub metered: bool,
+}
+
+impl NetworkState {
+    /// State of a device without connectivity
+    pub const OFFLINE: Self = Self {
+        kind: NetworkKind::Offline,
+        metered: false,
+    };
+
+    /// Whether peers may be reachable
+    pub fn is_online(&self) -> bool {
+        self.kind != NetworkKind::Offline
+    }
+
+    /// Whether traffic costs the user; cellular always counts as metered
+    pub fn is_metered(&self) -> bool {
+        self.metered || self.kind == NetworkKind::Cellular
+    }
+}
+
+/// Combines platform reports and interface probes into one state
+pub struct NetworkMonitor {
+    /// Last state the platform reported
+    reported: Mutex<Option<NetworkState>>,
+    /// Whether the last probe found a usable interface
+    probed: Mutex<Option<bool>>,
+    state: watch::Sender<NetworkState>,
+    events: Option<EventStream>,
+}
+
+impl Default for NetworkMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkMonitor {
+    /// Create a monitor in the `Unknown` state
+    pub fn new() -> Self {
+        Self {
+            reported: Mutex::new(None),
+            probed: Mutex::new(None),
+            state: watch::Sender::new(NetworkState::default()),
+            events: None,
+        }
+    }
+
+    /// Publish `NetworkStateChanged` events
+    pub fn with_events(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Current combined state
+    pub fn state(&self) -> NetworkState {
+        *self.state.borrow()
+    }
+
+    /// Receiver notified whenever the state changes
+    pub fn subscribe(&self) -> watch::Receiver<NetworkState> {
+        self.state.subscribe()
+    }
+
+    /// Record the state the platform reported
+    pub fn report(&self, state: NetworkState) {
+        *self.reported.lock().unwrap() = Some(state);
+        self.update();
+    }
+
+    /// Record whether a probe found a usable interface
+    pub fn record_probe(&self, online: bool) {
+        *self.probed.lock().unwrap() = Some(online);
+        self.update();
+    }
+
+    /// Probe the interfaces once
+    pub fn probe(&self) {
+        let online = match interface_endpoints(0, true) {
+            Ok(endpoints) => !endpoints.is_empty(),
+            Err(e) => {
+                tracing::debug!("Cannot list interfaces: {}", e);
+                return;
+            }
+        };
+        self.record_probe(online);
+    }
+
+    /// Probe every `interval` until `cancel` fires
+    pub async fn run(self: Arc<Self>, interval: Duration, cancel: CancellationToken) {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = ticks.tick() => self.probe(),
+            }
+        }
+    }
+
+    fn update(&self) {
+        let reported = *self.reported.lock().unwrap();
+        let probed = *self.probed.lock().unwrap();
+        let state = match (reported, probed) {
+            (_, Some(false)) => NetworkState::OFFLINE,
+            (Some(reported), _) => reported,
+            // Interfaces came back without a report: online, kind unknown
+            (None, _) => NetworkState::default(),
+        };
+        if !self.state.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        }) {
+            return;
+        }
+        tracing::info!(
+            "Network is now {}{}",
+            state.kind.as_str(),
+            if state.metered { " (metered)" } else { "" }
+        );
+        if let Some(events) = &self.events {
+            events.publish(Event::NetworkStateChanged {
+                kind: state.kind.as_str().to_string(),
+                metered: state.metered,
+            });
+        }
+    }
+}
+
+impl std::fmt::Debug for NetworkMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NetworkMonitor")
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_probes_override_stale_reports() {
+        let events = EventStream::new();
+        let mut changes = events.subscribe();
+        let monitor = NetworkMonitor::new().with_events(events);
+        let mut state = monitor.subscribe();
+        assert_eq!(monitor.state().kind, NetworkKind::Unknown);
+
+        let cellular: NetworkState = serde_json::from_str(r#"{"kind": "cellular"}"#).unwrap();
+        assert!(cellular.is_metered() && cellular.is_online());
+        monitor.report(cellular);
+        monitor.report(cellular);
+        assert!(state.has_changed().unwrap());
+        assert_eq!(*state.borrow_and_update(), cellular);
+        assert!(matches!(
+            changes.recv().await.unwrap(),
+            Event::NetworkStateChanged { kind, metered: false } if kind == "cellular"
+        ));
+
+        // No usable interface: offline, whatever was reported
+        monitor.record_probe(false);
+        assert_eq!(monitor.state(), NetworkState::OFFLINE);
+        monitor.record_probe(true);
+        assert_eq!(monitor.state(), cellular);
+        assert_eq!(
+            std::iter::from_fn(|| changes.try_recv().ok()).count(),
+            2,
+            "one event per change"
+        );
+    }
+}
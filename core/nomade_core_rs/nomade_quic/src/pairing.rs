@@ -0,0 +1,286 @@
+//! Code-based pairing over a transport connection
+//!
+//! Runs the SPAKE2 exchange from `nomade_crypto::pairing` on a dedicated
+//! stream. The responder (the device where the code was typed) opens the
+//! stream; each side sends its PAKE message, then its key confirmation,
+//! as `Handshake` frames.
+//!
+//! Before the PAKE starts, the initiator sends a `PairingPuzzle` and the
+//! responder answers with its solution, so a stranger flooding a device in
+//! pairing mode pays for every attempt (see `cookie`).
+//!
+//! Once the key is confirmed, `exchange_offers` swaps the devices'
+//! `PairingOffer`s encrypted under it on a second stream.
+
+use nomade_crypto::pairing::{PakeMessage, PakeRole, Spake2};
+use nomade_crypto::{decrypt_data, encrypt_data, EncryptedData, PairingOffer};
+use serde::de::DeserializeOwned;
+use tokio::io::AsyncRead;
+
+use crate::cookie::{PairingPuzzle, PuzzleSolution};
+use crate::frame::{read_frame, write_frame};
+use crate::limits::{ConnectionGuard, RejectReason};
+use crate::transport::Connection;
+use crate::{Frame, MessageType, ProtocolError, Result};
+
+/// Run the PAKE on `connection` and return the confirmed session key
+///
+/// The initiator takes its puzzles from `guard`; without one it asks for
+/// no work.
+pub async fn pair_over(
+    connection: &dyn Connection,
+    role: PakeRole,
+    code: &str,
+    guard: Option<&ConnectionGuard>,
+) -> Result<[u8; 32]> {
+    let (mut send, mut recv) = match role {
+        PakeRole::Responder => connection.open_bi().await?,
+        PakeRole::Initiator => connection
+            .accept_bi()
+            .await?
+            .ok_or_else(|| ProtocolError::NotConnected(connection.remote_addr()))?,
+    };
+
+    let source = connection.remote_addr();
+    match role {
+        PakeRole::Initiator => {
+            let puzzle = guard.map_or(
+                PairingPuzzle {
+                    cookie: vec![],
+                    difficulty: 0,
+                },
+                |guard| guard.cookies().challenge(&source),
+            );
+            write_frame(
+                &mut send,
+                &Frame::from_message(MessageType::Handshake, &puzzle)?,
+            )
+            .await?;
+            let solution: PuzzleSolution = read_handshake(&mut recv).await?;
+            if let Some(guard) = guard {
+                if guard.cookies().verify(&source, &solution).is_err() {
+                    return Err(guard.reject(&source, RejectReason::PairingPuzzle));
+                }
+            }
+        }
+        PakeRole::Responder => {
+            let puzzle: PairingPuzzle = read_handshake(&mut recv).await?;
+            let solution = tokio::task::spawn_blocking(move || puzzle.solve())
+                .await
+                .map_err(|e| ProtocolError::Transport(e.to_string()))??;
+            write_frame(
+                &mut send,
+                &Frame::from_message(MessageType::Handshake, &solution)?,
+            )
+            .await?;
+        }
+    }
+
+    let (spake, message) = Spake2::start(role, code).map_err(rejected)?;
+    write_frame(
+        &mut send,
+        &Frame::from_message(MessageType::Handshake, &message)?,
+    )
+    .await?;
+    let peer: PakeMessage = read_handshake(&mut recv).await?;
+
+    let session = spake.finish(&peer).map_err(rejected)?;
+    let confirmation = session.confirmation();
+    write_frame(
+        &mut send,
+        &Frame::from_message(MessageType::Handshake, &confirmation)?,
+    )
+    .await?;
+    let peer_confirmation: Vec<u8> = read_handshake(&mut recv).await?;
+
+    session
+        .confirm(&peer_confirmation)
+        .map_err(|_| ProtocolError::PeerRejected("Pairing code mismatch".into()))
+}
+
+/// Send `offer` encrypted under the PAKE `key` and return the peer's
+///
+/// The responder opens the stream and sends first, and closes the
+/// connection once it has the initiator's offer; the initiator waits for
+/// that so its offer is not cut off. An offer that does not decrypt
+/// rejects the peer; its signature is left to the caller.
+pub async fn exchange_offers(
+    connection: &dyn Connection,
+    role: PakeRole,
+    key: &[u8; 32],
+    offer: &PairingOffer,
+) -> Result<PairingOffer> {
+    let sealed = encrypt_data(&serde_json::to_vec(offer)?, key).map_err(rejected)?;
+    let sealed = Frame::from_message(MessageType::Handshake, &sealed)?;
+    let open = |sealed: &EncryptedData| -> Result<PairingOffer> {
+        let plaintext = decrypt_data(sealed, key).map_err(rejected)?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    };
+    match role {
+        PakeRole::Responder => {
+            let (mut send, mut recv) = connection.open_bi().await?;
+            write_frame(&mut send, &sealed).await?;
+            open(&read_handshake(&mut recv).await?)
+        }
+        PakeRole::Initiator => {
+            let (mut send, mut recv) = connection
+                .accept_bi()
+                .await?
+                .ok_or_else(|| ProtocolError::NotConnected(connection.remote_addr()))?;
+            let peer = open(&read_handshake(&mut recv).await?)?;
+            write_frame(&mut send, &sealed).await?;
+            // Anything but the end of the stream is a protocol error anyway
+            let _ = read_frame(&mut recv).await;
+            Ok(peer)
+        }
+    }
+}
+
+async fn read_handshake<R, T>(reader: &mut R) -> Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+{
+    let frame = read_frame(reader).await?.ok_or(ProtocolError::Truncated)?;
+    if frame.message_type != MessageType::Handshake {
+        return Err(ProtocolError::UnexpectedMessage(frame.message_type));
+    }
+    frame.to_message()
+}
+
+fn rejected(e: nomade_crypto::CryptoError) -> ProtocolError {
+    ProtocolError::PeerRejected(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryNetwork, Transport};
+
+    async fn pair(displayed: &str, typed: &str) -> (Result<[u8; 32]>, Result<[u8; 32]>) {
+        let network = MemoryNetwork::new();
+        let laptop = network.bind("laptop").unwrap();
+        let phone = network.bind("phone").unwrap();
+
+        let dialed = phone.connect("laptop").await.unwrap();
+        let accepted = laptop.accept().await.unwrap().unwrap();
+        tokio::join!(
+            pair_over(accepted.as_ref(), PakeRole::Initiator, displayed, None),
+            pair_over(dialed.as_ref(), PakeRole::Responder, typed, None),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pairing_agrees_on_key() {
+        let (initiator, responder) = pair("7K3M9Q", "7k3-m9q").await;
+        assert_eq!(initiator.unwrap(), responder.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_code_rejected_on_both_sides() {
+        let (initiator, responder) = pair("7K3M9Q", "7K3M9R").await;
+        assert!(matches!(initiator, Err(ProtocolError::PeerRejected(_))));
+        assert!(matches!(responder, Err(ProtocolError::PeerRejected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_offers_exchanged_under_key() {
+        let network = MemoryNetwork::new();
+        let laptop = network.bind("laptop").unwrap();
+        let phone = network.bind("phone").unwrap();
+        let offer = |name: &str| {
+            let keypair = nomade_crypto::generate_keypair();
+            PairingOffer::new(
+                keypair.device_id().clone(),
+                name.to_string(),
+                keypair.public_key_bytes(),
+                vec![],
+            )
+        };
+        let (laptop_offer, phone_offer) = (offer("Laptop"), offer("Phone"));
+
+        let dialed = phone.connect("laptop").await.unwrap();
+        let accepted = laptop.accept().await.unwrap().unwrap();
+        let (initiator, responder) = tokio::join!(
+            async {
+                let key = pair_over(accepted.as_ref(), PakeRole::Initiator, "7K3M9Q", None).await?;
+                exchange_offers(accepted.as_ref(), PakeRole::Initiator, &key, &laptop_offer).await
+            },
+            async {
+                let key = pair_over(dialed.as_ref(), PakeRole::Responder, "7K3M9Q", None).await?;
+                let offer =
+                    exchange_offers(dialed.as_ref(), PakeRole::Responder, &key, &phone_offer).await;
+                dialed.close();
+                offer
+            },
+        );
+        assert_eq!(initiator.unwrap().device_id, phone_offer.device_id);
+        assert_eq!(responder.unwrap().device_id, laptop_offer.device_id);
+
+        // An offer under another key is refused
+        let dialed = phone.connect("laptop").await.unwrap();
+        let accepted = laptop.accept().await.unwrap().unwrap();
+        let (initiator, _) = tokio::join!(
+            exchange_offers(
+                accepted.as_ref(),
+                PakeRole::Initiator,
+                &[1; 32],
+                &laptop_offer
+            ),
+            exchange_offers(dialed.as_ref(), PakeRole::Responder, &[2; 32], &phone_offer),
+        );
+        assert!(matches!(initiator, Err(ProtocolError::PeerRejected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_initiator_requires_solved_puzzle() {
+        use crate::limits::RateLimits;
+
+        let network = MemoryNetwork::new();
+        let laptop = network.bind("laptop").unwrap();
+        let phone = network.bind("phone").unwrap();
+        let guard = ConnectionGuard::new(RateLimits {
+            pairing_puzzle_bits: 8,
+            ..Default::default()
+        });
+
+        let dialed = phone.connect("laptop").await.unwrap();
+        let accepted = laptop.accept().await.unwrap().unwrap();
+        let (initiator, responder) = tokio::join!(
+            pair_over(
+                accepted.as_ref(),
+                PakeRole::Initiator,
+                "7K3M9Q",
+                Some(&guard)
+            ),
+            pair_over(dialed.as_ref(), PakeRole::Responder, "7K3M9Q", None),
+        );
+        assert_eq!(initiator.unwrap(), responder.unwrap());
+
+        // A peer skipping the work is dropped before the PAKE starts
+        let dialed = phone.connect("laptop").await.unwrap();
+        let accepted = laptop.accept().await.unwrap().unwrap();
+        let (initiator, _) = tokio::join!(
+            pair_over(
+                accepted.as_ref(),
+                PakeRole::Initiator,
+                "7K3M9Q",
+                Some(&guard)
+            ),
+            async {
+                let (mut send, mut recv) = dialed.open_bi().await.unwrap();
+                let puzzle: PairingPuzzle = read_handshake(&mut recv).await.unwrap();
+                let bogus = (0..)
+                    .map(|nonce| PuzzleSolution {
+                        cookie: puzzle.cookie.clone(),
+                        nonce,
+                    })
+                    .find(|answer| guard.cookies().verify("phone", answer).is_err())
+                    .unwrap();
+                let frame = Frame::from_message(MessageType::Handshake, &bogus).unwrap();
+                write_frame(&mut send, &frame).await.unwrap();
+            },
+        );
+        assert!(matches!(initiator, Err(ProtocolError::PeerRejected(_))));
+    }
+}
@@ -0,0 +1,600 @@
+//! Port mapping on home routers
+//!
+//! A listener behind a NAT is unreachable from outside the LAN unless the
+//! router forwards its port. `map_port` asks the gateway for a UDP mapping,
+//! first with NAT-PMP (RFC 6886), then with UPnP IGD (SSDP discovery and
+//! a SOAP `AddPortMapping` call). Mappings are leased: `PortMapper` renews
+//! the lease at half its lifetime and removes the mapping when stopped,
+//! and reports the external endpoint to advertise in pairing offers.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nomade_crypto::Endpoint;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_util::sync::CancellationToken;
+
+use crate::{ProtocolError, Result};
+
+/// Port NAT-PMP gateways listen on
+pub const NATPMP_PORT: u16 = 5351;
+
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const IGD_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// Wait before retrying after a failed mapping
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How to reach the gateway
+#[derive(Debug, Clone)]
+pub struct PortMapConfig {
+    /// Gateway for NAT-PMP; the default route when unset
+    pub gateway: Option<IpAddr>,
+    /// Lease requested for each mapping
+    pub lifetime: Duration,
+    /// How long to wait for each answer
+    pub timeout: Duration,
+}
+
+impl Default for PortMapConfig {
+    fn default() -> Self {
+        Self {
+            gateway: None,
+            lifetime: Duration::from_secs(2 * 60 * 60),
+            timeout: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Protocol a mapping was made with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MappingProtocol {
+    NatPmp {
+        gateway: SocketAddr,
+    },
+    Upnp {
+        control_url: String,
+        service: String,
+    },
+}
+
+/// UDP port forwarded by the gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortMapping {
+    pub protocol: MappingProtocol,
+    pub internal_port: u16,
+    /// Address peers outside the LAN dial
+    pub external: SocketAddr,
+    /// Lease granted by the gateway
+    pub lifetime: Duration,
+}
+
+impl PortMapping {
+    /// Endpoint to advertise for the mapping
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint::direct(self.external)
+    }
+
+    /// When to renew the lease
+    pub fn renew_after(&self) -> Duration {
+        (self.lifetime / 2).max(Duration::from_secs(1))
+    }
+}
+
+/// Map UDP `port` on the gateway, trying NAT-PMP before UPnP
+pub async fn map_port(port: u16, config: &PortMapConfig) -> Result<PortMapping> {
+    let gateway = config.gateway.or_else(|| default_gateway().map(IpAddr::V4));
+    if let Some(gateway) = gateway {
+        let gateway = SocketAddr::new(gateway, NATPMP_PORT);
+        match natpmp_map(gateway, port, config.lifetime, config.timeout).await {
+            Ok(mapping) => return Ok(mapping),
+            Err(e) => tracing::debug!("NAT-PMP mapping via {} failed: {}", gateway, e),
+        }
+    }
+    let location = ssdp_discover(config.timeout).await?;
+    upnp_map(&location, port, config.lifetime, config.timeout).await
+}
+
+/// Remove a mapping before its lease ends
+pub async fn unmap_port(mapping: &PortMapping, timeout: Duration) -> Result<()> {
+    match &mapping.protocol {
+        MappingProtocol::NatPmp { gateway } => {
+            natpmp_request(*gateway, mapping.internal_port, 0, 0, timeout).await?;
+        }
+        MappingProtocol::Upnp {
+            control_url,
+            service,
+        } => {
+            let arguments = format!(
+                "<NewRemoteHost></NewRemoteHost>\
+                 <NewExternalPort>{}</NewExternalPort>\
+                 <NewProtocol>UDP</NewProtocol>",
+                mapping.external.port()
+            );
+            soap_call(
+                control_url,
+                service,
+                "DeletePortMapping",
+                &arguments,
+                timeout,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Map `port` with NAT-PMP on `gateway`
+pub async fn natpmp_map(
+    gateway: SocketAddr,
+    port: u16,
+    lifetime: Duration,
+    timeout: Duration,
+) -> Result<PortMapping> {
+    let (external_port, granted) =
+        natpmp_request(gateway, port, port, lifetime.as_secs() as u32, timeout).await?;
+    let response = natpmp_exchange(gateway, &[0, 0], 12, timeout).await?;
+    let ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+    Ok(PortMapping {
+        protocol: MappingProtocol::NatPmp { gateway },
+        internal_port: port,
+        external: SocketAddr::new(IpAddr::V4(ip), external_port),
+        lifetime: Duration::from_secs(granted.into()),
+    })
+}
+
+/// Send a UDP mapping request; returns the external port and lifetime
+async fn natpmp_request(
+    gateway: SocketAddr,
+    internal: u16,
+    external: u16,
+    lifetime: u32,
+    timeout: Duration,
+) -> Result<(u16, u32)> {
+    let mut request = vec![0, 1, 0, 0];
+    request.extend_from_slice(&internal.to_be_bytes());
+    request.extend_from_slice(&external.to_be_bytes());
+    request.extend_from_slice(&lifetime.to_be_bytes());
+    let response = natpmp_exchange(gateway, &request, 16, timeout).await?;
+    Ok((
+        u16::from_be_bytes([response[10], response[11]]),
+        u32::from_be_bytes([response[12], response[13], response[14], response[15]]),
+    ))
+}
+
+/// Send a NAT-PMP request and check the answer's opcode and result code
+async fn natpmp_exchange(
+    gateway: SocketAddr,
+    request: &[u8],
+    len: usize,
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let bind: SocketAddr = match gateway {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().expect("valid address"),
+        SocketAddr::V6(_) => "[::]:0".parse().expect("valid address"),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(gateway).await?;
+    let mut buf = [0u8; 64];
+    // RFC 6886 retries with doubling delays
+    let mut wait = Duration::from_millis(250).min(timeout);
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        socket.send(request).await?;
+        match tokio::time::timeout(wait, socket.recv(&mut buf)).await {
+            Ok(received) => {
+                let n = received?;
+                if n < len || buf[0] != 0 || buf[1] != (request[1] | 0x80) {
+                    return Err(ProtocolError::Transport(
+                        "Malformed NAT-PMP response".into(),
+                    ));
+                }
+                let result = u16::from_be_bytes([buf[2], buf[3]]);
+                if result != 0 {
+                    return Err(ProtocolError::PeerRejected(format!(
+                        "NAT-PMP result code {}",
+                        result
+                    )));
+                }
+                return Ok(buf[..n].to_vec());
+            }
+            Err(_) if tokio::time::Instant::now() + wait < deadline => wait *= 2,
+            Err(_) => return Err(ProtocolError::PeerTimeout),
+        }
+    }
+}
+
+/// Find an Internet gateway device with SSDP; returns its description URL
+pub async fn ssdp_discover(timeout: Duration) -> Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let search = "M-SEARCH * HTTP/1.1\r\n\
+                  HOST: 239.255.255.250:1900\r\n\
+                  MAN: \"ssdp:discover\"\r\n\
+                  MX: 2\r\n\
+                  ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+    socket.send_to(search.as_bytes(), SSDP_ADDR).await?;
+    let mut buf = [0u8; 2048];
+    let (n, _) = tokio::time::timeout(timeout, socket.recv_from(&mut buf))
+        .await
+        .map_err(|_| ProtocolError::PeerTimeout)??;
+    header(&String::from_utf8_lossy(&buf[..n]), "location")
+        .ok_or_else(|| ProtocolError::Transport("SSDP response without location".into()))
+}
+
+/// Map `port` on the gateway described at `location`
+pub async fn upnp_map(
+    location: &str,
+    port: u16,
+    lifetime: Duration,
+    timeout: Duration,
+) -> Result<PortMapping> {
+    let (host, _) = split_url(location)?;
+    let description = http_request(location, "GET", &[], "", timeout).await?;
+    let (service, control_url) = IGD_SERVICES
+        .iter()
+        .find_map(|service| {
+            control_url(&description, service).map(|url| (service.to_string(), url))
+        })
+        .ok_or_else(|| ProtocolError::Transport("Gateway has no WAN connection service".into()))?;
+    let control_url = if control_url.starts_with("http://") {
+        control_url
+    } else {
+        format!("http://{}/{}", host, control_url.trim_start_matches('/'))
+    };
+
+    // The address the gateway sees this device at
+    let local = tokio::time::timeout(timeout, TcpStream::connect(&host))
+        .await
+        .map_err(|_| ProtocolError::PeerTimeout)??
+        .local_addr()?
+        .ip();
+    let arguments = format!(
+        "<NewRemoteHost></NewRemoteHost>\
+         <NewExternalPort>{port}</NewExternalPort>\
+         <NewProtocol>UDP</NewProtocol>\
+         <NewInternalPort>{port}</NewInternalPort>\
+         <NewInternalClient>{local}</NewInternalClient>\
+         <NewEnabled>1</NewEnabled>\
+         <NewPortMappingDescription>nomade</NewPortMappingDescription>\
+         <NewLeaseDuration>{}</NewLeaseDuration>",
+        lifetime.as_secs()
+    );
+    soap_call(
+        &control_url,
+        &service,
+        "AddPortMapping",
+        &arguments,
+        timeout,
+    )
+    .await?;
+    let response = soap_call(&control_url, &service, "GetExternalIPAddress", "", timeout).await?;
+    let ip: IpAddr = element(&response, "NewExternalIPAddress")
+        .and_then(|ip| ip.trim().parse().ok())
+        .ok_or_else(|| ProtocolError::Transport("Gateway reported no external address".into()))?;
+    Ok(PortMapping {
+        protocol: MappingProtocol::Upnp {
+            control_url,
+            service,
+        },
+        internal_port: port,
+        external: SocketAddr::new(ip, port),
+        lifetime,
+    })
+}
+
+async fn soap_call(
+    control_url: &str,
+    service: &str,
+    action: &str,
+    arguments: &str,
+    timeout: Duration,
+) -> Result<String> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{action} xmlns:u=\"{service}\">{arguments}</u:{action}></s:Body>\
+         </s:Envelope>"
+    );
+    let soap_action = format!("\"{}#{}\"", service, action);
+    http_request(
+        control_url,
+        "POST",
+        &[
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPAction", &soap_action),
+        ],
+        &body,
+        timeout,
+    )
+    .await
+}
+
+/// Minimal HTTP/1.0 client for the gateway's description and control URLs
+async fn http_request(
+    url: &str,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+    timeout: Duration,
+) -> Result<String> {
+    let (host, path) = split_url(url)?;
+    let mut request = format!("{method} {path} HTTP/1.0\r\nHost: {host}\r\n");
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(&host).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| ProtocolError::PeerTimeout)??;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| ProtocolError::Transport(format!("Malformed HTTP response from {}", url)))?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(ProtocolError::PeerRejected(format!(
+            "{} {} returned {}",
+            method, url, status
+        )));
+    }
+    Ok(body.to_string())
+}
+
+/// Split `http://host:port/path` into `host:port` and `/path`
+fn split_url(url: &str) -> Result<(String, String)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| ProtocolError::Transport(format!("Unsupported URL: {}", url)))?;
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((host, format!("/{}", path)))
+}
+
+/// Value of a header in an HTTP-like message, by case-insensitive name
+fn header(message: &str, name: &str) -> Option<String> {
+    message.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Text of the first `<name>` element
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(&xml[start..end])
+}
+
+/// Control URL of `service` in a device description
+fn control_url(description: &str, service: &str) -> Option<String> {
+    description.split("<service>").skip(1).find_map(|block| {
+        (element(block, "serviceType")?.trim() == service)
+            .then(|| element(block, "controlURL").map(|url| url.trim().to_string()))?
+    })
+}
+
+/// Default IPv4 gateway from the routing table, where it can be read
+fn default_gateway() -> Option<Ipv4Addr> {
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        // The kernel prints the address in host (little-endian) order
+        Some(Ipv4Addr::from(gateway.swap_bytes())).filter(|ip| !ip.is_unspecified())
+    })
+}
+
+/// Keeps a port mapped while the listener runs
+#[derive(Debug, Default)]
+pub struct PortMapper {
+    current: Mutex<Option<PortMapping>>,
+}
+
+impl PortMapper {
+    /// Create a mapper without a mapping
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current mapping, if the gateway granted one
+    pub fn mapping(&self) -> Option<PortMapping> {
+        self.current.lock().unwrap().clone()
+    }
+
+    /// Endpoint to advertise for the current mapping
+    pub fn external(&self) -> Option<Endpoint> {
+        self.mapping().map(|mapping| mapping.endpoint())
+    }
+
+    /// Map `port` and renew the lease until `cancel` fires, then unmap
+    pub async fn run(self: Arc<Self>, port: u16, config: PortMapConfig, cancel: CancellationToken) {
+        loop {
+            let wait = match map_port(port, &config).await {
+                Ok(mapping) => {
+                    if self.mapping().as_ref() != Some(&mapping) {
+                        tracing::info!("Port {} mapped to {}", port, mapping.external);
+                    }
+                    let wait = mapping.renew_after();
+                    *self.current.lock().unwrap() = Some(mapping);
+                    wait
+                }
+                Err(e) => {
+                    tracing::debug!("Port mapping failed: {}", e);
+                    RETRY_DELAY
+                }
+            };
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+        let mapping = self.current.lock().unwrap().take();
+        if let Some(mapping) = mapping {
+            if let Err(e) = unmap_port(&mapping, config.timeout).await {
+                tracing::debug!("Failed to remove port mapping: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// NAT-PMP gateway mapping every port to 203.0.113.7:<port + 1>
+    async fn natpmp_gateway() -> (SocketAddr, tokio::task::JoinHandle<Vec<u32>>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let mut lifetimes = Vec::new();
+            let mut buf = [0u8; 64];
+            while lifetimes.len() < 2 {
+                let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+                let mut response = vec![0, buf[1] | 0x80, 0, 0, 0, 0, 0, 1];
+                if buf[1] == 0 {
+                    response.extend_from_slice(&[203, 0, 113, 7]);
+                } else {
+                    assert_eq!(n, 12);
+                    let internal = u16::from_be_bytes([buf[4], buf[5]]);
+                    let lifetime = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+                    lifetimes.push(lifetime);
+                    response.extend_from_slice(&buf[4..6]);
+                    response.extend_from_slice(&(internal + 1).to_be_bytes());
+                    response.extend_from_slice(&lifetime.min(3600).to_be_bytes());
+                }
+                socket.send_to(&response, from).await.unwrap();
+            }
+            lifetimes
+        });
+        (addr, task)
+    }
+
+    #[tokio::test]
+    async fn test_natpmp_maps_and_unmaps() {
+        let (gateway, server) = natpmp_gateway().await;
+        let config = PortMapConfig {
+            gateway: Some(gateway.ip()),
+            ..Default::default()
+        };
+        let mapping = natpmp_map(gateway, 8765, config.lifetime, config.timeout)
+            .await
+            .unwrap();
+        assert_eq!(mapping.external, "203.0.113.7:8766".parse().unwrap());
+        assert_eq!(mapping.lifetime, Duration::from_secs(3600));
+        assert_eq!(mapping.renew_after(), Duration::from_secs(1800));
+        assert_eq!(
+            mapping.endpoint().to_string(),
+            Endpoint::direct(mapping.external).to_string()
+        );
+
+        unmap_port(&mapping, config.timeout).await.unwrap();
+        assert_eq!(server.await.unwrap(), [7200, 0]);
+
+        // Nobody answers any more
+        let silent = natpmp_map(gateway, 8765, config.lifetime, Duration::from_millis(300)).await;
+        assert!(silent.is_err());
+    }
+
+    async fn read_request(stream: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            let complete = text.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                header(head, "content-length").and_then(|len| len.parse().ok()) == Some(body.len())
+            });
+            if n == 0 || complete {
+                return text;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upnp_maps_through_control_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let location = format!("http://{}/rootDesc.xml", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut actions = Vec::new();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut stream).await;
+                let body = if request.starts_with("GET /rootDesc.xml") {
+                    "<root><device><serviceList>\
+                     <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>\
+                     <controlURL>/l3f</controlURL></service>\
+                     <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+                     <controlURL>/ctl/IPConn</controlURL></service>\
+                     </serviceList></device></root>"
+                        .to_string()
+                } else {
+                    // Connection probe for the local address
+                    let Some(action) = header(&request, "soapaction") else {
+                        continue;
+                    };
+                    assert!(request.starts_with("POST /ctl/IPConn"));
+                    actions.push(action.clone());
+                    if action.ends_with("#GetExternalIPAddress\"") {
+                        "<NewExternalIPAddress>198.51.100.4</NewExternalIPAddress>".to_string()
+                    } else {
+                        if action.ends_with("#AddPortMapping\"") {
+                            assert!(request.contains("<NewInternalClient>127.0.0.1<"));
+                        }
+                        String::new()
+                    }
+                };
+                let response = format!("HTTP/1.0 200 OK\r\n\r\n{}", body);
+                stream.write_all(response.as_bytes()).await.unwrap();
+                if actions.len() == 3 {
+                    return actions;
+                }
+            }
+        });
+
+        let timeout = Duration::from_secs(2);
+        let mapping = upnp_map(&location, 8765, Duration::from_secs(600), timeout)
+            .await
+            .unwrap();
+        assert_eq!(mapping.external, "198.51.100.4:8765".parse().unwrap());
+        unmap_port(&mapping, timeout).await.unwrap();
+        let actions: Vec<String> = server
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|a| a.rsplit('#').next().unwrap().trim_matches('"').to_string())
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                "AddPortMapping",
+                "GetExternalIPAddress",
+                "DeletePortMapping"
+            ]
+        );
+    }
+}
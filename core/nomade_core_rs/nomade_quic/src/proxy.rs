@@ -0,0 +1,435 @@
+//! Outbound proxies
+//!
+//! Networks that only let traffic out through a proxy get TCP tunnels via
+//! HTTP `CONNECT` or SOCKS5, each with optional username and password.
+//! Only TCP can be tunnelled, so proxied connections use the WebSocket
+//! transport; UDP (and with it QUIC) is not proxied.
+
+use std::fmt;
+
+use base64::Engine;
+use nomade_crypto::Endpoint;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::{ProtocolError, Result};
+
+/// Longest HTTP proxy response header accepted
+const MAX_RESPONSE_HEADER: usize = 8 * 1024;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_PASSWORD_AUTH: u8 = 2;
+const SOCKS_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_IPV4: u8 = 1;
+const SOCKS_DOMAIN: u8 = 3;
+const SOCKS_IPV6: u8 = 4;
+
+/// Proxy protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    /// HTTP proxy tunnelling with `CONNECT`
+    Http,
+    /// SOCKS5 proxy (RFC 1928)
+    Socks5,
+}
+
+/// Proxy that outbound connections tunnel through
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    /// Proxy `host:port`
+    pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Proxy without credentials
+    pub fn new(kind: ProxyKind, address: impl Into<String>) -> Self {
+        Self {
+            kind,
+            address: address.into(),
+            username: None,
+            password: None,
+        }
+    }
+
+    /// Authenticate to the proxy
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.username = Some(username.into());
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Check the address and credentials are usable
+    pub fn validate(&self) -> Result<()> {
+        match self.address.parse::<Endpoint>() {
+            Ok(Endpoint::Relay { .. }) | Err(_) => {
+                return Err(ProtocolError::Transport(format!(
+                    "Proxy address must be host:port: {}",
+                    self.address
+                )))
+            }
+            Ok(_) => {}
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err(ProtocolError::Transport(
+                "Proxy password given without a username".into(),
+            ));
+        }
+        if self.kind == ProxyKind::Socks5 {
+            let too_long = |value: &Option<String>| value.as_ref().is_some_and(|v| v.len() > 255);
+            if too_long(&self.username) || too_long(&self.password) {
+                return Err(ProtocolError::Transport(
+                    "SOCKS5 credentials are limited to 255 bytes".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Open a TCP tunnel to `target` through the proxy
+    ///
+    /// Host names are passed to the proxy unresolved, as networks behind
+    /// proxies often cannot resolve outside names themselves.
+    pub async fn connect(&self, target: &Endpoint) -> Result<TcpStream> {
+        self.validate()?;
+        let (host, port) = match target {
+            Endpoint::DirectV4(addr) => (addr.ip().to_string(), addr.port()),
+            Endpoint::DirectV6(addr) => (addr.ip().to_string(), addr.port()),
+            Endpoint::Hostname { host, port } => (host.clone(), *port),
+            Endpoint::Relay { .. } => {
+                return Err(ProtocolError::Transport(format!(
+                    "Relay endpoints cannot be proxied: {}",
+                    target
+                )))
+            }
+        };
+        let mut stream = TcpStream::connect(self.address.as_str()).await?;
+        stream.set_nodelay(true)?;
+        match self.kind {
+            ProxyKind::Http => self.http_connect(&mut stream, target).await?,
+            ProxyKind::Socks5 => self.socks5_connect(&mut stream, &host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    async fn http_connect(&self, stream: &mut TcpStream, target: &Endpoint) -> Result<()> {
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some(username) = &self.username {
+            let credentials = format!("{}:{}", username, self.password.as_deref().unwrap_or(""));
+            request.push_str(&format!(
+                "Proxy-Authorization: Basic {}\r\n",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            ));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte: anything after the header belongs to the tunnel
+        let mut header = Vec::new();
+        while !header.ends_with(b"\r\n\r\n") {
+            if header.len() >= MAX_RESPONSE_HEADER {
+                return Err(proxy_error("HTTP proxy response header too long"));
+            }
+            header.push(stream.read_u8().await?);
+        }
+        let header = String::from_utf8_lossy(&header);
+        let status_line = header.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1);
+        if !status_line.starts_with("HTTP/1.") || status.is_none() {
+            return Err(proxy_error("Malformed HTTP proxy response"));
+        }
+        if status != Some("200") {
+            return Err(proxy_error(&format!("HTTP proxy refused: {}", status_line)));
+        }
+        Ok(())
+    }
+
+    async fn socks5_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> Result<()> {
+        let method = if self.username.is_some() {
+            SOCKS_PASSWORD_AUTH
+        } else {
+            SOCKS_NO_AUTH
+        };
+        stream.write_all(&[SOCKS_VERSION, 1, method]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS_VERSION {
+            return Err(proxy_error("Not a SOCKS5 proxy"));
+        }
+        match reply[1] {
+            SOCKS_NO_AUTH if method == SOCKS_NO_AUTH => {}
+            SOCKS_PASSWORD_AUTH if method == SOCKS_PASSWORD_AUTH => {
+                // RFC 1929 username/password subnegotiation
+                let username = self.username.as_deref().unwrap_or("").as_bytes();
+                let password = self.password.as_deref().unwrap_or("").as_bytes();
+                let mut auth = vec![1, username.len() as u8];
+                auth.extend_from_slice(username);
+                auth.push(password.len() as u8);
+                auth.extend_from_slice(password);
+                stream.write_all(&auth).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    return Err(proxy_error("SOCKS5 proxy rejected the credentials"));
+                }
+            }
+            SOCKS_NO_ACCEPTABLE => {
+                return Err(proxy_error("SOCKS5 proxy accepts none of our auth methods"))
+            }
+            _ => return Err(proxy_error("SOCKS5 proxy chose an unknown auth method")),
+        }
+
+        let mut request = vec![SOCKS_VERSION, SOCKS_CONNECT, 0];
+        match host.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V4(ip)) => {
+                request.push(SOCKS_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(std::net::IpAddr::V6(ip)) => {
+                request.push(SOCKS_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                let len = u8::try_from(host.len())
+                    .map_err(|_| proxy_error("Host name too long for SOCKS5"))?;
+                request.extend_from_slice(&[SOCKS_DOMAIN, len]);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(proxy_error(&format!(
+                "SOCKS5 proxy refused: {}",
+                socks_reply_reason(reply[1])
+            )));
+        }
+        // Skip the bound address the proxy reports
+        let bound = match reply[3] {
+            SOCKS_IPV4 => 4,
+            SOCKS_IPV6 => 16,
+            SOCKS_DOMAIN => stream.read_u8().await? as usize,
+            _ => return Err(proxy_error("Malformed SOCKS5 reply")),
+        };
+        let mut skipped = vec![0u8; bound + 2];
+        stream.read_exact(&mut skipped).await?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Http => "http",
+            ProxyKind::Socks5 => "socks5",
+        };
+        write!(f, "{}://{}", scheme, self.address)
+    }
+}
+
+// Keeps the password out of logs
+impl fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("kind", &self.kind)
+            .field("address", &self.address)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+fn proxy_error(message: &str) -> ProtocolError {
+    ProtocolError::Transport(message.to_string())
+}
+
+fn socks_reply_reason(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+
+    /// Server echoing everything back on each connection
+    async fn echo_server() -> Endpoint {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    tokio::io::copy(&mut read, &mut write).await.ok();
+                });
+            }
+        });
+        addr.to_string().parse().unwrap()
+    }
+
+    /// Minimal proxy: runs `handshake`, then relays to the echo server
+    async fn proxy<F, Fut>(handshake: F) -> String
+    where
+        F: Fn(TcpStream) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Option<(TcpStream, String)>> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handshake = std::sync::Arc::new(handshake);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handshake = handshake.clone();
+                tokio::spawn(async move {
+                    if let Some((mut client, target)) = handshake(stream).await {
+                        let mut upstream = TcpStream::connect(target).await.unwrap();
+                        tokio::io::copy_bidirectional(&mut client, &mut upstream)
+                            .await
+                            .ok();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    async fn round_trip(mut stream: TcpStream) {
+        stream.write_all(b"ping").await.unwrap();
+        let mut echoed = [0u8; 4];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_http_connect() {
+        let target = echo_server().await;
+        let expected_auth = format!(
+            "Proxy-Authorization: Basic {}",
+            base64::engine::general_purpose::STANDARD.encode("alice:secret")
+        );
+        let address = proxy(move |stream| {
+            let expected_auth = expected_auth.clone();
+            async move {
+                let mut reader = tokio::io::BufReader::new(stream);
+                let mut lines = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    lines.push(line.trim_end().to_string());
+                }
+                let target = lines[0].split_whitespace().nth(1).unwrap().to_string();
+                let mut stream = reader.into_inner();
+                if !lines.contains(&expected_auth) {
+                    stream
+                        .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                        .await
+                        .unwrap();
+                    return None;
+                }
+                stream
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .await
+                    .unwrap();
+                Some((stream, target))
+            }
+        })
+        .await;
+
+        let proxy = ProxyConfig::new(ProxyKind::Http, &address).with_credentials("alice", "secret");
+        round_trip(proxy.connect(&target).await.unwrap()).await;
+
+        let anonymous = ProxyConfig::new(ProxyKind::Http, &address);
+        let refused = anonymous.connect(&target).await.unwrap_err();
+        assert!(refused.to_string().contains("407"));
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect() {
+        let target = echo_server().await;
+        let address = proxy(|mut stream: TcpStream| async move {
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [SOCKS_VERSION, 1, SOCKS_PASSWORD_AUTH]);
+            stream
+                .write_all(&[SOCKS_VERSION, SOCKS_PASSWORD_AUTH])
+                .await
+                .unwrap();
+            let mut auth = [0u8; 2 + 3 + 1 + 6];
+            stream.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x03bob\x06hunter");
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 5];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(
+                request[..4],
+                [SOCKS_VERSION, SOCKS_CONNECT, 0, SOCKS_DOMAIN]
+            );
+            let mut host = vec![0u8; request[4] as usize];
+            stream.read_exact(&mut host).await.unwrap();
+            assert_eq!(host, b"localhost");
+            let port = stream.read_u16().await.unwrap();
+            stream
+                .write_all(&[SOCKS_VERSION, 0, 0, SOCKS_IPV4, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+            Some((stream, format!("127.0.0.1:{}", port)))
+        })
+        .await;
+
+        let proxy = ProxyConfig::new(ProxyKind::Socks5, address).with_credentials("bob", "hunter");
+        let target = Endpoint::Hostname {
+            host: "localhost".into(),
+            port: target.socket_addr().unwrap().port(),
+        };
+        round_trip(proxy.connect(&target).await.unwrap()).await;
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ProxyConfig::new(ProxyKind::Http, "proxy.corp:3128")
+            .validate()
+            .is_ok());
+        assert!(ProxyConfig::new(ProxyKind::Http, "proxy.corp")
+            .validate()
+            .is_err());
+        assert!(ProxyConfig::new(ProxyKind::Socks5, "relay:eu-1")
+            .validate()
+            .is_err());
+
+        let mut proxy = ProxyConfig::new(ProxyKind::Socks5, "10.0.0.1:1080");
+        proxy.password = Some("secret".into());
+        assert!(proxy.validate().is_err());
+        let proxy = proxy.with_credentials("a".repeat(256), "secret");
+        assert!(proxy.validate().is_err());
+        assert!(!format!("{:?}", proxy).contains("secret"));
+        assert_eq!(proxy.to_string(), "socks5://10.0.0.1:1080");
+    }
+}
@@ -0,0 +1,559 @@
+//! Replay protection for session frames
+//!
+//! With `FeatureFlags::SEQUENCED_FRAMES` negotiated, every frame a session
+//! sends carries a sequence number, counting up from 1 across all of the
+//! session's channels. The receiver keeps a sliding window over the highest
+//! sequence numbers seen: frames reordered across streams are accepted
+//! once, while a frame seen before or older than the window is rejected.
+//!
+//! A `ReplayStore` keeps both counters per session, so a resumed session
+//! continues where it stopped and frames captured from an earlier
+//! connection are still rejected. Sequence numbers are reserved on disk in
+//! blocks before they are sent, and received ones are marked on disk in
+//! blocks before they are accepted, so a session resumed after a crash
+//! neither sends a number twice nor accepts one twice.
+//!
+//! Each side's outgoing counters belong to a random epoch. The hello
+//! announces the epoch and how far the peer's current epoch has been seen
+//! (`SessionHello`): a side whose counters were lost starts a new epoch,
+//! and its peer ends the old session instead of rejecting every frame of
+//! the new one, while a sender behind what its peer has seen (a stale
+//! restore, or a crash before it saved) skips ahead.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use nomade_crypto::fs::write_durable;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{ProtocolError, Result};
+
+/// Sequence numbers tracked behind the highest one received
+pub const REPLAY_WINDOW: u64 = 1024;
+
+/// Sequence numbers reserved in the store at a time
+pub const SEQUENCE_RESERVATION: u64 = 1024;
+
+const WINDOW_WORDS: usize = (REPLAY_WINDOW / 64) as usize;
+
+/// Sliding window of received sequence numbers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayWindow {
+    /// Highest sequence number accepted, 0 before the first
+    highest: u64,
+    /// Bit `n % REPLAY_WINDOW` is set once `n` within the window was seen
+    seen: Vec<u64>,
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self {
+            highest: 0,
+            seen: vec![0; WINDOW_WORDS],
+        }
+    }
+}
+
+impl ReplayWindow {
+    /// Create an empty window
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Window treating every sequence number up to `through` as seen
+    pub fn through(through: u64) -> Self {
+        Self {
+            highest: through,
+            seen: vec![u64::MAX; WINDOW_WORDS],
+        }
+    }
+
+    /// Highest sequence number accepted so far
+    pub fn highest(&self) -> u64 {
+        self.highest
+    }
+
+    /// Accept `sequence` unless it was seen before or is too old
+    pub fn accept(&mut self, sequence: u64) -> Result<()> {
+        if sequence == 0 || sequence.saturating_add(REPLAY_WINDOW) <= self.highest {
+            return Err(ProtocolError::Replayed(sequence));
+        }
+        if sequence > self.highest {
+            // Forget the slots the window slides over
+            let slid = (sequence - self.highest).min(REPLAY_WINDOW);
+            for n in sequence - slid + 1..=sequence {
+                self.set(n, false);
+            }
+            self.highest = sequence;
+        } else if self.get(sequence) {
+            return Err(ProtocolError::Replayed(sequence));
+        }
+        self.set(sequence, true);
+        Ok(())
+    }
+
+    fn get(&self, sequence: u64) -> bool {
+        let bit = sequence % REPLAY_WINDOW;
+        // A malformed window restored from disk counts everything as seen
+        self.seen
+            .get((bit / 64) as usize)
+            .is_none_or(|word| word & (1 << (bit % 64)) != 0)
+    }
+
+    fn set(&mut self, sequence: u64, value: bool) {
+        if self.seen.len() != WINDOW_WORDS {
+            self.seen = vec![0; WINDOW_WORDS];
+        }
+        let bit = sequence % REPLAY_WINDOW;
+        let word = &mut self.seen[(bit / 64) as usize];
+        if value {
+            *word |= 1 << (bit % 64);
+        } else {
+            *word &= !(1 << (bit % 64));
+        }
+    }
+}
+
+/// Counters of one session in both directions
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionCounters {
+    /// Last sequence number sent
+    pub sent: u64,
+    pub received: ReplayWindow,
+    /// Epoch of the sent counter, 0 until one is drawn
+    #[serde(default)]
+    pub epoch: u64,
+    /// Epoch of the peer's counter the window tracks, 0 if unknown
+    #[serde(default)]
+    pub peer_epoch: u64,
+    /// Received sequence numbers marked on disk: none above was accepted
+    #[serde(default)]
+    pub received_through: u64,
+}
+
+/// Session state a device announces in its hello
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionHello {
+    /// Epoch of the sender's outgoing counter
+    pub epoch: u64,
+    /// Epoch of the receiver's counter the sender has seen, 0 if none
+    pub peer_epoch: u64,
+    /// Highest sequence number of `peer_epoch` the sender may have accepted
+    pub received_through: u64,
+}
+
+/// Counters shared by all channels of a session
+#[derive(Debug, Clone, Default)]
+pub struct ReplayGuard(Arc<Mutex<GuardState>>);
+
+#[derive(Debug, Default)]
+struct GuardState {
+    counters: SessionCounters,
+    /// Highest sequence number reserved in the store
+    reserved: u64,
+    /// Store and session to reserve sequence numbers in
+    store: Option<(Arc<Sessions>, String)>,
+}
+
+impl ReplayGuard {
+    /// Guard for a new session
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Guard continuing from saved counters
+    pub fn resume(counters: SessionCounters) -> Self {
+        Self(Arc::new(Mutex::new(GuardState {
+            counters,
+            ..Default::default()
+        })))
+    }
+
+    /// Sequence number for the next frame sent
+    ///
+    /// With a persisted store, the next block of numbers is reserved on
+    /// disk before the first of them is handed out.
+    pub fn next_sequence(&self) -> Result<u64> {
+        let mut state = self.0.lock().unwrap();
+        let next = state.counters.sent + 1;
+        if let Some((sessions, session)) = &state.store {
+            if next > state.reserved {
+                let reserved = next.saturating_add(SEQUENCE_RESERVATION - 1);
+                sessions.reserve(session, reserved)?;
+                state.reserved = reserved;
+            }
+        }
+        state.counters.sent = next;
+        Ok(next)
+    }
+
+    /// Check a received frame's sequence number
+    ///
+    /// With a persisted store, the next block of numbers is marked on disk
+    /// before the first of them is accepted.
+    pub fn accept(&self, sequence: Option<u64>) -> Result<()> {
+        let sequence = sequence
+            .ok_or_else(|| ProtocolError::PeerRejected("Frame without sequence number".into()))?;
+        let mut state = self.0.lock().unwrap();
+        if let Some((sessions, session)) = &state.store {
+            // Past the mark means past the window too, so the frame is new
+            if sequence > state.counters.received_through {
+                let through = sequence.saturating_add(SEQUENCE_RESERVATION - 1);
+                sessions.mark_received(session, through)?;
+                state.counters.received_through = through;
+            }
+        }
+        state.counters.received.accept(sequence)
+    }
+
+    /// Current counters, to save for resumption
+    pub fn counters(&self) -> SessionCounters {
+        self.0.lock().unwrap().counters.clone()
+    }
+}
+
+/// Session counters kept across reconnects
+pub struct ReplayStore(Arc<Sessions>);
+
+#[derive(Debug)]
+struct Sessions {
+    counters: Mutex<HashMap<String, SessionCounters>>,
+    path: Option<PathBuf>,
+}
+
+impl Sessions {
+    /// Record that `session` may have sent up to `sent`
+    fn reserve(&self, session: &str, sent: u64) -> Result<()> {
+        let mut counters = self.counters.lock().unwrap();
+        let saved = counters.entry(session.to_string()).or_default();
+        saved.sent = saved.sent.max(sent);
+        self.persist(&counters)
+    }
+
+    /// Record that `session` may have accepted up to `through`
+    fn mark_received(&self, session: &str, through: u64) -> Result<()> {
+        let mut counters = self.counters.lock().unwrap();
+        let saved = counters.entry(session.to_string()).or_default();
+        saved.received_through = saved.received_through.max(through);
+        self.persist(&counters)
+    }
+
+    /// Write `counters` durably
+    ///
+    /// Callers hold the `counters` lock, so writes never interleave.
+    fn persist(&self, counters: &HashMap<String, SessionCounters>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(counters)?)?;
+        Ok(())
+    }
+}
+
+impl Default for ReplayStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReplayStore {
+    /// Create an in-memory store
+    pub fn new() -> Self {
+        Self(Arc::new(Sessions {
+            counters: Mutex::new(HashMap::new()),
+            path: None,
+        }))
+    }
+
+    /// Open a store persisted at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let sessions = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self(Arc::new(Sessions {
+            counters: Mutex::new(sessions),
+            path: Some(path),
+        })))
+    }
+
+    /// State of `session` to announce in the hello
+    ///
+    /// Draws and saves an epoch for the session's sent counter if it has
+    /// none.
+    pub fn hello(&self, session: &str) -> Result<SessionHello> {
+        let mut counters = self.0.counters.lock().unwrap();
+        let saved = counters.entry(session.to_string()).or_default();
+        let hello = match saved.epoch {
+            0 => {
+                saved.epoch = new_epoch();
+                let hello = announced(saved);
+                self.0.persist(&counters)?;
+                hello
+            }
+            _ => announced(saved),
+        };
+        Ok(hello)
+    }
+
+    /// Guard for `session`, continuing its saved counters if any
+    ///
+    /// `remote` is what the peer announced in its hello. If the peer's
+    /// epoch changed, it lost its counters: the session is ended and both
+    /// counters start over. Otherwise everything received before counts
+    /// as seen, and sending continues past whatever the peer has seen.
+    /// The guard reserves its sequence numbers in this store.
+    pub fn resume(&self, session: &str, remote: &SessionHello) -> Result<ReplayGuard> {
+        let saved = self.0.counters.lock().unwrap().get(session).cloned();
+        let epoch = match saved.as_ref().map_or(0, |saved| saved.epoch) {
+            0 => new_epoch(),
+            epoch => epoch,
+        };
+        let restarted = saved
+            .as_ref()
+            .is_some_and(|saved| saved.peer_epoch != 0 && saved.peer_epoch != remote.epoch);
+        let saved = match restarted {
+            true => {
+                tracing::info!("Peer of session {} started a new epoch", session);
+                self.end(session)?;
+                SessionCounters::default()
+            }
+            false => saved.unwrap_or_default(),
+        };
+        let mut sent = saved.sent;
+        if remote.peer_epoch == epoch {
+            sent = sent.max(remote.received_through);
+        }
+        let through = saved.received.highest().max(saved.received_through);
+        let counters = SessionCounters {
+            sent,
+            received: ReplayWindow::through(through),
+            epoch,
+            peer_epoch: remote.epoch,
+            received_through: through,
+        };
+        let mut stored = self.0.counters.lock().unwrap();
+        stored.insert(session.to_string(), counters.clone());
+        self.0.persist(&stored)?;
+        Ok(ReplayGuard(Arc::new(Mutex::new(GuardState {
+            reserved: counters.sent,
+            counters,
+            store: Some((self.0.clone(), session.to_string())),
+        }))))
+    }
+
+    /// Save the counters of `session` so it can be resumed
+    pub fn save(&self, session: &str, guard: &ReplayGuard) -> Result<()> {
+        // Taken first: the guard locks the store while it reserves
+        let saved = guard.counters();
+        let mut counters = self.0.counters.lock().unwrap();
+        counters.insert(session.to_string(), saved);
+        self.0.persist(&counters)
+    }
+
+    /// Forget a session that will not be resumed
+    pub fn end(&self, session: &str) -> Result<()> {
+        let mut counters = self.0.counters.lock().unwrap();
+        if counters.remove(session).is_some() {
+            self.0.persist(&counters)?;
+        }
+        Ok(())
+    }
+}
+
+fn announced(counters: &SessionCounters) -> SessionHello {
+    SessionHello {
+        epoch: counters.epoch,
+        peer_epoch: counters.peer_epoch,
+        received_through: counters.received.highest().max(counters.received_through),
+    }
+}
+
+/// Random nonzero epoch
+fn new_epoch() -> u64 {
+    rand::thread_rng().next_u64().max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replayed(result: Result<()>) -> bool {
+        matches!(result, Err(ProtocolError::Replayed(_)))
+    }
+
+    #[test]
+    fn test_window_accepts_reordered_frames_once() {
+        let mut window = ReplayWindow::new();
+        for sequence in [1, 3, 2, 10, 5] {
+            window.accept(sequence).unwrap();
+        }
+        assert_eq!(window.highest(), 10);
+        for sequence in [0, 1, 3, 10] {
+            assert!(replayed(window.accept(sequence)));
+        }
+        window.accept(4).unwrap();
+
+        // Far ahead: everything behind the window is rejected, the rest is new
+        let ahead = 10 + REPLAY_WINDOW * 3;
+        window.accept(ahead).unwrap();
+        assert!(replayed(window.accept(ahead - REPLAY_WINDOW)));
+        window.accept(ahead - REPLAY_WINDOW + 1).unwrap();
+        window.accept(ahead - 1).unwrap();
+        assert!(replayed(window.accept(ahead - 1)));
+    }
+
+    /// Exchange hellos between two stores and resume both guards
+    fn connect(laptop: &ReplayStore, phone: &ReplayStore) -> (ReplayGuard, ReplayGuard) {
+        let (to_phone, to_laptop) = (laptop.hello("s1").unwrap(), phone.hello("s1").unwrap());
+        (
+            laptop.resume("s1", &to_laptop).unwrap(),
+            phone.resume("s1", &to_phone).unwrap(),
+        )
+    }
+
+    /// Send `count` frames from `from` to `to`, returning their numbers
+    fn deliver(from: &ReplayGuard, to: &ReplayGuard, count: u64) -> Vec<u64> {
+        (0..count)
+            .map(|_| {
+                let sequence = from.next_sequence().unwrap();
+                to.accept(Some(sequence)).unwrap();
+                sequence
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_counters_survive_resumption() {
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop_path, phone_path) = (
+            dir.path().join("laptop.json"),
+            dir.path().join("phone.json"),
+        );
+        let (laptop, phone) = (
+            ReplayStore::open(&laptop_path).unwrap(),
+            ReplayStore::open(&phone_path).unwrap(),
+        );
+        let (sending, received) = connect(&laptop, &phone);
+        let captured = deliver(&sending, &received, 3);
+        laptop.save("s1", &sending).unwrap();
+        phone.save("s1", &received).unwrap();
+
+        // After reconnecting, counting continues and old frames stay rejected
+        let (laptop, phone) = (
+            ReplayStore::open(&laptop_path).unwrap(),
+            ReplayStore::open(&phone_path).unwrap(),
+        );
+        let (sending, received) = connect(&laptop, &phone);
+        let next = sending.next_sequence().unwrap();
+        assert!(next > captured[2]);
+        assert!(replayed(received.accept(Some(captured[1]))));
+        assert!(received.accept(None).is_err());
+        received.accept(Some(next)).unwrap();
+
+        phone.end("s1").unwrap();
+        let fresh = ReplayStore::open(&phone_path)
+            .unwrap()
+            .resume("s1", &SessionHello::default())
+            .unwrap();
+        assert_eq!(fresh.counters().sent, 0);
+        assert_eq!(fresh.counters().received, ReplayWindow::through(0));
+    }
+
+    #[test]
+    fn test_counters_survive_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop_path, phone_path) = (
+            dir.path().join("laptop.json"),
+            dir.path().join("phone.json"),
+        );
+        let (sending, received) = connect(
+            &ReplayStore::open(&laptop_path).unwrap(),
+            &ReplayStore::open(&phone_path).unwrap(),
+        );
+        let captured = deliver(&sending, &received, SEQUENCE_RESERVATION + 5);
+        // Both devices die without saving their counters
+
+        let (sending, received) = connect(
+            &ReplayStore::open(&laptop_path).unwrap(),
+            &ReplayStore::open(&phone_path).unwrap(),
+        );
+        for sequence in [captured[0], *captured.last().unwrap()] {
+            assert!(replayed(received.accept(Some(sequence))));
+        }
+        let next = sending.next_sequence().unwrap();
+        assert!(next > *captured.last().unwrap());
+        received.accept(Some(next)).unwrap();
+        // No temporary file is left next to the stores
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_reconnect_after_store_lost() {
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop_path, phone_path) = (
+            dir.path().join("laptop.json"),
+            dir.path().join("phone.json"),
+        );
+        let laptop = ReplayStore::open(&laptop_path).unwrap();
+        let phone = ReplayStore::open(&phone_path).unwrap();
+        let (on_laptop, on_phone) = connect(&laptop, &phone);
+        deliver(&on_laptop, &on_phone, 10);
+        deliver(&on_phone, &on_laptop, 10);
+        laptop.save("s1", &on_laptop).unwrap();
+        phone.save("s1", &on_phone).unwrap();
+
+        // The phone's data directory is cleared: it counts from 1 again
+        std::fs::remove_file(&phone_path).unwrap();
+        let phone = ReplayStore::open(&phone_path).unwrap();
+        let (on_laptop, on_phone) = connect(&laptop, &phone);
+        assert_eq!(on_phone.next_sequence().unwrap(), 1);
+        on_laptop.accept(Some(1)).unwrap();
+        deliver(&on_laptop, &on_phone, 3);
+        laptop.save("s1", &on_laptop).unwrap();
+        phone.save("s1", &on_phone).unwrap();
+
+        // So is the laptop's, while the phone keeps its counters
+        std::fs::remove_file(&laptop_path).unwrap();
+        let laptop = ReplayStore::open(&laptop_path).unwrap();
+        let (on_laptop, on_phone) = connect(&laptop, &phone);
+        assert_eq!(on_laptop.next_sequence().unwrap(), 1);
+        on_phone.accept(Some(1)).unwrap();
+        deliver(&on_phone, &on_laptop, 3);
+        // The new sessions still reject their own repeats
+        assert!(replayed(on_phone.accept(Some(1))));
+        assert!(replayed(on_laptop.accept(Some(1))));
+    }
+
+    #[test]
+    fn test_stale_restore_skips_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        let (laptop_path, phone_path) = (
+            dir.path().join("laptop.json"),
+            dir.path().join("phone.json"),
+        );
+        let laptop = ReplayStore::open(&laptop_path).unwrap();
+        let phone = ReplayStore::open(&phone_path).unwrap();
+        let (on_laptop, on_phone) = connect(&laptop, &phone);
+        laptop.save("s1", &on_laptop).unwrap();
+        phone.save("s1", &on_phone).unwrap();
+        let backup = std::fs::read(&laptop_path).unwrap();
+
+        let (on_laptop, on_phone) = connect(&laptop, &phone);
+        let sent = deliver(&on_laptop, &on_phone, SEQUENCE_RESERVATION * 3);
+        laptop.save("s1", &on_laptop).unwrap();
+        phone.save("s1", &on_phone).unwrap();
+
+        // The laptop is restored from a backup taken before those frames
+        std::fs::write(&laptop_path, backup).unwrap();
+        let laptop = ReplayStore::open(&laptop_path).unwrap();
+        let (on_laptop, on_phone) = connect(&laptop, &phone);
+        let next = on_laptop.next_sequence().unwrap();
+        assert!(next > *sent.last().unwrap());
+        on_phone.accept(Some(next)).unwrap();
+    }
+}
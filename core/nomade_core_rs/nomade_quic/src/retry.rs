@@ -0,0 +1,188 @@
+//! Retrying transient network failures
+//!
+//! `RetryPolicy` runs an operation again when it fails with an error its
+//! type classifies as transient (`Retryable`): a timeout, a reset
+//! connection, a transport hiccup. Refusals, protocol violations and bad
+//! data fail at once. Attempts are spaced by an exponentially growing
+//! delay, capped at `max_delay`, of which a random half is skipped
+//! ("equal jitter") so devices that lost the same peer do not retry in
+//! lockstep. Retries, and operations that ran out of attempts, are
+//! counted in metrics.
+
+use std::future::Future;
+use std::time::Duration;
+
+use nomade_metrics::names;
+use rand::Rng;
+
+use crate::ProtocolError;
+
+/// Errors that can tell transient failures from permanent ones
+pub trait Retryable {
+    /// Whether trying the same operation again may succeed
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for ProtocolError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::PeerTimeout | Self::Timeout(_) | Self::Transport(_) => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::Interrupted
+                    | std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// How often and how patiently to retry an operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first included; 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Try once, never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Longest wait before retry number `retry` (0 for the first)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Wait before retry number `retry`: between half and all of `backoff`
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        let half = backoff / 2;
+        half + rand::thread_rng().gen_range(Duration::ZERO..=backoff - half)
+    }
+
+    /// Run `attempt` until it succeeds, fails for good or runs out of
+    /// attempts, returning the last result
+    ///
+    /// `operation` names what is retried in logs.
+    pub async fn run<T, E, F, Fut>(&self, operation: &str, mut attempt: F) -> Result<T, E>
+    where
+        E: Retryable + std::fmt::Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut retry = 0;
+        loop {
+            let error = match attempt().await {
+                Ok(value) => return Ok(value),
+                Err(e) if !e.is_retryable() => return Err(e),
+                Err(e) => e,
+            };
+            let metrics = nomade_metrics::global();
+            if retry + 1 >= self.max_attempts {
+                if self.max_attempts > 1 {
+                    metrics
+                        .counter(
+                            names::NETWORK_RETRIES_EXHAUSTED,
+                            "Network operations that failed after every retry",
+                        )
+                        .inc();
+                }
+                return Err(error);
+            }
+            let delay = self.delay(retry);
+            tracing::debug!("{} failed ({}), retrying in {:?}", operation, error, delay);
+            metrics
+                .counter(
+                    names::NETWORK_RETRIES,
+                    "Network operations retried after a transient failure",
+                )
+                .inc();
+            tokio::time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(1600));
+        assert_eq!(policy.backoff(10), Duration::from_secs(5));
+        assert_eq!(policy.backoff(40), Duration::from_secs(5));
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_only_transient_failures() {
+        let policy = RetryPolicy::default();
+        let calls = AtomicU32::new(0);
+        let result = policy
+            .run("flaky", || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err(ProtocolError::PeerTimeout),
+                    n => Ok(n),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<(), _> = policy
+            .run("refused", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(ProtocolError::PeerRejected("revoked".into()))
+            })
+            .await;
+        assert!(matches!(result, Err(ProtocolError::PeerRejected(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        calls.store(0, Ordering::SeqCst);
+        let exhausted = nomade_metrics::global().counter(names::NETWORK_RETRIES_EXHAUSTED, "");
+        let before = exhausted.get();
+        let result: Result<(), _> = policy
+            .run("down", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(ProtocolError::Transport("unreachable".into()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert!(exhausted.get() > before);
+    }
+}
@@ -0,0 +1,24 @@
+//! End-to-end sealing of frame payloads
+//!
+//! Transport encryption ends at each device, and nothing in a frame says
+//! who wrote it. When both peers negotiate `FeatureFlags::SIGNED_FRAMES`,
+//! a link installs a `FrameSeal` on its channels: every payload sent on a
+//! `SyncMeta` or `LiveEvents` channel is sealed (encrypted and signed by
+//! the sending device), and incoming payloads are opened and checked
+//! before anything reads them. The runtime supplies the seal, since only
+//! it holds the keys and the trust store.
+//!
+//! Chunk transfers stay unsealed: chunks are checked against the content
+//! hashes of sealed manifests. Control frames that matter carry their own
+//! signatures.
+
+use crate::Result;
+
+/// Seals outgoing payloads and opens incoming ones for one peer
+pub trait FrameSeal: Send + Sync {
+    /// Seal the payload of a frame about to be sent
+    fn seal(&self, payload: &[u8]) -> Result<Vec<u8>>;
+
+    /// Open a sealed payload, rejecting it unless the peer sealed it
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>>;
+}
@@ -0,0 +1,128 @@
+//! Timeouts per protocol phase
+//!
+//! `Timeouts` bounds each phase of talking to a peer: the transport and
+//! TLS handshake, silence on an established connection, one request and
+//! its reply, and one chunk of a content transfer (so a transfer that
+//! stalls is noticed however large it is). A phase that runs out fails
+//! with `ProtocolError::Timeout` naming it, which callers may retry
+//! (see `retry`). Zero disables a timeout.
+
+use std::future::Future;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ProtocolError;
+
+/// Phase of a connection a timeout applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutPhase {
+    /// Transport, TLS and WebSocket handshakes
+    Handshake,
+    /// Established connection with no traffic
+    Idle,
+    /// One request and its reply
+    Request,
+    /// One chunk of a content transfer
+    ChunkStall,
+}
+
+impl std::fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Handshake => "handshake",
+            Self::Idle => "idle connection",
+            Self::Request => "request",
+            Self::ChunkStall => "chunk transfer",
+        })
+    }
+}
+
+/// Timeout of each phase in seconds; zero disables one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// Connecting, up to an authenticated connection
+    pub handshake_secs: u64,
+    /// Silence after which a connection is closed
+    pub idle_secs: u64,
+    /// Sending a request and receiving its whole reply
+    pub request_secs: u64,
+    /// Receiving one chunk of content, after which the transfer is
+    /// considered stalled
+    pub chunk_stall_secs: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            handshake_secs: 10,
+            idle_secs: 30,
+            request_secs: 30,
+            chunk_stall_secs: 20,
+        }
+    }
+}
+
+impl Timeouts {
+    /// Timeout of `phase`, `None` if disabled
+    pub fn get(&self, phase: TimeoutPhase) -> Option<Duration> {
+        let secs = match phase {
+            TimeoutPhase::Handshake => self.handshake_secs,
+            TimeoutPhase::Idle => self.idle_secs,
+            TimeoutPhase::Request => self.request_secs,
+            TimeoutPhase::ChunkStall => self.chunk_stall_secs,
+        };
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Run `future`, failing with `ProtocolError::Timeout(phase)` if it
+    /// outlasts the timeout of `phase`
+    pub async fn enforce<T, E, F>(&self, phase: TimeoutPhase, future: F) -> Result<T, E>
+    where
+        E: From<ProtocolError>,
+        F: Future<Output = Result<T, E>>,
+    {
+        match self.get(phase) {
+            Some(limit) => tokio::time::timeout(limit, future)
+                .await
+                .map_err(|_| ProtocolError::Timeout(phase))?,
+            None => future.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_enforce_names_the_phase() {
+        let timeouts = Timeouts {
+            request_secs: 1,
+            chunk_stall_secs: 0,
+            ..Timeouts::default()
+        };
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok::<_, ProtocolError>(())
+        };
+
+        let result = timeouts.enforce(TimeoutPhase::Request, slow()).await;
+        assert!(matches!(
+            result,
+            Err(ProtocolError::Timeout(TimeoutPhase::Request))
+        ));
+        assert_eq!(result.unwrap_err().to_string(), "Timed out during request");
+        assert!(timeouts
+            .enforce(TimeoutPhase::Handshake, slow())
+            .await
+            .is_ok());
+        // Disabled: waits as long as it takes
+        assert!(timeouts
+            .enforce(TimeoutPhase::ChunkStall, slow())
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,302 @@
+//! TLS certificates bound to device identities
+//!
+//! Each endpoint generates a fresh TLS key and a self-signed certificate
+//! carrying a binding extension: the device's Ed25519 public key and its
+//! signature over the certificate's SubjectPublicKeyInfo. Both sides of a
+//! handshake verify the binding, so a completed TLS handshake proves which
+//! `DeviceId` holds the TLS key. Whether that device is trusted is left to
+//! the `TrustStore`, as with any other handshake.
+
+use std::sync::Arc;
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use nomade_crypto::{DeviceId, DeviceKeypair};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, SignatureScheme};
+use x509_parser::oid_registry::Oid;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::quic::transport_error;
+use crate::limits::ConnectionGuard;
+use crate::{ProtocolError, Result};
+
+/// OID of the identity binding extension (private enterprise arc)
+const BINDING_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 61_462, 1, 1];
+/// Domain separation for the binding signature
+const BINDING_CONTEXT: &[u8] = b"nomade-tls-binding-v1";
+const PUBLIC_KEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// Self-signed certificate and key for a device
+pub(super) struct DeviceCertificate {
+    pub cert: CertificateDer<'static>,
+    pub key: PrivatePkcs8KeyDer<'static>,
+}
+
+impl DeviceCertificate {
+    /// Generate a TLS key and a certificate binding it to `keypair`
+    pub fn generate(keypair: &DeviceKeypair, server_name: &str) -> Result<Self> {
+        let tls_key = rcgen::KeyPair::generate().map_err(transport_error)?;
+        let mut binding = keypair.public_key_bytes();
+        binding.extend_from_slice(
+            &keypair
+                .sign(&binding_payload(&tls_key.public_key_der()))
+                .map_err(|e| ProtocolError::Transport(e.to_string()))?
+                .to_bytes(),
+        );
+        self_signed(&tls_key, server_name, binding)
+    }
+}
+
+fn self_signed(
+    tls_key: &rcgen::KeyPair,
+    server_name: &str,
+    binding: Vec<u8>,
+) -> Result<DeviceCertificate> {
+    let mut params =
+        rcgen::CertificateParams::new(vec![server_name.to_string()]).map_err(transport_error)?;
+    params
+        .custom_extensions
+        .push(rcgen::CustomExtension::from_oid_content(
+            BINDING_OID,
+            binding,
+        ));
+    let cert = params.self_signed(tls_key).map_err(transport_error)?;
+    Ok(DeviceCertificate {
+        cert: cert.der().clone(),
+        key: PrivatePkcs8KeyDer::from(tls_key.serialize_der()),
+    })
+}
+
+fn binding_payload(spki: &[u8]) -> Vec<u8> {
+    [BINDING_CONTEXT, spki].concat()
+}
+
+/// Device proven by a certificate's identity binding
+pub fn verify_certificate(cert: &CertificateDer<'_>) -> Result<DeviceId> {
+    let invalid = |reason: &str| ProtocolError::PeerRejected(format!("Certificate {}", reason));
+    let (_, parsed) =
+        X509Certificate::from_der(cert.as_ref()).map_err(|_| invalid("is malformed"))?;
+    if !parsed.validity().is_valid() {
+        return Err(invalid("has expired"));
+    }
+
+    let oid = Oid::from(BINDING_OID).map_err(|_| invalid("binding OID is invalid"))?;
+    let binding = parsed
+        .extensions()
+        .iter()
+        .find(|extension| extension.oid == oid)
+        .ok_or_else(|| invalid("has no identity binding"))?
+        .value;
+    let Some((public_key, signature)) = binding
+        .split_first_chunk::<PUBLIC_KEY_LEN>()
+        .filter(|(_, signature)| signature.len() == SIGNATURE_LEN)
+    else {
+        return Err(invalid("has a malformed identity binding"));
+    };
+    let public_key =
+        VerifyingKey::from_bytes(public_key).map_err(|_| invalid("has an invalid identity key"))?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| invalid("has a malformed identity binding"))?;
+    public_key
+        .verify(&binding_payload(parsed.public_key().raw), &signature)
+        .map_err(|_| invalid("identity binding signature is invalid"))?;
+    Ok(DeviceId::from_public_key(&public_key))
+}
+
+/// Verifies identity bindings on both client and server certificates
+#[derive(Debug)]
+pub(super) struct DeviceCertVerifier {
+    provider: Arc<CryptoProvider>,
+    /// Device the server must prove to be; any device if `None`
+    expected: Option<DeviceId>,
+    /// Admission of connecting clients in paired-only mode
+    guard: Option<Arc<ConnectionGuard>>,
+}
+
+impl DeviceCertVerifier {
+    pub fn new(provider: Arc<CryptoProvider>, expected: Option<DeviceId>) -> Self {
+        Self {
+            provider,
+            expected,
+            guard: None,
+        }
+    }
+
+    /// Reject devices the guard does not admit during the handshake
+    pub fn with_guard(mut self, guard: Option<Arc<ConnectionGuard>>) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    fn verify(&self, cert: &CertificateDer<'_>) -> std::result::Result<(), rustls::Error> {
+        let device_id = verify_certificate(cert).map_err(|e| {
+            tracing::debug!("Rejected peer certificate: {}", e);
+            rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure)
+        })?;
+        match &self.expected {
+            Some(expected) if *expected != device_id => {
+                return Err(rustls::Error::InvalidCertificate(
+                    CertificateError::NotValidForName,
+                ))
+            }
+            _ => {}
+        }
+        match &self.guard {
+            Some(guard) if !guard.allows_device(&device_id) => Err(
+                rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn verify_tls12(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+}
+
+impl ServerCertVerifier for DeviceCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        self.verify(end_entity)?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_tls12(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_tls13(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl ClientCertVerifier for DeviceCertVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> std::result::Result<ClientCertVerified, rustls::Error> {
+        self.verify(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_tls12(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_tls13(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::generate_keypair;
+
+    #[test]
+    fn test_binding_proves_device() {
+        let keypair = generate_keypair();
+        let certified = DeviceCertificate::generate(&keypair, "nomade").unwrap();
+        assert_eq!(
+            verify_certificate(&certified.cert).unwrap(),
+            *keypair.device_id()
+        );
+    }
+
+    #[test]
+    fn test_rejects_unbound_and_transplanted_bindings() {
+        let plain = rcgen::generate_simple_self_signed(vec!["nomade".into()]).unwrap();
+        assert!(verify_certificate(plain.cert.der()).is_err());
+
+        // A binding copied onto a certificate for another TLS key
+        let keypair = generate_keypair();
+        let bound_key = rcgen::KeyPair::generate().unwrap();
+        let mut binding = keypair.public_key_bytes();
+        binding.extend_from_slice(
+            &keypair
+                .sign(&binding_payload(&bound_key.public_key_der()))
+                .unwrap()
+                .to_bytes(),
+        );
+        let attacker_key = rcgen::KeyPair::generate().unwrap();
+        let forged = self_signed(&attacker_key, "nomade", binding.clone()).unwrap();
+        assert!(verify_certificate(&forged.cert).is_err());
+        assert!(
+            verify_certificate(&self_signed(&bound_key, "nomade", binding).unwrap().cert).is_ok()
+        );
+
+        assert!(verify_certificate(&CertificateDer::from(vec![0u8; 16])).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! QUIC with WebSocket fallback
+//!
+//! Dials QUIC first. When no QUIC handshake completes within the timeout,
+//! the usual sign of a network dropping UDP, the same address is retried
+//! over WebSocket on TCP. Both listeners use the same port number, so one
+//! advertised endpoint reaches either. Addresses that needed the fallback
+//! go straight to WebSocket for a while instead of waiting out QUIC again.
+//!
+//! With a proxy configured every dial uses WebSocket through the proxy:
+//! proxies only tunnel TCP.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use nomade_crypto::{DeviceId, DeviceKeypair};
+use tokio::sync::mpsc;
+
+use super::{BoxFuture, Connection, QuicTransport, Transport, WebSocketTransport};
+use crate::limits::ConnectionGuard;
+use crate::proxy::ProxyConfig;
+use crate::timeout::Timeouts;
+use crate::Result;
+
+/// Wait for a QUIC handshake before falling back to WebSocket
+const DEFAULT_QUIC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long an address that needed the fallback skips QUIC
+const RETRY_QUIC_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// Connections accepted by either listener, waiting for `accept`
+const ACCEPT_QUEUE: usize = 16;
+
+type Accepted = Result<Arc<dyn Connection>>;
+
+/// QUIC endpoint with a WebSocket listener on the same port
+pub struct FallbackTransport {
+    quic: Arc<QuicTransport>,
+    websocket: Arc<WebSocketTransport>,
+    quic_timeout: Duration,
+    proxy: Option<ProxyConfig>,
+    /// When each address last needed the fallback
+    fell_back: Mutex<HashMap<String, Instant>>,
+    accepted: tokio::sync::Mutex<mpsc::Receiver<Accepted>>,
+}
+
+impl FallbackTransport {
+    /// Bind QUIC to a local UDP address and WebSocket to the same TCP port
+    pub fn bind(addr: SocketAddr, keypair: &DeviceKeypair) -> Result<Self> {
+        let quic = QuicTransport::bind(addr, keypair)?;
+        let websocket = WebSocketTransport::bind(Self::tcp_addr(addr, &quic)?, keypair)?;
+        Ok(Self::new(quic, websocket))
+    }
+
+    /// Bind both listeners, policing incoming connections with `guard`
+    /// and enforcing `timeouts` on both
+    pub fn bind_guarded(
+        addr: SocketAddr,
+        keypair: &DeviceKeypair,
+        guard: Arc<ConnectionGuard>,
+        timeouts: Timeouts,
+    ) -> Result<Self> {
+        let quic =
+            QuicTransport::bind_guarded(addr, keypair, guard.clone())?.with_timeouts(timeouts)?;
+        let websocket =
+            WebSocketTransport::bind_guarded(Self::tcp_addr(addr, &quic)?, keypair, guard)?
+                .with_timeouts(timeouts);
+        Ok(Self::new(quic, websocket))
+    }
+
+    /// Wait `timeout` for QUIC before trying WebSocket
+    pub fn with_quic_timeout(mut self, timeout: Duration) -> Self {
+        self.quic_timeout = timeout;
+        self
+    }
+
+    /// Dial WebSocket through `proxy`, never QUIC
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Port 0 picks a UDP port; TCP then takes the same number
+    fn tcp_addr(addr: SocketAddr, quic: &QuicTransport) -> Result<SocketAddr> {
+        Ok(SocketAddr::new(addr.ip(), quic.socket_addr()?.port()))
+    }
+
+    fn new(quic: QuicTransport, websocket: WebSocketTransport) -> Self {
+        let (queue, accepted) = mpsc::channel(ACCEPT_QUEUE);
+        let quic = Arc::new(quic);
+        let websocket = Arc::new(websocket);
+        // Accept on both listeners in the background, so a handshake in
+        // progress on one is never abandoned for a connection on the other
+        tokio::spawn(forward_accepts(quic.clone(), queue.clone()));
+        tokio::spawn(forward_accepts(websocket.clone(), queue));
+        Self {
+            quic,
+            websocket,
+            quic_timeout: DEFAULT_QUIC_TIMEOUT,
+            proxy: None,
+            fell_back: Mutex::new(HashMap::new()),
+            accepted: tokio::sync::Mutex::new(accepted),
+        }
+    }
+
+    /// Bound socket address, the same port for UDP and TCP
+    pub fn socket_addr(&self) -> Result<SocketAddr> {
+        self.quic.socket_addr()
+    }
+
+    /// Close all QUIC connections and stop accepting on both listeners
+    pub fn close(&self) {
+        self.quic.close();
+        self.websocket.close();
+    }
+
+    /// Connect to `addr`, failing the handshake unless it is `device_id`
+    pub async fn connect_device(
+        &self,
+        addr: &str,
+        device_id: &DeviceId,
+    ) -> Result<Arc<dyn Connection>> {
+        self.dial(addr, Some(device_id)).await
+    }
+
+    async fn dial(&self, addr: &str, expected: Option<&DeviceId>) -> Result<Arc<dyn Connection>> {
+        if self.proxy.is_none() && !self.skips_quic(addr) {
+            let quic = async {
+                match expected {
+                    Some(device_id) => self.quic.connect_device(addr, device_id).await,
+                    None => self.quic.connect(addr).await,
+                }
+            };
+            match tokio::time::timeout(self.quic_timeout, quic).await {
+                Ok(connection) => return connection,
+                Err(_) => {
+                    tracing::debug!("QUIC to {} timed out, falling back to WebSocket", addr);
+                    self.fell_back
+                        .lock()
+                        .unwrap()
+                        .insert(addr.to_string(), Instant::now());
+                }
+            }
+        }
+        self.websocket
+            .dial(addr, expected.cloned(), self.proxy.as_ref())
+            .await
+    }
+
+    /// Whether `addr` needed the fallback recently
+    fn skips_quic(&self, addr: &str) -> bool {
+        let mut fell_back = self.fell_back.lock().unwrap();
+        fell_back.retain(|_, since| since.elapsed() < RETRY_QUIC_AFTER);
+        fell_back.contains_key(addr)
+    }
+}
+
+impl Transport for FallbackTransport {
+    fn local_addr(&self) -> String {
+        self.quic.local_addr()
+    }
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<Arc<dyn Connection>>> {
+        Box::pin(self.dial(addr, None))
+    }
+
+    fn accept(&self) -> BoxFuture<'_, Result<Option<Arc<dyn Connection>>>> {
+        Box::pin(async move {
+            // Ends once both listeners closed and dropped their queues
+            self.accepted.lock().await.recv().await.transpose()
+        })
+    }
+}
+
+impl Drop for FallbackTransport {
+    fn drop(&mut self) {
+        // Lets the accept tasks finish
+        self.close();
+    }
+}
+
+async fn forward_accepts(transport: Arc<dyn Transport>, queue: mpsc::Sender<Accepted>) {
+    loop {
+        let accepted = match transport.accept().await {
+            Ok(Some(connection)) => Ok(connection),
+            Ok(None) => return,
+            Err(e) => Err(e),
+        };
+        if queue.send(accepted).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::generate_keypair;
+
+    #[tokio::test]
+    async fn test_prefers_quic() {
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server_keys = generate_keypair();
+        let server = FallbackTransport::bind(loopback, &server_keys).unwrap();
+        let client = FallbackTransport::bind(loopback, &generate_keypair()).unwrap();
+        let server_addr = server.socket_addr().unwrap();
+        assert_eq!(
+            server.websocket.socket_addr().unwrap().port(),
+            server_addr.port()
+        );
+
+        let connection = client
+            .connect_device(&server_addr.to_string(), server_keys.device_id())
+            .await
+            .unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+        // Came from the client's UDP socket, not an ephemeral TCP port
+        assert_eq!(
+            accepted.remote_addr(),
+            client.socket_addr().unwrap().to_string()
+        );
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+        assert!(!client.skips_quic(&server_addr.to_string()));
+
+        server.close();
+        client.close();
+        assert!(server.accept().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_when_udp_is_blocked() {
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server_keys = generate_keypair();
+        // Only TCP is reachable: nothing answers QUIC on this port
+        let server = Arc::new(WebSocketTransport::bind(loopback, &server_keys).unwrap());
+        let server_addr = server.socket_addr().unwrap().to_string();
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move {
+                let mut accepted = Vec::new();
+                while let Ok(Some(connection)) = server.accept().await {
+                    accepted.push(connection);
+                }
+                accepted.len()
+            }
+        });
+
+        let client = FallbackTransport::bind(loopback, &generate_keypair())
+            .unwrap()
+            .with_quic_timeout(Duration::from_millis(200));
+        let connection = client
+            .connect_device(&server_addr, server_keys.device_id())
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+        assert!(client.skips_quic(&server_addr));
+
+        // Remembered: the next dial skips the QUIC timeout
+        let started = Instant::now();
+        client.connect(&server_addr).await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        server.close();
+        assert_eq!(accept.await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_dials_websocket() {
+        use crate::proxy::ProxyKind;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        // HTTP proxy counting the tunnels it opens
+        let tunnels = Arc::new(AtomicUsize::new(0));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn({
+            let tunnels = tunnels.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tunnels.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(async move {
+                        let mut reader = tokio::io::BufReader::new(stream);
+                        let mut request = String::new();
+                        while !request.ends_with("\r\n\r\n") {
+                            reader.read_line(&mut request).await.unwrap();
+                        }
+                        let target = request.split_whitespace().nth(1).unwrap().to_string();
+                        let mut client = reader.into_inner();
+                        let mut upstream = tokio::net::TcpStream::connect(target).await.unwrap();
+                        client.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await.unwrap();
+                        tokio::io::copy_bidirectional(&mut client, &mut upstream)
+                            .await
+                            .ok();
+                    });
+                }
+            }
+        });
+
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server_keys = generate_keypair();
+        let server = FallbackTransport::bind(loopback, &server_keys).unwrap();
+        let server_addr = server.socket_addr().unwrap().to_string();
+        let client = FallbackTransport::bind(loopback, &generate_keypair())
+            .unwrap()
+            .with_proxy(Some(ProxyConfig::new(ProxyKind::Http, proxy_addr)));
+
+        let connection = client
+            .connect_device(&server_addr, server_keys.device_id())
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+        assert_eq!(connection.remote_addr(), server_addr);
+        assert!(server.accept().await.unwrap().is_some());
+        assert_eq!(tunnels.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,284 @@
+//! In-process transport over tokio duplex pipes
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use nomade_crypto::DeviceId;
+use tokio::io::{DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{BoxFuture, Connection, RecvStream, SendStream, StreamWrite, Transport};
+use crate::{ProtocolError, Result};
+
+/// Bytes buffered in each direction of an in-memory stream
+const STREAM_BUFFER: usize = 256 * 1024;
+
+/// Incoming connections of an endpoint and the device it claims to be
+type Listener = (mpsc::UnboundedSender<Arc<dyn Connection>>, Option<DeviceId>);
+
+/// Registry of in-memory endpoints that can dial each other by name
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<HashMap<String, Listener>>>,
+}
+
+impl MemoryNetwork {
+    /// Create an empty network
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register an endpoint reachable at `addr`
+    pub fn bind(&self, addr: &str) -> Result<MemoryTransport> {
+        self.bind_endpoint(addr, None)
+    }
+
+    /// Register an endpoint of `device_id` reachable at `addr`
+    ///
+    /// Connections to and from it report the device as their peer, like
+    /// after a QUIC handshake. Nothing is proven: only for tests.
+    pub fn bind_device(&self, addr: &str, device_id: DeviceId) -> Result<MemoryTransport> {
+        self.bind_endpoint(addr, Some(device_id))
+    }
+
+    fn bind_endpoint(&self, addr: &str, device: Option<DeviceId>) -> Result<MemoryTransport> {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners.contains_key(addr) {
+            return Err(ProtocolError::Transport(format!(
+                "Address already in use: {}",
+                addr
+            )));
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        listeners.insert(addr.to_string(), (tx, device.clone()));
+        Ok(MemoryTransport {
+            addr: addr.to_string(),
+            device,
+            network: self.clone(),
+            incoming: tokio::sync::Mutex::new(rx),
+        })
+    }
+}
+
+/// Endpoint on a `MemoryNetwork`; unregistered when dropped
+pub struct MemoryTransport {
+    addr: String,
+    device: Option<DeviceId>,
+    network: MemoryNetwork,
+    incoming: tokio::sync::Mutex<mpsc::UnboundedReceiver<Arc<dyn Connection>>>,
+}
+
+impl Transport for MemoryTransport {
+    fn local_addr(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<Arc<dyn Connection>>> {
+        Box::pin(async move {
+            let (listener, device) = self
+                .network
+                .listeners
+                .lock()
+                .unwrap()
+                .get(addr)
+                .cloned()
+                .ok_or_else(|| ProtocolError::Transport(format!("No endpoint at {}", addr)))?;
+
+            let (mut local, mut remote) = MemoryConnection::pair(&self.addr, addr);
+            local.peer = device;
+            remote.peer = self.device.clone();
+            listener
+                .send(Arc::new(remote))
+                .map_err(|_| ProtocolError::Transport(format!("Endpoint {} closed", addr)))?;
+            Ok(Arc::new(local) as Arc<dyn Connection>)
+        })
+    }
+
+    fn accept(&self) -> BoxFuture<'_, Result<Option<Arc<dyn Connection>>>> {
+        Box::pin(async move { Ok(self.incoming.lock().await.recv().await) })
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        if let Ok(mut listeners) = self.network.listeners.lock() {
+            listeners.remove(&self.addr);
+        }
+    }
+}
+
+/// One side of an in-memory connection
+struct MemoryConnection {
+    remote: String,
+    /// Device bound at the remote endpoint, if any
+    peer: Option<DeviceId>,
+    outgoing: mpsc::UnboundedSender<DuplexStream>,
+    incoming: tokio::sync::Mutex<mpsc::UnboundedReceiver<DuplexStream>>,
+    outgoing_uni: mpsc::UnboundedSender<ReadHalf<DuplexStream>>,
+    incoming_uni: tokio::sync::Mutex<mpsc::UnboundedReceiver<ReadHalf<DuplexStream>>>,
+    closed: CancellationToken,
+}
+
+impl MemoryConnection {
+    /// Both ends of a connection between `a` and `b`, as seen from `a` then `b`
+    fn pair(a: &str, b: &str) -> (Self, Self) {
+        let (to_a, from_b) = mpsc::unbounded_channel();
+        let (to_b, from_a) = mpsc::unbounded_channel();
+        let (uni_to_a, uni_from_b) = mpsc::unbounded_channel();
+        let (uni_to_b, uni_from_a) = mpsc::unbounded_channel();
+        let closed = CancellationToken::new();
+        (
+            Self {
+                remote: b.to_string(),
+                peer: None,
+                outgoing: to_b,
+                incoming: tokio::sync::Mutex::new(from_b),
+                outgoing_uni: uni_to_b,
+                incoming_uni: tokio::sync::Mutex::new(uni_from_b),
+                closed: closed.clone(),
+            },
+            Self {
+                remote: a.to_string(),
+                peer: None,
+                outgoing: to_a,
+                incoming: tokio::sync::Mutex::new(from_a),
+                outgoing_uni: uni_to_a,
+                incoming_uni: tokio::sync::Mutex::new(uni_from_a),
+                closed,
+            },
+        )
+    }
+}
+
+impl StreamWrite for WriteHalf<DuplexStream> {}
+
+fn split(stream: DuplexStream) -> (SendStream, RecvStream) {
+    let (recv, send) = tokio::io::split(stream);
+    (Box::new(send), Box::new(recv))
+}
+
+impl Connection for MemoryConnection {
+    fn remote_addr(&self) -> String {
+        self.remote.clone()
+    }
+
+    fn open_bi(&self) -> BoxFuture<'_, Result<(SendStream, RecvStream)>> {
+        Box::pin(async move {
+            if self.closed.is_cancelled() {
+                return Err(ProtocolError::NotConnected(self.remote.clone()));
+            }
+            let (local, remote) = tokio::io::duplex(STREAM_BUFFER);
+            self.outgoing
+                .send(remote)
+                .map_err(|_| ProtocolError::NotConnected(self.remote.clone()))?;
+            Ok(split(local))
+        })
+    }
+
+    fn accept_bi(&self) -> BoxFuture<'_, Result<Option<(SendStream, RecvStream)>>> {
+        Box::pin(async move {
+            let mut incoming = self.incoming.lock().await;
+            tokio::select! {
+                biased;
+                _ = self.closed.cancelled() => Ok(None),
+                stream = incoming.recv() => Ok(stream.map(split)),
+            }
+        })
+    }
+
+    fn open_uni(&self) -> BoxFuture<'_, Result<SendStream>> {
+        Box::pin(async move {
+            if self.closed.is_cancelled() {
+                return Err(ProtocolError::NotConnected(self.remote.clone()));
+            }
+            let (local, remote) = tokio::io::duplex(STREAM_BUFFER);
+            let (remote, _) = tokio::io::split(remote);
+            self.outgoing_uni
+                .send(remote)
+                .map_err(|_| ProtocolError::NotConnected(self.remote.clone()))?;
+            Ok(split(local).0)
+        })
+    }
+
+    fn accept_uni(&self) -> BoxFuture<'_, Result<Option<RecvStream>>> {
+        Box::pin(async move {
+            let mut incoming = self.incoming_uni.lock().await;
+            tokio::select! {
+                biased;
+                _ = self.closed.cancelled() => Ok(None),
+                stream = incoming.recv() => Ok(stream.map(|recv| Box::new(recv) as RecvStream)),
+            }
+        })
+    }
+
+    fn close(&self) {
+        self.closed.cancel();
+    }
+
+    fn peer_device_id(&self) -> Option<DeviceId> {
+        self.peer.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_streams_between_endpoints() {
+        let network = MemoryNetwork::new();
+        let server = network.bind("server").unwrap();
+        let client = network.bind("client").unwrap();
+        assert!(network.bind("server").is_err());
+        assert!(client.connect("nowhere").await.is_err());
+
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+        assert_eq!(dialed.remote_addr(), "server");
+        assert_eq!(accepted.remote_addr(), "client");
+
+        let (mut send, mut recv) = dialed.open_bi().await.unwrap();
+        send.write_all(b"ping").await.unwrap();
+        let (mut peer_send, mut peer_recv) = accepted.accept_bi().await.unwrap().unwrap();
+        let mut buf = [0u8; 4];
+        peer_recv.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        peer_send.write_all(b"pong").await.unwrap();
+        recv.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        let mut send = accepted.open_uni().await.unwrap();
+        send.write_all(b"news").await.unwrap();
+        drop(send);
+        let mut recv = dialed.accept_uni().await.unwrap().unwrap();
+        let mut news = Vec::new();
+        recv.read_to_end(&mut news).await.unwrap();
+        assert_eq!(news, b"news");
+
+        assert_eq!(dialed.peer_device_id(), None);
+
+        dialed.close();
+        assert!(accepted.accept_bi().await.unwrap().is_none());
+        assert!(accepted.accept_uni().await.unwrap().is_none());
+        assert!(dialed.open_bi().await.is_err());
+        assert!(dialed.open_uni().await.is_err());
+
+        drop(server);
+        assert!(client.connect("server").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_device_endpoints_report_peers() {
+        let network = MemoryNetwork::new();
+        let (laptop, phone) = (DeviceId("laptop".into()), DeviceId("phone".into()));
+        let server = network.bind_device("server", laptop.clone()).unwrap();
+        let client = network.bind_device("client", phone.clone()).unwrap();
+
+        let dialed = client.connect("server").await.unwrap();
+        let accepted = server.accept().await.unwrap().unwrap();
+        assert_eq!(dialed.peer_device_id(), Some(laptop));
+        assert_eq!(accepted.peer_device_id(), Some(phone));
+    }
+}
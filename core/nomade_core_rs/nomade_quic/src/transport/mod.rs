@@ -0,0 +1,117 @@
+//! Transport abstraction
+//!
+//! Sync and pairing only need to dial a peer, accept incoming peers and
+//! open bidirectional streams on a connection. `Transport` and
+//! `Connection` capture exactly that, so the protocol runs unchanged over
+//! real QUIC (`QuicTransport`), over WebSocket where UDP is blocked
+//! (`WebSocketTransport`, or both via `FallbackTransport`) or over
+//! in-process channels (`MemoryTransport`) in tests and the sync
+//! simulator.
+
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use nomade_crypto::DeviceId;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{ProtocolError, Result};
+
+mod cert;
+mod fallback;
+mod memory;
+mod quic;
+mod websocket;
+
+pub use cert::verify_certificate;
+pub use fallback::FallbackTransport;
+pub use memory::{MemoryNetwork, MemoryTransport};
+pub use quic::QuicTransport;
+pub use websocket::WebSocketTransport;
+
+/// Bind on the first free port of `count` starting at `first`
+///
+/// Falls back to a port picked by the OS when all of them are taken; a
+/// `first` of 0 always lets the OS pick.
+pub fn bind_first_free<T>(
+    ip: IpAddr,
+    first: u16,
+    count: u16,
+    mut bind: impl FnMut(SocketAddr) -> Result<T>,
+) -> Result<T> {
+    if first != 0 {
+        for port in (first..=u16::MAX).take(count.max(1).into()) {
+            match bind(SocketAddr::new(ip, port)) {
+                Err(ProtocolError::Io(e)) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                    tracing::debug!("Port {} is busy", port);
+                }
+                result => return result,
+            }
+        }
+        tracing::warn!(
+            "Ports {}..{} are busy, letting the OS pick",
+            first,
+            first.saturating_add(count)
+        );
+    }
+    bind(SocketAddr::new(ip, 0))
+}
+
+/// Boxed future returned by transport methods
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Writable stream half that may support send scheduling
+pub trait StreamWrite: AsyncWrite + Send + Unpin {
+    /// Relative send priority; higher goes first when streams compete
+    ///
+    /// Ignored by transports without stream scheduling.
+    fn set_priority(&mut self, _priority: i32) {}
+}
+
+/// Sending half of a stream
+pub type SendStream = Box<dyn StreamWrite>;
+
+/// Receiving half of a stream
+pub type RecvStream = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Established connection to a peer
+pub trait Connection: Send + Sync {
+    /// Address of the remote side
+    fn remote_addr(&self) -> String;
+
+    /// Open a new bidirectional stream
+    fn open_bi(&self) -> BoxFuture<'_, Result<(SendStream, RecvStream)>>;
+
+    /// Wait for the peer to open a stream; `None` once the connection closed
+    fn accept_bi(&self) -> BoxFuture<'_, Result<Option<(SendStream, RecvStream)>>>;
+
+    /// Open a new unidirectional stream to the peer
+    ///
+    /// The peer may only learn of the stream once something is written.
+    fn open_uni(&self) -> BoxFuture<'_, Result<SendStream>>;
+
+    /// Wait for the peer to open a unidirectional stream; `None` once the
+    /// connection closed
+    fn accept_uni(&self) -> BoxFuture<'_, Result<Option<RecvStream>>>;
+
+    /// Close the connection and all of its streams
+    fn close(&self);
+
+    /// Peer device proven by the transport handshake, if it authenticates
+    fn peer_device_id(&self) -> Option<DeviceId> {
+        None
+    }
+}
+
+/// Endpoint able to dial and accept connections
+pub trait Transport: Send + Sync {
+    /// Address peers can dial to reach this endpoint
+    fn local_addr(&self) -> String;
+
+    /// Connect to a peer; QUIC and WebSocket take `Endpoint` syntax
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<Arc<dyn Connection>>>;
+
+    /// Wait for an incoming connection; `None` once the endpoint closed
+    fn accept(&self) -> BoxFuture<'_, Result<Option<Arc<dyn Connection>>>>;
+}
@@ -0,0 +1,533 @@
+//! QUIC transport backed by quinn
+//!
+//! Connections are mutually authenticated: both endpoints present a
+//! certificate bound to their device identity (see `cert`), so every
+//! connection knows the verified `DeviceId` of its peer.
+//!
+//! Handshakes are bounded by the handshake timeout of `Timeouts`, and
+//! connections silent for the idle timeout are closed by QUIC itself.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use nomade_crypto::{DeviceId, DeviceKeypair, Endpoint};
+use rustls::pki_types::CertificateDer;
+
+use super::cert::{verify_certificate, DeviceCertVerifier, DeviceCertificate};
+use super::{BoxFuture, Connection, RecvStream, SendStream, StreamWrite, Transport};
+use crate::limits::{throttled, ConnectionGuard, TokenBucket};
+use crate::timeout::{TimeoutPhase, Timeouts};
+use crate::{ProtocolError, Result};
+
+/// TLS server name used by every endpoint
+pub(super) const SERVER_NAME: &str = "nomade";
+
+/// UDP endpoint that both dials and accepts QUIC connections
+pub struct QuicTransport {
+    endpoint: quinn::Endpoint,
+    certificate: DeviceCertificate,
+    guard: Option<Arc<ConnectionGuard>>,
+    timeouts: Timeouts,
+}
+
+impl QuicTransport {
+    /// Bind an endpoint to a local UDP address, identified as `keypair`
+    pub fn bind(addr: SocketAddr, keypair: &DeviceKeypair) -> Result<Self> {
+        Self::bind_inner(addr, keypair, None)
+    }
+
+    /// Bind an endpoint whose incoming connections `guard` polices
+    pub fn bind_guarded(
+        addr: SocketAddr,
+        keypair: &DeviceKeypair,
+        guard: Arc<ConnectionGuard>,
+    ) -> Result<Self> {
+        Self::bind_inner(addr, keypair, Some(guard))
+    }
+
+    fn bind_inner(
+        addr: SocketAddr,
+        keypair: &DeviceKeypair,
+        guard: Option<Arc<ConnectionGuard>>,
+    ) -> Result<Self> {
+        let timeouts = Timeouts::default();
+        let certificate = DeviceCertificate::generate(keypair, SERVER_NAME)?;
+        let server = server_config(&certificate, guard.clone(), &timeouts)?;
+        let mut endpoint = quinn::Endpoint::server(server, addr)?;
+        endpoint.set_default_client_config(client_config(&certificate, None, &timeouts)?);
+        Ok(Self {
+            endpoint,
+            certificate,
+            guard,
+            timeouts,
+        })
+    }
+
+    /// Enforce `timeouts` on handshakes and idle connections from now on
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Result<Self> {
+        let server = server_config(&self.certificate, self.guard.clone(), &timeouts)?;
+        self.endpoint.set_server_config(Some(server));
+        self.endpoint
+            .set_default_client_config(client_config(&self.certificate, None, &timeouts)?);
+        self.timeouts = timeouts;
+        Ok(self)
+    }
+
+    /// Bound socket address
+    pub fn socket_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Close all connections and stop accepting new ones
+    pub fn close(&self) {
+        self.endpoint.close(0u32.into(), b"closed");
+    }
+
+    /// Connect to `addr`, failing the handshake unless it is `device_id`
+    pub async fn connect_device(
+        &self,
+        addr: &str,
+        device_id: &DeviceId,
+    ) -> Result<Arc<dyn Connection>> {
+        let config = client_config(&self.certificate, Some(device_id.clone()), &self.timeouts)?;
+        self.dial(addr, Some(config)).await
+    }
+
+    async fn dial(
+        &self,
+        addr: &str,
+        config: Option<quinn::ClientConfig>,
+    ) -> Result<Arc<dyn Connection>> {
+        let endpoint: Endpoint = addr
+            .parse()
+            .map_err(|_| ProtocolError::Transport(format!("Invalid address: {}", addr)))?;
+        let addr = resolve(&endpoint).await?;
+        let connecting = match config {
+            Some(config) => self.endpoint.connect_with(config, addr, SERVER_NAME),
+            None => self.endpoint.connect(addr, SERVER_NAME),
+        };
+        let connecting = connecting.map_err(transport_error)?;
+        let connection = self
+            .timeouts
+            .enforce(TimeoutPhase::Handshake, async {
+                connecting.await.map_err(transport_error)
+            })
+            .await?;
+        tracing::info!("Connected to {} over QUIC", addr);
+        let budget = self
+            .guard
+            .as_ref()
+            .and_then(|guard| guard.connection_budget());
+        Ok(Arc::new(QuicConnection::new(connection, budget)?) as Arc<dyn Connection>)
+    }
+}
+
+impl Transport for QuicTransport {
+    fn local_addr(&self) -> String {
+        self.endpoint
+            .local_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default()
+    }
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<Arc<dyn Connection>>> {
+        Box::pin(self.dial(addr, None))
+    }
+
+    fn accept(&self) -> BoxFuture<'_, Result<Option<Arc<dyn Connection>>>> {
+        Box::pin(async move {
+            let incoming = loop {
+                let Some(incoming) = self.endpoint.accept().await else {
+                    return Ok(None);
+                };
+                let Some(guard) = &self.guard else {
+                    break incoming;
+                };
+                // The client repeats its Initial with the retry token, proving
+                // it owns the source address; nothing is kept until then
+                if guard.limits().stateless_retry
+                    && !incoming.remote_address_validated()
+                    && incoming.may_retry()
+                {
+                    incoming.retry().ok();
+                    continue;
+                }
+                // Refused before spending anything on the handshake
+                let source = incoming.remote_address().ip().to_string();
+                match guard.check_handshake(&source) {
+                    Ok(()) => break incoming,
+                    Err(_) => incoming.refuse(),
+                }
+            };
+            let connection = self
+                .timeouts
+                .enforce(TimeoutPhase::Handshake, async {
+                    incoming.await.map_err(transport_error)
+                })
+                .await?;
+            let budget = self
+                .guard
+                .as_ref()
+                .and_then(|guard| guard.connection_budget());
+            Ok(Some(
+                Arc::new(QuicConnection::new(connection, budget)?) as Arc<dyn Connection>
+            ))
+        })
+    }
+}
+
+/// Socket address to dial for an endpoint
+pub(super) async fn resolve(endpoint: &Endpoint) -> Result<SocketAddr> {
+    match endpoint {
+        Endpoint::DirectV4(_) | Endpoint::DirectV6(_) => {
+            Ok(endpoint.socket_addr().expect("direct endpoint"))
+        }
+        Endpoint::Hostname { host, port } => tokio::net::lookup_host((host.as_str(), *port))
+            .await?
+            .next()
+            .ok_or_else(|| ProtocolError::Transport(format!("Cannot resolve {}", endpoint))),
+        Endpoint::Relay { .. } => Err(ProtocolError::Transport(format!(
+            "Relay endpoints are not supported: {}",
+            endpoint
+        ))),
+    }
+}
+
+struct QuicConnection {
+    connection: quinn::Connection,
+    peer: DeviceId,
+    /// Bytes the peer may send per second, shared by all streams
+    budget: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl QuicConnection {
+    /// Wrap an established connection, reading the verified peer identity
+    fn new(connection: quinn::Connection, budget: Option<Arc<Mutex<TokenBucket>>>) -> Result<Self> {
+        let peer = connection
+            .peer_identity()
+            .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+            .and_then(|certs| certs.first().map(verify_certificate))
+            .unwrap_or_else(|| {
+                Err(ProtocolError::PeerRejected(
+                    "Peer presented no certificate".into(),
+                ))
+            })?;
+        Ok(Self {
+            connection,
+            peer,
+            budget,
+        })
+    }
+}
+
+impl Connection for QuicConnection {
+    fn remote_addr(&self) -> String {
+        self.connection.remote_address().to_string()
+    }
+
+    fn peer_device_id(&self) -> Option<DeviceId> {
+        Some(self.peer.clone())
+    }
+
+    fn open_bi(&self) -> BoxFuture<'_, Result<(SendStream, RecvStream)>> {
+        Box::pin(async move {
+            let (send, recv) = self
+                .connection
+                .open_bi()
+                .await
+                .map_err(|e| ProtocolError::NotConnected(e.to_string()))?;
+            let recv = throttled(&self.budget, Box::new(recv));
+            Ok((Box::new(send) as SendStream, recv))
+        })
+    }
+
+    fn accept_bi(&self) -> BoxFuture<'_, Result<Option<(SendStream, RecvStream)>>> {
+        Box::pin(async move {
+            match self.connection.accept_bi().await {
+                Ok((send, recv)) => Ok(Some((
+                    Box::new(send) as SendStream,
+                    throttled(&self.budget, Box::new(recv)),
+                ))),
+                Err(
+                    quinn::ConnectionError::ApplicationClosed(_)
+                    | quinn::ConnectionError::LocallyClosed,
+                ) => Ok(None),
+                Err(e) => Err(transport_error(e)),
+            }
+        })
+    }
+
+    fn open_uni(&self) -> BoxFuture<'_, Result<SendStream>> {
+        Box::pin(async move {
+            let send = self
+                .connection
+                .open_uni()
+                .await
+                .map_err(|e| ProtocolError::NotConnected(e.to_string()))?;
+            Ok(Box::new(send) as SendStream)
+        })
+    }
+
+    fn accept_uni(&self) -> BoxFuture<'_, Result<Option<RecvStream>>> {
+        Box::pin(async move {
+            match self.connection.accept_uni().await {
+                Ok(recv) => Ok(Some(throttled(&self.budget, Box::new(recv)))),
+                Err(
+                    quinn::ConnectionError::ApplicationClosed(_)
+                    | quinn::ConnectionError::LocallyClosed,
+                ) => Ok(None),
+                Err(e) => Err(transport_error(e)),
+            }
+        })
+    }
+
+    fn close(&self) {
+        self.connection.close(0u32.into(), b"closed");
+    }
+}
+
+impl StreamWrite for quinn::SendStream {
+    fn set_priority(&mut self, priority: i32) {
+        // Fails only once the stream is closed, when priority is moot
+        quinn::SendStream::set_priority(self, priority).ok();
+    }
+}
+
+pub(super) fn transport_error(e: impl std::fmt::Display) -> ProtocolError {
+    ProtocolError::Transport(e.to_string())
+}
+
+fn server_config(
+    certificate: &DeviceCertificate,
+    guard: Option<Arc<ConnectionGuard>>,
+    timeouts: &Timeouts,
+) -> Result<quinn::ServerConfig> {
+    let mut transport = guard
+        .as_ref()
+        .map_or_else(quinn::TransportConfig::default, |guard| {
+            guard.transport_config()
+        });
+    set_idle_timeout(&mut transport, timeouts)?;
+    let crypto = tls_server_config(certificate, guard)?;
+    let crypto =
+        quinn::crypto::rustls::QuicServerConfig::try_from(crypto).map_err(transport_error)?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+    Ok(config)
+}
+
+fn client_config(
+    certificate: &DeviceCertificate,
+    expected: Option<DeviceId>,
+    timeouts: &Timeouts,
+) -> Result<quinn::ClientConfig> {
+    let crypto = tls_client_config(certificate, expected)?;
+    let crypto =
+        quinn::crypto::rustls::QuicClientConfig::try_from(crypto).map_err(transport_error)?;
+    let mut transport = quinn::TransportConfig::default();
+    set_idle_timeout(&mut transport, timeouts)?;
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(Arc::new(transport));
+    Ok(config)
+}
+
+/// Close connections silent for the idle timeout, or never if disabled
+fn set_idle_timeout(transport: &mut quinn::TransportConfig, timeouts: &Timeouts) -> Result<()> {
+    let idle = timeouts
+        .get(TimeoutPhase::Idle)
+        .map(quinn::IdleTimeout::try_from)
+        .transpose()
+        .map_err(transport_error)?;
+    transport.max_idle_timeout(idle);
+    Ok(())
+}
+
+/// TLS 1.3 server settings requiring a device certificate from clients
+pub(super) fn tls_server_config(
+    certificate: &DeviceCertificate,
+    guard: Option<Arc<ConnectionGuard>>,
+) -> Result<rustls::ServerConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = DeviceCertVerifier::new(provider.clone(), None).with_guard(guard);
+    rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(transport_error)?
+        .with_client_cert_verifier(Arc::new(verifier))
+        .with_single_cert(
+            vec![certificate.cert.clone()],
+            certificate.key.clone_key().into(),
+        )
+        .map_err(transport_error)
+}
+
+/// TLS 1.3 client settings, pinning the server to `expected` if given
+pub(super) fn tls_client_config(
+    certificate: &DeviceCertificate,
+    expected: Option<DeviceId>,
+) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(transport_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(DeviceCertVerifier::new(provider, expected)))
+        .with_client_auth_cert(
+            vec![certificate.cert.clone()],
+            certificate.key.clone_key().into(),
+        )
+        .map_err(transport_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::generate_keypair;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_loopback_stream() {
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let (server_keys, client_keys) = (generate_keypair(), generate_keypair());
+        let server = QuicTransport::bind(loopback, &server_keys).unwrap();
+        let client = QuicTransport::bind(loopback, &client_keys).unwrap();
+        let server_addr = server.socket_addr().unwrap().to_string();
+
+        let client_id = client_keys.device_id().clone();
+        let accept = tokio::spawn(async move {
+            let connection = server.accept().await.unwrap().unwrap();
+            assert_eq!(connection.peer_device_id(), Some(client_id));
+            let (mut send, mut recv) = connection.accept_bi().await.unwrap().unwrap();
+            let mut buf = [0u8; 4];
+            recv.read_exact(&mut buf).await.unwrap();
+            send.write_all(&buf).await.unwrap();
+            send.shutdown().await.unwrap();
+            let mut send = connection.open_uni().await.unwrap();
+            send.write_all(b"news").await.unwrap();
+            send.shutdown().await.unwrap();
+            // Keep the connection open until the client has read the echo
+            connection.accept_bi().await.ok();
+        });
+
+        let connection = client.connect(&server_addr).await.unwrap();
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+        let (mut send, mut recv) = connection.open_bi().await.unwrap();
+        send.write_all(b"ping").await.unwrap();
+        let mut echoed = Vec::new();
+        recv.read_to_end(&mut echoed).await.unwrap();
+        assert_eq!(echoed, b"ping");
+        let mut recv = connection.accept_uni().await.unwrap().unwrap();
+        let mut news = Vec::new();
+        recv.read_to_end(&mut news).await.unwrap();
+        assert_eq!(news, b"news");
+
+        connection.close();
+        accept.await.unwrap();
+
+        for unreachable in ["relay:eu-1", "not an endpoint"] {
+            assert!(matches!(
+                client.connect(unreachable).await,
+                Err(ProtocolError::Transport(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_busy_ports_are_skipped() {
+        let keypair = generate_keypair();
+        let ip = "127.0.0.1".parse().unwrap();
+        let taken = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let busy = taken.local_addr().unwrap().port();
+
+        let bind = |addr| QuicTransport::bind(addr, &keypair);
+        let transport = crate::transport::bind_first_free(ip, busy, 1, bind).unwrap();
+        let port = transport.socket_addr().unwrap().port();
+        assert_ne!(port, busy);
+        let next = crate::transport::bind_first_free(ip, port, 2, bind).unwrap();
+        assert_ne!(next.socket_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn test_connect_device_checks_identity() {
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server_keys = generate_keypair();
+        let server = Arc::new(QuicTransport::bind(loopback, &server_keys).unwrap());
+        let client = QuicTransport::bind(loopback, &generate_keypair()).unwrap();
+        let server_addr = server.socket_addr().unwrap().to_string();
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move { while !matches!(server.accept().await, Ok(None)) {} }
+        });
+
+        let impostor = generate_keypair();
+        assert!(matches!(
+            client
+                .connect_device(&server_addr, impostor.device_id())
+                .await,
+            Err(ProtocolError::Transport(_))
+        ));
+        let connection = client
+            .connect_device(&server_addr, server_keys.device_id())
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+
+        server.close();
+        accept.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_guard_rejects_strangers_and_floods() {
+        use crate::limits::RateLimits;
+
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let (server_keys, paired_keys) = (generate_keypair(), generate_keypair());
+        let paired = paired_keys.device_id().clone();
+        let guard = ConnectionGuard::new(RateLimits {
+            handshakes_per_minute: 2,
+            ..Default::default()
+        })
+        .with_filter(Arc::new(move |device_id| *device_id == paired));
+        let server =
+            Arc::new(QuicTransport::bind_guarded(loopback, &server_keys, Arc::new(guard)).unwrap());
+        let server_addr = server.socket_addr().unwrap().to_string();
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move {
+                let mut accepted = Vec::new();
+                loop {
+                    match server.accept().await {
+                        Ok(Some(connection)) => accepted.push(connection.peer_device_id()),
+                        Ok(None) => return accepted,
+                        Err(_) => {}
+                    }
+                }
+            }
+        });
+
+        // Unknown device: refused during the TLS handshake
+        let stranger = QuicTransport::bind(loopback, &generate_keypair()).unwrap();
+        let rejected = match stranger.connect(&server_addr).await {
+            Err(_) => true,
+            Ok(connection) => connection.accept_bi().await.is_err(),
+        };
+        assert!(rejected);
+
+        // Third handshake this minute from the same address is refused
+        let client = QuicTransport::bind(loopback, &paired_keys).unwrap();
+        let connection = client.connect(&server_addr).await.unwrap();
+        assert!(client.connect(&server_addr).await.is_err());
+
+        connection.close();
+        server.close();
+        assert_eq!(
+            accept.await.unwrap(),
+            [Some(paired_keys.device_id().clone())]
+        );
+    }
+}
@@ -0,0 +1,813 @@
+//! WebSocket transport over TLS
+//!
+//! Fallback for networks that drop UDP and with it QUIC. Connections run
+//! over TCP with the same mutually authenticated TLS 1.3 handshake as
+//! `QuicTransport`, so both sides still learn the verified `DeviceId` of
+//! their peer. A WebSocket session on top carries streams as binary
+//! messages, one stream frame each:
+//!
+//! ```text
+//! [stream id: u32 BE][kind: u8][payload]
+//! ```
+//!
+//! Dialers number their streams odd and acceptors even, so both sides
+//! open streams without coordinating. A stream opened with
+//! `KIND_OPEN_UNI` only carries data from its opener. Channels and
+//! framing run on these streams unchanged.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use nomade_crypto::{DeviceId, DeviceKeypair, Endpoint};
+use rustls::pki_types::{CertificateDer, ServerName};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::sync::{CancellationToken, PollSender};
+
+use super::cert::{verify_certificate, DeviceCertificate};
+use super::quic::{resolve, tls_client_config, tls_server_config, transport_error, SERVER_NAME};
+use super::{BoxFuture, Connection, RecvStream, SendStream, StreamWrite, Transport};
+use crate::limits::{throttled, ConnectionGuard, TokenBucket};
+use crate::proxy::ProxyConfig;
+use crate::timeout::{TimeoutPhase, Timeouts};
+use crate::{ProtocolError, Result};
+
+/// Request path of the WebSocket upgrade
+const WS_PATH: &str = "/nomade";
+
+/// Largest payload carried by one stream frame
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// Stream ID and kind ahead of each payload
+const HEADER_LEN: usize = 5;
+
+/// Frames queued for the socket before writers wait
+const OUTGOING_QUEUE: usize = 64;
+
+/// Peer opened a stream
+const KIND_OPEN: u8 = 0;
+/// Bytes on a stream
+const KIND_DATA: u8 = 1;
+/// Peer finished sending on a stream
+const KIND_FIN: u8 = 2;
+/// Peer opened a stream it only sends on
+const KIND_OPEN_UNI: u8 = 3;
+
+/// TCP listener that both dials and accepts WebSocket connections
+pub struct WebSocketTransport {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    certificate: DeviceCertificate,
+    guard: Option<Arc<ConnectionGuard>>,
+    proxy: Option<ProxyConfig>,
+    /// The handshake timeout covers the TCP, TLS and WebSocket handshakes
+    timeouts: Timeouts,
+    closed: CancellationToken,
+}
+
+impl WebSocketTransport {
+    /// Bind a listener to a local TCP address, identified as `keypair`
+    pub fn bind(addr: SocketAddr, keypair: &DeviceKeypair) -> Result<Self> {
+        Self::bind_inner(addr, keypair, None)
+    }
+
+    /// Bind a listener whose incoming connections `guard` polices
+    pub fn bind_guarded(
+        addr: SocketAddr,
+        keypair: &DeviceKeypair,
+        guard: Arc<ConnectionGuard>,
+    ) -> Result<Self> {
+        Self::bind_inner(addr, keypair, Some(guard))
+    }
+
+    fn bind_inner(
+        addr: SocketAddr,
+        keypair: &DeviceKeypair,
+        guard: Option<Arc<ConnectionGuard>>,
+    ) -> Result<Self> {
+        let certificate = DeviceCertificate::generate(keypair, SERVER_NAME)?;
+        let server = tls_server_config(&certificate, guard.clone())?;
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: TcpListener::from_std(listener)?,
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            certificate,
+            guard,
+            proxy: None,
+            timeouts: Timeouts::default(),
+            closed: CancellationToken::new(),
+        })
+    }
+
+    /// Dial through `proxy` instead of connecting directly
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Enforce the handshake timeout of `timeouts`
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Bound socket address
+    pub fn socket_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Stop accepting new connections
+    ///
+    /// Established connections stay open until closed or dropped.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// Connect to `addr`, failing the handshake unless it is `device_id`
+    pub async fn connect_device(
+        &self,
+        addr: &str,
+        device_id: &DeviceId,
+    ) -> Result<Arc<dyn Connection>> {
+        self.dial(addr, Some(device_id.clone()), self.proxy.as_ref())
+            .await
+    }
+
+    /// Connect to `addr`, through `proxy` if given
+    pub(super) async fn dial(
+        &self,
+        addr: &str,
+        expected: Option<DeviceId>,
+        proxy: Option<&ProxyConfig>,
+    ) -> Result<Arc<dyn Connection>> {
+        let endpoint: Endpoint = addr
+            .parse()
+            .map_err(|_| ProtocolError::Transport(format!("Invalid address: {}", addr)))?;
+        let connector =
+            TlsConnector::from(Arc::new(tls_client_config(&self.certificate, expected)?));
+        let handshake = async {
+            let (tcp, remote) = match proxy {
+                Some(proxy) => (proxy.connect(&endpoint).await?, endpoint.to_string()),
+                None => {
+                    let addr = resolve(&endpoint).await?;
+                    let tcp = TcpStream::connect(addr).await?;
+                    tcp.set_nodelay(true)?;
+                    (tcp, addr.to_string())
+                }
+            };
+            let server_name = ServerName::try_from(SERVER_NAME).expect("valid server name");
+            let tls = connector
+                .connect(server_name, tcp)
+                .await
+                .map_err(transport_error)?;
+            let peer = peer_identity(tls.get_ref().1.peer_certificates())?;
+            let url = format!("ws://{}{}", endpoint, WS_PATH);
+            let (ws, _) = tokio_tungstenite::client_async_with_config(url, tls, Some(ws_config()))
+                .await
+                .map_err(transport_error)?;
+            Ok::<_, ProtocolError>((ws, peer, remote))
+        };
+        let (ws, peer, remote) = self
+            .timeouts
+            .enforce(TimeoutPhase::Handshake, handshake)
+            .await?;
+        match proxy {
+            Some(proxy) => tracing::info!("Connected to {} over WebSocket via {}", remote, proxy),
+            None => tracing::info!("Connected to {} over WebSocket", remote),
+        }
+        let connection = WsConnection::new(ws, remote, peer, 1, self.guard.as_deref());
+        Ok(Arc::new(connection) as Arc<dyn Connection>)
+    }
+
+    async fn handshake(&self, tcp: TcpStream, source: SocketAddr) -> Result<Arc<dyn Connection>> {
+        tcp.set_nodelay(true)?;
+        let tls = self.acceptor.accept(tcp).await.map_err(transport_error)?;
+        let peer = peer_identity(tls.get_ref().1.peer_certificates())?;
+        let ws =
+            tokio_tungstenite::accept_hdr_async_with_config(tls, check_path, Some(ws_config()))
+                .await
+                .map_err(transport_error)?;
+        Ok(Arc::new(WsConnection::new(
+            ws,
+            source.to_string(),
+            peer,
+            2,
+            self.guard.as_deref(),
+        )) as Arc<dyn Connection>)
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn local_addr(&self) -> String {
+        self.listener
+            .local_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default()
+    }
+
+    fn connect<'a>(&'a self, addr: &'a str) -> BoxFuture<'a, Result<Arc<dyn Connection>>> {
+        Box::pin(self.dial(addr, None, self.proxy.as_ref()))
+    }
+
+    fn accept(&self) -> BoxFuture<'_, Result<Option<Arc<dyn Connection>>>> {
+        Box::pin(async move {
+            let (tcp, source) = loop {
+                let (tcp, source) = tokio::select! {
+                    _ = self.closed.cancelled() => return Ok(None),
+                    accepted = self.listener.accept() => accepted?,
+                };
+                let Some(guard) = &self.guard else {
+                    break (tcp, source);
+                };
+                // Refused before spending anything on the handshake
+                if guard.check_handshake(&source.ip().to_string()).is_ok() {
+                    break (tcp, source);
+                }
+            };
+            tokio::select! {
+                _ = self.closed.cancelled() => Ok(None),
+                connection = self.timeouts.enforce(TimeoutPhase::Handshake, self.handshake(tcp, source)) => {
+                    connection.map(Some)
+                }
+            }
+        })
+    }
+}
+
+fn ws_config() -> WebSocketConfig {
+    let max = Some(HEADER_LEN + MAX_CHUNK);
+    WebSocketConfig::default()
+        .max_message_size(max)
+        .max_frame_size(max)
+}
+
+/// Only upgrade requests for `WS_PATH`
+// Signature fixed by tungstenite's handshake callback
+#[allow(clippy::result_large_err)]
+fn check_path(
+    request: &Request,
+    response: Response,
+) -> std::result::Result<Response, ErrorResponse> {
+    if request.uri().path() == WS_PATH {
+        return Ok(response);
+    }
+    let mut error = ErrorResponse::new(None);
+    *error.status_mut() = StatusCode::NOT_FOUND;
+    Err(error)
+}
+
+fn peer_identity(certs: Option<&[CertificateDer<'static>]>) -> Result<DeviceId> {
+    certs
+        .and_then(|certs| certs.first())
+        .map(verify_certificate)
+        .unwrap_or_else(|| {
+            Err(ProtocolError::PeerRejected(
+                "Peer presented no certificate".into(),
+            ))
+        })
+}
+
+fn stream_frame(id: u32, kind: u8, payload: &[u8]) -> Message {
+    let mut frame = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    frame.put_u32(id);
+    frame.put_u8(kind);
+    frame.put_slice(payload);
+    Message::Binary(frame.freeze())
+}
+
+fn not_connected() -> ProtocolError {
+    ProtocolError::NotConnected("connection closed".into())
+}
+
+fn closed_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotConnected, "connection closed")
+}
+
+/// Receiving side of one stream, fed by the session's reader
+type Inbox = mpsc::UnboundedSender<std::io::Result<Bytes>>;
+
+/// Payloads arriving on one stream
+type Incoming = mpsc::UnboundedReceiver<std::io::Result<Bytes>>;
+
+/// Stream the peer opened, waiting for `accept_bi` or `accept_uni`
+type Accepted = (u32, Incoming);
+
+/// Where streams the peer opens wait to be accepted
+struct Acceptors {
+    bi: mpsc::UnboundedSender<Accepted>,
+    uni: mpsc::UnboundedSender<Accepted>,
+}
+
+/// State shared by a connection, its streams and its socket tasks
+struct Session {
+    outgoing: mpsc::Sender<Message>,
+    /// Streams still receiving, by ID
+    streams: Mutex<HashMap<u32, Inbox>>,
+    next_id: AtomicU32,
+    /// Parity of the stream IDs this side opens
+    parity: u32,
+    /// Streams the peer may have open at once; 0 is unlimited
+    max_streams: usize,
+    closed: CancellationToken,
+}
+
+impl Session {
+    fn register(streams: &mut HashMap<u32, Inbox>, id: u32) -> Incoming {
+        // Unbounded: TCP already paces the peer, and one slow stream must
+        // not stall the reader for every other stream
+        let (inbox, incoming) = mpsc::unbounded_channel();
+        streams.insert(id, inbox);
+        incoming
+    }
+
+    /// Deliver one stream frame from the peer
+    fn route(&self, frame: Bytes, acceptors: &Acceptors) -> Result<()> {
+        if frame.len() < HEADER_LEN {
+            return Err(ProtocolError::Truncated);
+        }
+        let id = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+        let payload = frame.slice(HEADER_LEN..);
+        let mut streams = self.streams.lock().unwrap();
+        match frame[4] {
+            kind @ (KIND_OPEN | KIND_OPEN_UNI) => {
+                if id % 2 == self.parity || streams.contains_key(&id) {
+                    return Err(ProtocolError::Transport(format!(
+                        "Peer reopened stream {}",
+                        id
+                    )));
+                }
+                if self.max_streams > 0 && streams.len() >= self.max_streams {
+                    // Refused: the peer's reader ends, its writes are dropped
+                    self.outgoing.try_send(stream_frame(id, KIND_FIN, &[])).ok();
+                    return Ok(());
+                }
+                let accepted = match kind {
+                    KIND_OPEN => &acceptors.bi,
+                    _ => &acceptors.uni,
+                };
+                accepted.send((id, Self::register(&mut streams, id))).ok();
+            }
+            KIND_DATA => {
+                if let Some(inbox) = streams.get(&id) {
+                    if inbox.send(Ok(payload)).is_err() {
+                        // Reader dropped; discard the rest of the stream
+                        streams.remove(&id);
+                    }
+                }
+            }
+            KIND_FIN => {
+                streams.remove(&id);
+            }
+            kind => return Err(ProtocolError::UnknownMessageType(kind)),
+        }
+        Ok(())
+    }
+
+    /// Fail every stream still receiving
+    fn reset_streams(&self) {
+        for (_, inbox) in self.streams.lock().unwrap().drain() {
+            inbox
+                .send(Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "connection closed",
+                )))
+                .ok();
+        }
+    }
+}
+
+struct WsConnection {
+    session: Arc<Session>,
+    /// Streams the peer opened, not yet accepted
+    accepted: tokio::sync::Mutex<mpsc::UnboundedReceiver<Accepted>>,
+    /// Unidirectional streams the peer opened, not yet accepted
+    accepted_uni: tokio::sync::Mutex<mpsc::UnboundedReceiver<Accepted>>,
+    remote: String,
+    peer: DeviceId,
+    /// Bytes the peer may send per second, shared by all streams
+    budget: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl WsConnection {
+    /// Take over an upgraded socket, opening streams with IDs from `first_id`
+    fn new<S>(
+        ws: WebSocketStream<S>,
+        remote: String,
+        peer: DeviceId,
+        first_id: u32,
+        guard: Option<&ConnectionGuard>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (outgoing, queued) = mpsc::channel(OUTGOING_QUEUE);
+        let (accept, accepted) = mpsc::unbounded_channel();
+        let (accept_uni, accepted_uni) = mpsc::unbounded_channel();
+        let session = Arc::new(Session {
+            outgoing,
+            streams: Mutex::new(HashMap::new()),
+            next_id: AtomicU32::new(first_id),
+            parity: first_id % 2,
+            max_streams: guard.map_or(0, |guard| guard.limits().max_concurrent_streams as usize),
+            closed: CancellationToken::new(),
+        });
+        let acceptors = Acceptors {
+            bi: accept,
+            uni: accept_uni,
+        };
+        tokio::spawn(drive(ws, session.clone(), queued, acceptors));
+        Self {
+            session,
+            accepted: tokio::sync::Mutex::new(accepted),
+            accepted_uni: tokio::sync::Mutex::new(accepted_uni),
+            remote,
+            peer,
+            budget: guard.and_then(|guard| guard.connection_budget()),
+        }
+    }
+
+    fn stream(&self, id: u32, inbox: Incoming) -> (SendStream, RecvStream) {
+        (self.send_stream(id), self.recv_stream(inbox))
+    }
+
+    fn send_stream(&self, id: u32) -> SendStream {
+        Box::new(WsSendStream {
+            id,
+            sender: PollSender::new(self.session.outgoing.clone()),
+            finished: false,
+        })
+    }
+
+    fn recv_stream(&self, inbox: Incoming) -> RecvStream {
+        let recv = WsRecvStream {
+            inbox,
+            buffered: Bytes::new(),
+        };
+        throttled(&self.budget, Box::new(recv))
+    }
+
+    /// ID for a stream this side opens
+    fn next_id(&self) -> Result<u32> {
+        if self.session.closed.is_cancelled() {
+            return Err(not_connected());
+        }
+        Ok(self.session.next_id.fetch_add(2, Ordering::Relaxed))
+    }
+
+    /// Tell the peer about a stream this side opened
+    async fn announce(&self, id: u32, kind: u8) -> Result<()> {
+        self.session
+            .outgoing
+            .send(stream_frame(id, kind, &[]))
+            .await
+            .map_err(|_| not_connected())
+    }
+}
+
+impl Connection for WsConnection {
+    fn remote_addr(&self) -> String {
+        self.remote.clone()
+    }
+
+    fn peer_device_id(&self) -> Option<DeviceId> {
+        Some(self.peer.clone())
+    }
+
+    fn open_bi(&self) -> BoxFuture<'_, Result<(SendStream, RecvStream)>> {
+        Box::pin(async move {
+            let id = self.next_id()?;
+            // Registered first so an early reply is not lost
+            let inbox = Session::register(&mut self.session.streams.lock().unwrap(), id);
+            self.announce(id, KIND_OPEN).await?;
+            Ok(self.stream(id, inbox))
+        })
+    }
+
+    fn accept_bi(&self) -> BoxFuture<'_, Result<Option<(SendStream, RecvStream)>>> {
+        Box::pin(async move {
+            let accepted = self.accepted.lock().await.recv().await;
+            Ok(accepted.map(|(id, inbox)| self.stream(id, inbox)))
+        })
+    }
+
+    fn open_uni(&self) -> BoxFuture<'_, Result<SendStream>> {
+        Box::pin(async move {
+            let id = self.next_id()?;
+            self.announce(id, KIND_OPEN_UNI).await?;
+            Ok(self.send_stream(id))
+        })
+    }
+
+    fn accept_uni(&self) -> BoxFuture<'_, Result<Option<RecvStream>>> {
+        Box::pin(async move {
+            let accepted = self.accepted_uni.lock().await.recv().await;
+            Ok(accepted.map(|(_, inbox)| self.recv_stream(inbox)))
+        })
+    }
+
+    fn close(&self) {
+        self.session.closed.cancel();
+    }
+}
+
+impl Drop for WsConnection {
+    fn drop(&mut self) {
+        self.session.closed.cancel();
+    }
+}
+
+/// Pump frames between the socket and the session until either side closes
+async fn drive<S>(
+    ws: WebSocketStream<S>,
+    session: Arc<Session>,
+    mut queued: mpsc::Receiver<Message>,
+    acceptors: Acceptors,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut sink, mut messages) = ws.split();
+    // Reading and writing run side by side so a peer blocked on a full
+    // socket buffer can never deadlock against this one
+    let write = async {
+        loop {
+            let message = tokio::select! {
+                _ = session.closed.cancelled() => break,
+                message = queued.recv() => message,
+            };
+            let Some(message) = message else { break };
+            if let Err(e) = sink.send(message).await {
+                tracing::debug!("WebSocket write failed: {}", e);
+                break;
+            }
+        }
+        session.closed.cancel();
+        sink.send(Message::Close(None)).await.ok();
+    };
+    let read = async {
+        loop {
+            let message = tokio::select! {
+                _ = session.closed.cancelled() => break,
+                message = messages.next() => message,
+            };
+            match message {
+                Some(Ok(Message::Binary(frame))) => {
+                    if let Err(e) = session.route(frame, &acceptors) {
+                        tracing::debug!("Closing WebSocket connection: {}", e);
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // Pings are answered by the protocol layer
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    tracing::debug!("WebSocket read failed: {}", e);
+                    break;
+                }
+            }
+        }
+        session.closed.cancel();
+    };
+    tokio::join!(write, read);
+    session.reset_streams();
+}
+
+struct WsSendStream {
+    id: u32,
+    sender: PollSender<Message>,
+    finished: bool,
+}
+
+impl AsyncWrite for WsSendStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.finished {
+            return Poll::Ready(Err(closed_error()));
+        }
+        ready!(self.sender.poll_reserve(cx)).map_err(|_| closed_error())?;
+        let len = buf.len().min(MAX_CHUNK);
+        let frame = stream_frame(self.id, KIND_DATA, &buf[..len]);
+        self.sender.send_item(frame).map_err(|_| closed_error())?;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if self.finished {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.sender.poll_reserve(cx)).map_err(|_| closed_error())?;
+        let frame = stream_frame(self.id, KIND_FIN, &[]);
+        self.sender.send_item(frame).map_err(|_| closed_error())?;
+        self.finished = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl StreamWrite for WsSendStream {}
+
+impl Drop for WsSendStream {
+    fn drop(&mut self) {
+        // Dropping finishes the stream, as with QUIC
+        if !self.finished {
+            if let Some(sender) = self.sender.get_ref() {
+                sender.try_send(stream_frame(self.id, KIND_FIN, &[])).ok();
+            }
+        }
+    }
+}
+
+struct WsRecvStream {
+    inbox: Incoming,
+    /// Rest of the last payload not yet read
+    buffered: Bytes,
+}
+
+impl AsyncRead for WsRecvStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.buffered.is_empty() {
+            match ready!(self.inbox.poll_recv(cx)) {
+                Some(payload) => self.buffered = payload?,
+                // Peer finished the stream
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let len = self.buffered.len().min(buf.remaining());
+        buf.put_slice(&self.buffered.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_crypto::generate_keypair;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_loopback_streams() {
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let (server_keys, client_keys) = (generate_keypair(), generate_keypair());
+        let server = WebSocketTransport::bind(loopback, &server_keys).unwrap();
+        let client = WebSocketTransport::bind(loopback, &client_keys).unwrap();
+        let server_addr = server.socket_addr().unwrap().to_string();
+
+        let client_id = client_keys.device_id().clone();
+        let accept = tokio::spawn(async move {
+            let connection = server.accept().await.unwrap().unwrap();
+            assert_eq!(connection.peer_device_id(), Some(client_id));
+            let one_way = tokio::spawn({
+                let connection = connection.clone();
+                async move {
+                    let mut recv = connection.accept_uni().await.unwrap().unwrap();
+                    let mut data = Vec::new();
+                    recv.read_to_end(&mut data).await.unwrap();
+                    data
+                }
+            });
+            // Echo every stream the client opens
+            while let Ok(Some((mut send, mut recv))) = connection.accept_bi().await {
+                tokio::spawn(async move {
+                    let mut data = Vec::new();
+                    recv.read_to_end(&mut data).await.unwrap();
+                    send.write_all(&data).await.unwrap();
+                    send.shutdown().await.unwrap();
+                });
+            }
+            one_way.await.unwrap()
+        });
+
+        let connection = client.connect(&server_addr).await.unwrap();
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+        // Several streams at once, larger than one stream frame
+        let echoes = (0..4u8).map(|n| {
+            let connection = connection.clone();
+            async move {
+                let data = vec![n; 3 * MAX_CHUNK + 17];
+                let (mut send, mut recv) = connection.open_bi().await.unwrap();
+                send.write_all(&data).await.unwrap();
+                send.shutdown().await.unwrap();
+                let mut echoed = Vec::new();
+                recv.read_to_end(&mut echoed).await.unwrap();
+                assert_eq!(echoed, data);
+            }
+        });
+        // Frames keep their order, so the echoes arrive after it
+        let mut send = connection.open_uni().await.unwrap();
+        send.write_all(b"one way").await.unwrap();
+        send.shutdown().await.unwrap();
+        futures::future::join_all(echoes).await;
+
+        connection.close();
+        assert_eq!(accept.await.unwrap(), b"one way");
+        assert!(connection.open_bi().await.is_err());
+        assert!(connection.open_uni().await.is_err());
+
+        for unreachable in ["relay:eu-1", "not an endpoint"] {
+            assert!(matches!(
+                client.connect(unreachable).await,
+                Err(ProtocolError::Transport(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_device_checks_identity() {
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let server_keys = generate_keypair();
+        let server = Arc::new(WebSocketTransport::bind(loopback, &server_keys).unwrap());
+        let client = WebSocketTransport::bind(loopback, &generate_keypair()).unwrap();
+        let server_addr = server.socket_addr().unwrap().to_string();
+        let accept = tokio::spawn({
+            let server = server.clone();
+            async move {
+                let mut accepted = 0;
+                loop {
+                    match server.accept().await {
+                        Ok(Some(_)) => accepted += 1,
+                        Ok(None) => return accepted,
+                        Err(_) => {}
+                    }
+                }
+            }
+        });
+
+        let impostor = generate_keypair();
+        assert!(matches!(
+            client
+                .connect_device(&server_addr, impostor.device_id())
+                .await,
+            Err(ProtocolError::Transport(_))
+        ));
+        let connection = client
+            .connect_device(&server_addr, server_keys.device_id())
+            .await
+            .unwrap();
+        assert_eq!(
+            connection.peer_device_id().as_ref(),
+            Some(server_keys.device_id())
+        );
+
+        server.close();
+        assert_eq!(accept.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_limit_refuses_extra_streams() {
+        use crate::limits::RateLimits;
+
+        let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let guard = ConnectionGuard::new(RateLimits {
+            max_concurrent_streams: 1,
+            ..Default::default()
+        });
+        let server =
+            WebSocketTransport::bind_guarded(loopback, &generate_keypair(), Arc::new(guard))
+                .unwrap();
+        let client = WebSocketTransport::bind(loopback, &generate_keypair()).unwrap();
+        let server_addr = server.socket_addr().unwrap().to_string();
+        let accept = tokio::spawn(async move {
+            let connection = server.accept().await.unwrap().unwrap();
+            let streams = connection.accept_bi().await.unwrap().unwrap();
+            // Hold the first stream open until the client is done
+            connection.accept_bi().await.ok();
+            drop(streams);
+        });
+
+        let connection = client.connect(&server_addr).await.unwrap();
+        let (_first_send, _first_recv) = connection.open_bi().await.unwrap();
+        let (_send, mut refused) = connection.open_bi().await.unwrap();
+        let mut data = Vec::new();
+        refused.read_to_end(&mut data).await.unwrap();
+        assert!(data.is_empty());
+
+        connection.close();
+        accept.await.unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+//! Content hashing, chunking and collection CRDT merges
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nomade_storage::{content_hash, CollectionStore, HashTree, HASH_GROUP_SIZE};
+
+const SIZES: [usize; 3] = [64 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+fn content(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0; size];
+    blake3::Hasher::new()
+        .update(b"bench")
+        .finalize_xof()
+        .fill(&mut bytes);
+    bytes
+}
+
+fn hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blake3");
+    for size in SIZES {
+        let data = content(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("content_hash", size), &data, |b, data| {
+            b.iter(|| content_hash(data))
+        });
+    }
+    group.finish();
+}
+
+/// Splitting content into sync chunks with their tree, and checking one
+fn chunking(c: &mut Criterion) {
+    let mut group = c.benchmark_group("hash_tree");
+    for size in SIZES {
+        let data = content(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("build", size), &data, |b, data| {
+            b.iter(|| HashTree::build(data))
+        });
+    }
+    let data = content(16 * 1024 * 1024);
+    let tree = HashTree::build(&data);
+    let last = tree.group_count() - 1;
+    group.throughput(Throughput::Bytes(HASH_GROUP_SIZE as u64));
+    group.bench_function("verify_group", |b| {
+        b.iter(|| assert!(tree.verify_group(last, &data[last * HASH_GROUP_SIZE..])))
+    });
+    group.finish();
+}
+
+/// Replicas exchanging every op of a large collection tree
+fn crdt_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("collections");
+    for count in [100, 1000] {
+        let mut source = CollectionStore::new("laptop");
+        let mut parent = None;
+        for i in 0..count {
+            let collection = source
+                .create(format!("c{}", i), parent.as_deref(), i)
+                .unwrap();
+            source
+                .rename(&collection.id, format!("renamed {}", i))
+                .unwrap();
+            if i % 10 == 0 {
+                parent = Some(collection.id);
+            }
+        }
+        let ops = source.ops();
+        group.throughput(Throughput::Elements(ops.len() as u64));
+        group.bench_with_input(BenchmarkId::new("merge", ops.len()), &ops, |b, ops| {
+            b.iter(|| {
+                let mut replica = CollectionStore::new("phone");
+                replica.merge(ops.iter().cloned()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hashing, chunking, crdt_merge);
+criterion_main!(benches);
@@ -0,0 +1,176 @@
+//! Storage backends selected by URL
+//!
+//! A backend is named by a URL whose scheme picks the implementation and
+//! whose remainder locates the data, e.g. `memory://`,
+//! `sled:///var/lib/nomade/artifacts`,
+//! `sqlite:///var/lib/nomade/artifacts.db` or `fs:///var/lib/nomade/files`.
+//! Built-in backends are compiled in only when their cargo feature is
+//! enabled, so mobile builds can leave out what they do not use;
+//! applications can register further schemes.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+
+use crate::{ArtifactStore, ContentStore, InMemoryStore};
+
+/// Artifact and content stores opened from one backend
+#[derive(Clone)]
+pub struct Stores {
+    pub artifacts: Arc<dyn ArtifactStore>,
+    pub content: Arc<dyn ContentStore>,
+}
+
+impl Stores {
+    /// Use one store for both artifacts and content
+    pub fn shared<S: ArtifactStore + ContentStore + 'static>(store: S) -> Self {
+        Self::shared_arc(Arc::new(store))
+    }
+
+    /// Use one shared store for both artifacts and content
+    pub fn shared_arc<S: ArtifactStore + ContentStore + 'static>(store: Arc<S>) -> Self {
+        Self {
+            artifacts: store.clone(),
+            content: store,
+        }
+    }
+}
+
+/// Opens stores from the location part of a backend URL
+pub type BackendFactory = Box<dyn Fn(&str) -> anyhow::Result<Stores> + Send + Sync>;
+
+/// Split a backend URL into scheme and location
+pub fn parse_url(url: &str) -> anyhow::Result<(&str, &str)> {
+    let (scheme, location) = url
+        .split_once("://")
+        .ok_or_else(|| anyhow!("Storage URL has no scheme: {}", url))?;
+    let valid = !scheme.is_empty()
+        && scheme
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'+');
+    if !valid {
+        bail!("Invalid storage URL scheme: {}", scheme);
+    }
+    Ok((scheme, location))
+}
+
+/// Registry of storage backends keyed by URL scheme
+pub struct StoreBackends {
+    factories: BTreeMap<String, BackendFactory>,
+}
+
+impl StoreBackends {
+    /// Registry without any backend
+    pub fn empty() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// Registry with the backends compiled into this build
+    pub fn builtin() -> Self {
+        let mut backends = Self::empty();
+        backends.register("memory", |_| Ok(Stores::shared(InMemoryStore::new())));
+        backends.register("fs", |path| {
+            if path.is_empty() {
+                bail!("fs:// URL needs a directory path");
+            }
+            Ok(Stores::shared(crate::FsStore::open(path)?))
+        });
+        #[cfg(feature = "sled")]
+        backends.register("sled", |path| {
+            if path.is_empty() {
+                bail!("sled:// URL needs a database path");
+            }
+            Ok(Stores::shared(crate::SledStore::open(path)?))
+        });
+        #[cfg(feature = "sqlite")]
+        backends.register("sqlite", |path| {
+            if path.is_empty() {
+                bail!("sqlite:// URL needs a database path");
+            }
+            Ok(Stores::shared(crate::SqliteStore::open(path)?))
+        });
+        backends
+    }
+
+    /// Register a backend, replacing any previous one for `scheme`
+    pub fn register(
+        &mut self,
+        scheme: &str,
+        factory: impl Fn(&str) -> anyhow::Result<Stores> + Send + Sync + 'static,
+    ) {
+        self.factories.insert(scheme.to_string(), Box::new(factory));
+    }
+
+    /// Registered schemes
+    pub fn schemes(&self) -> Vec<&str> {
+        self.factories.keys().map(String::as_str).collect()
+    }
+
+    /// Whether `url` names a registered backend
+    pub fn supports(&self, url: &str) -> bool {
+        parse_url(url).is_ok_and(|(scheme, _)| self.factories.contains_key(scheme))
+    }
+
+    /// Open the backend named by `url`
+    pub fn open(&self, url: &str) -> anyhow::Result<Stores> {
+        let (scheme, location) = parse_url(url)?;
+        let factory = self.factories.get(scheme).ok_or_else(|| {
+            anyhow!(
+                "Storage backend {:?} is not available (built with: {})",
+                scheme,
+                self.schemes().join(", ")
+            )
+        })?;
+        factory(location)
+    }
+}
+
+impl Default for StoreBackends {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_backends_by_scheme() {
+        let mut backends = StoreBackends::builtin();
+        let stores = backends.open("memory://").unwrap();
+        stores.content.put_content("h", b"x").unwrap();
+        assert!(stores.content.has_content("h").unwrap());
+
+        assert!(backends.open("memory").is_err());
+        assert!(backends.open("Mem ory://").is_err());
+        assert!(!backends.supports("s3://bucket"));
+        assert!(backends.open("s3://bucket").is_err());
+
+        backends.register("s3", |bucket| {
+            assert_eq!(bucket, "bucket");
+            Ok(Stores::shared(InMemoryStore::new()))
+        });
+        assert!(backends.supports("s3://bucket"));
+        assert!(backends.open("s3://bucket").is_ok());
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_url_opens_database_at_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sled://{}", dir.path().join("db").display());
+        let backends = StoreBackends::builtin();
+        backends
+            .open(&url)
+            .unwrap()
+            .content
+            .put_content("h", b"x")
+            .unwrap();
+        assert!(dir.path().join("db").exists());
+        assert!(backends.open("sled://").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Point-in-time backups of a whole store
+//!
+//! A `StoreBackup` copies the metadata of every artifact together with the
+//! chunks of the content each one refers to. Content is not copied: blobs
+//! are addressed by hash and never rewritten, so a backup shares them with
+//! the live store, and garbage collection keeps every blob a backup still
+//! refers to (`StoreBackups::retained_content`).
+//!
+//! Restoring writes back the artifacts that changed or went missing since
+//! the backup and deletes those created after it, each in one transaction.
+//! Restored artifacts are stamped as new edits so peers take the rollback
+//! instead of the later state.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use nomade_crypto::fs::write_durable;
+use serde::{Deserialize, Serialize};
+
+use crate::{bulk, Artifact, ArtifactStore, BulkProgress, ContentStore, DedupStore};
+
+/// Consistent copy of a store's metadata at one point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreBackup {
+    pub info: BackupInfo,
+    pub artifacts: Vec<Artifact>,
+    /// Chunk hashes of each content blob the artifacts refer to
+    pub chunks: BTreeMap<String, Vec<String>>,
+}
+
+/// Summary of a backup, for listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub id: String,
+    pub label: String,
+    pub created_at: u64,
+    pub artifact_count: usize,
+}
+
+/// Outcome of restoring a backup
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestoreReport {
+    /// Artifacts written back
+    pub restored: usize,
+    /// Artifacts created after the backup and deleted
+    pub removed: usize,
+    /// Content hashes no longer stored locally, to be fetched from peers
+    pub missing_content: Vec<String>,
+}
+
+/// Backups taken of a store, in memory or one file each in a directory
+pub struct StoreBackups {
+    backups: Mutex<BTreeMap<String, StoreBackup>>,
+    dir: Option<PathBuf>,
+}
+
+impl StoreBackups {
+    /// Keep backups in memory only
+    pub fn new() -> Self {
+        Self {
+            backups: Mutex::new(BTreeMap::new()),
+            dir: None,
+        }
+    }
+
+    /// Keep backups as JSON files in `dir`, created if missing
+    pub fn open(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut backups = BTreeMap::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let backup: StoreBackup = serde_json::from_slice(&std::fs::read(&path)?)?;
+                backups.insert(backup.info.id.clone(), backup);
+            }
+        }
+        Ok(Self {
+            backups: Mutex::new(backups),
+            dir: Some(dir),
+        })
+    }
+
+    /// Back up every artifact in `artifacts` and its content's chunks
+    pub fn create(
+        &self,
+        artifacts: &dyn ArtifactStore,
+        content: &DedupStore,
+        label: &str,
+        created_at: u64,
+    ) -> anyhow::Result<BackupInfo> {
+        let mut listed = artifacts.list()?;
+        listed.sort_by(|a, b| a.id.cmp(&b.id));
+        let chunks = listed
+            .iter()
+            .filter_map(|artifact| {
+                let hash = &artifact.content_hash;
+                content.chunks(hash).map(|chunks| (hash.clone(), chunks))
+            })
+            .collect();
+
+        let mut backups = self.backups.lock().unwrap();
+        let mut seq = 0;
+        let id = loop {
+            let id = format!("{}-{}", created_at, seq);
+            if !backups.contains_key(&id) {
+                break id;
+            }
+            seq += 1;
+        };
+        let backup = StoreBackup {
+            info: BackupInfo {
+                id: id.clone(),
+                label: label.to_string(),
+                created_at,
+                artifact_count: listed.len(),
+            },
+            artifacts: listed,
+            chunks,
+        };
+        if let Some(dir) = &self.dir {
+            let path = backup_path(dir, &id);
+            write_durable(&path, &serde_json::to_vec(&backup)?)?;
+        }
+        let info = backup.info.clone();
+        backups.insert(id, backup);
+        Ok(info)
+    }
+
+    /// Backups taken so far, oldest first
+    pub fn list(&self) -> Vec<BackupInfo> {
+        let mut infos: Vec<_> = self
+            .backups
+            .lock()
+            .unwrap()
+            .values()
+            .map(|backup| backup.info.clone())
+            .collect();
+        infos.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        infos
+    }
+
+    /// The backup `id`
+    pub fn get(&self, id: &str) -> Option<StoreBackup> {
+        self.backups.lock().unwrap().get(id).cloned()
+    }
+
+    /// Forget the backup `id`, letting its content be collected
+    pub fn delete(&self, id: &str) -> anyhow::Result<()> {
+        let mut backups = self.backups.lock().unwrap();
+        if backups.remove(id).is_none() {
+            return Err(anyhow!("Backup not found: {}", id));
+        }
+        if let Some(dir) = &self.dir {
+            std::fs::remove_file(backup_path(dir, id))?;
+        }
+        Ok(())
+    }
+
+    /// Roll `artifacts` back to the backup `id`, stamping writes `modified_at`
+    pub fn restore(
+        &self,
+        id: &str,
+        artifacts: &dyn ArtifactStore,
+        content: &dyn ContentStore,
+        modified_at: u64,
+        mut progress: impl FnMut(&BulkProgress),
+    ) -> anyhow::Result<RestoreReport> {
+        let backup = self
+            .get(id)
+            .ok_or_else(|| anyhow!("Backup not found: {}", id))?;
+        let current: BTreeMap<String, Artifact> = artifacts
+            .list()?
+            .into_iter()
+            .map(|artifact| (artifact.id.clone(), artifact))
+            .collect();
+
+        let mut changed = Vec::new();
+        for artifact in &backup.artifacts {
+            let now = current.get(&artifact.id);
+            if now.is_some_and(|now| same_state(now, artifact)) {
+                continue;
+            }
+            let mut artifact = artifact.clone();
+            // Strictly newer than anything the store or peers have seen
+            let latest = now.map_or(artifact.modified_at, |now| now.modified_at);
+            artifact.modified_at = modified_at.max(latest.max(artifact.modified_at) + 1);
+            changed.push(artifact);
+        }
+        let kept: HashSet<&str> = backup.artifacts.iter().map(|a| a.id.as_str()).collect();
+        let extra: Vec<String> = current
+            .keys()
+            .filter(|id| !kept.contains(id.as_str()))
+            .cloned()
+            .collect();
+
+        let mut missing_content = Vec::new();
+        for artifact in &changed {
+            let hash = &artifact.content_hash;
+            if !content.has_content(hash)? && !missing_content.contains(hash) {
+                missing_content.push(hash.clone());
+            }
+        }
+        let restored = bulk::store_many(artifacts, &changed, &mut progress)?;
+        let removed = bulk::delete_many(artifacts, &extra, &mut progress)?;
+        Ok(RestoreReport {
+            restored: restored.done,
+            removed: removed.done,
+            missing_content,
+        })
+    }
+
+    /// Content hashes any backup refers to, for garbage collection to keep
+    pub fn retained_content(&self) -> Vec<String> {
+        let backups = self.backups.lock().unwrap();
+        let hashes: HashSet<&String> = backups
+            .values()
+            .flat_map(|backup| &backup.artifacts)
+            .map(|artifact| &artifact.content_hash)
+            .collect();
+        hashes.into_iter().cloned().collect()
+    }
+}
+
+impl Default for StoreBackups {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `now` still matches `then` apart from its timestamp
+fn same_state(now: &Artifact, then: &Artifact) -> bool {
+    let mut now = now.clone();
+    now.modified_at = then.modified_at;
+    now == *then
+}
+
+fn backup_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{collect_garbage, content_hash, InMemoryStore};
+
+    #[test]
+    fn test_restores_after_mass_deletion() {
+        let dir = tempfile::tempdir().unwrap();
+        let artifacts = InMemoryStore::new();
+        let content = DedupStore::new(Arc::new(InMemoryStore::new()));
+        for i in 0..3u8 {
+            let data = vec![i; 100];
+            content.put_content(&content_hash(&data), &data).unwrap();
+            artifacts
+                .store(&Artifact {
+                    id: format!("a{}", i),
+                    content_hash: content_hash(&data),
+                    modified_at: 10,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let backups = StoreBackups::open(dir.path()).unwrap();
+        let info = backups.create(&artifacts, &content, "before", 20).unwrap();
+        assert_eq!(info.artifact_count, 3);
+
+        let ids: Vec<_> = (0..3).map(|i| format!("a{}", i)).collect();
+        bulk::delete_many(&artifacts, &ids, |_| {}).unwrap();
+        artifacts
+            .store(&Artifact {
+                id: "later".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let keep = backups.retained_content();
+        let report = collect_garbage(&artifacts, &content, &keep).unwrap();
+        assert!(report.removed.is_empty());
+
+        let backups = StoreBackups::open(dir.path()).unwrap();
+        assert_eq!(backups.list(), std::slice::from_ref(&info));
+        let report = backups
+            .restore(&info.id, &artifacts, &content, 30, |_| {})
+            .unwrap();
+        assert_eq!(report.restored, 3);
+        assert_eq!(report.removed, 1);
+        assert!(report.missing_content.is_empty());
+        let mut restored = artifacts.list().unwrap();
+        restored.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(restored.len(), 3);
+        assert!(restored.iter().all(|a| a.modified_at == 30));
+
+        // Nothing changed since, so a second restore writes nothing
+        let report = backups
+            .restore(&info.id, &artifacts, &content, 40, |_| {})
+            .unwrap();
+        assert_eq!(report, RestoreReport::default());
+
+        backups.delete(&info.id).unwrap();
+        assert!(backups.list().is_empty());
+        assert!(backups.delete(&info.id).is_err());
+    }
+}
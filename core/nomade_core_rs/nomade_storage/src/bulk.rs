@@ -0,0 +1,169 @@
+//! Writing many artifacts at once
+//!
+//! `ArtifactStore::store_many` and `delete_many` commit a whole batch in
+//! one transaction on the SQLite, sled and in-memory backends. The helpers
+//! here build bulk operations on them: retagging, and progress reported
+//! every `PROGRESS_STEP` artifacts rather than after each one, for callers
+//! forwarding it across FFI.
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{Artifact, ArtifactStore};
+
+/// Artifacts between two progress reports
+pub const PROGRESS_STEP: usize = 100;
+
+/// Progress of a bulk operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BulkProgress {
+    /// Artifacts in the batch
+    pub total: usize,
+    /// Artifacts written so far
+    pub done: usize,
+}
+
+/// Tags to add to and remove from artifacts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Retag {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+impl Retag {
+    /// Apply to `artifact`; returns whether its tags changed
+    pub fn apply(&self, artifact: &mut Artifact) -> bool {
+        let before = artifact.tags.clone();
+        artifact.tags.retain(|tag| !self.remove.contains(tag));
+        for tag in &self.add {
+            if !artifact.tags.contains(tag) {
+                artifact.tags.push(tag.clone());
+            }
+        }
+        artifact.tags != before
+    }
+}
+
+/// Store `artifacts` in one transaction
+pub fn store_many(
+    store: &dyn ArtifactStore,
+    artifacts: &[Artifact],
+    progress: impl FnMut(&BulkProgress),
+) -> anyhow::Result<BulkProgress> {
+    let total = artifacts.len();
+    store.store_many(artifacts, &mut reporter(total, progress))?;
+    Ok(BulkProgress { total, done: total })
+}
+
+/// Delete the artifacts `ids` in one transaction
+pub fn delete_many(
+    store: &dyn ArtifactStore,
+    ids: &[String],
+    progress: impl FnMut(&BulkProgress),
+) -> anyhow::Result<BulkProgress> {
+    let total = ids.len();
+    store.delete_many(ids, &mut reporter(total, progress))?;
+    Ok(BulkProgress { total, done: total })
+}
+
+/// Retag the artifacts `ids` in one transaction, stamped `modified_at`
+///
+/// Only artifacts whose tags change are written and counted. Fails without
+/// writing anything if an artifact is missing.
+pub fn retag_many(
+    store: &dyn ArtifactStore,
+    ids: &[String],
+    retag: &Retag,
+    modified_at: u64,
+    progress: impl FnMut(&BulkProgress),
+) -> anyhow::Result<BulkProgress> {
+    let mut changed = Vec::new();
+    for id in ids {
+        let mut artifact = store
+            .get(id)?
+            .ok_or_else(|| anyhow!("Artifact not found: {}", id))?;
+        if retag.apply(&mut artifact) {
+            // Strictly newer, so peers take the change
+            artifact.modified_at = modified_at.max(artifact.modified_at + 1);
+            changed.push(artifact);
+        }
+    }
+    store_many(store, &changed, progress)
+}
+
+fn reporter(total: usize, mut progress: impl FnMut(&BulkProgress)) -> impl FnMut(usize) {
+    move |done| {
+        if done % PROGRESS_STEP == 0 || done == total {
+            progress(&BulkProgress { total, done });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nomade_events::{Event, EventStream};
+
+    use super::*;
+    use crate::{InMemoryStore, WatchedStore};
+
+    #[test]
+    fn test_bulk_writes_publish_one_event() {
+        let events = EventStream::new();
+        let mut published = events.subscribe();
+        let store = WatchedStore::new(Arc::new(InMemoryStore::new())).with_events(events);
+        let artifacts: Vec<_> = (0..250)
+            .map(|i| Artifact {
+                id: format!("a{}", i),
+                modified_at: 10,
+                tags: vec!["inbox".into()],
+                ..Default::default()
+            })
+            .collect();
+
+        let mut reports = Vec::new();
+        let done = store_many(&store, &artifacts, |p| reports.push(p.done)).unwrap();
+        assert_eq!(
+            done,
+            BulkProgress {
+                total: 250,
+                done: 250
+            }
+        );
+        assert_eq!(reports, [100, 200, 250]);
+        let Event::Batched { events } = published.try_recv().unwrap() else {
+            panic!("Expected one batch");
+        };
+        assert_eq!(events.len(), 250);
+        assert!(published.try_recv().is_err());
+
+        let ids: Vec<_> = artifacts.iter().map(|a| a.id.clone()).collect();
+        let retag = Retag {
+            add: vec!["archive".into()],
+            remove: vec!["inbox".into()],
+        };
+        let done = retag_many(&store, &ids[..3], &retag, 5, |_| {}).unwrap();
+        assert_eq!(done.total, 3);
+        let retagged = store.get("a0").unwrap().unwrap();
+        assert_eq!(retagged.tags, ["archive"]);
+        assert_eq!(retagged.modified_at, 11);
+        // Already retagged artifacts are left alone
+        assert_eq!(
+            retag_many(&store, &ids[..3], &retag, 5, |_| {})
+                .unwrap()
+                .total,
+            0
+        );
+        assert!(retag_many(&store, &["missing".into()], &retag, 5, |_| {}).is_err());
+
+        published.try_recv().unwrap();
+        delete_many(&store, &ids, |_| {}).unwrap();
+        let Event::Batched { events } = published.try_recv().unwrap() else {
+            panic!("Expected one batch");
+        };
+        assert!(matches!(events[0], Event::ArtifactDeleted { .. }));
+        assert!(store.list().unwrap().is_empty());
+    }
+}
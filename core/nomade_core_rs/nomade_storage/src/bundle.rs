@@ -0,0 +1,368 @@
+//! Portable encrypted bundles (`.nomade` files)
+//!
+//! A bundle carries a set of artifacts to someone without pairing. Layout:
+//!
+//! ```text
+//! MAGIC (8) | header length (u32 BE) | header (JSON) | ciphertext
+//! ```
+//!
+//! The header says how to get the content key: from a password (Argon2id
+//! salt) or, per recipient device, an ephemeral key sealing a random
+//! content key. The decrypted payload is a JSON manifest length-prefixed
+//! like the header, followed by deduplicated chunks, each a 32-byte BLAKE3
+//! hash, a u32 BE length and the data.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, bail, ensure};
+use nomade_crypto::{
+    decrypt_data, encrypt_data, open_sealed_key, password_key, seal_key, DeviceKeypair,
+    EncryptedData,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::{content_hash, Artifact, ArtifactStore, ContentStore};
+
+/// Leading bytes of every bundle
+pub const MAGIC: &[u8; 8] = b"NOMADEB1";
+/// Conventional bundle file extension
+pub const EXTENSION: &str = "nomade";
+/// Size of the chunks content is split into
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+/// How an exported bundle is protected
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BundleSeal {
+    /// Anyone with the password can open it
+    Password(String),
+    /// Only devices holding these Ed25519 identity keys can open it
+    Recipients(Vec<Vec<u8>>),
+}
+
+/// Credential for opening a bundle
+pub enum BundleKey<'a> {
+    Password(&'a str),
+    Device(&'a DeviceKeypair),
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Artifacts that were new locally
+    pub created: Vec<String>,
+    /// Artifacts that replaced an older local version
+    pub updated: Vec<String>,
+    /// Artifacts skipped because the local version is as new or newer
+    pub skipped: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    protection: Protection,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Protection {
+    Password { salt: Vec<u8> },
+    Recipients { recipients: Vec<Recipient> },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Recipient {
+    ephemeral_public: [u8; 32],
+    wrapped_key: EncryptedData,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    artifact: Artifact,
+    /// Chunk hashes (hex) in content order
+    chunks: Vec<String>,
+}
+
+/// Export artifacts and their content as an encrypted bundle
+pub fn export_bundle(
+    artifacts: &dyn ArtifactStore,
+    content: &dyn ContentStore,
+    ids: &[String],
+    seal: &BundleSeal,
+) -> anyhow::Result<Vec<u8>> {
+    let mut manifest = Vec::with_capacity(ids.len());
+    let mut chunks: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for id in ids {
+        let artifact = artifacts
+            .get(id)?
+            .ok_or_else(|| anyhow!("Artifact not found: {}", id))?;
+        let data = content
+            .get_content(&artifact.content_hash)?
+            .ok_or_else(|| anyhow!("Content of {} is not stored locally", id))?;
+        let mut hashes = Vec::new();
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let hash = content_hash(chunk);
+            chunks.entry(hash.clone()).or_insert_with(|| chunk.to_vec());
+            hashes.push(hash);
+        }
+        manifest.push(ManifestEntry {
+            artifact,
+            chunks: hashes,
+        });
+    }
+
+    let mut payload = Vec::new();
+    write_framed(&mut payload, &serde_json::to_vec(&manifest)?);
+    for (hash, data) in &chunks {
+        payload.extend_from_slice(blake3::Hash::from_hex(hash)?.as_bytes());
+        write_framed(&mut payload, data);
+    }
+
+    let (protection, key) = match seal {
+        BundleSeal::Password(password) => {
+            let salt = nomade_crypto::seal::generate_salt().to_vec();
+            let key = password_key(password, &salt)?;
+            (Protection::Password { salt }, key)
+        }
+        BundleSeal::Recipients(public_keys) => {
+            ensure!(
+                !public_keys.is_empty(),
+                "Bundle needs at least one recipient"
+            );
+            let mut key = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            let recipients = public_keys
+                .iter()
+                .map(|public_key| {
+                    let (ephemeral_public, kek) = seal_key(public_key)?;
+                    Ok(Recipient {
+                        ephemeral_public,
+                        wrapped_key: encrypt_data(&key, &kek)?,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+            (Protection::Recipients { recipients }, key)
+        }
+    };
+    let encrypted = encrypt_data(&payload, &key)?;
+
+    let mut bundle = MAGIC.to_vec();
+    write_framed(
+        &mut bundle,
+        &serde_json::to_vec(&Header {
+            version: 1,
+            protection,
+        })?,
+    );
+    bundle.extend_from_slice(&encrypted.nonce);
+    bundle.extend_from_slice(&encrypted.ciphertext);
+    Ok(bundle)
+}
+
+/// Decrypt and validate a bundle, merging it into the local stores
+///
+/// Every chunk and every artifact's content is checked against its hash
+/// before anything is written. Artifacts replace local ones only if newer.
+pub fn import_bundle(
+    bundle: &[u8],
+    key: BundleKey<'_>,
+    artifacts: &dyn ArtifactStore,
+    content: &dyn ContentStore,
+) -> anyhow::Result<ImportReport> {
+    let rest = bundle
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or_else(|| anyhow!("Not a Nomade bundle"))?;
+    let (header, rest) = read_framed(rest)?;
+    let header: Header = serde_json::from_slice(header)?;
+    ensure!(
+        header.version == 1,
+        "Unsupported bundle version {}",
+        header.version
+    );
+    ensure!(rest.len() >= 12, "Truncated bundle");
+    let (nonce, ciphertext) = rest.split_at(12);
+
+    let key = match (header.protection, key) {
+        (Protection::Password { salt }, BundleKey::Password(password)) => {
+            password_key(password, &salt)?
+        }
+        (Protection::Recipients { recipients }, BundleKey::Device(keypair)) => recipients
+            .iter()
+            .find_map(|r| {
+                let kek = open_sealed_key(keypair, &r.ephemeral_public).ok()?;
+                decrypt_data(&r.wrapped_key, &kek).ok()
+            })
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| anyhow!("Bundle is not addressed to this device"))?,
+        (Protection::Password { .. }, _) => bail!("Bundle is password protected"),
+        (Protection::Recipients { .. }, _) => bail!("Bundle is sealed to recipient devices"),
+    };
+    let payload = decrypt_data(
+        &EncryptedData {
+            ciphertext: ciphertext.to_vec(),
+            nonce: nonce.to_vec(),
+            algorithm: "AES-256-GCM".into(),
+        },
+        &key,
+    )
+    .map_err(|_| anyhow!("Wrong key or corrupted bundle"))?;
+
+    let (manifest, mut rest) = read_framed(&payload)?;
+    let manifest: Vec<ManifestEntry> = serde_json::from_slice(manifest)?;
+    let mut chunks: HashMap<String, &[u8]> = HashMap::new();
+    while !rest.is_empty() {
+        ensure!(rest.len() >= 32, "Truncated chunk");
+        let (hash, tail) = rest.split_at(32);
+        let (data, tail) = read_framed(tail)?;
+        let hash = blake3::Hash::from_slice(hash)?.to_hex().to_string();
+        ensure!(
+            content_hash(data) == hash,
+            "Chunk {} failed verification",
+            hash
+        );
+        chunks.insert(hash, data);
+        rest = tail;
+    }
+
+    let mut contents = Vec::with_capacity(manifest.len());
+    for entry in &manifest {
+        let mut data = Vec::new();
+        for hash in &entry.chunks {
+            let chunk = chunks
+                .get(hash)
+                .ok_or_else(|| anyhow!("Missing chunk {}", hash))?;
+            data.extend_from_slice(chunk);
+        }
+        ensure!(
+            content_hash(&data) == entry.artifact.content_hash,
+            "Content of {} failed verification",
+            entry.artifact.id
+        );
+        contents.push(data);
+    }
+
+    let mut report = ImportReport::default();
+    for (entry, data) in manifest.into_iter().zip(contents) {
+        let artifact = entry.artifact;
+        match artifacts.get(&artifact.id)? {
+            Some(local) if local.modified_at >= artifact.modified_at => {
+                report.skipped.push(artifact.id);
+                continue;
+            }
+            Some(_) => report.updated.push(artifact.id.clone()),
+            None => report.created.push(artifact.id.clone()),
+        }
+        content.put_content(&artifact.content_hash, &data)?;
+        artifacts.store(&artifact)?;
+    }
+    Ok(report)
+}
+
+fn write_framed(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn read_framed(input: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    ensure!(input.len() >= 4, "Truncated bundle");
+    let (len, rest) = input.split_at(4);
+    let len = u32::from_be_bytes(len.try_into()?) as usize;
+    ensure!(rest.len() >= len, "Truncated bundle");
+    Ok(rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+    use nomade_crypto::generate_keypair;
+    use proptest::prelude::*;
+
+    fn source() -> InMemoryStore {
+        let store = InMemoryStore::new();
+        for (id, data) in [
+            ("a", vec![1u8; CHUNK_SIZE + 10]),
+            ("b", vec![1u8; CHUNK_SIZE]),
+        ] {
+            let hash = content_hash(&data);
+            store.put_content(&hash, &data).unwrap();
+            store
+                .store(&Artifact {
+                    id: id.into(),
+                    modified_at: 10,
+                    content_hash: hash,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn test_password_bundle_roundtrip() {
+        let source = source();
+        let ids = vec!["a".to_string(), "b".to_string()];
+        let bundle = export_bundle(
+            &source,
+            &source,
+            &ids,
+            &BundleSeal::Password("hunter2".into()),
+        )
+        .unwrap();
+        assert!(bundle.starts_with(MAGIC));
+
+        let target = InMemoryStore::new();
+        target
+            .store(&Artifact {
+                id: "b".into(),
+                modified_at: 20,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(import_bundle(&bundle, BundleKey::Password("nope"), &target, &target).is_err());
+        let report =
+            import_bundle(&bundle, BundleKey::Password("hunter2"), &target, &target).unwrap();
+        assert_eq!(report.created, vec!["a"]);
+        assert_eq!(report.skipped, vec!["b"]);
+        let a = target.get("a").unwrap().unwrap();
+        assert_eq!(
+            target.get_content(&a.content_hash).unwrap().unwrap().len(),
+            CHUNK_SIZE + 10
+        );
+    }
+
+    #[test]
+    fn test_recipient_bundle() {
+        let source = source();
+        let recipient = generate_keypair();
+        let ids = vec!["a".to_string()];
+        let seal = BundleSeal::Recipients(vec![recipient.public_key_bytes()]);
+        let mut bundle = export_bundle(&source, &source, &ids, &seal).unwrap();
+
+        let target = InMemoryStore::new();
+        let stranger = generate_keypair();
+        assert!(import_bundle(&bundle, BundleKey::Device(&stranger), &target, &target).is_err());
+        let report =
+            import_bundle(&bundle, BundleKey::Device(&recipient), &target, &target).unwrap();
+        assert_eq!(report.created, vec!["a"]);
+
+        let last = bundle.len() - 1;
+        bundle[last] ^= 1;
+        assert!(import_bundle(&bundle, BundleKey::Device(&recipient), &target, &target).is_err());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn import_never_panics(tail in prop::collection::vec(any::<u8>(), 0..256)) {
+            let target = InMemoryStore::new();
+            let keypair = generate_keypair();
+            let mut bundle = MAGIC.to_vec();
+            bundle.extend_from_slice(&tail);
+            let _ = import_bundle(&bundle, BundleKey::Device(&keypair), &target, &target);
+            let _ = import_bundle(&tail, BundleKey::Device(&keypair), &target, &target);
+        }
+    }
+}
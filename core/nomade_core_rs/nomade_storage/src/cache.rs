@@ -0,0 +1,243 @@
+//! Pinned content and the evictable cache tier
+//!
+//! Pinned artifacts keep their content on the device. The content of every
+//! other artifact is a cache: when stored content exceeds the quota,
+//! `evict` deletes it, least recently modified first, keeping the metadata
+//! so the artifact stays listed and its content can be fetched again from
+//! a peer. Evicted hashes are remembered so that scrubs can tell eviction
+//! from loss.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use nomade_crypto::fs::write_durable;
+use serde::{Deserialize, Serialize};
+
+use crate::{ArtifactStore, ContentStore, DedupStore};
+
+/// Outcome of enforcing the content quota
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EvictionReport {
+    /// Artifacts whose content was evicted
+    pub evicted: Vec<String>,
+    /// Bytes no longer stored once shared chunks are accounted for
+    pub freed_bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Pinned artifact IDs
+    pinned: BTreeSet<String>,
+    /// Hashes of evicted content
+    evicted: BTreeSet<String>,
+}
+
+/// Pins and evicted content, optionally persisted
+#[derive(Debug, Default)]
+pub struct CacheTiers {
+    state: State,
+    path: Option<PathBuf>,
+}
+
+impl CacheTiers {
+    /// In-memory cache tiers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open cache tiers persisted at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            state,
+            path: Some(path),
+        })
+    }
+
+    /// Keep an artifact's content on the device
+    ///
+    /// Returns `false` if it was already pinned.
+    pub fn pin(&mut self, artifact_id: &str) -> anyhow::Result<bool> {
+        let added = self.state.pinned.insert(artifact_id.to_string());
+        if added {
+            self.save()?;
+        }
+        Ok(added)
+    }
+
+    /// Move an artifact back to the cache tier
+    ///
+    /// Returns `false` if it was not pinned.
+    pub fn unpin(&mut self, artifact_id: &str) -> anyhow::Result<bool> {
+        let removed = self.state.pinned.remove(artifact_id);
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Whether an artifact is pinned
+    pub fn is_pinned(&self, artifact_id: &str) -> bool {
+        self.state.pinned.contains(artifact_id)
+    }
+
+    /// Pinned artifact IDs, sorted
+    pub fn pinned(&self) -> Vec<String> {
+        self.state.pinned.iter().cloned().collect()
+    }
+
+    /// Whether content was evicted rather than lost
+    pub fn is_evicted(&self, hash: &str) -> bool {
+        self.state.evicted.contains(hash)
+    }
+
+    /// Forget evicted content that has been fetched again
+    pub fn refresh(&mut self, content: &dyn ContentStore) -> anyhow::Result<()> {
+        let before = self.state.evicted.len();
+        let mut restored = Vec::new();
+        for hash in &self.state.evicted {
+            if content.has_content(hash)? {
+                restored.push(hash.clone());
+            }
+        }
+        for hash in &restored {
+            self.state.evicted.remove(hash);
+        }
+        if self.state.evicted.len() != before {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Evict unpinned content until at most `quota` bytes are stored
+    ///
+    /// Content shared with a pinned artifact stays.
+    pub fn evict(
+        &mut self,
+        artifacts: &dyn ArtifactStore,
+        content: &DedupStore,
+        quota: u64,
+    ) -> anyhow::Result<EvictionReport> {
+        let before = content.stats().stored_bytes;
+        if before <= quota {
+            return Ok(EvictionReport::default());
+        }
+
+        // Newest modification and referencing artifacts of each blob
+        let mut blobs: BTreeMap<String, (u64, Vec<String>)> = BTreeMap::new();
+        let mut keep = BTreeSet::new();
+        for artifact in artifacts.list()? {
+            if self.is_pinned(&artifact.id) {
+                keep.insert(artifact.content_hash);
+                continue;
+            }
+            let entry = blobs.entry(artifact.content_hash).or_default();
+            entry.0 = entry.0.max(artifact.modified_at);
+            entry.1.push(artifact.id);
+        }
+        let mut candidates: Vec<(String, (u64, Vec<String>))> = blobs
+            .into_iter()
+            .filter(|(hash, _)| !keep.contains(hash))
+            .collect();
+        candidates.sort_by_key(|(_, (modified_at, _))| *modified_at);
+
+        let mut report = EvictionReport::default();
+        for (hash, (_, ids)) in candidates {
+            if content.stats().stored_bytes <= quota {
+                break;
+            }
+            if !content.has_content(&hash)? {
+                continue;
+            }
+            content.delete_content(&hash)?;
+            self.state.evicted.insert(hash);
+            report.evicted.extend(ids);
+        }
+        report.evicted.sort();
+        report.freed_bytes = before - content.stats().stored_bytes;
+        if !report.evicted.is_empty() {
+            self.save()?;
+        }
+        Ok(report)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(&self.state)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content_hash, Artifact, InMemoryStore};
+    use std::sync::Arc;
+
+    fn add(artifacts: &InMemoryStore, content: &DedupStore, id: &str, modified_at: u64) {
+        let data = id.repeat(100).into_bytes();
+        content.put_content(&content_hash(&data), &data).unwrap();
+        artifacts
+            .store(&Artifact {
+                id: id.into(),
+                modified_at,
+                content_hash: content_hash(&data),
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_evicts_oldest_unpinned_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let artifacts = InMemoryStore::new();
+        let content = DedupStore::new(Arc::new(InMemoryStore::new()));
+        add(&artifacts, &content, "a", 1);
+        add(&artifacts, &content, "b", 2);
+        add(&artifacts, &content, "c", 3);
+        let mut tiers = CacheTiers::open(dir.path().join("cache.json")).unwrap();
+        assert!(tiers.pin("a").unwrap());
+        assert!(!tiers.pin("a").unwrap());
+
+        assert_eq!(
+            tiers.evict(&artifacts, &content, 300).unwrap(),
+            EvictionReport::default()
+        );
+        let report = tiers.evict(&artifacts, &content, 200).unwrap();
+        assert_eq!(report.evicted, ["b"]);
+        assert_eq!(report.freed_bytes, 100);
+        // Metadata stays
+        assert!(artifacts.get("b").unwrap().is_some());
+        let hash = artifacts.get("b").unwrap().unwrap().content_hash;
+        assert!(!content.has_content(&hash).unwrap());
+
+        // Pinned content survives even a zero quota
+        let report = tiers.evict(&artifacts, &content, 0).unwrap();
+        assert_eq!(report.evicted, ["c"]);
+        let tiers = CacheTiers::open(dir.path().join("cache.json")).unwrap();
+        assert_eq!(tiers.pinned(), ["a"]);
+        assert!(tiers.is_evicted(&hash));
+    }
+
+    #[test]
+    fn test_refresh_forgets_refetched_content() {
+        let artifacts = InMemoryStore::new();
+        let content = DedupStore::new(Arc::new(InMemoryStore::new()));
+        add(&artifacts, &content, "a", 1);
+        let mut tiers = CacheTiers::new();
+        tiers.evict(&artifacts, &content, 0).unwrap();
+        let data = "a".repeat(100).into_bytes();
+        assert!(tiers.is_evicted(&content_hash(&data)));
+
+        content.put_content(&content_hash(&data), &data).unwrap();
+        tiers.refresh(&content).unwrap();
+        assert!(!tiers.is_evicted(&content_hash(&data)));
+    }
+}
@@ -0,0 +1,574 @@
+//! Collections (folders) of artifacts
+//!
+//! Collections form a tree and are replicated as an operation-based CRDT:
+//! every change is a `CollectionOp` stamped with a Lamport counter and the
+//! replica that made it. A collection's state is the replay of its ops in
+//! stamp order, so replicas that have seen the same ops agree regardless
+//! of arrival order. Name and placement are last-writer-wins; deletion
+//! wins over concurrent edits. A move that races into a cycle is resolved
+//! at read time by treating the collection as a root.
+//!
+//! Artifact membership is the `Artifact::collection` field.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+use nomade_crypto::fs::write_durable;
+use nomade_events::{Event, EventStream};
+use serde::{Deserialize, Serialize};
+
+use crate::{Artifact, ArtifactStore};
+
+/// Causal stamp of an operation; unique per replica
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Stamp {
+    pub counter: u64,
+    pub replica: String,
+}
+
+/// Change to a collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollectionOpKind {
+    Create {
+        name: String,
+        parent: Option<String>,
+        position: i64,
+    },
+    Rename {
+        name: String,
+    },
+    Move {
+        parent: Option<String>,
+        position: i64,
+    },
+    Delete,
+}
+
+/// Replicated collection operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionOp {
+    pub collection_id: String,
+    pub stamp: Stamp,
+    pub kind: CollectionOpKind,
+}
+
+/// Collection as seen by the application
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+    /// Parent collection; `None` for top-level collections
+    pub parent: Option<String>,
+    /// Ordering among siblings (ascending)
+    pub position: i64,
+}
+
+/// Replayed state of one collection, before tree resolution
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct State {
+    name: String,
+    parent: Option<String>,
+    position: i64,
+}
+
+/// Replicated store of collections
+pub struct CollectionStore {
+    replica: String,
+    clock: u64,
+    ops: HashMap<String, BTreeMap<Stamp, CollectionOpKind>>,
+    path: Option<PathBuf>,
+    events: Option<EventStream>,
+}
+
+impl CollectionStore {
+    /// Create in-memory store for the given replica (device) ID
+    pub fn new(replica: impl Into<String>) -> Self {
+        Self {
+            replica: replica.into(),
+            clock: 0,
+            ops: HashMap::new(),
+            path: None,
+            events: None,
+        }
+    }
+
+    /// Open store persisted at `path`, creating it if missing
+    pub fn open(path: impl AsRef<Path>, replica: impl Into<String>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut store = Self::new(replica);
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                let ops: Vec<CollectionOp> = serde_json::from_slice(&bytes)?;
+                for op in ops {
+                    store.insert(op);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        store.path = Some(path);
+        Ok(store)
+    }
+
+    /// Publish `Collection*` events on changes
+    pub fn with_events(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Get a live collection
+    pub fn get(&self, id: &str) -> Option<Collection> {
+        let state = self.state(id)?;
+        Some(Collection {
+            id: id.to_string(),
+            parent: self.resolve_parent(id, &state),
+            name: state.name,
+            position: state.position,
+        })
+    }
+
+    /// All live collections, ordered by parent, position and name
+    pub fn list(&self) -> Vec<Collection> {
+        let mut collections: Vec<Collection> =
+            self.ops.keys().filter_map(|id| self.get(id)).collect();
+        collections.sort_by(|a, b| {
+            (&a.parent, a.position, &a.name, &a.id).cmp(&(&b.parent, b.position, &b.name, &b.id))
+        });
+        collections
+    }
+
+    /// Direct children of a collection (or top-level collections)
+    pub fn children(&self, parent: Option<&str>) -> Vec<Collection> {
+        self.list()
+            .into_iter()
+            .filter(|c| c.parent.as_deref() == parent)
+            .collect()
+    }
+
+    /// Create a collection
+    pub fn create(
+        &mut self,
+        name: impl Into<String>,
+        parent: Option<&str>,
+        position: i64,
+    ) -> anyhow::Result<Collection> {
+        if let Some(parent) = parent {
+            self.require(parent)?;
+        }
+        let stamp = self.next_stamp();
+        let id = format!(
+            "col-{}",
+            &blake3::hash(format!("{}:{}", stamp.replica, stamp.counter).as_bytes()).to_hex()[..16]
+        );
+        self.commit(CollectionOp {
+            collection_id: id.clone(),
+            stamp,
+            kind: CollectionOpKind::Create {
+                name: name.into(),
+                parent: parent.map(Into::into),
+                position,
+            },
+        })?;
+        self.get(&id)
+            .ok_or_else(|| anyhow!("Collection {} vanished", id))
+    }
+
+    /// Rename a collection
+    pub fn rename(&mut self, id: &str, name: impl Into<String>) -> anyhow::Result<()> {
+        self.require(id)?;
+        let op = self.local_op(id, CollectionOpKind::Rename { name: name.into() });
+        self.commit(op)
+    }
+
+    /// Move a collection under a new parent (`None` for top level)
+    pub fn move_to(&mut self, id: &str, parent: Option<&str>, position: i64) -> anyhow::Result<()> {
+        self.require(id)?;
+        if let Some(parent) = parent {
+            self.require(parent)?;
+            if parent == id || self.is_descendant(parent, id) {
+                bail!("Cannot move collection {} into its own subtree", id);
+            }
+        }
+        let op = self.local_op(
+            id,
+            CollectionOpKind::Move {
+                parent: parent.map(Into::into),
+                position,
+            },
+        );
+        self.commit(op)
+    }
+
+    /// Delete a collection and everything below it
+    ///
+    /// Artifacts in deleted collections are not deleted; they resolve to
+    /// no collection.
+    pub fn delete(&mut self, id: &str) -> anyhow::Result<()> {
+        self.require(id)?;
+        let mut doomed = vec![id.to_string()];
+        let mut index = 0;
+        while index < doomed.len() {
+            let parent = doomed[index].clone();
+            doomed.extend(self.children(Some(&parent)).into_iter().map(|c| c.id));
+            index += 1;
+        }
+        // Children first so every intermediate state is a valid tree
+        for id in doomed.into_iter().rev() {
+            let op = self.local_op(&id, CollectionOpKind::Delete);
+            self.commit(op)?;
+        }
+        Ok(())
+    }
+
+    /// Every operation, for replication to peers
+    pub fn ops(&self) -> Vec<CollectionOp> {
+        let mut ops: Vec<CollectionOp> = self
+            .ops
+            .iter()
+            .flat_map(|(id, ops)| {
+                ops.iter().map(|(stamp, kind)| CollectionOp {
+                    collection_id: id.clone(),
+                    stamp: stamp.clone(),
+                    kind: kind.clone(),
+                })
+            })
+            .collect();
+        ops.sort_by(|a, b| a.stamp.cmp(&b.stamp));
+        ops
+    }
+
+    /// Apply operations received from a peer
+    ///
+    /// Already known operations are ignored. Returns the number applied.
+    pub fn merge(&mut self, ops: impl IntoIterator<Item = CollectionOp>) -> anyhow::Result<usize> {
+        let before: HashMap<String, Collection> =
+            self.list().into_iter().map(|c| (c.id.clone(), c)).collect();
+        let applied = ops.into_iter().filter(|op| self.insert(op.clone())).count();
+        if applied > 0 {
+            self.save()?;
+            self.publish_changes(before);
+        }
+        Ok(applied)
+    }
+
+    /// Put an artifact into a collection (`None` to remove it from one)
+    pub fn assign(
+        &self,
+        artifacts: &dyn ArtifactStore,
+        artifact_id: &str,
+        collection: Option<&str>,
+    ) -> anyhow::Result<()> {
+        if let Some(collection) = collection {
+            self.require(collection)?;
+        }
+        let mut artifact = artifacts
+            .get(artifact_id)?
+            .ok_or_else(|| anyhow!("Artifact not found: {}", artifact_id))?;
+        artifact.collection = collection.map(Into::into);
+        artifacts.store(&artifact)
+    }
+
+    /// Artifacts directly in a collection
+    ///
+    /// Artifacts pointing at a deleted collection count as top-level.
+    pub fn artifacts_in(
+        &self,
+        artifacts: &dyn ArtifactStore,
+        collection: Option<&str>,
+    ) -> anyhow::Result<Vec<Artifact>> {
+        Ok(artifacts
+            .list()?
+            .into_iter()
+            .filter(|a| {
+                let live = a
+                    .collection
+                    .as_deref()
+                    .filter(|id| self.state(id).is_some());
+                live == collection
+            })
+            .collect())
+    }
+
+    fn require(&self, id: &str) -> anyhow::Result<()> {
+        match self.state(id) {
+            Some(_) => Ok(()),
+            None => bail!("Collection not found: {}", id),
+        }
+    }
+
+    fn next_stamp(&mut self) -> Stamp {
+        self.clock += 1;
+        Stamp {
+            counter: self.clock,
+            replica: self.replica.clone(),
+        }
+    }
+
+    fn local_op(&mut self, id: &str, kind: CollectionOpKind) -> CollectionOp {
+        CollectionOp {
+            collection_id: id.to_string(),
+            stamp: self.next_stamp(),
+            kind,
+        }
+    }
+
+    fn commit(&mut self, op: CollectionOp) -> anyhow::Result<()> {
+        let before: HashMap<String, Collection> =
+            self.list().into_iter().map(|c| (c.id.clone(), c)).collect();
+        self.insert(op);
+        self.save()?;
+        self.publish_changes(before);
+        Ok(())
+    }
+
+    /// Record an op, advancing the Lamport clock; `false` if already known
+    fn insert(&mut self, op: CollectionOp) -> bool {
+        self.clock = self.clock.max(op.stamp.counter);
+        self.ops
+            .entry(op.collection_id)
+            .or_default()
+            .insert(op.stamp, op.kind)
+            .is_none()
+    }
+
+    /// Replay a collection's ops; `None` if never created or deleted
+    fn state(&self, id: &str) -> Option<State> {
+        let mut state: Option<State> = None;
+        for kind in self.ops.get(id)?.values() {
+            match kind {
+                CollectionOpKind::Create {
+                    name,
+                    parent,
+                    position,
+                } => {
+                    state = Some(State {
+                        name: name.clone(),
+                        parent: parent.clone(),
+                        position: *position,
+                    })
+                }
+                CollectionOpKind::Rename { name } => {
+                    if let Some(state) = &mut state {
+                        state.name = name.clone();
+                    }
+                }
+                CollectionOpKind::Move { parent, position } => {
+                    if let Some(state) = &mut state {
+                        state.parent = parent.clone();
+                        state.position = *position;
+                    }
+                }
+                CollectionOpKind::Delete => return None,
+            }
+        }
+        state
+    }
+
+    /// Effective parent: missing parents and cycles resolve to the root
+    fn resolve_parent(&self, id: &str, state: &State) -> Option<String> {
+        let parent = state.parent.clone()?;
+        let mut seen = HashSet::from([id.to_string()]);
+        let mut current = parent.clone();
+        loop {
+            if !seen.insert(current.clone()) {
+                return None;
+            }
+            match self.state(&current)?.parent {
+                Some(next) => current = next,
+                None => return Some(parent),
+            }
+        }
+    }
+
+    fn is_descendant(&self, id: &str, ancestor: &str) -> bool {
+        let mut current = self.get(id).and_then(|c| c.parent);
+        let mut steps = 0;
+        while let Some(parent) = current {
+            if parent == ancestor {
+                return true;
+            }
+            steps += 1;
+            if steps > self.ops.len() {
+                return false;
+            }
+            current = self.get(&parent).and_then(|c| c.parent);
+        }
+        false
+    }
+
+    fn publish_changes(&self, before: HashMap<String, Collection>) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let after: HashMap<String, Collection> =
+            self.list().into_iter().map(|c| (c.id.clone(), c)).collect();
+        for (id, new) in &after {
+            match before.get(id) {
+                None => events.publish(Event::CollectionCreated { id: id.clone() }),
+                Some(old) => {
+                    if old.name != new.name {
+                        events.publish(Event::CollectionRenamed {
+                            id: id.clone(),
+                            name: new.name.clone(),
+                        });
+                    }
+                    if old.parent != new.parent || old.position != new.position {
+                        events.publish(Event::CollectionMoved {
+                            id: id.clone(),
+                            parent: new.parent.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        for id in before.keys().filter(|id| !after.contains_key(*id)) {
+            events.publish(Event::CollectionDeleted { id: id.clone() });
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(&self.ops())?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_crud_and_membership() {
+        let mut store = CollectionStore::new("laptop");
+        let work = store.create("Work", None, 0).unwrap();
+        let drafts = store.create("Drafts", Some(&work.id), 1).unwrap();
+        store.rename(&drafts.id, "WIP").unwrap();
+        assert_eq!(store.get(&drafts.id).unwrap().name, "WIP");
+        assert_eq!(
+            store.children(Some(&work.id)),
+            vec![store.get(&drafts.id).unwrap()]
+        );
+
+        let artifacts = InMemoryStore::new();
+        artifacts
+            .store(&Artifact {
+                id: "note".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        store.assign(&artifacts, "note", Some(&drafts.id)).unwrap();
+        assert_eq!(
+            store
+                .artifacts_in(&artifacts, Some(&drafts.id))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert!(store.move_to(&work.id, Some(&drafts.id), 0).is_err());
+        store.delete(&work.id).unwrap();
+        assert!(store.get(&drafts.id).is_none());
+        assert_eq!(store.artifacts_in(&artifacts, None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_ops_converge() {
+        let mut laptop = CollectionStore::new("laptop");
+        let a = laptop.create("A", None, 0).unwrap();
+        let b = laptop.create("B", None, 1).unwrap();
+        let mut phone = CollectionStore::new("phone");
+        phone.merge(laptop.ops()).unwrap();
+
+        // Concurrent renames and moves that together would form a cycle
+        laptop.rename(&a.id, "From laptop").unwrap();
+        laptop.move_to(&a.id, Some(&b.id), 0).unwrap();
+        phone.rename(&a.id, "From phone").unwrap();
+        phone.move_to(&b.id, Some(&a.id), 0).unwrap();
+
+        let laptop_ops = laptop.ops();
+        laptop.merge(phone.ops()).unwrap();
+        phone.merge(laptop_ops).unwrap();
+
+        assert_eq!(laptop.list(), phone.list());
+        // The cycle resolves without losing either collection
+        assert_eq!(laptop.list().len(), 2);
+        assert_eq!(laptop.merge(phone.ops()).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_events_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("collections.json");
+        let events = EventStream::new();
+        let mut rx = events.subscribe();
+
+        let mut store = CollectionStore::open(&path, "laptop")
+            .unwrap()
+            .with_events(events);
+        let work = store.create("Work", None, 0).unwrap();
+        store.rename(&work.id, "Job").unwrap();
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::CollectionCreated { .. }
+        ));
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::CollectionRenamed { name, .. } if name == "Job"
+        ));
+
+        let mut reopened = CollectionStore::open(&path, "laptop").unwrap();
+        assert_eq!(reopened.get(&work.id).unwrap().name, "Job");
+        // The Lamport clock resumes past persisted ops
+        let other = reopened.create("Other", None, 1).unwrap();
+        assert_ne!(other.id, work.id);
+    }
+
+    fn op_kind() -> impl Strategy<Value = CollectionOpKind> {
+        let parent = prop::option::of("[a-z]{1,8}");
+        prop_oneof![
+            (".{0,16}", parent.clone(), any::<i64>()).prop_map(|(name, parent, position)| {
+                CollectionOpKind::Create {
+                    name,
+                    parent,
+                    position,
+                }
+            }),
+            ".{0,16}".prop_map(|name| CollectionOpKind::Rename { name }),
+            (parent, any::<i64>())
+                .prop_map(|(parent, position)| CollectionOpKind::Move { parent, position }),
+            Just(CollectionOpKind::Delete),
+        ]
+    }
+
+    fn op() -> impl Strategy<Value = CollectionOp> {
+        ("[a-z]{1,8}", any::<u64>(), "[a-z]{1,8}", op_kind()).prop_map(
+            |(collection_id, counter, replica, kind)| CollectionOp {
+                collection_id,
+                stamp: Stamp { counter, replica },
+                kind,
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn op_serde_roundtrip(op in op()) {
+            let json = serde_json::to_vec(&op).unwrap();
+            prop_assert_eq!(serde_json::from_slice::<CollectionOp>(&json).unwrap(), op);
+        }
+
+        #[test]
+        fn merge_order_independent(ops in prop::collection::vec(op(), 0..24)) {
+            let mut forward = CollectionStore::new("a");
+            forward.merge(ops.clone()).unwrap();
+            let mut backward = CollectionStore::new("b");
+            backward.merge(ops.into_iter().rev()).unwrap();
+            prop_assert_eq!(forward.list(), backward.list());
+        }
+    }
+}
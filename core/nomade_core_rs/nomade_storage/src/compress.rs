@@ -0,0 +1,336 @@
+//! Transparent zstd compression of stored content
+//!
+//! `CompressedStore` wraps another `ContentStore` and compresses blobs on
+//! the way in. Compressed blobs start with a short header naming how they
+//! were encoded; anything without it is stored as-is, so blobs written
+//! before compression was enabled still read back unchanged. Content that
+//! is already compressed (media, archives) or looks random (encrypted) is
+//! detected up front and stored raw instead of wasting CPU on it.
+//!
+//! A dictionary trained on sample chunks with `Compression::train_dictionary`
+//! makes small blobs compress far better, since each blob is compressed on
+//! its own and cannot learn from its neighbours.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use nomade_crypto::{KeyRecipient, KeyShares};
+use nomade_metrics::names;
+
+use crate::{ArtifactStore, ContentStore};
+
+/// Default zstd level: fast, with most of the gains of higher levels
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Content shorter than this is never worth compressing
+const MIN_COMPRESS_SIZE: usize = 64;
+
+/// Prefix of encoded blobs, followed by one `Mode` byte
+const MAGIC: &[u8; 4] = b"\x00nzc";
+
+/// Bytes sampled by the entropy check
+const ENTROPY_SAMPLE: usize = 4096;
+
+/// Above this many bits per byte, content is taken to be incompressible
+const MAX_ENTROPY: f64 = 7.5;
+
+/// Signatures of formats that are compressed already
+const COMPRESSED_SIGNATURES: &[&[u8]] = &[
+    b"\x1f\x8b",           // gzip
+    b"PK\x03\x04",         // zip, docx, apk, ...
+    b"\x28\xb5\x2f\xfd",   // zstd
+    b"\xfd7zXZ\x00",       // xz
+    b"BZh",                // bzip2
+    b"7z\xbc\xaf\x27\x1c", // 7z
+    b"Rar!",               // rar
+    b"\x89PNG",            // png
+    b"\xff\xd8\xff",       // jpeg
+    b"GIF8",               // gif
+    b"OggS",               // ogg
+    b"fLaC",               // flac
+    b"ID3",                // mp3
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Mode {
+    /// Raw content that happens to start with `MAGIC`
+    Stored = 0,
+    Zstd = 1,
+    ZstdDictionary = 2,
+}
+
+/// Whether `data` is likely to shrink when compressed
+pub fn is_compressible(data: &[u8]) -> bool {
+    if data.len() < MIN_COMPRESS_SIZE {
+        return false;
+    }
+    if COMPRESSED_SIGNATURES
+        .iter()
+        .any(|sig| data.starts_with(sig))
+    {
+        return false;
+    }
+    // ISO media (mp4, mov, heic) and RIFF containers (webp, avi)
+    if data.get(4..8) == Some(b"ftyp") || data.starts_with(b"RIFF") {
+        return false;
+    }
+    entropy(&data[..data.len().min(ENTROPY_SAMPLE)]) <= MAX_ENTROPY
+}
+
+/// Shannon entropy in bits per byte
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// zstd settings used to encode blobs
+#[derive(Clone)]
+pub struct Compression {
+    level: i32,
+    dictionary: Option<Arc<Vec<u8>>>,
+}
+
+impl Compression {
+    /// Compress at `level` (1 fastest, 22 smallest)
+    pub fn new(level: i32) -> Self {
+        let range = zstd::compression_level_range();
+        Self {
+            level: level.clamp(*range.start(), *range.end()),
+            dictionary: None,
+        }
+    }
+
+    /// Compress with a dictionary shared by every blob
+    ///
+    /// Blobs written with a dictionary can only be read back with it.
+    pub fn with_dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.dictionary = Some(Arc::new(dictionary));
+        self
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on sample chunks
+    pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> anyhow::Result<Vec<u8>> {
+        Ok(zstd::dict::from_samples(samples, max_size)?)
+    }
+
+    /// Compression level
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    /// Encode `data` for storage, compressing it when that pays off
+    pub fn encode(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if is_compressible(data) {
+            let (mode, compressed) = match &self.dictionary {
+                Some(dictionary) => (
+                    Mode::ZstdDictionary,
+                    zstd::bulk::Compressor::with_dictionary(self.level, dictionary)?
+                        .compress(data)?,
+                ),
+                None => (Mode::Zstd, zstd::bulk::compress(data, self.level)?),
+            };
+            if compressed.len() + MAGIC.len() + 1 < data.len() {
+                return Ok(with_header(mode, &compressed));
+            }
+        }
+        if data.starts_with(MAGIC) {
+            return Ok(with_header(Mode::Stored, data));
+        }
+        Ok(data.to_vec())
+    }
+
+    /// Decode a blob written by `encode`, or stored before compression
+    pub fn decode(&self, stored: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let Some(rest) = stored.strip_prefix(MAGIC) else {
+            return Ok(stored.to_vec());
+        };
+        let (mode, body) = rest
+            .split_first()
+            .ok_or_else(|| anyhow!("Truncated compressed blob"))?;
+        match *mode {
+            m if m == Mode::Stored as u8 => Ok(body.to_vec()),
+            m if m == Mode::Zstd as u8 => Ok(zstd::stream::decode_all(body)?),
+            m if m == Mode::ZstdDictionary as u8 => {
+                let dictionary = self
+                    .dictionary
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Blob needs a compression dictionary"))?;
+                let mut decoder = zstd::stream::Decoder::with_dictionary(body, dictionary)?;
+                let mut data = Vec::new();
+                std::io::Read::read_to_end(&mut decoder, &mut data)?;
+                Ok(data)
+            }
+            other => bail!("Unknown compression mode {}", other),
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new(DEFAULT_LEVEL)
+    }
+}
+
+fn with_header(mode: Mode, body: &[u8]) -> Vec<u8> {
+    [MAGIC.as_slice(), &[mode as u8], body].concat()
+}
+
+/// Content store compressing blobs written to another store
+pub struct CompressedStore {
+    inner: Arc<dyn ContentStore>,
+    compression: Compression,
+}
+
+impl CompressedStore {
+    /// Compress content written to `inner`
+    pub fn new(inner: Arc<dyn ContentStore>, compression: Compression) -> Self {
+        Self { inner, compression }
+    }
+
+    fn record(&self, raw: usize, stored: usize) {
+        let metrics = nomade_metrics::global();
+        let input = metrics.counter(
+            names::COMPRESSION_INPUT_BYTES,
+            "Content bytes written to compressed storage",
+        );
+        let output = metrics.counter(
+            names::COMPRESSION_OUTPUT_BYTES,
+            "Bytes stored after compression",
+        );
+        input.add(raw as u64);
+        output.add(stored as u64);
+        if let Some(ratio) = (output.get() * 100).checked_div(input.get()) {
+            metrics
+                .gauge(
+                    names::COMPRESSION_RATIO,
+                    "Stored size as a percentage of content size",
+                )
+                .set(ratio as i64);
+        }
+    }
+}
+
+impl ContentStore for CompressedStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        let encoded = self.compression.encode(data)?;
+        self.inner.put_content(hash, &encoded)?;
+        self.record(data.len(), encoded.len());
+        Ok(())
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.inner
+            .get_content(hash)?
+            .map(|stored| self.compression.decode(&stored))
+            .transpose()
+    }
+
+    fn has_content(&self, hash: &str) -> anyhow::Result<bool> {
+        self.inner.has_content(hash)
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        self.inner.delete_content(hash)
+    }
+
+    fn key_shares(&self, hash: &str) -> anyhow::Result<Option<KeyShares>> {
+        self.inner.key_shares(hash)
+    }
+
+    fn adopt_key_shares(&self, hash: &str, shares: &KeyShares) -> anyhow::Result<()> {
+        self.inner.adopt_key_shares(hash, shares)
+    }
+
+    fn rotate_key(&self, hash: &str) -> anyhow::Result<bool> {
+        self.inner.rotate_key(hash)
+    }
+
+    fn share_keys(
+        &self,
+        recipients: &[KeyRecipient],
+        artifacts: &dyn ArtifactStore,
+    ) -> anyhow::Result<usize> {
+        self.inner.share_keys(recipients, artifacts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+    use rand::RngCore;
+
+    fn text(len: usize) -> Vec<u8> {
+        b"the quick brown fox jumps over the lazy dog. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    #[test]
+    fn test_compresses_text_and_skips_compressed_data() {
+        let inner = Arc::new(InMemoryStore::new());
+        let store = CompressedStore::new(inner.clone(), Compression::default());
+
+        let note = text(10_000);
+        store.put_content("note", &note).unwrap();
+        assert!(inner.get_content("note").unwrap().unwrap().len() < note.len() / 10);
+        assert_eq!(store.get_content("note").unwrap().unwrap(), note);
+
+        let mut random = vec![0u8; 10_000];
+        rand::thread_rng().fill_bytes(&mut random);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(text(1000));
+        for (hash, data) in [("random", &random), ("png", &png)] {
+            assert!(!is_compressible(data));
+            store.put_content(hash, data).unwrap();
+            assert_eq!(inner.get_content(hash).unwrap().unwrap(), *data);
+            assert_eq!(store.get_content(hash).unwrap().unwrap(), *data);
+        }
+
+        // Raw content that looks like a header, and blobs from before compression
+        let tricky = [MAGIC.as_slice(), &[Mode::Zstd as u8], b"not zstd"].concat();
+        store.put_content("tricky", &tricky).unwrap();
+        assert_eq!(store.get_content("tricky").unwrap().unwrap(), tricky);
+        inner.put_content("legacy", b"plain old blob").unwrap();
+        assert_eq!(
+            store.get_content("legacy").unwrap().unwrap(),
+            b"plain old blob"
+        );
+    }
+
+    #[test]
+    fn test_dictionary_round_trip() {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| {
+                format!(
+                    r#"{{"id":"note-{}","title":"Note {}","tags":["work"]}}"#,
+                    i, i
+                )
+            })
+            .map(String::into_bytes)
+            .collect();
+        let dictionary = Compression::train_dictionary(&samples, 4096).unwrap();
+        let with_dictionary = Compression::default().with_dictionary(dictionary);
+
+        let blob = br#"{"id":"note-999","title":"Note 999","tags":["work"],"body":"hello"}"#;
+        let encoded = with_dictionary.encode(blob).unwrap();
+        assert!(encoded.len() < Compression::default().encode(blob).unwrap().len());
+        assert_eq!(with_dictionary.decode(&encoded).unwrap(), blob);
+        assert!(Compression::default().decode(&encoded).is_err());
+    }
+}
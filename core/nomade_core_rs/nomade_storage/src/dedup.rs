@@ -0,0 +1,558 @@
+//! Deduplicated chunk storage
+//!
+//! `DedupStore` wraps another `ContentStore` and splits content into
+//! `HASH_GROUP_SIZE` chunks, stored once each under `chunk:<hash>` no matter
+//! how many blobs contain them. A chunk index maps every blob to its chunks
+//! and counts references to each chunk; a chunk is deleted when its last
+//! reference goes.
+//!
+//! The index is persisted as a snapshot plus an append-only journal. Every
+//! change is journaled before the inner store is touched: a `Prepare`
+//! entry lists the chunks a put is about to write, and `Put` or `Delete`
+//! commits the change. Replaying the journal at open removes chunks of puts
+//! that never committed and chunks freed by deletes that were interrupted,
+//! so a crash at any point leaves no dangling or leaked chunks.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use nomade_crypto::fs::write_durable;
+use nomade_metrics::names;
+use serde::{Deserialize, Serialize};
+
+use crate::{content_hash, ContentStore, HASH_GROUP_SIZE};
+
+/// Journal entries kept before they are folded into the snapshot
+const COMPACT_AFTER: usize = 1024;
+
+/// Space saved by deduplication
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Blobs in the store
+    pub blobs: usize,
+    /// Distinct chunks stored
+    pub chunks: usize,
+    /// Total size of all blobs
+    pub logical_bytes: u64,
+    /// Size of the distinct chunks actually stored
+    pub stored_bytes: u64,
+}
+
+impl DedupStats {
+    /// Bytes not stored thanks to shared chunks
+    pub fn saved_bytes(&self) -> u64 {
+        self.logical_bytes - self.stored_bytes
+    }
+}
+
+/// Outcome of checking the chunk index
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexCheck {
+    pub chunks_checked: usize,
+    /// Chunks a blob needs that are not stored
+    pub missing_chunks: Vec<String>,
+    /// Chunks whose reference count disagrees with the blobs using them
+    pub miscounted_chunks: Vec<String>,
+}
+
+impl IndexCheck {
+    /// Whether the index matches the stored chunks
+    pub fn is_consistent(&self) -> bool {
+        self.missing_chunks.is_empty() && self.miscounted_chunks.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ChunkRef {
+    refs: u64,
+    size: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    /// Chunk hashes of each blob, in order
+    blobs: HashMap<String, Vec<String>>,
+    chunks: HashMap<String, ChunkRef>,
+}
+
+impl Index {
+    fn add(&mut self, hash: &str, chunks: &[(String, u64)]) {
+        for (chunk, size) in chunks {
+            let entry = self.chunks.entry(chunk.clone()).or_default();
+            entry.refs += 1;
+            entry.size = *size;
+        }
+        let ids = chunks.iter().map(|(chunk, _)| chunk.clone()).collect();
+        self.blobs.insert(hash.to_string(), ids);
+    }
+
+    /// Drop a blob, returning chunks no longer referenced
+    fn remove(&mut self, hash: &str) -> Vec<String> {
+        let mut freed = Vec::new();
+        for chunk in self.blobs.remove(hash).unwrap_or_default() {
+            if let Some(entry) = self.chunks.get_mut(&chunk) {
+                entry.refs -= 1;
+                if entry.refs == 0 {
+                    self.chunks.remove(&chunk);
+                    freed.push(chunk);
+                }
+            }
+        }
+        freed
+    }
+
+    fn stats(&self) -> DedupStats {
+        let size = |chunk: &String| self.chunks.get(chunk).map_or(0, |entry| entry.size);
+        DedupStats {
+            blobs: self.blobs.len(),
+            chunks: self.chunks.len(),
+            logical_bytes: self.blobs.values().flatten().map(size).sum(),
+            stored_bytes: self.chunks.values().map(|entry| entry.size).sum(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalEntry {
+    /// Chunks about to be written for a put
+    Prepare {
+        chunks: Vec<String>,
+    },
+    Put {
+        hash: String,
+        chunks: Vec<(String, u64)>,
+    },
+    Delete {
+        hash: String,
+    },
+}
+
+struct Journal {
+    snapshot: PathBuf,
+    file: File,
+    entries: usize,
+}
+
+impl Journal {
+    fn append(&mut self, entry: &JournalEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Fold the journal into a new snapshot
+    ///
+    /// The journal is only emptied once the snapshot is on disk; a crash in
+    /// between replays entries the snapshot already holds, which is safe.
+    fn compact(&mut self, index: &Index) -> anyhow::Result<()> {
+        write_durable(&self.snapshot, &serde_json::to_vec(index)?)?;
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.entries = 0;
+        Ok(())
+    }
+}
+
+struct State {
+    index: Index,
+    journal: Option<Journal>,
+}
+
+impl State {
+    fn log(&mut self, entry: &JournalEntry) -> anyhow::Result<()> {
+        match &mut self.journal {
+            Some(journal) => journal.append(entry),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Content store keeping each distinct chunk once
+pub struct DedupStore {
+    inner: Arc<dyn ContentStore>,
+    state: Mutex<State>,
+}
+
+impl DedupStore {
+    /// Deduplicate content written to `inner`, with an in-memory index
+    pub fn new(inner: Arc<dyn ContentStore>) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(State {
+                index: Index::default(),
+                journal: None,
+            }),
+        }
+    }
+
+    /// Deduplicate with the index persisted at `path`
+    ///
+    /// The journal lives next to it, with a `.journal` extension. Changes
+    /// interrupted by a crash are cleaned up here.
+    pub fn open(inner: Arc<dyn ContentStore>, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let snapshot = path.as_ref().to_path_buf();
+        let journal_path = snapshot.with_extension("journal");
+        let mut index: Index = match std::fs::read(&snapshot) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut garbage = HashSet::new();
+        // Chunks of puts that never committed
+        let mut pending = HashSet::new();
+        if let Ok(file) = File::open(&journal_path) {
+            for line in BufReader::new(file).lines() {
+                // A torn last line is a change that never committed
+                let Ok(entry) = serde_json::from_str::<JournalEntry>(&line?) else {
+                    break;
+                };
+                match entry {
+                    JournalEntry::Prepare { chunks } => pending.extend(chunks),
+                    JournalEntry::Put { hash, chunks } => {
+                        for (chunk, _) in &chunks {
+                            pending.remove(chunk);
+                        }
+                        if !index.blobs.contains_key(&hash) {
+                            index.add(&hash, &chunks);
+                        }
+                    }
+                    JournalEntry::Delete { hash } => garbage.extend(index.remove(&hash)),
+                }
+            }
+        }
+        garbage.extend(pending);
+        for chunk in garbage {
+            if !index.chunks.contains_key(&chunk) {
+                inner.delete_content(&chunk_key(&chunk))?;
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+        let mut journal = Journal {
+            snapshot,
+            file,
+            entries: 0,
+        };
+        journal.compact(&index)?;
+        Ok(Self {
+            inner,
+            state: Mutex::new(State {
+                index,
+                journal: Some(journal),
+            }),
+        })
+    }
+
+    /// Hashes of the blobs in the store
+    pub fn blobs(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .index
+            .blobs
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Chunk hashes of the blob `hash`, in order
+    pub fn chunks(&self, hash: &str) -> Option<Vec<String>> {
+        self.state.lock().unwrap().index.blobs.get(hash).cloned()
+    }
+
+    /// Current deduplication savings
+    pub fn stats(&self) -> DedupStats {
+        self.state.lock().unwrap().index.stats()
+    }
+
+    /// Blobs not in `referenced`, and the bytes only they hold
+    pub fn orphans(&self, referenced: &HashSet<String>) -> (usize, u64) {
+        let state = self.state.lock().unwrap();
+        let index = &state.index;
+        let live: HashSet<&String> = index
+            .blobs
+            .iter()
+            .filter(|(hash, _)| referenced.contains(*hash))
+            .flat_map(|(_, chunks)| chunks)
+            .collect();
+        let orphans = index
+            .blobs
+            .keys()
+            .filter(|hash| !referenced.contains(*hash))
+            .count();
+        let bytes = index
+            .chunks
+            .iter()
+            .filter(|(chunk, _)| !live.contains(chunk))
+            .map(|(_, entry)| entry.size)
+            .sum();
+        (orphans, bytes)
+    }
+
+    /// Check the chunk index against the chunks actually stored
+    pub fn check_index(&self) -> anyhow::Result<IndexCheck> {
+        let state = self.state.lock().unwrap();
+        let index = &state.index;
+        let mut refs: HashMap<&String, u64> = HashMap::new();
+        for chunk in index.blobs.values().flatten() {
+            *refs.entry(chunk).or_default() += 1;
+        }
+        let mut check = IndexCheck {
+            chunks_checked: index.chunks.len(),
+            ..Default::default()
+        };
+        for (chunk, entry) in &index.chunks {
+            if !self.inner.has_content(&chunk_key(chunk))? {
+                check.missing_chunks.push(chunk.clone());
+            }
+            if refs.get(chunk).copied().unwrap_or(0) != entry.refs {
+                check.miscounted_chunks.push(chunk.clone());
+            }
+        }
+        // Chunks a blob lists but the index lost track of
+        check.missing_chunks.extend(
+            refs.keys()
+                .filter(|chunk| !index.chunks.contains_key(**chunk))
+                .map(|chunk| chunk.to_string()),
+        );
+        check.missing_chunks.sort();
+        check.miscounted_chunks.sort();
+        Ok(check)
+    }
+
+    /// Compact the journal when due and refresh the savings metric
+    fn committed(&self, state: &mut State) -> anyhow::Result<()> {
+        if let Some(journal) = &mut state.journal {
+            if journal.entries >= COMPACT_AFTER {
+                journal.compact(&state.index)?;
+            }
+        }
+        nomade_metrics::global()
+            .gauge(
+                names::DEDUP_SAVED_BYTES,
+                "Content bytes not stored thanks to shared chunks",
+            )
+            .set(state.index.stats().saved_bytes() as i64);
+        Ok(())
+    }
+}
+
+fn chunk_key(chunk: &str) -> String {
+    format!("chunk:{}", chunk)
+}
+
+impl ContentStore for DedupStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.index.blobs.contains_key(hash) {
+            return Ok(());
+        }
+        let chunks: Vec<(&[u8], String)> = data
+            .chunks(HASH_GROUP_SIZE)
+            .map(|chunk| (chunk, content_hash(chunk)))
+            .collect();
+        let mut new: HashSet<&String> = chunks
+            .iter()
+            .map(|(_, id)| id)
+            .filter(|id| !state.index.chunks.contains_key(*id))
+            .collect();
+
+        let prepare = JournalEntry::Prepare {
+            chunks: new.iter().map(|id| id.to_string()).collect(),
+        };
+        state.log(&prepare)?;
+        for (chunk, id) in &chunks {
+            // Repeated chunks within the blob are written once too
+            if new.remove(id) {
+                self.inner.put_content(&chunk_key(id), chunk)?;
+            }
+        }
+        let refs: Vec<(String, u64)> = chunks
+            .iter()
+            .map(|(chunk, id)| (id.clone(), chunk.len() as u64))
+            .collect();
+        state.log(&JournalEntry::Put {
+            hash: hash.to_string(),
+            chunks: refs.clone(),
+        })?;
+        state.index.add(hash, &refs);
+        self.committed(&mut state)
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let chunks = self.state.lock().unwrap().index.blobs.get(hash).cloned();
+        // Blobs stored before deduplication was enabled
+        let Some(chunks) = chunks else {
+            return self.inner.get_content(hash);
+        };
+        let mut data = Vec::new();
+        for chunk in chunks {
+            let bytes = self
+                .inner
+                .get_content(&chunk_key(&chunk))?
+                .ok_or_else(|| anyhow!("Missing chunk {} of content {}", chunk, hash))?;
+            data.extend_from_slice(&bytes);
+        }
+        Ok(Some(data))
+    }
+
+    fn has_content(&self, hash: &str) -> anyhow::Result<bool> {
+        if self.state.lock().unwrap().index.blobs.contains_key(hash) {
+            return Ok(true);
+        }
+        self.inner.has_content(hash)
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.index.blobs.contains_key(hash) {
+            let entry = JournalEntry::Delete {
+                hash: hash.to_string(),
+            };
+            state.log(&entry)?;
+            for chunk in state.index.remove(hash) {
+                self.inner.delete_content(&chunk_key(&chunk))?;
+            }
+            self.committed(&mut state)?;
+        }
+        self.inner.delete_content(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+
+    fn content(seed: u8, groups: usize) -> Vec<u8> {
+        (0..groups * HASH_GROUP_SIZE)
+            .map(|i| ((i / HASH_GROUP_SIZE) as u8).wrapping_add(seed))
+            .collect()
+    }
+
+    #[test]
+    fn test_shared_chunks_are_stored_once() {
+        let inner = Arc::new(InMemoryStore::new());
+        let store = DedupStore::new(inner.clone());
+        let original = content(0, 3);
+        // An edit of the last group shares the first two
+        let mut edited = original.clone();
+        edited[3 * HASH_GROUP_SIZE - 1] ^= 1;
+        store.put_content("a", &original).unwrap();
+        store.put_content("b", &original).unwrap();
+        store.put_content("c", &edited).unwrap();
+
+        let stats = store.stats();
+        assert_eq!((stats.blobs, stats.chunks), (3, 4));
+        assert_eq!(stats.saved_bytes(), 5 * HASH_GROUP_SIZE as u64);
+        assert_eq!(store.get_content("c").unwrap().unwrap(), edited);
+
+        store.delete_content("a").unwrap();
+        store.delete_content("b").unwrap();
+        assert_eq!(store.stats().chunks, 3);
+        let last = content_hash(&original[2 * HASH_GROUP_SIZE..]);
+        assert!(!inner.has_content(&chunk_key(&last)).unwrap());
+        assert_eq!(store.get_content("c").unwrap().unwrap(), edited);
+
+        // Blobs written before deduplication still read back
+        inner.put_content("legacy", b"old").unwrap();
+        assert_eq!(store.get_content("legacy").unwrap().unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_replay_recovers_interrupted_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chunks.json");
+        let inner = Arc::new(InMemoryStore::new());
+        let data = content(7, 2);
+        {
+            let store = DedupStore::open(inner.clone(), &path).unwrap();
+            store.put_content("kept", &data).unwrap();
+        }
+
+        // A put that wrote its chunk but crashed before committing, and a
+        // torn entry after it
+        let orphan = content_hash(b"orphan");
+        inner.put_content(&chunk_key(&orphan), b"orphan").unwrap();
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(path.with_extension("journal"))
+            .unwrap();
+        writeln!(journal, r#"{{"op":"prepare","chunks":["{}"]}}"#, orphan).unwrap();
+        write!(journal, r#"{{"op":"put","hash":"#).unwrap();
+        drop(journal);
+
+        let store = DedupStore::open(inner.clone(), &path).unwrap();
+        assert!(!inner.has_content(&chunk_key(&orphan)).unwrap());
+        assert_eq!(store.get_content("kept").unwrap().unwrap(), data);
+        assert_eq!(store.stats().chunks, 2);
+
+        store.delete_content("kept").unwrap();
+        drop(store);
+        let store = DedupStore::open(inner.clone(), &path).unwrap();
+        assert_eq!(store.stats(), DedupStats::default());
+    }
+
+    #[test]
+    fn test_replay_after_compaction_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chunks.json");
+        let inner = Arc::new(InMemoryStore::new());
+        let (kept, gone) = (content(1, 2), content(2, 1));
+        let journal = {
+            let store = DedupStore::open(inner.clone(), &path).unwrap();
+            store.put_content("kept", &kept).unwrap();
+            store.put_content("gone", &gone).unwrap();
+            store.delete_content("gone").unwrap();
+            std::fs::read(path.with_extension("journal")).unwrap()
+        };
+        let stats = DedupStore::open(inner.clone(), &path).unwrap().stats();
+        assert!(std::fs::read(path.with_extension("journal"))
+            .unwrap()
+            .is_empty());
+
+        // A crash after the snapshot was written but before the journal was
+        // emptied replays changes the snapshot already holds
+        std::fs::write(path.with_extension("journal"), journal).unwrap();
+        let store = DedupStore::open(inner.clone(), &path).unwrap();
+        assert_eq!(store.stats(), stats);
+        assert_eq!(store.get_content("kept").unwrap().unwrap(), kept);
+        store.delete_content("kept").unwrap();
+        assert_eq!(store.stats(), DedupStats::default());
+    }
+
+    #[test]
+    fn test_check_index_finds_lost_chunks() {
+        let inner = Arc::new(InMemoryStore::new());
+        let store = DedupStore::new(inner.clone());
+        let shared = content(0, 2);
+        let mut other = shared.clone();
+        other.extend(content(9, 1));
+        store.put_content("a", &shared).unwrap();
+        store.put_content("b", &other).unwrap();
+        assert!(store.check_index().unwrap().is_consistent());
+
+        // Only the chunk "b" adds is held by "b" alone
+        let referenced = HashSet::from(["a".to_string()]);
+        assert_eq!(store.orphans(&referenced), (1, HASH_GROUP_SIZE as u64));
+
+        let lost = content_hash(&shared[..HASH_GROUP_SIZE]);
+        inner.delete_content(&chunk_key(&lost)).unwrap();
+        let check = store.check_index().unwrap();
+        assert_eq!(check.chunks_checked, 3);
+        assert_eq!(check.missing_chunks, [lost]);
+        assert!(!check.is_consistent());
+    }
+}
@@ -0,0 +1,324 @@
+//! Derived assets (thumbnails, excerpts)
+//!
+//! `ArtifactProcessor`s turn artifact content into small derived blobs for
+//! list views. Results are cached under (content hash, processor, params),
+//! so changed content never hits a stale entry; `prune` drops entries for
+//! content no artifact references anymore.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::anyhow;
+
+use crate::{Artifact, ArtifactStore, ContentStore};
+
+/// Producer of a derived asset from artifact content
+pub trait ArtifactProcessor: Send + Sync {
+    /// Unique processor name (e.g. "thumbnail")
+    fn name(&self) -> &str;
+
+    /// Whether the processor handles this MIME type
+    fn accepts(&self, content_type: &str) -> bool;
+
+    /// Derive the asset; `params` is processor specific (empty for defaults)
+    fn process(&self, content: &[u8], params: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Plain-text excerpt of text content
+///
+/// Params: maximum number of characters (default 200).
+pub struct TextExcerpt;
+
+impl TextExcerpt {
+    const DEFAULT_CHARS: usize = 200;
+}
+
+impl ArtifactProcessor for TextExcerpt {
+    fn name(&self) -> &str {
+        "excerpt"
+    }
+
+    fn accepts(&self, content_type: &str) -> bool {
+        content_type.starts_with("text/") || content_type == "application/json"
+    }
+
+    fn process(&self, content: &[u8], params: &str) -> anyhow::Result<Vec<u8>> {
+        let max_chars = if params.is_empty() {
+            Self::DEFAULT_CHARS
+        } else {
+            params.parse()?
+        };
+        let text = String::from_utf8_lossy(content);
+        let excerpt: String = text
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(max_chars)
+            .collect();
+        Ok(excerpt.into_bytes())
+    }
+}
+
+/// PNG thumbnail of PNG or JPEG images
+///
+/// Params: maximum edge length in pixels (default 256).
+#[cfg(feature = "thumbnails")]
+pub struct ImageThumbnail;
+
+#[cfg(feature = "thumbnails")]
+impl ImageThumbnail {
+    const DEFAULT_EDGE: u32 = 256;
+}
+
+#[cfg(feature = "thumbnails")]
+impl ArtifactProcessor for ImageThumbnail {
+    fn name(&self) -> &str {
+        "thumbnail"
+    }
+
+    fn accepts(&self, content_type: &str) -> bool {
+        matches!(content_type, "image/png" | "image/jpeg")
+    }
+
+    fn process(&self, content: &[u8], params: &str) -> anyhow::Result<Vec<u8>> {
+        let edge = if params.is_empty() {
+            Self::DEFAULT_EDGE
+        } else {
+            params.parse()?
+        };
+        let thumbnail = image::load_from_memory(content)?.thumbnail(edge, edge);
+        let mut png = std::io::Cursor::new(Vec::new());
+        thumbnail.write_to(&mut png, image::ImageFormat::Png)?;
+        Ok(png.into_inner())
+    }
+}
+
+/// Processors built into Nomade
+pub fn default_processors() -> Vec<Arc<dyn ArtifactProcessor>> {
+    vec![
+        Arc::new(TextExcerpt),
+        #[cfg(feature = "thumbnails")]
+        Arc::new(ImageThumbnail),
+    ]
+}
+
+/// Registry of processors and cache of their output
+pub struct DerivedAssets {
+    content: Arc<dyn ContentStore>,
+    processors: RwLock<BTreeMap<String, Arc<dyn ArtifactProcessor>>>,
+    /// Cache directory; in-memory cache when `None`
+    dir: Option<PathBuf>,
+    memory: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl DerivedAssets {
+    /// Create with an in-memory cache
+    pub fn new(content: Arc<dyn ContentStore>) -> Self {
+        Self {
+            content,
+            processors: RwLock::new(BTreeMap::new()),
+            dir: None,
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create with a cache in `dir`, creating the directory if needed
+    pub fn open(dir: impl AsRef<Path>, content: Arc<dyn ContentStore>) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: Some(dir.as_ref().to_path_buf()),
+            ..Self::new(content)
+        })
+    }
+
+    /// Register a processor, replacing any with the same name
+    pub fn register(&self, processor: Arc<dyn ArtifactProcessor>) {
+        self.processors
+            .write()
+            .unwrap()
+            .insert(processor.name().to_string(), processor);
+    }
+
+    /// Names of registered processors
+    pub fn processors(&self) -> Vec<String> {
+        self.processors.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Derived asset for an artifact, computing and caching it on a miss
+    ///
+    /// Returns `None` if the processor does not handle the artifact's
+    /// content type or the content is not stored locally.
+    pub fn get(
+        &self,
+        artifact: &Artifact,
+        processor: &str,
+        params: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let processor = self
+            .processors
+            .read()
+            .unwrap()
+            .get(processor)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown processor: {}", processor))?;
+        let accepted = artifact
+            .content_type
+            .as_deref()
+            .is_some_and(|t| processor.accepts(t));
+        if !accepted {
+            return Ok(None);
+        }
+
+        let key = cache_key(&artifact.content_hash, processor.name(), params);
+        if let Some(cached) = self.read(&key)? {
+            return Ok(Some(cached));
+        }
+        let Some(content) = self.content.get_content(&artifact.content_hash)? else {
+            return Ok(None);
+        };
+        let derived = processor.process(&content, params)?;
+        self.write(&key, &derived)?;
+        Ok(Some(derived))
+    }
+
+    /// Drop cached assets derived from content with this hash
+    pub fn invalidate(&self, content_hash: &str) -> anyhow::Result<usize> {
+        self.retain(|hash| hash != content_hash)
+    }
+
+    /// Drop cached assets whose content no artifact references anymore
+    pub fn prune(&self, artifacts: &dyn ArtifactStore) -> anyhow::Result<usize> {
+        let live: HashSet<String> = artifacts
+            .list()?
+            .into_iter()
+            .map(|a| a.content_hash)
+            .collect();
+        self.retain(|hash| live.contains(hash))
+    }
+
+    fn read(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(dir) = &self.dir else {
+            return Ok(self.memory.lock().unwrap().get(key).cloned());
+        };
+        match std::fs::read(dir.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let Some(dir) = &self.dir else {
+            self.memory
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), data.to_vec());
+            return Ok(());
+        };
+        let tmp = dir.join(format!("{}.tmp", key));
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, dir.join(key))?;
+        Ok(())
+    }
+
+    /// Keep entries whose content hash satisfies `keep`; returns removed count
+    fn retain(&self, keep: impl Fn(&str) -> bool) -> anyhow::Result<usize> {
+        let hash_of = |key: &str| {
+            key.split_once('-')
+                .map_or(key, |(hash, _)| hash)
+                .to_string()
+        };
+        let Some(dir) = &self.dir else {
+            let mut memory = self.memory.lock().unwrap();
+            let before = memory.len();
+            memory.retain(|key, _| keep(&hash_of(key)));
+            return Ok(before - memory.len());
+        };
+        let mut removed = 0;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !keep(&hash_of(&name)) {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Cache file name: content hash plus a digest of processor and params
+fn cache_key(content_hash: &str, processor: &str, params: &str) -> String {
+    let digest = blake3::hash(format!("{}\0{}", processor, params).as_bytes()).to_hex();
+    format!("{}-{}", content_hash, &digest[..16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content_hash, InMemoryStore};
+
+    fn stored(store: &InMemoryStore, id: &str, content_type: &str, data: &[u8]) -> Artifact {
+        let hash = content_hash(data);
+        store.put_content(&hash, data).unwrap();
+        let artifact = Artifact {
+            id: id.into(),
+            content_hash: hash,
+            content_type: Some(content_type.into()),
+            ..Default::default()
+        };
+        store.store(&artifact).unwrap();
+        artifact
+    }
+
+    #[test]
+    fn test_excerpt_cached_by_content() {
+        let store = Arc::new(InMemoryStore::new());
+        let dir = tempfile::tempdir().unwrap();
+        let derived = DerivedAssets::open(dir.path(), store.clone()).unwrap();
+        derived.register(Arc::new(TextExcerpt));
+
+        let note = stored(&store, "note", "text/plain", b"Hello\n\n  nomade world");
+        let excerpt = derived.get(&note, "excerpt", "12").unwrap().unwrap();
+        assert_eq!(excerpt, b"Hello nomade");
+        assert_eq!(
+            derived.get(&note, "excerpt", "").unwrap().unwrap(),
+            b"Hello nomade world"
+        );
+
+        // Editing the note changes its hash, so the old entries get pruned
+        let edited = stored(&store, "note", "text/plain", b"Goodbye");
+        assert_eq!(
+            derived.get(&edited, "excerpt", "").unwrap().unwrap(),
+            b"Goodbye"
+        );
+        assert_eq!(derived.prune(store.as_ref()).unwrap(), 2);
+        assert_eq!(derived.invalidate(&edited.content_hash).unwrap(), 1);
+
+        let image = stored(&store, "pic", "image/png", b"not text");
+        assert!(derived.get(&image, "excerpt", "").unwrap().is_none());
+        assert!(derived.get(&note, "missing", "").is_err());
+    }
+
+    #[cfg(feature = "thumbnails")]
+    #[test]
+    fn test_thumbnail() {
+        let store = Arc::new(InMemoryStore::new());
+        let derived = DerivedAssets::new(store.clone());
+        for processor in default_processors() {
+            derived.register(processor);
+        }
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(640, 320)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let artifact = stored(&store, "pic", "image/png", png.get_ref());
+
+        let thumbnail = derived.get(&artifact, "thumbnail", "64").unwrap().unwrap();
+        let decoded = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 32));
+    }
+}
@@ -0,0 +1,484 @@
+//! Content encrypted at rest with wrapped per-blob keys
+//!
+//! `EncryptedContentStore` wraps another `ContentStore`. Each blob is
+//! encrypted under its own random data key (DEK), stored as
+//! `nonce || ciphertext` under the content hash; the DEK is wrapped by the
+//! device master key and stored separately under `dek:<hash>`. Rotating the
+//! master key rewraps DEKs only, never the blobs themselves. The master is
+//! long-lived, so it is held in a `KeyContext` that draws its wrapping
+//! nonces and enforces the key's usage limit.
+//!
+//! With a device identity, each DEK is also sealed to the paired devices
+//! (`KeyShares` under `shares:<hash>`). Shares travel with synced metadata
+//! and the receiving device adopts the DEK before storing the content, so
+//! every device keeps a blob under the same key.
+//!
+//! Rotating a DEK re-encrypts the blob. The new key is first staged under
+//! `dek-next:<hash>`, so a blob written just before a crash still opens.
+
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+use nomade_crypto::{
+    ct_eq, decrypt_data, encrypt_data, generate_key, unwrap_key_with, wrap_key_with, DeviceKeypair,
+    EncryptedData, KeyContext, KeyRecipient, KeyShares, WrappedKey,
+};
+
+use crate::{ArtifactStore, ContentStore};
+
+/// AES-GCM nonce length prefixed to stored blobs
+const NONCE_LEN: usize = 12;
+
+/// Content store encrypting blobs under master-wrapped data keys
+pub struct EncryptedContentStore {
+    inner: Arc<dyn ContentStore>,
+    /// Current master key first, then retired ones still able to unwrap
+    masters: RwLock<Vec<KeyContext>>,
+    /// Opens key shares sealed to this device
+    identity: Option<DeviceKeypair>,
+    /// Devices new keys are shared with
+    recipients: RwLock<Vec<KeyRecipient>>,
+}
+
+impl EncryptedContentStore {
+    /// Encrypt content written to `inner` under `master`
+    pub fn new(inner: Arc<dyn ContentStore>, master: KeyContext) -> Self {
+        Self {
+            inner,
+            masters: RwLock::new(vec![master]),
+            identity: None,
+            recipients: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Share data keys as `identity`, and accept keys shared with it
+    pub fn with_identity(mut self, identity: DeviceKeypair) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Wrap new keys under `master`, keeping older masters for reading
+    pub fn rotate_master(&self, master: KeyContext) {
+        let mut masters = self.masters.write().unwrap();
+        let id = master.key_id();
+        masters.retain(|key| key.key_id() != id);
+        masters.insert(0, master);
+    }
+
+    /// Rewrap the keys of all referenced content under the current master
+    ///
+    /// Returns the number of keys rewrapped. Once it succeeds, retired
+    /// masters can be dropped with `retire_previous`.
+    pub fn rewrap(&self, artifacts: &dyn ArtifactStore) -> anyhow::Result<usize> {
+        let current_id = self.masters.read().unwrap()[0].key_id();
+        let hashes: HashSet<String> = artifacts
+            .list()?
+            .into_iter()
+            .map(|artifact| artifact.content_hash)
+            .collect();
+
+        let mut rewrapped = 0;
+        for hash in hashes {
+            let Some(wrapped) = self.wrapped_key(&hash)? else {
+                continue;
+            };
+            if ct_eq(wrapped.kek_id.as_bytes(), current_id.as_bytes()) {
+                continue;
+            }
+            let dek = self.unwrap(&wrapped)?;
+            self.put_wrapped_key(&hash, &self.wrap(&dek)?)?;
+            rewrapped += 1;
+        }
+        Ok(rewrapped)
+    }
+
+    /// Forget every master key but the current one
+    pub fn retire_previous(&self) {
+        self.masters.write().unwrap().truncate(1);
+    }
+
+    /// Wrap `dek` under the current master
+    fn wrap(&self, dek: &[u8; 32]) -> anyhow::Result<WrappedKey> {
+        Ok(wrap_key_with(&mut self.masters.write().unwrap()[0], dek)?)
+    }
+
+    fn wrapped_key(&self, hash: &str) -> anyhow::Result<Option<WrappedKey>> {
+        self.inner
+            .get_content(&dek_key(hash))?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn put_wrapped_key(&self, hash: &str, wrapped: &WrappedKey) -> anyhow::Result<()> {
+        self.inner
+            .put_content(&dek_key(hash), &serde_json::to_vec(wrapped)?)
+    }
+
+    fn put_key_shares(&self, hash: &str, shares: &KeyShares) -> anyhow::Result<()> {
+        self.inner
+            .put_content(&shares_key(hash), &serde_json::to_vec(shares)?)
+    }
+
+    /// Seal `dek` to the current recipients, keeping existing shares
+    ///
+    /// Until recipients are known, shares are left as they are.
+    fn share(&self, hash: &str, dek: &[u8; 32]) -> anyhow::Result<()> {
+        let recipients = self.recipients.read().unwrap().clone();
+        if self.identity.is_none() || recipients.is_empty() {
+            return Ok(());
+        }
+        let existing = self.key_shares(hash)?.unwrap_or_default();
+        if !existing.is_shared_with(&recipients) {
+            self.put_key_shares(hash, &existing.reseal(dek, &recipients)?)?;
+        }
+        Ok(())
+    }
+
+    fn staged_key(&self, hash: &str) -> anyhow::Result<Option<WrappedKey>> {
+        self.inner
+            .get_content(&staged_key(hash))?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Re-encrypt stored content under `dek`, shared as `shares`
+    ///
+    /// Returns `false` if the content is not stored.
+    fn rekey(&self, hash: &str, dek: &[u8; 32], shares: &KeyShares) -> anyhow::Result<bool> {
+        let Some(data) = self.get_content(hash)? else {
+            return Ok(false);
+        };
+        let wrapped = self.wrap(dek)?;
+        self.inner
+            .put_content(&staged_key(hash), &serde_json::to_vec(&wrapped)?)?;
+        let encrypted = encrypt_data(&data, dek)?;
+        self.inner
+            .put_content(hash, &[encrypted.nonce, encrypted.ciphertext].concat())?;
+        self.put_wrapped_key(hash, &wrapped)?;
+        match shares.keys.is_empty() {
+            true => self.inner.delete_content(&shares_key(hash))?,
+            false => self.put_key_shares(hash, shares)?,
+        }
+        self.inner.delete_content(&staged_key(hash))?;
+        Ok(true)
+    }
+
+    fn decrypt(&self, hash: &str, blob: &[u8], wrapped: &WrappedKey) -> anyhow::Result<Vec<u8>> {
+        if blob.len() < NONCE_LEN {
+            return Err(anyhow!("Truncated encrypted content {}", hash));
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let encrypted = EncryptedData {
+            ciphertext: ciphertext.to_vec(),
+            nonce: nonce.to_vec(),
+            algorithm: "AES-256-GCM".into(),
+        };
+        Ok(decrypt_data(&encrypted, &self.unwrap(wrapped)?)?)
+    }
+
+    fn unwrap(&self, wrapped: &WrappedKey) -> anyhow::Result<[u8; 32]> {
+        let masters = self.masters.read().unwrap();
+        let master = masters
+            .iter()
+            .find(|key| ct_eq(key.key_id().as_bytes(), wrapped.kek_id.as_bytes()))
+            .ok_or_else(|| anyhow!("No master key {} to unwrap content", wrapped.kek_id))?;
+        Ok(unwrap_key_with(master, wrapped)?)
+    }
+}
+
+fn dek_key(hash: &str) -> String {
+    format!("dek:{}", hash)
+}
+
+fn shares_key(hash: &str) -> String {
+    format!("shares:{}", hash)
+}
+
+fn staged_key(hash: &str) -> String {
+    format!("dek-next:{}", hash)
+}
+
+impl ContentStore for EncryptedContentStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        // A key without its blob was adopted from a peer or left by an
+        // interrupted put; reuse it so shares stay valid
+        let adopted = match self.wrapped_key(hash)? {
+            Some(wrapped) if !self.inner.has_content(hash)? => self.unwrap(&wrapped).ok(),
+            _ => None,
+        };
+        let dek = match adopted {
+            Some(dek) => dek,
+            None => {
+                let dek = generate_key();
+                // Key first: a crash in between leaves an unused key, not an unreadable blob
+                self.put_wrapped_key(hash, &self.wrap(&dek)?)?;
+                self.inner.delete_content(&shares_key(hash))?;
+                dek
+            }
+        };
+        self.share(hash, &dek)?;
+        let encrypted = encrypt_data(data, &dek)?;
+        self.inner
+            .put_content(hash, &[encrypted.nonce, encrypted.ciphertext].concat())
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(blob) = self.inner.get_content(hash)? else {
+            return Ok(None);
+        };
+        let wrapped = self
+            .wrapped_key(hash)?
+            .ok_or_else(|| anyhow!("Missing data key for content {}", hash))?;
+        match self.decrypt(hash, &blob, &wrapped) {
+            Ok(data) => Ok(Some(data)),
+            // Rotation interrupted after the blob was rewritten
+            Err(e) => match self.staged_key(hash)? {
+                Some(staged) => self.decrypt(hash, &blob, &staged).map(Some),
+                None => Err(e),
+            },
+        }
+    }
+
+    fn has_content(&self, hash: &str) -> anyhow::Result<bool> {
+        self.inner.has_content(hash)
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        // Blob first: a crash in between leaves an unused key, as in `put_content`
+        self.inner.delete_content(hash)?;
+        self.inner.delete_content(&dek_key(hash))?;
+        self.inner.delete_content(&staged_key(hash))?;
+        self.inner.delete_content(&shares_key(hash))
+    }
+
+    fn key_shares(&self, hash: &str) -> anyhow::Result<Option<KeyShares>> {
+        self.inner
+            .get_content(&shares_key(hash))?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    fn adopt_key_shares(&self, hash: &str, shares: &KeyShares) -> anyhow::Result<()> {
+        let Some(identity) = &self.identity else {
+            return Ok(());
+        };
+        if self.inner.has_content(hash)? {
+            if self.key_shares(hash)?.as_ref() == Some(shares) {
+                return Ok(());
+            }
+            // Follow a rotation done by another device
+            let dek = shares.open(identity)?;
+            let current = self
+                .wrapped_key(hash)?
+                .map(|wrapped| self.unwrap(&wrapped))
+                .transpose()?;
+            return match current == Some(dek) {
+                true => self.put_key_shares(hash, shares),
+                false => self.rekey(hash, &dek, shares).map(drop),
+            };
+        }
+        let dek = shares.open(identity)?;
+        self.put_wrapped_key(hash, &self.wrap(&dek)?)?;
+        self.put_key_shares(hash, shares)
+    }
+
+    fn rotate_key(&self, hash: &str) -> anyhow::Result<bool> {
+        let dek = generate_key();
+        let recipients = self.recipients.read().unwrap().clone();
+        let shares = match self.identity.is_some() {
+            true => KeyShares::seal(&dek, &recipients)?,
+            false => KeyShares::default(),
+        };
+        self.rekey(hash, &dek, &shares)
+    }
+
+    fn share_keys(
+        &self,
+        recipients: &[KeyRecipient],
+        artifacts: &dyn ArtifactStore,
+    ) -> anyhow::Result<usize> {
+        *self.recipients.write().unwrap() = recipients.to_vec();
+        if self.identity.is_none() || recipients.is_empty() {
+            return Ok(0);
+        }
+        let hashes: HashSet<String> = artifacts
+            .list()?
+            .into_iter()
+            .map(|artifact| artifact.content_hash)
+            .collect();
+
+        let mut resealed = 0;
+        for hash in hashes {
+            let Some(wrapped) = self.wrapped_key(&hash)? else {
+                continue;
+            };
+            if self
+                .key_shares(&hash)?
+                .unwrap_or_default()
+                .is_shared_with(recipients)
+            {
+                continue;
+            }
+            self.share(&hash, &self.unwrap(&wrapped)?)?;
+            resealed += 1;
+        }
+        Ok(resealed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content_hash, Artifact, InMemoryStore};
+    use nomade_crypto::{wrap_key, NonceSequence};
+
+    /// Fresh master key with counter nonces
+    fn master() -> KeyContext {
+        KeyContext::new(generate_key(), NonceSequence::counter())
+    }
+
+    #[test]
+    fn test_master_rotation_rewraps_keys_only() {
+        let inner = Arc::new(InMemoryStore::new());
+        let store = EncryptedContentStore::new(inner.clone(), master());
+        let data = b"meeting notes".to_vec();
+        let hash = content_hash(&data);
+        store.put_content(&hash, &data).unwrap();
+        inner
+            .store(&Artifact {
+                id: "notes".into(),
+                content_hash: hash.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let blob = inner.get_content(&hash).unwrap().unwrap();
+        assert!(!blob.windows(data.len()).any(|w| w == data.as_slice()));
+        assert_eq!(store.get_content(&hash).unwrap().unwrap(), data);
+
+        store.rotate_master(master());
+        assert_eq!(store.get_content(&hash).unwrap().unwrap(), data);
+        assert_eq!(store.rewrap(inner.as_ref()).unwrap(), 1);
+        assert_eq!(store.rewrap(inner.as_ref()).unwrap(), 0);
+        store.retire_previous();
+
+        assert_eq!(inner.get_content(&hash).unwrap().unwrap(), blob);
+        assert_eq!(store.get_content(&hash).unwrap().unwrap(), data);
+
+        let stranger = EncryptedContentStore::new(inner, master());
+        assert!(stranger.get_content(&hash).is_err());
+    }
+
+    #[test]
+    fn test_interrupted_rotation_still_opens() {
+        let key = generate_key();
+        let inner = Arc::new(InMemoryStore::new());
+        let store = EncryptedContentStore::new(
+            inner.clone(),
+            KeyContext::new(key, NonceSequence::counter()),
+        );
+        let data = b"draft".to_vec();
+        let hash = content_hash(&data);
+        store.put_content(&hash, &data).unwrap();
+
+        // Crash after the blob was rewritten, before the key was swapped
+        let dek = generate_key();
+        inner
+            .put_content(
+                &staged_key(&hash),
+                &serde_json::to_vec(&wrap_key(&key, &dek).unwrap()).unwrap(),
+            )
+            .unwrap();
+        let encrypted = encrypt_data(&data, &dek).unwrap();
+        inner
+            .put_content(&hash, &[encrypted.nonce, encrypted.ciphertext].concat())
+            .unwrap();
+        assert_eq!(store.get_content(&hash).unwrap().unwrap(), data);
+
+        assert!(store.rotate_key(&hash).unwrap());
+        assert!(!inner.has_content(&staged_key(&hash)).unwrap());
+        assert_eq!(store.get_content(&hash).unwrap().unwrap(), data);
+    }
+
+    fn recipient(keypair: &DeviceKeypair) -> KeyRecipient {
+        KeyRecipient {
+            device_id: keypair.device_id().clone(),
+            public_key: keypair.public_key_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_paired_device_adopts_shared_key() {
+        let (laptop_id, phone_id, tablet_id) = (
+            nomade_crypto::generate_keypair(),
+            nomade_crypto::generate_keypair(),
+            nomade_crypto::generate_keypair(),
+        );
+        let devices = [recipient(&laptop_id), recipient(&phone_id)];
+        let laptop_inner = Arc::new(InMemoryStore::new());
+        let laptop = EncryptedContentStore::new(laptop_inner.clone(), master())
+            .with_identity(laptop_id.clone());
+        assert_eq!(
+            laptop.share_keys(&devices, laptop_inner.as_ref()).unwrap(),
+            0
+        );
+
+        let data = b"shared plans".to_vec();
+        let hash = content_hash(&data);
+        laptop.put_content(&hash, &data).unwrap();
+        laptop_inner
+            .store(&Artifact {
+                id: "plans".into(),
+                content_hash: hash.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+        let shares = laptop.key_shares(&hash).unwrap().unwrap();
+        assert!(shares.is_shared_with(&devices));
+
+        // The phone stores synced content under the laptop's key
+        let phone_inner = Arc::new(InMemoryStore::new());
+        let phone = EncryptedContentStore::new(phone_inner.clone(), master())
+            .with_identity(phone_id.clone());
+        phone.share_keys(&devices, phone_inner.as_ref()).unwrap();
+        phone.adopt_key_shares(&hash, &shares).unwrap();
+        phone.put_content(&hash, &data).unwrap();
+        assert_eq!(phone.key_shares(&hash).unwrap().unwrap(), shares);
+        phone_inner
+            .put_content(&hash, &laptop_inner.get_content(&hash).unwrap().unwrap())
+            .unwrap();
+        assert_eq!(phone.get_content(&hash).unwrap().unwrap(), data);
+
+        // A key rotated on the laptop is followed by the phone
+        let blob = laptop_inner.get_content(&hash).unwrap().unwrap();
+        assert!(laptop.rotate_key(&hash).unwrap());
+        assert_ne!(laptop_inner.get_content(&hash).unwrap().unwrap(), blob);
+        assert_eq!(laptop.get_content(&hash).unwrap().unwrap(), data);
+        let rotated = laptop.key_shares(&hash).unwrap().unwrap();
+        assert_ne!(rotated, shares);
+        phone.adopt_key_shares(&hash, &rotated).unwrap();
+        assert_eq!(phone.key_shares(&hash).unwrap().unwrap(), rotated);
+        phone_inner
+            .put_content(&hash, &laptop_inner.get_content(&hash).unwrap().unwrap())
+            .unwrap();
+        assert_eq!(phone.get_content(&hash).unwrap().unwrap(), data);
+
+        // Pairing the tablet and revoking the phone reseals the key
+        let devices = [recipient(&laptop_id), recipient(&tablet_id)];
+        assert_eq!(
+            laptop.share_keys(&devices, laptop_inner.as_ref()).unwrap(),
+            1
+        );
+        assert_eq!(
+            laptop.share_keys(&devices, laptop_inner.as_ref()).unwrap(),
+            0
+        );
+        let resealed = laptop.key_shares(&hash).unwrap().unwrap();
+        assert!(resealed.open(&tablet_id).is_ok());
+        assert!(resealed.open(&phone_id).is_err());
+    }
+}
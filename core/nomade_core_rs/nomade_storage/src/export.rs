@@ -0,0 +1,534 @@
+//! Plain directory export and re-import
+//!
+//! Unlike bundles, an exported directory is readable without Nomade: each
+//! artifact's content is a file named after its title, inside folders
+//! mirroring its collection. Names are sanitized to be valid on every
+//! desktop filesystem, and clashes get a ` (2)` suffix.
+//!
+//! With sidecars enabled, every file is paired with `<name>.nomade.json`
+//! holding the artifact's metadata, so importing the directory again
+//! restores the same artifact IDs. Files without a sidecar (added by hand)
+//! are imported as new artifacts with an ID derived from their path, so
+//! importing twice does not duplicate them.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::{content_hash, Artifact, ArtifactStore, CollectionStore, ContentStore, ImportReport};
+
+/// Suffix of metadata sidecar files
+pub const SIDECAR_SUFFIX: &str = ".nomade.json";
+
+/// Longest file or folder name written, in characters
+const MAX_NAME_LEN: usize = 120;
+
+/// What to write besides content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// Write a metadata sidecar next to each file
+    pub sidecars: bool,
+    /// Mirror collections as folders instead of writing every file at the top
+    pub collections: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            sidecars: true,
+            collections: true,
+        }
+    }
+}
+
+/// Progress of an export, reported after each artifact
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportProgress {
+    /// Artifacts to export
+    pub total: usize,
+    /// Artifacts handled so far, written or missing
+    pub done: usize,
+    /// File last written, relative to the export directory
+    pub current: Option<String>,
+    /// Artifacts skipped because their content is not on this device
+    pub missing: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Sidecar {
+    version: u32,
+    artifact: Artifact,
+}
+
+/// Write artifacts `ids` (all artifacts if empty) into `dir`
+///
+/// `progress` is called after each artifact; the final progress is
+/// returned.
+pub fn export_dir(
+    artifacts: &dyn ArtifactStore,
+    content: &dyn ContentStore,
+    collections: &CollectionStore,
+    ids: &[String],
+    dir: &Path,
+    options: &ExportOptions,
+    mut progress: impl FnMut(&ExportProgress),
+) -> anyhow::Result<ExportProgress> {
+    let mut selected = if ids.is_empty() {
+        artifacts.list()?
+    } else {
+        ids.iter()
+            .map(|id| {
+                artifacts
+                    .get(id)?
+                    .ok_or_else(|| anyhow!("Artifact not found: {}", id))
+            })
+            .collect::<anyhow::Result<_>>()?
+    };
+    selected.sort_by(|a, b| (&a.title, &a.id).cmp(&(&b.title, &b.id)));
+    std::fs::create_dir_all(dir)?;
+
+    let mut folders = Folders::new(collections, dir);
+    let mut state = ExportProgress {
+        total: selected.len(),
+        ..Default::default()
+    };
+    for artifact in selected {
+        state.done += 1;
+        let Some(data) = content.get_content(&artifact.content_hash)? else {
+            state.missing.push(artifact.id);
+            progress(&state);
+            continue;
+        };
+        let folder = match &artifact.collection {
+            Some(collection) if options.collections => folders.path(collection)?,
+            _ => dir.to_path_buf(),
+        };
+        let path = folders.claim(&folder, &file_name(&artifact));
+        std::fs::write(&path, &data)?;
+        if options.sidecars {
+            let sidecar = Sidecar {
+                version: 1,
+                artifact,
+            };
+            std::fs::write(sidecar_path(&path), serde_json::to_vec_pretty(&sidecar)?)?;
+        }
+        state.current = path
+            .strip_prefix(dir)
+            .ok()
+            .and_then(|rel| rel.to_str())
+            .map(|rel| rel.replace('\\', "/"));
+        progress(&state);
+    }
+    Ok(state)
+}
+
+/// Import a directory written by `export_dir`
+///
+/// Artifacts replace local ones only if newer. A file edited since it was
+/// exported counts as modified at its modification time. Folders map back
+/// to the collection recorded in the sidecar if it still exists, and
+/// otherwise to collections of the same names, created as needed.
+pub fn import_dir(
+    dir: &Path,
+    artifacts: &dyn ArtifactStore,
+    content: &dyn ContentStore,
+    collections: &mut CollectionStore,
+) -> anyhow::Result<ImportReport> {
+    let mut files = Vec::new();
+    walk(dir, &mut files)?;
+    files.sort();
+
+    let mut report = ImportReport::default();
+    for path in files {
+        let rel = path.strip_prefix(dir)?;
+        let data = std::fs::read(&path)?;
+        let hash = content_hash(&data);
+        let modified = std::fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut artifact = match std::fs::read(sidecar_path(&path)) {
+            Ok(json) => {
+                let sidecar: Sidecar = serde_json::from_slice(&json)
+                    .map_err(|e| anyhow!("Bad sidecar for {}: {}", rel.display(), e))?;
+                let mut artifact = sidecar.artifact;
+                if artifact.content_hash != hash {
+                    artifact.content_hash = hash.clone();
+                    artifact.modified_at = modified.max(artifact.modified_at + 1);
+                }
+                artifact
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let rel_str = rel.to_string_lossy().replace('\\', "/");
+                Artifact {
+                    id: format!(
+                        "import-{}",
+                        &blake3::hash(rel_str.as_bytes()).to_hex()[..16]
+                    ),
+                    title: file_stem(&path),
+                    created_at: modified,
+                    modified_at: modified,
+                    content_hash: hash.clone(),
+                    content_type: path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .and_then(content_type),
+                    ..Default::default()
+                }
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match artifacts.get(&artifact.id)? {
+            Some(local) if local.modified_at >= artifact.modified_at => {
+                report.skipped.push(artifact.id);
+                continue;
+            }
+            Some(_) => report.updated.push(artifact.id.clone()),
+            None => report.created.push(artifact.id.clone()),
+        }
+
+        let recorded = artifact
+            .collection
+            .take()
+            .filter(|id| collections.get(id).is_some());
+        let names: Vec<String> = rel
+            .parent()
+            .into_iter()
+            .flat_map(|parent| parent.iter())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        artifact.collection = match recorded {
+            Some(id) => Some(id),
+            None if names.is_empty() => None,
+            None => Some(resolve_collection(collections, &names)?),
+        };
+        content.put_content(&hash, &data)?;
+        artifacts.store(&artifact)?;
+    }
+    Ok(report)
+}
+
+/// Folders of collections, and the names already taken in each
+struct Folders<'a> {
+    collections: &'a CollectionStore,
+    root: &'a Path,
+    paths: HashMap<String, PathBuf>,
+    /// Lowercased paths written, for case-insensitive filesystems
+    taken: HashSet<PathBuf>,
+}
+
+impl<'a> Folders<'a> {
+    fn new(collections: &'a CollectionStore, root: &'a Path) -> Self {
+        Self {
+            collections,
+            root,
+            paths: HashMap::new(),
+            taken: HashSet::new(),
+        }
+    }
+
+    /// Folder of collection `id`, created on first use
+    ///
+    /// Artifacts of a deleted collection go to the top.
+    fn path(&mut self, id: &str) -> anyhow::Result<PathBuf> {
+        if let Some(path) = self.paths.get(id) {
+            return Ok(path.clone());
+        }
+        let Some(collection) = self.collections.get(id) else {
+            return Ok(self.root.to_path_buf());
+        };
+        let parent = match &collection.parent {
+            Some(parent) => self.path(parent)?,
+            None => self.root.to_path_buf(),
+        };
+        let path = self.claim(&parent, &sanitize(&collection.name, &collection.id));
+        std::fs::create_dir_all(&path)?;
+        self.paths.insert(id.to_string(), path.clone());
+        Ok(path)
+    }
+
+    /// Free path for `name` in `folder`
+    fn claim(&mut self, folder: &Path, name: &str) -> PathBuf {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+            _ => (name, String::new()),
+        };
+        (1..)
+            .map(|n| match n {
+                1 => folder.join(name),
+                n => folder.join(format!("{} ({}){}", stem, n, ext)),
+            })
+            .find(|path| {
+                let key = PathBuf::from(path.to_string_lossy().to_lowercase());
+                !path.exists() && self.taken.insert(key)
+            })
+            .expect("some name is free")
+    }
+}
+
+/// File name for an artifact: its sanitized title, with an extension
+/// matching its content type if the title has none
+fn file_name(artifact: &Artifact) -> String {
+    let name = sanitize(&artifact.title, &artifact.id);
+    let has_ext = name
+        .rsplit_once('.')
+        .is_some_and(|(stem, ext)| !stem.is_empty() && !ext.contains(' '));
+    match artifact.content_type.as_deref().and_then(extension) {
+        Some(ext) if !has_ext => format!("{}.{}", name, ext),
+        _ => name,
+    }
+}
+
+/// `name` made safe as a single path component on Windows, macOS and Linux
+fn sanitize(name: &str, fallback: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(MAX_NAME_LEN)
+        .collect();
+    // Windows drops trailing dots and spaces; leading dots hide files
+    let cleaned = cleaned
+        .trim_end_matches(['.', ' '])
+        .trim_start_matches(['.', ' ']);
+    if cleaned.is_empty() {
+        return sanitize_fallback(fallback);
+    }
+    let stem = cleaned.split('.').next().unwrap_or_default();
+    let reserved = matches!(
+        stem.to_ascii_uppercase().as_str(),
+        "CON" | "PRN" | "AUX" | "NUL"
+    ) || (stem.len() == 4
+        && ["COM", "LPT"]
+            .iter()
+            .any(|prefix| stem.to_ascii_uppercase().starts_with(prefix))
+        && stem.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        format!("_{}", cleaned)
+    } else {
+        cleaned.to_string()
+    }
+}
+
+fn sanitize_fallback(id: &str) -> String {
+    let id: String = id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .take(MAX_NAME_LEN)
+        .collect();
+    if id.is_empty() {
+        "untitled".into()
+    } else {
+        id
+    }
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(SIDECAR_SUFFIX);
+    path.with_file_name(name)
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Files to import under `dir`, skipping sidecars and hidden entries
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name.ends_with(SIDECAR_SUFFIX) {
+            continue;
+        }
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            walk(&entry.path(), files)?;
+        } else if kind.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Collection at folder path `names`, creating missing ones
+fn resolve_collection(
+    collections: &mut CollectionStore,
+    names: &[String],
+) -> anyhow::Result<String> {
+    let mut parent: Option<String> = None;
+    for name in names {
+        let existing = collections
+            .children(parent.as_deref())
+            .into_iter()
+            .find(|c| sanitize(&c.name, &c.id) == *name);
+        let collection = match existing {
+            Some(collection) => collection,
+            None => {
+                let position = collections.children(parent.as_deref()).len() as i64;
+                collections.create(name.clone(), parent.as_deref(), position)?
+            }
+        };
+        parent = Some(collection.id);
+    }
+    parent.ok_or_else(|| anyhow!("Empty collection path"))
+}
+
+fn extension(content_type: &str) -> Option<&'static str> {
+    Some(match content_type {
+        "text/markdown" => "md",
+        "text/plain" => "txt",
+        "text/html" => "html",
+        "application/json" => "json",
+        "application/pdf" => "pdf",
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        _ => return None,
+    })
+}
+
+fn content_type(ext: &str) -> Option<String> {
+    let content_type = match ext.to_ascii_lowercase().as_str() {
+        "md" | "markdown" => "text/markdown",
+        "txt" => "text/plain",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => return None,
+    };
+    Some(content_type.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+
+    fn put(store: &InMemoryStore, artifact: Artifact, text: &str) {
+        let hash = content_hash(text.as_bytes());
+        store.put_content(&hash, text.as_bytes()).unwrap();
+        store
+            .store(&Artifact {
+                content_hash: hash,
+                ..artifact
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("a/b: c?", "id"), "a_b_ c_");
+        assert_eq!(sanitize("CON", "id"), "_CON");
+        assert_eq!(sanitize("com1.txt", "id"), "_com1.txt");
+        assert_eq!(sanitize("Compiler", "id"), "Compiler");
+        assert_eq!(sanitize(" ..", "a/1"), "a1");
+        assert_eq!(sanitize("x".repeat(500).as_str(), "id").len(), MAX_NAME_LEN);
+    }
+
+    #[test]
+    fn test_export_and_reimport_preserve_ids() {
+        let source = InMemoryStore::new();
+        let mut collections = CollectionStore::new("laptop");
+        let work = collections.create("Work", None, 0).unwrap();
+        let plans = collections.create("Plans", Some(&work.id), 0).unwrap();
+        for (id, title, collection) in [
+            ("a1", "Trip", Some(&plans.id)),
+            ("a2", "Trip", Some(&plans.id)),
+            ("a3", "notes.txt", None),
+        ] {
+            put(
+                &source,
+                Artifact {
+                    id: id.into(),
+                    title: title.into(),
+                    modified_at: 10,
+                    content_type: Some("text/markdown".into()),
+                    collection: collection.cloned(),
+                    ..Default::default()
+                },
+                id,
+            );
+        }
+        source
+            .store(&Artifact {
+                id: "lazy".into(),
+                title: "Not here".into(),
+                content_hash: content_hash(b"elsewhere"),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut updates = Vec::new();
+        let done = export_dir(
+            &source,
+            &source,
+            &collections,
+            &[],
+            dir.path(),
+            &ExportOptions::default(),
+            |progress| updates.push(progress.done),
+        )
+        .unwrap();
+        assert_eq!(updates, [1, 2, 3, 4]);
+        assert_eq!(done.missing, ["lazy"]);
+        let trip = dir.path().join("Work/Plans/Trip.md");
+        assert_eq!(std::fs::read_to_string(&trip).unwrap(), "a1");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("Work/Plans/Trip (2).md")).unwrap(),
+            "a2"
+        );
+        assert!(dir.path().join("notes.txt.nomade.json").is_file());
+
+        // A fresh device rebuilds the same artifacts and collection tree
+        let target = InMemoryStore::new();
+        let mut fresh = CollectionStore::new("phone");
+        std::fs::write(dir.path().join("Work/added.txt"), "by hand").unwrap();
+        let report = import_dir(dir.path(), &target, &target, &mut fresh).unwrap();
+        assert_eq!(report.created.len(), 4);
+        let a1 = target.get("a1").unwrap().unwrap();
+        assert_eq!(a1.modified_at, 10);
+        let folder = fresh.get(a1.collection.as_deref().unwrap()).unwrap();
+        assert_eq!(folder.name, "Plans");
+        let added = report
+            .created
+            .iter()
+            .find(|id| id.starts_with("import-"))
+            .unwrap();
+        let added = target.get(added).unwrap().unwrap();
+        assert_eq!(added.title, "added");
+        assert_eq!(
+            fresh
+                .get(added.collection.as_deref().unwrap())
+                .unwrap()
+                .name,
+            "Work"
+        );
+
+        // Importing again changes nothing; edited files update in place
+        std::fs::write(&trip, "a1 edited").unwrap();
+        let report = import_dir(dir.path(), &target, &target, &mut fresh).unwrap();
+        assert_eq!(report.updated, ["a1"]);
+        assert_eq!(report.skipped.len(), 3);
+        assert_eq!(fresh.list().len(), 2);
+        let a1 = target.get("a1").unwrap().unwrap();
+        assert_eq!(a1.content_hash, content_hash(b"a1 edited"));
+    }
+}
@@ -0,0 +1,484 @@
+//! Artifact store in plain files, with a write-ahead journal
+//!
+//! Each artifact is a JSON file under `artifacts/`, each blob a file under
+//! `content/`, and `index.json` maps artifact IDs to their modification
+//! time. A write touches several of these files, which atomic renames
+//! alone cannot keep consistent across a power loss. Every artifact write
+//! is therefore first appended to `journal.log` as intent records ending
+//! with a commit record, and synced, before any file changes. Once the
+//! files and index are updated the journal is emptied.
+//!
+//! Opening the store replays committed transactions left in the journal,
+//! which is safe to repeat, and drops a trailing transaction without its
+//! commit record. Blobs are content-addressed and need no journal: a blob
+//! is complete once renamed into place.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{Artifact, ArtifactStore, ContentStore};
+
+const ARTIFACTS_DIR: &str = "artifacts";
+const CONTENT_DIR: &str = "content";
+const INDEX_FILE: &str = "index.json";
+const JOURNAL_FILE: &str = "journal.log";
+
+/// Line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    Store { txn: u64, artifact: Artifact },
+    Delete { txn: u64, id: String },
+    Commit { txn: u64 },
+}
+
+/// Change to apply, recorded before it is applied
+#[derive(Debug)]
+enum Intent {
+    Store(Artifact),
+    Delete(String),
+}
+
+struct State {
+    /// Modification time by artifact ID
+    index: BTreeMap<String, u64>,
+    next_txn: u64,
+}
+
+/// Artifact and content store in a directory of plain files
+pub struct FsStore {
+    root: PathBuf,
+    state: Mutex<State>,
+}
+
+impl FsStore {
+    /// Open or create the store at `root`, recovering interrupted writes
+    pub fn open(root: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir_all(root.join(ARTIFACTS_DIR))?;
+        fs::create_dir_all(root.join(CONTENT_DIR))?;
+        let index = match fs::read(root.join(INDEX_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes).ok(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let store = Self {
+            state: Mutex::new(State {
+                index: BTreeMap::new(),
+                next_txn: 0,
+            }),
+            root,
+        };
+        {
+            let mut state = store.state.lock().unwrap();
+            state.index = match index {
+                Some(index) => index,
+                None => store.scan()?,
+            };
+            store.replay(&mut state)?;
+        }
+        Ok(store)
+    }
+
+    /// Redo committed transactions left in the journal
+    fn replay(&self, state: &mut State) -> anyhow::Result<()> {
+        let file = match File::open(self.root.join(JOURNAL_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pending: Vec<Intent> = Vec::new();
+        let mut pending_txn = None;
+        let mut replayed = 0;
+        for line in BufReader::new(file).lines() {
+            // A torn last line is a write cut short before its commit
+            let Ok(record) = serde_json::from_str(&line?) else {
+                break;
+            };
+            let (txn, intent) = match record {
+                Record::Store { txn, artifact } => (txn, Some(Intent::Store(artifact))),
+                Record::Delete { txn, id } => (txn, Some(Intent::Delete(id))),
+                Record::Commit { txn } => (txn, None),
+            };
+            // Intents of a transaction that never committed are dropped
+            if pending_txn != Some(txn) {
+                pending.clear();
+                pending_txn = Some(txn);
+            }
+            match intent {
+                Some(intent) => pending.push(intent),
+                None => {
+                    self.apply(state, &std::mem::take(&mut pending))?;
+                    state.next_txn = txn + 1;
+                    replayed += 1;
+                }
+            }
+        }
+        if replayed > 0 {
+            self.write_index(&state.index)?;
+        }
+        self.truncate_journal()
+    }
+
+    /// Journal `intents`, then apply them
+    fn commit(&self, intents: &[Intent]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let txn = state.next_txn;
+        state.next_txn += 1;
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.root.join(JOURNAL_FILE))?;
+        let mut lines = Vec::new();
+        for intent in intents {
+            let record = match intent {
+                Intent::Store(artifact) => Record::Store {
+                    txn,
+                    artifact: artifact.clone(),
+                },
+                Intent::Delete(id) => Record::Delete {
+                    txn,
+                    id: id.clone(),
+                },
+            };
+            serde_json::to_writer(&mut lines, &record)?;
+            lines.push(b'\n');
+        }
+        journal.write_all(&lines)?;
+        crash_point("journaling");
+        serde_json::to_writer(&mut journal, &Record::Commit { txn })?;
+        journal.write_all(b"\n")?;
+        journal.sync_data()?;
+        crash_point("journaled");
+
+        self.apply(&mut state, intents)?;
+        self.write_index(&state.index)?;
+        crash_point("applied");
+        self.truncate_journal()
+    }
+
+    /// Update artifact files and the in-memory index; safe to repeat
+    fn apply(&self, state: &mut State, intents: &[Intent]) -> anyhow::Result<()> {
+        for intent in intents {
+            match intent {
+                Intent::Store(artifact) => {
+                    write_atomic(
+                        &self.artifact_path(&artifact.id),
+                        &serde_json::to_vec(artifact)?,
+                    )?;
+                    state
+                        .index
+                        .insert(artifact.id.clone(), artifact.modified_at);
+                }
+                Intent::Delete(id) => {
+                    remove_if_exists(&self.artifact_path(id))?;
+                    state.index.remove(id);
+                }
+            }
+            crash_point("applying");
+        }
+        Ok(())
+    }
+
+    fn write_index(&self, index: &BTreeMap<String, u64>) -> anyhow::Result<()> {
+        write_atomic(&self.root.join(INDEX_FILE), &serde_json::to_vec(index)?)
+    }
+
+    fn truncate_journal(&self) -> anyhow::Result<()> {
+        match OpenOptions::new()
+            .write(true)
+            .open(self.root.join(JOURNAL_FILE))
+        {
+            Ok(journal) => {
+                journal.set_len(0)?;
+                journal.sync_data()?;
+                Ok(())
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Rebuild the index from the artifact files
+    fn scan(&self) -> anyhow::Result<BTreeMap<String, u64>> {
+        let mut index = BTreeMap::new();
+        for entry in fs::read_dir(self.root.join(ARTIFACTS_DIR))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let artifact: Artifact = serde_json::from_slice(&fs::read(&path)?)?;
+                index.insert(artifact.id, artifact.modified_at);
+            }
+        }
+        Ok(index)
+    }
+
+    /// File of artifact `id`, named by its hex encoding to allow any ID
+    fn artifact_path(&self, id: &str) -> PathBuf {
+        let name: String = id.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.root.join(ARTIFACTS_DIR).join(format!("{}.json", name))
+    }
+
+    /// File of blob `hash`, which may carry a namespace (`chunk:<hash>`)
+    ///
+    /// Colons are not allowed in Windows file names, so the namespace is
+    /// separated by a dash instead, which bare hashes never contain.
+    fn content_path(&self, hash: &str) -> anyhow::Result<PathBuf> {
+        let valid = hash
+            .split(':')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric()));
+        if !valid {
+            bail!("Invalid content hash {:?}", hash);
+        }
+        Ok(self.root.join(CONTENT_DIR).join(hash.replace(':', "-")))
+    }
+}
+
+/// Write `data` to `path` through a synced temporary file and a rename
+///
+/// The temporary file has a unique name, so concurrent writers of the same
+/// path never share it, and the directory is synced after the rename.
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(data)?;
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+    // Directories cannot be opened as files on Windows
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> anyhow::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Abort at `stage` when a test asks to, as a power loss would
+#[cfg(test)]
+fn crash_point(stage: &str) {
+    if std::env::var("NOMADE_FS_CRASH").as_deref() == Ok(stage) {
+        std::process::abort();
+    }
+}
+
+#[cfg(not(test))]
+fn crash_point(_stage: &str) {}
+
+impl ArtifactStore for FsStore {
+    fn store(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        self.commit(&[Intent::Store(artifact.clone())])
+    }
+
+    fn get(&self, id: &str) -> anyhow::Result<Option<Artifact>> {
+        match fs::read(self.artifact_path(id)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<Artifact>> {
+        let ids: Vec<String> = self.state.lock().unwrap().index.keys().cloned().collect();
+        let mut artifacts = Vec::with_capacity(ids.len());
+        for id in ids {
+            // Deleted since the index was read
+            if let Some(artifact) = self.get(&id)? {
+                artifacts.push(artifact);
+            }
+        }
+        Ok(artifacts)
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.commit(&[Intent::Delete(id.to_string())])
+    }
+
+    fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        let intents: Vec<_> = artifacts.iter().cloned().map(Intent::Store).collect();
+        self.commit(&intents)?;
+        progress(artifacts.len());
+        Ok(())
+    }
+
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        let intents: Vec<_> = ids.iter().cloned().map(Intent::Delete).collect();
+        self.commit(&intents)?;
+        progress(ids.len());
+        Ok(())
+    }
+
+    fn disk_usage(&self) -> anyhow::Result<u64> {
+        let mut total = 0;
+        for dir in [ARTIFACTS_DIR, CONTENT_DIR] {
+            for entry in fs::read_dir(self.root.join(dir))? {
+                total += entry?.metadata()?.len();
+            }
+        }
+        Ok(total)
+    }
+}
+
+impl ContentStore for FsStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        write_atomic(&self.content_path(hash)?, data)
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match fs::read(self.content_path(hash)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn has_content(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self.content_path(hash)?.exists())
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        remove_if_exists(&self.content_path(hash)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    fn note(id: &str, title: &str) -> Artifact {
+        Artifact {
+            id: id.into(),
+            title: title.into(),
+            modified_at: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Batch the crashing child writes over the parent's `a`, `b` and `c`
+    fn batch() -> Vec<Intent> {
+        vec![
+            Intent::Store(note("a", "edited")),
+            Intent::Store(note("d/new", "added")),
+            Intent::Delete("b".into()),
+        ]
+    }
+
+    #[test]
+    fn test_recovers_from_crash_mid_write() {
+        // Child: write the batch and die at the requested point
+        if let Ok(dir) = std::env::var("NOMADE_FS_DIR") {
+            FsStore::open(dir).unwrap().commit(&batch()).unwrap();
+            unreachable!("Expected to crash");
+        }
+
+        for (stage, committed) in [
+            ("journaling", false),
+            ("journaled", true),
+            ("applying", true),
+            ("applied", true),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let store = FsStore::open(dir.path()).unwrap();
+            for id in ["a", "b", "c"] {
+                store.store(&note(id, "original")).unwrap();
+            }
+            drop(store);
+
+            let status = Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "fs_store::tests::test_recovers_from_crash_mid_write",
+                ])
+                .env("NOMADE_FS_DIR", dir.path())
+                .env("NOMADE_FS_CRASH", stage)
+                .output()
+                .unwrap()
+                .status;
+            assert!(!status.success(), "{} did not crash", stage);
+
+            let store = FsStore::open(dir.path()).unwrap();
+            let mut titles: Vec<_> = store
+                .list()
+                .unwrap()
+                .into_iter()
+                .map(|a| format!("{}={}", a.id, a.title))
+                .collect();
+            titles.sort();
+            let expected = match committed {
+                true => ["a=edited", "c=original", "d/new=added"].as_slice(),
+                false => ["a=original", "b=original", "c=original"].as_slice(),
+            };
+            assert_eq!(titles, expected, "after crash while {}", stage);
+            // The index matches the files and the journal is spent
+            assert_eq!(store.state.lock().unwrap().index, store.scan().unwrap());
+            assert_eq!(
+                fs::metadata(dir.path().join(JOURNAL_FILE)).unwrap().len(),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn test_fs_store_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore::open(dir.path()).unwrap();
+        store.store(&note("a", "Note")).unwrap();
+        store.put_content("abc123", b"body").unwrap();
+        assert!(store.put_content("../escape", b"x").is_err());
+        drop(store);
+
+        // A lost index is rebuilt from the files
+        fs::remove_file(dir.path().join(INDEX_FILE)).unwrap();
+        let store = FsStore::open(dir.path()).unwrap();
+        assert_eq!(store.get("a").unwrap().unwrap().title, "Note");
+        assert_eq!(store.get_content("abc123").unwrap().unwrap(), b"body");
+        store.delete("a").unwrap();
+        store.delete_content("abc123").unwrap();
+        assert!(store.list().unwrap().is_empty());
+        assert!(!store.has_content("abc123").unwrap());
+
+        // Namespaced keys of the chunk index and wrapped keys
+        store.put_content("chunk:abc123", b"chunk").unwrap();
+        assert_eq!(
+            store.get_content("chunk:abc123").unwrap().unwrap(),
+            b"chunk"
+        );
+        assert!(!store.has_content("abc123").unwrap());
+        for hash in ["", "chunk:", ":abc", "../abc", "a-b"] {
+            assert!(store.put_content(hash, b"x").is_err());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_blob_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore::open(dir.path()).unwrap();
+        let body = vec![7u8; 64 * 1024];
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| store.put_content("abc123", &body).unwrap());
+            }
+        });
+        assert_eq!(store.get_content("abc123").unwrap().unwrap(), body);
+        // No temporary file is left next to the blob
+        let names: Vec<_> = fs::read_dir(dir.path().join(CONTENT_DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["abc123"]);
+    }
+}
@@ -0,0 +1,86 @@
+//! Reclaiming unreferenced content
+//!
+//! Content outlives its artifact when the artifact is deleted or points at
+//! new content after an edit. `collect_garbage` deletes every blob no
+//! artifact refers to anymore, except those the caller still needs (such
+//! as versions kept for merging); shared chunks stay until their last blob
+//! goes.
+
+use std::collections::HashSet;
+
+use crate::{ArtifactStore, ContentStore, DedupStore};
+
+/// Outcome of a garbage collection
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Hashes of the deleted blobs
+    pub removed: Vec<String>,
+    /// Bytes no longer stored once shared chunks are accounted for
+    pub freed_bytes: u64,
+}
+
+/// Delete content neither an artifact nor `keep` refers to
+///
+/// Run while nothing else writes to the stores: content synced ahead of
+/// its artifact would be collected.
+pub fn collect_garbage(
+    artifacts: &dyn ArtifactStore,
+    content: &DedupStore,
+    keep: &[String],
+) -> anyhow::Result<GcReport> {
+    let referenced: HashSet<String> = artifacts
+        .list()?
+        .into_iter()
+        .map(|artifact| artifact.content_hash)
+        .chain(keep.iter().cloned())
+        .collect();
+    let before = content.stats().stored_bytes;
+    let mut removed: Vec<String> = content
+        .blobs()
+        .into_iter()
+        .filter(|hash| !referenced.contains(hash))
+        .collect();
+    removed.sort();
+    for hash in &removed {
+        content.delete_content(hash)?;
+    }
+    Ok(GcReport {
+        removed,
+        freed_bytes: before - content.stats().stored_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content_hash, Artifact, InMemoryStore};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_collects_only_unreferenced_content() {
+        let artifacts = InMemoryStore::new();
+        let content = DedupStore::new(Arc::new(InMemoryStore::new()));
+        let kept = vec![1u8; 100];
+        let orphan = vec![2u8; 100];
+        for data in [&kept, &orphan] {
+            content.put_content(&content_hash(data), data).unwrap();
+        }
+        artifacts
+            .store(&Artifact {
+                id: "note".into(),
+                content_hash: content_hash(&kept),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let report = collect_garbage(&artifacts, &content, &[]).unwrap();
+        assert_eq!(report.removed, [content_hash(&orphan)]);
+        assert_eq!(report.freed_bytes, 100);
+        assert!(content.has_content(&content_hash(&kept)).unwrap());
+        assert!(!content.has_content(&content_hash(&orphan)).unwrap());
+        assert_eq!(
+            collect_garbage(&artifacts, &content, &[]).unwrap(),
+            GcReport::default()
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Store statistics and health checks
+//!
+//! `store_health` gathers what a storage settings screen shows: how much is
+//! stored and saved by deduplication, content no artifact refers to (what
+//! garbage collection would free) and whether the chunk index matches the
+//! stored chunks. `Maintenance` remembers when content was last scrubbed
+//! and collected.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use nomade_crypto::fs::write_durable;
+use serde::{Deserialize, Serialize};
+
+use crate::{ArtifactStore, DedupStore, IndexCheck};
+
+/// One backend behind the stores
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendHealth {
+    /// What the backend holds (`artifacts`, `chunk_index`, ...)
+    pub name: String,
+    /// Kind of backend (`memory`, `sled`, ...)
+    pub kind: String,
+    /// Bytes used on disk, or 0 for volatile backends
+    pub disk_bytes: u64,
+}
+
+/// Statistics and consistency of the stores
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoreHealth {
+    pub artifacts: usize,
+    /// Artifacts whose content is not on this device (evicted, not yet
+    /// fetched or lost)
+    pub artifacts_without_content: usize,
+    /// Total size of all stored content
+    pub logical_bytes: u64,
+    /// Size of the distinct chunks actually stored
+    pub stored_bytes: u64,
+    /// `logical_bytes` over `stored_bytes`; 1.0 for an empty store
+    pub dedup_ratio: f64,
+    /// Stored content no artifact refers to
+    pub orphaned_blobs: usize,
+    /// Bytes garbage collection would free
+    pub orphaned_bytes: u64,
+    pub index: IndexCheck,
+    /// Unix time of the last scrub, in seconds
+    pub last_scrub: Option<u64>,
+    /// Unix time of the last garbage collection, in seconds
+    pub last_gc: Option<u64>,
+    pub backends: Vec<BackendHealth>,
+}
+
+impl StoreHealth {
+    /// Whether the checks found nothing needing repair
+    pub fn is_healthy(&self) -> bool {
+        self.index.is_consistent()
+    }
+}
+
+/// Gather statistics and check the chunk index
+///
+/// Content in `keep` counts as referenced. `last_scrub`, `last_gc` and
+/// `backends` are left for the caller to fill in.
+pub fn store_health(
+    artifacts: &dyn ArtifactStore,
+    content: &DedupStore,
+    keep: &[String],
+) -> anyhow::Result<StoreHealth> {
+    let artifacts = artifacts.list()?;
+    let referenced: HashSet<String> = artifacts
+        .iter()
+        .map(|artifact| artifact.content_hash.clone())
+        .chain(keep.iter().cloned())
+        .collect();
+    let stored: HashSet<String> = content.blobs().into_iter().collect();
+    let artifacts_without_content = artifacts
+        .iter()
+        .filter(|artifact| !stored.contains(&artifact.content_hash))
+        .count();
+    let stats = content.stats();
+    let (orphaned_blobs, orphaned_bytes) = content.orphans(&referenced);
+    Ok(StoreHealth {
+        artifacts: artifacts.len(),
+        artifacts_without_content,
+        logical_bytes: stats.logical_bytes,
+        stored_bytes: stats.stored_bytes,
+        dedup_ratio: match stats.stored_bytes {
+            0 => 1.0,
+            stored => stats.logical_bytes as f64 / stored as f64,
+        },
+        orphaned_blobs,
+        orphaned_bytes,
+        index: content.check_index()?,
+        ..Default::default()
+    })
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct MaintenanceState {
+    last_scrub: Option<u64>,
+    last_gc: Option<u64>,
+}
+
+/// When maintenance last ran
+pub struct Maintenance {
+    state: Mutex<MaintenanceState>,
+    path: Option<PathBuf>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Maintenance {
+    /// Create an in-memory record
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(MaintenanceState::default()),
+            path: None,
+        }
+    }
+
+    /// Open a record persisted at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => MaintenanceState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            state: Mutex::new(state),
+            path: Some(path),
+        })
+    }
+
+    /// Record a scrub finished at `at`
+    pub fn scrubbed(&self, at: u64) -> anyhow::Result<()> {
+        self.update(|state| state.last_scrub = Some(at))
+    }
+
+    /// Record a garbage collection finished at `at`
+    pub fn collected(&self, at: u64) -> anyhow::Result<()> {
+        self.update(|state| state.last_gc = Some(at))
+    }
+
+    /// Fill in `health` with the last scrub and collection times
+    pub fn report(&self, health: &mut StoreHealth) {
+        let state = *self.state.lock().unwrap();
+        health.last_scrub = state.last_scrub;
+        health.last_gc = state.last_gc;
+    }
+
+    fn update(&self, change: impl FnOnce(&mut MaintenanceState)) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(&*state)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{content_hash, Artifact, ContentStore, InMemoryStore, HASH_GROUP_SIZE};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reports_dedup_and_orphans() {
+        let artifacts = InMemoryStore::new();
+        let content = DedupStore::new(Arc::new(InMemoryStore::new()));
+        let note = vec![1u8; HASH_GROUP_SIZE];
+        let orphan = vec![2u8; 100];
+        for data in [&note, &orphan] {
+            content.put_content(&content_hash(data), data).unwrap();
+        }
+        for id in ["a", "b"] {
+            artifacts
+                .store(&Artifact {
+                    id: id.into(),
+                    content_hash: content_hash(&note),
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        artifacts
+            .store(&Artifact {
+                id: "lazy".into(),
+                content_hash: content_hash(b"elsewhere"),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let health = store_health(&artifacts, &content, &[]).unwrap();
+        assert_eq!((health.artifacts, health.artifacts_without_content), (3, 1));
+        assert_eq!((health.orphaned_blobs, health.orphaned_bytes), (1, 100));
+        assert!(health.is_healthy());
+        let kept = store_health(&artifacts, &content, &[content_hash(&orphan)]).unwrap();
+        assert_eq!(kept.orphaned_blobs, 0);
+    }
+
+    #[test]
+    fn test_maintenance_times_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("maintenance.json");
+        let maintenance = Maintenance::open(&path).unwrap();
+        maintenance.scrubbed(10).unwrap();
+        maintenance.collected(20).unwrap();
+
+        let mut health = StoreHealth::default();
+        Maintenance::open(&path).unwrap().report(&mut health);
+        assert_eq!((health.last_scrub, health.last_gc), (Some(10), Some(20)));
+    }
+}
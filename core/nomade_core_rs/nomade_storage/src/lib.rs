@@ -1,17 +1,97 @@
 //! Storage layer for Nomade
 //!
-//! Provides artifact store interface
+//! Provides artifact store interface, content-addressed blob storage,
+//! encryption at rest, derived assets, pinned and evictable content, the
+//! replicated collection hierarchy, portable encrypted bundles and plain
+//! directory exports, typed custom fields checked against per-type
+//! schemas, a trash bin for deleted artifacts, and point-in-time backups
+//! of the whole store.
+//! Persistent backends sit behind cargo features and are opened by URL
+//! through `StoreBackends`.
 
+use std::collections::BTreeMap;
+
+use nomade_crypto::{KeyRecipient, KeyShares};
 use serde::{Deserialize, Serialize};
 
+pub mod backend;
+pub mod backup;
+pub mod bulk;
+pub mod bundle;
+pub mod cache;
+pub mod collection;
+pub mod compress;
+pub mod dedup;
+pub mod derived;
+pub mod encrypted;
+pub mod export;
+mod fs_store;
+pub mod gc;
+pub mod health;
+pub mod page;
+pub mod projection;
+pub mod schema;
+pub mod scrub;
+#[cfg(feature = "sled")]
+mod sled_store;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod trash;
+pub mod tree;
+pub mod watch;
+
+pub use backend::{BackendFactory, StoreBackends, Stores};
+pub use backup::{BackupInfo, RestoreReport, StoreBackup, StoreBackups};
+pub use bulk::{BulkProgress, Retag};
+pub use bundle::{export_bundle, import_bundle, BundleKey, BundleSeal, ImportReport};
+pub use cache::{CacheTiers, EvictionReport};
+pub use collection::{Collection, CollectionOp, CollectionOpKind, CollectionStore, Stamp};
+pub use compress::{is_compressible, CompressedStore, Compression};
+pub use dedup::{DedupStats, DedupStore, IndexCheck};
+#[cfg(feature = "thumbnails")]
+pub use derived::ImageThumbnail;
+pub use derived::{default_processors, ArtifactProcessor, DerivedAssets, TextExcerpt};
+pub use encrypted::EncryptedContentStore;
+pub use export::{export_dir, import_dir, ExportOptions, ExportProgress};
+pub use fs_store::FsStore;
+pub use gc::{collect_garbage, GcReport};
+pub use health::{store_health, BackendHealth, Maintenance, StoreHealth};
+pub use page::{Page, PageCursor, SortOrder};
+pub use projection::{ArtifactField, ArtifactSummary, Projection};
+pub use schema::{FieldDef, FieldQuery, FieldType, MetadataSchema, SchemaRegistry, SchemaStore};
+pub use scrub::{scrub, CorruptContent, ScrubReport};
+#[cfg(feature = "sled")]
+pub use sled_store::SledStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::SqliteStore;
+pub use tree::{hash_reader, HashTree, HASH_GROUP_SIZE};
+pub use watch::{StoreChange, StoreChangeKind, WatchedStore};
+
 /// Artifact metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Artifact {
     pub id: String,
     pub title: String,
     pub created_at: u64,
     pub modified_at: u64,
     pub content_hash: String,
+    /// MIME type of the content (e.g. "text/markdown", "image/png")
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Kind of artifact (e.g. "note", "snippet", "image")
+    #[serde(default)]
+    pub artifact_type: Option<String>,
+    /// Collection the artifact belongs to
+    #[serde(default)]
+    pub collection: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Custom fields, typed by the `MetadataSchema` of `artifact_type`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, serde_json::Value>,
+    /// When the artifact was moved to the trash, if it is there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed_at: Option<u64>,
 }
 
 /// Artifact store interface
@@ -27,17 +107,144 @@ pub trait ArtifactStore: Send + Sync {
 
     /// Delete an artifact
     fn delete(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Up to `limit` artifacts after `cursor`, in `sort` order
+    ///
+    /// Pass `Page::next` as the cursor of the following page. The default
+    /// sorts the whole `list()`; stores with indexes page natively.
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+    ) -> anyhow::Result<Page> {
+        page::page_of(self.list()?, cursor, limit, sort)
+    }
+
+    /// Page of `list_page` reduced to the fields of `projection`
+    fn list_projected(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+        projection: &Projection,
+    ) -> anyhow::Result<Page<ArtifactSummary>> {
+        Ok(self
+            .list_page(cursor, limit, sort)?
+            .map(|artifact| projection.apply(artifact)))
+    }
+
+    /// Store artifacts in one transaction, all or none
+    ///
+    /// `progress` receives the number written so far. The default writes
+    /// one by one, for backends without transactions.
+    fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        for (i, artifact) in artifacts.iter().enumerate() {
+            self.store(artifact)?;
+            progress(i + 1);
+        }
+        Ok(())
+    }
+
+    /// Delete artifacts in one transaction, all or none
+    ///
+    /// `progress` receives the number deleted so far.
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        for (i, id) in ids.iter().enumerate() {
+            self.delete(id)?;
+            progress(i + 1);
+        }
+        Ok(())
+    }
+
+    /// Flush pending writes to durable storage
+    fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Bytes used on disk, or 0 for volatile stores
+    fn disk_usage(&self) -> anyhow::Result<u64> {
+        Ok(0)
+    }
+}
+
+/// Content-addressed blob storage for artifact content
+///
+/// Blobs are keyed by their `content_hash()`.
+pub trait ContentStore: Send + Sync {
+    /// Store content under its hash
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Retrieve content by hash
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Whether content with this hash is stored
+    fn has_content(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self.get_content(hash)?.is_some())
+    }
+
+    /// Remove content; removing missing content is not an error
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()>;
+
+    /// Data key of encrypted content, sealed to the devices sharing it
+    ///
+    /// `None` for stores that do not encrypt content.
+    fn key_shares(&self, hash: &str) -> anyhow::Result<Option<KeyShares>> {
+        let _ = hash;
+        Ok(None)
+    }
+
+    /// Keep content stored under `hash` encrypted with the data key in
+    /// `shares`, so paired devices keep it under the same key
+    ///
+    /// Content already stored under another key is re-encrypted.
+    fn adopt_key_shares(&self, hash: &str, shares: &KeyShares) -> anyhow::Result<()> {
+        let _ = (hash, shares);
+        Ok(())
+    }
+
+    /// Re-encrypt stored content under a new data key
+    ///
+    /// Returns `false` if the content is not stored or not encrypted.
+    fn rotate_key(&self, hash: &str) -> anyhow::Result<bool> {
+        let _ = hash;
+        Ok(false)
+    }
+
+    /// Share data keys with exactly `recipients` from now on, resealing
+    /// the keys of the content `artifacts` reference
+    ///
+    /// Returns the number of keys resealed.
+    fn share_keys(
+        &self,
+        recipients: &[KeyRecipient],
+        artifacts: &dyn ArtifactStore,
+    ) -> anyhow::Result<usize> {
+        let _ = (recipients, artifacts);
+        Ok(0)
+    }
+}
+
+/// Hash identifying artifact content (BLAKE3, hex)
+pub fn content_hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
 }
 
 /// Simple in-memory artifact store for testing
 pub struct InMemoryStore {
     artifacts: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, Artifact>>>,
+    content: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, Vec<u8>>>>,
 }
 
 impl InMemoryStore {
     pub fn new() -> Self {
         Self {
             artifacts: std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
+            content: std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
         }
     }
 }
@@ -70,6 +277,46 @@ impl ArtifactStore for InMemoryStore {
         artifacts.remove(id);
         Ok(())
     }
+
+    fn store_many(
+        &self,
+        batch: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        let mut artifacts = self.artifacts.lock().unwrap();
+        for (i, artifact) in batch.iter().enumerate() {
+            artifacts.insert(artifact.id.clone(), artifact.clone());
+            progress(i + 1);
+        }
+        Ok(())
+    }
+
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        let mut artifacts = self.artifacts.lock().unwrap();
+        for (i, id) in ids.iter().enumerate() {
+            artifacts.remove(id);
+            progress(i + 1);
+        }
+        Ok(())
+    }
+}
+
+impl ContentStore for InMemoryStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut content = self.content.lock().unwrap();
+        content.insert(hash.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let content = self.content.lock().unwrap();
+        Ok(content.get(hash).cloned())
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        self.content.lock().unwrap().remove(hash);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -86,6 +333,7 @@ mod tests {
             created_at: 0,
             modified_at: 0,
             content_hash: "hash".into(),
+            ..Default::default()
         };
 
         store.store(&artifact).unwrap();
@@ -0,0 +1,206 @@
+//! Cursor-based paging over artifacts
+//!
+//! A page ends with an opaque cursor naming its last artifact by sort key
+//! and ID, and the next page starts strictly after that position. Artifacts
+//! written or deleted between two reads therefore never shift the others:
+//! nothing is repeated or skipped, and new artifacts show up only if they
+//! sort after the cursor.
+
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::Artifact;
+
+/// Order of a paged listing; ties are broken by ID
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// By ID
+    #[default]
+    Id,
+    /// Most recently modified first
+    Modified,
+    /// By title, byte-wise
+    Title,
+}
+
+impl SortOrder {
+    /// Compare two artifacts in this order
+    pub fn compare(self, a: &Artifact, b: &Artifact) -> Ordering {
+        let key = match self {
+            Self::Id => Ordering::Equal,
+            Self::Modified => b.modified_at.cmp(&a.modified_at),
+            Self::Title => a.title.cmp(&b.title),
+        };
+        key.then_with(|| a.id.cmp(&b.id))
+    }
+}
+
+/// One page of artifacts, or of their summaries
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T = Artifact> {
+    pub artifacts: Vec<T>,
+    /// Cursor of the following page; `None` on the last one
+    pub next: Option<String>,
+    /// Artifacts in the store when the page was read, an estimate once
+    /// the store changes
+    pub total: u64,
+}
+
+impl<T> Page<T> {
+    /// Convert the artifacts of the page
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            artifacts: self.artifacts.into_iter().map(f).collect(),
+            next: self.next,
+            total: self.total,
+        }
+    }
+}
+
+/// Position after the last artifact of a page
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCursor {
+    pub sort: SortOrder,
+    pub id: String,
+    pub modified_at: u64,
+    pub title: String,
+}
+
+impl PageCursor {
+    /// Cursor after `artifact`
+    pub fn after(sort: SortOrder, artifact: &Artifact) -> Self {
+        Self {
+            sort,
+            id: artifact.id.clone(),
+            modified_at: artifact.modified_at,
+            title: artifact.title.clone(),
+        }
+    }
+
+    /// Opaque form handed to callers
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes");
+        json.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Parse a cursor for a listing in `sort` order
+    pub fn decode(cursor: &str, sort: SortOrder) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("Invalid page cursor");
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let decoded: Self = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+        if decoded.sort != sort {
+            bail!(
+                "Page cursor is for {:?} order, not {:?}",
+                decoded.sort,
+                sort
+            );
+        }
+        Ok(decoded)
+    }
+
+    /// Whether `artifact` sorts after this position
+    pub fn precedes(&self, artifact: &Artifact) -> bool {
+        let last = Artifact {
+            id: self.id.clone(),
+            modified_at: self.modified_at,
+            title: self.title.clone(),
+            ..Default::default()
+        };
+        self.sort.compare(&last, artifact) == Ordering::Less
+    }
+}
+
+/// Page of `artifacts`, for stores that cannot page natively
+pub fn page_of(
+    mut artifacts: Vec<Artifact>,
+    cursor: Option<&str>,
+    limit: usize,
+    sort: SortOrder,
+) -> anyhow::Result<Page> {
+    let total = artifacts.len() as u64;
+    if let Some(cursor) = cursor {
+        let cursor = PageCursor::decode(cursor, sort)?;
+        artifacts.retain(|artifact| cursor.precedes(artifact));
+    }
+    artifacts.sort_by(|a, b| sort.compare(a, b));
+    Ok(finish(artifacts, limit, sort, total))
+}
+
+/// Page from the sorted artifacts following a cursor
+///
+/// Given more than `limit` artifacts, the page gets a next cursor.
+pub(crate) fn finish(
+    mut artifacts: Vec<Artifact>,
+    limit: usize,
+    sort: SortOrder,
+    total: u64,
+) -> Page {
+    let limit = limit.max(1);
+    let more = artifacts.len() > limit;
+    artifacts.truncate(limit);
+    let next = more
+        .then(|| artifacts.last())
+        .flatten()
+        .map(|last| PageCursor::after(sort, last).encode());
+    Page {
+        artifacts,
+        next,
+        total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArtifactStore, InMemoryStore};
+
+    fn note(id: &str, modified_at: u64) -> Artifact {
+        Artifact {
+            id: id.into(),
+            title: format!("Note {}", id),
+            modified_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pages_stable_across_writes() {
+        let store = InMemoryStore::new();
+        for (i, id) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            store.store(&note(id, i as u64)).unwrap();
+        }
+        let ids =
+            |page: &Page| -> Vec<String> { page.artifacts.iter().map(|a| a.id.clone()).collect() };
+
+        let first = store.list_page(None, 2, SortOrder::Modified).unwrap();
+        assert_eq!(ids(&first), ["e", "d"]);
+        assert_eq!(first.total, 5);
+        // Changes before the cursor do not shift the next page
+        store.delete("e").unwrap();
+        store.store(&note("f", 9)).unwrap();
+        let cursor = first.next.as_deref();
+        let second = store.list_page(cursor, 2, SortOrder::Modified).unwrap();
+        assert_eq!(ids(&second), ["c", "b"]);
+        let last = store
+            .list_page(second.next.as_deref(), 2, SortOrder::Modified)
+            .unwrap();
+        assert_eq!(ids(&last), ["a"]);
+        assert!(last.next.is_none());
+
+        assert!(store.list_page(cursor, 2, SortOrder::Id).is_err());
+        assert!(store.list_page(Some("zz"), 2, SortOrder::Id).is_err());
+        let by_id = store.list_page(None, 10, SortOrder::Id).unwrap();
+        assert_eq!(ids(&by_id), ["a", "b", "c", "d", "f"]);
+        assert!(by_id.next.is_none());
+    }
+}
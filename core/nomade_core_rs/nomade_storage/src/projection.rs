@@ -0,0 +1,148 @@
+//! Partial artifacts for list views
+//!
+//! A list screen needs a few fields per artifact, not tags and custom
+//! field maps. A `Projection` names the fields to keep and turns artifacts
+//! into `ArtifactSummary`s that serialize only those, which cuts the JSON
+//! crossing FFI for large stores. Stores that keep the requested fields
+//! in columns (SQLite: ID, title, type, modification time) skip decoding
+//! the full artifacts.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Artifact;
+
+/// Standard artifact field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactField {
+    Title,
+    CreatedAt,
+    ModifiedAt,
+    ContentHash,
+    ContentType,
+    ArtifactType,
+    Collection,
+    Tags,
+    TrashedAt,
+}
+
+/// Fields to keep; the ID is always kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Projection {
+    pub fields: BTreeSet<ArtifactField>,
+    /// Custom fields to keep, by name
+    pub custom: BTreeSet<String>,
+}
+
+impl Default for Projection {
+    /// What a list row shows: title and modification time
+    fn default() -> Self {
+        Self {
+            fields: [ArtifactField::Title, ArtifactField::ModifiedAt].into(),
+            custom: BTreeSet::new(),
+        }
+    }
+}
+
+impl Projection {
+    /// Whether only `available` standard fields are needed
+    pub fn within(&self, available: &[ArtifactField]) -> bool {
+        self.custom.is_empty() && self.fields.iter().all(|field| available.contains(field))
+    }
+
+    /// Keep the projected fields of `artifact`
+    pub fn apply(&self, artifact: Artifact) -> ArtifactSummary {
+        let has = |field| self.fields.contains(&field);
+        let mut custom = artifact.fields;
+        custom.retain(|name, _| self.custom.contains(name));
+        ArtifactSummary {
+            title: has(ArtifactField::Title).then_some(artifact.title),
+            created_at: has(ArtifactField::CreatedAt).then_some(artifact.created_at),
+            modified_at: has(ArtifactField::ModifiedAt).then_some(artifact.modified_at),
+            content_hash: has(ArtifactField::ContentHash).then_some(artifact.content_hash),
+            content_type: artifact
+                .content_type
+                .filter(|_| has(ArtifactField::ContentType)),
+            artifact_type: artifact
+                .artifact_type
+                .filter(|_| has(ArtifactField::ArtifactType)),
+            collection: artifact
+                .collection
+                .filter(|_| has(ArtifactField::Collection)),
+            tags: has(ArtifactField::Tags).then_some(artifact.tags),
+            trashed_at: artifact
+                .trashed_at
+                .filter(|_| has(ArtifactField::TrashedAt)),
+            fields: custom,
+            id: artifact.id,
+        }
+    }
+}
+
+/// Artifact reduced to the fields of a `Projection`
+///
+/// Fields left out are absent from the JSON, not null.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactSummary {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_projection_keeps_requested_fields() {
+        let artifact = Artifact {
+            id: "photo-1".into(),
+            title: "Beach".into(),
+            modified_at: 7,
+            artifact_type: Some("photo".into()),
+            tags: vec!["summer".into()],
+            fields: [
+                ("camera".to_string(), "X100".into()),
+                ("iso".to_string(), 200.into()),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let row = Projection::default().apply(artifact.clone());
+        assert_eq!(
+            serde_json::to_string(&row).unwrap(),
+            r#"{"id":"photo-1","title":"Beach","modified_at":7}"#
+        );
+        assert!(Projection::default().within(&[ArtifactField::Title, ArtifactField::ModifiedAt]));
+
+        let projection: Projection =
+            serde_json::from_str(r#"{"fields": ["tags", "collection"], "custom": ["camera"]}"#)
+                .unwrap();
+        let summary = projection.apply(artifact);
+        assert_eq!(summary.tags.unwrap(), ["summer"]);
+        assert!(summary.title.is_none() && summary.collection.is_none());
+        assert_eq!(summary.fields.keys().collect::<Vec<_>>(), ["camera"]);
+        assert!(!projection.within(&[ArtifactField::Tags, ArtifactField::Collection]));
+    }
+}
@@ -0,0 +1,420 @@
+//! Typed custom fields per artifact type
+//!
+//! Artifacts carry free-form `fields`; a `MetadataSchema` registered for
+//! an `artifact_type` ("note", "photo", "track", ...) declares which
+//! fields exist, their type, whether they are required and whether the
+//! SQLite backend indexes them for `FieldQuery`. Writes through a
+//! `SchemaStore` are checked against the schema of their type; types
+//! without a schema accept any fields.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, bail};
+use nomade_crypto::fs::write_durable;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Artifact, ArtifactStore, ArtifactSummary, Page, Projection, SortOrder};
+
+/// Type of a custom field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Integer,
+    Number,
+    Boolean,
+    /// Seconds since the Unix epoch
+    Timestamp,
+}
+
+impl FieldType {
+    /// Whether `value` has this type
+    pub fn accepts(self, value: &Value) -> bool {
+        match self {
+            Self::Text => value.is_string(),
+            Self::Integer => value.is_i64(),
+            Self::Number => value.is_number(),
+            Self::Boolean => value.is_boolean(),
+            Self::Timestamp => value.is_u64(),
+        }
+    }
+}
+
+/// Declared custom field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldDef {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    /// Kept in the SQLite field index for queries
+    #[serde(default)]
+    pub indexed: bool,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Custom fields of one artifact type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataSchema {
+    pub artifact_type: String,
+    pub fields: Vec<FieldDef>,
+}
+
+impl MetadataSchema {
+    /// Declared field `name`
+    pub fn field(&self, name: &str) -> Option<&FieldDef> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Check an artifact of this type
+    pub fn validate(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        for field in &self.fields {
+            match artifact.fields.get(&field.name) {
+                Some(value) if !field.field_type.accepts(value) => bail!(
+                    "Field {:?} of {} must be {:?}, got {}",
+                    field.name,
+                    artifact.id,
+                    field.field_type,
+                    value
+                ),
+                None if field.required => {
+                    bail!("Field {:?} of {} is required", field.name, artifact.id)
+                }
+                _ => {}
+            }
+        }
+        if let Some(name) = artifact
+            .fields
+            .keys()
+            .find(|name| self.field(name).is_none())
+        {
+            bail!(
+                "Field {:?} is not declared for {:?} artifacts",
+                name,
+                self.artifact_type
+            );
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if self.artifact_type.is_empty() {
+            bail!("Schema needs an artifact_type");
+        }
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            if field.name.is_empty() || !names.insert(field.name.as_str()) {
+                bail!("Invalid or duplicate field name {:?}", field.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Artifacts of a type whose field matches
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldQuery {
+    pub artifact_type: String,
+    pub field: String,
+    /// Exact value
+    #[serde(default)]
+    pub equals: Option<Value>,
+    /// Inclusive lower bound, for numeric fields
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// Inclusive upper bound, for numeric fields
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl FieldQuery {
+    /// Whether `artifact` matches, for stores without a field index
+    pub fn matches(&self, artifact: &Artifact) -> bool {
+        if artifact.artifact_type.as_deref() != Some(self.artifact_type.as_str()) {
+            return false;
+        }
+        let Some(value) = artifact.fields.get(&self.field) else {
+            return false;
+        };
+        if self
+            .equals
+            .as_ref()
+            .is_some_and(|equals| !same(equals, value))
+        {
+            return false;
+        }
+        if self.min.is_some() || self.max.is_some() {
+            let Some(number) = index_number(value) else {
+                return false;
+            };
+            return self.min.is_none_or(|min| number >= min)
+                && self.max.is_none_or(|max| number <= max);
+        }
+        true
+    }
+}
+
+/// Numeric column of an indexed value; booleans index as 0 and 1
+pub(crate) fn index_number(value: &Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_bool().map(|b| b as u8 as f64))
+}
+
+fn same(a: &Value, b: &Value) -> bool {
+    match (index_number(a), index_number(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Schemas by artifact type
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    schemas: RwLock<BTreeMap<String, MetadataSchema>>,
+    path: Option<PathBuf>,
+}
+
+impl SchemaRegistry {
+    /// Create an in-memory registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a registry persisted at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let schemas: Vec<MetadataSchema> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            schemas: RwLock::new(
+                schemas
+                    .into_iter()
+                    .map(|schema| (schema.artifact_type.clone(), schema))
+                    .collect(),
+            ),
+            path: Some(path),
+        })
+    }
+
+    /// Add or replace the schema of its artifact type
+    ///
+    /// Existing artifacts are not rechecked; they are on their next write.
+    pub fn register(&self, schema: MetadataSchema) -> anyhow::Result<()> {
+        schema.check()?;
+        let mut schemas = self.schemas.write().unwrap();
+        let mut next = schemas.clone();
+        next.insert(schema.artifact_type.clone(), schema);
+        if let Some(path) = &self.path {
+            write_durable(
+                path,
+                &serde_json::to_vec(&next.values().collect::<Vec<_>>())?,
+            )?;
+        }
+        *schemas = next;
+        Ok(())
+    }
+
+    /// Schema of `artifact_type`
+    pub fn get(&self, artifact_type: &str) -> Option<MetadataSchema> {
+        self.schemas.read().unwrap().get(artifact_type).cloned()
+    }
+
+    /// All schemas, by artifact type
+    pub fn list(&self) -> Vec<MetadataSchema> {
+        self.schemas.read().unwrap().values().cloned().collect()
+    }
+
+    /// Check `artifact` against the schema of its type, if any
+    pub fn validate(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        let schemas = self.schemas.read().unwrap();
+        match artifact.artifact_type.as_ref().and_then(|t| schemas.get(t)) {
+            Some(schema) => schema.validate(artifact),
+            None => Ok(()),
+        }
+    }
+
+    /// Values of the indexed fields of `artifact`
+    pub fn indexed_fields<'a>(&self, artifact: &'a Artifact) -> Vec<(&'a str, &'a Value)> {
+        let schemas = self.schemas.read().unwrap();
+        let Some(schema) = artifact.artifact_type.as_ref().and_then(|t| schemas.get(t)) else {
+            return Vec::new();
+        };
+        artifact
+            .fields
+            .iter()
+            .filter(|(name, _)| schema.field(name).is_some_and(|field| field.indexed))
+            .map(|(name, value)| (name.as_str(), value))
+            .collect()
+    }
+
+    /// Fail unless `query` names an indexed field
+    pub fn check_query(&self, query: &FieldQuery) -> anyhow::Result<()> {
+        let field = self
+            .get(&query.artifact_type)
+            .and_then(|schema| schema.field(&query.field).cloned())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No field {:?} declared for {:?} artifacts",
+                    query.field,
+                    query.artifact_type
+                )
+            })?;
+        if !field.indexed {
+            bail!("Field {:?} is not indexed", query.field);
+        }
+        Ok(())
+    }
+}
+
+/// Artifact store checking writes against a `SchemaRegistry`
+pub struct SchemaStore {
+    inner: Arc<dyn ArtifactStore>,
+    schemas: Arc<SchemaRegistry>,
+}
+
+impl SchemaStore {
+    /// Check writes to `inner` against `schemas`
+    pub fn new(inner: Arc<dyn ArtifactStore>, schemas: Arc<SchemaRegistry>) -> Self {
+        Self { inner, schemas }
+    }
+}
+
+impl ArtifactStore for SchemaStore {
+    fn store(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        self.schemas.validate(artifact)?;
+        self.inner.store(artifact)
+    }
+
+    fn get(&self, id: &str) -> anyhow::Result<Option<Artifact>> {
+        self.inner.get(id)
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<Artifact>> {
+        self.inner.list()
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+    ) -> anyhow::Result<Page> {
+        self.inner.list_page(cursor, limit, sort)
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.inner.delete(id)
+    }
+
+    fn list_projected(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+        projection: &Projection,
+    ) -> anyhow::Result<Page<ArtifactSummary>> {
+        self.inner.list_projected(cursor, limit, sort, projection)
+    }
+
+    fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        for artifact in artifacts {
+            self.schemas.validate(artifact)?;
+        }
+        self.inner.store_many(artifacts, progress)
+    }
+
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        self.inner.delete_many(ids, progress)
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        self.inner.flush()
+    }
+
+    fn disk_usage(&self) -> anyhow::Result<u64> {
+        self.inner.disk_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+
+    fn track(fields: Value) -> Artifact {
+        Artifact {
+            id: "track-1".into(),
+            artifact_type: Some("track".into()),
+            fields: serde_json::from_value(fields).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_writes_checked_against_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schemas.json");
+        let schemas = Arc::new(SchemaRegistry::open(&path).unwrap());
+        let schema: MetadataSchema = serde_json::from_str(
+            r#"{"artifact_type": "track", "fields": [
+                {"name": "artist", "type": "text", "indexed": true, "required": true},
+                {"name": "bpm", "type": "integer", "indexed": true},
+                {"name": "explicit", "type": "boolean"}
+            ]}"#,
+        )
+        .unwrap();
+        schemas.register(schema.clone()).unwrap();
+        assert!(schemas
+            .register(MetadataSchema {
+                artifact_type: "photo".into(),
+                fields: vec![schema.fields[0].clone(), schema.fields[0].clone()],
+            })
+            .is_err());
+        assert_eq!(SchemaRegistry::open(&path).unwrap().list(), [schema]);
+
+        let store = SchemaStore::new(Arc::new(InMemoryStore::new()), schemas.clone());
+        store
+            .store(&track(serde_json::json!({"artist": "Nina", "bpm": 92})))
+            .unwrap();
+        for bad in [
+            serde_json::json!({"bpm": 92}),
+            serde_json::json!({"artist": "Nina", "bpm": "fast"}),
+            serde_json::json!({"artist": "Nina", "mood": "calm"}),
+        ] {
+            assert!(store.store(&track(bad)).is_err());
+        }
+        // Types without a schema take any fields
+        let mut note = track(serde_json::json!({"mood": "calm"}));
+        note.id = "note-1".into();
+        note.artifact_type = Some("note".into());
+        store.store(&note).unwrap();
+
+        let query = FieldQuery {
+            artifact_type: "track".into(),
+            field: "bpm".into(),
+            equals: None,
+            min: Some(90.0),
+            max: Some(100.0),
+        };
+        schemas.check_query(&query).unwrap();
+        assert!(query.matches(&store.get("track-1").unwrap().unwrap()));
+        assert!(!query.matches(&note));
+        assert!(schemas
+            .check_query(&FieldQuery {
+                field: "explicit".into(),
+                ..query
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Detecting corrupted content
+//!
+//! Content is addressed by its hash, so a blob is intact exactly when it
+//! still hashes to its key. `scrub` re-reads the content of every
+//! artifact and checks that. Damaged blobs (bit rot, torn writes, or
+//! encrypted blobs failing authentication) are moved out of the store
+//! into a quarantine directory, so the content reads as missing and can be
+//! fetched again from a peer.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::{content_hash, ArtifactStore, ContentStore};
+
+/// Content found damaged or missing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptContent {
+    pub content_hash: String,
+    /// Artifacts referring to the content
+    pub artifact_ids: Vec<String>,
+    /// `false` if the content was missing rather than damaged
+    pub quarantined: bool,
+}
+
+/// Outcome of a scrub
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// Distinct blobs checked
+    pub checked: usize,
+    pub corrupt: Vec<CorruptContent>,
+}
+
+impl ScrubReport {
+    /// Artifacts whose content needs to be fetched again
+    pub fn damaged_artifacts(&self) -> Vec<String> {
+        self.corrupt
+            .iter()
+            .flat_map(|corrupt| corrupt.artifact_ids.iter().cloned())
+            .collect()
+    }
+}
+
+/// Verify the content of every artifact, quarantining damaged blobs
+///
+/// Damaged blobs are copied to `quarantine/<hash>` (when given) before
+/// being removed from `content`.
+pub fn scrub(
+    artifacts: &dyn ArtifactStore,
+    content: &dyn ContentStore,
+    quarantine: Option<&Path>,
+) -> anyhow::Result<ScrubReport> {
+    let mut referenced: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for artifact in artifacts.list()? {
+        referenced
+            .entry(artifact.content_hash)
+            .or_default()
+            .push(artifact.id);
+    }
+
+    let mut report = ScrubReport::default();
+    for (hash, mut artifact_ids) in referenced {
+        report.checked += 1;
+        let quarantined = match content.get_content(&hash) {
+            Ok(Some(data)) if content_hash(&data) == hash => continue,
+            Ok(None) => false,
+            Ok(Some(data)) => {
+                if let Some(dir) = quarantine {
+                    std::fs::create_dir_all(dir)?;
+                    std::fs::write(dir.join(&hash), data)?;
+                }
+                content.delete_content(&hash)?;
+                true
+            }
+            Err(_) => {
+                // Unreadable, e.g. an encrypted blob failing authentication
+                content.delete_content(&hash)?;
+                true
+            }
+        };
+        artifact_ids.sort();
+        report.corrupt.push(CorruptContent {
+            content_hash: hash,
+            artifact_ids,
+            quarantined,
+        });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Artifact, InMemoryStore};
+
+    #[test]
+    fn test_quarantines_damaged_and_reports_missing_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = InMemoryStore::new();
+        let add = |id: &str, body: &[u8]| {
+            let hash = content_hash(body);
+            store
+                .store(&Artifact {
+                    id: id.into(),
+                    content_hash: hash.clone(),
+                    ..Default::default()
+                })
+                .unwrap();
+            hash
+        };
+        let intact = add("intact", b"fine");
+        store.put_content(&intact, b"fine").unwrap();
+        let rotten = add("rotten", b"original");
+        add("copy", b"original");
+        store.put_content(&rotten, b"0riginal").unwrap();
+        add("missing", b"gone");
+
+        let report = scrub(&store, &store, Some(dir.path())).unwrap();
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.corrupt.len(), 2);
+        let damaged = report
+            .corrupt
+            .iter()
+            .find(|corrupt| corrupt.content_hash == rotten)
+            .unwrap();
+        assert!(damaged.quarantined);
+        assert_eq!(damaged.artifact_ids, ["copy", "rotten"]);
+        assert!(!store.has_content(&rotten).unwrap());
+        assert_eq!(
+            std::fs::read(dir.path().join(&rotten)).unwrap(),
+            b"0riginal"
+        );
+        assert!(store.has_content(&intact).unwrap());
+        assert_eq!(report.damaged_artifacts().len(), 3);
+    }
+}
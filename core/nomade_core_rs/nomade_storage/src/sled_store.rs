@@ -0,0 +1,201 @@
+//! Persistent artifact store backed by sled
+
+use std::ops::Bound;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::page::{finish, page_of, PageCursor};
+use crate::{Artifact, ArtifactStore, ContentStore, Page, SortOrder};
+
+/// Artifact and content store persisted in a sled database
+pub struct SledStore {
+    db: sled::Db,
+    artifacts: sled::Tree,
+    content: sled::Tree,
+}
+
+impl SledStore {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let db = open_db(path.as_ref())?;
+        let artifacts = db.open_tree("artifacts")?;
+        let content = db.open_tree("content")?;
+        Ok(Self {
+            db,
+            artifacts,
+            content,
+        })
+    }
+}
+
+/// Time to wait for a previous handle's lock to be released
+const LOCK_WAIT: Duration = Duration::from_secs(2);
+
+/// Open the database, waiting out the file lock of a handle being dropped
+///
+/// sled releases its lock from a background thread, so reopening right
+/// after dropping the previous handle can briefly fail with `WouldBlock`.
+fn open_db(path: &Path) -> sled::Result<sled::Db> {
+    let deadline = Instant::now() + LOCK_WAIT;
+    loop {
+        match sled::open(path) {
+            Err(sled::Error::Io(e))
+                if e.kind() == std::io::ErrorKind::WouldBlock && Instant::now() < deadline =>
+            {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            result => return result,
+        }
+    }
+}
+
+impl ArtifactStore for SledStore {
+    fn store(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        self.artifacts
+            .insert(artifact.id.as_bytes(), serde_json::to_vec(artifact)?)?;
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> anyhow::Result<Option<Artifact>> {
+        match self.artifacts.get(id.as_bytes())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<Artifact>> {
+        self.artifacts
+            .iter()
+            .values()
+            .map(|bytes| Ok(serde_json::from_slice(&bytes?)?))
+            .collect()
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+    ) -> anyhow::Result<Page> {
+        if sort != SortOrder::Id {
+            return page_of(self.list()?, cursor, limit, sort);
+        }
+        // Keys are IDs, so the tree is already in order
+        let start = match cursor {
+            Some(cursor) => Bound::Excluded(PageCursor::decode(cursor, sort)?.id.into_bytes()),
+            None => Bound::Unbounded,
+        };
+        let artifacts = self
+            .artifacts
+            .range::<Vec<u8>, _>((start, Bound::Unbounded))
+            .values()
+            .take(limit.max(1) + 1)
+            .map(|bytes| Ok(serde_json::from_slice(&bytes?)?))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(finish(artifacts, limit, sort, self.artifacts.len() as u64))
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.artifacts.remove(id.as_bytes())?;
+        Ok(())
+    }
+
+    fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        let mut batch = sled::Batch::default();
+        for (i, artifact) in artifacts.iter().enumerate() {
+            batch.insert(artifact.id.as_bytes(), serde_json::to_vec(artifact)?);
+            progress(i + 1);
+        }
+        self.artifacts.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        let mut batch = sled::Batch::default();
+        for (i, id) in ids.iter().enumerate() {
+            batch.remove(id.as_bytes());
+            progress(i + 1);
+        }
+        self.artifacts.apply_batch(batch)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn disk_usage(&self) -> anyhow::Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
+}
+
+impl ContentStore for SledStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.content.insert(hash.as_bytes(), data)?;
+        Ok(())
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.content.get(hash.as_bytes())?.map(|v| v.to_vec()))
+    }
+
+    fn has_content(&self, hash: &str) -> anyhow::Result<bool> {
+        Ok(self.content.contains_key(hash.as_bytes())?)
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        self.content.remove(hash.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sled_store_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let artifact = Artifact {
+            id: "note-1".into(),
+            title: "Note".into(),
+            created_at: 1,
+            modified_at: 2,
+            content_hash: "hash".into(),
+            ..Default::default()
+        };
+
+        {
+            let store = SledStore::open(dir.path()).unwrap();
+            store.store(&artifact).unwrap();
+            store.put_content("hash", b"body").unwrap();
+            store.flush().unwrap();
+        }
+
+        let store = SledStore::open(dir.path()).unwrap();
+        assert_eq!(store.get("note-1").unwrap().unwrap().title, "Note");
+        assert_eq!(store.list().unwrap().len(), 1);
+        assert_eq!(store.get_content("hash").unwrap().unwrap(), b"body");
+        assert!(!store.has_content("other").unwrap());
+        store
+            .store(&Artifact {
+                id: "note-2".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let page = store.list_page(None, 1, SortOrder::Id).unwrap();
+        assert_eq!((page.artifacts[0].id.as_str(), page.total), ("note-1", 2));
+        let page = store
+            .list_page(page.next.as_deref(), 1, SortOrder::Id)
+            .unwrap();
+        assert_eq!(page.artifacts[0].id, "note-2");
+        assert!(page.next.is_none());
+        store.delete("note-1").unwrap();
+        assert!(store.get("note-1").unwrap().is_none());
+    }
+}
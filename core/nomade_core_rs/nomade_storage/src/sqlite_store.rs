@@ -0,0 +1,613 @@
+//! Persistent artifact store backed by SQLite
+//!
+//! Titles and tags are kept in indexed columns for queries, so anyone who
+//! reads the file reads them too. Opened with a key, the database is
+//! encrypted page by page with SQLCipher (AES-256, HMAC per page):
+//! `open_encrypted` converts a plaintext database on first use and
+//! `rekey` rotates the key in place.
+//!
+//! Custom fields declared `indexed` in a `SchemaRegistry` given to
+//! `index_fields` are copied to a `fields` table, text and numbers in
+//! separate indexed columns, for `query`.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{bail, Context};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::page::{finish, PageCursor};
+use crate::schema::{index_number, FieldQuery, SchemaRegistry};
+use crate::{
+    Artifact, ArtifactField, ArtifactStore, ArtifactSummary, ContentStore, Page, Projection,
+    SortOrder,
+};
+
+/// Fields kept in columns of the artifacts table
+const COLUMNS: [ArtifactField; 3] = [
+    ArtifactField::Title,
+    ArtifactField::ArtifactType,
+    ArtifactField::ModifiedAt,
+];
+
+/// First bytes of every plaintext SQLite database
+const PLAINTEXT_HEADER: &[u8] = b"SQLite format 3\0";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS artifacts (
+        id TEXT PRIMARY KEY,
+        title TEXT NOT NULL,
+        artifact_type TEXT,
+        modified_at INTEGER NOT NULL,
+        data BLOB NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS idx_modified ON artifacts(modified_at, id);
+    CREATE INDEX IF NOT EXISTS idx_title ON artifacts(title, id);
+    CREATE TABLE IF NOT EXISTS tags (
+        artifact_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (artifact_id, tag)
+    );
+    CREATE INDEX IF NOT EXISTS idx_tag ON tags(tag);
+    CREATE TABLE IF NOT EXISTS fields (
+        artifact_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        text TEXT,
+        number REAL,
+        PRIMARY KEY (artifact_id, name)
+    );
+    CREATE INDEX IF NOT EXISTS idx_field_text ON fields(name, text);
+    CREATE INDEX IF NOT EXISTS idx_field_number ON fields(name, number);
+    CREATE TABLE IF NOT EXISTS content (
+        hash TEXT PRIMARY KEY,
+        data BLOB NOT NULL
+    );
+";
+
+/// Artifact and content store persisted in a SQLite database
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    path: PathBuf,
+    encrypted: bool,
+    schemas: RwLock<Option<Arc<SchemaRegistry>>>,
+}
+
+impl SqliteStore {
+    /// Open or create a plaintext database at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if is_encrypted(path)? {
+            bail!("{} is encrypted; open it with its key", path.display());
+        }
+        Self::connect(path, None)
+    }
+
+    /// Open or create a database encrypted under `key`
+    ///
+    /// A plaintext database at `path` is encrypted first.
+    pub fn open_encrypted(path: impl AsRef<Path>, key: &[u8; 32]) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() && !is_encrypted(path)? {
+            encrypt_in_place(path, key)?;
+        }
+        Self::connect(path, Some(key))
+    }
+
+    fn connect(path: &Path, key: Option<&[u8; 32]>) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        if let Some(key) = key {
+            conn.execute_batch(&format!("PRAGMA key = \"{}\";", raw_key(key)))?;
+        }
+        // The first read fails if the key is wrong
+        conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+            .with_context(|| format!("Cannot read {}: wrong key?", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            path: path.to_path_buf(),
+            encrypted: key.is_some(),
+            schemas: RwLock::new(None),
+        })
+    }
+
+    /// Whether the database is encrypted
+    pub fn encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Re-encrypt the database under `key`
+    pub fn rekey(&self, key: &[u8; 32]) -> anyhow::Result<()> {
+        if !self.encrypted {
+            bail!("{} is not encrypted", self.path.display());
+        }
+        self.conn
+            .lock()
+            .unwrap()
+            .execute_batch(&format!("PRAGMA rekey = \"{}\";", raw_key(key)))?;
+        Ok(())
+    }
+
+    /// IDs of artifacts tagged `tag`
+    pub fn tagged(&self, tag: &str) -> anyhow::Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare("SELECT artifact_id FROM tags WHERE tag = ?1")?;
+        let ids = query
+            .query_map([tag], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(ids)
+    }
+
+    /// Keyset page, of artifacts holding only their columns if `columns_only`
+    fn page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+        columns_only: bool,
+    ) -> anyhow::Result<Page> {
+        let order = match sort {
+            SortOrder::Id => "id",
+            SortOrder::Modified => "modified_at DESC, id",
+            SortOrder::Title => "title, id",
+        };
+        let mut sql = format!(
+            "SELECT {} FROM artifacts",
+            match columns_only {
+                true => "id, title, artifact_type, modified_at",
+                false => "data",
+            }
+        );
+        let mut args: Vec<rusqlite::types::Value> = Vec::new();
+        if let Some(cursor) = cursor {
+            let cursor = PageCursor::decode(cursor, sort)?;
+            // Keyset conditions the indexes on (key, id) can seek to
+            sql.push_str(match sort {
+                SortOrder::Id => " WHERE id > ?1",
+                SortOrder::Modified => " WHERE modified_at < ?2 OR (modified_at = ?2 AND id > ?1)",
+                SortOrder::Title => " WHERE title > ?2 OR (title = ?2 AND id > ?1)",
+            });
+            args.push(cursor.id.into());
+            match sort {
+                SortOrder::Id => {}
+                SortOrder::Modified => args.push((cursor.modified_at as i64).into()),
+                SortOrder::Title => args.push(cursor.title.into()),
+            }
+        }
+        // One more tells whether a next page exists
+        sql.push_str(&format!(" ORDER BY {} LIMIT {}", order, limit.max(1) + 1));
+        let conn = self.conn.lock().unwrap();
+        let total: i64 = conn.query_row("SELECT count(*) FROM artifacts", [], |row| row.get(0))?;
+        let mut query = conn.prepare(&sql)?;
+        let mut rows = query.query(rusqlite::params_from_iter(args))?;
+        let mut artifacts = Vec::new();
+        while let Some(row) = rows.next()? {
+            artifacts.push(match columns_only {
+                true => Artifact {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    artifact_type: row.get(2)?,
+                    modified_at: row.get::<_, i64>(3)? as u64,
+                    ..Default::default()
+                },
+                false => serde_json::from_slice(&row.get::<_, Vec<u8>>(0)?)?,
+            });
+        }
+        Ok(finish(artifacts, limit, sort, total as u64))
+    }
+
+    /// Index the fields `schemas` declares indexed, now and on every write
+    ///
+    /// Call again after registering a schema to reindex stored artifacts.
+    pub fn index_fields(&self, schemas: Arc<SchemaRegistry>) -> anyhow::Result<()> {
+        *self.schemas.write().unwrap() = Some(schemas.clone());
+        let artifacts = self.list()?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM fields", [])?;
+        for artifact in &artifacts {
+            insert_fields(&tx, &schemas, artifact)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Artifacts matching `query` on an indexed field
+    pub fn query(&self, query: &FieldQuery) -> anyhow::Result<Vec<Artifact>> {
+        match &*self.schemas.read().unwrap() {
+            Some(schemas) => schemas.check_query(query)?,
+            None => bail!("{} has no field index", self.path.display()),
+        }
+        let mut sql = String::from(
+            "SELECT a.data FROM fields f JOIN artifacts a ON a.id = f.artifact_id
+             WHERE a.artifact_type = ?1 AND f.name = ?2",
+        );
+        let mut args: Vec<rusqlite::types::Value> = vec![
+            query.artifact_type.clone().into(),
+            query.field.clone().into(),
+        ];
+        if let Some(equals) = &query.equals {
+            match (equals.as_str(), index_number(equals)) {
+                (Some(text), _) => {
+                    sql.push_str(" AND f.text = ?");
+                    args.push(text.to_string().into());
+                }
+                (None, Some(number)) => {
+                    sql.push_str(" AND f.number = ?");
+                    args.push(number.into());
+                }
+                (None, None) => bail!("Cannot query by {}", equals),
+            }
+        }
+        for (bound, op) in [(query.min, ">="), (query.max, "<=")] {
+            if let Some(bound) = bound {
+                sql.push_str(&format!(" AND f.number {} ?", op));
+                args.push(bound.into());
+            }
+        }
+        sql.push_str(" ORDER BY a.id");
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&sql)?;
+        let rows = statement.query_map(rusqlite::params_from_iter(args), |row| {
+            row.get::<_, Vec<u8>>(0)
+        })?;
+        rows.map(|data| Ok(serde_json::from_slice(&data?)?))
+            .collect()
+    }
+}
+
+fn insert_fields(
+    tx: &rusqlite::Transaction,
+    schemas: &SchemaRegistry,
+    artifact: &Artifact,
+) -> anyhow::Result<()> {
+    for (name, value) in schemas.indexed_fields(artifact) {
+        tx.execute(
+            "INSERT INTO fields (artifact_id, name, text, number) VALUES (?1, ?2, ?3, ?4)",
+            params![artifact.id, name, value.as_str(), index_number(value)],
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether the file at `path` is not a plaintext SQLite database
+///
+/// Missing and empty files count as plaintext.
+pub fn is_encrypted(path: &Path) -> anyhow::Result<bool> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    let mut header = Vec::with_capacity(PLAINTEXT_HEADER.len());
+    file.take(PLAINTEXT_HEADER.len() as u64)
+        .read_to_end(&mut header)?;
+    Ok(!header.is_empty() && header != PLAINTEXT_HEADER)
+}
+
+/// Replace the plaintext database at `path` with an encrypted copy
+fn encrypt_in_place(path: &Path, key: &[u8; 32]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("encrypting");
+    if tmp.exists() {
+        std::fs::remove_file(&tmp)?;
+    }
+    {
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            params![tmp.to_string_lossy(), raw_key(key)],
+        )?;
+        conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+        conn.execute_batch("DETACH DATABASE encrypted")?;
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// SQLCipher raw key literal, used as is instead of stretched
+fn raw_key(key: &[u8; 32]) -> String {
+    let hex: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("x'{}'", hex)
+}
+
+impl ArtifactStore for SqliteStore {
+    fn store(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        self.store_many(std::slice::from_ref(artifact), &mut |_| {})
+    }
+
+    fn get(&self, id: &str) -> anyhow::Result<Option<Artifact>> {
+        let data: Option<Vec<u8>> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT data FROM artifacts WHERE id = ?1", [id], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(data.map(|data| serde_json::from_slice(&data)).transpose()?)
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<Artifact>> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare("SELECT data FROM artifacts")?;
+        let rows = query.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+        rows.map(|data| Ok(serde_json::from_slice(&data?)?))
+            .collect()
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+    ) -> anyhow::Result<Page> {
+        self.page(cursor, limit, sort, false)
+    }
+
+    fn list_projected(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+        projection: &Projection,
+    ) -> anyhow::Result<Page<ArtifactSummary>> {
+        let columns_only = projection.within(&COLUMNS);
+        Ok(self
+            .page(cursor, limit, sort, columns_only)?
+            .map(|artifact| projection.apply(artifact)))
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        self.delete_many(&[id.to_string()], &mut |_| {})
+    }
+
+    fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        let schemas = self.schemas.read().unwrap();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (i, artifact) in artifacts.iter().enumerate() {
+            tx.execute(
+                "INSERT OR REPLACE INTO artifacts (id, title, artifact_type, modified_at, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    artifact.id,
+                    artifact.title,
+                    artifact.artifact_type,
+                    artifact.modified_at as i64,
+                    serde_json::to_vec(artifact)?,
+                ],
+            )?;
+            tx.execute("DELETE FROM tags WHERE artifact_id = ?1", [&artifact.id])?;
+            for tag in &artifact.tags {
+                tx.execute(
+                    "INSERT OR IGNORE INTO tags (artifact_id, tag) VALUES (?1, ?2)",
+                    [&artifact.id, tag],
+                )?;
+            }
+            tx.execute("DELETE FROM fields WHERE artifact_id = ?1", [&artifact.id])?;
+            if let Some(schemas) = &*schemas {
+                insert_fields(&tx, schemas, artifact)?;
+            }
+            progress(i + 1);
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for (i, id) in ids.iter().enumerate() {
+            tx.execute("DELETE FROM tags WHERE artifact_id = ?1", [id])?;
+            tx.execute("DELETE FROM fields WHERE artifact_id = ?1", [id])?;
+            tx.execute("DELETE FROM artifacts WHERE id = ?1", [id])?;
+            progress(i + 1);
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn disk_usage(&self) -> anyhow::Result<u64> {
+        Ok(std::fs::metadata(&self.path)?.len())
+    }
+}
+
+impl ContentStore for SqliteStore {
+    fn put_content(&self, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO content (hash, data) VALUES (?1, ?2)",
+            params![hash, data],
+        )?;
+        Ok(())
+    }
+
+    fn get_content(&self, hash: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT data FROM content WHERE hash = ?1", [hash], |row| {
+                row.get(0)
+            })
+            .optional()?)
+    }
+
+    fn delete_content(&self, hash: &str) -> anyhow::Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM content WHERE hash = ?1", [hash])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifact() -> Artifact {
+        Artifact {
+            id: "note-1".into(),
+            title: "Tax return 2025".into(),
+            created_at: 1,
+            modified_at: 2,
+            content_hash: "hash".into(),
+            tags: vec!["finance".into()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_plaintext_database_migrates_and_rekeys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("artifacts.db");
+        {
+            let store = SqliteStore::open(&path).unwrap();
+            store.store(&artifact()).unwrap();
+            store.put_content("hash", b"body").unwrap();
+            assert_eq!(store.tagged("finance").unwrap(), ["note-1"]);
+        }
+        let leaks = |path: &Path| {
+            let bytes = std::fs::read(path).unwrap();
+            bytes.windows(8).any(|w| w == b"Tax retu")
+        };
+        assert!(leaks(&path));
+
+        let key = [1u8; 32];
+        let store = SqliteStore::open_encrypted(&path, &key).unwrap();
+        assert!(store.encrypted() && is_encrypted(&path).unwrap());
+        assert!(!leaks(&path));
+        assert_eq!(store.get("note-1").unwrap().unwrap(), artifact());
+        assert_eq!(store.get_content("hash").unwrap().unwrap(), b"body");
+
+        let rotated = [2u8; 32];
+        store.rekey(&rotated).unwrap();
+        drop(store);
+        assert!(SqliteStore::open(&path).is_err());
+        assert!(SqliteStore::open_encrypted(&path, &key).is_err());
+        let store = SqliteStore::open_encrypted(&path, &rotated).unwrap();
+        assert_eq!(store.list().unwrap().len(), 1);
+        store.delete("note-1").unwrap();
+        assert!(store.get("note-1").unwrap().is_none());
+        assert!(store.tagged("finance").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_indexed_fields_queried() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(dir.path().join("artifacts.db")).unwrap();
+        let track = |id: &str, fields: serde_json::Value| Artifact {
+            id: id.into(),
+            artifact_type: Some("track".into()),
+            fields: serde_json::from_value(fields).unwrap(),
+            ..Default::default()
+        };
+        store
+            .store(&track(
+                "a",
+                serde_json::json!({"artist": "Nina", "bpm": 92}),
+            ))
+            .unwrap();
+        store
+            .store(&track(
+                "b",
+                serde_json::json!({"artist": "Miles", "bpm": 120}),
+            ))
+            .unwrap();
+        let query = FieldQuery {
+            artifact_type: "track".into(),
+            field: "bpm".into(),
+            equals: None,
+            min: Some(100.0),
+            max: None,
+        };
+        assert!(store.query(&query).is_err());
+
+        // Registering the schema indexes what is already stored
+        let schemas = Arc::new(SchemaRegistry::new());
+        schemas
+            .register(
+                serde_json::from_str(
+                    r#"{"artifact_type": "track", "fields": [
+                    {"name": "artist", "type": "text", "indexed": true},
+                    {"name": "bpm", "type": "integer", "indexed": true}
+                ]}"#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        store.index_fields(schemas).unwrap();
+        let ids = |query: &FieldQuery| -> Vec<String> {
+            store
+                .query(query)
+                .unwrap()
+                .into_iter()
+                .map(|a| a.id)
+                .collect()
+        };
+        assert_eq!(ids(&query), ["b"]);
+        store
+            .store(&track(
+                "c",
+                serde_json::json!({"artist": "Nina", "bpm": 140}),
+            ))
+            .unwrap();
+        assert_eq!(ids(&query), ["b", "c"]);
+        let by_artist = FieldQuery {
+            field: "artist".into(),
+            equals: Some("Nina".into()),
+            min: None,
+            ..query
+        };
+        assert_eq!(ids(&by_artist), ["a", "c"]);
+        store.delete("a").unwrap();
+        assert_eq!(ids(&by_artist), ["c"]);
+    }
+
+    #[test]
+    fn test_pages_in_sort_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::open(dir.path().join("artifacts.db")).unwrap();
+        for (id, title, modified_at) in [("a", "Zebra", 3), ("b", "Apple", 3), ("c", "Mango", 7)] {
+            store
+                .store(&Artifact {
+                    id: id.into(),
+                    title: title.into(),
+                    modified_at,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let walk = |sort| {
+            let mut ids = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = store.list_page(cursor.as_deref(), 1, sort).unwrap();
+                assert_eq!(page.total, 3);
+                ids.extend(page.artifacts.into_iter().map(|a| a.id));
+                match page.next {
+                    Some(next) => cursor = Some(next),
+                    None => return ids,
+                }
+            }
+        };
+        assert_eq!(walk(SortOrder::Id), ["a", "b", "c"]);
+        assert_eq!(walk(SortOrder::Modified), ["c", "a", "b"]);
+        assert_eq!(walk(SortOrder::Title), ["b", "c", "a"]);
+
+        // Column-only projections match the decoded artifacts
+        let rows = store
+            .list_projected(None, 10, SortOrder::Title, &Projection::default())
+            .unwrap();
+        let full = store.list_page(None, 10, SortOrder::Title).unwrap();
+        assert_eq!(
+            rows.artifacts,
+            full.map(|a| Projection::default().apply(a)).artifacts
+        );
+        assert_eq!(rows.artifacts[0].title.as_deref(), Some("Apple"));
+    }
+}
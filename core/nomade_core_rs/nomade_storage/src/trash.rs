@@ -0,0 +1,126 @@
+//! Trash bin for deleted artifacts
+//!
+//! Trashing an artifact sets `Artifact::trashed_at` instead of deleting
+//! it. That is an ordinary edit with a newer `modified_at`, so it syncs
+//! and peers trash their copy too, and restoring syncs the same way.
+//! Trashed artifacts stay in the store, and in list results with
+//! `trashed_at` set, until the trash is emptied or they pass the
+//! retention period; only then are they deleted for good.
+
+use anyhow::anyhow;
+
+use crate::{bulk, Artifact, ArtifactStore};
+
+/// Days trashed artifacts are kept before they are purged
+pub const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// Move the artifact `id` to the trash at `now`
+///
+/// Trashing an artifact already in the trash keeps its original time.
+pub fn trash(store: &dyn ArtifactStore, id: &str, now: u64) -> anyhow::Result<Artifact> {
+    let mut artifact = get(store, id)?;
+    if artifact.trashed_at.is_none() {
+        artifact.trashed_at = Some(now);
+        touch(&mut artifact, now);
+        store.store(&artifact)?;
+    }
+    Ok(artifact)
+}
+
+/// Take the artifact `id` back out of the trash
+pub fn restore(store: &dyn ArtifactStore, id: &str, now: u64) -> anyhow::Result<Artifact> {
+    let mut artifact = get(store, id)?;
+    if artifact.trashed_at.take().is_none() {
+        return Err(anyhow!("Artifact is not in the trash: {}", id));
+    }
+    touch(&mut artifact, now);
+    store.store(&artifact)?;
+    Ok(artifact)
+}
+
+/// Artifacts in the trash, most recently trashed first
+pub fn trashed(store: &dyn ArtifactStore) -> anyhow::Result<Vec<Artifact>> {
+    let mut trashed: Vec<_> = store
+        .list()?
+        .into_iter()
+        .filter(|artifact| artifact.trashed_at.is_some())
+        .collect();
+    trashed.sort_by(|a, b| (b.trashed_at, &a.id).cmp(&(a.trashed_at, &b.id)));
+    Ok(trashed)
+}
+
+/// Delete for good the artifacts trashed at or before `cutoff`
+///
+/// Returns the IDs deleted, in one transaction.
+pub fn empty_trash(store: &dyn ArtifactStore, cutoff: u64) -> anyhow::Result<Vec<String>> {
+    let ids: Vec<String> = store
+        .list()?
+        .into_iter()
+        .filter(|artifact| artifact.trashed_at.is_some_and(|at| at <= cutoff))
+        .map(|artifact| artifact.id)
+        .collect();
+    bulk::delete_many(store, &ids, |_| {})?;
+    Ok(ids)
+}
+
+fn get(store: &dyn ArtifactStore, id: &str) -> anyhow::Result<Artifact> {
+    store
+        .get(id)?
+        .ok_or_else(|| anyhow!("Artifact not found: {}", id))
+}
+
+/// Strictly newer, so peers take the change
+fn touch(artifact: &mut Artifact, now: u64) {
+    artifact.modified_at = now.max(artifact.modified_at + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use nomade_events::{Event, EventStream};
+
+    use super::*;
+    use crate::{InMemoryStore, WatchedStore};
+
+    #[test]
+    fn test_trash_restore_and_empty() {
+        let events = EventStream::new();
+        let mut published = events.subscribe();
+        let store = WatchedStore::new(Arc::new(InMemoryStore::new())).with_events(events);
+        for id in ["a", "b"] {
+            store
+                .store(&Artifact {
+                    id: id.into(),
+                    modified_at: 10,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        while published.try_recv().is_ok() {}
+
+        let trashed_a = trash(&store, "a", 5).unwrap();
+        assert_eq!(trashed_a.trashed_at, Some(5));
+        assert_eq!(trashed_a.modified_at, 11);
+        trash(&store, "b", 20).unwrap();
+        assert_eq!(trash(&store, "b", 30).unwrap().trashed_at, Some(20));
+        assert!(matches!(
+            published.try_recv().unwrap(),
+            Event::ArtifactTrashed { ref id } if id == "a"
+        ));
+        let ids: Vec<_> = trashed(&store).unwrap().into_iter().map(|a| a.id).collect();
+        assert_eq!(ids, ["b", "a"]);
+
+        let restored = restore(&store, "a", 25).unwrap();
+        assert_eq!(restored.trashed_at, None);
+        assert_eq!(restored.modified_at, 25);
+        assert!(restore(&store, "a", 26).is_err());
+        assert!(std::iter::from_fn(|| published.try_recv().ok())
+            .any(|event| matches!(event, Event::ArtifactRestored { ref id } if id == "a")));
+
+        assert!(empty_trash(&store, 19).unwrap().is_empty());
+        assert_eq!(empty_trash(&store, 20).unwrap(), ["b"]);
+        assert!(store.get("b").unwrap().is_none());
+        assert!(store.get("a").unwrap().is_some());
+    }
+}
@@ -0,0 +1,208 @@
+//! Verified streaming of content
+//!
+//! A content hash is a BLAKE3 root, and BLAKE3 is a tree hash: content
+//! split into `HASH_GROUP_SIZE` groups has one chaining value per group,
+//! and those combine into the root. A `HashTree` holds the group chaining
+//! values (32 bytes per group). Once the tree is checked against the
+//! content hash, each group can be verified on its own as it arrives, so
+//! a partial download is known to be intact before the whole content is
+//! there (the idea behind Bao's verified streaming, with the tree sent
+//! up front instead of interleaved).
+
+use std::io::Read;
+
+use anyhow::{anyhow, bail};
+use blake3::hazmat::{
+    left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+
+/// Content bytes covered by one tree leaf; equals the sync chunk size
+pub const HASH_GROUP_SIZE: usize = 64 * 1024;
+
+/// Hash content read incrementally, without holding it in memory
+pub fn hash_reader(mut reader: impl Read) -> std::io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; HASH_GROUP_SIZE];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => return Ok(hasher.finalize().to_hex().to_string()),
+            n => {
+                hasher.update(&buffer[..n]);
+            }
+        }
+    }
+}
+
+/// Chaining values of every group of some content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashTree {
+    len: u64,
+    /// One per group; for single-group content, the root hash itself
+    leaves: Vec<ChainingValue>,
+}
+
+impl HashTree {
+    /// Build the tree of `content`
+    pub fn build(content: &[u8]) -> Self {
+        let len = content.len() as u64;
+        if content.len() <= HASH_GROUP_SIZE {
+            return Self {
+                len,
+                leaves: vec![*blake3::hash(content).as_bytes()],
+            };
+        }
+        let leaves = content
+            .chunks(HASH_GROUP_SIZE)
+            .enumerate()
+            .map(|(index, group)| group_cv(index, group))
+            .collect();
+        Self { len, leaves }
+    }
+
+    /// Decode `to_bytes` output
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (len, leaves) = bytes
+            .split_first_chunk::<8>()
+            .ok_or_else(|| anyhow!("Truncated hash tree"))?;
+        let len = u64::from_le_bytes(*len);
+        let groups = len.div_ceil(HASH_GROUP_SIZE as u64).max(1);
+        if leaves.len() as u64 != groups.saturating_mul(32) {
+            bail!("Hash tree for {} bytes must have {} leaves", len, groups);
+        }
+        let leaves = leaves.as_chunks::<32>().0.to_vec();
+        Ok(Self { len, leaves })
+    }
+
+    /// Content length (8 bytes, little endian) followed by the leaves
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.len.to_le_bytes().as_slice(), &self.leaves.concat()].concat()
+    }
+
+    /// Content length in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the content is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of groups
+    pub fn group_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Content hash the tree combines to
+    pub fn root_hash(&self) -> String {
+        let root = match self.leaves.as_slice() {
+            [root] => blake3::Hash::from_bytes(*root),
+            leaves => {
+                let (left, right) = split(leaves, self.len);
+                merge_subtrees_root(&left, &right, Mode::Hash)
+            }
+        };
+        root.to_hex().to_string()
+    }
+
+    /// Check the tree against the content hash it should describe
+    pub fn verify(&self, content_hash: &str) -> anyhow::Result<()> {
+        if self.root_hash() != content_hash {
+            return Err(anyhow!("Hash tree does not match content {}", content_hash));
+        }
+        Ok(())
+    }
+
+    /// Whether `group` is the intact content of group `index`
+    ///
+    /// Only meaningful for a tree that passed `verify`.
+    pub fn verify_group(&self, index: usize, group: &[u8]) -> bool {
+        let Some(leaf) = self.leaves.get(index) else {
+            return false;
+        };
+        let start = (index * HASH_GROUP_SIZE) as u64;
+        let expected = (self.len - start).min(HASH_GROUP_SIZE as u64);
+        if group.len() as u64 != expected {
+            return false;
+        }
+        let actual = match self.leaves.len() {
+            1 => *blake3::hash(group).as_bytes(),
+            _ => group_cv(index, group),
+        };
+        actual == *leaf
+    }
+}
+
+fn group_cv(index: usize, group: &[u8]) -> ChainingValue {
+    blake3::Hasher::new()
+        .set_input_offset((index * HASH_GROUP_SIZE) as u64)
+        .update(group)
+        .finalize_non_root()
+}
+
+/// Chaining values of the left and right subtrees over `leaves`
+fn split(leaves: &[ChainingValue], len: u64) -> (ChainingValue, ChainingValue) {
+    let left_len = left_subtree_len(len);
+    let left_groups = (left_len / HASH_GROUP_SIZE as u64) as usize;
+    (
+        subtree(&leaves[..left_groups], left_len),
+        subtree(&leaves[left_groups..], len - left_len),
+    )
+}
+
+fn subtree(leaves: &[ChainingValue], len: u64) -> ChainingValue {
+    match leaves {
+        [leaf] => *leaf,
+        leaves => {
+            let (left, right) = split(leaves, len);
+            merge_subtrees_non_root(&left, &right, Mode::Hash)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_hash;
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_tree_root_is_content_hash() {
+        let g = HASH_GROUP_SIZE;
+        for len in [0, 1, g, g + 1, 2 * g, 3 * g + 5, 8 * g] {
+            let data = content(len);
+            let tree = HashTree::build(&data);
+            assert_eq!(tree.root_hash(), content_hash(&data), "length {}", len);
+            assert_eq!(hash_reader(data.as_slice()).unwrap(), content_hash(&data));
+            let decoded = HashTree::from_bytes(&tree.to_bytes()).unwrap();
+            assert_eq!(decoded, tree);
+        }
+    }
+
+    #[test]
+    fn test_groups_verify_independently() {
+        let data = content(3 * HASH_GROUP_SIZE + 100);
+        let tree = HashTree::build(&data);
+        tree.verify(&content_hash(&data)).unwrap();
+        assert!(tree.verify(&content_hash(b"other")).is_err());
+
+        let groups: Vec<&[u8]> = data.chunks(HASH_GROUP_SIZE).collect();
+        assert!(tree.verify_group(3, groups[3]));
+        assert!(tree.verify_group(1, groups[1]));
+        // Right bytes at the wrong position, and flipped bits
+        assert!(!tree.verify_group(2, groups[1]));
+        let mut damaged = groups[0].to_vec();
+        damaged[7] ^= 1;
+        assert!(!tree.verify_group(0, &damaged));
+        assert!(!tree.verify_group(4, b""));
+
+        let small = HashTree::build(b"tiny");
+        assert!(small.verify_group(0, b"tiny"));
+        assert!(!small.verify_group(0, b"tint"));
+        assert!(HashTree::from_bytes(&[5, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        assert!(HashTree::from_bytes(&[0; 4]).is_err());
+    }
+}
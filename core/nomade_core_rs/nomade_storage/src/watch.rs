@@ -0,0 +1,276 @@
+//! Change feed for artifact stores
+//!
+//! `WatchedStore` wraps another `ArtifactStore` and reports every
+//! successful mutation to its watchers, in commit order, once the inner
+//! store accepted it. Mutations are serialized so a change record always
+//! matches the state a reader sees right after it. With an event stream
+//! attached, each change is also published as the matching artifact
+//! event, so writers never publish those by hand. Bulk writes publish a
+//! single `Event::Batched` holding one event per artifact. Updates that
+//! move an artifact into or out of the trash are reported as such.
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use nomade_events::{Event, EventStream};
+
+use crate::{Artifact, ArtifactStore, ArtifactSummary, Page, Projection, SortOrder};
+
+/// Kind of change made to an artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreChangeKind {
+    Inserted,
+    Updated,
+    Deleted,
+    /// Updated into the trash
+    Trashed,
+    /// Updated out of the trash
+    Restored,
+}
+
+impl StoreChangeKind {
+    /// Kind of storing `after` over `before`
+    fn of(before: Option<&Artifact>, after: &Artifact) -> Self {
+        match before {
+            None => Self::Inserted,
+            Some(before) => match (before.trashed_at, after.trashed_at) {
+                (None, Some(_)) => Self::Trashed,
+                (Some(_), None) => Self::Restored,
+                _ => Self::Updated,
+            },
+        }
+    }
+}
+
+/// One committed mutation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreChange {
+    pub kind: StoreChangeKind,
+    pub id: String,
+}
+
+impl From<StoreChange> for Event {
+    fn from(change: StoreChange) -> Self {
+        let id = change.id;
+        match change.kind {
+            StoreChangeKind::Inserted => Event::ArtifactCreated { id },
+            StoreChangeKind::Updated => Event::ArtifactUpdated { id },
+            StoreChangeKind::Deleted => Event::ArtifactDeleted { id },
+            StoreChangeKind::Trashed => Event::ArtifactTrashed { id },
+            StoreChangeKind::Restored => Event::ArtifactRestored { id },
+        }
+    }
+}
+
+/// Artifact store reporting its mutations
+pub struct WatchedStore {
+    inner: Arc<dyn ArtifactStore>,
+    watchers: Mutex<Vec<mpsc::Sender<StoreChange>>>,
+    /// Serializes mutations with their notifications
+    write: Mutex<()>,
+    events: Option<EventStream>,
+}
+
+impl WatchedStore {
+    /// Watch mutations made through this wrapper to `inner`
+    pub fn new(inner: Arc<dyn ArtifactStore>) -> Self {
+        Self {
+            inner,
+            watchers: Mutex::new(Vec::new()),
+            write: Mutex::new(()),
+            events: None,
+        }
+    }
+
+    /// Publish each change as an artifact event
+    pub fn with_events(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Receive every change committed from now on
+    ///
+    /// Dropping the receiver unsubscribes.
+    pub fn watch(&self) -> mpsc::Receiver<StoreChange> {
+        let (tx, rx) = mpsc::channel();
+        self.watchers.lock().unwrap().push(tx);
+        rx
+    }
+
+    fn notify(&self, kind: StoreChangeKind, id: &str) {
+        let change = StoreChange {
+            kind,
+            id: id.to_string(),
+        };
+        self.watchers
+            .lock()
+            .unwrap()
+            .retain(|watcher| watcher.send(change.clone()).is_ok());
+        if let Some(events) = &self.events {
+            events.publish(change.into());
+        }
+    }
+
+    fn notify_batch(&self, changes: Vec<StoreChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        self.watchers.lock().unwrap().retain(|watcher| {
+            changes
+                .iter()
+                .all(|change| watcher.send(change.clone()).is_ok())
+        });
+        if let Some(events) = &self.events {
+            events.publish(Event::Batched {
+                events: changes.into_iter().map(Into::into).collect(),
+            });
+        }
+    }
+}
+
+impl ArtifactStore for WatchedStore {
+    fn store(&self, artifact: &Artifact) -> anyhow::Result<()> {
+        let _write = self.write.lock().unwrap();
+        let kind = StoreChangeKind::of(self.inner.get(&artifact.id)?.as_ref(), artifact);
+        self.inner.store(artifact)?;
+        self.notify(kind, &artifact.id);
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> anyhow::Result<Option<Artifact>> {
+        self.inner.get(id)
+    }
+
+    fn list(&self) -> anyhow::Result<Vec<Artifact>> {
+        self.inner.list()
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+    ) -> anyhow::Result<Page> {
+        self.inner.list_page(cursor, limit, sort)
+    }
+
+    fn delete(&self, id: &str) -> anyhow::Result<()> {
+        let _write = self.write.lock().unwrap();
+        if self.inner.get(id)?.is_none() {
+            return Ok(());
+        }
+        self.inner.delete(id)?;
+        self.notify(StoreChangeKind::Deleted, id);
+        Ok(())
+    }
+
+    fn list_projected(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+        sort: SortOrder,
+        projection: &Projection,
+    ) -> anyhow::Result<Page<ArtifactSummary>> {
+        self.inner.list_projected(cursor, limit, sort, projection)
+    }
+
+    fn store_many(
+        &self,
+        artifacts: &[Artifact],
+        progress: &mut dyn FnMut(usize),
+    ) -> anyhow::Result<()> {
+        let _write = self.write.lock().unwrap();
+        let mut changes = Vec::with_capacity(artifacts.len());
+        // Later copies of an artifact in the batch follow the earlier ones
+        let mut seen: HashMap<&str, &Artifact> = HashMap::new();
+        for artifact in artifacts {
+            let before = match seen.insert(&artifact.id, artifact) {
+                Some(earlier) => Some(earlier.clone()),
+                None => self.inner.get(&artifact.id)?,
+            };
+            let kind = StoreChangeKind::of(before.as_ref(), artifact);
+            changes.push(StoreChange {
+                kind,
+                id: artifact.id.clone(),
+            });
+        }
+        self.inner.store_many(artifacts, progress)?;
+        self.notify_batch(changes);
+        Ok(())
+    }
+
+    fn delete_many(&self, ids: &[String], progress: &mut dyn FnMut(usize)) -> anyhow::Result<()> {
+        let _write = self.write.lock().unwrap();
+        let mut changes = Vec::with_capacity(ids.len());
+        for id in ids {
+            if self.inner.get(id)?.is_some() {
+                changes.push(StoreChange {
+                    kind: StoreChangeKind::Deleted,
+                    id: id.clone(),
+                });
+            }
+        }
+        self.inner.delete_many(ids, progress)?;
+        self.notify_batch(changes);
+        Ok(())
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        self.inner.flush()
+    }
+
+    fn disk_usage(&self) -> anyhow::Result<u64> {
+        self.inner.disk_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryStore;
+
+    #[test]
+    fn test_reports_changes_in_order() {
+        let events = EventStream::new();
+        let mut published = events.subscribe();
+        let store = WatchedStore::new(Arc::new(InMemoryStore::new())).with_events(events);
+        let changes = store.watch();
+        let artifact = Artifact {
+            id: "a1".into(),
+            ..Default::default()
+        };
+
+        store.store(&artifact).unwrap();
+        store.store(&artifact).unwrap();
+        store.delete("a1").unwrap();
+        store.delete("a1").unwrap();
+
+        let kinds: Vec<_> = changes.try_iter().map(|change| change.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                StoreChangeKind::Inserted,
+                StoreChangeKind::Updated,
+                StoreChangeKind::Deleted
+            ]
+        );
+        assert!(matches!(
+            published.try_recv().unwrap(),
+            Event::ArtifactCreated { ref id } if id == "a1"
+        ));
+        assert!(matches!(
+            published.try_recv().unwrap(),
+            Event::ArtifactUpdated { .. }
+        ));
+        assert!(matches!(
+            published.try_recv().unwrap(),
+            Event::ArtifactDeleted { .. }
+        ));
+        assert!(published.try_recv().is_err());
+
+        drop(changes);
+        store.store(&artifact).unwrap();
+        assert!(store.watchers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+//! Delta transfer and three-way text merges
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nomade_sync::{merge_text, Delta, Signature};
+
+fn content(size: usize) -> Vec<u8> {
+    let mut bytes = vec![0; size];
+    blake3::Hasher::new()
+        .update(b"bench")
+        .finalize_xof()
+        .fill(&mut bytes);
+    bytes
+}
+
+/// Diffing a large file against a version with a few edits
+fn delta(c: &mut Criterion) {
+    let mut group = c.benchmark_group("delta");
+    for size in [1024 * 1024, 16 * 1024 * 1024] {
+        let old = content(size);
+        let mut new = old.clone();
+        for pos in [size / 4, size / 2, 3 * size / 4] {
+            new[pos..pos + 100].fill(0);
+        }
+        let signature = Signature::new(&old);
+        let delta = Delta::compute(&signature, &new);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("signature", size), &old, |b, old| {
+            b.iter(|| Signature::new(old))
+        });
+        group.bench_with_input(BenchmarkId::new("compute", size), &new, |b, new| {
+            b.iter(|| Delta::compute(&signature, new))
+        });
+        group.bench_with_input(BenchmarkId::new("apply", size), &old, |b, old| {
+            b.iter(|| {
+                delta
+                    .apply(old, signature.block_size, new.len() as u64)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Merging concurrent edits to different parts of a long document
+///
+/// 4000 lines is about the largest document `merge_text` accepts.
+fn text_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge_text");
+    for lines in [1000, 4000] {
+        let base: Vec<String> = (0..lines)
+            .map(|i| format!("line {} of the note", i))
+            .collect();
+        let edit = |every: usize, tag: &str| {
+            base.iter()
+                .enumerate()
+                .map(|(i, line)| match i % every {
+                    0 => format!("{} ({})\n", line, tag),
+                    _ => format!("{}\n", line),
+                })
+                .collect::<String>()
+        };
+        let (local, remote) = (edit(7, "local"), edit(11, "remote"));
+        let base: String = base.iter().map(|line| format!("{}\n", line)).collect();
+        group.throughput(Throughput::Elements(lines as u64));
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| merge_text(&base, &local, &remote).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, delta, text_merge);
+criterion_main!(benches);
@@ -0,0 +1,258 @@
+//! Per-device permissions
+//!
+//! A peer's `Permissions` limit what it can read from this device and
+//! whether its changes are accepted. Writes are enforced when pulling from
+//! the peer: a read-only peer's artifacts are never applied, and a peer
+//! scoped to some collections can only update artifacts in them. Reads are
+//! enforced by serving the peer through `SyncEngine::scoped`, which hides
+//! everything outside its collections.
+
+use std::sync::Arc;
+
+use nomade_crypto::Permissions;
+use nomade_storage::{content_hash, Artifact, HashTree};
+
+use crate::{
+    BoxFuture, Changes, Delta, ManifestEntry, RemoteArtifact, Result, Signature, SyncCursor,
+    SyncEngine, SyncError, SyncPeer,
+};
+
+impl SyncEngine {
+    /// Permissions granted to a peer
+    pub fn permissions(&self, peer_id: &str) -> Permissions {
+        self.permissions
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace the permissions granted to a peer
+    ///
+    /// A change forgets the peer's cursor, so artifacts it was not allowed
+    /// to write before are pulled on the next sync.
+    pub fn set_permissions(&self, peer_id: &str, permissions: Permissions) {
+        let previous = self
+            .permissions
+            .lock()
+            .unwrap()
+            .insert(peer_id.to_string(), permissions.clone());
+        if previous.is_some_and(|previous| previous != permissions) {
+            self.reset_cursor(peer_id);
+        }
+    }
+
+    /// Whether an artifact pulled from `peer_id` may be applied locally
+    pub(crate) fn accepts_from(&self, peer_id: &str, artifact: &Artifact) -> bool {
+        let permissions = self.permissions(peer_id);
+        permissions.can_write() && permissions.can_access(artifact.collection.as_deref())
+    }
+
+    /// View of this engine to serve to `peer_id`, limited by its permissions
+    pub fn scoped(self: &Arc<Self>, peer_id: &str) -> Arc<dyn SyncPeer> {
+        Arc::new(ScopedPeer {
+            engine: self.clone(),
+            permissions: self.permissions(peer_id),
+        })
+    }
+}
+
+/// Local engine as seen by a peer with restricted permissions
+struct ScopedPeer {
+    engine: Arc<SyncEngine>,
+    permissions: Permissions,
+}
+
+impl ScopedPeer {
+    fn visible(&self, artifact: &Artifact) -> bool {
+        self.permissions.can_access(artifact.collection.as_deref())
+    }
+
+    /// Cursor scope: cursors issued for other collections are not reused
+    fn scope(&self) -> String {
+        match &self.permissions.collections {
+            None => String::new(),
+            Some(collections) => content_hash(
+                &serde_json::to_vec(collections).expect("collection names always serialize"),
+            ),
+        }
+    }
+
+    /// Fail unless `content_hash` belongs to an artifact the peer can see
+    fn check_content(&self, content_hash: &str) -> Result<()> {
+        if self.permissions.collections.is_none()
+            || self
+                .engine
+                .store
+                .list()?
+                .iter()
+                .any(|a| a.content_hash == content_hash && self.visible(a))
+        {
+            return Ok(());
+        }
+        Err(SyncError::NotFound(content_hash.to_string()))
+    }
+}
+
+impl SyncPeer for ScopedPeer {
+    fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+        Box::pin(async move {
+            let mut manifest: Vec<ManifestEntry> = self
+                .engine
+                .store
+                .list()?
+                .iter()
+                .filter(|a| self.visible(a) && self.engine.serves(a))
+                .map(ManifestEntry::from)
+                .collect();
+            manifest.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(manifest)
+        })
+    }
+
+    fn changes<'a>(&'a self, cursor: Option<&'a SyncCursor>) -> BoxFuture<'a, Result<Changes>> {
+        Box::pin(async move {
+            self.engine
+                .changes_since(cursor, &self.scope(), |a| self.visible(a))
+        })
+    }
+
+    fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+        Box::pin(async move {
+            let remote = self.engine.fetch_artifact(id).await?;
+            if !self.visible(&remote.artifact) {
+                // Hidden artifacts look missing rather than forbidden
+                return Err(SyncError::NotFound(id.to_string()));
+            }
+            Ok(remote)
+        })
+    }
+
+    fn fetch_chunk<'a>(
+        &'a self,
+        content_hash: &'a str,
+        index: u32,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            self.check_content(content_hash)?;
+            self.engine.fetch_chunk(content_hash, index).await
+        })
+    }
+
+    fn fetch_hash_tree<'a>(&'a self, content_hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+        Box::pin(async move {
+            self.check_content(content_hash)?;
+            self.engine.fetch_hash_tree(content_hash).await
+        })
+    }
+
+    fn fetch_delta<'a>(
+        &'a self,
+        content_hash: &'a str,
+        signature: &'a Signature,
+    ) -> BoxFuture<'a, Result<Delta>> {
+        Box::pin(async move {
+            self.check_content(content_hash)?;
+            self.engine.serve_delta(content_hash, signature)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SyncState, CHUNK_SIZE};
+    use nomade_events::EventStream;
+    use nomade_storage::{content_hash, InMemoryStore};
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    fn add(engine: &SyncEngine, id: &str, collection: Option<&str>) -> String {
+        let content = format!("{} ", id).repeat(CHUNK_SIZE / 4);
+        let hash = content_hash(content.as_bytes());
+        engine
+            .content()
+            .put_content(&hash, content.as_bytes())
+            .unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: id.into(),
+                modified_at: 1,
+                content_hash: hash.clone(),
+                collection: collection.map(Into::into),
+                ..Default::default()
+            })
+            .unwrap();
+        hash
+    }
+
+    #[tokio::test]
+    async fn test_scoped_peer_hides_other_collections() {
+        let laptop = engine();
+        add(&laptop, "work-notes", Some("work"));
+        let diary = add(&laptop, "diary", Some("personal"));
+        add(&laptop, "loose", None);
+        laptop.set_permissions(
+            "phone",
+            Permissions::read_only().with_collections(["work".to_string()]),
+        );
+
+        let served = laptop.scoped("phone");
+        let ids: Vec<_> = served
+            .manifest()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec!["work-notes"]);
+        assert!(matches!(
+            served.fetch_artifact("diary").await,
+            Err(SyncError::NotFound(_))
+        ));
+        assert!(served.fetch_chunk(&diary, 0).await.is_err());
+        assert!(served.fetch_hash_tree(&diary).await.is_err());
+        assert_eq!(laptop.scoped("tablet").manifest().await.unwrap().len(), 3);
+
+        // The phone only ever receives the collection it was granted
+        let phone = engine();
+        let progress = phone.start_sync("laptop", served).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert_eq!(phone.manifest().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_changes_from_read_only_peer_are_ignored() {
+        let laptop = engine();
+        let phone = engine();
+        add(&phone, "work-notes", Some("work"));
+        add(&phone, "diary", Some("personal"));
+
+        laptop.set_permissions("phone", Permissions::read_only());
+        let progress = laptop
+            .start_sync("phone", phone.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert!(laptop.manifest().unwrap().is_empty());
+
+        laptop.set_permissions(
+            "phone",
+            Permissions::full().with_collections(["work".to_string()]),
+        );
+        laptop.start_sync("phone", phone).unwrap().wait().await;
+        let ids: Vec<_> = laptop
+            .manifest()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(ids, vec!["work-notes"]);
+    }
+}
@@ -0,0 +1,264 @@
+//! Budgeted sync for limited background execution time
+//!
+//! iOS and Android grant apps a short window of background time. A
+//! budgeted sync pulls from a peer like any session but stops once its
+//! time or byte budget runs out. Metadata and small artifacts go first, so
+//! a short window lands as many changes as it can, and each artifact is
+//! applied as soon as its content arrives. An interrupted download keeps
+//! its verified chunks, so the next sync resumes rather than restarts. The
+//! report lists what is left.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, SyncEngine, SyncError, SyncPeer};
+
+/// Limits for a budgeted sync; `None` means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncBudget {
+    /// Wall-clock time in milliseconds
+    pub time_ms: Option<u64>,
+    /// Content bytes to fetch
+    pub bytes: Option<u64>,
+}
+
+impl SyncBudget {
+    /// Budget limited to `time`
+    pub fn time(time: Duration) -> Self {
+        Self {
+            time_ms: Some(time.as_millis() as u64),
+            bytes: None,
+        }
+    }
+
+    /// Also limit the content bytes fetched
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+}
+
+/// How a budgeted sync ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetOutcome {
+    Completed,
+    /// Time ran out; see `remaining`
+    OutOfTime,
+    /// The next artifact would exceed the byte budget; see `remaining`
+    OutOfBytes,
+    Cancelled,
+    /// See `error`
+    Failed,
+}
+
+/// Artifact planned but not synced yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingArtifact {
+    pub id: String,
+    /// Content bytes still to fetch
+    pub bytes: u64,
+}
+
+/// Result of a budgeted sync
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BudgetReport {
+    pub peer_id: String,
+    pub outcome: BudgetOutcome,
+    pub artifacts_synced: usize,
+    pub bytes_transferred: u64,
+    /// In the order the next sync would fetch them; empty if the budget
+    /// ran out before the plan was known
+    pub remaining: Vec<PendingArtifact>,
+    pub remaining_bytes: u64,
+    pub error: Option<String>,
+}
+
+impl SyncEngine {
+    /// Pull changes from a peer until done or `budget` runs out
+    ///
+    /// Runs as a regular session, so `cancel_sync` and `sync_progress`
+    /// work on it while it runs.
+    pub async fn sync_within(
+        &self,
+        peer_id: impl Into<String>,
+        peer: &dyn SyncPeer,
+        budget: SyncBudget,
+    ) -> Result<BudgetReport> {
+        let (id, cancel, tx) = self.register_session(peer_id.into())?;
+        // Separate token, so running out of time is told apart from cancelling
+        let out_of_time = cancel.child_token();
+        let timer = budget.time_ms.map(|ms| {
+            let out_of_time = out_of_time.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                out_of_time.cancel();
+            })
+        });
+        let mut remaining = VecDeque::new();
+        let max_bytes = budget.bytes.unwrap_or(u64::MAX);
+        let result = self
+            .run_session(peer, &tx, &out_of_time, max_bytes, &mut remaining)
+            .await;
+        if let Some(timer) = timer {
+            timer.abort();
+        }
+        self.finish_session(id, &tx, &result);
+
+        let outcome = match &result {
+            Ok(()) if remaining.is_empty() => BudgetOutcome::Completed,
+            Ok(()) => BudgetOutcome::OutOfBytes,
+            Err(SyncError::Cancelled) if !cancel.is_cancelled() => BudgetOutcome::OutOfTime,
+            Err(SyncError::Cancelled) => BudgetOutcome::Cancelled,
+            Err(_) => BudgetOutcome::Failed,
+        };
+        let remaining: Vec<PendingArtifact> = remaining
+            .iter()
+            .map(|remote| PendingArtifact {
+                id: remote.artifact.id.clone(),
+                bytes: self.fetch_cost(remote),
+            })
+            .collect();
+        let progress = tx.borrow().clone();
+        Ok(BudgetReport {
+            peer_id: progress.peer_id,
+            outcome,
+            artifacts_synced: progress.artifacts_synced,
+            bytes_transferred: progress.bytes_transferred,
+            remaining_bytes: remaining.iter().map(|p| p.bytes).sum(),
+            remaining,
+            error: progress.error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHUNK_SIZE;
+    use nomade_events::EventStream;
+    use nomade_storage::{content_hash, Artifact, InMemoryStore};
+    use std::sync::Arc;
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    fn add_artifact(engine: &SyncEngine, id: &str, content: &[u8]) {
+        let hash = content_hash(content);
+        engine.content().put_content(&hash, content).unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: id.into(),
+                title: id.into(),
+                created_at: 0,
+                modified_at: 1,
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_byte_budget_syncs_small_artifacts_first() {
+        let laptop = engine();
+        let phone = engine();
+        add_artifact(&phone, "a-large", &vec![1u8; CHUNK_SIZE * 2]);
+        add_artifact(&phone, "b-small", b"note");
+        // Content the laptop already has costs only the metadata
+        add_artifact(&phone, "c-copy", b"shared");
+        add_artifact(&laptop, "other", b"shared");
+
+        let budget = SyncBudget::default().with_bytes(100);
+        let report = laptop
+            .sync_within("phone", phone.as_ref(), budget)
+            .await
+            .unwrap();
+        assert_eq!(report.outcome, BudgetOutcome::OutOfBytes);
+        assert_eq!(report.artifacts_synced, 2);
+        assert!(laptop.store().get("b-small").unwrap().is_some());
+        assert!(laptop.store().get("c-copy").unwrap().is_some());
+        assert_eq!(
+            report.remaining,
+            vec![PendingArtifact {
+                id: "a-large".into(),
+                bytes: CHUNK_SIZE as u64 * 2,
+            }]
+        );
+        assert_eq!(report.remaining_bytes, CHUNK_SIZE as u64 * 2);
+
+        let report = laptop
+            .sync_within("phone", phone.as_ref(), SyncBudget::default())
+            .await
+            .unwrap();
+        assert_eq!(report.outcome, BudgetOutcome::Completed);
+        assert!(report.remaining.is_empty());
+        assert!(laptop.active_sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_time_budget_keeps_partial_download() {
+        use crate::{BoxFuture, ManifestEntry, RemoteArtifact};
+        use nomade_storage::HashTree;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        /// Serves `limit` chunks, then stalls
+        struct SlowPeer {
+            inner: Arc<SyncEngine>,
+            served: AtomicU32,
+            limit: u32,
+        }
+
+        impl SyncPeer for SlowPeer {
+            fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+                SyncPeer::manifest(self.inner.as_ref())
+            }
+
+            fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+                self.inner.fetch_artifact(id)
+            }
+
+            fn fetch_chunk<'a>(
+                &'a self,
+                hash: &'a str,
+                index: u32,
+            ) -> BoxFuture<'a, Result<Vec<u8>>> {
+                if self.served.fetch_add(1, Ordering::SeqCst) >= self.limit {
+                    return Box::pin(std::future::pending());
+                }
+                self.inner.fetch_chunk(hash, index)
+            }
+
+            fn fetch_hash_tree<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+                self.inner.fetch_hash_tree(hash)
+            }
+        }
+
+        let laptop = engine();
+        let phone = engine();
+        add_artifact(&phone, "video", &vec![5u8; CHUNK_SIZE * 3]);
+        let slow = SlowPeer {
+            inner: phone.clone(),
+            served: AtomicU32::new(0),
+            limit: 1,
+        };
+
+        let budget = SyncBudget::time(Duration::from_millis(200));
+        let report = laptop.sync_within("phone", &slow, budget).await.unwrap();
+        assert_eq!(report.outcome, BudgetOutcome::OutOfTime);
+        assert_eq!(report.artifacts_synced, 0);
+        // The chunk received is kept for the next sync
+        assert_eq!(report.remaining_bytes, CHUNK_SIZE as u64 * 2);
+
+        let report = laptop
+            .sync_within("phone", phone.as_ref(), budget)
+            .await
+            .unwrap();
+        assert_eq!(report.outcome, BudgetOutcome::Completed);
+        assert_eq!(report.bytes_transferred, CHUNK_SIZE as u64 * 3);
+    }
+}
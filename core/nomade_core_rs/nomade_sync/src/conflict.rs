@@ -0,0 +1,658 @@
+//! Inbox of sync conflicts waiting for the user
+//!
+//! The inbox remembers, for each artifact, the version last agreed with a
+//! peer (the base). When a pulled version and the local one both differ
+//! from the base, both devices edited the artifact since they last synced
+//! and neither edit can win on its timestamp alone. The remote version's
+//! content is fetched and, for text, merged three-way against the base.
+//! A clean merge is stored as the resolution right away; otherwise the
+//! local version is kept and a `ConflictRecord`, carrying the merge with
+//! conflict markers, waits here until the user picks one side or supplies
+//! a merge.
+//!
+//! A resolution is stored as a new version, newer than both sides, and
+//! announced with a forwardable `ConflictResolved` event naming the content
+//! it supersedes. Peers receiving it accept the resolved version over
+//! their own edit instead of raising the conflict again. Artifacts that
+//! were never synced have no base and fall back to last-writer-wins.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nomade_crypto::fs::write_durable;
+use nomade_events::Event;
+use nomade_storage::{content_hash, Artifact};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{merge_text, ManifestEntry, MergeOutcome, Result, SyncEngine, SyncError};
+
+/// How the two versions differ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffSummary {
+    /// Artifact fields that differ, e.g. "title" or "content"
+    pub fields: Vec<String>,
+    pub local_bytes: u64,
+    pub remote_bytes: u64,
+    /// Lines only in the local version, when both are text
+    pub local_lines: Option<usize>,
+    /// Lines only in the remote version, when both are text
+    pub remote_lines: Option<usize>,
+}
+
+/// Concurrent edits of one artifact awaiting a choice
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConflictRecord {
+    pub artifact_id: String,
+    /// Peer the remote version came from
+    pub peer_id: String,
+    /// Version last agreed with peers, which both sides edited
+    pub base: ManifestEntry,
+    pub local: Artifact,
+    pub remote: Artifact,
+    pub diff: DiffSummary,
+    /// Three-way merge of text versions, with conflict markers
+    #[serde(default)]
+    pub merged: Option<String>,
+    /// Unix time in seconds
+    pub detected_at: u64,
+}
+
+/// The user's choice for a conflict
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "choice", rename_all = "snake_case")]
+pub enum Resolution {
+    /// Keep this device's version
+    Local,
+    /// Take the peer's version
+    Remote,
+    /// Store merged text with the local version's metadata
+    Merged { content: String },
+}
+
+/// Resolved version and the content it replaces
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Resolved {
+    version: ManifestEntry,
+    superseded: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Version last agreed with a peer, by artifact ID
+    bases: BTreeMap<String, ManifestEntry>,
+    conflicts: BTreeMap<String, ConflictRecord>,
+    /// Latest resolution of each artifact, local or announced by a peer
+    resolutions: BTreeMap<String, Resolved>,
+}
+
+/// Persistent conflict inbox
+#[derive(Debug, Default)]
+pub struct ConflictInbox {
+    state: Mutex<State>,
+    path: Option<PathBuf>,
+}
+
+impl ConflictInbox {
+    /// Create an in-memory inbox
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open an inbox persisted at `path`, creating it if missing
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            state: Mutex::new(state),
+            path: Some(path),
+        })
+    }
+
+    /// Pending conflicts, sorted by artifact ID
+    pub fn list(&self) -> Vec<ConflictRecord> {
+        self.state
+            .lock()
+            .unwrap()
+            .conflicts
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Pending conflict of one artifact
+    pub fn get(&self, artifact_id: &str) -> Option<ConflictRecord> {
+        self.state
+            .lock()
+            .unwrap()
+            .conflicts
+            .get(artifact_id)
+            .cloned()
+    }
+
+    /// Version last agreed with a peer
+    pub fn base(&self, artifact_id: &str) -> Option<ManifestEntry> {
+        self.state.lock().unwrap().bases.get(artifact_id).cloned()
+    }
+
+    /// Content to keep although no artifact refers to it: bases, which
+    /// later merges diff against, and both sides of pending conflicts
+    pub fn retained_content(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut hashes: Vec<String> = state
+            .bases
+            .values()
+            .map(|base| base.content_hash.clone())
+            .chain(state.conflicts.values().flat_map(|record| {
+                [
+                    record.local.content_hash.clone(),
+                    record.remote.content_hash.clone(),
+                ]
+            }))
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+
+    /// Record versions both sides hold as agreed
+    pub(crate) fn agree<'a>(
+        &self,
+        entries: impl IntoIterator<Item = &'a ManifestEntry>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut changed = false;
+        for entry in entries {
+            if state.bases.get(&entry.id) != Some(entry) {
+                state.bases.insert(entry.id.clone(), entry.clone());
+                changed = true;
+            }
+        }
+        if changed {
+            self.save(&state)?;
+        }
+        Ok(())
+    }
+
+    /// Whether both versions were edited since the base
+    pub(crate) fn diverged(&self, ours: &ManifestEntry, theirs: &ManifestEntry) -> bool {
+        if ours == theirs {
+            return false;
+        }
+        let state = self.state.lock().unwrap();
+        if let Some(resolved) = state.resolutions.get(&ours.id) {
+            let replaces = |version: &ManifestEntry, other: &ManifestEntry| {
+                resolved.version == *version && resolved.superseded.contains(&other.content_hash)
+            };
+            if replaces(theirs, ours) || replaces(ours, theirs) {
+                return false;
+            }
+        }
+        match state.bases.get(&ours.id) {
+            Some(base) => base != ours && base != theirs,
+            None => false,
+        }
+    }
+
+    /// Whether this remote version is already waiting in the inbox
+    pub(crate) fn is_pending(&self, theirs: &ManifestEntry) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .conflicts
+            .get(&theirs.id)
+            .is_some_and(|record| ManifestEntry::from(&record.remote) == *theirs)
+    }
+
+    fn insert(&self, record: ConflictRecord) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.conflicts.insert(record.artifact_id.clone(), record);
+        self.save(&state)?;
+        Ok(())
+    }
+
+    /// Store a resolution and make its version the new base
+    fn resolve(&self, version: &ManifestEntry, superseded: Vec<String>) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.conflicts.remove(&version.id);
+        state.bases.insert(version.id.clone(), version.clone());
+        state.resolutions.insert(
+            version.id.clone(),
+            Resolved {
+                version: version.clone(),
+                superseded,
+            },
+        );
+        self.save(&state)?;
+        Ok(())
+    }
+
+    /// Take in a resolution announced by a peer; other events are ignored
+    ///
+    /// A pending conflict between versions the resolution supersedes is
+    /// dropped, since the resolved version replaces both on the next sync.
+    pub fn observe(&self, event: &Event) -> Result<()> {
+        let Event::Remote { event, .. } = event else {
+            return Ok(());
+        };
+        let Event::ConflictResolved {
+            artifact_id,
+            content_hash,
+            modified_at,
+            superseded,
+        } = event.as_ref()
+        else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().unwrap();
+        let settled = state.conflicts.get(artifact_id).is_some_and(|record| {
+            superseded.contains(&record.local.content_hash)
+                && superseded.contains(&record.remote.content_hash)
+        });
+        if settled {
+            state.conflicts.remove(artifact_id);
+        }
+        state.resolutions.insert(
+            artifact_id.clone(),
+            Resolved {
+                version: ManifestEntry {
+                    id: artifact_id.clone(),
+                    modified_at: *modified_at,
+                    content_hash: content_hash.clone(),
+                },
+                superseded: superseded.clone(),
+            },
+        );
+        self.save(&state)?;
+        Ok(())
+    }
+
+    fn save(&self, state: &State) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(state)?)?;
+        Ok(())
+    }
+}
+
+/// Lines of `a` not matched by a line of `b`
+fn unmatched_lines(a: &str, b: &str) -> usize {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in b.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+    a.lines()
+        .filter(|line| match counts.get_mut(line) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .count()
+}
+
+impl SyncEngine {
+    /// Keep conflicts and agreed versions in `inbox`
+    pub fn with_conflicts(mut self, inbox: ConflictInbox) -> Self {
+        self.conflicts = inbox;
+        self
+    }
+
+    /// Conflicts waiting for the user
+    pub fn conflicts(&self) -> &ConflictInbox {
+        &self.conflicts
+    }
+
+    /// Record a conflict if both versions were edited since the base
+    ///
+    /// Returns `true` if `theirs` must not be applied.
+    pub(crate) fn check_conflict(
+        &self,
+        peer_id: &str,
+        ours: &Artifact,
+        theirs: &Artifact,
+    ) -> Result<bool> {
+        let (local, remote) = (ManifestEntry::from(ours), ManifestEntry::from(theirs));
+        if !self.conflicts.diverged(&local, &remote) {
+            return Ok(false);
+        }
+        if self.conflicts.is_pending(&remote) {
+            return Ok(true);
+        }
+        let base = self
+            .conflicts
+            .base(&ours.id)
+            .expect("diverged versions have a base");
+        let merge = self.merge(&base, ours, theirs)?;
+        if let Some(outcome) = merge.as_ref().filter(|m| m.is_clean()) {
+            // Metadata has no ancestor to merge against, so it must agree
+            let metadata = |a: &Artifact| Artifact {
+                modified_at: 0,
+                content_hash: String::new(),
+                ..a.clone()
+            };
+            if metadata(ours) == metadata(theirs) {
+                // A resolution that already includes our edit
+                if content_hash(outcome.text.as_bytes()) == theirs.content_hash {
+                    return Ok(false);
+                }
+                let merged = self.merged(ours, &outcome.text)?;
+                self.settle(ours, theirs, merged)?;
+                tracing::info!("Merged concurrent edits of {} from {}", ours.id, peer_id);
+                return Ok(true);
+            }
+        }
+        self.conflicts.insert(ConflictRecord {
+            artifact_id: ours.id.clone(),
+            peer_id: peer_id.to_string(),
+            base,
+            local: ours.clone(),
+            remote: theirs.clone(),
+            diff: self.diff(ours, theirs)?,
+            merged: merge.map(|outcome| outcome.text),
+            detected_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })?;
+        tracing::info!("Conflicting edits of {} from {}", ours.id, peer_id);
+        self.events.publish(Event::ConflictDetected {
+            artifact_id: ours.id.clone(),
+        });
+        Ok(true)
+    }
+
+    fn diff(&self, ours: &Artifact, theirs: &Artifact) -> Result<DiffSummary> {
+        let mut fields = Vec::new();
+        let mut differs = |field: &str, same: bool| {
+            if !same {
+                fields.push(field.to_string());
+            }
+        };
+        differs("title", ours.title == theirs.title);
+        differs("content", ours.content_hash == theirs.content_hash);
+        differs("content_type", ours.content_type == theirs.content_type);
+        differs("artifact_type", ours.artifact_type == theirs.artifact_type);
+        differs("collection", ours.collection == theirs.collection);
+        differs("tags", ours.tags == theirs.tags);
+
+        let local = self.content.get_content(&ours.content_hash)?;
+        let remote = self.content.get_content(&theirs.content_hash)?;
+        let size = |content: &Option<Vec<u8>>| content.as_ref().map_or(0, |c| c.len() as u64);
+        let mut diff = DiffSummary {
+            fields,
+            local_bytes: size(&local),
+            remote_bytes: size(&remote),
+            ..Default::default()
+        };
+        let text = |content: &Option<Vec<u8>>| {
+            content
+                .as_deref()
+                .and_then(|c| std::str::from_utf8(c).ok())
+                .map(str::to_owned)
+        };
+        if let (Some(local), Some(remote)) = (text(&local), text(&remote)) {
+            diff.local_lines = Some(unmatched_lines(&local, &remote));
+            diff.remote_lines = Some(unmatched_lines(&remote, &local));
+        }
+        Ok(diff)
+    }
+
+    /// Settle a conflict and announce the choice to peers
+    ///
+    /// The chosen version is stored as a new version, newer than both
+    /// sides, so it replaces them wherever it syncs to.
+    pub fn resolve_conflict(&self, artifact_id: &str, resolution: Resolution) -> Result<Artifact> {
+        self.ensure_running()?;
+        let record = self
+            .conflicts
+            .get(artifact_id)
+            .ok_or_else(|| SyncError::NotFound(artifact_id.to_string()))?;
+        let resolved = match resolution {
+            Resolution::Local => record.local.clone(),
+            Resolution::Remote => record.remote.clone(),
+            Resolution::Merged { content } => self.merged(&record.local, &content)?,
+        };
+        self.settle(&record.local, &record.remote, resolved)
+    }
+
+    /// `local` with merged text as its content
+    fn merged(&self, local: &Artifact, text: &str) -> Result<Artifact> {
+        let hash = content_hash(text.as_bytes());
+        self.content.put_content(&hash, text.as_bytes())?;
+        Ok(Artifact {
+            content_hash: hash,
+            ..local.clone()
+        })
+    }
+
+    /// Store `resolved` as the version replacing `local` and `remote`
+    ///
+    /// Its timestamp is derived from both sides only, so two devices
+    /// merging the same edits cleanly end up with the same version.
+    fn settle(
+        &self,
+        local: &Artifact,
+        remote: &Artifact,
+        mut resolved: Artifact,
+    ) -> Result<Artifact> {
+        let current = self.store.get(&resolved.id)?;
+        resolved.modified_at = local
+            .modified_at
+            .max(remote.modified_at)
+            .max(current.map_or(0, |a| a.modified_at))
+            + 1;
+        self.store.store(&resolved)?;
+
+        let version = ManifestEntry::from(&resolved);
+        let mut superseded = vec![local.content_hash.clone(), remote.content_hash.clone()];
+        superseded.dedup();
+        self.conflicts.resolve(&version, superseded.clone())?;
+        self.events.publish(Event::ConflictResolved {
+            artifact_id: resolved.id.clone(),
+            content_hash: version.content_hash,
+            modified_at: version.modified_at,
+            superseded,
+        });
+        Ok(resolved)
+    }
+
+    /// Three-way merge of text versions against the base content
+    fn merge(
+        &self,
+        base: &ManifestEntry,
+        ours: &Artifact,
+        theirs: &Artifact,
+    ) -> Result<Option<MergeOutcome>> {
+        let mut texts = Vec::with_capacity(3);
+        for hash in [&base.content_hash, &ours.content_hash, &theirs.content_hash] {
+            let Some(content) = self.content.get_content(hash)? else {
+                return Ok(None);
+            };
+            match String::from_utf8(content) {
+                Ok(text) => texts.push(text),
+                Err(_) => return Ok(None),
+            }
+        }
+        Ok(merge_text(&texts[0], &texts[1], &texts[2]))
+    }
+
+    /// Take in resolutions announced by peers until the stream closes
+    pub async fn watch_resolutions(&self) {
+        let mut rx = self.events.subscribe();
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Err(e) = self.conflicts.observe(&event) {
+                        tracing::warn!("Failed to record conflict resolution: {}", e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyncBudget;
+    use nomade_events::EventStream;
+    use nomade_storage::InMemoryStore;
+    use std::sync::Arc;
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    fn edit(engine: &SyncEngine, modified_at: u64, text: &str) {
+        let hash = content_hash(text.as_bytes());
+        engine
+            .content()
+            .put_content(&hash, text.as_bytes())
+            .unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: "note".into(),
+                title: "Note".into(),
+                modified_at,
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    async fn pull(to: &SyncEngine, from: &Arc<SyncEngine>, peer_id: &str) {
+        to.sync_within(peer_id, from.as_ref(), SyncBudget::default())
+            .await
+            .unwrap();
+    }
+
+    fn text(engine: &SyncEngine) -> String {
+        let hash = engine.store().get("note").unwrap().unwrap().content_hash;
+        String::from_utf8(engine.content().get_content(&hash).unwrap().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_edits_converge_after_resolution() {
+        let (laptop, phone) = (engine(), engine());
+        edit(&phone, 1, "hello\n");
+        pull(&laptop, &phone, "phone").await;
+        pull(&phone, &laptop, "laptop").await;
+        assert!(phone.conflicts().base("note").is_some());
+
+        edit(&laptop, 2, "hello\nfrom laptop\n");
+        edit(&phone, 3, "hello\nfrom phone\n");
+        let mut events = laptop.events.subscribe();
+        pull(&laptop, &phone, "phone").await;
+        // The newer edit does not silently win
+        assert_eq!(text(&laptop), "hello\nfrom laptop\n");
+        let record = laptop.conflicts().get("note").unwrap();
+        assert_eq!(record.peer_id, "phone");
+        assert_eq!(record.diff.fields, ["content"]);
+        assert_eq!(record.diff.local_lines, Some(1));
+        assert_eq!(record.diff.remote_lines, Some(1));
+        assert_eq!(
+            record.merged.unwrap(),
+            "hello\n<<<<<<< local\nfrom laptop\n=======\nfrom phone\n>>>>>>> remote\n"
+        );
+        loop {
+            if let Event::ConflictDetected { artifact_id } = events.recv().await.unwrap() {
+                assert_eq!(artifact_id, "note");
+                break;
+            }
+        }
+        // The older side is fetched too, so the phone sees the conflict
+        pull(&phone, &laptop, "laptop").await;
+        assert_eq!(phone.conflicts().list().len(), 1);
+
+        let merged = "hello\nfrom laptop\nfrom phone\n";
+        let resolved = laptop
+            .resolve_conflict(
+                "note",
+                Resolution::Merged {
+                    content: merged.into(),
+                },
+            )
+            .unwrap();
+        assert_eq!(resolved.modified_at, 4);
+        assert!(laptop.conflicts().list().is_empty());
+        let announcement = loop {
+            let event = events.recv().await.unwrap();
+            if matches!(event, Event::ConflictResolved { .. }) {
+                break event;
+            }
+        };
+        phone
+            .conflicts()
+            .observe(&Event::Remote {
+                origin: "laptop".into(),
+                event: Box::new(announcement),
+            })
+            .unwrap();
+        assert!(phone.conflicts().list().is_empty());
+
+        pull(&phone, &laptop, "laptop").await;
+        pull(&laptop, &phone, "phone").await;
+        assert_eq!(text(&phone), merged);
+        assert_eq!(text(&laptop), merged);
+        assert!(laptop.conflicts().list().is_empty());
+        assert!(phone.conflicts().list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_one_sided_edit_fast_forwards() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("conflicts.json");
+        let store = Arc::new(InMemoryStore::new());
+        let laptop = Arc::new(
+            SyncEngine::new(store.clone(), store, EventStream::new())
+                .with_conflicts(ConflictInbox::open(&path).unwrap()),
+        );
+        let phone = engine();
+        edit(&phone, 5, "draft");
+        pull(&laptop, &phone, "phone").await;
+
+        edit(&phone, 6, "final");
+        pull(&laptop, &phone, "phone").await;
+        assert_eq!(text(&laptop), "final");
+        assert!(laptop.conflicts().list().is_empty());
+        let base = ConflictInbox::open(&path).unwrap().base("note").unwrap();
+        assert_eq!(base.content_hash, content_hash(b"final"));
+    }
+
+    #[tokio::test]
+    async fn test_text_edits_to_different_lines_merge() {
+        let (laptop, phone) = (engine(), engine());
+        edit(&phone, 1, "one\ntwo\nthree\n");
+        pull(&laptop, &phone, "phone").await;
+        pull(&phone, &laptop, "laptop").await;
+
+        edit(&laptop, 2, "ONE\ntwo\nthree\n");
+        edit(&phone, 3, "one\ntwo\nTHREE\n");
+        pull(&laptop, &phone, "phone").await;
+        assert!(laptop.conflicts().list().is_empty());
+        assert_eq!(text(&laptop), "ONE\ntwo\nTHREE\n");
+        // The phone finds its edit already merged and takes that version
+        pull(&phone, &laptop, "laptop").await;
+        assert!(phone.conflicts().list().is_empty());
+        assert_eq!(
+            phone.store().get("note").unwrap(),
+            laptop.store().get("note").unwrap()
+        );
+        // Both sides are superseded, and the base is kept for later merges
+        let retained = laptop.conflicts().retained_content();
+        assert_eq!(retained, [content_hash(b"ONE\ntwo\nTHREE\n")]);
+    }
+}
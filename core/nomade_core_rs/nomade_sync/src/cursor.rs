@@ -0,0 +1,455 @@
+//! Per-peer sync cursors for incremental catch-up
+//!
+//! Every committed change to the local artifact store gets the next
+//! sequence number in the `ChangeLog`, fed by the store's change feed. A
+//! puller keeps the `SyncCursor` a peer last gave it and, once a session
+//! has applied everything it planned, hands it back next time to receive
+//! only the manifest entries changed since. The peer answers with its full
+//! manifest instead, and the puller reconciles everything as before, when
+//! the cursor is from another epoch (the log was lost, reset or not saved
+//! cleanly), older than the changes still kept, or issued for different
+//! permissions.
+//!
+//! The log is marked unclean while open and only saved as clean on
+//! shutdown, so a crash starts a new epoch rather than missing changes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+use nomade_crypto::fs::write_durable;
+use nomade_storage::StoreChange;
+use serde::{Deserialize, Serialize};
+
+use crate::{ManifestEntry, Result, SyncEngine};
+
+/// Changes kept in the log; older cursors get the full manifest
+pub const MAX_TRACKED_CHANGES: usize = 100_000;
+
+/// Position in a peer's change log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncCursor {
+    epoch: String,
+    seq: u64,
+    /// Digest of the permissions the entries were filtered by
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    scope: String,
+}
+
+/// Manifest entries sent in answer to a cursor
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+    pub entries: Vec<ManifestEntry>,
+    /// Cursor to send next time, if the peer keeps a change log
+    #[serde(default)]
+    pub cursor: Option<SyncCursor>,
+    /// Whether `entries` is the full manifest
+    pub full: bool,
+}
+
+impl Changes {
+    /// Full manifest without a cursor
+    pub fn full(entries: Vec<ManifestEntry>) -> Self {
+        Self {
+            entries,
+            cursor: None,
+            full: true,
+        }
+    }
+}
+
+/// Called with a peer's new cursor, or `None` when it was reset
+pub type CursorSink = Arc<dyn Fn(&str, Option<&SyncCursor>) + Send + Sync>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogState {
+    epoch: String,
+    seq: u64,
+    /// Cursors below this sequence are answered in full
+    floor: u64,
+    /// Sequence of each artifact's latest change
+    changes: HashMap<String, u64>,
+    /// Whether every change was saved
+    clean: bool,
+}
+
+impl LogState {
+    fn fresh() -> Self {
+        let epoch = nomade_crypto::generate_key()[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Self {
+            epoch,
+            seq: 0,
+            floor: 0,
+            changes: HashMap::new(),
+            clean: false,
+        }
+    }
+
+    fn record(&mut self, id: String) {
+        self.seq += 1;
+        self.changes.insert(id, self.seq);
+        if self.changes.len() > MAX_TRACKED_CHANGES {
+            // Drop the oldest quarter so this stays amortized
+            let mut seqs: Vec<u64> = self.changes.values().copied().collect();
+            let (_, floor, _) = seqs.select_nth_unstable(MAX_TRACKED_CHANGES / 4);
+            self.floor = *floor;
+            let floor = self.floor;
+            self.changes.retain(|_, seq| *seq > floor);
+        }
+    }
+}
+
+/// Sequence of local artifact changes
+pub struct ChangeLog {
+    state: Mutex<LogState>,
+    feed: Mutex<Option<mpsc::Receiver<StoreChange>>>,
+    path: Option<PathBuf>,
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeLog {
+    /// Create an in-memory log with a new epoch
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(LogState::fresh()),
+            feed: Mutex::new(None),
+            path: None,
+        }
+    }
+
+    /// Open a log persisted at `path`
+    ///
+    /// A missing log, or one not saved cleanly, starts a new epoch.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<LogState>(&bytes) {
+                Ok(state) if state.clean => state,
+                Ok(_) => {
+                    tracing::info!("Change log was not saved cleanly; peers will reconcile");
+                    LogState::fresh()
+                }
+                Err(e) => {
+                    tracing::warn!("Discarding unreadable change log: {}", e);
+                    LogState::fresh()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => LogState::fresh(),
+            Err(e) => return Err(e.into()),
+        };
+        let log = Self {
+            state: Mutex::new(LogState {
+                clean: false,
+                ..state
+            }),
+            feed: Mutex::new(None),
+            path: Some(path),
+        };
+        log.save(&log.state.lock().unwrap())?;
+        Ok(log)
+    }
+
+    /// Track the changes a `WatchedStore` reports on `feed`
+    ///
+    /// Without a feed the log cannot tell what changed, and peers always
+    /// get the full manifest.
+    pub fn with_feed(self, feed: mpsc::Receiver<StoreChange>) -> Self {
+        *self.feed.lock().unwrap() = Some(feed);
+        self
+    }
+
+    /// Take in the changes reported so far
+    pub fn drain(&self) -> Result<()> {
+        let feed = self.feed.lock().unwrap();
+        let Some(feed) = feed.as_ref() else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut changed = false;
+        while let Ok(change) = feed.try_recv() {
+            state.record(change.id);
+            changed = true;
+        }
+        if changed {
+            self.touch(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Mark an artifact changed, e.g. once its content became available
+    pub(crate) fn record(&self, id: &str) -> Result<()> {
+        self.drain()?;
+        let mut state = self.state.lock().unwrap();
+        state.record(id.to_string());
+        self.touch(&mut state)?;
+        Ok(())
+    }
+
+    /// Save the log as clean, so cursors stay valid across a restart
+    pub fn flush(&self) -> Result<()> {
+        self.drain()?;
+        let mut state = self.state.lock().unwrap();
+        state.clean = true;
+        self.save(&state)?;
+        Ok(())
+    }
+
+    /// Current cursor, and the artifacts changed after `cursor`
+    ///
+    /// Returns `None` without a feed. The IDs are `None` if `cursor`
+    /// cannot be answered incrementally.
+    pub(crate) fn since(
+        &self,
+        cursor: Option<&SyncCursor>,
+        scope: &str,
+    ) -> Result<Option<(SyncCursor, Option<Vec<String>>)>> {
+        if self.feed.lock().unwrap().is_none() {
+            return Ok(None);
+        }
+        self.drain()?;
+        let state = self.state.lock().unwrap();
+        let next = SyncCursor {
+            epoch: state.epoch.clone(),
+            seq: state.seq,
+            scope: scope.to_string(),
+        };
+        let ids = cursor
+            .filter(|c| {
+                c.epoch == state.epoch
+                    && c.scope == scope
+                    && c.seq >= state.floor
+                    && c.seq <= state.seq
+            })
+            .map(|c| {
+                let mut ids: Vec<String> = state
+                    .changes
+                    .iter()
+                    .filter(|(_, seq)| **seq > c.seq)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                ids.sort();
+                ids
+            });
+        Ok(Some((next, ids)))
+    }
+
+    /// Mark the log unclean before the first change after a clean save
+    fn touch(&self, state: &mut LogState) -> anyhow::Result<()> {
+        if state.clean {
+            state.clean = false;
+            self.save(state)?;
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &LogState) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(state)?)?;
+        Ok(())
+    }
+}
+
+impl SyncEngine {
+    /// Use `log` to answer peers' cursors (an untracked in-memory log by
+    /// default)
+    pub fn with_change_log(mut self, log: ChangeLog) -> Self {
+        self.change_log = log;
+        self
+    }
+
+    /// Report each peer's new cursor to `sink`, e.g. to persist it
+    pub fn with_cursor_sink(mut self, sink: CursorSink) -> Self {
+        self.cursor_sink = Some(sink);
+        self
+    }
+
+    /// Local change log
+    pub fn change_log(&self) -> &ChangeLog {
+        &self.change_log
+    }
+
+    /// Cursor last acknowledged from a peer
+    pub fn cursor(&self, peer_id: &str) -> Option<SyncCursor> {
+        self.cursors.lock().unwrap().get(peer_id).cloned()
+    }
+
+    /// Restore a peer's cursor, e.g. after a restart
+    pub fn set_cursor(&self, peer_id: &str, cursor: SyncCursor) {
+        self.cursors
+            .lock()
+            .unwrap()
+            .insert(peer_id.to_string(), cursor);
+    }
+
+    /// Forget a peer's cursor, so the next sync compares full manifests
+    pub fn reset_cursor(&self, peer_id: &str) {
+        if self.cursors.lock().unwrap().remove(peer_id).is_some() {
+            if let Some(sink) = &self.cursor_sink {
+                sink(peer_id, None);
+            }
+        }
+    }
+
+    /// Record that everything up to `cursor` from a peer was applied
+    pub(crate) fn acknowledge(&self, peer_id: &str, cursor: SyncCursor) {
+        if let Some(sink) = &self.cursor_sink {
+            sink(peer_id, Some(&cursor));
+        }
+        self.set_cursor(peer_id, cursor);
+    }
+
+    /// Served entries changed after `cursor`, among those `visible`
+    pub(crate) fn changes_since(
+        &self,
+        cursor: Option<&SyncCursor>,
+        scope: &str,
+        visible: impl Fn(&nomade_storage::Artifact) -> bool,
+    ) -> Result<Changes> {
+        let (next, ids) = match self.change_log.since(cursor, scope)? {
+            Some((next, ids)) => (Some(next), ids),
+            None => (None, None),
+        };
+        let artifacts = match &ids {
+            Some(ids) => {
+                let mut artifacts = Vec::with_capacity(ids.len());
+                for id in ids {
+                    artifacts.extend(self.store.get(id)?);
+                }
+                artifacts
+            }
+            None => self.store.list()?,
+        };
+        let mut entries: Vec<ManifestEntry> = artifacts
+            .iter()
+            .filter(|a| visible(a) && self.serves(a))
+            .map(ManifestEntry::from)
+            .collect();
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(Changes {
+            entries,
+            cursor: next,
+            full: ids.is_none(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SyncPeer, SyncState};
+    use nomade_events::EventStream;
+    use nomade_storage::{content_hash, Artifact, ArtifactStore, InMemoryStore, WatchedStore};
+
+    /// Engine whose change log follows its store
+    fn engine() -> Arc<SyncEngine> {
+        let content = Arc::new(InMemoryStore::new());
+        let store = Arc::new(WatchedStore::new(content.clone()));
+        let log = ChangeLog::new().with_feed(store.watch());
+        Arc::new(SyncEngine::new(store, content, EventStream::new()).with_change_log(log))
+    }
+
+    fn add(engine: &SyncEngine, id: &str, text: &str, modified_at: u64) {
+        let hash = content_hash(text.as_bytes());
+        engine
+            .content()
+            .put_content(&hash, text.as_bytes())
+            .unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: id.into(),
+                modified_at,
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_sends_only_changes() {
+        let (laptop, phone) = (engine(), engine());
+        add(&phone, "a", "one", 1);
+        add(&phone, "b", "two", 1);
+
+        let first = phone.changes(None).await.unwrap();
+        assert!(first.full);
+        assert_eq!(first.entries.len(), 2);
+        let synced = laptop
+            .start_sync("phone", phone.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(synced.state, SyncState::Completed);
+        let cursor = laptop.cursor("phone").unwrap();
+
+        add(&phone, "b", "two, edited", 2);
+        let changes = phone.changes(Some(&cursor)).await.unwrap();
+        assert!(!changes.full);
+        assert_eq!(
+            changes.entries,
+            [ManifestEntry::from(
+                &phone.store().get("b").unwrap().unwrap()
+            )]
+        );
+        laptop
+            .start_sync("phone", phone.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(laptop.manifest().unwrap(), phone.manifest().unwrap());
+        assert!(phone
+            .changes(laptop.cursor("phone").as_ref())
+            .await
+            .unwrap()
+            .entries
+            .is_empty());
+
+        // Another epoch, or a reset, falls back to the full manifest
+        assert!(engine().changes(Some(&cursor)).await.unwrap().full);
+        laptop.reset_cursor("phone");
+        assert!(laptop.cursor("phone").is_none());
+    }
+
+    #[test]
+    fn test_unclean_log_starts_new_epoch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("changes.json");
+        let store = WatchedStore::new(Arc::new(InMemoryStore::new()));
+        let log = ChangeLog::open(&path).unwrap().with_feed(store.watch());
+        store
+            .store(&Artifact {
+                id: "a".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        let (cursor, _) = log.since(None, "").unwrap().unwrap();
+        assert_eq!(cursor.seq, 1);
+
+        // Saved cleanly: the cursor still answers incrementally
+        log.flush().unwrap();
+        let log = ChangeLog::open(&path).unwrap().with_feed(store.watch());
+        let (_, ids) = log.since(Some(&cursor), "").unwrap().unwrap();
+        assert_eq!(ids, Some(vec![]));
+        assert!(log
+            .since(Some(&cursor), "scoped")
+            .unwrap()
+            .unwrap()
+            .1
+            .is_none());
+
+        // Not saved cleanly: a new epoch
+        drop(log);
+        let log = ChangeLog::open(&path).unwrap().with_feed(store.watch());
+        assert!(log.since(Some(&cursor), "").unwrap().unwrap().1.is_none());
+    }
+}
@@ -0,0 +1,444 @@
+//! rsync-style delta transfer of modified content
+//!
+//! Inserting a byte near the start of a large file shifts every chunk
+//! after it, so chunked transfer re-sends the whole file. When the local
+//! store holds an older version of an artifact, the puller sends a
+//! `Signature` of it instead: a weak rolling checksum and a strong hash
+//! per fixed-size block. The peer slides a window over the new content and
+//! answers with a `Delta` of block copies and literal bytes, which the
+//! puller applies to its old version and verifies against the content
+//! hash. Peers that do not support deltas, deltas larger than a frame and
+//! any mismatch fall back to chunk transfer.
+
+use std::collections::HashMap;
+
+use nomade_storage::{content_hash, Artifact};
+use tokio_util::sync::CancellationToken;
+
+use crate::session::cancellable;
+use crate::{RemoteArtifact, Result, SyncEngine, SyncError, SyncPeer, CHUNK_SIZE};
+
+/// Smallest content worth a delta rather than a few chunks
+pub const DELTA_MIN_SIZE: u64 = 4 * CHUNK_SIZE as u64;
+
+/// Bounds of the block size; larger content uses larger blocks so that
+/// signatures stay small
+const MIN_BLOCK_SIZE: usize = 2048;
+const MAX_BLOCKS: usize = 64 * 1024;
+
+const COPY: u8 = 0;
+const LITERAL: u8 = 1;
+
+/// Block checksums of the version a puller already has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub block_size: u32,
+    /// Weak rolling checksum and truncated strong hash of each full block
+    pub blocks: Vec<(u32, u64)>,
+}
+
+/// Rolling checksum of a window, updated in constant time per byte
+#[derive(Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut rolling = Self { a: 0, b: 0, len };
+        for (i, &byte) in window.iter().enumerate() {
+            rolling.a = rolling.a.wrapping_add(byte as u32);
+            rolling.b = rolling
+                .b
+                .wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        rolling
+    }
+
+    fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong(block: &[u8]) -> u64 {
+    let hash = blake3::hash(block);
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().expect("8 bytes"))
+}
+
+impl Signature {
+    /// Signature of `old`
+    pub fn new(old: &[u8]) -> Self {
+        let block_size = old.len().div_ceil(MAX_BLOCKS).max(MIN_BLOCK_SIZE);
+        let blocks = old
+            .chunks_exact(block_size)
+            .map(|block| (Rolling::new(block).digest(), strong(block)))
+            .collect();
+        Self {
+            block_size: block_size as u32,
+            blocks,
+        }
+    }
+
+    /// Encode for the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.blocks.len() * 12);
+        bytes.extend_from_slice(&self.block_size.to_le_bytes());
+        for (weak, strong) in &self.blocks {
+            bytes.extend_from_slice(&weak.to_le_bytes());
+            bytes.extend_from_slice(&strong.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a signature received from a peer
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || SyncError::Peer("Malformed delta signature".into());
+        let (size, rest) = bytes.split_first_chunk::<4>().ok_or_else(invalid)?;
+        let block_size = u32::from_le_bytes(*size);
+        if (block_size as usize) < MIN_BLOCK_SIZE
+            || rest.len() % 12 != 0
+            || rest.len() / 12 > MAX_BLOCKS
+        {
+            return Err(invalid());
+        }
+        let blocks = rest
+            .chunks_exact(12)
+            .filter_map(|block| {
+                let (weak, strong) = block.split_first_chunk::<4>()?;
+                Some((
+                    u32::from_le_bytes(*weak),
+                    u64::from_le_bytes(strong.try_into().ok()?),
+                ))
+            })
+            .collect();
+        Ok(Self { block_size, blocks })
+    }
+}
+
+/// Instructions rebuilding new content from the old version
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Delta {
+    ops: Vec<DeltaOp>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DeltaOp {
+    /// `count` old blocks starting at `block`
+    Copy {
+        block: u32,
+        count: u32,
+    },
+    Literal(Vec<u8>),
+}
+
+impl Delta {
+    /// Delta turning the version `signature` describes into `new`
+    pub fn compute(signature: &Signature, new: &[u8]) -> Self {
+        let size = signature.block_size as usize;
+        let mut index: HashMap<u32, Vec<(u32, u64)>> = HashMap::new();
+        for (block, &(weak, strong)) in signature.blocks.iter().enumerate() {
+            index.entry(weak).or_default().push((block as u32, strong));
+        }
+
+        let mut delta = Delta::default();
+        let mut literal = Vec::new();
+        let mut pos = 0;
+        let mut rolling = (new.len() >= size).then(|| Rolling::new(&new[..size]));
+        while let Some(window) = rolling.as_mut() {
+            let matched = index.get(&window.digest()).and_then(|candidates| {
+                let strong = strong(&new[pos..pos + size]);
+                candidates
+                    .iter()
+                    .find(|(_, candidate)| *candidate == strong)
+                    .map(|(block, _)| *block)
+            });
+            match matched {
+                Some(block) => {
+                    if !literal.is_empty() {
+                        delta
+                            .ops
+                            .push(DeltaOp::Literal(std::mem::take(&mut literal)));
+                    }
+                    delta.push_copy(block);
+                    pos += size;
+                    rolling =
+                        (new.len() - pos >= size).then(|| Rolling::new(&new[pos..pos + size]));
+                }
+                None => {
+                    literal.push(new[pos]);
+                    if pos + size < new.len() {
+                        window.roll(new[pos], new[pos + size]);
+                        pos += 1;
+                    } else {
+                        pos += 1;
+                        rolling = None;
+                    }
+                }
+            }
+        }
+        literal.extend_from_slice(&new[pos..]);
+        if !literal.is_empty() {
+            delta.ops.push(DeltaOp::Literal(literal));
+        }
+        delta
+    }
+
+    fn push_copy(&mut self, block: u32) {
+        if let Some(DeltaOp::Copy {
+            block: first,
+            count,
+        }) = self.ops.last_mut()
+        {
+            if *first + *count == block {
+                *count += 1;
+                return;
+            }
+        }
+        self.ops.push(DeltaOp::Copy { block, count: 1 });
+    }
+
+    /// Bytes sent literally rather than copied
+    pub fn literal_bytes(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Literal(bytes) => bytes.len() as u64,
+                DeltaOp::Copy { .. } => 0,
+            })
+            .sum()
+    }
+
+    /// Rebuild the new content from `old` cut into `block_size` blocks
+    ///
+    /// Fails rather than grow past `max_len` bytes: a delta can copy the
+    /// same blocks over and over.
+    pub fn apply(&self, old: &[u8], block_size: u32, max_len: u64) -> Result<Vec<u8>> {
+        let size = block_size as usize;
+        let mut new = Vec::new();
+        for op in &self.ops {
+            let bytes = match op {
+                DeltaOp::Copy { block, count } => {
+                    let start = (*block as usize).checked_mul(size);
+                    let len = (*count as usize).checked_mul(size);
+                    let end = start
+                        .zip(len)
+                        .and_then(|(start, len)| start.checked_add(len));
+                    start
+                        .zip(end)
+                        .and_then(|(start, end)| old.get(start..end))
+                        .ok_or_else(|| {
+                            SyncError::Peer("Delta copies past the old version".into())
+                        })?
+                }
+                DeltaOp::Literal(bytes) => bytes,
+            };
+            if (new.len() + bytes.len()) as u64 > max_len {
+                return Err(SyncError::Peer(format!(
+                    "Delta rebuilds more than the expected {} bytes",
+                    max_len
+                )));
+            }
+            new.extend_from_slice(bytes);
+        }
+        Ok(new)
+    }
+
+    /// Encode for the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for op in &self.ops {
+            match op {
+                DeltaOp::Copy { block, count } => {
+                    bytes.push(COPY);
+                    bytes.extend_from_slice(&block.to_le_bytes());
+                    bytes.extend_from_slice(&count.to_le_bytes());
+                }
+                DeltaOp::Literal(literal) => {
+                    bytes.push(LITERAL);
+                    bytes.extend_from_slice(&(literal.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(literal);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Decode a delta received from a peer
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+        let invalid = || SyncError::Peer("Malformed delta".into());
+        let take_u32 = |bytes: &mut &[u8]| -> Result<u32> {
+            let (value, rest) = bytes.split_first_chunk::<4>().ok_or_else(invalid)?;
+            *bytes = rest;
+            Ok(u32::from_le_bytes(*value))
+        };
+        let mut delta = Delta::default();
+        while let Some((&tag, rest)) = bytes.split_first() {
+            bytes = rest;
+            let op = match tag {
+                COPY => DeltaOp::Copy {
+                    block: take_u32(&mut bytes)?,
+                    count: take_u32(&mut bytes)?,
+                },
+                LITERAL => {
+                    let len = take_u32(&mut bytes)? as usize;
+                    if bytes.len() < len {
+                        return Err(invalid());
+                    }
+                    let (literal, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    DeltaOp::Literal(literal.to_vec())
+                }
+                _ => return Err(invalid()),
+            };
+            delta.ops.push(op);
+        }
+        Ok(delta)
+    }
+}
+
+impl SyncEngine {
+    /// Fetch only the delta to `remote` from the local version of it
+    ///
+    /// Returns `false` without a request if delta transfer is off, the
+    /// content is small or no older version is here.
+    pub(crate) async fn fetch_by_delta(
+        &self,
+        peer: &dyn SyncPeer,
+        remote: &RemoteArtifact,
+        cancel: &CancellationToken,
+    ) -> Result<bool> {
+        if !self.delta_transfer || remote.size < DELTA_MIN_SIZE {
+            return Ok(false);
+        }
+        let Some(old) = self.delta_base(&remote.artifact)? else {
+            return Ok(false);
+        };
+        let signature = Signature::new(&old);
+        let hash = &remote.artifact.content_hash;
+        let delta = cancellable(cancel, peer.fetch_delta(hash, &signature)).await?;
+        let content = delta.apply(&old, signature.block_size, remote.size)?;
+        if content_hash(&content) != *hash {
+            return Err(SyncError::HashMismatch(remote.artifact.id.clone()));
+        }
+        self.content.put_content(hash, &content)?;
+        tracing::debug!(
+            "Fetched {} as a delta of {} literal bytes",
+            remote.artifact.id,
+            delta.literal_bytes()
+        );
+        Ok(true)
+    }
+
+    /// Content of the local version of an artifact, if older than `remote`
+    fn delta_base(&self, remote: &Artifact) -> Result<Option<Vec<u8>>> {
+        let Some(local) = self.store.get(&remote.id)? else {
+            return Ok(None);
+        };
+        if local.content_hash == remote.content_hash {
+            return Ok(None);
+        }
+        Ok(self.content.get_content(&local.content_hash)?)
+    }
+
+    /// Delta from the version `signature` describes to local content
+    pub(crate) fn serve_delta(&self, content_hash: &str, signature: &Signature) -> Result<Delta> {
+        let content = self
+            .content
+            .get_content(content_hash)?
+            .ok_or_else(|| SyncError::NotFound(content_hash.to_string()))?;
+        Ok(Delta::compute(signature, &content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    fn random(len: usize, seed: u64) -> Vec<u8> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn test_delta_survives_shifted_content() {
+        let old = random(200_000, 1);
+        // Insert near the start so every later chunk boundary shifts
+        let mut new = old[..1000].to_vec();
+        new.extend_from_slice(b"inserted");
+        new.extend_from_slice(&old[1000..150_000]);
+        new.extend_from_slice(&random(500, 2));
+
+        let signature = Signature::from_bytes(&Signature::new(&old).to_bytes()).unwrap();
+        let delta = Delta::compute(&signature, &new);
+        assert!(delta.literal_bytes() < 3 * signature.block_size as u64 + 600);
+        let delta = Delta::from_bytes(&delta.to_bytes()).unwrap();
+        assert_eq!(
+            delta
+                .apply(&old, signature.block_size, new.len() as u64)
+                .unwrap(),
+            new
+        );
+
+        // Unrelated content is all literal but still correct
+        let other = random(10_000, 3);
+        let delta = Delta::compute(&signature, &other);
+        assert_eq!(delta.literal_bytes(), other.len() as u64);
+        assert_eq!(
+            delta.apply(&old, signature.block_size, u64::MAX).unwrap(),
+            other
+        );
+    }
+
+    #[test]
+    fn test_malformed_input_is_rejected() {
+        assert!(Signature::from_bytes(&[1, 0]).is_err());
+        assert!(Delta::from_bytes(&[LITERAL, 9, 0, 0, 0, 1]).is_err());
+        assert!(Delta::from_bytes(&[7]).is_err());
+        let copy = Delta {
+            ops: vec![DeltaOp::Copy { block: 5, count: 1 }],
+        };
+        assert!(copy.apply(&[0u8; 4096], 2048, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_hostile_deltas_are_bounded() {
+        // Signatures longer than any real one
+        let mut huge = (MIN_BLOCK_SIZE as u32).to_le_bytes().to_vec();
+        huge.resize(4 + (MAX_BLOCKS + 1) * 12, 0);
+        assert!(Signature::from_bytes(&huge).is_err());
+
+        // Offsets that overflow instead of indexing past the end
+        let overflow = Delta {
+            ops: vec![DeltaOp::Copy {
+                block: u32::MAX,
+                count: u32::MAX,
+            }],
+        };
+        assert!(overflow.apply(&[0u8; 4096], u32::MAX, u64::MAX).is_err());
+
+        // The same block copied over and over
+        let old = [7u8; 2048];
+        let repeated = Delta {
+            ops: vec![DeltaOp::Copy { block: 0, count: 1 }; 1000],
+        };
+        let bytes = repeated.to_bytes();
+        let decoded = Delta::from_bytes(&bytes).unwrap();
+        assert!(decoded.apply(&old, 2048, 10 * 2048).is_err());
+        assert_eq!(
+            decoded.apply(&old, 2048, 1000 * 2048).unwrap().len(),
+            1000 * 2048
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Sync engine operating on the local stores
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+
+use nomade_crypto::Permissions;
+use nomade_events::EventStream;
+use nomade_quic::RetryPolicy;
+use nomade_storage::{Artifact, ArtifactStore, ContentStore};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::session::SyncProgress;
+use crate::{
+    ChangeLog, ConflictInbox, CursorSink, ManifestEntry, PeerScores, Result, SyncCursor, SyncError,
+    SyncPlan, SyncRules,
+};
+
+/// How freely sync transfers may use the network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferMode {
+    /// Download from up to `MAX_SOURCES` peers at once
+    #[default]
+    Full,
+    /// One source per download, e.g. on a metered network
+    Reduced,
+    /// Hold chunk transfers until resumed; sessions wait, keeping progress
+    Paused,
+}
+
+pub(crate) struct SessionEntry {
+    pub(crate) cancel: CancellationToken,
+    pub(crate) progress: watch::Receiver<SyncProgress>,
+}
+
+/// Sync engine operating on the local artifact and content stores
+pub struct SyncEngine {
+    pub(crate) store: Arc<dyn ArtifactStore>,
+    pub(crate) content: Arc<dyn ContentStore>,
+    pub(crate) events: EventStream,
+    stopped: AtomicBool,
+    /// Parent of every session's cancellation token
+    pub(crate) shutdown: CancellationToken,
+    pub(crate) next_session: AtomicU64,
+    pub(crate) sessions: Mutex<HashMap<u64, SessionEntry>>,
+    /// Content received so far for interrupted downloads, by content hash
+    pub(crate) partials: Mutex<HashMap<String, Vec<u8>>>,
+    /// Selective sync rules by peer ID
+    pub(crate) rules: Mutex<HashMap<String, SyncRules>>,
+    /// Permissions granted to peers, by peer ID
+    pub(crate) permissions: Mutex<HashMap<String, Permissions>>,
+    /// Artifacts whose local content was lost and must be fetched again
+    pub(crate) repairs: Mutex<HashSet<String>>,
+    /// How well peers served as download sources
+    pub(crate) scores: PeerScores,
+    pub(crate) conflicts: ConflictInbox,
+    /// Fetch modified content as deltas of the local version
+    pub(crate) delta_transfer: bool,
+    pub(crate) change_log: ChangeLog,
+    /// Cursor last acknowledged from each peer, by peer ID
+    pub(crate) cursors: Mutex<HashMap<String, SyncCursor>>,
+    pub(crate) cursor_sink: Option<CursorSink>,
+    pub(crate) transfer_mode: watch::Sender<TransferMode>,
+    /// How chunk fetches failing transiently are retried
+    pub(crate) retry: RetryPolicy,
+}
+
+impl SyncEngine {
+    /// Create sync engine
+    ///
+    /// Artifact events are left to the store; wrap it in a `WatchedStore`
+    /// to publish them.
+    pub fn new(
+        store: Arc<dyn ArtifactStore>,
+        content: Arc<dyn ContentStore>,
+        events: EventStream,
+    ) -> Self {
+        Self {
+            store,
+            content,
+            events,
+            stopped: AtomicBool::new(false),
+            shutdown: CancellationToken::new(),
+            next_session: AtomicU64::new(1),
+            sessions: Mutex::new(HashMap::new()),
+            partials: Mutex::new(HashMap::new()),
+            rules: Mutex::new(HashMap::new()),
+            permissions: Mutex::new(HashMap::new()),
+            repairs: Mutex::new(HashSet::new()),
+            scores: PeerScores::default(),
+            conflicts: ConflictInbox::new(),
+            delta_transfer: true,
+            change_log: ChangeLog::new(),
+            cursors: Mutex::new(HashMap::new()),
+            cursor_sink: None,
+            transfer_mode: watch::Sender::new(TransferMode::Full),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Enable or disable delta transfer of modified content (on by default)
+    pub fn with_delta_transfer(mut self, enabled: bool) -> Self {
+        self.delta_transfer = enabled;
+        self
+    }
+
+    /// Override how chunk fetches failing transiently are retried
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Local artifact store
+    pub fn store(&self) -> &Arc<dyn ArtifactStore> {
+        &self.store
+    }
+
+    /// Local content store
+    pub fn content(&self) -> &Arc<dyn ContentStore> {
+        &self.content
+    }
+
+    /// Manifest of all local artifacts, sorted by ID
+    pub fn manifest(&self) -> Result<Vec<ManifestEntry>> {
+        let mut manifest: Vec<ManifestEntry> =
+            self.store.list()?.iter().map(ManifestEntry::from).collect();
+        manifest.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(manifest)
+    }
+
+    /// Compare the local manifest against a peer's
+    pub fn plan(&self, remote: &[ManifestEntry]) -> Result<SyncPlan> {
+        self.ensure_running()?;
+        let local: HashMap<String, ManifestEntry> = self
+            .manifest()?
+            .into_iter()
+            .map(|entry| (entry.id.clone(), entry))
+            .collect();
+
+        let repairs = self.repairs.lock().unwrap().clone();
+        let mut plan = SyncPlan::default();
+        for entry in remote {
+            match local.get(&entry.id) {
+                // The same version restores content we lost
+                Some(ours) if ours == entry && repairs.contains(&entry.id) => {
+                    plan.download.push(entry.id.clone())
+                }
+                // Concurrent edits are fetched for the conflict inbox
+                Some(ours)
+                    if self.conflicts.diverged(ours, entry)
+                        && !self.conflicts.is_pending(entry) =>
+                {
+                    plan.download.push(entry.id.clone())
+                }
+                Some(ours) if ours.cmp_version(entry) != Ordering::Less => {}
+                _ => plan.download.push(entry.id.clone()),
+            }
+        }
+        let remote: HashMap<&str, &ManifestEntry> =
+            remote.iter().map(|e| (e.id.as_str(), e)).collect();
+        for (id, ours) in &local {
+            match remote.get(id.as_str()) {
+                Some(theirs) if ours.cmp_version(theirs) != Ordering::Greater => {}
+                _ => plan.upload.push(id.clone()),
+            }
+        }
+        plan.download.sort();
+        plan.upload.sort();
+        Ok(plan)
+    }
+
+    /// Apply an artifact received from `peer_id`
+    ///
+    /// Returns `false` if the local version is the same or newer, or if
+    /// both were edited since they last agreed and the conflict went to the
+    /// inbox.
+    pub fn apply_remote(&self, peer_id: &str, artifact: &Artifact) -> Result<bool> {
+        self.ensure_running()?;
+        if let Some(existing) = self.store.get(&artifact.id)? {
+            if self.check_conflict(peer_id, &existing, artifact)? {
+                return Ok(false);
+            }
+            let ours = ManifestEntry::from(&existing);
+            if ours.cmp_version(&ManifestEntry::from(artifact)) != Ordering::Less {
+                return Ok(false);
+            }
+        }
+
+        self.store.store(artifact)?;
+        self.conflicts.agree([&ManifestEntry::from(artifact)])?;
+        Ok(true)
+    }
+
+    /// Fetch the content of these artifacts again on the next sync
+    pub fn request_repair(&self, ids: impl IntoIterator<Item = String>) {
+        self.repairs.lock().unwrap().extend(ids);
+    }
+
+    /// Artifacts waiting for their content to be fetched again
+    pub fn pending_repairs(&self) -> Vec<String> {
+        let mut ids: Vec<_> = self.repairs.lock().unwrap().iter().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Pause, resume or downshift transfers, e.g. on network changes
+    pub fn set_transfer_mode(&self, mode: TransferMode) {
+        if self.transfer_mode.send_replace(mode) != mode {
+            tracing::info!("Sync transfers now {:?}", mode);
+        }
+    }
+
+    /// Current transfer mode
+    pub fn transfer_mode(&self) -> TransferMode {
+        *self.transfer_mode.borrow()
+    }
+
+    /// Wait until transfers are not paused, or the session is cancelled
+    pub(crate) async fn wait_unpaused(&self, cancel: &CancellationToken) -> Result<()> {
+        let mut mode = self.transfer_mode.subscribe();
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(SyncError::Cancelled),
+            _ = mode.wait_for(|mode| *mode != TransferMode::Paused) => Ok(()),
+        }
+    }
+
+    /// Stop accepting sync work and cancel running sessions
+    pub fn stop(&self) {
+        if !self.stopped.swap(true, AtomicOrdering::SeqCst) {
+            self.shutdown.cancel();
+            tracing::debug!("Sync engine stopped");
+        }
+    }
+
+    /// Whether the engine has been stopped
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(AtomicOrdering::SeqCst)
+    }
+
+    pub(crate) fn ensure_running(&self) -> Result<()> {
+        if self.is_stopped() {
+            return Err(SyncError::Stopped);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_events::Event;
+    use nomade_storage::{InMemoryStore, WatchedStore};
+
+    fn artifact(id: &str, modified_at: u64, hash: &str) -> Artifact {
+        Artifact {
+            id: id.into(),
+            title: id.into(),
+            created_at: 0,
+            modified_at,
+            content_hash: hash.into(),
+            ..Default::default()
+        }
+    }
+
+    fn engine(artifacts: &[Artifact]) -> SyncEngine {
+        let content = Arc::new(InMemoryStore::new());
+        let events = EventStream::new();
+        let store = Arc::new(WatchedStore::new(content.clone()).with_events(events.clone()));
+        for a in artifacts {
+            store.store(a).unwrap();
+        }
+        SyncEngine::new(store, content, events)
+    }
+
+    #[test]
+    fn test_plan_compares_versions() {
+        let laptop = engine(&[
+            artifact("a", 1, "h1"),
+            artifact("b", 5, "h2"),
+            artifact("c", 3, "h3"),
+        ]);
+        let phone = engine(&[
+            artifact("b", 2, "h0"),
+            artifact("c", 3, "h3"),
+            artifact("d", 1, "h4"),
+        ]);
+
+        let plan = laptop.plan(&phone.manifest().unwrap()).unwrap();
+        assert_eq!(plan.download, vec!["d"]);
+        assert_eq!(plan.upload, vec!["a", "b"]);
+
+        let reverse = phone.plan(&laptop.manifest().unwrap()).unwrap();
+        assert_eq!(reverse.download, plan.upload);
+        assert_eq!(reverse.upload, plan.download);
+    }
+
+    #[tokio::test]
+    async fn test_apply_remote_keeps_newest() {
+        let engine = engine(&[artifact("a", 5, "h1")]);
+        let mut events = engine.events.subscribe();
+
+        assert!(!engine
+            .apply_remote("phone", &artifact("a", 4, "h0"))
+            .unwrap());
+        assert!(engine
+            .apply_remote("phone", &artifact("a", 5, "h2"))
+            .unwrap());
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            Event::ArtifactUpdated { .. }
+        ));
+        assert!(engine
+            .apply_remote("phone", &artifact("b", 1, "h3"))
+            .unwrap());
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            Event::ArtifactCreated { .. }
+        ));
+        assert_eq!(engine.store().get("a").unwrap().unwrap().content_hash, "h2");
+
+        engine.stop();
+        assert!(matches!(engine.plan(&[]), Err(SyncError::Stopped)));
+    }
+
+    #[tokio::test]
+    async fn test_apply_remote_follows_trash() {
+        let engine = engine(&[artifact("a", 5, "h1")]);
+        let mut events = engine.events.subscribe();
+
+        let mut trashed = artifact("a", 6, "h1");
+        trashed.trashed_at = Some(6);
+        assert!(engine.apply_remote("phone", &trashed).unwrap());
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            Event::ArtifactTrashed { .. }
+        ));
+        assert!(engine
+            .apply_remote("phone", &artifact("a", 7, "h1"))
+            .unwrap());
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            Event::ArtifactRestored { .. }
+        ));
+        assert_eq!(engine.store().get("a").unwrap().unwrap().trashed_at, None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Advisory edit intents for artifacts that cannot be merged
+//!
+//! Concurrent edits of a binary artifact end in last-writer-wins, so
+//! devices announce when they start editing one. The announcement is an
+//! `Event::EditIntent`, forwarded to connected peers like any other
+//! shared event, and repeated every `HEARTBEAT_INTERVAL` while the edit
+//! lasts. Peers record it until `INTENT_TTL` passes without a heartbeat,
+//! so a device that vanishes mid-edit releases its intents on its own.
+//! Intents are advisory: nothing stops a peer from editing anyway.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nomade_events::{Event, EventStream};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+/// Interval at which held intents are re-announced
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// Time a peer's intent lasts without a heartbeat
+pub const INTENT_TTL: Duration = Duration::from_secs(30);
+
+/// A peer's announced intent to edit an artifact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditIntent {
+    pub artifact_id: String,
+    pub device_id: String,
+    pub expires_at: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    /// Artifacts this device is editing
+    held: Vec<String>,
+    /// Peer intents by artifact, then device
+    remote: HashMap<String, HashMap<String, Instant>>,
+}
+
+/// Tracks local and peer edit intents
+pub struct EditIntents {
+    events: EventStream,
+    state: Mutex<State>,
+}
+
+impl EditIntents {
+    /// Create a tracker publishing on `events`
+    pub fn new(events: EventStream) -> Self {
+        Self {
+            events,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Announce that this device is editing an artifact
+    ///
+    /// Returns the peers already editing it, so the caller can warn.
+    pub fn begin_edit(&self, artifact_id: &str) -> Vec<EditIntent> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.held.iter().any(|held| held == artifact_id) {
+                state.held.push(artifact_id.to_string());
+            }
+        }
+        self.announce(artifact_id, true);
+        self.holders(artifact_id)
+    }
+
+    /// Announce that this device stopped editing an artifact
+    pub fn end_edit(&self, artifact_id: &str) {
+        let held = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.held.len();
+            state.held.retain(|held| held != artifact_id);
+            state.held.len() != before
+        };
+        if held {
+            self.announce(artifact_id, false);
+        }
+    }
+
+    /// Artifacts this device is editing
+    pub fn held(&self) -> Vec<String> {
+        self.state.lock().unwrap().held.clone()
+    }
+
+    /// Peers currently editing an artifact
+    pub fn holders(&self, artifact_id: &str) -> Vec<EditIntent> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        let mut holders: Vec<EditIntent> = state
+            .remote
+            .get(artifact_id)
+            .into_iter()
+            .flatten()
+            .filter(|(_, expires_at)| **expires_at > now)
+            .map(|(device_id, expires_at)| EditIntent {
+                artifact_id: artifact_id.to_string(),
+                device_id: device_id.clone(),
+                expires_at: *expires_at,
+            })
+            .collect();
+        holders.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        holders
+    }
+
+    /// Record an intent forwarded by a peer; other events are ignored
+    pub fn observe(&self, event: &Event) {
+        let Event::Remote { origin, event } = event else {
+            return;
+        };
+        let Event::EditIntent {
+            artifact_id,
+            editing,
+        } = event.as_ref()
+        else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        if *editing {
+            state
+                .remote
+                .entry(artifact_id.clone())
+                .or_default()
+                .insert(origin.clone(), Instant::now() + INTENT_TTL);
+        } else if let Some(devices) = state.remote.get_mut(artifact_id) {
+            devices.remove(origin);
+            if devices.is_empty() {
+                state.remote.remove(artifact_id);
+            }
+        }
+    }
+
+    /// Re-announce held intents and drop peer intents that lapsed
+    pub fn heartbeat(&self) {
+        let (held, expired) = {
+            let now = Instant::now();
+            let mut state = self.state.lock().unwrap();
+            let mut expired = Vec::new();
+            state.remote.retain(|artifact_id, devices| {
+                devices.retain(|device_id, expires_at| {
+                    let live = *expires_at > now;
+                    if !live {
+                        expired.push((artifact_id.clone(), device_id.clone()));
+                    }
+                    live
+                });
+                !devices.is_empty()
+            });
+            (state.held.clone(), expired)
+        };
+        for artifact_id in held {
+            self.announce(&artifact_id, true);
+        }
+        for (artifact_id, device_id) in expired {
+            self.events.publish(Event::EditIntentExpired {
+                artifact_id,
+                device_id,
+            });
+        }
+    }
+
+    /// Track peer intents and send heartbeats until the event stream closes
+    pub async fn run(self: Arc<Self>) {
+        let mut rx = self.events.subscribe();
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => self.heartbeat(),
+                event = rx.recv() => match event {
+                    Ok(event) => self.observe(&event),
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+    }
+
+    fn announce(&self, artifact_id: &str, editing: bool) {
+        self.events.publish(Event::EditIntent {
+            artifact_id: artifact_id.to_string(),
+            editing,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(origin: &str, artifact_id: &str, editing: bool) -> Event {
+        Event::Remote {
+            origin: origin.into(),
+            event: Box::new(Event::EditIntent {
+                artifact_id: artifact_id.into(),
+                editing,
+            }),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_peer_intents_expire_without_heartbeat() {
+        let events = EventStream::new();
+        let mut rx = events.subscribe();
+        let intents = EditIntents::new(events);
+
+        intents.observe(&remote("phone", "a1", true));
+        intents.observe(&remote("tablet", "a1", true));
+        intents.observe(&remote("tablet", "a1", false));
+        let holders = intents.begin_edit("a1");
+        assert_eq!(holders.len(), 1);
+        assert_eq!(holders[0].device_id, "phone");
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::EditIntent { ref artifact_id, editing: true } if artifact_id == "a1"
+        ));
+
+        // A heartbeat from the peer keeps its intent alive
+        tokio::time::advance(INTENT_TTL - Duration::from_secs(1)).await;
+        intents.observe(&remote("phone", "a1", true));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        intents.heartbeat();
+        assert_eq!(intents.holders("a1").len(), 1);
+        assert!(matches!(rx.recv().await.unwrap(), Event::EditIntent { .. }));
+
+        tokio::time::advance(INTENT_TTL).await;
+        assert!(intents.holders("a1").is_empty());
+        intents.heartbeat();
+        rx.recv().await.unwrap();
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::EditIntentExpired { ref device_id, .. } if device_id == "phone"
+        ));
+
+        intents.end_edit("a1");
+        assert!(intents.held().is_empty());
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Event::EditIntent { editing: false, .. }
+        ));
+    }
+}
@@ -0,0 +1,254 @@
+//! Sync engine for Nomade
+//!
+//! Compares artifact manifests with a peer, decides which artifacts each
+//! side is missing, and pulls remote artifacts into the local store in
+//! resumable chunks, honoring per-peer selective sync rules and device
+//! permissions. Newer versions win on `modified_at`, with the content hash
+//! as a deterministic tiebreaker so both sides agree. Edits made on both
+//! sides since they last agreed go to the conflict inbox instead. Peers
+//! that synced before only exchange what changed since their last cursor.
+
+use std::cmp::Ordering;
+
+use nomade_storage::Artifact;
+use serde::{Deserialize, Serialize};
+
+mod access;
+mod budget;
+mod conflict;
+mod cursor;
+mod delta;
+mod engine;
+mod intent;
+mod merge;
+mod outbox;
+mod peer;
+mod preview;
+mod reencrypt;
+mod remote;
+mod rules;
+mod session;
+mod share;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+mod sources;
+
+pub use budget::{BudgetOutcome, BudgetReport, PendingArtifact, SyncBudget};
+pub use conflict::{ConflictInbox, ConflictRecord, DiffSummary, Resolution};
+pub use cursor::{ChangeLog, Changes, CursorSink, SyncCursor, MAX_TRACKED_CHANGES};
+pub use delta::{Delta, Signature, DELTA_MIN_SIZE};
+pub use engine::{SyncEngine, TransferMode};
+pub use intent::{EditIntent, EditIntents, HEARTBEAT_INTERVAL, INTENT_TTL};
+pub use merge::{merge_text, MergeOutcome};
+pub use outbox::{drain_on_reconnect, OutboundOp, Outbox, OutboxSink, QueuedOp};
+pub use peer::{BoxFuture, RemoteArtifact, SyncPeer, CHUNK_SIZE};
+pub use preview::{SyncPreview, TransferEstimate};
+pub use reencrypt::{ReencryptProgress, ReencryptScope, Reencryption};
+pub use remote::{serve, serve_channels, RemotePeer};
+pub use rules::{RuleAction, RuleEvaluation, RuleMatcher, SyncRule, SyncRules};
+pub use session::{SyncHandle, SyncProgress, SyncState};
+pub use share::{fetch_shared, serve_shares};
+pub use sources::{PeerHints, PeerScores, MAX_SOURCES};
+
+/// Common error type for sync operations
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("Sync engine stopped")]
+    Stopped,
+
+    #[error("Sync cancelled")]
+    Cancelled,
+
+    #[error("Artifact not found: {0}")]
+    NotFound(String),
+
+    #[error("Content hash mismatch for artifact {0}")]
+    HashMismatch(String),
+
+    #[error("Peer error: {0}")]
+    Peer(String),
+
+    #[error("Network error: {0}")]
+    Network(#[from] nomade_quic::ProtocolError),
+
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+
+    #[error("Crypto error: {0}")]
+    Crypto(#[from] nomade_crypto::CryptoError),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] anyhow::Error),
+}
+
+pub type Result<T> = std::result::Result<T, SyncError>;
+
+impl nomade_quic::Retryable for SyncError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            SyncError::Network(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+impl SyncError {
+    /// Message safe to send to a remote peer
+    ///
+    /// Decryption failures are reduced to a fixed message, so a peer
+    /// probing with crafted ciphertexts learns nothing about why they
+    /// failed.
+    pub(crate) fn peer_message(&self) -> String {
+        let crypto = match self {
+            SyncError::Crypto(e) => Some(e),
+            SyncError::Storage(e) => e.downcast_ref::<nomade_crypto::CryptoError>(),
+            _ => None,
+        };
+        match crypto {
+            Some(nomade_crypto::CryptoError::DecryptionFailed(reason)) => {
+                tracing::debug!("Hiding decryption failure from peer: {}", reason);
+                "Decryption failed".into()
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// Version summary of one artifact, exchanged with peers
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: String,
+    pub modified_at: u64,
+    pub content_hash: String,
+}
+
+impl ManifestEntry {
+    /// Order two versions of the same artifact; greater wins
+    fn cmp_version(&self, other: &Self) -> Ordering {
+        self.modified_at
+            .cmp(&other.modified_at)
+            .then_with(|| self.content_hash.cmp(&other.content_hash))
+    }
+}
+
+impl From<&Artifact> for ManifestEntry {
+    fn from(artifact: &Artifact) -> Self {
+        Self {
+            id: artifact.id.clone(),
+            modified_at: artifact.modified_at,
+            content_hash: artifact.content_hash.clone(),
+        }
+    }
+}
+
+/// Most entries accepted in a manifest received from a peer
+pub const MAX_MANIFEST_ENTRIES: usize = 1_000_000;
+
+/// Encode a manifest for the wire
+pub fn encode_manifest(entries: &[ManifestEntry]) -> Vec<u8> {
+    serde_json::to_vec(entries).expect("manifest entries always serialize")
+}
+
+/// Decode and validate a manifest received from a peer
+///
+/// Never panics: malformed input, empty IDs, hashes that are not 64 hex
+/// digits and oversized manifests are reported as `InvalidManifest`.
+pub fn decode_manifest(bytes: &[u8]) -> Result<Vec<ManifestEntry>> {
+    let entries: Vec<ManifestEntry> =
+        serde_json::from_slice(bytes).map_err(|e| SyncError::InvalidManifest(e.to_string()))?;
+    if entries.len() > MAX_MANIFEST_ENTRIES {
+        return Err(SyncError::InvalidManifest(format!(
+            "{} entries exceed the limit of {}",
+            entries.len(),
+            MAX_MANIFEST_ENTRIES
+        )));
+    }
+    for entry in &entries {
+        if entry.id.is_empty() {
+            return Err(SyncError::InvalidManifest("Empty artifact ID".into()));
+        }
+        let valid_hash = entry.content_hash.len() == 64
+            && entry.content_hash.bytes().all(|b| b.is_ascii_hexdigit());
+        if !valid_hash {
+            return Err(SyncError::InvalidManifest(format!(
+                "Bad content hash for {}",
+                entry.id
+            )));
+        }
+    }
+    Ok(entries)
+}
+
+/// Artifacts to exchange with a peer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// Artifacts the peer has a newer version of
+    pub download: Vec<String>,
+    /// Artifacts we have a newer version of
+    pub upload: Vec<String>,
+}
+
+impl SyncPlan {
+    /// Whether both sides are already in sync
+    pub fn is_empty(&self) -> bool {
+        self.download.is_empty() && self.upload.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn manifest_entry() -> impl Strategy<Value = ManifestEntry> {
+        ("[a-z0-9-]{1,24}", any::<u64>(), "[0-9a-f]{64}").prop_map(
+            |(id, modified_at, content_hash)| ManifestEntry {
+                id,
+                modified_at,
+                content_hash,
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn manifest_roundtrip(entries in prop::collection::vec(manifest_entry(), 0..16)) {
+            prop_assert_eq!(decode_manifest(&encode_manifest(&entries)).unwrap(), entries);
+        }
+
+        #[test]
+        fn manifest_decode_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = decode_manifest(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_manifest_rejects_bad_entries() {
+        let entry = |id: &str, hash: &str| ManifestEntry {
+            id: id.into(),
+            modified_at: 1,
+            content_hash: hash.into(),
+        };
+        let ok = "ab".repeat(32);
+        assert!(decode_manifest(&encode_manifest(&[entry("a", &ok)])).is_ok());
+        assert!(decode_manifest(&encode_manifest(&[entry("", &ok)])).is_err());
+        assert!(decode_manifest(&encode_manifest(&[entry("a", "zz")])).is_err());
+    }
+
+    #[test]
+    fn test_peer_message_hides_decryption_detail() {
+        use nomade_crypto::{CryptoError, DecryptError};
+        let failure = || CryptoError::DecryptionFailed(DecryptError::WrongKey);
+        assert_eq!(
+            SyncError::from(failure()).peer_message(),
+            "Decryption failed"
+        );
+        let wrapped = anyhow::Error::from(failure()).context("Unwrapping content key");
+        assert_eq!(
+            SyncError::Storage(wrapped).peer_message(),
+            "Decryption failed"
+        );
+        let missing = SyncError::NotFound("a1".into());
+        assert_eq!(missing.peer_message(), missing.to_string());
+    }
+}
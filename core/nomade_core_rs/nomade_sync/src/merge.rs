@@ -0,0 +1,173 @@
+//! Three-way merge of text edited on two devices
+//!
+//! `merge_text` is a line-based diff3: both versions are diffed against
+//! their common ancestor, changes made on one side only are taken, and
+//! regions changed differently on both sides are written with conflict
+//! markers. The sync engine runs it on concurrent edits of text artifacts
+//! before they reach the conflict inbox, using the base version as the
+//! ancestor.
+
+/// Most base lines times edited lines diffed; larger inputs are not merged
+const MAX_DIFF_CELLS: usize = 16 * 1024 * 1024;
+
+/// Result of a three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeOutcome {
+    /// Merged text, with conflict markers if `conflicts` is not zero
+    pub text: String,
+    /// Regions changed differently on both sides
+    pub conflicts: usize,
+}
+
+impl MergeOutcome {
+    /// Whether every change merged without conflict markers
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+/// Merge `local` and `remote`, both edited from `base`
+///
+/// Returns `None` if the texts are too large to diff.
+pub fn merge_text(base: &str, local: &str, remote: &str) -> Option<MergeOutcome> {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let local: Vec<&str> = local.split_inclusive('\n').collect();
+    let remote: Vec<&str> = remote.split_inclusive('\n').collect();
+    let to_local = matches(&base, &local)?;
+    let to_remote = matches(&base, &remote)?;
+
+    let mut outcome = MergeOutcome {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut o, mut a, mut b) = (0, 0, 0);
+    loop {
+        // Lines unchanged on both sides
+        while o < base.len() && to_local[o] == Some(a) && to_remote[o] == Some(b) {
+            outcome.text.push_str(base[o]);
+            o += 1;
+            a += 1;
+            b += 1;
+        }
+        if o == base.len() && a == local.len() && b == remote.len() {
+            return Some(outcome);
+        }
+        // Next base line both sides kept ends the changed region
+        let (o2, a2, b2) = (o..base.len())
+            .find_map(|k| Some((k, to_local[k]?, to_remote[k]?)))
+            .unwrap_or((base.len(), local.len(), remote.len()));
+        let (ancestor, ours, theirs) = (&base[o..o2], &local[a..a2], &remote[b..b2]);
+        if ours == ancestor || ours == theirs {
+            theirs.iter().for_each(|line| outcome.text.push_str(line));
+        } else if theirs == ancestor {
+            ours.iter().for_each(|line| outcome.text.push_str(line));
+        } else {
+            outcome.conflicts += 1;
+            push_marked(&mut outcome.text, "<<<<<<< local\n", ours);
+            push_marked(&mut outcome.text, "=======\n", theirs);
+            outcome.text.push_str(">>>>>>> remote\n");
+        }
+        (o, a, b) = (o2, a2, b2);
+    }
+}
+
+fn push_marked(text: &mut String, marker: &str, lines: &[&str]) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(marker);
+    lines.iter().for_each(|line| text.push_str(line));
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// For each base line, the line of `other` it matches in a longest
+/// common subsequence
+fn matches(base: &[&str], other: &[&str]) -> Option<Vec<Option<usize>>> {
+    let mut matched = vec![None; base.len()];
+    // Common prefix and suffix need no table
+    let prefix = base.iter().zip(other).take_while(|(x, y)| x == y).count();
+    let suffix = base[prefix..]
+        .iter()
+        .rev()
+        .zip(other[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    for (i, slot) in matched.iter_mut().enumerate().take(prefix) {
+        *slot = Some(i);
+    }
+    for k in 1..=suffix {
+        matched[base.len() - k] = Some(other.len() - k);
+    }
+
+    let x = &base[prefix..base.len() - suffix];
+    let y = &other[prefix..other.len() - suffix];
+    if x.is_empty() || y.is_empty() {
+        return Some(matched);
+    }
+    if x.len().saturating_mul(y.len()) > MAX_DIFF_CELLS {
+        return None;
+    }
+    // lcs[i][j]: length of the LCS of x[i..] and y[j..]
+    let width = y.len() + 1;
+    let mut lcs = vec![0u32; (x.len() + 1) * width];
+    for i in (0..x.len()).rev() {
+        for j in (0..y.len()).rev() {
+            lcs[i * width + j] = if x[i] == y[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < x.len() && j < y.len() {
+        if x[i] == y[j] {
+            matched[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    Some(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merges_changes_to_different_lines() {
+        let base = "title\n\none\ntwo\nthree\n";
+        let local = "title\n\none\n2\nthree\nfour\n";
+        let remote = "Title\n\none\ntwo\nthree\n";
+        let outcome = merge_text(base, local, remote).unwrap();
+        assert!(outcome.is_clean());
+        assert_eq!(outcome.text, "Title\n\none\n2\nthree\nfour\n");
+
+        // The same change on both sides is taken once
+        let outcome = merge_text(base, local, local).unwrap();
+        assert_eq!(outcome.text, local);
+    }
+
+    #[test]
+    fn test_marks_overlapping_changes() {
+        let base = "a\nb\nc";
+        let outcome = merge_text(base, "a\nlocal\nc", "a\nremote\nc").unwrap();
+        assert_eq!(outcome.conflicts, 1);
+        assert_eq!(
+            outcome.text,
+            "a\n<<<<<<< local\nlocal\n=======\nremote\n>>>>>>> remote\nc"
+        );
+
+        // Both appending at the end without a trailing newline
+        let outcome = merge_text(base, "a\nb\nc\nx", "a\nb\nc\ny").unwrap();
+        assert_eq!(outcome.conflicts, 1);
+        assert!(outcome.text.starts_with("a\nb\n<<<<<<< local\nc\nx\n"));
+        assert!(outcome.text.ends_with("=======\nc\ny\n>>>>>>> remote\n"));
+    }
+}
@@ -0,0 +1,374 @@
+//! Durable per-peer queue of outbound operations
+//!
+//! Changes made while a peer is unreachable are queued here (collection
+//! CRDT ops, chunk uploads) and persisted, so they survive restarts. When
+//! the peer reconnects the queue is drained in order through an
+//! `OutboxSink`. An operation is removed only after delivery succeeded,
+//! so a failed drain resends it next time; receivers must therefore apply
+//! operations idempotently, which both kinds are (CRDT merge, content
+//! addressed chunks). The same operation is never queued twice for a peer.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use nomade_crypto::fs::write_durable;
+use nomade_events::{Event, EventStream};
+use nomade_metrics::names;
+use nomade_storage::CollectionOp;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{BoxFuture, Result};
+
+/// Operation waiting to be delivered to a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutboundOp {
+    /// Replicated collection change
+    Collection { op: CollectionOp },
+    /// Content chunk to push to the peer
+    ChunkUpload { content_hash: String, index: u32 },
+}
+
+/// Queued operation with its position in the peer's queue
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedOp {
+    pub seq: u64,
+    pub op: OutboundOp,
+}
+
+/// Delivers queued operations to connected peers
+pub trait OutboxSink: Send + Sync {
+    /// Deliver one operation; an error leaves it queued for the next drain
+    fn deliver<'a>(&'a self, peer: &'a str, op: &'a OutboundOp) -> BoxFuture<'a, Result<()>>;
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    next_seq: u64,
+    queues: BTreeMap<String, VecDeque<QueuedOp>>,
+    /// Peers with a drain in progress
+    #[serde(skip)]
+    draining: HashSet<String>,
+}
+
+/// Durable outbound operation queue
+pub struct Outbox {
+    state: Mutex<State>,
+    path: Option<PathBuf>,
+    events: Option<EventStream>,
+}
+
+impl Outbox {
+    /// Create an in-memory outbox
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            path: None,
+            events: None,
+        }
+    }
+
+    /// Open an outbox persisted at `path`, creating it if missing
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let outbox = Self {
+            state: Mutex::new(state),
+            path: Some(path),
+            events: None,
+        };
+        outbox.record_depth();
+        Ok(outbox)
+    }
+
+    /// Publish `OutboxChanged` events when a queue grows or shrinks
+    pub fn with_events(mut self, events: EventStream) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Queue an operation for a peer
+    ///
+    /// Returns `false` if the same operation is already queued.
+    pub fn enqueue(&self, peer: &str, op: OutboundOp) -> Result<bool> {
+        let depth = {
+            let mut state = self.state.lock().unwrap();
+            let seq = state.next_seq;
+            let queue = state.queues.entry(peer.to_string()).or_default();
+            if queue.iter().any(|queued| queued.op == op) {
+                return Ok(false);
+            }
+            queue.push_back(QueuedOp { seq, op });
+            let depth = queue.len();
+            state.next_seq += 1;
+            self.save(&state)?;
+            depth
+        };
+        self.changed(peer, depth);
+        Ok(true)
+    }
+
+    /// Operations waiting for a peer, oldest first
+    pub fn pending(&self, peer: &str) -> Vec<QueuedOp> {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .get(peer)
+            .map(|queue| queue.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of operations waiting for a peer
+    pub fn depth(&self, peer: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .get(peer)
+            .map_or(0, VecDeque::len)
+    }
+
+    /// Number of operations waiting across all peers
+    pub fn total_depth(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .queues
+            .values()
+            .map(VecDeque::len)
+            .sum()
+    }
+
+    /// Drop a delivered operation
+    pub fn ack(&self, peer: &str, seq: u64) -> Result<()> {
+        let depth = {
+            let mut state = self.state.lock().unwrap();
+            let Some(queue) = state.queues.get_mut(peer) else {
+                return Ok(());
+            };
+            queue.retain(|queued| queued.seq != seq);
+            let depth = queue.len();
+            if depth == 0 {
+                state.queues.remove(peer);
+            }
+            self.save(&state)?;
+            depth
+        };
+        self.changed(peer, depth);
+        Ok(())
+    }
+
+    /// Discard everything queued for a peer, e.g. after it was revoked
+    pub fn clear(&self, peer: &str) -> Result<usize> {
+        let removed = {
+            let mut state = self.state.lock().unwrap();
+            let removed = state.queues.remove(peer).map_or(0, |queue| queue.len());
+            self.save(&state)?;
+            removed
+        };
+        if removed > 0 {
+            self.changed(peer, 0);
+        }
+        Ok(removed)
+    }
+
+    /// Deliver a peer's queue in order, stopping at the first failure
+    ///
+    /// Returns how many operations were delivered. A drain already running
+    /// for the same peer makes this a no-op.
+    pub async fn drain(&self, peer: &str, sink: &dyn OutboxSink) -> Result<usize> {
+        if !self.state.lock().unwrap().draining.insert(peer.to_string()) {
+            return Ok(0);
+        }
+        let result = self.drain_queue(peer, sink).await;
+        self.state.lock().unwrap().draining.remove(peer);
+        result
+    }
+
+    async fn drain_queue(&self, peer: &str, sink: &dyn OutboxSink) -> Result<usize> {
+        let mut delivered = 0;
+        for queued in self.pending(peer) {
+            sink.deliver(peer, &queued.op).await?;
+            self.ack(peer, queued.seq)?;
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+
+    fn save(&self, state: &State) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(state)?)?;
+        Ok(())
+    }
+
+    fn changed(&self, peer: &str, depth: usize) {
+        self.record_depth();
+        if let Some(events) = &self.events {
+            events.publish(Event::OutboxChanged {
+                peer: peer.to_string(),
+                depth,
+            });
+        }
+    }
+
+    fn record_depth(&self) {
+        nomade_metrics::global()
+            .gauge(
+                names::OUTBOX_DEPTH,
+                "Operations queued for unreachable peers",
+            )
+            .set(self.total_depth() as i64);
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drain a peer's queue whenever it connects, and drop it when revoked
+///
+/// Runs until the event stream closes.
+pub async fn drain_on_reconnect(
+    outbox: Arc<Outbox>,
+    sink: Arc<dyn OutboxSink>,
+    events: EventStream,
+) {
+    let mut rx = events.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(Event::DeviceConnected { device_id }) => {
+                match outbox.drain(&device_id, sink.as_ref()).await {
+                    Ok(0) => {}
+                    Ok(delivered) => {
+                        tracing::debug!("Delivered {} queued ops to {}", delivered, device_id)
+                    }
+                    Err(e) => tracing::warn!("Outbox drain to {} failed: {}", device_id, e),
+                }
+            }
+            Ok(Event::DeviceRevoked { device_id }) => {
+                if let Err(e) = outbox.clear(&device_id) {
+                    tracing::warn!("Failed to clear outbox for {}: {}", device_id, e);
+                }
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyncError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Records deliveries; fails while `offline` is set
+    #[derive(Default)]
+    struct Recorder {
+        offline: AtomicBool,
+        delivered: Mutex<Vec<(String, OutboundOp)>>,
+    }
+
+    impl OutboxSink for Recorder {
+        fn deliver<'a>(&'a self, peer: &'a str, op: &'a OutboundOp) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                if self.offline.load(Ordering::SeqCst) {
+                    return Err(SyncError::Peer(format!("{} unreachable", peer)));
+                }
+                self.delivered
+                    .lock()
+                    .unwrap()
+                    .push((peer.to_string(), op.clone()));
+                Ok(())
+            })
+        }
+    }
+
+    fn chunk(index: u32) -> OutboundOp {
+        OutboundOp::ChunkUpload {
+            content_hash: "abc".into(),
+            index,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_survives_restart_and_drains_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.json");
+        {
+            let outbox = Outbox::open(&path).unwrap();
+            assert!(outbox.enqueue("phone", chunk(0)).unwrap());
+            assert!(outbox.enqueue("phone", chunk(1)).unwrap());
+            assert!(!outbox.enqueue("phone", chunk(0)).unwrap());
+            assert!(outbox.enqueue("tablet", chunk(0)).unwrap());
+        }
+
+        let outbox = Outbox::open(&path).unwrap();
+        assert_eq!(outbox.depth("phone"), 2);
+        assert_eq!(outbox.total_depth(), 3);
+
+        let sink = Recorder::default();
+        sink.offline.store(true, Ordering::SeqCst);
+        assert!(outbox.drain("phone", &sink).await.is_err());
+        assert_eq!(outbox.depth("phone"), 2);
+
+        sink.offline.store(false, Ordering::SeqCst);
+        assert_eq!(outbox.drain("phone", &sink).await.unwrap(), 2);
+        assert_eq!(outbox.depth("phone"), 0);
+        let delivered = sink.delivered.lock().unwrap().clone();
+        assert_eq!(
+            delivered,
+            vec![("phone".into(), chunk(0)), ("phone".into(), chunk(1))]
+        );
+        assert_eq!(Outbox::open(&path).unwrap().total_depth(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_drains_on_reconnect_and_clears_on_revoke() {
+        let events = EventStream::new();
+        let mut changes = events.subscribe();
+        let outbox = Arc::new(Outbox::new().with_events(events.clone()));
+        outbox.enqueue("phone", chunk(0)).unwrap();
+        outbox.enqueue("tablet", chunk(0)).unwrap();
+        assert!(matches!(
+            changes.recv().await.unwrap(),
+            Event::OutboxChanged { ref peer, depth: 1 } if peer == "phone"
+        ));
+
+        let sink = Arc::new(Recorder::default());
+        let task = tokio::spawn(drain_on_reconnect(
+            outbox.clone(),
+            sink.clone(),
+            events.clone(),
+        ));
+        tokio::task::yield_now().await;
+        events.publish(Event::DeviceConnected {
+            device_id: "phone".into(),
+        });
+        events.publish(Event::DeviceRevoked {
+            device_id: "tablet".into(),
+        });
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while outbox.total_depth() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(sink.delivered.lock().unwrap().len(), 1);
+        drop(events);
+        task.abort();
+    }
+}
@@ -0,0 +1,156 @@
+//! Remote side of a sync session
+//!
+//! `SyncPeer` abstracts how manifests, artifact metadata and content
+//! chunks are fetched from another device, so the engine does not depend
+//! on a particular transport. `SyncEngine` itself implements it to serve
+//! its local stores; artifacts whose content is not local are left out.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use nomade_crypto::KeyShares;
+use nomade_storage::{Artifact, HashTree, HASH_GROUP_SIZE};
+use serde::{Deserialize, Serialize};
+
+use crate::{Changes, Delta, ManifestEntry, Result, Signature, SyncCursor, SyncEngine, SyncError};
+
+/// Size of content chunks transferred between peers
+///
+/// Chunks line up with hash tree groups, so each verifies on its own.
+pub const CHUNK_SIZE: usize = HASH_GROUP_SIZE;
+
+/// Boxed future returned by `SyncPeer` methods
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Artifact metadata with the size of its content
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteArtifact {
+    pub artifact: Artifact,
+    /// Content size in bytes
+    pub size: u64,
+    /// Data key of the content when the peer encrypts it at rest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_shares: Option<KeyShares>,
+}
+
+impl RemoteArtifact {
+    /// Number of chunks needed to transfer the content
+    pub fn chunk_count(&self) -> u32 {
+        self.size.div_ceil(CHUNK_SIZE as u64) as u32
+    }
+}
+
+/// Device we can pull artifacts from
+pub trait SyncPeer: Send + Sync {
+    /// Manifest of the peer's artifacts
+    fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>>;
+
+    /// Manifest entries changed after `cursor`, with the cursor to send next
+    ///
+    /// Peers without a change log send their full manifest.
+    fn changes<'a>(&'a self, cursor: Option<&'a SyncCursor>) -> BoxFuture<'a, Result<Changes>> {
+        let _ = cursor;
+        Box::pin(async move { Ok(Changes::full(self.manifest().await?)) })
+    }
+
+    /// Metadata and content size of an artifact
+    fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>>;
+
+    /// One `CHUNK_SIZE` chunk of content; the last chunk may be shorter
+    fn fetch_chunk<'a>(
+        &'a self,
+        content_hash: &'a str,
+        index: u32,
+    ) -> BoxFuture<'a, Result<Vec<u8>>>;
+
+    /// Hash tree of content, to verify chunks as they arrive
+    fn fetch_hash_tree<'a>(&'a self, content_hash: &'a str) -> BoxFuture<'a, Result<HashTree>>;
+
+    /// Delta from the version `signature` describes to this content
+    ///
+    /// Peers without delta support fail, and the puller fetches chunks.
+    fn fetch_delta<'a>(
+        &'a self,
+        content_hash: &'a str,
+        signature: &'a Signature,
+    ) -> BoxFuture<'a, Result<Delta>> {
+        let _ = (content_hash, signature);
+        Box::pin(async { Err(SyncError::Peer("Delta transfer not supported".into())) })
+    }
+}
+
+impl SyncEngine {
+    /// Whether the artifact's content is here to serve, e.g. not evicted
+    pub(crate) fn serves(&self, artifact: &Artifact) -> bool {
+        self.content
+            .has_content(&artifact.content_hash)
+            .unwrap_or(false)
+    }
+
+    fn local_content(&self, content_hash: &str) -> Result<Vec<u8>> {
+        self.content
+            .get_content(content_hash)?
+            .ok_or_else(|| SyncError::NotFound(content_hash.to_string()))
+    }
+}
+
+impl SyncPeer for SyncEngine {
+    fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+        Box::pin(async move {
+            let mut manifest = SyncEngine::manifest(self)?;
+            manifest.retain(|entry| {
+                self.content
+                    .has_content(&entry.content_hash)
+                    .unwrap_or(false)
+            });
+            Ok(manifest)
+        })
+    }
+
+    fn changes<'a>(&'a self, cursor: Option<&'a SyncCursor>) -> BoxFuture<'a, Result<Changes>> {
+        Box::pin(async move { self.changes_since(cursor, "", |_| true) })
+    }
+
+    fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+        Box::pin(async move {
+            let artifact = self
+                .store
+                .get(id)?
+                .ok_or_else(|| SyncError::NotFound(id.to_string()))?;
+            let size = self.local_content(&artifact.content_hash)?.len() as u64;
+            let key_shares = self.content.key_shares(&artifact.content_hash)?;
+            Ok(RemoteArtifact {
+                artifact,
+                size,
+                key_shares,
+            })
+        })
+    }
+
+    fn fetch_chunk<'a>(
+        &'a self,
+        content_hash: &'a str,
+        index: u32,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let content = self.local_content(content_hash)?;
+            content
+                .chunks(CHUNK_SIZE)
+                .nth(index as usize)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| SyncError::Peer(format!("Chunk {} out of range", index)))
+        })
+    }
+
+    fn fetch_hash_tree<'a>(&'a self, content_hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+        Box::pin(async move { Ok(HashTree::build(&self.local_content(content_hash)?)) })
+    }
+
+    fn fetch_delta<'a>(
+        &'a self,
+        content_hash: &'a str,
+        signature: &'a Signature,
+    ) -> BoxFuture<'a, Result<Delta>> {
+        Box::pin(async move { self.serve_delta(content_hash, signature) })
+    }
+}
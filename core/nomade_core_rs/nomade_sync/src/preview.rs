@@ -0,0 +1,122 @@
+//! Dry run of a sync
+//!
+//! `preview` plans a sync with a peer the way a session would, fetching
+//! only the peer's manifest and artifact metadata, so the app can ask
+//! before a large sync over a metered network. Downloads honor the peer's
+//! permissions and selective sync rules and skip content already here or
+//! partially fetched. Uploads are what the peer would pull from this
+//! device's view for it; its own rules are not known here, so they are an
+//! upper bound, as are downloads that end up transferred as deltas.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, SyncEngine, SyncPeer, CHUNK_SIZE};
+
+/// Transfers in one direction
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferEstimate {
+    /// Artifact IDs, sorted
+    pub artifacts: Vec<String>,
+    /// Content chunks to transfer
+    pub chunks: u64,
+    /// Content bytes to transfer
+    pub bytes: u64,
+}
+
+impl TransferEstimate {
+    fn add(&mut self, id: &str, bytes: u64) {
+        self.artifacts.push(id.to_string());
+        self.chunks += bytes.div_ceil(CHUNK_SIZE as u64);
+        self.bytes += bytes;
+    }
+}
+
+/// What syncing with a peer would transfer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncPreview {
+    pub peer_id: String,
+    pub download: TransferEstimate,
+    pub upload: TransferEstimate,
+}
+
+impl SyncEngine {
+    /// Plan a sync with `peer` without transferring any content
+    pub async fn preview(&self, peer_id: &str, peer: &dyn SyncPeer) -> Result<SyncPreview> {
+        let remote = peer.manifest().await?;
+        let plan = self.plan(&remote)?;
+        let rules = self.rules(peer_id);
+        let mut preview = SyncPreview {
+            peer_id: peer_id.to_string(),
+            ..Default::default()
+        };
+
+        for id in &plan.download {
+            let remote = peer.fetch_artifact(id).await?;
+            if self.accepts_from(peer_id, &remote.artifact)
+                && rules.allows(&remote.artifact, remote.size)
+            {
+                preview.download.add(id, self.fetch_cost(&remote));
+            }
+        }
+
+        let permissions = self.permissions(peer_id);
+        for id in &plan.upload {
+            let Some(artifact) = self.store.get(id)? else {
+                continue;
+            };
+            if !permissions.can_access(artifact.collection.as_deref()) {
+                continue;
+            }
+            if let Some(content) = self.content.get_content(&artifact.content_hash)? {
+                preview.upload.add(id, content.len() as u64);
+            }
+        }
+        Ok(preview)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_events::EventStream;
+    use nomade_storage::{content_hash, Artifact, InMemoryStore};
+    use std::sync::Arc;
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    fn add(engine: &SyncEngine, id: &str, content: &[u8], modified_at: u64) {
+        let hash = content_hash(content);
+        engine.content().put_content(&hash, content).unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: id.into(),
+                modified_at,
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preview_counts_without_transferring() {
+        let (laptop, phone) = (engine(), engine());
+        add(&phone, "video", &vec![1u8; CHUNK_SIZE * 2 + 1], 1);
+        // Content the laptop already holds costs nothing
+        add(&phone, "copy", b"shared", 1);
+        add(&laptop, "other", b"shared", 1);
+        add(&laptop, "note", b"laptop only", 2);
+
+        let preview = laptop.preview("phone", phone.as_ref()).await.unwrap();
+        assert_eq!(preview.download.artifacts, ["copy", "video"]);
+        assert_eq!(preview.download.chunks, 3);
+        assert_eq!(preview.download.bytes, CHUNK_SIZE as u64 * 2 + 1);
+        assert_eq!(preview.upload.artifacts, ["note", "other"]);
+        assert_eq!(preview.upload.bytes, 17);
+        assert!(laptop.store().get("video").unwrap().is_none());
+        assert!(laptop.active_sessions().is_empty());
+    }
+}
@@ -0,0 +1,309 @@
+//! Re-encryption after a device is revoked
+//!
+//! A revoked device no longer receives key shares, but it may still hold
+//! the data keys of content it synced. `Reencryption` queues the content
+//! whose keys it could know: artifacts modified since the revocation by
+//! default (peers that had not yet heard of it may have shared their keys),
+//! or everything. `SyncEngine::reencrypt` works through the queue in
+//! batches, rotating each data key; the artifacts land in the change log,
+//! so peers fetch the new key shares and re-encrypt their copies under the
+//! same key. The queue is saved after every key, so the job resumes where
+//! it stopped.
+//!
+//! Only one device rotates keys for a revocation: the one that signed it,
+//! or the lowest remaining device ID when a device revoked itself.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use nomade_crypto::fs::write_durable;
+use nomade_crypto::DeviceId;
+use nomade_storage::ArtifactStore;
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, SyncEngine};
+
+/// Content re-encrypted after a revocation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReencryptScope {
+    /// Artifacts modified since the revocation
+    #[default]
+    Modified,
+    /// All content
+    All,
+}
+
+/// Progress of the re-encryption queue
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReencryptProgress {
+    /// Devices whose revocation the queued keys are rotated for
+    pub revoked: Vec<String>,
+    /// Keys rotated so far
+    pub done: usize,
+    /// Keys still to rotate
+    pub remaining: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JobState {
+    revoked: BTreeSet<String>,
+    /// Artifact IDs by content hash whose key to rotate
+    pending: BTreeMap<String, BTreeSet<String>>,
+    done: usize,
+}
+
+/// Resumable queue of data keys to rotate
+pub struct Reencryption {
+    state: Mutex<JobState>,
+    path: Option<PathBuf>,
+}
+
+impl Default for Reencryption {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reencryption {
+    /// Create an empty in-memory queue
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(JobState::default()),
+            path: None,
+        }
+    }
+
+    /// Open a queue persisted at `path`
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => JobState::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            state: Mutex::new(state),
+            path: Some(path),
+        })
+    }
+
+    /// Whether device `me` rotates keys after `revoked_by` revoked `revoked`
+    ///
+    /// `remaining` are the device IDs still trusted, `me` included.
+    pub fn coordinates(
+        revoked: &DeviceId,
+        revoked_by: &DeviceId,
+        me: &DeviceId,
+        remaining: &[DeviceId],
+    ) -> bool {
+        if revoked_by != revoked {
+            return revoked_by == me;
+        }
+        remaining.iter().min_by(|a, b| a.0.cmp(&b.0)) == Some(me)
+    }
+
+    /// Queue the keys `revoked` may know, returning how many were added
+    pub fn schedule(
+        &self,
+        revoked: &DeviceId,
+        since: u64,
+        scope: ReencryptScope,
+        artifacts: &dyn ArtifactStore,
+    ) -> Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        let before = state.pending.len();
+        for artifact in artifacts.list()? {
+            if scope == ReencryptScope::Modified && artifact.modified_at < since {
+                continue;
+            }
+            state
+                .pending
+                .entry(artifact.content_hash)
+                .or_default()
+                .insert(artifact.id);
+        }
+        state.revoked.insert(revoked.to_string());
+        self.save(&state)?;
+        Ok(state.pending.len() - before)
+    }
+
+    /// Current progress
+    pub fn progress(&self) -> ReencryptProgress {
+        progress(&self.state.lock().unwrap())
+    }
+
+    fn save(&self, state: &JobState) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_durable(path, &serde_json::to_vec(state)?)?;
+        Ok(())
+    }
+}
+
+fn progress(state: &JobState) -> ReencryptProgress {
+    ReencryptProgress {
+        revoked: state.revoked.iter().cloned().collect(),
+        done: state.done,
+        remaining: state.pending.len(),
+    }
+}
+
+impl SyncEngine {
+    /// Rotate up to `limit` queued data keys
+    ///
+    /// Content replaced since it was queued already has a new key and is
+    /// skipped. The queue is cleared once empty.
+    pub fn reencrypt(&self, job: &Reencryption, limit: usize) -> Result<ReencryptProgress> {
+        let mut state = job.state.lock().unwrap();
+        for _ in 0..limit {
+            let Some((hash, ids)) = state.pending.pop_first() else {
+                break;
+            };
+            let mut current = Vec::new();
+            for id in ids {
+                if let Some(artifact) = self.store.get(&id)? {
+                    if artifact.content_hash == hash {
+                        current.push(id);
+                    }
+                }
+            }
+            if !current.is_empty() && self.content.rotate_key(&hash)? {
+                for id in &current {
+                    self.change_log().record(id)?;
+                }
+            }
+            state.done += 1;
+            job.save(&state)?;
+        }
+        let report = progress(&state);
+        if state.pending.is_empty() && !state.revoked.is_empty() {
+            tracing::info!(
+                "Re-encrypted {} keys after revoking {:?}",
+                state.done,
+                state.revoked
+            );
+            *state = JobState::default();
+            job.save(&state)?;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyncState;
+    use nomade_crypto::{generate_key, generate_keypair, KeyContext, KeyRecipient, NonceSequence};
+    use nomade_events::EventStream;
+    use nomade_storage::{
+        content_hash, Artifact, ContentStore, EncryptedContentStore, InMemoryStore,
+    };
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_revocation_rotates_keys_once() {
+        let identities = [generate_keypair(), generate_keypair()];
+        let devices: Vec<_> = identities
+            .iter()
+            .map(|keypair| KeyRecipient {
+                device_id: keypair.device_id().clone(),
+                public_key: keypair.public_key_bytes(),
+            })
+            .collect();
+        let [laptop, phone] = identities.map(|identity| {
+            let store = Arc::new(InMemoryStore::new());
+            let content = EncryptedContentStore::new(
+                store.clone(),
+                KeyContext::new(generate_key(), NonceSequence::counter()),
+            )
+            .with_identity(identity);
+            content.share_keys(&devices, store.as_ref()).unwrap();
+            let (_tx, feed) = mpsc::channel();
+            Arc::new(
+                SyncEngine::new(store, Arc::new(content), EventStream::new())
+                    .with_change_log(crate::ChangeLog::new().with_feed(feed)),
+            )
+        });
+        for (id, text, modified_at) in [("old", "old notes", 10), ("new", "new notes", 30)] {
+            let hash = content_hash(text.as_bytes());
+            laptop
+                .content()
+                .put_content(&hash, text.as_bytes())
+                .unwrap();
+            laptop
+                .store()
+                .store(&Artifact {
+                    id: id.into(),
+                    content_hash: hash,
+                    modified_at,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        let progress = phone
+            .start_sync("laptop", laptop.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(progress.state, SyncState::Completed);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("reencrypt.json");
+        let job = Reencryption::open(&path).unwrap();
+        let tablet = generate_keypair().device_id().clone();
+        assert_eq!(
+            job.schedule(
+                &tablet,
+                20,
+                ReencryptScope::Modified,
+                laptop.store().as_ref()
+            )
+            .unwrap(),
+            1
+        );
+        // Resumes from disk
+        let job = Reencryption::open(&path).unwrap();
+        assert_eq!(job.progress().remaining, 1);
+
+        let hash = content_hash(b"new notes");
+        let before = laptop.content().key_shares(&hash).unwrap();
+        let report = laptop.reencrypt(&job, 10).unwrap();
+        assert_eq!((report.done, report.remaining), (1, 0));
+        assert_eq!(job.progress(), ReencryptProgress::default());
+        let rotated = laptop.content().key_shares(&hash).unwrap();
+        assert_ne!(rotated, before);
+
+        // The phone picks up the rotated key on its next catch-up
+        let progress = phone
+            .start_sync("laptop", laptop.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert_eq!(phone.content().key_shares(&hash).unwrap(), rotated);
+        assert_eq!(
+            phone.content().get_content(&hash).unwrap().unwrap(),
+            b"new notes"
+        );
+    }
+
+    #[test]
+    fn test_one_device_coordinates() {
+        let [laptop, phone, tablet] = [(); 3].map(|_| generate_keypair().device_id().clone());
+        let ids = [laptop.clone(), phone.clone()];
+        assert!(Reencryption::coordinates(&tablet, &laptop, &laptop, &ids));
+        assert!(!Reencryption::coordinates(&tablet, &laptop, &phone, &ids));
+
+        // A device that revoked itself leaves the work to the lowest ID
+        let lowest = ids.iter().min_by(|a, b| a.0.cmp(&b.0)).unwrap();
+        let coordinators: Vec<_> = ids
+            .iter()
+            .filter(|id| Reencryption::coordinates(&tablet, &tablet, id, &ids))
+            .collect();
+        assert_eq!(coordinators, [lowest]);
+    }
+}
@@ -0,0 +1,574 @@
+//! Sync with a peer over a transport connection
+//!
+//! `RemotePeer` implements `SyncPeer` by sending each call as a
+//! `SyncRequest` frame on a fresh channel (`ChunkTransfer` for content,
+//! `SyncMeta` for everything else), and `serve`
+//! answers those requests from a local `SyncPeer` (normally the
+//! `SyncEngine`). Chunks, hash trees and deltas come back in `ChunkData`
+//! frames, all other replies in `SyncRequest` frames. A delta request is
+//! followed by the signature in a `ChunkData` frame.
+//!
+//! Each call is bounded by a timeout of `Timeouts`: the chunk stall
+//! timeout on `ChunkTransfer`, the request timeout otherwise. On links
+//! that seal frames, `SyncMeta` requests and replies are sealed.
+
+use std::sync::Arc;
+
+use nomade_quic::frame::MAX_FRAME_SIZE;
+use nomade_quic::{
+    Channel, ChannelId, Connection, Frame, FrameSeal, MessageType, ProtocolError, ReplayGuard,
+    TimeoutPhase, Timeouts,
+};
+use nomade_storage::HashTree;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    BoxFuture, Changes, Delta, ManifestEntry, RemoteArtifact, Result, Signature, SyncCursor,
+    SyncError, SyncPeer,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Manifest,
+    Changes {
+        cursor: Option<SyncCursor>,
+    },
+    Artifact {
+        id: String,
+    },
+    Chunk {
+        content_hash: String,
+        index: u32,
+        /// zstd level the reply may be compressed at
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        compression: Option<i32>,
+    },
+    HashTree {
+        content_hash: String,
+    },
+    /// Followed by the `Signature` in a `ChunkData` frame
+    Delta {
+        content_hash: String,
+    },
+}
+
+impl Request {
+    fn compression(&self) -> Option<i32> {
+        match self {
+            Request::Chunk { compression, .. } => *compression,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    Manifest { entries: Vec<ManifestEntry> },
+    Changes { changes: Changes },
+    Artifact { artifact: RemoteArtifact },
+    Error { message: String },
+}
+
+/// `SyncPeer` reached through a transport connection
+pub struct RemotePeer {
+    connection: Arc<dyn Connection>,
+    compression: Option<i32>,
+    timeouts: Timeouts,
+    /// Session counters for the channels of each call
+    replay: Option<ReplayGuard>,
+    /// Seal of the peer's sync payloads
+    seal: Option<Arc<dyn FrameSeal>>,
+}
+
+impl RemotePeer {
+    /// Wrap a connection to a device running `serve`
+    pub fn new(connection: Arc<dyn Connection>) -> Self {
+        Self {
+            connection,
+            compression: None,
+            timeouts: Timeouts::default(),
+            replay: None,
+            seal: None,
+        }
+    }
+
+    /// Bound requests and chunk transfers by `timeouts`
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Ask the peer to compress chunks at zstd `level`
+    ///
+    /// Peers that predate compression ignore the request and send chunks
+    /// uncompressed.
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression = Some(level);
+        self
+    }
+
+    /// Sequence and check frames with `guard`, shared with the session's
+    /// other channels
+    ///
+    /// Only when the peer negotiated `FeatureFlags::SEQUENCED_FRAMES`.
+    pub fn with_replay_guard(mut self, guard: ReplayGuard) -> Self {
+        self.replay = Some(guard);
+        self
+    }
+
+    /// Seal requests and open replies on `SyncMeta` channels with `seal`
+    ///
+    /// Only when the peer negotiated `FeatureFlags::SIGNED_FRAMES`.
+    pub fn with_seal(mut self, seal: Arc<dyn FrameSeal>) -> Self {
+        self.seal = Some(seal);
+        self
+    }
+
+    async fn call(&self, request: &Request) -> Result<Frame> {
+        self.call_with(request, None).await
+    }
+
+    /// Send a request, then `data` in a `ChunkData` frame if given
+    async fn call_with(&self, request: &Request, data: Option<Vec<u8>>) -> Result<Frame> {
+        let (id, phase) = match request {
+            Request::Chunk { .. } | Request::HashTree { .. } | Request::Delta { .. } => {
+                (ChannelId::ChunkTransfer, TimeoutPhase::ChunkStall)
+            }
+            _ => (ChannelId::SyncMeta, TimeoutPhase::Request),
+        };
+        self.timeouts
+            .enforce(phase, async {
+                let mut channel = Channel::open(self.connection.as_ref(), id).await?;
+                channel.set_replay_guard(self.replay.clone());
+                channel.set_seal(self.seal.clone());
+                let frame =
+                    Frame::from_message(MessageType::SyncRequest, request).map_err(peer_error)?;
+                channel.send(&frame).await?;
+                if let Some(data) = data {
+                    channel
+                        .send(&Frame::new(MessageType::ChunkData, data))
+                        .await?;
+                }
+                channel.finish().await?;
+                read_reply(&mut channel).await
+            })
+            .await
+    }
+
+    async fn call_response(&self, request: &Request) -> Result<Response> {
+        let frame = self.call(request).await?;
+        if frame.message_type != MessageType::SyncRequest {
+            return Err(peer_error(ProtocolError::UnexpectedMessage(
+                frame.message_type,
+            )));
+        }
+        frame.to_message().map_err(peer_error)
+    }
+
+    /// Call a request answered with binary `ChunkData`
+    async fn call_data(&self, request: &Request) -> Result<Vec<u8>> {
+        self.call_data_with(request, None).await
+    }
+
+    async fn call_data_with(&self, request: &Request, data: Option<Vec<u8>>) -> Result<Vec<u8>> {
+        let frame = self.call_with(request, data).await?;
+        match frame.message_type {
+            MessageType::ChunkData => Ok(frame.payload),
+            MessageType::SyncRequest => Err(unexpected(frame.to_message().map_err(peer_error)?)),
+            other => Err(peer_error(ProtocolError::UnexpectedMessage(other))),
+        }
+    }
+}
+
+async fn read_reply(channel: &mut Channel) -> Result<Frame> {
+    channel
+        .recv()
+        .await?
+        .ok_or_else(|| peer_error(ProtocolError::Truncated))
+}
+
+impl SyncPeer for RemotePeer {
+    fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+        Box::pin(async move {
+            match self.call_response(&Request::Manifest).await? {
+                Response::Manifest { entries } => Ok(entries),
+                other => Err(unexpected(other)),
+            }
+        })
+    }
+
+    fn changes<'a>(&'a self, cursor: Option<&'a SyncCursor>) -> BoxFuture<'a, Result<Changes>> {
+        Box::pin(async move {
+            let request = Request::Changes {
+                cursor: cursor.cloned(),
+            };
+            match self.call_response(&request).await? {
+                Response::Changes { changes } => Ok(changes),
+                // Peers that predate cursors reject the request
+                Response::Error { message } => {
+                    tracing::debug!("Falling back to the full manifest: {}", message);
+                    Ok(Changes::full(self.manifest().await?))
+                }
+                other => Err(unexpected(other)),
+            }
+        })
+    }
+
+    fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+        Box::pin(async move {
+            let request = Request::Artifact { id: id.to_string() };
+            match self.call_response(&request).await? {
+                Response::Artifact { artifact } => Ok(artifact),
+                other => Err(unexpected(other)),
+            }
+        })
+    }
+
+    fn fetch_chunk<'a>(
+        &'a self,
+        content_hash: &'a str,
+        index: u32,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let request = Request::Chunk {
+                content_hash: content_hash.to_string(),
+                index,
+                compression: self.compression,
+            };
+            self.call_data(&request).await
+        })
+    }
+
+    fn fetch_hash_tree<'a>(&'a self, content_hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+        Box::pin(async move {
+            let request = Request::HashTree {
+                content_hash: content_hash.to_string(),
+            };
+            Ok(HashTree::from_bytes(&self.call_data(&request).await?)?)
+        })
+    }
+
+    fn fetch_delta<'a>(
+        &'a self,
+        content_hash: &'a str,
+        signature: &'a Signature,
+    ) -> BoxFuture<'a, Result<Delta>> {
+        Box::pin(async move {
+            let request = Request::Delta {
+                content_hash: content_hash.to_string(),
+            };
+            let delta = self
+                .call_data_with(&request, Some(signature.to_bytes()))
+                .await?;
+            Delta::from_bytes(&delta)
+        })
+    }
+}
+
+/// Answer sync requests arriving on `connection` until it closes
+pub async fn serve(local: Arc<dyn SyncPeer>, connection: Arc<dyn Connection>) -> Result<()> {
+    while let Some(channel) = Channel::accept(connection.as_ref())
+        .await
+        .map_err(peer_error)?
+    {
+        if !matches!(channel.id(), ChannelId::SyncMeta | ChannelId::ChunkTransfer) {
+            tracing::debug!("Ignoring {:?} channel from sync peer", channel.id());
+            continue;
+        }
+        tokio::spawn(answer_channel(local.clone(), channel));
+    }
+    Ok(())
+}
+
+/// Answer sync requests on channels routed by a `ChannelRouter`
+///
+/// Use instead of `serve` when other subsystems share the connection.
+pub async fn serve_channels(local: Arc<dyn SyncPeer>, mut channels: UnboundedReceiver<Channel>) {
+    while let Some(channel) = channels.recv().await {
+        tokio::spawn(answer_channel(local.clone(), channel));
+    }
+}
+
+async fn answer_channel(local: Arc<dyn SyncPeer>, mut channel: Channel) {
+    let reply = match read_request(&mut channel).await {
+        Ok(Request::Delta { content_hash }) => {
+            answer_delta(local.as_ref(), &mut channel, &content_hash).await
+        }
+        Ok(request) => {
+            channel.set_compression(request.compression());
+            answer(local.as_ref(), request).await
+        }
+        Err(e) => error_frame(e),
+    };
+    if let Err(e) = channel.send(&reply).await {
+        tracing::debug!("Failed to send sync reply: {}", e);
+    }
+    channel.finish().await.ok();
+}
+
+async fn read_request(channel: &mut Channel) -> Result<Request> {
+    let frame = read_reply(channel).await?;
+    if frame.message_type != MessageType::SyncRequest {
+        return Err(peer_error(ProtocolError::UnexpectedMessage(
+            frame.message_type,
+        )));
+    }
+    frame.to_message().map_err(peer_error)
+}
+
+async fn answer(local: &dyn SyncPeer, request: Request) -> Frame {
+    let response = match request {
+        Request::Manifest => local
+            .manifest()
+            .await
+            .map(|entries| Response::Manifest { entries }),
+        Request::Changes { cursor } => local
+            .changes(cursor.as_ref())
+            .await
+            .map(|changes| Response::Changes { changes }),
+        Request::Artifact { id } => local
+            .fetch_artifact(&id)
+            .await
+            .map(|artifact| Response::Artifact { artifact }),
+        Request::Chunk {
+            content_hash,
+            index,
+            ..
+        } => {
+            return match local.fetch_chunk(&content_hash, index).await {
+                Ok(chunk) => Frame::new(MessageType::ChunkData, chunk),
+                Err(e) => error_frame(e),
+            }
+        }
+        Request::Delta { .. } => Err(SyncError::Peer("Delta request without signature".into())),
+        Request::HashTree { content_hash } => {
+            return match local.fetch_hash_tree(&content_hash).await {
+                Ok(tree) => Frame::new(MessageType::ChunkData, tree.to_bytes()),
+                Err(e) => error_frame(e),
+            }
+        }
+    };
+    match response {
+        Ok(response) => response_frame(&response),
+        Err(e) => error_frame(e),
+    }
+}
+
+async fn answer_delta(local: &dyn SyncPeer, channel: &mut Channel, content_hash: &str) -> Frame {
+    let delta = async {
+        let frame = read_reply(channel).await?;
+        if frame.message_type != MessageType::ChunkData {
+            return Err(peer_error(ProtocolError::UnexpectedMessage(
+                frame.message_type,
+            )));
+        }
+        let signature = Signature::from_bytes(&frame.payload)?;
+        let delta = local
+            .fetch_delta(content_hash, &signature)
+            .await?
+            .to_bytes();
+        // Leave room for the frame header; the puller fetches chunks instead
+        if delta.len() + 16 > MAX_FRAME_SIZE {
+            return Err(SyncError::Peer("Delta too large".into()));
+        }
+        Ok(delta)
+    }
+    .await;
+    match delta {
+        Ok(delta) => Frame::new(MessageType::ChunkData, delta),
+        Err(e) => error_frame(e),
+    }
+}
+
+fn response_frame(response: &Response) -> Frame {
+    // Responses only contain JSON-safe types
+    Frame::from_message(MessageType::SyncRequest, response)
+        .expect("sync responses always serialize")
+}
+
+fn error_frame(error: SyncError) -> Frame {
+    response_frame(&Response::Error {
+        message: error.peer_message(),
+    })
+}
+
+fn unexpected(response: Response) -> SyncError {
+    match response {
+        Response::Error { message } => SyncError::Peer(message),
+        other => SyncError::Peer(format!("Unexpected response: {:?}", other)),
+    }
+}
+
+fn peer_error(e: ProtocolError) -> SyncError {
+    SyncError::Peer(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SyncEngine, SyncState, CHUNK_SIZE};
+    use nomade_events::EventStream;
+    use nomade_quic::{MemoryNetwork, Transport};
+    use nomade_storage::{content_hash, Artifact, InMemoryStore};
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    #[tokio::test]
+    async fn test_sync_over_memory_transport() {
+        let laptop = engine();
+        let phone = engine();
+        let content = vec![3u8; CHUNK_SIZE + 17];
+        let hash = content_hash(&content);
+        phone.content().put_content(&hash, &content).unwrap();
+        phone
+            .store()
+            .store(&Artifact {
+                id: "notes".into(),
+                modified_at: 1,
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let network = MemoryNetwork::new();
+        let phone_endpoint = network.bind("phone").unwrap();
+        let laptop_endpoint = network.bind("laptop").unwrap();
+        let dialed = laptop_endpoint.connect("phone").await.unwrap();
+        let accepted = phone_endpoint.accept().await.unwrap().unwrap();
+        let server = tokio::spawn(serve(phone.clone(), accepted));
+
+        let remote = Arc::new(RemotePeer::new(dialed.clone()).with_compression(3));
+        assert!(remote.fetch_artifact("missing").await.is_err());
+        assert!(remote.fetch_chunk("missing", 0).await.is_err());
+        let changes = remote.changes(None).await.unwrap();
+        assert!(changes.full && changes.cursor.is_none());
+        assert_eq!(changes.entries, phone.manifest().unwrap());
+
+        let progress = laptop.start_sync("phone", remote).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert_eq!(laptop.manifest().unwrap(), phone.manifest().unwrap());
+
+        dialed.close();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_modified_content_syncs_as_delta() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        /// Counts chunks served
+        struct Counting {
+            inner: Arc<SyncEngine>,
+            chunks: AtomicU32,
+        }
+
+        impl SyncPeer for Counting {
+            fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+                SyncPeer::manifest(self.inner.as_ref())
+            }
+
+            fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+                self.inner.fetch_artifact(id)
+            }
+
+            fn fetch_chunk<'a>(
+                &'a self,
+                hash: &'a str,
+                index: u32,
+            ) -> BoxFuture<'a, Result<Vec<u8>>> {
+                self.chunks.fetch_add(1, Ordering::SeqCst);
+                self.inner.fetch_chunk(hash, index)
+            }
+
+            fn fetch_hash_tree<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+                self.inner.fetch_hash_tree(hash)
+            }
+
+            fn fetch_delta<'a>(
+                &'a self,
+                hash: &'a str,
+                signature: &'a Signature,
+            ) -> BoxFuture<'a, Result<Delta>> {
+                self.inner.fetch_delta(hash, signature)
+            }
+        }
+
+        let store = |engine: &SyncEngine, content: &[u8], modified_at: u64| {
+            let hash = content_hash(content);
+            engine.content().put_content(&hash, content).unwrap();
+            engine
+                .store()
+                .store(&Artifact {
+                    id: "video".into(),
+                    modified_at,
+                    content_hash: hash,
+                    ..Default::default()
+                })
+                .unwrap();
+        };
+        let old: Vec<u8> = (0..CHUNK_SIZE * 6).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new = old.clone();
+        new.insert(100, 42);
+        let (laptop, phone) = (engine(), engine());
+        store(&laptop, &old, 1);
+        store(&phone, &new, 2);
+
+        let network = MemoryNetwork::new();
+        let phone_endpoint = network.bind("phone").unwrap();
+        let laptop_endpoint = network.bind("laptop").unwrap();
+        let dialed = laptop_endpoint.connect("phone").await.unwrap();
+        let accepted = phone_endpoint.accept().await.unwrap().unwrap();
+        let served = Arc::new(Counting {
+            inner: phone.clone(),
+            chunks: AtomicU32::new(0),
+        });
+        let server = tokio::spawn(serve(served.clone(), accepted));
+
+        let remote = Arc::new(RemotePeer::new(dialed.clone()));
+        let progress = laptop.start_sync("phone", remote).unwrap().wait().await;
+        assert_eq!(progress.state, SyncState::Completed);
+        assert_eq!(served.chunks.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            laptop.content().get_content(&content_hash(&new)).unwrap(),
+            Some(new)
+        );
+
+        dialed.close();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unanswered_request_times_out() {
+        let network = MemoryNetwork::new();
+        let phone_endpoint = network.bind("phone").unwrap();
+        let laptop_endpoint = network.bind("laptop").unwrap();
+        let dialed = laptop_endpoint.connect("phone").await.unwrap();
+        // Accepted, but nothing serves it
+        let _accepted = phone_endpoint.accept().await.unwrap().unwrap();
+
+        let remote = RemotePeer::new(dialed).with_timeouts(Timeouts {
+            request_secs: 2,
+            ..Timeouts::default()
+        });
+        let result = remote.manifest().await;
+        assert!(matches!(
+            result,
+            Err(SyncError::Network(ProtocolError::Timeout(
+                TimeoutPhase::Request
+            )))
+        ));
+        let result = remote.fetch_chunk("missing", 0).await;
+        assert!(matches!(
+            result,
+            Err(SyncError::Network(ProtocolError::Timeout(
+                TimeoutPhase::ChunkStall
+            )))
+        ));
+    }
+}
@@ -0,0 +1,230 @@
+//! Selective sync rules
+//!
+//! Each peer can carry an ordered list of include/exclude rules matching
+//! artifacts by tag, collection, type or content size. The first matching
+//! rule decides; artifacts matching no rule fall back to the default
+//! action. Rules are applied when pulling from a peer, and changing them
+//! triggers a re-evaluation of the local artifacts.
+
+use std::collections::BTreeSet;
+
+use nomade_storage::Artifact;
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, SyncEngine};
+
+/// Whether a matching artifact is synced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    #[default]
+    Include,
+    Exclude,
+}
+
+/// Artifact property a rule matches on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMatcher {
+    Tag(String),
+    Collection(String),
+    ArtifactType(String),
+    /// Content larger than this many bytes
+    LargerThan(u64),
+}
+
+impl RuleMatcher {
+    fn matches(&self, artifact: &Artifact, size: u64) -> bool {
+        match self {
+            Self::Tag(tag) => artifact.tags.contains(tag),
+            Self::Collection(id) => artifact.collection.as_ref() == Some(id),
+            Self::ArtifactType(kind) => artifact.artifact_type.as_ref() == Some(kind),
+            Self::LargerThan(threshold) => size > *threshold,
+        }
+    }
+}
+
+/// Single selection rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRule {
+    pub action: RuleAction,
+    pub matcher: RuleMatcher,
+}
+
+impl SyncRule {
+    /// Include artifacts matching `matcher`
+    pub fn include(matcher: RuleMatcher) -> Self {
+        Self {
+            action: RuleAction::Include,
+            matcher,
+        }
+    }
+
+    /// Exclude artifacts matching `matcher`
+    pub fn exclude(matcher: RuleMatcher) -> Self {
+        Self {
+            action: RuleAction::Exclude,
+            matcher,
+        }
+    }
+}
+
+/// Ordered selection rules for one peer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRules {
+    #[serde(default)]
+    pub rules: Vec<SyncRule>,
+    /// Action for artifacts matching no rule
+    #[serde(default)]
+    pub default: RuleAction,
+}
+
+impl SyncRules {
+    /// Rules syncing everything
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Whether an artifact with `size` bytes of content is synced
+    pub fn allows(&self, artifact: &Artifact, size: u64) -> bool {
+        let action = self
+            .rules
+            .iter()
+            .find(|rule| rule.matcher.matches(artifact, size))
+            .map_or(self.default, |rule| rule.action);
+        action == RuleAction::Include
+    }
+}
+
+/// Effect of a rule change on the local artifacts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleEvaluation {
+    /// Artifacts now synced with the peer that were not before
+    pub newly_included: Vec<String>,
+    /// Artifacts no longer synced with the peer
+    pub newly_excluded: Vec<String>,
+}
+
+impl SyncEngine {
+    /// Selection rules for a peer
+    pub fn rules(&self, peer_id: &str) -> SyncRules {
+        self.rules
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replace a peer's rules and re-evaluate local artifacts against them
+    ///
+    /// A change forgets the peer's cursor, so artifacts the old rules
+    /// skipped are pulled on the next sync.
+    pub fn set_rules(&self, peer_id: &str, rules: SyncRules) -> Result<RuleEvaluation> {
+        let previous = self
+            .rules
+            .lock()
+            .unwrap()
+            .insert(peer_id.to_string(), rules.clone())
+            .unwrap_or_default();
+        if previous != rules {
+            self.reset_cursor(peer_id);
+        }
+
+        let mut newly_included = BTreeSet::new();
+        let mut newly_excluded = BTreeSet::new();
+        for artifact in self.store.list()? {
+            let size = self.content_size(&artifact.content_hash)?;
+            let before = previous.allows(&artifact, size);
+            let after = rules.allows(&artifact, size);
+            if after && !before {
+                newly_included.insert(artifact.id);
+            } else if before && !after {
+                newly_excluded.insert(artifact.id);
+            }
+        }
+        Ok(RuleEvaluation {
+            newly_included: newly_included.into_iter().collect(),
+            newly_excluded: newly_excluded.into_iter().collect(),
+        })
+    }
+
+    /// Size of locally stored content, 0 if missing
+    pub(crate) fn content_size(&self, content_hash: &str) -> Result<u64> {
+        Ok(self
+            .content
+            .get_content(content_hash)?
+            .map_or(0, |data| data.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomade_events::EventStream;
+    use nomade_storage::{content_hash, ArtifactStore, ContentStore, InMemoryStore};
+    use std::sync::Arc;
+
+    fn artifact(id: &str, tags: &[&str], collection: Option<&str>, kind: &str) -> Artifact {
+        Artifact {
+            id: id.into(),
+            title: id.into(),
+            artifact_type: Some(kind.into()),
+            collection: collection.map(Into::into),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = SyncRules {
+            rules: vec![
+                SyncRule::include(RuleMatcher::Tag("pinned".into())),
+                SyncRule::exclude(RuleMatcher::LargerThan(1024)),
+                SyncRule::exclude(RuleMatcher::Collection("private".into())),
+                SyncRule::include(RuleMatcher::ArtifactType("note".into())),
+            ],
+            default: RuleAction::Exclude,
+        };
+
+        let note = artifact("a", &[], None, "note");
+        assert!(rules.allows(&note, 10));
+        assert!(!rules.allows(&note, 4096));
+        assert!(!rules.allows(&artifact("b", &[], Some("private"), "note"), 10));
+        assert!(rules.allows(&artifact("c", &["pinned"], Some("private"), "note"), 4096));
+        assert!(!rules.allows(&artifact("d", &[], None, "image"), 10));
+        assert!(SyncRules::all().allows(&artifact("d", &[], None, "image"), 10));
+    }
+
+    #[test]
+    fn test_set_rules_reevaluates() {
+        let store = Arc::new(InMemoryStore::new());
+        for (a, content) in [
+            (artifact("small", &[], None, "note"), vec![0u8; 10]),
+            (artifact("big", &[], None, "video"), vec![0u8; 2048]),
+        ] {
+            let hash = content_hash(&content);
+            store.put_content(&hash, &content).unwrap();
+            store
+                .store(&Artifact {
+                    content_hash: hash,
+                    ..a
+                })
+                .unwrap();
+        }
+        let engine = SyncEngine::new(store.clone(), store, EventStream::new());
+
+        let rules = SyncRules {
+            rules: vec![SyncRule::exclude(RuleMatcher::LargerThan(1024))],
+            default: RuleAction::Include,
+        };
+        let evaluation = engine.set_rules("phone", rules.clone()).unwrap();
+        assert_eq!(evaluation.newly_excluded, vec!["big"]);
+        assert!(evaluation.newly_included.is_empty());
+        assert_eq!(engine.rules("phone"), rules);
+
+        let evaluation = engine.set_rules("phone", SyncRules::all()).unwrap();
+        assert_eq!(evaluation.newly_included, vec!["big"]);
+    }
+}
@@ -0,0 +1,855 @@
+//! Sync sessions with progress reporting and cancellation
+//!
+//! A session pulls every artifact the peer has a newer version of. Content
+//! is fetched chunk by chunk; progress is published on a watch channel
+//! after each chunk. Cancelling a session stops it between (or during)
+//! chunk fetches and keeps the chunks already received, so the next
+//! session resumes where this one stopped.
+//!
+//! Artifacts whose content is already present go first, as they cost only
+//! a metadata write, then the rest smallest first.
+
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::Ordering as AtomicOrdering;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use nomade_events::{Event, RepairStatus};
+use nomade_storage::{content_hash, HashTree};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::engine::SessionEntry;
+use crate::{ManifestEntry, RemoteArtifact, Result, SyncEngine, SyncError, SyncPeer, CHUNK_SIZE};
+
+/// Lifecycle state of a sync session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// Progress of a sync session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncProgress {
+    pub session_id: u64,
+    pub peer_id: String,
+    pub state: SyncState,
+    /// 0.0 to 100.0
+    pub percent: f32,
+    /// Artifact currently being transferred
+    pub current_artifact: Option<String>,
+    pub artifacts_synced: usize,
+    pub bytes_transferred: u64,
+    pub total_bytes: u64,
+    /// Transfer rate over the session so far
+    pub bytes_per_sec: f64,
+    /// Failure reason when `state` is `Failed`
+    pub error: Option<String>,
+}
+
+impl SyncProgress {
+    fn new(session_id: u64, peer_id: String) -> Self {
+        Self {
+            session_id,
+            peer_id,
+            state: SyncState::Running,
+            percent: 0.0,
+            current_artifact: None,
+            artifacts_synced: 0,
+            bytes_transferred: 0,
+            total_bytes: 0,
+            bytes_per_sec: 0.0,
+            error: None,
+        }
+    }
+}
+
+/// Handle to a running sync session
+pub struct SyncHandle {
+    pub id: u64,
+    progress: watch::Receiver<SyncProgress>,
+}
+
+impl SyncHandle {
+    /// Latest progress
+    pub fn progress(&self) -> SyncProgress {
+        self.progress.borrow().clone()
+    }
+
+    /// Receiver notified on every progress update
+    pub fn subscribe(&self) -> watch::Receiver<SyncProgress> {
+        self.progress.clone()
+    }
+
+    /// Wait for the session to finish and return its final progress
+    pub async fn wait(mut self) -> SyncProgress {
+        let _ = self
+            .progress
+            .wait_for(|p| p.state != SyncState::Running)
+            .await;
+        self.progress()
+    }
+}
+
+/// Progress bookkeeping for one session
+pub(crate) struct Tracker<'a> {
+    pub(crate) tx: &'a watch::Sender<SyncProgress>,
+    pub(crate) started: Instant,
+    /// Bytes resumed from earlier sessions, excluded from the rate
+    pub(crate) resumed: u64,
+}
+
+impl Tracker<'_> {
+    fn add_bytes(&self, bytes: u64) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let resumed = self.resumed;
+        self.tx.send_modify(|p| {
+            p.bytes_transferred += bytes;
+            if p.total_bytes > 0 {
+                p.percent = (p.bytes_transferred as f64 / p.total_bytes as f64 * 100.0) as f32;
+            }
+            if elapsed > 0.0 {
+                p.bytes_per_sec = p.bytes_transferred.saturating_sub(resumed) as f64 / elapsed;
+            }
+        });
+    }
+}
+
+impl SyncEngine {
+    /// Start pulling changes from a peer in the background
+    pub fn start_sync(
+        self: &Arc<Self>,
+        peer_id: impl Into<String>,
+        peer: Arc<dyn SyncPeer>,
+    ) -> Result<SyncHandle> {
+        let (id, cancel, tx) = self.register_session(peer_id.into())?;
+        let progress = tx.subscribe();
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let mut remaining = VecDeque::new();
+            let result = engine
+                .run_session(peer.as_ref(), &tx, &cancel, u64::MAX, &mut remaining)
+                .await;
+            engine.finish_session(id, &tx, &result);
+        });
+        Ok(SyncHandle { id, progress })
+    }
+
+    pub(crate) fn register_session(
+        &self,
+        peer_id: String,
+    ) -> Result<(u64, CancellationToken, watch::Sender<SyncProgress>)> {
+        self.ensure_running()?;
+        let id = self.next_session.fetch_add(1, AtomicOrdering::Relaxed);
+        let cancel = self.shutdown.child_token();
+        let (tx, rx) = watch::channel(SyncProgress::new(id, peer_id));
+        self.sessions.lock().unwrap().insert(
+            id,
+            SessionEntry {
+                cancel: cancel.clone(),
+                progress: rx,
+            },
+        );
+        Ok((id, cancel, tx))
+    }
+
+    pub(crate) fn finish_session(
+        &self,
+        id: u64,
+        tx: &watch::Sender<SyncProgress>,
+        result: &Result<()>,
+    ) {
+        tx.send_modify(|p| {
+            p.current_artifact = None;
+            match result {
+                Ok(()) => {
+                    p.state = SyncState::Completed;
+                    p.percent = 100.0;
+                }
+                Err(SyncError::Cancelled) => p.state = SyncState::Cancelled,
+                Err(e) => {
+                    tracing::warn!("Sync session {} failed: {}", p.session_id, e);
+                    p.state = SyncState::Failed;
+                    p.error = Some(e.to_string());
+                }
+            }
+        });
+        self.sessions.lock().unwrap().remove(&id);
+    }
+
+    /// Cancel a running session, keeping its partial progress
+    ///
+    /// Returns `false` if no such session is running.
+    pub fn cancel_sync(&self, id: u64) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Progress receiver of a running session
+    pub fn sync_progress(&self, id: u64) -> Option<watch::Receiver<SyncProgress>> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.progress.clone())
+    }
+
+    /// IDs of running sessions
+    pub fn active_sessions(&self) -> Vec<u64> {
+        self.sessions.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Pull the plan's artifacts, fetching at most `max_bytes` of content
+    ///
+    /// `remaining` holds the planned artifacts not applied yet, in fetch
+    /// order, including those past the byte limit.
+    pub(crate) async fn run_session(
+        &self,
+        peer: &dyn SyncPeer,
+        tx: &watch::Sender<SyncProgress>,
+        cancel: &CancellationToken,
+        max_bytes: u64,
+        remaining: &mut VecDeque<RemoteArtifact>,
+    ) -> Result<()> {
+        self.events.publish(Event::SyncStarted);
+        let peer_id = tx.borrow().peer_id.clone();
+        // Versions to repair may not have changed since the cursor
+        let cursor = match self.repairs.lock().unwrap().is_empty() {
+            true => self.cursor(&peer_id),
+            false => None,
+        };
+        let changes = cancellable(cancel, peer.changes(cursor.as_ref())).await?;
+        let remote = changes.entries;
+        let plan = self.plan(&remote)?;
+        let local: HashSet<ManifestEntry> = self.manifest()?.into_iter().collect();
+        self.conflicts
+            .agree(remote.iter().filter(|entry| local.contains(*entry)))?;
+        if !changes.full {
+            // Unchanged versions listed as changes had their key rotated
+            for entry in remote.iter().filter(|entry| local.contains(*entry)) {
+                self.follow_key(peer, entry, cancel).await?;
+            }
+        }
+        let rules = self.rules(&peer_id);
+
+        let mut artifacts = Vec::with_capacity(plan.download.len());
+        for id in &plan.download {
+            let started = Instant::now();
+            let remote = cancellable(cancel, peer.fetch_artifact(id)).await?;
+            self.scores.record_latency(&peer_id, started.elapsed());
+            if self.accepts_from(&peer_id, &remote.artifact)
+                && rules.allows(&remote.artifact, remote.size)
+            {
+                artifacts.push(remote);
+            }
+        }
+        // Metadata whose content is already here first, then smallest
+        // first, so an interrupted session lands as many changes as it can
+        artifacts.sort_by_cached_key(|remote| self.fetch_cost(remote));
+        let mut allowance = max_bytes;
+        let affordable = artifacts
+            .iter()
+            .take_while(
+                |remote| match allowance.checked_sub(self.fetch_cost(remote)) {
+                    Some(left) => {
+                        allowance = left;
+                        true
+                    }
+                    None => false,
+                },
+            )
+            .count();
+        remaining.extend(artifacts.iter().cloned());
+        artifacts.truncate(affordable);
+        let total_bytes = artifacts.iter().map(|a| a.size).sum();
+        tx.send_modify(|p| p.total_bytes = total_bytes);
+
+        let mut tracker = Tracker {
+            tx,
+            started: Instant::now(),
+            resumed: 0,
+        };
+        for remote in &artifacts {
+            let id = remote.artifact.id.clone();
+            tx.send_modify(|p| p.current_artifact = Some(id));
+            if self.content.has_content(&remote.artifact.content_hash)? {
+                // Content already present (e.g. shared with another artifact)
+                tracker.resumed += remote.size;
+                tracker.add_bytes(remote.size);
+            } else {
+                self.download(&[(&peer_id, peer)], remote, &mut tracker, cancel)
+                    .await?;
+            }
+            self.apply_remote(&peer_id, &remote.artifact)?;
+            if self.repairs.lock().unwrap().remove(&remote.artifact.id) {
+                self.events.publish(Event::ArtifactCorrupted {
+                    id: remote.artifact.id.clone(),
+                    status: RepairStatus::Repaired,
+                });
+            }
+            remaining.pop_front();
+            tx.send_modify(|p| p.artifacts_synced += 1);
+        }
+        if let (true, Some(cursor)) = (remaining.is_empty(), changes.cursor) {
+            self.acknowledge(&peer_id, cursor);
+        }
+
+        self.events.publish(Event::SyncCompleted {
+            artifacts_synced: artifacts.len(),
+        });
+        Ok(())
+    }
+
+    /// Content bytes still to fetch for an artifact
+    pub(crate) fn fetch_cost(&self, remote: &RemoteArtifact) -> u64 {
+        if self
+            .content
+            .has_content(&remote.artifact.content_hash)
+            .unwrap_or(false)
+        {
+            return 0;
+        }
+        let partial = self
+            .partials
+            .lock()
+            .unwrap()
+            .get(&remote.artifact.content_hash)
+            .map_or(0, |data| data.len() as u64);
+        remote.size.saturating_sub(partial)
+    }
+
+    /// Adopt the peer's current data key for content held here
+    async fn follow_key(
+        &self,
+        peer: &dyn SyncPeer,
+        entry: &ManifestEntry,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        let remote = match cancellable(cancel, peer.fetch_artifact(&entry.id)).await {
+            Ok(remote) => remote,
+            Err(SyncError::Cancelled) => return Err(SyncError::Cancelled),
+            Err(e) => {
+                tracing::debug!("Key of {} not checked: {}", entry.id, e);
+                return Ok(());
+            }
+        };
+        if let Some(shares) = &remote.key_shares {
+            if let Err(e) = self.content.adopt_key_shares(&entry.content_hash, shares) {
+                tracing::debug!("Key of {} not adopted: {}", entry.id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetch the content of one artifact, resuming any partial download
+    ///
+    /// Chunks are spread over `sources`, best first, each pulling the next
+    /// missing chunk as it finishes one, so faster sources take more.
+    /// Transient failures are retried under the engine's `RetryPolicy`; a
+    /// source that still fails or serves a bad chunk is dropped and its
+    /// chunk left to the others.
+    pub(crate) async fn download(
+        &self,
+        sources: &[(&str, &dyn SyncPeer)],
+        remote: &RemoteArtifact,
+        tracker: &mut Tracker<'_>,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        let hash = &remote.artifact.content_hash;
+        if let Some(shares) = &remote.key_shares {
+            // Keep the content under the key the peer shared with us
+            if let Err(e) = self.content.adopt_key_shares(hash, shares) {
+                tracing::debug!("Key of {} not adopted: {}", remote.artifact.id, e);
+            }
+        }
+        let resuming = self.partials.lock().unwrap().contains_key(hash);
+        if let (false, Some((peer_id, peer))) = (resuming, sources.first()) {
+            match self.fetch_by_delta(*peer, remote, cancel).await {
+                Ok(true) => {
+                    tracker.add_bytes(remote.size);
+                    return Ok(());
+                }
+                Ok(false) => {}
+                Err(SyncError::Cancelled) => return Err(SyncError::Cancelled),
+                Err(e) => tracing::debug!(
+                    "Delta of {} from {} failed, fetching chunks: {}",
+                    remote.artifact.id,
+                    peer_id,
+                    e
+                ),
+            }
+        }
+        let mut data = self
+            .partials
+            .lock()
+            .unwrap()
+            .remove(hash)
+            .unwrap_or_default();
+        let chunks = Mutex::new(Vec::new());
+
+        let result = async {
+            // Verify each chunk on arrival, not only the whole content
+            let tree = self.fetch_tree(sources, remote, cancel).await?;
+            let intact = data
+                .chunks(CHUNK_SIZE)
+                .enumerate()
+                .take_while(|(index, chunk)| tree.verify_group(*index, chunk))
+                .count();
+            data.truncate(intact * CHUNK_SIZE);
+            if !data.is_empty() {
+                tracing::debug!("Resuming {} at {} bytes", remote.artifact.id, data.len());
+                tracker.resumed += data.len() as u64;
+                tracker.add_bytes(data.len() as u64);
+            }
+
+            let first = intact;
+            let count = remote.chunk_count() as usize;
+            *chunks.lock().unwrap() = vec![None; count.saturating_sub(first)];
+            let transfer = Transfer {
+                engine: self,
+                remote,
+                tree: &tree,
+                queue: Mutex::new((first..count).collect()),
+                chunks: &chunks,
+                first,
+                tracker: &*tracker,
+                cancel,
+            };
+            let mut healthy = sources.to_vec();
+            let mut failure = None;
+            while !transfer.queue.lock().unwrap().is_empty() {
+                if healthy.is_empty() {
+                    return Err(
+                        failure.unwrap_or_else(|| SyncError::NotFound(remote.artifact.id.clone()))
+                    );
+                }
+                let pulls = healthy.iter().map(|source| transfer.pull(*source));
+                let results = futures::future::join_all(pulls).await;
+                let mut still_healthy = Vec::with_capacity(healthy.len());
+                for (source, result) in healthy.into_iter().zip(results) {
+                    match result {
+                        Ok(()) => still_healthy.push(source),
+                        Err(SyncError::Cancelled) => return Err(SyncError::Cancelled),
+                        Err(e) => {
+                            tracing::debug!("Dropping {} as a source: {}", source.0, e);
+                            self.scores.record_failure(source.0);
+                            failure = Some(e);
+                        }
+                    }
+                }
+                healthy = still_healthy;
+            }
+            Ok(())
+        }
+        .await;
+
+        // Whole chunks in order after the resumed prefix
+        for chunk in chunks.into_inner().unwrap() {
+            match chunk {
+                Some(chunk) => data.extend_from_slice(&chunk),
+                None => break,
+            }
+        }
+        if let Err(e) = result {
+            // Keep verified whole chunks so the next session can resume
+            data.truncate(data.len() / CHUNK_SIZE * CHUNK_SIZE);
+            if !data.is_empty() {
+                self.partials.lock().unwrap().insert(hash.clone(), data);
+            }
+            return Err(e);
+        }
+        if content_hash(&data) != *hash {
+            return Err(SyncError::HashMismatch(remote.artifact.id.clone()));
+        }
+        self.content.put_content(hash, &data)?;
+        // Peers were not served the artifact while its content was missing
+        self.change_log.record(&remote.artifact.id)?;
+        Ok(())
+    }
+
+    /// Hash tree of the content from the first source serving a valid one
+    async fn fetch_tree(
+        &self,
+        sources: &[(&str, &dyn SyncPeer)],
+        remote: &RemoteArtifact,
+        cancel: &CancellationToken,
+    ) -> Result<HashTree> {
+        let hash = &remote.artifact.content_hash;
+        let mut failure = SyncError::HashMismatch(remote.artifact.id.clone());
+        for (peer_id, peer) in sources {
+            match cancellable(cancel, peer.fetch_hash_tree(hash)).await {
+                Ok(tree) if tree.verify(hash).is_ok() && tree.len() == remote.size => {
+                    return Ok(tree)
+                }
+                Ok(_) => failure = SyncError::HashMismatch(remote.artifact.id.clone()),
+                Err(SyncError::Cancelled) => return Err(SyncError::Cancelled),
+                Err(e) => failure = e,
+            }
+            self.scores.record_failure(peer_id);
+        }
+        Err(failure)
+    }
+}
+
+/// Chunks of one download shared between its sources
+struct Transfer<'a> {
+    engine: &'a SyncEngine,
+    remote: &'a RemoteArtifact,
+    tree: &'a HashTree,
+    /// Indexes of chunks nobody is fetching yet
+    queue: Mutex<VecDeque<usize>>,
+    /// Verified chunks, from index `first`
+    chunks: &'a Mutex<Vec<Option<Vec<u8>>>>,
+    first: usize,
+    tracker: &'a Tracker<'a>,
+    cancel: &'a CancellationToken,
+}
+
+impl Transfer<'_> {
+    /// Fetch queued chunks from one source until none are left
+    async fn pull(&self, (peer_id, peer): (&str, &dyn SyncPeer)) -> Result<()> {
+        let hash = &self.remote.artifact.content_hash;
+        loop {
+            self.engine.wait_unpaused(self.cancel).await?;
+            let Some(index) = self.queue.lock().unwrap().pop_front() else {
+                return Ok(());
+            };
+            let started = Instant::now();
+            let fetch = self
+                .engine
+                .retry
+                .run("Chunk fetch", || peer.fetch_chunk(hash, index as u32));
+            let fetched = cancellable(self.cancel, fetch)
+                .await
+                .and_then(|chunk| self.check(index, chunk));
+            match fetched {
+                Ok(chunk) => {
+                    let len = chunk.len() as u64;
+                    self.engine
+                        .scores
+                        .record_transfer(peer_id, len, started.elapsed());
+                    self.chunks.lock().unwrap()[index - self.first] = Some(chunk);
+                    self.tracker.add_bytes(len);
+                }
+                Err(e) => {
+                    self.queue.lock().unwrap().push_front(index);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn check(&self, index: usize, chunk: Vec<u8>) -> Result<Vec<u8>> {
+        let offset = (index * CHUNK_SIZE) as u64;
+        let expected = (self.remote.size - offset).min(CHUNK_SIZE as u64);
+        if chunk.len() as u64 != expected {
+            return Err(SyncError::Peer(format!(
+                "Invalid chunk {} for {}",
+                index, self.remote.artifact.id
+            )));
+        }
+        if !self.tree.verify_group(index, &chunk) {
+            return Err(SyncError::HashMismatch(self.remote.artifact.id.clone()));
+        }
+        Ok(chunk)
+    }
+}
+
+/// Await a peer request unless the session is cancelled first
+pub(crate) async fn cancellable<T>(
+    cancel: &CancellationToken,
+    request: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(SyncError::Cancelled),
+        result = request => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoxFuture, ManifestEntry, RuleMatcher, SyncRule, SyncRules, TransferMode};
+    use nomade_events::EventStream;
+    use nomade_storage::{Artifact, HashTree, InMemoryStore};
+    use std::sync::atomic::AtomicU32;
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    fn add_artifact(engine: &SyncEngine, id: &str, content: &[u8]) {
+        let hash = content_hash(content);
+        engine.content().put_content(&hash, content).unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: id.into(),
+                title: id.into(),
+                created_at: 0,
+                modified_at: 1,
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+    }
+
+    /// Peer serving another engine, blocking after `limit` chunks
+    struct GatedPeer {
+        inner: Arc<SyncEngine>,
+        served: AtomicU32,
+        limit: u32,
+        /// Chunk served with a flipped bit
+        corrupt: Option<u32>,
+    }
+
+    impl GatedPeer {
+        fn new(inner: Arc<SyncEngine>, limit: u32) -> Self {
+            Self {
+                inner,
+                served: AtomicU32::new(0),
+                limit,
+                corrupt: None,
+            }
+        }
+    }
+
+    impl SyncPeer for GatedPeer {
+        fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+            SyncPeer::manifest(self.inner.as_ref())
+        }
+
+        fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+            SyncPeer::fetch_artifact(self.inner.as_ref(), id)
+        }
+
+        fn fetch_chunk<'a>(
+            &'a self,
+            content_hash: &'a str,
+            index: u32,
+        ) -> BoxFuture<'a, Result<Vec<u8>>> {
+            if self.served.fetch_add(1, AtomicOrdering::SeqCst) >= self.limit {
+                return Box::pin(std::future::pending());
+            }
+            Box::pin(async move {
+                let mut chunk = self.inner.fetch_chunk(content_hash, index).await?;
+                if self.corrupt == Some(index) {
+                    chunk[0] ^= 1;
+                }
+                Ok(chunk)
+            })
+        }
+
+        fn fetch_hash_tree<'a>(&'a self, content_hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+            self.inner.fetch_hash_tree(content_hash)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_pulls_artifacts_with_progress() {
+        let laptop = engine();
+        let phone = engine();
+        add_artifact(&phone, "small", b"hello");
+        add_artifact(&phone, "large", &vec![7u8; CHUNK_SIZE * 2 + 10]);
+
+        let handle = laptop.start_sync("phone", phone.clone()).unwrap();
+        let progress = handle.wait().await;
+
+        assert_eq!(progress.state, SyncState::Completed);
+        assert_eq!(progress.artifacts_synced, 2);
+        assert_eq!(progress.percent, 100.0);
+        assert_eq!(progress.bytes_transferred, progress.total_bytes);
+        assert_eq!(laptop.manifest().unwrap(), phone.manifest().unwrap());
+        assert!(laptop.active_sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sync_carries_key_shares() {
+        use nomade_crypto::{
+            generate_key, generate_keypair, KeyContext, KeyRecipient, NonceSequence,
+        };
+        use nomade_storage::{ContentStore, EncryptedContentStore};
+
+        let identities = [generate_keypair(), generate_keypair()];
+        let devices: Vec<_> = identities
+            .iter()
+            .map(|keypair| KeyRecipient {
+                device_id: keypair.device_id().clone(),
+                public_key: keypair.public_key_bytes(),
+            })
+            .collect();
+        let [laptop, phone] = identities.map(|identity| {
+            let store = Arc::new(InMemoryStore::new());
+            let content = EncryptedContentStore::new(
+                store.clone(),
+                KeyContext::new(generate_key(), NonceSequence::counter()),
+            )
+            .with_identity(identity);
+            content.share_keys(&devices, store.as_ref()).unwrap();
+            Arc::new(SyncEngine::new(
+                store,
+                Arc::new(content),
+                EventStream::new(),
+            ))
+        });
+        add_artifact(&phone, "notes", b"encrypted notes");
+
+        let progress = laptop
+            .start_sync("phone", phone.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(progress.state, SyncState::Completed);
+        let hash = content_hash(b"encrypted notes");
+        let shares = phone.content().key_shares(&hash).unwrap().unwrap();
+        assert_eq!(laptop.content().key_shares(&hash).unwrap().unwrap(), shares);
+        assert_eq!(
+            laptop.content().get_content(&hash).unwrap().unwrap(),
+            b"encrypted notes"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_refetches_quarantined_content() {
+        let laptop = engine();
+        let phone = engine();
+        add_artifact(&laptop, "note", b"body");
+        add_artifact(&phone, "note", b"body");
+        let hash = content_hash(b"body");
+        laptop.content().delete_content(&hash).unwrap();
+
+        // Same version on both sides: nothing to do until a repair is asked
+        let progress = laptop
+            .start_sync("phone", phone.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(progress.artifacts_synced, 0);
+
+        let mut events = laptop.events.subscribe();
+        laptop.request_repair(["note".to_string()]);
+        let progress = laptop.start_sync("phone", phone).unwrap().wait().await;
+        assert_eq!(progress.artifacts_synced, 1);
+        assert!(laptop.content().has_content(&hash).unwrap());
+        assert!(laptop.pending_repairs().is_empty());
+        let repaired = loop {
+            if let Event::ArtifactCorrupted { id, status } = events.recv().await.unwrap() {
+                break (id, status);
+            }
+        };
+        assert_eq!(repaired, ("note".to_string(), RepairStatus::Repaired));
+    }
+
+    #[tokio::test]
+    async fn test_sync_honors_peer_rules() {
+        let laptop = engine();
+        let phone = engine();
+        add_artifact(&phone, "small", b"hello");
+        add_artifact(&phone, "large", &vec![7u8; 1000]);
+        let rules = SyncRules {
+            rules: vec![SyncRule::exclude(RuleMatcher::LargerThan(100))],
+            ..Default::default()
+        };
+        laptop.set_rules("phone", rules).unwrap();
+
+        let progress = laptop.start_sync("phone", phone).unwrap().wait().await;
+        assert_eq!(progress.artifacts_synced, 1);
+        assert!(laptop.store().get("small").unwrap().is_some());
+        assert!(laptop.store().get("large").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_keeps_partial_progress() {
+        let laptop = engine();
+        let phone = engine();
+        let content = vec![3u8; CHUNK_SIZE * 3];
+        add_artifact(&phone, "video", &content);
+
+        let gated = Arc::new(GatedPeer::new(phone.clone(), 2));
+        let handle = laptop.start_sync("phone", gated).unwrap();
+        let mut progress = handle.subscribe();
+        progress
+            .wait_for(|p| p.bytes_transferred == 2 * CHUNK_SIZE as u64)
+            .await
+            .unwrap();
+        assert!(laptop.cancel_sync(handle.id));
+
+        let cancelled = handle.wait().await;
+        assert_eq!(cancelled.state, SyncState::Cancelled);
+        assert!(laptop.store().get("video").unwrap().is_none());
+
+        // Resuming only fetches the missing chunk
+        let gated = Arc::new(GatedPeer::new(phone.clone(), 1));
+        let resumed = laptop.start_sync("phone", gated).unwrap().wait().await;
+        assert_eq!(resumed.state, SyncState::Completed);
+        assert_eq!(
+            laptop
+                .content()
+                .get_content(&content_hash(&content))
+                .unwrap()
+                .unwrap(),
+            content
+        );
+    }
+
+    #[tokio::test]
+    async fn test_paused_transfers_wait_for_resume() {
+        let laptop = engine();
+        let phone = engine();
+        add_artifact(&phone, "video", &vec![5u8; CHUNK_SIZE * 2]);
+
+        laptop.set_transfer_mode(TransferMode::Paused);
+        let handle = laptop.start_sync("phone", phone.clone()).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let progress = handle.progress();
+        assert_eq!(progress.state, SyncState::Running);
+        assert_eq!(progress.bytes_transferred, 0);
+
+        laptop.set_transfer_mode(TransferMode::Reduced);
+        let done = handle.wait().await;
+        assert_eq!(done.state, SyncState::Completed);
+        assert_eq!(done.bytes_transferred, CHUNK_SIZE as u64 * 2);
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_chunk_fails_before_download_completes() {
+        let laptop = engine();
+        let phone = engine();
+        let content: Vec<u8> = (0..CHUNK_SIZE * 4).map(|i| i as u8).collect();
+        add_artifact(&phone, "video", &content);
+
+        let tampering = Arc::new(GatedPeer {
+            corrupt: Some(1),
+            ..GatedPeer::new(phone.clone(), u32::MAX)
+        });
+        let failed = laptop
+            .start_sync("phone", tampering.clone())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(failed.state, SyncState::Failed);
+        // Stopped at the bad chunk instead of fetching the rest
+        assert_eq!(tampering.served.load(AtomicOrdering::SeqCst), 2);
+        let hash = content_hash(&content);
+        assert_eq!(laptop.partials.lock().unwrap()[&hash].len(), CHUNK_SIZE);
+
+        let honest = Arc::new(GatedPeer::new(phone, 3));
+        let resumed = laptop.start_sync("phone", honest).unwrap().wait().await;
+        assert_eq!(resumed.state, SyncState::Completed);
+        assert_eq!(
+            laptop.content().get_content(&hash).unwrap().unwrap(),
+            content
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Serving shared artifacts to guests holding a share token
+//!
+//! Guests are not paired devices, so they never run a sync session.
+//! Instead `fetch_shared` presents a `ShareToken` on a `ChunkTransfer`
+//! channel and `serve_shares` answers with the one artifact it names, its
+//! content encrypted under the token's key so a relay in between learns
+//! nothing. The reply is a `SyncRequest` frame with the artifact metadata,
+//! followed by `ChunkData` frames carrying the ciphertext.
+
+use std::sync::{Arc, RwLock};
+
+use nomade_crypto::{decrypt_data, encrypt_data, EncryptedData, ShareRegistry, ShareToken};
+use nomade_quic::{Channel, ChannelId, Connection, Frame, MessageType, ProtocolError};
+use nomade_storage::{content_hash, Artifact};
+use serde::{Deserialize, Serialize};
+
+use crate::{Result, SyncError, SyncPeer, CHUNK_SIZE};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareRequest {
+    token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ShareResponse {
+    Artifact {
+        artifact: Box<Artifact>,
+        nonce: Vec<u8>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Fetch the artifact a share token grants access to, with its content
+pub async fn fetch_shared(
+    connection: &dyn Connection,
+    token: &ShareToken,
+) -> Result<(Artifact, Vec<u8>)> {
+    let request = ShareRequest {
+        token: token.encode()?,
+    };
+    let mut channel = Channel::open(connection, ChannelId::ChunkTransfer)
+        .await
+        .map_err(peer_error)?;
+    let frame = Frame::from_message(MessageType::SyncRequest, &request).map_err(peer_error)?;
+    channel.send(&frame).await.map_err(peer_error)?;
+    channel.finish().await.map_err(peer_error)?;
+
+    let reply = channel
+        .recv()
+        .await
+        .map_err(peer_error)?
+        .ok_or_else(|| peer_error(ProtocolError::Truncated))?;
+    let (artifact, nonce) = match reply.to_message().map_err(peer_error)? {
+        ShareResponse::Artifact { artifact, nonce } => (*artifact, nonce),
+        ShareResponse::Error { message } => return Err(SyncError::Peer(message)),
+    };
+    let mut ciphertext = Vec::new();
+    while let Some(frame) = channel.recv().await.map_err(peer_error)? {
+        if frame.message_type != MessageType::ChunkData {
+            return Err(peer_error(ProtocolError::UnexpectedMessage(
+                frame.message_type,
+            )));
+        }
+        ciphertext.extend_from_slice(&frame.payload);
+    }
+
+    let encrypted = EncryptedData {
+        ciphertext,
+        nonce,
+        algorithm: "AES-256-GCM".into(),
+    };
+    let content = decrypt_data(&encrypted, &token.content_key()?)?;
+    if content_hash(&content) != artifact.content_hash {
+        return Err(SyncError::HashMismatch(artifact.id));
+    }
+    Ok((artifact, content))
+}
+
+/// Answer share token requests arriving on `connection` until it closes
+///
+/// Only the artifact named by a valid, unrevoked token is ever served;
+/// every other request is refused.
+pub async fn serve_shares(
+    local: Arc<dyn SyncPeer>,
+    shares: Arc<RwLock<ShareRegistry>>,
+    connection: Arc<dyn Connection>,
+) -> Result<()> {
+    while let Some(channel) = Channel::accept(connection.as_ref())
+        .await
+        .map_err(peer_error)?
+    {
+        if channel.id() != ChannelId::ChunkTransfer {
+            tracing::debug!("Ignoring {:?} channel from share guest", channel.id());
+            continue;
+        }
+        tokio::spawn(answer_share(local.clone(), shares.clone(), channel));
+    }
+    Ok(())
+}
+
+async fn answer_share(
+    local: Arc<dyn SyncPeer>,
+    shares: Arc<RwLock<ShareRegistry>>,
+    mut channel: Channel,
+) {
+    let frames = match shared_content(local.as_ref(), &shares, &mut channel).await {
+        Ok(frames) => frames,
+        Err(e) => {
+            tracing::debug!("Refused share request: {}", e);
+            vec![response_frame(&ShareResponse::Error {
+                message: e.peer_message(),
+            })]
+        }
+    };
+    for frame in &frames {
+        if let Err(e) = channel.send(frame).await {
+            tracing::debug!("Failed to send shared artifact: {}", e);
+            return;
+        }
+    }
+    channel.finish().await.ok();
+}
+
+/// Reply frames for one share request
+async fn shared_content(
+    local: &dyn SyncPeer,
+    shares: &RwLock<ShareRegistry>,
+    channel: &mut Channel,
+) -> Result<Vec<Frame>> {
+    let frame = channel
+        .recv()
+        .await
+        .map_err(peer_error)?
+        .ok_or_else(|| peer_error(ProtocolError::Truncated))?;
+    if frame.message_type != MessageType::SyncRequest {
+        return Err(peer_error(ProtocolError::UnexpectedMessage(
+            frame.message_type,
+        )));
+    }
+    let request: ShareRequest = frame.to_message().map_err(peer_error)?;
+    let token = ShareToken::decode(&request.token)?;
+    let artifact_id = shares.read().unwrap().check(&token)?.to_string();
+
+    let remote = local.fetch_artifact(&artifact_id).await?;
+    let mut content = Vec::with_capacity(remote.size as usize);
+    for index in 0..remote.size.div_ceil(CHUNK_SIZE as u64) {
+        let chunk = local
+            .fetch_chunk(&remote.artifact.content_hash, index as u32)
+            .await?;
+        content.extend_from_slice(&chunk);
+    }
+    let encrypted = encrypt_data(&content, &token.content_key()?)?;
+
+    let mut frames = vec![response_frame(&ShareResponse::Artifact {
+        artifact: Box::new(remote.artifact),
+        nonce: encrypted.nonce,
+    })];
+    frames.extend(
+        encrypted
+            .ciphertext
+            .chunks(CHUNK_SIZE)
+            .map(|chunk| Frame::new(MessageType::ChunkData, chunk.to_vec())),
+    );
+    Ok(frames)
+}
+
+fn response_frame(response: &ShareResponse) -> Frame {
+    Frame::from_message(MessageType::SyncRequest, response)
+        .expect("share responses always serialize")
+}
+
+fn peer_error(e: ProtocolError) -> SyncError {
+    SyncError::Peer(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SyncEngine;
+    use nomade_crypto::generate_keypair;
+    use nomade_events::EventStream;
+    use nomade_quic::{MemoryNetwork, Transport};
+    use nomade_storage::{ArtifactStore, ContentStore, InMemoryStore};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_guest_fetches_shared_artifact_until_revoked() {
+        let store = Arc::new(InMemoryStore::new());
+        let laptop = Arc::new(SyncEngine::new(
+            store.clone(),
+            store.clone(),
+            EventStream::new(),
+        ));
+        for (id, body) in [
+            ("notes", vec![7u8; CHUNK_SIZE + 5]),
+            ("diary", vec![1u8; 9]),
+        ] {
+            let hash = content_hash(&body);
+            store.put_content(&hash, &body).unwrap();
+            store
+                .store(&Artifact {
+                    id: id.into(),
+                    content_hash: hash,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+
+        let keypair = generate_keypair();
+        let shares = Arc::new(RwLock::new(ShareRegistry::new(*keypair.verifying_key())));
+        let token = shares
+            .write()
+            .unwrap()
+            .issue(&keypair, "notes".into(), Duration::from_secs(60))
+            .unwrap();
+
+        let network = MemoryNetwork::new();
+        let laptop_endpoint = network.bind("laptop").unwrap();
+        let guest = network.bind("guest").unwrap();
+        let dialed = guest.connect("laptop").await.unwrap();
+        let accepted = laptop_endpoint.accept().await.unwrap().unwrap();
+        let server = tokio::spawn(serve_shares(laptop.clone(), shares.clone(), accepted));
+
+        let (artifact, content) = fetch_shared(dialed.as_ref(), &token).await.unwrap();
+        assert_eq!(artifact.id, "notes");
+        assert_eq!(content, vec![7u8; CHUNK_SIZE + 5]);
+
+        // A token for another artifact cannot be forged from this one
+        let mut forged = token.clone();
+        forged.artifact_id = "diary".into();
+        assert!(fetch_shared(dialed.as_ref(), &forged).await.is_err());
+
+        shares.write().unwrap().revoke(&token.id).unwrap();
+        assert!(fetch_shared(dialed.as_ref(), &token).await.is_err());
+
+        dialed.close();
+        server.await.unwrap().unwrap();
+    }
+}
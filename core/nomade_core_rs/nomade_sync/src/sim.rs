@@ -0,0 +1,430 @@
+//! Deterministic sync simulator
+//!
+//! Runs N virtual devices in one process, each with its own in-memory
+//! stores and `SyncEngine`, connected by simulated links that inject
+//! latency, message drops and partitions drawn from a seeded RNG. Run it
+//! on a current-thread tokio runtime with paused time
+//! (`#[tokio::test(start_paused = true)]`) and the same seed replays the
+//! same schedule, so a failing seed is a reproducible bug report.
+//!
+//! ```ignore
+//! let sim = Simulator::new(42, 3).with_faults(Faults::lossy());
+//! sim.write(0, "note", b"hello");
+//! sim.run_until_converged(20).await.unwrap();
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nomade_events::EventStream;
+use nomade_storage::{
+    content_hash, Artifact, ArtifactStore, ContentStore, HashTree, InMemoryStore,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::peer::BoxFuture;
+use crate::{ManifestEntry, RemoteArtifact, Result, SyncEngine, SyncError, SyncPeer, SyncState};
+
+/// Faults injected on every simulated request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faults {
+    /// Minimum one-way latency
+    pub min_latency: Duration,
+    /// Maximum one-way latency
+    pub max_latency: Duration,
+    /// Probability in `[0, 1]` that a request is lost
+    pub drop_rate: f64,
+}
+
+impl Faults {
+    /// Instant, lossless links
+    pub fn none() -> Self {
+        Self {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            drop_rate: 0.0,
+        }
+    }
+
+    /// Typical mobile conditions: 10–300 ms latency, 10% loss
+    pub fn lossy() -> Self {
+        Self {
+            min_latency: Duration::from_millis(10),
+            max_latency: Duration::from_millis(300),
+            drop_rate: 0.1,
+        }
+    }
+}
+
+/// Outcome of one simulated request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered,
+    Dropped,
+    Partitioned,
+}
+
+/// Entry of the simulation trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Simulated time since the simulator was created
+    pub at: Duration,
+    /// Device issuing the request
+    pub from: usize,
+    /// Device serving it
+    pub to: usize,
+    /// Request kind and argument, e.g. "chunk abc…#3"
+    pub request: String,
+    pub delivery: Delivery,
+}
+
+/// One virtual device
+pub struct SimDevice {
+    pub name: String,
+    pub store: Arc<InMemoryStore>,
+    pub engine: Arc<SyncEngine>,
+}
+
+struct Shared {
+    rng: Mutex<StdRng>,
+    faults: Mutex<Faults>,
+    /// Unordered pairs of devices that cannot reach each other
+    partitions: Mutex<BTreeSet<(usize, usize)>>,
+    trace: Mutex<Vec<TraceEntry>>,
+    started: tokio::time::Instant,
+}
+
+impl Shared {
+    /// Decide a request's fate and sleep for its latency
+    async fn transmit(&self, from: usize, to: usize, request: String) -> Result<()> {
+        let partitioned = self
+            .partitions
+            .lock()
+            .unwrap()
+            .contains(&(from.min(to), from.max(to)));
+        let (delivery, latency) = {
+            let faults = self.faults.lock().unwrap().clone();
+            let mut rng = self.rng.lock().unwrap();
+            let latency = if faults.max_latency > faults.min_latency {
+                rng.gen_range(faults.min_latency..=faults.max_latency)
+            } else {
+                faults.min_latency
+            };
+            let delivery = if partitioned {
+                Delivery::Partitioned
+            } else if rng.gen_bool(faults.drop_rate.clamp(0.0, 1.0)) {
+                Delivery::Dropped
+            } else {
+                Delivery::Delivered
+            };
+            (delivery, latency)
+        };
+
+        tokio::time::sleep(latency).await;
+        self.trace.lock().unwrap().push(TraceEntry {
+            at: self.started.elapsed(),
+            from,
+            to,
+            request,
+            delivery,
+        });
+        match delivery {
+            Delivery::Delivered => Ok(()),
+            Delivery::Dropped => Err(SyncError::Peer("request dropped".into())),
+            Delivery::Partitioned => Err(SyncError::Peer("peer unreachable".into())),
+        }
+    }
+}
+
+/// Simulated link from one device to another
+struct SimLink {
+    shared: Arc<Shared>,
+    from: usize,
+    to: usize,
+    target: Arc<SyncEngine>,
+}
+
+impl SyncPeer for SimLink {
+    fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+        Box::pin(async move {
+            self.shared
+                .transmit(self.from, self.to, "manifest".into())
+                .await?;
+            SyncPeer::manifest(self.target.as_ref()).await
+        })
+    }
+
+    fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+        Box::pin(async move {
+            self.shared
+                .transmit(self.from, self.to, format!("artifact {}", id))
+                .await?;
+            self.target.fetch_artifact(id).await
+        })
+    }
+
+    fn fetch_chunk<'a>(
+        &'a self,
+        content_hash: &'a str,
+        index: u32,
+    ) -> BoxFuture<'a, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let request = format!(
+                "chunk {}#{}",
+                &content_hash[..content_hash.len().min(8)],
+                index
+            );
+            self.shared.transmit(self.from, self.to, request).await?;
+            self.target.fetch_chunk(content_hash, index).await
+        })
+    }
+
+    fn fetch_hash_tree<'a>(&'a self, content_hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+        Box::pin(async move {
+            let request = format!("tree {}", &content_hash[..content_hash.len().min(8)]);
+            self.shared.transmit(self.from, self.to, request).await?;
+            self.target.fetch_hash_tree(content_hash).await
+        })
+    }
+}
+
+/// Network of virtual devices
+pub struct Simulator {
+    shared: Arc<Shared>,
+    devices: Vec<SimDevice>,
+    /// Logical clock stamping writes, so versions are totally ordered
+    clock: Mutex<u64>,
+    /// Newest version written of each artifact, the expected end state
+    expected: Mutex<BTreeMap<String, ManifestEntry>>,
+}
+
+impl Simulator {
+    /// Create `devices` empty devices with RNG `seed`
+    pub fn new(seed: u64, devices: usize) -> Self {
+        let devices = (0..devices)
+            .map(|i| {
+                let store = Arc::new(InMemoryStore::new());
+                let engine = Arc::new(SyncEngine::new(
+                    store.clone(),
+                    store.clone(),
+                    EventStream::new(),
+                ));
+                SimDevice {
+                    name: format!("device-{}", i),
+                    store,
+                    engine,
+                }
+            })
+            .collect();
+        Self {
+            shared: Arc::new(Shared {
+                rng: Mutex::new(StdRng::seed_from_u64(seed)),
+                faults: Mutex::new(Faults::none()),
+                partitions: Mutex::new(BTreeSet::new()),
+                trace: Mutex::new(Vec::new()),
+                started: tokio::time::Instant::now(),
+            }),
+            devices,
+            clock: Mutex::new(0),
+            expected: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Set the faults injected on every link
+    pub fn with_faults(self, faults: Faults) -> Self {
+        self.set_faults(faults);
+        self
+    }
+
+    /// Change the injected faults
+    pub fn set_faults(&self, faults: Faults) {
+        *self.shared.faults.lock().unwrap() = faults;
+    }
+
+    /// Virtual devices
+    pub fn devices(&self) -> &[SimDevice] {
+        &self.devices
+    }
+
+    /// Create or overwrite an artifact on one device
+    pub fn write(&self, device: usize, id: &str, content: &[u8]) {
+        let modified_at = {
+            let mut clock = self.clock.lock().unwrap();
+            *clock += 1;
+            *clock
+        };
+        let hash = content_hash(content);
+        let store = &self.devices[device].store;
+        store.put_content(&hash, content).unwrap();
+        let artifact = Artifact {
+            id: id.to_string(),
+            title: id.to_string(),
+            created_at: modified_at,
+            modified_at,
+            content_hash: hash,
+            ..Default::default()
+        };
+        store.store(&artifact).unwrap();
+        self.expected
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), ManifestEntry::from(&artifact));
+    }
+
+    /// Cut the link between two devices in both directions
+    pub fn partition(&self, a: usize, b: usize) {
+        self.shared
+            .partitions
+            .lock()
+            .unwrap()
+            .insert((a.min(b), a.max(b)));
+    }
+
+    /// Restore all links
+    pub fn heal(&self) {
+        self.shared.partitions.lock().unwrap().clear();
+    }
+
+    /// Requests made so far, in completion order
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        self.shared.trace.lock().unwrap().clone()
+    }
+
+    /// Every device pulls from every other device once, concurrently
+    ///
+    /// Returns the number of sessions that did not complete.
+    pub async fn sync_round(&self) -> usize {
+        let mut handles = Vec::new();
+        for (from, device) in self.devices.iter().enumerate() {
+            for (to, target) in self.devices.iter().enumerate() {
+                if from == to {
+                    continue;
+                }
+                let link = Arc::new(SimLink {
+                    shared: self.shared.clone(),
+                    from,
+                    to,
+                    target: target.engine.clone(),
+                });
+                match device.engine.start_sync(target.name.clone(), link) {
+                    Ok(handle) => handles.push(handle),
+                    Err(e) => tracing::warn!("Simulated sync not started: {}", e),
+                }
+            }
+        }
+        let mut failed = 0;
+        for handle in handles {
+            if handle.wait().await.state != SyncState::Completed {
+                failed += 1;
+            }
+        }
+        failed
+    }
+
+    /// Run rounds until all devices converge, up to `max_rounds`
+    ///
+    /// Returns the number of rounds taken, or the violated invariant.
+    pub async fn run_until_converged(
+        &self,
+        max_rounds: usize,
+    ) -> std::result::Result<usize, String> {
+        let mut violation = self.check_converged().err();
+        for round in 1..=max_rounds {
+            if violation.is_none() {
+                return Ok(round - 1);
+            }
+            self.sync_round().await;
+            violation = self.check_converged().err();
+        }
+        match violation {
+            None => Ok(max_rounds),
+            Some(violation) => Err(format!(
+                "not converged after {} rounds: {}",
+                max_rounds, violation
+            )),
+        }
+    }
+
+    /// Check the convergence invariants
+    ///
+    /// Every device holds exactly the newest written version of every
+    /// artifact, and its content is present and matches the hash.
+    pub fn check_converged(&self) -> std::result::Result<(), String> {
+        let expected: Vec<ManifestEntry> =
+            self.expected.lock().unwrap().values().cloned().collect();
+        for device in &self.devices {
+            let mut manifest = device.engine.manifest().map_err(|e| e.to_string())?;
+            manifest.sort_by(|a, b| a.id.cmp(&b.id));
+            if manifest != expected {
+                return Err(format!("{} has a diverging manifest", device.name));
+            }
+            for entry in &manifest {
+                let content = device
+                    .store
+                    .get_content(&entry.content_hash)
+                    .map_err(|e| e.to_string())?
+                    .ok_or_else(|| format!("{} lacks content of {}", device.name, entry.id))?;
+                if content_hash(&content) != entry.content_hash {
+                    return Err(format!(
+                        "{} has corrupt content of {}",
+                        device.name, entry.id
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content(seed: u8, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_converges_under_loss() {
+        let sim = Simulator::new(7, 4).with_faults(Faults::lossy());
+        sim.write(0, "big", &content(1, 5 * crate::CHUNK_SIZE + 17));
+        sim.write(1, "note", b"from one");
+        sim.write(2, "note", b"from two, newer");
+        sim.write(3, "todo", b"milk");
+
+        let rounds = sim.run_until_converged(30).await.unwrap();
+        assert!(rounds > 0);
+        assert!(sim
+            .trace()
+            .iter()
+            .any(|entry| entry.delivery == Delivery::Dropped));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partition_blocks_until_healed() {
+        let sim = Simulator::new(3, 2);
+        sim.partition(0, 1);
+        sim.write(0, "a", b"left");
+        sim.write(1, "b", b"right");
+
+        assert!(sim.run_until_converged(3).await.is_err());
+        sim.heal();
+        assert_eq!(sim.run_until_converged(3).await, Ok(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_same_seed_same_trace() {
+        async fn run(seed: u64) -> Vec<TraceEntry> {
+            let sim = Simulator::new(seed, 3).with_faults(Faults::lossy());
+            sim.write(0, "a", &content(0, 3 * crate::CHUNK_SIZE));
+            sim.write(1, "b", b"b");
+            sim.run_until_converged(30).await.unwrap();
+            sim.trace()
+        }
+        assert_eq!(run(11).await, run(11).await);
+    }
+}
@@ -0,0 +1,393 @@
+//! Peer selection and multi-source downloads
+//!
+//! When several devices hold the same content, `fetch_content` asks all of
+//! them for the artifact, ranks the ones offering its newest version with
+//! `PeerScores` and splits the chunks across the best few. Every chunk is
+//! verified against the hash tree on arrival, whichever peer sent it.
+//!
+//! Scores come from what sessions observe: request latency and chunk
+//! throughput, smoothed over time, plus failures. Peers can also report
+//! that they are low on battery or on a metered network, which makes them
+//! a last resort.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::session::Tracker;
+use crate::{
+    ManifestEntry, Result, SyncEngine, SyncError, SyncPeer, SyncProgress, TransferMode, CHUNK_SIZE,
+};
+
+/// Most peers one download is split across
+pub const MAX_SOURCES: usize = 4;
+
+/// Weight of a new sample in the smoothed latency and throughput
+const SMOOTHING: f64 = 0.3;
+
+/// Assumed for peers nothing was measured for yet
+const DEFAULT_LATENCY: f64 = 0.1;
+const DEFAULT_BYTES_PER_SEC: f64 = 1024.0 * 1024.0;
+
+/// State a peer reports about itself
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerHints {
+    #[serde(default)]
+    pub low_battery: bool,
+    #[serde(default)]
+    pub metered: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PeerStats {
+    /// Seconds
+    latency: Option<f64>,
+    bytes_per_sec: Option<f64>,
+    /// Recent failures; each success forgives one
+    failures: u32,
+    hints: PeerHints,
+}
+
+fn smooth(current: Option<f64>, sample: f64) -> f64 {
+    match current {
+        Some(current) => current + SMOOTHING * (sample - current),
+        None => sample,
+    }
+}
+
+/// Scores peers as download sources
+#[derive(Debug, Default)]
+pub struct PeerScores {
+    peers: Mutex<HashMap<String, PeerStats>>,
+}
+
+impl PeerScores {
+    /// Record how long a small request to a peer took
+    pub fn record_latency(&self, peer_id: &str, latency: Duration) {
+        let mut peers = self.peers.lock().unwrap();
+        let stats = peers.entry(peer_id.to_string()).or_default();
+        stats.latency = Some(smooth(stats.latency, latency.as_secs_f64()));
+    }
+
+    /// Record a chunk of `bytes` received from a peer in `elapsed`
+    pub fn record_transfer(&self, peer_id: &str, bytes: u64, elapsed: Duration) {
+        let rate = bytes as f64 / elapsed.as_secs_f64().max(1e-6);
+        let mut peers = self.peers.lock().unwrap();
+        let stats = peers.entry(peer_id.to_string()).or_default();
+        stats.bytes_per_sec = Some(smooth(stats.bytes_per_sec, rate));
+        stats.failures = stats.failures.saturating_sub(1);
+    }
+
+    /// Record a failed request or bad data from a peer
+    pub fn record_failure(&self, peer_id: &str) {
+        let mut peers = self.peers.lock().unwrap();
+        let stats = peers.entry(peer_id.to_string()).or_default();
+        stats.failures = stats.failures.saturating_add(1);
+    }
+
+    /// Replace the state a peer reported about itself
+    pub fn set_hints(&self, peer_id: &str, hints: PeerHints) {
+        let mut peers = self.peers.lock().unwrap();
+        peers.entry(peer_id.to_string()).or_default().hints = hints;
+    }
+
+    /// Expected seconds to fetch one chunk from a peer; lower is better
+    pub fn cost(&self, peer_id: &str) -> f64 {
+        let stats = self
+            .peers
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .copied()
+            .unwrap_or_default();
+        let transfer = CHUNK_SIZE as f64 / stats.bytes_per_sec.unwrap_or(DEFAULT_BYTES_PER_SEC);
+        let mut cost = stats.latency.unwrap_or(DEFAULT_LATENCY) + transfer;
+        if stats.hints.metered {
+            cost *= 4.0;
+        }
+        if stats.hints.low_battery {
+            cost *= 2.0;
+        }
+        cost * (1 + stats.failures) as f64
+    }
+
+    /// Sort peers best first by the ID `peer_id` picks out
+    pub fn rank<T>(&self, peers: &mut [T], peer_id: impl Fn(&T) -> &str) {
+        peers.sort_by_cached_key(|peer| {
+            // Costs are never NaN; whole nanoseconds order well enough
+            (self.cost(peer_id(peer)) * 1e9) as u64
+        });
+    }
+}
+
+impl SyncEngine {
+    /// Scores of the peers this engine fetched from
+    pub fn scores(&self) -> &PeerScores {
+        &self.scores
+    }
+
+    /// Fetch one artifact's content from whichever peers hold it
+    ///
+    /// The newest version among `sources` wins and replaces the local one.
+    /// Its chunks are split across the best `MAX_SOURCES` peers offering
+    /// it, or only the best one in `TransferMode::Reduced`. Runs as a
+    /// session, reported under the best peer.
+    pub async fn fetch_content(
+        &self,
+        sources: &[(String, Arc<dyn SyncPeer>)],
+        artifact_id: &str,
+    ) -> Result<SyncProgress> {
+        let offers = futures::future::join_all(sources.iter().map(|(peer_id, peer)| async move {
+            let started = Instant::now();
+            let offer = peer.fetch_artifact(artifact_id).await;
+            if offer.is_ok() {
+                self.scores.record_latency(peer_id, started.elapsed());
+            }
+            (peer_id.as_str(), peer.as_ref(), offer)
+        }))
+        .await;
+        let mut holders = Vec::new();
+        for (peer_id, peer, offer) in offers {
+            match offer {
+                Ok(remote) if self.accepts_from(peer_id, &remote.artifact) => {
+                    holders.push((peer_id, peer, remote))
+                }
+                Ok(_) => {}
+                Err(e) => tracing::debug!("{} cannot serve {}: {}", peer_id, artifact_id, e),
+            }
+        }
+        let newest = holders
+            .iter()
+            .map(|(_, _, remote)| ManifestEntry::from(&remote.artifact))
+            .max_by(|a, b| a.cmp_version(b))
+            .ok_or_else(|| SyncError::NotFound(artifact_id.to_string()))?;
+        holders.retain(|(_, _, remote)| ManifestEntry::from(&remote.artifact) == newest);
+        self.scores.rank(&mut holders, |(peer_id, _, _)| peer_id);
+        let remote = holders[0].2.clone();
+        // Disagreeing on the size means the same hash cannot verify
+        holders.retain(|(_, _, offer)| offer.size == remote.size);
+        holders.truncate(match self.transfer_mode() {
+            TransferMode::Reduced => 1,
+            _ => MAX_SOURCES,
+        });
+
+        let (id, cancel, tx) = self.register_session(holders[0].0.to_string())?;
+        let result = async {
+            tx.send_modify(|p| {
+                p.total_bytes = remote.size;
+                p.current_artifact = Some(artifact_id.to_string());
+            });
+            let mut tracker = Tracker {
+                tx: &tx,
+                started: Instant::now(),
+                resumed: 0,
+            };
+            if !self.content.has_content(&remote.artifact.content_hash)? {
+                let sources: Vec<(&str, &dyn SyncPeer)> = holders
+                    .iter()
+                    .map(|(peer_id, peer, _)| (*peer_id, *peer))
+                    .collect();
+                self.download(&sources, &remote, &mut tracker, &cancel)
+                    .await?;
+            }
+            self.apply_remote(holders[0].0, &remote.artifact)?;
+            tx.send_modify(|p| p.artifacts_synced = 1);
+            Ok(())
+        }
+        .await;
+        self.finish_session(id, &tx, &result);
+        result?;
+        let progress = tx.borrow().clone();
+        Ok(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BoxFuture, RemoteArtifact};
+    use nomade_events::EventStream;
+    use nomade_storage::{content_hash, Artifact, HashTree, InMemoryStore};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn engine() -> Arc<SyncEngine> {
+        let store = Arc::new(InMemoryStore::new());
+        Arc::new(SyncEngine::new(store.clone(), store, EventStream::new()))
+    }
+
+    /// Peer yielding before each chunk, like a network would
+    struct Source {
+        inner: Arc<SyncEngine>,
+        served: AtomicU32,
+        corrupt: bool,
+        /// Chunk requests left to fail as if the network dropped them
+        drops: AtomicU32,
+    }
+
+    impl Source {
+        fn new(inner: Arc<SyncEngine>, corrupt: bool) -> Arc<Self> {
+            Arc::new(Self {
+                inner,
+                served: AtomicU32::new(0),
+                corrupt,
+                drops: AtomicU32::new(0),
+            })
+        }
+    }
+
+    impl SyncPeer for Source {
+        fn manifest(&self) -> BoxFuture<'_, Result<Vec<ManifestEntry>>> {
+            SyncPeer::manifest(self.inner.as_ref())
+        }
+
+        fn fetch_artifact<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<RemoteArtifact>> {
+            self.inner.fetch_artifact(id)
+        }
+
+        fn fetch_chunk<'a>(&'a self, hash: &'a str, index: u32) -> BoxFuture<'a, Result<Vec<u8>>> {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                let dropped = self
+                    .drops
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                if dropped.is_ok() {
+                    return Err(nomade_quic::ProtocolError::PeerTimeout.into());
+                }
+                self.served.fetch_add(1, Ordering::SeqCst);
+                let mut chunk = self.inner.fetch_chunk(hash, index).await?;
+                if self.corrupt {
+                    chunk[0] ^= 1;
+                }
+                Ok(chunk)
+            })
+        }
+
+        fn fetch_hash_tree<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HashTree>> {
+            self.inner.fetch_hash_tree(hash)
+        }
+    }
+
+    fn holder(content: &[u8]) -> Arc<SyncEngine> {
+        let engine = engine();
+        let hash = content_hash(content);
+        engine.content().put_content(&hash, content).unwrap();
+        engine
+            .store()
+            .store(&Artifact {
+                id: "video".into(),
+                content_hash: hash,
+                ..Default::default()
+            })
+            .unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_rank_prefers_fast_peers_on_mains_power() {
+        let scores = PeerScores::default();
+        let mut peers = ["slow", "fast"];
+        scores.record_latency("fast", Duration::from_millis(5));
+        scores.record_transfer("fast", CHUNK_SIZE as u64, Duration::from_millis(32));
+        scores.rank(&mut peers, |peer| peer);
+        assert_eq!(peers, ["fast", "slow"]);
+
+        let drained = PeerHints {
+            low_battery: true,
+            metered: true,
+        };
+        scores.set_hints("fast", drained);
+        scores.rank(&mut peers, |peer| peer);
+        assert_eq!(peers, ["slow", "fast"]);
+
+        scores.set_hints("fast", PeerHints::default());
+        for _ in 0..20 {
+            scores.record_failure("fast");
+        }
+        scores.rank(&mut peers, |peer| peer);
+        assert_eq!(peers, ["slow", "fast"]);
+    }
+
+    #[tokio::test]
+    async fn test_chunks_split_across_sources() {
+        let content: Vec<u8> = (0..CHUNK_SIZE * 8).map(|i| (i % 251) as u8).collect();
+        let (a, b) = (
+            Source::new(holder(&content), false),
+            Source::new(holder(&content), false),
+        );
+        let phone = engine();
+        for peer_id in ["a", "b"] {
+            phone.set_permissions(peer_id, nomade_crypto::Permissions::full());
+        }
+        let sources: Vec<(String, Arc<dyn SyncPeer>)> =
+            vec![("a".into(), a.clone()), ("b".into(), b.clone())];
+
+        let progress = phone.fetch_content(&sources, "video").await.unwrap();
+        assert_eq!(progress.bytes_transferred, content.len() as u64);
+        let (served_a, served_b) = (
+            a.served.load(Ordering::SeqCst),
+            b.served.load(Ordering::SeqCst),
+        );
+        assert_eq!(served_a + served_b, 8);
+        assert!(served_a > 0 && served_b > 0);
+        let hash = content_hash(&content);
+        assert_eq!(
+            phone.content().get_content(&hash).unwrap().unwrap(),
+            content
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bad_source_is_dropped() {
+        let content = vec![4u8; CHUNK_SIZE * 4];
+        let (honest, liar) = (
+            Source::new(holder(&content), false),
+            Source::new(holder(&content), true),
+        );
+        let phone = engine();
+        for peer_id in ["honest", "liar"] {
+            phone.set_permissions(peer_id, nomade_crypto::Permissions::full());
+        }
+        // The liar looks best until it serves a bad chunk
+        phone
+            .scores()
+            .record_transfer("liar", CHUNK_SIZE as u64, Duration::from_millis(1));
+        let sources: Vec<(String, Arc<dyn SyncPeer>)> = vec![
+            ("honest".into(), honest.clone()),
+            ("liar".into(), liar.clone()),
+        ];
+
+        let progress = phone.fetch_content(&sources, "video").await.unwrap();
+        assert_eq!(progress.peer_id, "liar");
+        assert_eq!(liar.served.load(Ordering::SeqCst), 1);
+        assert_eq!(honest.served.load(Ordering::SeqCst), 4);
+        assert!(phone.scores().cost("liar") > phone.scores().cost("honest"));
+        assert_eq!(
+            phone
+                .content()
+                .get_content(&content_hash(&content))
+                .unwrap()
+                .unwrap(),
+            content
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transient_chunk_failures_are_retried() {
+        let content: Vec<u8> = (0..CHUNK_SIZE * 3).map(|i| (i % 251) as u8).collect();
+        let flaky = Source::new(holder(&content), false);
+        flaky.drops.store(2, Ordering::SeqCst);
+        let phone = engine();
+        phone.set_permissions("flaky", nomade_crypto::Permissions::full());
+        let sources: Vec<(String, Arc<dyn SyncPeer>)> = vec![("flaky".into(), flaky.clone())];
+
+        // The only source survives its dropped requests
+        phone.fetch_content(&sources, "video").await.unwrap();
+        assert_eq!(flaky.served.load(Ordering::SeqCst), 3);
+        assert!(phone
+            .content()
+            .has_content(&content_hash(&content))
+            .unwrap());
+    }
+}
@@ -0,0 +1,308 @@
+//! Multi-node harness for end-to-end tests
+//!
+//! A `Cluster` runs several `NomadeRuntime`s in one process, each with
+//! in-memory stores, serving a `MemoryNetwork` or localhost QUIC endpoint
+//! like the daemon serves its listener. Connecting two nodes dials one
+//! from the other and leaves the rest to the runtimes' links: admission,
+//! sync in both directions over the one connection, and the control
+//! frames (revocations and the like) the connection managers queue. The
+//! scenarios in `tests/` script pairing, edits, disconnects and
+//! revocations against it and check that devices converge.
+//!
+//! ```ignore
+//! let cluster = Cluster::new(Wire::Memory, &["laptop", "phone"])?;
+//! cluster.pair_all()?;
+//! cluster.connect("laptop", "phone").await?;
+//! cluster.write("laptop", "note", "hello")?;
+//! cluster.converge(&["laptop", "phone"]).await?;
+//! ```
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context as _, Result};
+use nomade_core::config::StorageBackend;
+use nomade_core::nomade_crypto::DeviceId;
+use nomade_core::nomade_events::{Event, EventReceiver};
+use nomade_core::nomade_quic::{MemoryNetwork, QuicTransport, Transport};
+use nomade_core::nomade_storage::{content_hash, Artifact};
+use nomade_core::nomade_sync::{SyncProgress, SyncState};
+use nomade_core::{Context, NomadeConfig, NomadeRuntime};
+use tempfile::TempDir;
+
+/// Sync rounds `Cluster::converge` runs before giving up
+pub const MAX_ROUNDS: usize = 8;
+
+/// How long `Cluster::connect` waits for both sides to link
+pub const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How nodes reach each other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wire {
+    /// `MemoryNetwork`, addressed by node name
+    Memory,
+    /// QUIC on 127.0.0.1
+    Quic,
+}
+
+/// Runtime for the device `name`, in its own directory under `dir`
+pub fn device(dir: &Path, name: &str, backend: StorageBackend) -> Result<Arc<NomadeRuntime>> {
+    let mut config = NomadeConfig::new(dir.join(name));
+    config.storage_backend = backend;
+    Ok(Arc::new(
+        NomadeRuntime::builder(Context::new(config)?).build()?,
+    ))
+}
+
+/// One device of the cluster
+pub struct Node {
+    pub name: String,
+    pub runtime: Arc<NomadeRuntime>,
+    transport: Arc<dyn Transport>,
+}
+
+impl Node {
+    /// Device ID of the node
+    pub fn id(&self) -> DeviceId {
+        self.runtime.device_id().clone()
+    }
+}
+
+/// Devices running side by side in one process
+pub struct Cluster {
+    nodes: Vec<Node>,
+    /// Modification times handed out by `write`
+    clock: AtomicU64,
+    _dir: TempDir,
+}
+
+impl Cluster {
+    /// Start one runtime per name, none of them paired yet
+    pub fn new(wire: Wire, names: &[&str]) -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let network = MemoryNetwork::new();
+        let mut nodes = Vec::new();
+        for name in names {
+            let runtime = device(dir.path(), name, StorageBackend::Memory)?;
+            let transport: Arc<dyn Transport> = match wire {
+                Wire::Memory => Arc::new(network.bind_device(name, runtime.device_id().clone())?),
+                Wire::Quic => Arc::new(QuicTransport::bind(
+                    SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+                    runtime.keystore().keypair(),
+                )?),
+            };
+            runtime.serve(transport.clone())?;
+            nodes.push(Node {
+                name: name.to_string(),
+                runtime,
+                transport,
+            });
+        }
+        Ok(Self {
+            nodes,
+            clock: AtomicU64::new(0),
+            _dir: dir,
+        })
+    }
+
+    /// Node called `name`
+    ///
+    /// Panics on unknown names, which are bugs in the scenario.
+    pub fn node(&self, name: &str) -> &Node {
+        &self.nodes[self.index(name)]
+    }
+
+    /// Runtime of the node called `name`
+    pub fn runtime(&self, name: &str) -> &Arc<NomadeRuntime> {
+        &self.node(name).runtime
+    }
+
+    /// Subscribe to the events of a node
+    pub fn events(&self, name: &str) -> EventReceiver {
+        self.runtime(name).events().subscribe()
+    }
+
+    /// Pair two nodes with each other's pairing offers
+    pub fn pair(&self, a: &str, b: &str) -> Result<()> {
+        let (first, second) = (self.runtime(a), self.runtime(b));
+        first.accept_pairing_offer(&second.pairing_offer(b)?)?;
+        second.accept_pairing_offer(&first.pairing_offer(a)?)?;
+        Ok(())
+    }
+
+    /// Pair every node with every other
+    pub fn pair_all(&self) -> Result<()> {
+        for (i, a) in self.nodes.iter().enumerate() {
+            for b in &self.nodes[i + 1..] {
+                self.pair(&a.name, &b.name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Connect two nodes, `a` dialing `b`, replacing an existing link
+    ///
+    /// Returns once both runtimes linked to the other; fails when either
+    /// refuses the other, e.g. after a revocation.
+    pub async fn connect(&self, a: &str, b: &str) -> Result<()> {
+        self.disconnect(a, b);
+        let (dialer, listener) = (self.node(a), self.node(b));
+        dialer
+            .runtime
+            .connect_peer(
+                dialer.transport.as_ref(),
+                &listener.id(),
+                &listener.transport.local_addr(),
+            )
+            .await?;
+        tokio::time::timeout(LINK_TIMEOUT, async {
+            loop {
+                if !self.is_linked(a, b) {
+                    bail!("{} refused {}", b, a);
+                }
+                if self.is_linked(b, a) {
+                    return Ok(());
+                }
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .with_context(|| format!("{} did not link to {}", b, a))?
+    }
+
+    /// Connect every pair of nodes
+    pub async fn connect_all(&self) -> Result<()> {
+        for (i, a) in self.nodes.iter().enumerate() {
+            for b in &self.nodes[i + 1..] {
+                self.connect(&a.name, &b.name).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close the link between two nodes, if any
+    pub fn disconnect(&self, a: &str, b: &str) {
+        let (first, second) = (self.node(a), self.node(b));
+        first.runtime.disconnect_peer(&second.id());
+        second.runtime.disconnect_peer(&first.id());
+    }
+
+    /// Whether `a` can currently sync with `b`
+    pub fn is_linked(&self, a: &str, b: &str) -> bool {
+        self.runtime(a).linked_peers().contains(&self.node(b).id())
+    }
+
+    /// Pull changes from `from` into `to`
+    pub async fn sync(&self, to: &str, from: &str) -> Result<SyncProgress> {
+        let handle = self.runtime(to).start_sync(&self.node(from).id())?;
+        Ok(handle.wait().await)
+    }
+
+    /// Sync over every link until the named nodes hold the same artifacts
+    ///
+    /// Returns the number of rounds it took; fails after `MAX_ROUNDS` or
+    /// when a sync session does not complete.
+    pub async fn converge(&self, names: &[&str]) -> Result<usize> {
+        for round in 1..=MAX_ROUNDS {
+            for to in &self.nodes {
+                for from in &self.nodes {
+                    if !self.is_linked(&to.name, &from.name) {
+                        continue;
+                    }
+                    let progress = self.sync(&to.name, &from.name).await?;
+                    if progress.state != SyncState::Completed {
+                        bail!(
+                            "{} failed to sync from {}: {:?}",
+                            to.name,
+                            from.name,
+                            progress.error
+                        );
+                    }
+                }
+            }
+            if self.converged(names)? {
+                return Ok(round);
+            }
+        }
+        bail!("{:?} did not converge in {} rounds", names, MAX_ROUNDS)
+    }
+
+    /// Whether the named nodes hold the same artifacts
+    pub fn converged(&self, names: &[&str]) -> Result<bool> {
+        let mut manifests = names
+            .iter()
+            .map(|name| self.runtime(name).sync().manifest());
+        let Some(first) = manifests.next().transpose()? else {
+            return Ok(true);
+        };
+        for manifest in manifests {
+            if manifest? != first {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Create or edit an artifact with `text` as its content
+    ///
+    /// Each write is later than all previous ones in the cluster.
+    pub fn write(&self, node: &str, id: &str, text: &str) -> Result<Artifact> {
+        let runtime = self.runtime(node);
+        let hash = content_hash(text.as_bytes());
+        runtime.content().put_content(&hash, text.as_bytes())?;
+        let now = self.clock.fetch_add(1, Ordering::SeqCst) + 1;
+        let artifact = match runtime.artifacts().get(id)? {
+            Some(existing) => Artifact {
+                modified_at: now,
+                content_hash: hash,
+                ..existing
+            },
+            None => Artifact {
+                id: id.into(),
+                title: id.into(),
+                created_at: now,
+                modified_at: now,
+                content_hash: hash,
+                ..Default::default()
+            },
+        };
+        runtime.artifacts().store(&artifact)?;
+        Ok(artifact)
+    }
+
+    /// Content of an artifact as text, if the node has it
+    pub fn read(&self, node: &str, id: &str) -> Result<Option<String>> {
+        let runtime = self.runtime(node);
+        let Some(artifact) = runtime.artifacts().get(id)? else {
+            return Ok(None);
+        };
+        let content = runtime
+            .content()
+            .get_content(&artifact.content_hash)?
+            .with_context(|| format!("{} has no content for {}", node, id))?;
+        Ok(Some(String::from_utf8(content)?))
+    }
+
+    /// Stop every runtime, closing their links
+    pub async fn shutdown(self) -> Result<()> {
+        for node in &self.nodes {
+            node.runtime.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    fn index(&self, name: &str) -> usize {
+        self.nodes
+            .iter()
+            .position(|node| node.name == name)
+            .unwrap_or_else(|| panic!("unknown node {}", name))
+    }
+}
+
+/// Events received so far, without waiting
+pub fn drain(events: &mut EventReceiver) -> Vec<Event> {
+    std::iter::from_fn(|| events.try_recv().ok()).collect()
+}
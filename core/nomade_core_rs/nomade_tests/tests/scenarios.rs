@@ -0,0 +1,222 @@
+//! End-to-end scenarios across several runtimes
+
+use std::time::Duration;
+
+use nomade_core::nomade_events::Event;
+use nomade_core::nomade_sync::Resolution;
+use nomade_tests::{drain, Cluster, Wire};
+
+const DEVICES: [&str; 3] = ["laptop", "phone", "tablet"];
+
+/// Position of the first event matching `pred`
+fn position(events: &[Event], pred: impl Fn(&Event) -> bool) -> usize {
+    events
+        .iter()
+        .position(pred)
+        .unwrap_or_else(|| panic!("missing event in {:?}", events))
+}
+
+#[tokio::test]
+async fn test_created_artifacts_reach_every_device() {
+    let cluster = Cluster::new(Wire::Memory, &DEVICES).unwrap();
+    cluster.pair_all().unwrap();
+    let mut events = cluster.events("phone");
+    cluster.connect_all().await.unwrap();
+    cluster.write("laptop", "note", "hello").unwrap();
+    cluster.write("tablet", "sketch", "lines").unwrap();
+
+    cluster.converge(&DEVICES).await.unwrap();
+    for device in DEVICES {
+        assert_eq!(cluster.read(device, "note").unwrap().unwrap(), "hello");
+        assert_eq!(cluster.read(device, "sketch").unwrap().unwrap(), "lines");
+    }
+
+    // Peers connect before any sync starts, and each sync ends once
+    let events = drain(&mut events);
+    let connected = position(&events, |e| matches!(e, Event::DeviceConnected { .. }));
+    let started = position(&events, |e| matches!(e, Event::SyncStarted));
+    let completed = position(&events, |e| matches!(e, Event::SyncCompleted { .. }));
+    assert!(connected < started && started < completed);
+    let count = |pred: fn(&Event) -> bool| events.iter().filter(|e| pred(e)).count();
+    assert_eq!(
+        count(|e| matches!(e, Event::SyncStarted)),
+        count(|e| matches!(e, Event::SyncCompleted { .. }))
+    );
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrent_edits_converge_after_resolution() {
+    let cluster = Cluster::new(Wire::Memory, &["laptop", "phone"]).unwrap();
+    cluster.pair_all().unwrap();
+    cluster.connect_all().await.unwrap();
+    cluster.write("laptop", "note", "hello\n").unwrap();
+    cluster.converge(&["laptop", "phone"]).await.unwrap();
+    // The author agrees on the base once it sees the phone holds it too
+    cluster.sync("laptop", "phone").await.unwrap();
+
+    cluster
+        .write("laptop", "note", "hello\nfrom laptop\n")
+        .unwrap();
+    cluster
+        .write("phone", "note", "hello\nfrom phone\n")
+        .unwrap();
+    let mut events = cluster.events("laptop");
+    cluster.sync("laptop", "phone").await.unwrap();
+    cluster.sync("phone", "laptop").await.unwrap();
+    // Neither edit silently wins
+    assert_eq!(
+        cluster.read("laptop", "note").unwrap().unwrap(),
+        "hello\nfrom laptop\n"
+    );
+    assert_eq!(
+        cluster.read("phone", "note").unwrap().unwrap(),
+        "hello\nfrom phone\n"
+    );
+    assert_eq!(cluster.runtime("phone").conflicts().len(), 1);
+    assert!(drain(&mut events)
+        .iter()
+        .any(|e| matches!(e, Event::ConflictDetected { artifact_id } if artifact_id == "note")));
+
+    let merged = "hello\nfrom laptop\nfrom phone\n";
+    cluster
+        .runtime("laptop")
+        .resolve_conflict(
+            "note",
+            Resolution::Merged {
+                content: merged.into(),
+            },
+        )
+        .unwrap();
+    cluster.converge(&["laptop", "phone"]).await.unwrap();
+    for device in ["laptop", "phone"] {
+        assert_eq!(cluster.read(device, "note").unwrap().unwrap(), merged);
+        assert!(cluster.runtime(device).conflicts().is_empty());
+    }
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_offline_edits_catch_up_on_reconnect() {
+    let cluster = Cluster::new(Wire::Memory, &DEVICES).unwrap();
+    cluster.pair_all().unwrap();
+    cluster.connect_all().await.unwrap();
+    cluster.write("laptop", "note", "v1").unwrap();
+    cluster.converge(&DEVICES).await.unwrap();
+
+    cluster.disconnect("phone", "laptop");
+    cluster.disconnect("phone", "tablet");
+    assert!(!cluster.is_linked("laptop", "phone"));
+    assert!(cluster.sync("phone", "laptop").await.is_err());
+    cluster.write("laptop", "note", "v2").unwrap();
+    cluster.write("tablet", "todo", "milk").unwrap();
+    cluster.write("phone", "draft", "offline").unwrap();
+    cluster.converge(&["laptop", "tablet"]).await.unwrap();
+    assert_eq!(cluster.read("phone", "note").unwrap().unwrap(), "v1");
+    assert!(!cluster.converged(&DEVICES).unwrap());
+
+    // Reconnecting to one peer is enough to catch up on everything
+    cluster.connect("phone", "tablet").await.unwrap();
+    cluster.converge(&DEVICES).await.unwrap();
+    assert_eq!(cluster.read("phone", "note").unwrap().unwrap(), "v2");
+    assert_eq!(cluster.read("phone", "todo").unwrap().unwrap(), "milk");
+    assert_eq!(cluster.read("laptop", "draft").unwrap().unwrap(), "offline");
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_revocation_propagates_and_cuts_off_device() {
+    let cluster = Cluster::new(Wire::Memory, &DEVICES).unwrap();
+    cluster.pair_all().unwrap();
+    cluster.connect_all().await.unwrap();
+    cluster.write("laptop", "note", "shared").unwrap();
+    cluster.converge(&DEVICES).await.unwrap();
+
+    let tablet = cluster.node("tablet").id();
+    let mut events = cluster.events("phone");
+    cluster
+        .runtime("laptop")
+        .revoke_device(&tablet, "lost")
+        .unwrap();
+
+    // The phone learns of it from the laptop and drops the tablet too
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while cluster.is_linked("phone", "tablet") {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("revocation reaches the phone");
+    assert!(cluster
+        .runtime("phone")
+        .trust()
+        .read()
+        .unwrap()
+        .check_handshake(&tablet)
+        .is_err());
+    let events = drain(&mut events);
+    let disconnected = position(
+        &events,
+        |e| matches!(e, Event::DeviceDisconnected { device_id } if *device_id == tablet.0),
+    );
+    let revoked = position(
+        &events,
+        |e| matches!(e, Event::DeviceRevoked { device_id } if *device_id == tablet.0),
+    );
+    assert!(disconnected < revoked);
+
+    // The tablet can neither reconnect nor receive new edits
+    assert!(cluster.connect("tablet", "phone").await.is_err());
+    assert!(cluster.connect("laptop", "tablet").await.is_err());
+    cluster.write("laptop", "note", "after revocation").unwrap();
+    cluster.converge(&["laptop", "phone"]).await.unwrap();
+    assert_eq!(cluster.read("tablet", "note").unwrap().unwrap(), "shared");
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_attestations_spread_through_peers() {
+    let cluster = Cluster::new(Wire::Memory, &DEVICES).unwrap();
+    cluster.pair_all().unwrap();
+    // The tablet only hears from the laptop through the phone
+    cluster.connect("laptop", "phone").await.unwrap();
+    cluster.connect("phone", "tablet").await.unwrap();
+    let laptop = cluster.node("laptop").id();
+    let mut events = cluster.events("tablet");
+    cluster
+        .runtime("laptop")
+        .publish_attestation("Laptop", "alice", Duration::from_secs(3600))
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !cluster
+            .runtime("tablet")
+            .attestations()
+            .iter()
+            .any(|info| info.attestation.device_id == laptop)
+        {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("attestation reaches the tablet");
+    assert!(drain(&mut events)
+        .iter()
+        .any(|e| matches!(e, Event::DeviceAttested { device_id } if *device_id == laptop.0)));
+    cluster.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sync_over_localhost_quic() {
+    let cluster = Cluster::new(Wire::Quic, &["laptop", "phone"]).unwrap();
+    cluster.pair_all().unwrap();
+    cluster.connect("phone", "laptop").await.unwrap();
+    let large = "x".repeat(300 * 1024);
+    cluster.write("laptop", "video", &large).unwrap();
+    cluster.write("phone", "note", "hi").unwrap();
+
+    cluster.converge(&["laptop", "phone"]).await.unwrap();
+    assert_eq!(cluster.read("phone", "video").unwrap().unwrap(), large);
+    assert_eq!(cluster.read("laptop", "note").unwrap().unwrap(), "hi");
+    cluster.shutdown().await.unwrap();
+}
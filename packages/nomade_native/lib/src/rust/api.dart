@@ -3,8 +3,671 @@
 
 // ignore_for_file: invalid_use_of_internal_member, unused_import, unnecessary_import
 
+import 'dart:async';
 import 'frb_generated.dart';
 import 'package:flutter_rust_bridge/flutter_rust_bridge_for_generated.dart';
 
+/// Trust the device behind a pairing offer URL, returning its device ID
+Future<String> ffiAcceptPairingOffer({required String offerUrl}) =>
+    RustLib.instance.api.crateApiFfiAcceptPairingOffer(offerUrl: offerUrl);
+
+/// Admit a device presenting a JSON-encoded `EnrollmentCertificate` from a
+/// trusted user, returning `false` if it was already trusted
+Future<bool> ffiAdmitEnrolled({required String certificateJson}) =>
+    RustLib.instance.api.crateApiFfiAdmitEnrolled(
+      certificateJson: certificateJson,
+    );
+
+/// Apply a JSON-encoded `UserRevocation` from a trusted user
+Future<bool> ffiApplyUserRevocation({required String revocationJson}) =>
+    RustLib.instance.api.crateApiFfiApplyUserRevocation(
+      revocationJson: revocationJson,
+    );
+
+/// Honor a JSON-encoded `WipeCommand` from another device of this user
+///
+/// Fails without wiping unless the command is signed, fresh and sent by a
+/// trusted device enrolled under the same user.
+Future<void> ffiApplyWipeCommand({required String commandJson}) =>
+    RustLib.instance.api.crateApiFfiApplyWipeCommand(commandJson: commandJson);
+
+/// Content of an artifact, fetched from a connected peer if evicted
+Future<Uint8List> ffiArtifactContent({required String artifactId}) =>
+    RustLib.instance.api.crateApiFfiArtifactContent(artifactId: artifactId);
+
+/// List stored artifacts as JSON-encoded `Vec<Artifact>`
+Future<String> ffiArtifacts() => RustLib.instance.api.crateApiFfiArtifacts();
+
+/// Statements of paired devices as a JSON array of `AttestationInfo`
+Future<String> ffiAttestations() =>
+    RustLib.instance.api.crateApiFfiAttestations();
+
+/// Sync with every connected peer within a JSON-encoded `SyncBudget`
+///
+/// Metadata and small artifacts go first. Returns JSON-encoded
+/// `BudgetReport`s listing what is left for the next background window.
+Future<String> ffiBackgroundSync({required String budgetJson}) =>
+    RustLib.instance.api.crateApiFfiBackgroundSync(budgetJson: budgetJson);
+
+/// Backups taken so far as JSON-encoded `Vec<BackupInfo>`, oldest first
+Future<String> ffiBackups() => RustLib.instance.api.crateApiFfiBackups();
+
+/// Leave pairing mode without pairing
+Future<void> ffiCancelCodePairing() =>
+    RustLib.instance.api.crateApiFfiCancelCodePairing();
+
+/// Cancel a running sync, keeping partial progress for the next sync
+Future<bool> ffiCancelSync({required BigInt handle}) =>
+    RustLib.instance.api.crateApiFfiCancelSync(handle: handle);
+
+/// List collections as JSON-encoded `Vec<Collection>`
+Future<String> ffiCollections() =>
+    RustLib.instance.api.crateApiFfiCollections();
+
+/// Sync conflicts waiting for the user as a JSON array of `ConflictRecord`
+Future<String> ffiConflicts() => RustLib.instance.api.crateApiFfiConflicts();
+
+/// Connect to a paired device at `address`, e.g. from its pairing offer
+///
+/// Needs `ffi_listen` first; the device can be synced with once this
+/// returns.
+Future<void> ffiConnectPeer({
+  required String peerId,
+  required String address,
+}) => RustLib.instance.api.crateApiFfiConnectPeer(peerId: peerId, address: address);
+
+/// JSON-encoded `ConnectionQuality` of a linked peer, `null` until its
+/// keepalive has reported
+Future<String> ffiConnectionQuality({required String peerId}) =>
+    RustLib.instance.api.crateApiFfiConnectionQuality(peerId: peerId);
+
+/// Vouch for a paired device's statement, returning the JSON-encoded
+/// `Attestation`
+Future<String> ffiCountersignAttestation({required String deviceId}) =>
+    RustLib.instance.api.crateApiFfiCountersignAttestation(deviceId: deviceId);
+
+/// Back up the whole store now, returning JSON-encoded `BackupInfo`
+Future<String> ffiCreateBackup({required String label}) =>
+    RustLib.instance.api.crateApiFfiCreateBackup(label: label);
+
+/// Create a collection, returning the JSON-encoded `Collection`
+///
+/// An empty `parent_id` creates a top-level collection.
+Future<String> ffiCreateCollection({
+  required String name,
+  required String parentId,
+  required int position,
+}) => RustLib.instance.api.crateApiFfiCreateCollection(
+  name: name,
+  parentId: parentId,
+  position: position,
+);
+
+/// Check the crypto primitives against known-answer vectors
+///
+/// Needs no running runtime. Fails naming the first vector that does not
+/// match; `paranoid` in the configuration runs it before every start.
+Future<void> ffiCryptoSelfTest() =>
+    RustLib.instance.api.crateApiFfiCryptoSelfTest();
+
+/// Delete a backup, letting content only it kept be collected
+Future<void> ffiDeleteBackup({required String backupId}) =>
+    RustLib.instance.api.crateApiFfiDeleteBackup(backupId: backupId);
+
+/// Delete a collection and its sub-collections
+Future<void> ffiDeleteCollection({required String id}) =>
+    RustLib.instance.api.crateApiFfiDeleteCollection(id: id);
+
+/// Delete a JSON array of artifact IDs in one transaction, streaming
+/// JSON-encoded `BulkProgress` like `ffi_store_many`
+Stream<String> ffiDeleteMany({required String artifactIdsJson}) =>
+    RustLib.instance.api.crateApiFfiDeleteMany(
+      artifactIdsJson: artifactIdsJson,
+    );
+
+/// Derived asset (e.g. "thumbnail", "excerpt") of an artifact
+///
+/// Computed on first request and cached. Returns an empty buffer when the
+/// processor does not apply to the artifact or its content is not local.
+Future<Uint8List> ffiDerivedAsset({
+  required String artifactId,
+  required String processor,
+  required String params,
+}) => RustLib.instance.api.crateApiFfiDerivedAsset(
+  artifactId: artifactId,
+  processor: processor,
+  params: params,
+);
+
+/// ID of the local device
+Future<String> ffiDeviceId() => RustLib.instance.api.crateApiFfiDeviceId();
+
+/// Permissions of a paired device as JSON-encoded `Permissions`
+Future<String> ffiDevicePermissions({required String deviceId}) =>
+    RustLib.instance.api.crateApiFfiDevicePermissions(deviceId: deviceId);
+
+/// Delete everything in the trash for good, returning the IDs as a JSON
+/// array
+Future<String> ffiEmptyTrash() => RustLib.instance.api.crateApiFfiEmptyTrash();
+
+/// Enroll this device or a paired one under the user of `passphrase`,
+/// returning the JSON-encoded `EnrollmentCertificate`
+Future<String> ffiEnrollDevice({
+  required String passphrase,
+  required String deviceId,
+  required String deviceName,
+}) => RustLib.instance.api.crateApiFfiEnrollDevice(
+  passphrase: passphrase,
+  deviceId: deviceId,
+  deviceName: deviceName,
+);
+
+/// Stream runtime events to the app
+///
+/// Each item is a JSON-encoded `Event` envelope carrying its schema version
+/// (`{"v":2,"type":...,"payload":...}`); apps should skip types they don't
+/// recognize. Bursts arrive as one `batched` event. The stream ends when the
+/// Dart side closes it or the runtime shuts down.
+Stream<String> ffiEventStream() =>
+    RustLib.instance.api.crateApiFfiEventStream();
+
+/// Export artifacts to an encrypted `.nomade` bundle file
+///
+/// `seal_json` is a JSON-encoded `BundleSeal`: `{"password": "..."}` or
+/// `{"recipients": [[...public key bytes...]]}`.
+Future<void> ffiExportBundle({
+  required String path,
+  required String artifactIdsJson,
+  required String sealJson,
+}) => RustLib.instance.api.crateApiFfiExportBundle(
+  path: path,
+  artifactIdsJson: artifactIdsJson,
+  sealJson: sealJson,
+);
+
+/// Export artifacts as plain files into the directory at `path`
+///
+/// `artifact_ids_json` is a JSON array of IDs (empty for all artifacts) and
+/// `options_json` a JSON-encoded `ExportOptions` (`{}` for the defaults:
+/// metadata sidecars and collection folders). The stream carries
+/// JSON-encoded `ExportProgress` after each artifact and ends when the
+/// export is done.
+Stream<String> ffiExportDir({
+  required String path,
+  required String artifactIdsJson,
+  required String optionsJson,
+}) => RustLib.instance.api.crateApiFfiExportDir(
+  path: path,
+  artifactIdsJson: artifactIdsJson,
+  optionsJson: optionsJson,
+);
+
+/// Raw identity secret key, after authenticating if gated
+Future<Uint8List> ffiExportSecretKey() =>
+    RustLib.instance.api.crateApiFfiExportSecretKey();
+
+/// Handle a push notification carrying a wake token
+///
+/// Waits for the sending device to connect and syncs with it for at most
+/// `budget_ms`, then returns a JSON-encoded `WakeReport`. End the
+/// platform background task when this returns.
+Future<String> ffiHandlePush({
+  required String payload,
+  required int budgetMs,
+}) => RustLib.instance.api.crateApiFfiHandlePush(
+  payload: payload,
+  budgetMs: budgetMs,
+);
+
+/// Import a `.nomade` bundle file, returning the JSON-encoded `ImportReport`
+///
+/// An empty `password` opens bundles sealed to this device.
+Future<String> ffiImportBundle({
+  required String path,
+  required String password,
+}) => RustLib.instance.api.crateApiFfiImportBundle(path: path, password: password);
+
+/// Import a directory written by `ffi_export_dir`, returning the
+/// JSON-encoded `ImportReport`
+Future<String> ffiImportDir({required String path}) =>
+    RustLib.instance.api.crateApiFfiImportDir(path: path);
+
+/// Initialize Nomade core from a JSON-encoded `NomadeConfig`
+Future<void> ffiInit({required String configJson}) =>
+    RustLib.instance.api.crateApiFfiInit(configJson: configJson);
+
+/// Lock state as JSON-encoded `KeystoreStatus`
+Future<String> ffiKeystoreStatus() =>
+    RustLib.instance.api.crateApiFfiKeystoreStatus();
+
+/// Connected peers available for sync as JSON-encoded device IDs
+Future<String> ffiLinkedPeers() =>
+    RustLib.instance.api.crateApiFfiLinkedPeers();
+
+/// Page of at most `limit` artifacts as JSON-encoded `Page`
+///
+/// `cursor` is the `next` cursor of the previous page, empty for the first
+/// one; `sort` is `id`, `modified` or `title` and must stay the same
+/// across pages. Prefer this over `ffi_artifacts` for large stores.
+Future<String> ffiListPage({
+  required String cursor,
+  required int limit,
+  required String sort,
+}) => RustLib.instance.api.crateApiFfiListPage(
+  cursor: cursor,
+  limit: limit,
+  sort: sort,
+);
+
+/// Page like `ffi_list_page` of JSON-encoded `ArtifactSummary`s keeping
+/// the fields of a JSON-encoded `Projection`
+///
+/// An empty `projection_json` keeps what a list row shows: ID, title and
+/// modification time.
+Future<String> ffiListSummaries({
+  required String cursor,
+  required int limit,
+  required String sort,
+  required String projectionJson,
+}) => RustLib.instance.api.crateApiFfiListSummaries(
+  cursor: cursor,
+  limit: limit,
+  sort: sort,
+  projectionJson: projectionJson,
+);
+
+/// Accept paired devices on the network listener, returning its port
+///
+/// Connected peers sync both ways over one connection, whichever side
+/// dialed. Calling it again returns the port already bound.
+Future<int> ffiListen() => RustLib.instance.api.crateApiFfiListen();
+
+/// Zeroize keystore keys now
+Future<void> ffiLockKeystore() =>
+    RustLib.instance.api.crateApiFfiLockKeystore();
+
+/// Stream structured log records to the in-app debug console
+///
+/// Each item is a JSON-encoded `LogRecord`. The stream ends when the Dart
+/// side closes it.
+Stream<String> ffiLogStream() => RustLib.instance.api.crateApiFfiLogStream();
+
+/// Current metrics in the Prometheus text exposition format
+Future<String> ffiMetricsPrometheus() =>
+    RustLib.instance.api.crateApiFfiMetricsPrometheus();
+
+/// Current metrics as a JSON-encoded `MetricsSnapshot`
+Future<String> ffiMetricsSnapshot() =>
+    RustLib.instance.api.crateApiFfiMetricsSnapshot();
+
+/// Stream a JSON-encoded `MetricsSnapshot` every `interval_ms`
+///
+/// The stream stops when the Dart side closes it or the runtime shuts down.
+Stream<String> ffiMetricsStream({required int intervalMs}) =>
+    RustLib.instance.api.crateApiFfiMetricsStream(intervalMs: intervalMs);
+
+/// Move a collection; an empty `parent_id` moves it to the top level
+Future<void> ffiMoveCollection({
+  required String id,
+  required String parentId,
+  required int position,
+}) => RustLib.instance.api.crateApiFfiMoveCollection(
+  id: id,
+  parentId: parentId,
+  position: position,
+);
+
+/// Current network state as JSON-encoded `NetworkState`
+Future<String> ffiNetworkState() =>
+    RustLib.instance.api.crateApiFfiNetworkState();
+
+/// Pair with the device at `address` showing `code`, returning its
+/// device ID
+///
+/// Needs `ffi_listen` first. A code that fails cannot be tried again.
+Future<String> ffiPairWithCode({
+  required String address,
+  required String code,
+  required String deviceName,
+}) => RustLib.instance.api.crateApiFfiPairWithCode(
+  address: address,
+  code: code,
+  deviceName: deviceName,
+);
+
+/// Signed pairing offer URL advertising this device as `device_name`
+Future<String> ffiPairingOffer({required String deviceName}) =>
+    RustLib.instance.api.crateApiFfiPairingOffer(deviceName: deviceName);
+
+/// Keep an artifact's content on this device, exempt from eviction
+Future<void> ffiPinArtifact({required String artifactId}) =>
+    RustLib.instance.api.crateApiFfiPinArtifact(artifactId: artifactId);
+
+/// Pinned artifact IDs as a JSON array
+Future<String> ffiPinnedArtifacts() =>
+    RustLib.instance.api.crateApiFfiPinnedArtifacts();
+
+/// Preview a sync with a connected peer as a JSON-encoded `SyncPreview`
+///
+/// Fetches only the peer's manifest and metadata, so the app can show how
+/// many artifacts, chunks and bytes would move before starting the sync.
+Future<String> ffiPlanSync({required String peerId}) =>
+    RustLib.instance.api.crateApiFfiPlanSync(peerId: peerId);
+
+/// Sign and send this device's identity statement, valid for `ttl_secs`,
+/// returning the JSON-encoded `Attestation`
+Future<String> ffiPublishAttestation({
+  required String deviceName,
+  required String user,
+  required BigInt ttlSecs,
+}) => RustLib.instance.api.crateApiFfiPublishAttestation(
+  deviceName: deviceName,
+  user: user,
+  ttlSecs: ttlSecs,
+);
+
+/// Artifacts matching a JSON-encoded `FieldQuery` on an indexed field, as
+/// JSON-encoded `Vec<Artifact>`
+Future<String> ffiQueryArtifacts({required String queryJson}) =>
+    RustLib.instance.api.crateApiFfiQueryArtifacts(queryJson: queryJson);
+
+/// Matches of a JSON-encoded `FieldQuery` as JSON-encoded
+/// `Vec<ArtifactSummary>`, keeping the fields of a JSON-encoded
+/// `Projection` (empty for the list row defaults)
+Future<String> ffiQuerySummaries({
+  required String queryJson,
+  required String projectionJson,
+}) => RustLib.instance.api.crateApiFfiQuerySummaries(
+  queryJson: queryJson,
+  projectionJson: projectionJson,
+);
+
+/// Progress rotating data keys after revocations as JSON-encoded
+/// `ReencryptProgress`
+Future<String> ffiReencryptionProgress() =>
+    RustLib.instance.api.crateApiFfiReencryptionProgress();
+
+/// Ask the user to authenticate before sensitive operations
+///
+/// Call before `ffi_init`. `prompt` shows the platform prompt (biometrics
+/// or device credential) with the given reason and returns whether the
+/// user authenticated. `auth.gated` in the config selects the operations.
+Future<void> ffiRegisterAuthGate({
+  required FutureOr<bool> Function(String) prompt,
+}) => RustLib.instance.api.crateApiFfiRegisterAuthGate(prompt: prompt);
+
+/// Keep the identity key in the platform keystore
+///
+/// Call before `ffi_init`. `public_key` is the raw Ed25519 public key held
+/// by the Secure Enclave or StrongBox; `sign` returns the 64-byte signature
+/// of its argument, or an empty list if signing failed or was refused.
+Future<void> ffiRegisterPlatformSigner({
+  required Uint8List publicKey,
+  required FutureOr<Uint8List> Function(Uint8List) sign,
+}) => RustLib.instance.api.crateApiFfiRegisterPlatformSigner(
+  publicKey: publicKey,
+  sign: sign,
+);
+
+/// Add or replace a custom field schema from a JSON-encoded `MetadataSchema`
+Future<void> ffiRegisterSchema({required String schemaJson}) =>
+    RustLib.instance.api.crateApiFfiRegisterSchema(schemaJson: schemaJson);
+
+/// Remove the keystore passphrase, after authenticating if gated
+Future<void> ffiRemoveKeystorePassphrase() =>
+    RustLib.instance.api.crateApiFfiRemoveKeystorePassphrase();
+
+/// Rename a collection
+Future<void> ffiRenameCollection({required String id, required String name}) =>
+    RustLib.instance.api.crateApiFfiRenameCollection(id: id, name: name);
+
+/// Settle a sync conflict from a JSON-encoded `Resolution`
+///
+/// `{"choice":"local"}`, `{"choice":"remote"}` or
+/// `{"choice":"merged","content":"..."}`. Returns the resolved artifact as
+/// JSON.
+Future<String> ffiResolveConflict({
+  required String artifactId,
+  required String resolutionJson,
+}) => RustLib.instance.api.crateApiFfiResolveConflict(
+  artifactId: artifactId,
+  resolutionJson: resolutionJson,
+);
+
+/// Take an artifact back out of the trash
+Future<void> ffiRestoreArtifact({required String artifactId}) =>
+    RustLib.instance.api.crateApiFfiRestoreArtifact(artifactId: artifactId);
+
+/// Roll the store back to a backup, returning JSON-encoded `RestoreReport`
+Future<String> ffiRestoreBackup({required String backupId}) =>
+    RustLib.instance.api.crateApiFfiRestoreBackup(backupId: backupId);
+
+/// Apply a JSON-encoded `Retag` to a JSON array of artifact IDs in one
+/// transaction, streaming JSON-encoded `BulkProgress` like `ffi_store_many`
+Stream<String> ffiRetagMany({
+  required String artifactIdsJson,
+  required String retagJson,
+}) => RustLib.instance.api.crateApiFfiRetagMany(
+  artifactIdsJson: artifactIdsJson,
+  retagJson: retagJson,
+);
+
+/// Revoke a paired device, returning the JSON-encoded `RevocationRecord`
+Future<String> ffiRevokeDevice({
+  required String deviceId,
+  required String reason,
+}) => RustLib.instance.api.crateApiFfiRevokeDevice(
+  deviceId: deviceId,
+  reason: reason,
+);
+
+/// Withdraw a device from the user of `passphrase`, returning the
+/// JSON-encoded `UserRevocation` to send to the user's other devices
+Future<String> ffiRevokeEnrolled({
+  required String passphrase,
+  required String deviceId,
+  required String reason,
+}) => RustLib.instance.api.crateApiFfiRevokeEnrolled(
+  passphrase: passphrase,
+  deviceId: deviceId,
+  reason: reason,
+);
+
+/// Revoke a share token by ID, returning `false` if unknown or revoked
+Future<bool> ffiRevokeShare({required String tokenId}) =>
+    RustLib.instance.api.crateApiFfiRevokeShare(tokenId: tokenId);
+
+/// Rekey the encrypted metadata store, returning the new key generation
+///
+/// Fails unless `encrypt_metadata` is enabled.
+Future<int> ffiRotateMetadataKey() =>
+    RustLib.instance.api.crateApiFfiRotateMetadataKey();
+
+/// Keep a JSON-encoded `Snippet` as an artifact, returning the
+/// JSON-encoded `Artifact`
+Future<String> ffiSaveSnippet({required String snippetJson}) =>
+    RustLib.instance.api.crateApiFfiSaveSnippet(snippetJson: snippetJson);
+
+/// Registered custom field schemas as JSON-encoded `Vec<MetadataSchema>`
+Future<String> ffiSchemas() => RustLib.instance.api.crateApiFfiSchemas();
+
+/// Send a text snippet to a connected peer, returning the JSON-encoded
+/// `Snippet`
+Future<String> ffiSendSnippet({required String peerId, required String text}) =>
+    RustLib.instance.api.crateApiFfiSendSnippet(peerId: peerId, text: text);
+
+/// Put an artifact into a collection; an empty `collection_id` removes it
+Future<void> ffiSetArtifactCollection({
+  required String artifactId,
+  required String collectionId,
+}) => RustLib.instance.api.crateApiFfiSetArtifactCollection(
+  artifactId: artifactId,
+  collectionId: collectionId,
+);
+
+/// Replace a paired device's permissions from JSON-encoded `Permissions`
+Future<void> ffiSetDevicePermissions({
+  required String deviceId,
+  required String permissionsJson,
+}) => RustLib.instance.api.crateApiFfiSetDevicePermissions(
+  deviceId: deviceId,
+  permissionsJson: permissionsJson,
+);
+
+/// Set or change the passphrase protecting keystore keys
+///
+/// Changing it needs the keystore unlocked.
+Future<void> ffiSetKeystorePassphrase({required String passphrase}) =>
+    RustLib.instance.api.crateApiFfiSetKeystorePassphrase(
+      passphrase: passphrase,
+    );
+
+/// Report the network the device is on, from the platform's connectivity
+/// callbacks
+///
+/// `state_json` is a `NetworkState`, e.g. `{"kind": "cellular"}` or
+/// `{"kind": "wifi", "metered": true}`; kinds are `wifi`, `ethernet`,
+/// `cellular`, `offline` and `unknown`.
+Future<void> ffiSetNetworkState({required String stateJson}) =>
+    RustLib.instance.api.crateApiFfiSetNetworkState(stateJson: stateJson);
+
+/// Record a connected peer's self-reported state from JSON-encoded `PeerHints`
+///
+/// Low-battery and metered peers are the last choice as download sources.
+Future<void> ffiSetPeerHints({
+  required String peerId,
+  required String hintsJson,
+}) => RustLib.instance.api.crateApiFfiSetPeerHints(
+  peerId: peerId,
+  hintsJson: hintsJson,
+);
+
+/// Replace a peer's selective sync rules from JSON-encoded `SyncRules`
+///
+/// Returns the JSON-encoded `RuleEvaluation` listing affected artifacts.
+Future<String> ffiSetSyncRules({
+  required String peerId,
+  required String rulesJson,
+}) => RustLib.instance.api.crateApiFfiSetSyncRules(
+  peerId: peerId,
+  rulesJson: rulesJson,
+);
+
+/// Issue a share link for one artifact, valid for `ttl_secs` seconds
+Future<String> ffiShareArtifact({
+  required String artifactId,
+  required BigInt ttlSecs,
+}) => RustLib.instance.api.crateApiFfiShareArtifact(
+  artifactId: artifactId,
+  ttlSecs: ttlSecs,
+);
+
+/// List issued share tokens as JSON-encoded `Vec<IssuedShare>`
+Future<String> ffiShares() => RustLib.instance.api.crateApiFfiShares();
+
+/// Stop the runtime started by `ffi_init`
+///
+/// Cancels background tasks, flushes storage, closes connections and
+/// drains pending events before returning.
+Future<void> ffiShutdown() => RustLib.instance.api.crateApiFfiShutdown();
+
+/// Enter pairing mode as `device_name`, returning the code to show
+///
+/// Needs `ffi_listen` first; the next unpaired device to connect may try
+/// the code, once.
+Future<String> ffiStartCodePairing({required String deviceName}) =>
+    RustLib.instance.api.crateApiFfiStartCodePairing(deviceName: deviceName);
+
+/// Start syncing with a connected peer, returning the sync handle
+Future<BigInt> ffiStartSync({required String peerId}) =>
+    RustLib.instance.api.crateApiFfiStartSync(peerId: peerId);
+
+/// Storage statistics and consistency checks as a JSON-encoded
+/// `StoreHealth`, for the storage settings screen
+Future<String> ffiStoreHealth() =>
+    RustLib.instance.api.crateApiFfiStoreHealth();
+
+/// Store a JSON array of artifacts in one transaction
+///
+/// The stream carries JSON-encoded `BulkProgress` every hundred artifacts
+/// and ends when the batch is committed.
+Stream<String> ffiStoreMany({required String artifactsJson}) =>
+    RustLib.instance.api.crateApiFfiStoreMany(artifactsJson: artifactsJson);
+
+/// Stream progress of a sync as JSON-encoded `SyncProgress`
+///
+/// The stream ends after the final (completed, cancelled or failed) update.
+Stream<String> ffiSyncProgress({required BigInt handle}) =>
+    RustLib.instance.api.crateApiFfiSyncProgress(handle: handle);
+
+/// Selective sync rules for a peer as JSON-encoded `SyncRules`
+Future<String> ffiSyncRules({required String peerId}) =>
+    RustLib.instance.api.crateApiFfiSyncRules(peerId: peerId);
+
+/// Artifacts in the trash as JSON-encoded `Vec<Artifact>`, most recently
+/// trashed first
+Future<String> ffiTrash() => RustLib.instance.api.crateApiFfiTrash();
+
+/// Move an artifact to the trash, on paired devices too
+Future<void> ffiTrashArtifact({required String artifactId}) =>
+    RustLib.instance.api.crateApiFfiTrashArtifact(artifactId: artifactId);
+
+/// Trust the user behind a JSON-encoded `EnrollmentCertificate` confirmed
+/// in person, admitting its device
+Future<bool> ffiTrustUser({
+  required String certificateJson,
+  required String name,
+}) => RustLib.instance.api.crateApiFfiTrustUser(
+  certificateJson: certificateJson,
+  name: name,
+);
+
+/// List paired devices as JSON-encoded `Vec<TrustedDevice>`
+Future<String> ffiTrustedDevices() =>
+    RustLib.instance.api.crateApiFfiTrustedDevices();
+
+/// Unlock keystore keys with the passphrase
+///
+/// Keys stay in memory for `auto_lock_secs`. Operations needing them fail
+/// with "Keystore is locked" meanwhile; prompt the user and call this.
+Future<void> ffiUnlockKeystore({required String passphrase}) =>
+    RustLib.instance.api.crateApiFfiUnlockKeystore(passphrase: passphrase);
+
+/// Unlock keystore keys through a platform biometric prompt
+///
+/// `release` shows the prompt and returns the key the app stored behind
+/// it, or an empty list if the user cancelled.
+Future<void> ffiUnlockKeystoreBiometric({
+  required FutureOr<Uint8List> Function() release,
+}) => RustLib.instance.api.crateApiFfiUnlockKeystoreBiometric(release: release);
+
+/// Let an artifact's content be evicted when storage is over quota
+Future<bool> ffiUnpinArtifact({required String artifactId}) =>
+    RustLib.instance.api.crateApiFfiUnpinArtifact(artifactId: artifactId);
+
+/// Signed wake token asking a paired device to sync with this one
+///
+/// Hand it to the push service that reaches `peer_id`; the token itself
+/// needs no further protection.
+Future<String> ffiWakeToken({required String peerId}) =>
+    RustLib.instance.api.crateApiFfiWakeToken(peerId: peerId);
+
+/// Warm-start snapshot loaded at launch as JSON, or `null` if none
+///
+/// Lets the UI render the artifact list and collections before the
+/// stores are queried.
+Future<String> ffiWarmSnapshot() =>
+    RustLib.instance.api.crateApiFfiWarmSnapshot();
+
+/// Destroy this device's keys, stores and journals at once
+///
+/// Stops the runtime; `ffi_init` sets the device up from scratch.
+Future<void> ffiWipe() => RustLib.instance.api.crateApiFfiWipe();
+
+/// Ask another device of this user to wipe itself, returning the
+/// JSON-encoded `WipeCommand` for the app to deliver if not connected
+Future<String> ffiWipeDevice({required String deviceId}) =>
+    RustLib.instance.api.crateApiFfiWipeDevice(deviceId: deviceId);
+
 String processMessage({required String input}) =>
     RustLib.instance.api.crateApiProcessMessage(input: input);
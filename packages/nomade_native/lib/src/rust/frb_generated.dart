@@ -66,7 +66,7 @@ class RustLib extends BaseEntrypoint<RustLibApi, RustLibApiImpl, RustLibWire> {
   String get codegenVersion => '2.11.1';
 
   @override
-  int get rustContentHash => 772411924;
+  int get rustContentHash => 430101551;
 
   static const kDefaultExternalLibraryLoaderConfig =
       ExternalLibraryLoaderConfig(
@@ -77,18 +77,3346 @@ class RustLib extends BaseEntrypoint<RustLibApi, RustLibApiImpl, RustLibWire> {
 }
 
 abstract class RustLibApi extends BaseApi {
+  Future<String> crateApiFfiAcceptPairingOffer({required String offerUrl});
+
+  Future<bool> crateApiFfiAdmitEnrolled({required String certificateJson});
+
+  Future<bool> crateApiFfiApplyUserRevocation({required String revocationJson});
+
+  Future<void> crateApiFfiApplyWipeCommand({required String commandJson});
+
+  Future<Uint8List> crateApiFfiArtifactContent({required String artifactId});
+
+  Future<String> crateApiFfiArtifacts();
+
+  Future<String> crateApiFfiAttestations();
+
+  Future<String> crateApiFfiBackgroundSync({required String budgetJson});
+
+  Future<String> crateApiFfiBackups();
+
+  Future<void> crateApiFfiCancelCodePairing();
+
+  Future<bool> crateApiFfiCancelSync({required BigInt handle});
+
+  Future<String> crateApiFfiCollections();
+
+  Future<String> crateApiFfiConflicts();
+
+  Future<void> crateApiFfiConnectPeer({
+    required String peerId,
+    required String address,
+  });
+
+  Future<String> crateApiFfiConnectionQuality({required String peerId});
+
+  Future<String> crateApiFfiCountersignAttestation({required String deviceId});
+
+  Future<String> crateApiFfiCreateBackup({required String label});
+
+  Future<String> crateApiFfiCreateCollection({
+    required String name,
+    required String parentId,
+    required int position,
+  });
+
+  Future<void> crateApiFfiCryptoSelfTest();
+
+  Future<void> crateApiFfiDeleteBackup({required String backupId});
+
+  Future<void> crateApiFfiDeleteCollection({required String id});
+
+  Stream<String> crateApiFfiDeleteMany({required String artifactIdsJson});
+
+  Future<Uint8List> crateApiFfiDerivedAsset({
+    required String artifactId,
+    required String processor,
+    required String params,
+  });
+
+  Future<String> crateApiFfiDeviceId();
+
+  Future<String> crateApiFfiDevicePermissions({required String deviceId});
+
+  Future<String> crateApiFfiEmptyTrash();
+
+  Future<String> crateApiFfiEnrollDevice({
+    required String passphrase,
+    required String deviceId,
+    required String deviceName,
+  });
+
+  Stream<String> crateApiFfiEventStream();
+
+  Future<void> crateApiFfiExportBundle({
+    required String path,
+    required String artifactIdsJson,
+    required String sealJson,
+  });
+
+  Stream<String> crateApiFfiExportDir({
+    required String path,
+    required String artifactIdsJson,
+    required String optionsJson,
+  });
+
+  Future<Uint8List> crateApiFfiExportSecretKey();
+
+  Future<String> crateApiFfiHandlePush({
+    required String payload,
+    required int budgetMs,
+  });
+
+  Future<String> crateApiFfiImportBundle({
+    required String path,
+    required String password,
+  });
+
+  Future<String> crateApiFfiImportDir({required String path});
+
+  Future<void> crateApiFfiInit({required String configJson});
+
+  Future<String> crateApiFfiKeystoreStatus();
+
+  Future<String> crateApiFfiLinkedPeers();
+
+  Future<String> crateApiFfiListPage({
+    required String cursor,
+    required int limit,
+    required String sort,
+  });
+
+  Future<String> crateApiFfiListSummaries({
+    required String cursor,
+    required int limit,
+    required String sort,
+    required String projectionJson,
+  });
+
+  Future<int> crateApiFfiListen();
+
+  Future<void> crateApiFfiLockKeystore();
+
+  Stream<String> crateApiFfiLogStream();
+
+  Future<String> crateApiFfiMetricsPrometheus();
+
+  Future<String> crateApiFfiMetricsSnapshot();
+
+  Stream<String> crateApiFfiMetricsStream({required int intervalMs});
+
+  Future<void> crateApiFfiMoveCollection({
+    required String id,
+    required String parentId,
+    required int position,
+  });
+
+  Future<String> crateApiFfiNetworkState();
+
+  Future<String> crateApiFfiPairWithCode({
+    required String address,
+    required String code,
+    required String deviceName,
+  });
+
+  Future<String> crateApiFfiPairingOffer({required String deviceName});
+
+  Future<void> crateApiFfiPinArtifact({required String artifactId});
+
+  Future<String> crateApiFfiPinnedArtifacts();
+
+  Future<String> crateApiFfiPlanSync({required String peerId});
+
+  Future<String> crateApiFfiPublishAttestation({
+    required String deviceName,
+    required String user,
+    required BigInt ttlSecs,
+  });
+
+  Future<String> crateApiFfiQueryArtifacts({required String queryJson});
+
+  Future<String> crateApiFfiQuerySummaries({
+    required String queryJson,
+    required String projectionJson,
+  });
+
+  Future<String> crateApiFfiReencryptionProgress();
+
+  Future<void> crateApiFfiRegisterAuthGate({
+    required FutureOr<bool> Function(String) prompt,
+  });
+
+  Future<void> crateApiFfiRegisterPlatformSigner({
+    required Uint8List publicKey,
+    required FutureOr<Uint8List> Function(Uint8List) sign,
+  });
+
+  Future<void> crateApiFfiRegisterSchema({required String schemaJson});
+
+  Future<void> crateApiFfiRemoveKeystorePassphrase();
+
+  Future<void> crateApiFfiRenameCollection({
+    required String id,
+    required String name,
+  });
+
+  Future<String> crateApiFfiResolveConflict({
+    required String artifactId,
+    required String resolutionJson,
+  });
+
+  Future<void> crateApiFfiRestoreArtifact({required String artifactId});
+
+  Future<String> crateApiFfiRestoreBackup({required String backupId});
+
+  Stream<String> crateApiFfiRetagMany({
+    required String artifactIdsJson,
+    required String retagJson,
+  });
+
+  Future<String> crateApiFfiRevokeDevice({
+    required String deviceId,
+    required String reason,
+  });
+
+  Future<String> crateApiFfiRevokeEnrolled({
+    required String passphrase,
+    required String deviceId,
+    required String reason,
+  });
+
+  Future<bool> crateApiFfiRevokeShare({required String tokenId});
+
+  Future<int> crateApiFfiRotateMetadataKey();
+
+  Future<String> crateApiFfiSaveSnippet({required String snippetJson});
+
+  Future<String> crateApiFfiSchemas();
+
+  Future<String> crateApiFfiSendSnippet({
+    required String peerId,
+    required String text,
+  });
+
+  Future<void> crateApiFfiSetArtifactCollection({
+    required String artifactId,
+    required String collectionId,
+  });
+
+  Future<void> crateApiFfiSetDevicePermissions({
+    required String deviceId,
+    required String permissionsJson,
+  });
+
+  Future<void> crateApiFfiSetKeystorePassphrase({required String passphrase});
+
+  Future<void> crateApiFfiSetNetworkState({required String stateJson});
+
+  Future<void> crateApiFfiSetPeerHints({
+    required String peerId,
+    required String hintsJson,
+  });
+
+  Future<String> crateApiFfiSetSyncRules({
+    required String peerId,
+    required String rulesJson,
+  });
+
+  Future<String> crateApiFfiShareArtifact({
+    required String artifactId,
+    required BigInt ttlSecs,
+  });
+
+  Future<String> crateApiFfiShares();
+
+  Future<void> crateApiFfiShutdown();
+
+  Future<String> crateApiFfiStartCodePairing({required String deviceName});
+
+  Future<BigInt> crateApiFfiStartSync({required String peerId});
+
+  Future<String> crateApiFfiStoreHealth();
+
+  Stream<String> crateApiFfiStoreMany({required String artifactsJson});
+
+  Stream<String> crateApiFfiSyncProgress({required BigInt handle});
+
+  Future<String> crateApiFfiSyncRules({required String peerId});
+
+  Future<String> crateApiFfiTrash();
+
+  Future<void> crateApiFfiTrashArtifact({required String artifactId});
+
+  Future<bool> crateApiFfiTrustUser({
+    required String certificateJson,
+    required String name,
+  });
+
+  Future<String> crateApiFfiTrustedDevices();
+
+  Future<void> crateApiFfiUnlockKeystore({required String passphrase});
+
+  Future<void> crateApiFfiUnlockKeystoreBiometric({
+    required FutureOr<Uint8List> Function() release,
+  });
+
+  Future<bool> crateApiFfiUnpinArtifact({required String artifactId});
+
+  Future<String> crateApiFfiWakeToken({required String peerId});
+
+  Future<String> crateApiFfiWarmSnapshot();
+
+  Future<void> crateApiFfiWipe();
+
+  Future<String> crateApiFfiWipeDevice({required String deviceId});
+
   Future<void> crateApiInitApp();
 
-  String crateApiProcessMessage({required String input});
-}
+  String crateApiProcessMessage({required String input});
+}
+
+class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
+  RustLibApiImpl({
+    required super.handler,
+    required super.wire,
+    required super.generalizedFrbRustBinding,
+    required super.portManager,
+  });
+
+  @override
+  Future<String> crateApiFfiAcceptPairingOffer({required String offerUrl}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(offerUrl, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 1,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiAcceptPairingOfferConstMeta,
+        argValues: [offerUrl],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiAcceptPairingOfferConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_accept_pairing_offer",
+        argNames: ["offer_url"],
+      );
+
+  @override
+  Future<bool> crateApiFfiAdmitEnrolled({required String certificateJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(certificateJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 2,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_bool,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiAdmitEnrolledConstMeta,
+        argValues: [certificateJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiAdmitEnrolledConstMeta => const TaskConstMeta(
+    debugName: "ffi_admit_enrolled",
+    argNames: ["certificate_json"],
+  );
+
+  @override
+  Future<bool> crateApiFfiApplyUserRevocation({
+    required String revocationJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(revocationJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 3,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_bool,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiApplyUserRevocationConstMeta,
+        argValues: [revocationJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiApplyUserRevocationConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_apply_user_revocation",
+        argNames: ["revocation_json"],
+      );
+
+  @override
+  Future<void> crateApiFfiApplyWipeCommand({required String commandJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(commandJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 4,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiApplyWipeCommandConstMeta,
+        argValues: [commandJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiApplyWipeCommandConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_apply_wipe_command",
+        argNames: ["command_json"],
+      );
+
+  @override
+  Future<Uint8List> crateApiFfiArtifactContent({required String artifactId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 5,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_list_prim_u_8_strict,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiArtifactContentConstMeta,
+        argValues: [artifactId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiArtifactContentConstMeta => const TaskConstMeta(
+    debugName: "ffi_artifact_content",
+    argNames: ["artifact_id"],
+  );
+
+  @override
+  Future<String> crateApiFfiArtifacts() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 6,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiArtifactsConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiArtifactsConstMeta =>
+      const TaskConstMeta(debugName: "ffi_artifacts", argNames: []);
+
+  @override
+  Future<String> crateApiFfiAttestations() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 7,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiAttestationsConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiAttestationsConstMeta =>
+      const TaskConstMeta(debugName: "ffi_attestations", argNames: []);
+
+  @override
+  Future<String> crateApiFfiBackgroundSync({required String budgetJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(budgetJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 8,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiBackgroundSyncConstMeta,
+        argValues: [budgetJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiBackgroundSyncConstMeta => const TaskConstMeta(
+    debugName: "ffi_background_sync",
+    argNames: ["budget_json"],
+  );
+
+  @override
+  Future<String> crateApiFfiBackups() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 9,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiBackupsConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiBackupsConstMeta =>
+      const TaskConstMeta(debugName: "ffi_backups", argNames: []);
+
+  @override
+  Future<void> crateApiFfiCancelCodePairing() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 10,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCancelCodePairingConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCancelCodePairingConstMeta =>
+      const TaskConstMeta(debugName: "ffi_cancel_code_pairing", argNames: []);
+
+  @override
+  Future<bool> crateApiFfiCancelSync({required BigInt handle}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_u_64(handle, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 11,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_bool,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCancelSyncConstMeta,
+        argValues: [handle],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCancelSyncConstMeta =>
+      const TaskConstMeta(debugName: "ffi_cancel_sync", argNames: ["handle"]);
+
+  @override
+  Future<String> crateApiFfiCollections() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 12,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCollectionsConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCollectionsConstMeta =>
+      const TaskConstMeta(debugName: "ffi_collections", argNames: []);
+
+  @override
+  Future<String> crateApiFfiConflicts() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 13,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiConflictsConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiConflictsConstMeta =>
+      const TaskConstMeta(debugName: "ffi_conflicts", argNames: []);
+
+  @override
+  Future<void> crateApiFfiConnectPeer({
+    required String peerId,
+    required String address,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          sse_encode_String(address, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 14,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiConnectPeerConstMeta,
+        argValues: [peerId, address],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiConnectPeerConstMeta => const TaskConstMeta(
+    debugName: "ffi_connect_peer",
+    argNames: ["peer_id", "address"],
+  );
+
+  @override
+  Future<String> crateApiFfiConnectionQuality({required String peerId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 15,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiConnectionQualityConstMeta,
+        argValues: [peerId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiConnectionQualityConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_connection_quality",
+        argNames: ["peer_id"],
+      );
+
+  @override
+  Future<String> crateApiFfiCountersignAttestation({required String deviceId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 16,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCountersignAttestationConstMeta,
+        argValues: [deviceId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCountersignAttestationConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_countersign_attestation",
+        argNames: ["device_id"],
+      );
+
+  @override
+  Future<String> crateApiFfiCreateBackup({required String label}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(label, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 17,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCreateBackupConstMeta,
+        argValues: [label],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCreateBackupConstMeta =>
+      const TaskConstMeta(debugName: "ffi_create_backup", argNames: ["label"]);
+
+  @override
+  Future<String> crateApiFfiCreateCollection({
+    required String name,
+    required String parentId,
+    required int position,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(name, serializer);
+          sse_encode_String(parentId, serializer);
+          sse_encode_i_32(position, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 18,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCreateCollectionConstMeta,
+        argValues: [name, parentId, position],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCreateCollectionConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_create_collection",
+        argNames: ["name", "parent_id", "position"],
+      );
+
+  @override
+  Future<void> crateApiFfiCryptoSelfTest() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 19,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiCryptoSelfTestConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiCryptoSelfTestConstMeta =>
+      const TaskConstMeta(debugName: "ffi_crypto_self_test", argNames: []);
+
+  @override
+  Future<void> crateApiFfiDeleteBackup({required String backupId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(backupId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 20,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiDeleteBackupConstMeta,
+        argValues: [backupId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiDeleteBackupConstMeta => const TaskConstMeta(
+    debugName: "ffi_delete_backup",
+    argNames: ["backup_id"],
+  );
+
+  @override
+  Future<void> crateApiFfiDeleteCollection({required String id}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(id, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 21,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiDeleteCollectionConstMeta,
+        argValues: [id],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiDeleteCollectionConstMeta =>
+      const TaskConstMeta(debugName: "ffi_delete_collection", argNames: ["id"]);
+
+  @override
+  Stream<String> crateApiFfiDeleteMany({required String artifactIdsJson}) {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_String(artifactIdsJson, serializer);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 22,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiDeleteManyConstMeta,
+          argValues: [artifactIdsJson, sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiDeleteManyConstMeta => const TaskConstMeta(
+    debugName: "ffi_delete_many",
+    argNames: ["artifact_ids_json", "sink"],
+  );
+
+  @override
+  Future<Uint8List> crateApiFfiDerivedAsset({
+    required String artifactId,
+    required String processor,
+    required String params,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          sse_encode_String(processor, serializer);
+          sse_encode_String(params, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 23,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_list_prim_u_8_strict,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiDerivedAssetConstMeta,
+        argValues: [artifactId, processor, params],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiDerivedAssetConstMeta => const TaskConstMeta(
+    debugName: "ffi_derived_asset",
+    argNames: ["artifact_id", "processor", "params"],
+  );
+
+  @override
+  Future<String> crateApiFfiDeviceId() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 24,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiDeviceIdConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiDeviceIdConstMeta =>
+      const TaskConstMeta(debugName: "ffi_device_id", argNames: []);
+
+  @override
+  Future<String> crateApiFfiDevicePermissions({required String deviceId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 25,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiDevicePermissionsConstMeta,
+        argValues: [deviceId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiDevicePermissionsConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_device_permissions",
+        argNames: ["device_id"],
+      );
+
+  @override
+  Future<String> crateApiFfiEmptyTrash() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 26,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiEmptyTrashConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiEmptyTrashConstMeta =>
+      const TaskConstMeta(debugName: "ffi_empty_trash", argNames: []);
+
+  @override
+  Future<String> crateApiFfiEnrollDevice({
+    required String passphrase,
+    required String deviceId,
+    required String deviceName,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(passphrase, serializer);
+          sse_encode_String(deviceId, serializer);
+          sse_encode_String(deviceName, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 27,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiEnrollDeviceConstMeta,
+        argValues: [passphrase, deviceId, deviceName],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiEnrollDeviceConstMeta => const TaskConstMeta(
+    debugName: "ffi_enroll_device",
+    argNames: ["passphrase", "device_id", "device_name"],
+  );
+
+  @override
+  Stream<String> crateApiFfiEventStream() {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 28,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiEventStreamConstMeta,
+          argValues: [sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiEventStreamConstMeta =>
+      const TaskConstMeta(debugName: "ffi_event_stream", argNames: ["sink"]);
+
+  @override
+  Future<void> crateApiFfiExportBundle({
+    required String path,
+    required String artifactIdsJson,
+    required String sealJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(path, serializer);
+          sse_encode_String(artifactIdsJson, serializer);
+          sse_encode_String(sealJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 29,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiExportBundleConstMeta,
+        argValues: [path, artifactIdsJson, sealJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiExportBundleConstMeta => const TaskConstMeta(
+    debugName: "ffi_export_bundle",
+    argNames: ["path", "artifact_ids_json", "seal_json"],
+  );
+
+  @override
+  Stream<String> crateApiFfiExportDir({
+    required String path,
+    required String artifactIdsJson,
+    required String optionsJson,
+  }) {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_String(path, serializer);
+            sse_encode_String(artifactIdsJson, serializer);
+            sse_encode_String(optionsJson, serializer);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 30,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiExportDirConstMeta,
+          argValues: [path, artifactIdsJson, optionsJson, sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiExportDirConstMeta => const TaskConstMeta(
+    debugName: "ffi_export_dir",
+    argNames: ["path", "artifact_ids_json", "options_json", "sink"],
+  );
+
+  @override
+  Future<Uint8List> crateApiFfiExportSecretKey() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 31,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_list_prim_u_8_strict,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiExportSecretKeyConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiExportSecretKeyConstMeta =>
+      const TaskConstMeta(debugName: "ffi_export_secret_key", argNames: []);
+
+  @override
+  Future<String> crateApiFfiHandlePush({
+    required String payload,
+    required int budgetMs,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(payload, serializer);
+          sse_encode_u_32(budgetMs, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 32,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiHandlePushConstMeta,
+        argValues: [payload, budgetMs],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiHandlePushConstMeta => const TaskConstMeta(
+    debugName: "ffi_handle_push",
+    argNames: ["payload", "budget_ms"],
+  );
+
+  @override
+  Future<String> crateApiFfiImportBundle({
+    required String path,
+    required String password,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(path, serializer);
+          sse_encode_String(password, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 33,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiImportBundleConstMeta,
+        argValues: [path, password],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiImportBundleConstMeta => const TaskConstMeta(
+    debugName: "ffi_import_bundle",
+    argNames: ["path", "password"],
+  );
+
+  @override
+  Future<String> crateApiFfiImportDir({required String path}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(path, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 34,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiImportDirConstMeta,
+        argValues: [path],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiImportDirConstMeta =>
+      const TaskConstMeta(debugName: "ffi_import_dir", argNames: ["path"]);
+
+  @override
+  Future<void> crateApiFfiInit({required String configJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(configJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 35,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiInitConstMeta,
+        argValues: [configJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiInitConstMeta =>
+      const TaskConstMeta(debugName: "ffi_init", argNames: ["config_json"]);
+
+  @override
+  Future<String> crateApiFfiKeystoreStatus() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 36,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiKeystoreStatusConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiKeystoreStatusConstMeta =>
+      const TaskConstMeta(debugName: "ffi_keystore_status", argNames: []);
+
+  @override
+  Future<String> crateApiFfiLinkedPeers() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 37,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiLinkedPeersConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiLinkedPeersConstMeta =>
+      const TaskConstMeta(debugName: "ffi_linked_peers", argNames: []);
+
+  @override
+  Future<String> crateApiFfiListPage({
+    required String cursor,
+    required int limit,
+    required String sort,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(cursor, serializer);
+          sse_encode_u_32(limit, serializer);
+          sse_encode_String(sort, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 38,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiListPageConstMeta,
+        argValues: [cursor, limit, sort],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiListPageConstMeta => const TaskConstMeta(
+    debugName: "ffi_list_page",
+    argNames: ["cursor", "limit", "sort"],
+  );
+
+  @override
+  Future<String> crateApiFfiListSummaries({
+    required String cursor,
+    required int limit,
+    required String sort,
+    required String projectionJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(cursor, serializer);
+          sse_encode_u_32(limit, serializer);
+          sse_encode_String(sort, serializer);
+          sse_encode_String(projectionJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 39,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiListSummariesConstMeta,
+        argValues: [cursor, limit, sort, projectionJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiListSummariesConstMeta => const TaskConstMeta(
+    debugName: "ffi_list_summaries",
+    argNames: ["cursor", "limit", "sort", "projection_json"],
+  );
+
+  @override
+  Future<int> crateApiFfiListen() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 40,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_u_32,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiListenConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiListenConstMeta =>
+      const TaskConstMeta(debugName: "ffi_listen", argNames: []);
+
+  @override
+  Future<void> crateApiFfiLockKeystore() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 41,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiLockKeystoreConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiLockKeystoreConstMeta =>
+      const TaskConstMeta(debugName: "ffi_lock_keystore", argNames: []);
+
+  @override
+  Stream<String> crateApiFfiLogStream() {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 42,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiLogStreamConstMeta,
+          argValues: [sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiLogStreamConstMeta =>
+      const TaskConstMeta(debugName: "ffi_log_stream", argNames: ["sink"]);
+
+  @override
+  Future<String> crateApiFfiMetricsPrometheus() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 43,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiMetricsPrometheusConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiMetricsPrometheusConstMeta =>
+      const TaskConstMeta(debugName: "ffi_metrics_prometheus", argNames: []);
+
+  @override
+  Future<String> crateApiFfiMetricsSnapshot() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 44,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiMetricsSnapshotConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiMetricsSnapshotConstMeta =>
+      const TaskConstMeta(debugName: "ffi_metrics_snapshot", argNames: []);
+
+  @override
+  Stream<String> crateApiFfiMetricsStream({required int intervalMs}) {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            sse_encode_u_32(intervalMs, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 45,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiMetricsStreamConstMeta,
+          argValues: [sink, intervalMs],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiMetricsStreamConstMeta => const TaskConstMeta(
+    debugName: "ffi_metrics_stream",
+    argNames: ["sink", "interval_ms"],
+  );
+
+  @override
+  Future<void> crateApiFfiMoveCollection({
+    required String id,
+    required String parentId,
+    required int position,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(id, serializer);
+          sse_encode_String(parentId, serializer);
+          sse_encode_i_32(position, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 46,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiMoveCollectionConstMeta,
+        argValues: [id, parentId, position],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiMoveCollectionConstMeta => const TaskConstMeta(
+    debugName: "ffi_move_collection",
+    argNames: ["id", "parent_id", "position"],
+  );
+
+  @override
+  Future<String> crateApiFfiNetworkState() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 47,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiNetworkStateConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiNetworkStateConstMeta =>
+      const TaskConstMeta(debugName: "ffi_network_state", argNames: []);
+
+  @override
+  Future<String> crateApiFfiPairWithCode({
+    required String address,
+    required String code,
+    required String deviceName,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(address, serializer);
+          sse_encode_String(code, serializer);
+          sse_encode_String(deviceName, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 48,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiPairWithCodeConstMeta,
+        argValues: [address, code, deviceName],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiPairWithCodeConstMeta => const TaskConstMeta(
+    debugName: "ffi_pair_with_code",
+    argNames: ["address", "code", "device_name"],
+  );
+
+  @override
+  Future<String> crateApiFfiPairingOffer({required String deviceName}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceName, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 49,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiPairingOfferConstMeta,
+        argValues: [deviceName],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiPairingOfferConstMeta => const TaskConstMeta(
+    debugName: "ffi_pairing_offer",
+    argNames: ["device_name"],
+  );
+
+  @override
+  Future<void> crateApiFfiPinArtifact({required String artifactId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 50,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiPinArtifactConstMeta,
+        argValues: [artifactId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiPinArtifactConstMeta => const TaskConstMeta(
+    debugName: "ffi_pin_artifact",
+    argNames: ["artifact_id"],
+  );
+
+  @override
+  Future<String> crateApiFfiPinnedArtifacts() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 51,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiPinnedArtifactsConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiPinnedArtifactsConstMeta =>
+      const TaskConstMeta(debugName: "ffi_pinned_artifacts", argNames: []);
+
+  @override
+  Future<String> crateApiFfiPlanSync({required String peerId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 52,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiPlanSyncConstMeta,
+        argValues: [peerId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiPlanSyncConstMeta =>
+      const TaskConstMeta(debugName: "ffi_plan_sync", argNames: ["peer_id"]);
+
+  @override
+  Future<String> crateApiFfiPublishAttestation({
+    required String deviceName,
+    required String user,
+    required BigInt ttlSecs,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceName, serializer);
+          sse_encode_String(user, serializer);
+          sse_encode_u_64(ttlSecs, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 53,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiPublishAttestationConstMeta,
+        argValues: [deviceName, user, ttlSecs],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiPublishAttestationConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_publish_attestation",
+        argNames: ["device_name", "user", "ttl_secs"],
+      );
+
+  @override
+  Future<String> crateApiFfiQueryArtifacts({required String queryJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(queryJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 54,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiQueryArtifactsConstMeta,
+        argValues: [queryJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiQueryArtifactsConstMeta => const TaskConstMeta(
+    debugName: "ffi_query_artifacts",
+    argNames: ["query_json"],
+  );
+
+  @override
+  Future<String> crateApiFfiQuerySummaries({
+    required String queryJson,
+    required String projectionJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(queryJson, serializer);
+          sse_encode_String(projectionJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 55,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiQuerySummariesConstMeta,
+        argValues: [queryJson, projectionJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiQuerySummariesConstMeta => const TaskConstMeta(
+    debugName: "ffi_query_summaries",
+    argNames: ["query_json", "projection_json"],
+  );
+
+  @override
+  Future<String> crateApiFfiReencryptionProgress() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 56,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiReencryptionProgressConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiReencryptionProgressConstMeta =>
+      const TaskConstMeta(debugName: "ffi_reencryption_progress", argNames: []);
+
+  @override
+  Future<void> crateApiFfiRegisterAuthGate({
+    required FutureOr<bool> Function(String) prompt,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_DartFn_Inputs_String_Output_bool_AnyhowException(
+            prompt,
+            serializer,
+          );
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 57,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRegisterAuthGateConstMeta,
+        argValues: [prompt],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRegisterAuthGateConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_register_auth_gate",
+        argNames: ["prompt"],
+      );
+
+  @override
+  Future<void> crateApiFfiRegisterPlatformSigner({
+    required Uint8List publicKey,
+    required FutureOr<Uint8List> Function(Uint8List) sign,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_list_prim_u_8_strict(publicKey, serializer);
+          sse_encode_DartFn_Inputs_list_prim_u_8_strict_Output_list_prim_u_8_strict_AnyhowException(
+            sign,
+            serializer,
+          );
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 58,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRegisterPlatformSignerConstMeta,
+        argValues: [publicKey, sign],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRegisterPlatformSignerConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_register_platform_signer",
+        argNames: ["public_key", "sign"],
+      );
+
+  @override
+  Future<void> crateApiFfiRegisterSchema({required String schemaJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(schemaJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 59,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRegisterSchemaConstMeta,
+        argValues: [schemaJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRegisterSchemaConstMeta => const TaskConstMeta(
+    debugName: "ffi_register_schema",
+    argNames: ["schema_json"],
+  );
+
+  @override
+  Future<void> crateApiFfiRemoveKeystorePassphrase() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 60,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRemoveKeystorePassphraseConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRemoveKeystorePassphraseConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_remove_keystore_passphrase",
+        argNames: [],
+      );
+
+  @override
+  Future<void> crateApiFfiRenameCollection({
+    required String id,
+    required String name,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(id, serializer);
+          sse_encode_String(name, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 61,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRenameCollectionConstMeta,
+        argValues: [id, name],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRenameCollectionConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_rename_collection",
+        argNames: ["id", "name"],
+      );
+
+  @override
+  Future<String> crateApiFfiResolveConflict({
+    required String artifactId,
+    required String resolutionJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          sse_encode_String(resolutionJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 62,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiResolveConflictConstMeta,
+        argValues: [artifactId, resolutionJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiResolveConflictConstMeta => const TaskConstMeta(
+    debugName: "ffi_resolve_conflict",
+    argNames: ["artifact_id", "resolution_json"],
+  );
+
+  @override
+  Future<void> crateApiFfiRestoreArtifact({required String artifactId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 63,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRestoreArtifactConstMeta,
+        argValues: [artifactId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRestoreArtifactConstMeta => const TaskConstMeta(
+    debugName: "ffi_restore_artifact",
+    argNames: ["artifact_id"],
+  );
+
+  @override
+  Future<String> crateApiFfiRestoreBackup({required String backupId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(backupId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 64,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRestoreBackupConstMeta,
+        argValues: [backupId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRestoreBackupConstMeta => const TaskConstMeta(
+    debugName: "ffi_restore_backup",
+    argNames: ["backup_id"],
+  );
+
+  @override
+  Stream<String> crateApiFfiRetagMany({
+    required String artifactIdsJson,
+    required String retagJson,
+  }) {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_String(artifactIdsJson, serializer);
+            sse_encode_String(retagJson, serializer);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 65,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiRetagManyConstMeta,
+          argValues: [artifactIdsJson, retagJson, sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiRetagManyConstMeta => const TaskConstMeta(
+    debugName: "ffi_retag_many",
+    argNames: ["artifact_ids_json", "retag_json", "sink"],
+  );
+
+  @override
+  Future<String> crateApiFfiRevokeDevice({
+    required String deviceId,
+    required String reason,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceId, serializer);
+          sse_encode_String(reason, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 66,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRevokeDeviceConstMeta,
+        argValues: [deviceId, reason],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRevokeDeviceConstMeta => const TaskConstMeta(
+    debugName: "ffi_revoke_device",
+    argNames: ["device_id", "reason"],
+  );
+
+  @override
+  Future<String> crateApiFfiRevokeEnrolled({
+    required String passphrase,
+    required String deviceId,
+    required String reason,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(passphrase, serializer);
+          sse_encode_String(deviceId, serializer);
+          sse_encode_String(reason, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 67,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRevokeEnrolledConstMeta,
+        argValues: [passphrase, deviceId, reason],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRevokeEnrolledConstMeta => const TaskConstMeta(
+    debugName: "ffi_revoke_enrolled",
+    argNames: ["passphrase", "device_id", "reason"],
+  );
+
+  @override
+  Future<bool> crateApiFfiRevokeShare({required String tokenId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(tokenId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 68,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_bool,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRevokeShareConstMeta,
+        argValues: [tokenId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRevokeShareConstMeta => const TaskConstMeta(
+    debugName: "ffi_revoke_share",
+    argNames: ["token_id"],
+  );
+
+  @override
+  Future<int> crateApiFfiRotateMetadataKey() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 69,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_u_32,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiRotateMetadataKeyConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiRotateMetadataKeyConstMeta =>
+      const TaskConstMeta(debugName: "ffi_rotate_metadata_key", argNames: []);
+
+  @override
+  Future<String> crateApiFfiSaveSnippet({required String snippetJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(snippetJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 70,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSaveSnippetConstMeta,
+        argValues: [snippetJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSaveSnippetConstMeta => const TaskConstMeta(
+    debugName: "ffi_save_snippet",
+    argNames: ["snippet_json"],
+  );
+
+  @override
+  Future<String> crateApiFfiSchemas() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 71,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSchemasConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSchemasConstMeta =>
+      const TaskConstMeta(debugName: "ffi_schemas", argNames: []);
+
+  @override
+  Future<String> crateApiFfiSendSnippet({
+    required String peerId,
+    required String text,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          sse_encode_String(text, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 72,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSendSnippetConstMeta,
+        argValues: [peerId, text],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSendSnippetConstMeta => const TaskConstMeta(
+    debugName: "ffi_send_snippet",
+    argNames: ["peer_id", "text"],
+  );
+
+  @override
+  Future<void> crateApiFfiSetArtifactCollection({
+    required String artifactId,
+    required String collectionId,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          sse_encode_String(collectionId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 73,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSetArtifactCollectionConstMeta,
+        argValues: [artifactId, collectionId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSetArtifactCollectionConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_set_artifact_collection",
+        argNames: ["artifact_id", "collection_id"],
+      );
+
+  @override
+  Future<void> crateApiFfiSetDevicePermissions({
+    required String deviceId,
+    required String permissionsJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceId, serializer);
+          sse_encode_String(permissionsJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 74,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSetDevicePermissionsConstMeta,
+        argValues: [deviceId, permissionsJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSetDevicePermissionsConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_set_device_permissions",
+        argNames: ["device_id", "permissions_json"],
+      );
+
+  @override
+  Future<void> crateApiFfiSetKeystorePassphrase({required String passphrase}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(passphrase, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 75,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSetKeystorePassphraseConstMeta,
+        argValues: [passphrase],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSetKeystorePassphraseConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_set_keystore_passphrase",
+        argNames: ["passphrase"],
+      );
+
+  @override
+  Future<void> crateApiFfiSetNetworkState({required String stateJson}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(stateJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 76,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSetNetworkStateConstMeta,
+        argValues: [stateJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSetNetworkStateConstMeta => const TaskConstMeta(
+    debugName: "ffi_set_network_state",
+    argNames: ["state_json"],
+  );
+
+  @override
+  Future<void> crateApiFfiSetPeerHints({
+    required String peerId,
+    required String hintsJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          sse_encode_String(hintsJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 77,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSetPeerHintsConstMeta,
+        argValues: [peerId, hintsJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSetPeerHintsConstMeta => const TaskConstMeta(
+    debugName: "ffi_set_peer_hints",
+    argNames: ["peer_id", "hints_json"],
+  );
+
+  @override
+  Future<String> crateApiFfiSetSyncRules({
+    required String peerId,
+    required String rulesJson,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          sse_encode_String(rulesJson, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 78,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSetSyncRulesConstMeta,
+        argValues: [peerId, rulesJson],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSetSyncRulesConstMeta => const TaskConstMeta(
+    debugName: "ffi_set_sync_rules",
+    argNames: ["peer_id", "rules_json"],
+  );
+
+  @override
+  Future<String> crateApiFfiShareArtifact({
+    required String artifactId,
+    required BigInt ttlSecs,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          sse_encode_u_64(ttlSecs, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 79,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiShareArtifactConstMeta,
+        argValues: [artifactId, ttlSecs],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiShareArtifactConstMeta => const TaskConstMeta(
+    debugName: "ffi_share_artifact",
+    argNames: ["artifact_id", "ttl_secs"],
+  );
+
+  @override
+  Future<String> crateApiFfiShares() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 80,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSharesConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSharesConstMeta =>
+      const TaskConstMeta(debugName: "ffi_shares", argNames: []);
+
+  @override
+  Future<void> crateApiFfiShutdown() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 81,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiShutdownConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiShutdownConstMeta =>
+      const TaskConstMeta(debugName: "ffi_shutdown", argNames: []);
+
+  @override
+  Future<String> crateApiFfiStartCodePairing({required String deviceName}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceName, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 82,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiStartCodePairingConstMeta,
+        argValues: [deviceName],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiStartCodePairingConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_start_code_pairing",
+        argNames: ["device_name"],
+      );
+
+  @override
+  Future<BigInt> crateApiFfiStartSync({required String peerId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 83,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_u_64,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiStartSyncConstMeta,
+        argValues: [peerId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiStartSyncConstMeta =>
+      const TaskConstMeta(debugName: "ffi_start_sync", argNames: ["peer_id"]);
+
+  @override
+  Future<String> crateApiFfiStoreHealth() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 84,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiStoreHealthConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiStoreHealthConstMeta =>
+      const TaskConstMeta(debugName: "ffi_store_health", argNames: []);
+
+  @override
+  Stream<String> crateApiFfiStoreMany({required String artifactsJson}) {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_String(artifactsJson, serializer);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 85,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiStoreManyConstMeta,
+          argValues: [artifactsJson, sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiStoreManyConstMeta => const TaskConstMeta(
+    debugName: "ffi_store_many",
+    argNames: ["artifacts_json", "sink"],
+  );
+
+  @override
+  Stream<String> crateApiFfiSyncProgress({required BigInt handle}) {
+    final sink = RustStreamSink<String>();
+    unawaited(
+      handler.executeNormal(
+        NormalTask(
+          callFfi: (port_) {
+            final serializer = SseSerializer(generalizedFrbRustBinding);
+            sse_encode_u_64(handle, serializer);
+            sse_encode_StreamSink_String_Sse(sink, serializer);
+            pdeCallFfi(
+              generalizedFrbRustBinding,
+              serializer,
+              funcId: 86,
+              port: port_,
+            );
+          },
+          codec: SseCodec(
+            decodeSuccessData: sse_decode_unit,
+            decodeErrorData: sse_decode_AnyhowException,
+          ),
+          constMeta: kCrateApiFfiSyncProgressConstMeta,
+          argValues: [handle, sink],
+          apiImpl: this,
+        ),
+      ),
+    );
+    return sink.stream;
+  }
+
+  TaskConstMeta get kCrateApiFfiSyncProgressConstMeta => const TaskConstMeta(
+    debugName: "ffi_sync_progress",
+    argNames: ["handle", "sink"],
+  );
+
+  @override
+  Future<String> crateApiFfiSyncRules({required String peerId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 87,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiSyncRulesConstMeta,
+        argValues: [peerId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiSyncRulesConstMeta =>
+      const TaskConstMeta(debugName: "ffi_sync_rules", argNames: ["peer_id"]);
+
+  @override
+  Future<String> crateApiFfiTrash() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 88,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiTrashConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiTrashConstMeta =>
+      const TaskConstMeta(debugName: "ffi_trash", argNames: []);
+
+  @override
+  Future<void> crateApiFfiTrashArtifact({required String artifactId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 89,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiTrashArtifactConstMeta,
+        argValues: [artifactId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiTrashArtifactConstMeta => const TaskConstMeta(
+    debugName: "ffi_trash_artifact",
+    argNames: ["artifact_id"],
+  );
+
+  @override
+  Future<bool> crateApiFfiTrustUser({
+    required String certificateJson,
+    required String name,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(certificateJson, serializer);
+          sse_encode_String(name, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 90,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_bool,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiTrustUserConstMeta,
+        argValues: [certificateJson, name],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiTrustUserConstMeta => const TaskConstMeta(
+    debugName: "ffi_trust_user",
+    argNames: ["certificate_json", "name"],
+  );
+
+  @override
+  Future<String> crateApiFfiTrustedDevices() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 91,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiTrustedDevicesConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiTrustedDevicesConstMeta =>
+      const TaskConstMeta(debugName: "ffi_trusted_devices", argNames: []);
+
+  @override
+  Future<void> crateApiFfiUnlockKeystore({required String passphrase}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(passphrase, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 92,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiUnlockKeystoreConstMeta,
+        argValues: [passphrase],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiUnlockKeystoreConstMeta => const TaskConstMeta(
+    debugName: "ffi_unlock_keystore",
+    argNames: ["passphrase"],
+  );
+
+  @override
+  Future<void> crateApiFfiUnlockKeystoreBiometric({
+    required FutureOr<Uint8List> Function() release,
+  }) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_DartFn_Inputs__Output_list_prim_u_8_strict_AnyhowException(
+            release,
+            serializer,
+          );
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 93,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiUnlockKeystoreBiometricConstMeta,
+        argValues: [release],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiUnlockKeystoreBiometricConstMeta =>
+      const TaskConstMeta(
+        debugName: "ffi_unlock_keystore_biometric",
+        argNames: ["release"],
+      );
 
-class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
-  RustLibApiImpl({
-    required super.handler,
-    required super.wire,
-    required super.generalizedFrbRustBinding,
-    required super.portManager,
-  });
+  @override
+  Future<bool> crateApiFfiUnpinArtifact({required String artifactId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(artifactId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 94,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_bool,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiUnpinArtifactConstMeta,
+        argValues: [artifactId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiUnpinArtifactConstMeta => const TaskConstMeta(
+    debugName: "ffi_unpin_artifact",
+    argNames: ["artifact_id"],
+  );
+
+  @override
+  Future<String> crateApiFfiWakeToken({required String peerId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(peerId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 95,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiWakeTokenConstMeta,
+        argValues: [peerId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiWakeTokenConstMeta =>
+      const TaskConstMeta(debugName: "ffi_wake_token", argNames: ["peer_id"]);
+
+  @override
+  Future<String> crateApiFfiWarmSnapshot() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 96,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiWarmSnapshotConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiWarmSnapshotConstMeta =>
+      const TaskConstMeta(debugName: "ffi_warm_snapshot", argNames: []);
+
+  @override
+  Future<void> crateApiFfiWipe() {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 97,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_unit,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiWipeConstMeta,
+        argValues: [],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiWipeConstMeta =>
+      const TaskConstMeta(debugName: "ffi_wipe", argNames: []);
+
+  @override
+  Future<String> crateApiFfiWipeDevice({required String deviceId}) {
+    return handler.executeNormal(
+      NormalTask(
+        callFfi: (port_) {
+          final serializer = SseSerializer(generalizedFrbRustBinding);
+          sse_encode_String(deviceId, serializer);
+          pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 98,
+            port: port_,
+          );
+        },
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+        constMeta: kCrateApiFfiWipeDeviceConstMeta,
+        argValues: [deviceId],
+        apiImpl: this,
+      ),
+    );
+  }
+
+  TaskConstMeta get kCrateApiFfiWipeDeviceConstMeta => const TaskConstMeta(
+    debugName: "ffi_wipe_device",
+    argNames: ["device_id"],
+  );
 
   @override
   Future<void> crateApiInitApp() {
@@ -99,7 +3427,7 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
           pdeCallFfi(
             generalizedFrbRustBinding,
             serializer,
-            funcId: 1,
+            funcId: 99,
             port: port_,
           );
         },
@@ -124,7 +3452,11 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
         callFfi: () {
           final serializer = SseSerializer(generalizedFrbRustBinding);
           sse_encode_String(input, serializer);
-          return pdeCallFfi(generalizedFrbRustBinding, serializer, funcId: 2)!;
+          return pdeCallFfi(
+            generalizedFrbRustBinding,
+            serializer,
+            funcId: 100,
+          )!;
         },
         codec: SseCodec(
           decodeSuccessData: sse_decode_String,
@@ -140,18 +3472,145 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   TaskConstMeta get kCrateApiProcessMessageConstMeta =>
       const TaskConstMeta(debugName: "process_message", argNames: ["input"]);
 
+  Future<void> Function(int, dynamic)
+  encode_DartFn_Inputs_String_Output_bool_AnyhowException(
+    FutureOr<bool> Function(String) raw,
+  ) {
+    return (callId, rawArg0) async {
+      final arg0 = dco_decode_String(rawArg0);
+
+      Box<bool>? rawOutput;
+      Box<AnyhowException>? rawError;
+      try {
+        rawOutput = Box(await raw(arg0));
+      } catch (e, s) {
+        rawError = Box(AnyhowException("$e\n\n$s"));
+      }
+
+      final serializer = SseSerializer(generalizedFrbRustBinding);
+      assert((rawOutput != null) ^ (rawError != null));
+      if (rawOutput != null) {
+        serializer.buffer.putUint8(0);
+        sse_encode_bool(rawOutput.value, serializer);
+      } else {
+        serializer.buffer.putUint8(1);
+        sse_encode_AnyhowException(rawError!.value, serializer);
+      }
+      final output = serializer.intoRaw();
+
+      generalizedFrbRustBinding.dartFnDeliverOutput(
+        callId: callId,
+        ptr: output.ptr,
+        rustVecLen: output.rustVecLen,
+        dataLen: output.dataLen,
+      );
+    };
+  }
+
+  Future<void> Function(int)
+  encode_DartFn_Inputs__Output_list_prim_u_8_strict_AnyhowException(
+    FutureOr<Uint8List> Function() raw,
+  ) {
+    return (callId) async {
+      Box<Uint8List>? rawOutput;
+      Box<AnyhowException>? rawError;
+      try {
+        rawOutput = Box(await raw());
+      } catch (e, s) {
+        rawError = Box(AnyhowException("$e\n\n$s"));
+      }
+
+      final serializer = SseSerializer(generalizedFrbRustBinding);
+      assert((rawOutput != null) ^ (rawError != null));
+      if (rawOutput != null) {
+        serializer.buffer.putUint8(0);
+        sse_encode_list_prim_u_8_strict(rawOutput.value, serializer);
+      } else {
+        serializer.buffer.putUint8(1);
+        sse_encode_AnyhowException(rawError!.value, serializer);
+      }
+      final output = serializer.intoRaw();
+
+      generalizedFrbRustBinding.dartFnDeliverOutput(
+        callId: callId,
+        ptr: output.ptr,
+        rustVecLen: output.rustVecLen,
+        dataLen: output.dataLen,
+      );
+    };
+  }
+
+  Future<void> Function(int, dynamic)
+  encode_DartFn_Inputs_list_prim_u_8_strict_Output_list_prim_u_8_strict_AnyhowException(
+    FutureOr<Uint8List> Function(Uint8List) raw,
+  ) {
+    return (callId, rawArg0) async {
+      final arg0 = dco_decode_list_prim_u_8_strict(rawArg0);
+
+      Box<Uint8List>? rawOutput;
+      Box<AnyhowException>? rawError;
+      try {
+        rawOutput = Box(await raw(arg0));
+      } catch (e, s) {
+        rawError = Box(AnyhowException("$e\n\n$s"));
+      }
+
+      final serializer = SseSerializer(generalizedFrbRustBinding);
+      assert((rawOutput != null) ^ (rawError != null));
+      if (rawOutput != null) {
+        serializer.buffer.putUint8(0);
+        sse_encode_list_prim_u_8_strict(rawOutput.value, serializer);
+      } else {
+        serializer.buffer.putUint8(1);
+        sse_encode_AnyhowException(rawError!.value, serializer);
+      }
+      final output = serializer.intoRaw();
+
+      generalizedFrbRustBinding.dartFnDeliverOutput(
+        callId: callId,
+        ptr: output.ptr,
+        rustVecLen: output.rustVecLen,
+        dataLen: output.dataLen,
+      );
+    };
+  }
+
+  @protected
+  AnyhowException dco_decode_AnyhowException(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    return AnyhowException(raw as String);
+  }
+
   @protected
   String dco_decode_String(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
     return raw as String;
   }
 
+  @protected
+  bool dco_decode_bool(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    return raw as bool;
+  }
+
   @protected
   Uint8List dco_decode_list_prim_u_8_strict(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
     return raw as Uint8List;
   }
 
+  @protected
+  int dco_decode_u_32(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    return raw as int;
+  }
+
+  @protected
+  BigInt dco_decode_u_64(dynamic raw) {
+    // Codec=Dco (DartCObject based), see doc to use other codecs
+    return dcoDecodeU64(raw);
+  }
+
   @protected
   int dco_decode_u_8(dynamic raw) {
     // Codec=Dco (DartCObject based), see doc to use other codecs
@@ -164,6 +3623,13 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     return;
   }
 
+  @protected
+  AnyhowException sse_decode_AnyhowException(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    var inner = sse_decode_String(deserializer);
+    return AnyhowException(inner);
+  }
+
   @protected
   String sse_decode_String(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -171,6 +3637,12 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     return utf8.decoder.convert(inner);
   }
 
+  @protected
+  bool sse_decode_bool(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    return deserializer.buffer.getUint8() != 0;
+  }
+
   @protected
   Uint8List sse_decode_list_prim_u_8_strict(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -178,6 +3650,18 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     return deserializer.buffer.getUint8List(len_);
   }
 
+  @protected
+  int sse_decode_u_32(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    return deserializer.buffer.getUint32();
+  }
+
+  @protected
+  BigInt sse_decode_u_64(SseDeserializer deserializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    return deserializer.buffer.getBigUint64();
+  }
+
   @protected
   int sse_decode_u_8(SseDeserializer deserializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
@@ -196,9 +3680,80 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   }
 
   @protected
-  bool sse_decode_bool(SseDeserializer deserializer) {
+  void sse_encode_AnyhowException(
+    AnyhowException self,
+    SseSerializer serializer,
+  ) {
     // Codec=Sse (Serialization based), see doc to use other codecs
-    return deserializer.buffer.getUint8() != 0;
+    sse_encode_String(self.message, serializer);
+  }
+
+  @protected
+  void sse_encode_DartFn_Inputs_String_Output_bool_AnyhowException(
+    FutureOr<bool> Function(String) self,
+    SseSerializer serializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_DartOpaque(
+      encode_DartFn_Inputs_String_Output_bool_AnyhowException(self),
+      serializer,
+    );
+  }
+
+  @protected
+  void sse_encode_DartFn_Inputs__Output_list_prim_u_8_strict_AnyhowException(
+    FutureOr<Uint8List> Function() self,
+    SseSerializer serializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_DartOpaque(
+      encode_DartFn_Inputs__Output_list_prim_u_8_strict_AnyhowException(self),
+      serializer,
+    );
+  }
+
+  @protected
+  void sse_encode_DartFn_Inputs_list_prim_u_8_strict_Output_list_prim_u_8_strict_AnyhowException(
+    FutureOr<Uint8List> Function(Uint8List) self,
+    SseSerializer serializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_DartOpaque(
+      encode_DartFn_Inputs_list_prim_u_8_strict_Output_list_prim_u_8_strict_AnyhowException(self),
+      serializer,
+    );
+  }
+
+  @protected
+  void sse_encode_DartOpaque(Object self, SseSerializer serializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_usize(
+      PlatformPointerUtil.ptrToBigInt(
+        encodeDartOpaque(
+          self,
+          portManager.dartHandlerPort,
+          generalizedFrbRustBinding,
+        ),
+      ),
+      serializer,
+    );
+  }
+
+  @protected
+  void sse_encode_StreamSink_String_Sse(
+    RustStreamSink<String> self,
+    SseSerializer serializer,
+  ) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    sse_encode_String(
+      self.setupAndSerialize(
+        codec: SseCodec(
+          decodeSuccessData: sse_decode_String,
+          decodeErrorData: sse_decode_AnyhowException,
+        ),
+      ),
+      serializer,
+    );
   }
 
   @protected
@@ -207,6 +3762,18 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
     sse_encode_list_prim_u_8_strict(utf8.encoder.convert(self), serializer);
   }
 
+  @protected
+  void sse_encode_bool(bool self, SseSerializer serializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    serializer.buffer.putUint8(self ? 1 : 0);
+  }
+
+  @protected
+  void sse_encode_i_32(int self, SseSerializer serializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    serializer.buffer.putInt32(self);
+  }
+
   @protected
   void sse_encode_list_prim_u_8_strict(
     Uint8List self,
@@ -218,25 +3785,31 @@ class RustLibApiImpl extends RustLibApiImplPlatform implements RustLibApi {
   }
 
   @protected
-  void sse_encode_u_8(int self, SseSerializer serializer) {
+  void sse_encode_u_32(int self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
-    serializer.buffer.putUint8(self);
+    serializer.buffer.putUint32(self);
   }
 
   @protected
-  void sse_encode_unit(void self, SseSerializer serializer) {
+  void sse_encode_u_64(BigInt self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
+    serializer.buffer.putBigUint64(self);
   }
 
   @protected
-  void sse_encode_i_32(int self, SseSerializer serializer) {
+  void sse_encode_u_8(int self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
-    serializer.buffer.putInt32(self);
+    serializer.buffer.putUint8(self);
   }
 
   @protected
-  void sse_encode_bool(bool self, SseSerializer serializer) {
+  void sse_encode_unit(void self, SseSerializer serializer) {
     // Codec=Sse (Serialization based), see doc to use other codecs
-    serializer.buffer.putUint8(self ? 1 : 0);
+  }
+
+  @protected
+  void sse_encode_usize(BigInt self, SseSerializer serializer) {
+    // Codec=Sse (Serialization based), see doc to use other codecs
+    serializer.buffer.putBigUint64(self);
   }
 }